@@ -1,36 +1,147 @@
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use rayon::prelude::*;
-use needletail::{parse_fastx_file, FastxReader};
-use fnv::FnvHashMap;
+use needletail::{parse_fastx_file, FastxReader, parser::SequenceRecord};
+use fnv::{FnvHashMap, FnvHasher};
 use serde::{Serialize, Deserialize};
 use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap},
     fs::{File, create_dir_all},
+    hash::Hasher,
     io::{BufWriter, BufReader, Write},
-    path::PathBuf,
+    ops::AddAssign,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    thread,
+    time::Instant,
 };
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use crossbeam_channel::bounded;
+
+/// Global allocator wrapper that keeps a running total of live heap bytes,
+/// so the merge step can notice when it is approaching `--max-memory`.
+struct CountingAllocator;
+
+static ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            ALLOCATED_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        ALLOCATED_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { System.realloc(ptr, layout, new_size) };
+        if !new_ptr.is_null() {
+            ALLOCATED_BYTES.fetch_add(new_size, Ordering::Relaxed);
+            ALLOCATED_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+        }
+        new_ptr
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Current number of live heap bytes as seen by the counting allocator.
+fn allocated_bytes() -> usize {
+    ALLOCATED_BYTES.load(Ordering::Relaxed)
+}
+
+/// Fraction of `--max-memory` at which the in-memory merge is spilled to disk.
+const SPILL_FRACTION: f64 = 0.8;
+
+/// Parses a human-readable memory size such as `512M`, `4G` or `1048576`.
+fn parse_memory_size(s: &str) -> Result<usize, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let value: f64 = number.parse().map_err(|_| format!("invalid memory size '{}'", s))?;
+    let multiplier = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1u64,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        other => return Err(format!("unknown memory unit '{}'", other)),
+    };
+    Ok((value * multiplier as f64) as usize)
+}
 
 /// Command-line arguments for chunk-based k-mer counting and de Bruijn graph construction.
 #[derive(Parser, Debug)]
 #[command(name = "kmer_debruijn_builder")]
 #[command(about = "Performs chunked k-mer counting and builds a minimal de Bruijn graph")]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
-    /// The input FASTQ file path (supports gzipped FASTQ).
+    /// Without a subcommand, the graph is built from --input.
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Input FASTQ/FASTA files (gzipped or otherwise compressed is fine), read in order and
+    /// counted together. A directory stands for the sequence files it contains.
+    #[arg(long, required = true, num_args = 1..)]
+    input: Vec<PathBuf>,
+
+    /// Treat the inputs as R1/R2 pairs, matched by an _R1/_R2 (or _1/_2) file name marker.
+    /// Mates are read in lockstep and must have the same number of reads with matching names.
     #[arg(long)]
-    input: PathBuf,
+    paired: bool,
 
-    /// The size of k.
-    #[arg(long, default_value_t = 31)]
+    /// The size of k (at most 63, so k-mers fit a 128-bit packed key).
+    #[arg(long, default_value_t = 31, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(2..=MAX_K as u64))]
     k: usize,
 
+    /// Skip k-mers containing a base with Phred quality below this (FASTQ only; 0 keeps all bases).
+    #[arg(long, default_value_t = 0)]
+    min_baseq: u8,
+
     /// Minimum count threshold to include a k-mer in the de Bruijn graph.
     #[arg(long, default_value_t = 2)]
     threshold: u64,
 
+    /// Count canonical k-mers (the lesser of a k-mer and its reverse complement), so both
+    /// strands of the same locus share nodes; edges record how often each orientation was seen.
+    #[arg(long)]
+    canonical: bool,
+
     /// Number of sequences to read per chunk, to avoid loading the entire FASTQ at once.
     #[arg(long, default_value_t = 10_000)]
     chunk_size: usize,
 
+    /// Maximum number of chunks waiting between pipeline stages (reading -> counting -> writing),
+    /// which bounds the memory held by reads and partial graphs in flight.
+    #[arg(long, default_value_t = 2)]
+    max_inflight_chunks: usize,
+
+    /// Run a first pass over the input that records k-mers in Bloom filters, so the counting
+    /// pass can skip k-mers seen only once (mostly sequencing errors) and keep the exact map small.
+    #[arg(long)]
+    prefilter: bool,
+
+    /// Memory for the --prefilter Bloom filters (e.g. 256M, 2G); more memory lets fewer singletons through.
+    #[arg(long, default_value = "256M", value_parser = parse_memory_size)]
+    prefilter_memory: usize,
+
+    /// Number of buckets to partition each partial graph into by node prefix. Buckets are merged
+    /// one at a time, so the merge only holds one bucket's nodes in memory.
+    #[arg(long, default_value_t = 1, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    buckets: usize,
+
+    /// Continue an interrupted run: chunks that the manifest in --partial-outdir lists as done,
+    /// and whose partial files are intact, are read past instead of being counted again.
+    #[arg(long)]
+    resume: bool,
+
     /// Directory in which to store partial k-mer maps from each chunk.
     #[arg(long, default_value = "partial_kmer_maps")]
     partial_outdir: PathBuf,
@@ -38,31 +149,535 @@ struct Args {
     /// Name of the final merged adjacency file.
     #[arg(long, default_value = "final_debruijn.bin")]
     final_output: PathBuf,
+
+    /// Soft heap limit (e.g. 512M, 8G). When the merge approaches it, the merged
+    /// graph is spilled to sorted runs on disk and combined with an external merge.
+    #[arg(long, value_parser = parse_memory_size)]
+    max_memory: Option<usize>,
+
+    /// Write the k-mer multiplicity spectrum (multiplicity, distinct k-mers) to this TSV,
+    /// headed by a genome size and coverage estimate from the spectrum peak.
+    #[arg(long)]
+    histogram: Option<PathBuf>,
+
+    /// Write a JSON summary with per-file read, base and k-mer statistics to this path.
+    #[arg(long)]
+    summary_json: Option<PathBuf>,
+
+    /// Write a JSON runtime breakdown (per-stage wall time, bytes, records/sec) to this path on exit.
+    #[arg(long)]
+    profile_json: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Look up query sequences in a built graph: k-mer presence, counts and contiguity.
+    Query(QueryArgs),
+}
+
+/// Arguments of the `query` subcommand.
+#[derive(clap::Args, Debug)]
+struct QueryArgs {
+    /// Graph written by a previous run.
+    #[arg(long, default_value = "final_debruijn.bin")]
+    graph: PathBuf,
+
+    /// FASTA/FASTQ file of query sequences.
+    #[arg(long, required_unless_present = "sequence")]
+    query: Option<PathBuf>,
+
+    /// A query sequence given on the command line; may be repeated.
+    #[arg(long)]
+    sequence: Vec<String>,
+
+    /// Minimum edge count for a k-mer to be reported as present.
+    #[arg(long, default_value_t = 1)]
+    min_count: u64,
+
+    /// Write the per-query report as TSV to this path.
+    #[arg(long)]
+    output: Option<PathBuf>,
+
+    /// Write one TSV row per query k-mer (position, k-mer, count, in/out degree) to this path.
+    #[arg(long)]
+    kmer_output: Option<PathBuf>,
+}
+
+/// Wall time and I/O volume for one stage of the run.
+#[derive(Serialize, Debug)]
+struct StageProfile {
+    stage: String,
+    wall_seconds: f64,
+    bytes_read: u64,
+    bytes_written: u64,
+    records: u64,
+    records_per_sec: f64,
+}
+
+/// Runtime breakdown emitted by `--profile-json`.
+#[derive(Serialize, Debug, Default)]
+struct RunProfile {
+    tool: String,
+    threads: usize,
+    total_wall_seconds: f64,
+    stages: Vec<StageProfile>,
+}
+
+impl RunProfile {
+    fn new() -> Self {
+        Self {
+            tool: env!("CARGO_PKG_NAME").to_string(),
+            threads: rayon::current_num_threads(),
+            ..Default::default()
+        }
+    }
+
+    /// Records a finished stage that started at `started`.
+    fn record(&mut self, stage: &str, started: Instant, records: u64, bytes_read: u64, bytes_written: u64) {
+        let wall_seconds = started.elapsed().as_secs_f64();
+        self.stages.push(StageProfile {
+            stage: stage.to_string(),
+            wall_seconds,
+            bytes_read,
+            bytes_written,
+            records,
+            records_per_sec: if wall_seconds > 0.0 { records as f64 / wall_seconds } else { 0.0 },
+        });
+    }
+
+    fn write(mut self, path: &Path, run_start: Instant) -> Result<()> {
+        self.total_wall_seconds = run_start.elapsed().as_secs_f64();
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create profile file {:?}", path))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), &self)
+            .with_context(|| format!("Failed to write profile JSON to {:?}", path))?;
+        Ok(())
+    }
+}
+
+/// Size of a file on disk, or 0 if it cannot be inspected.
+fn file_len(path: &Path) -> u64 {
+    std::fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
+
+/// Per-file input statistics reported in `--summary-json`.
+#[derive(Serialize, Debug, Default)]
+struct InputStats {
+    path: PathBuf,
+    /// 1 or 2 for the mates of a `--paired` pair.
+    mate: Option<u8>,
+    records: u64,
+    bases: u64,
+    /// Bases masked for falling below --min-baseq.
+    low_quality_bases: u64,
+    /// K-mer windows free of ambiguous and low-quality bases.
+    kmers: u64,
+}
+
+/// Summary of a run emitted by `--summary-json`.
+#[derive(Serialize, Debug)]
+struct RunSummary {
+    k: usize,
+    canonical: bool,
+    threshold: u64,
+    paired: bool,
+    total_records: u64,
+    total_bases: u64,
+    prefix_nodes: u64,
+    genome_estimate: Option<GenomeEstimate>,
+    inputs: Vec<InputStats>,
+}
+
+/// Genome size and coverage read off the k-mer spectrum.
+#[derive(Serialize, Debug)]
+struct GenomeEstimate {
+    /// Multiplicity of the trough between error k-mers and genomic k-mers.
+    error_valley: u64,
+    /// Multiplicity at the spectrum peak, i.e. the k-mer coverage of the genome.
+    kmer_coverage: u64,
+    /// K-mer occurrences at or above the valley.
+    solid_kmers: u64,
+    genome_size: u64,
+    /// Per-base coverage, from the k-mer coverage and the mean read length.
+    base_coverage: f64,
+}
+
+/// K-mer multiplicity spectrum: how many distinct k-mers were seen exactly m times.
+#[derive(Debug, Default)]
+struct KmerSpectrum {
+    bins: BTreeMap<u64, u64>,
+}
+
+impl KmerSpectrum {
+    fn add(&mut self, multiplicity: u64) {
+        *self.bins.entry(multiplicity).or_default() += 1;
+    }
+
+    fn kmers_at(&self, multiplicity: u64) -> u64 {
+        self.bins.get(&multiplicity).copied().unwrap_or(0)
+    }
+
+    /// Estimates genome size the usual way: walk down from `min_multiplicity` to the first
+    /// valley, take the highest bin beyond it as the coverage peak, and divide the k-mer
+    /// occurrences from the valley on by the peak multiplicity. Returns `None` when the
+    /// spectrum has no peak past the error k-mers (e.g. too little coverage).
+    fn estimate(&self, min_multiplicity: u64, mean_read_length: f64, k: usize) -> Option<GenomeEstimate> {
+        let max = *self.bins.keys().next_back()?;
+        let mut valley = min_multiplicity;
+        while valley < max && self.kmers_at(valley + 1) < self.kmers_at(valley) {
+            valley += 1;
+        }
+        let (&peak, _) = self.bins.range(valley + 1..).max_by_key(|&(&m, &n)| (n, Reverse(m)))?;
+        let solid_kmers: u64 = self.bins.range(valley..).map(|(m, n)| m * n).sum();
+        let kmers_per_read = mean_read_length - k as f64 + 1.0;
+        Some(GenomeEstimate {
+            error_valley: valley,
+            kmer_coverage: peak,
+            solid_kmers,
+            genome_size: solid_kmers / peak,
+            base_coverage: if kmers_per_read > 0.0 { peak as f64 * mean_read_length / kmers_per_read } else { 0.0 },
+        })
+    }
+
+    /// Writes the spectrum as `multiplicity<TAB>kmers` rows for every multiplicity up to the
+    /// largest seen, after `#` comment lines carrying the estimate.
+    fn write_tsv(&self, path: &Path, estimate: Option<&GenomeEstimate>) -> Result<()> {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create histogram file {:?}", path))?;
+        let mut writer = BufWriter::new(file);
+        match estimate {
+            Some(estimate) => {
+                writeln!(writer, "# error_valley\t{}", estimate.error_valley)?;
+                writeln!(writer, "# kmer_coverage\t{}", estimate.kmer_coverage)?;
+                writeln!(writer, "# solid_kmers\t{}", estimate.solid_kmers)?;
+                writeln!(writer, "# genome_size\t{}", estimate.genome_size)?;
+                writeln!(writer, "# base_coverage\t{:.2}", estimate.base_coverage)?;
+            }
+            None => writeln!(writer, "# genome_size\tNA (no coverage peak in the spectrum)")?,
+        }
+        let max = self.bins.keys().next_back().copied().unwrap_or(0);
+        for multiplicity in 1..=max {
+            writeln!(writer, "{}\t{}", multiplicity, self.kmers_at(multiplicity))?;
+        }
+        writer.flush()
+            .with_context(|| format!("Failed to write histogram to {:?}", path))?;
+        Ok(())
+    }
+}
+
+/// File extensions picked up when an input is a directory; compressed variants are also accepted.
+const SEQUENCE_EXTENSIONS: [&str; 5] = ["fastq", "fq", "fasta", "fa", "fna"];
+
+/// Expands directories among the inputs into the sequence files they contain, in name order.
+fn expand_inputs(inputs: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for input in inputs {
+        if !input.is_dir() {
+            files.push(input.clone());
+            continue;
+        }
+        let mut dir_files = Vec::new();
+        for entry in std::fs::read_dir(input).with_context(|| format!("Failed to read input directory {:?}", input))? {
+            let path = entry?.path();
+            let name = path.file_name().map(|f| f.to_string_lossy().to_ascii_lowercase()).unwrap_or_default();
+            let name = ["gz", "bz2", "xz", "zst"]
+                .iter()
+                .find_map(|ext| name.strip_suffix(&format!(".{}", ext)))
+                .unwrap_or(&name);
+            if path.is_file() && SEQUENCE_EXTENSIONS.iter().any(|ext| name.ends_with(&format!(".{}", ext))) {
+                dir_files.push(path);
+            }
+        }
+        if dir_files.is_empty() {
+            bail!("Input directory {:?} contains no FASTQ/FASTA files", input);
+        }
+        dir_files.sort();
+        files.extend(dir_files);
+    }
+    Ok(files)
+}
+
+/// Splits an R1/R2 file name into a key shared by both mates and the mate number, using the
+/// last `_R1`, `.R2`, `_1`, ... marker that is followed by `_` or `.`.
+fn mate_key(name: &str) -> Option<(String, u8)> {
+    let bytes = name.as_bytes();
+    (1..bytes.len().saturating_sub(1)).rev().find_map(|i| {
+        let mate = match bytes[i] {
+            b'1' => 1,
+            b'2' => 2,
+            _ => return None,
+        };
+        let before = if i >= 2 && matches!(bytes[i - 1], b'R' | b'r') { bytes[i - 2] } else { bytes[i - 1] };
+        let marked = matches!(before, b'_' | b'.') && matches!(bytes[i + 1], b'_' | b'.');
+        marked.then(|| (format!("{}#{}", &name[..i], &name[i + 1..]), mate))
+    })
+}
+
+/// Groups the input files into the units read together: each file on its own, or with
+/// `paired`, R1/R2 pairs ordered by where their R1 file appears.
+fn input_groups(files: &[PathBuf], paired: bool) -> Result<Vec<[Option<usize>; 2]>> {
+    if !paired {
+        return Ok((0..files.len()).map(|i| [Some(i), None]).collect());
+    }
+    let mut keys: Vec<PathBuf> = Vec::new();
+    let mut groups: Vec<[Option<usize>; 2]> = Vec::new();
+    for (i, file) in files.iter().enumerate() {
+        let name = file.file_name().map(|f| f.to_string_lossy()).unwrap_or_default();
+        let Some((key, mate)) = mate_key(&name) else {
+            bail!("Cannot tell the mate of {:?}: expected an _R1/_R2 or _1/_2 marker in its name", file);
+        };
+        // Mates pair up within the same directory.
+        let key = file.with_file_name(key);
+        let group = match keys.iter().position(|k| *k == key) {
+            Some(group) => group,
+            None => {
+                keys.push(key);
+                groups.push([None, None]);
+                groups.len() - 1
+            }
+        };
+        let slot = &mut groups[group][mate as usize - 1];
+        if let Some(other) = slot {
+            bail!("Both {:?} and {:?} look like mate {} of the same pair", files[*other], file, mate);
+        }
+        *slot = Some(i);
+    }
+    for group in &groups {
+        if let [Some(found), None] | [None, Some(found)] = group {
+            bail!("No mate found for paired input {:?}", files[*found]);
+        }
+    }
+    groups.sort_by_key(|group| group[0]);
+    Ok(groups)
+}
+
+/// Read name shared by both mates: the first word, without a trailing /1 or /2.
+fn mate_name(id: &[u8]) -> &[u8] {
+    let name = id.split(|b| b.is_ascii_whitespace()).next().unwrap_or(id);
+    name.strip_suffix(b"/1").or_else(|| name.strip_suffix(b"/2")).unwrap_or(name)
+}
+
+/// Number of k-mer windows in a sequence that contain no ambiguous base.
+fn kmer_windows(seq: &[u8], k: usize) -> u64 {
+    let mut windows = 0;
+    let mut valid = 0;
+    for &base in seq {
+        if encode_base(base).is_some() {
+            valid += 1;
+            if valid >= k {
+                windows += 1;
+            }
+        } else {
+            valid = 0;
+        }
+    }
+    windows
+}
+
+/// Reads all input files as one stream of sequences, opening each file only when the previous
+/// one is exhausted. Paired mates are interleaved read by read. Keeps per-file statistics.
+struct InputReader {
+    files: Vec<PathBuf>,
+    groups: Vec<[Option<usize>; 2]>,
+    next_group: usize,
+    /// Open readers of the current group with the index of their file.
+    open: Vec<(usize, Box<dyn FastxReader>)>,
+    k: usize,
+    min_baseq: u8,
+    stats: Vec<InputStats>,
+}
+
+impl InputReader {
+    fn new(files: &[PathBuf], paired: bool, k: usize, min_baseq: u8) -> Result<Self> {
+        let groups = input_groups(files, paired)?;
+        let mut stats: Vec<InputStats> =
+            files.iter().map(|path| InputStats { path: path.clone(), ..Default::default() }).collect();
+        if paired {
+            for group in &groups {
+                for (mate, file) in group.iter().enumerate() {
+                    stats[file.expect("pairs are complete")].mate = Some(mate as u8 + 1);
+                }
+            }
+        }
+        Ok(Self { files: files.to_vec(), groups, next_group: 0, open: Vec::new(), k, min_baseq, stats })
+    }
+
+    /// Opens the next group of files; returns false when all have been read.
+    fn open_next_group(&mut self) -> Result<bool> {
+        let Some(group) = self.groups.get(self.next_group) else {
+            return Ok(false);
+        };
+        self.next_group += 1;
+        for &file in group.iter().flatten() {
+            let reader = parse_fastx_file(&self.files[file])
+                .with_context(|| format!("Failed to open input file at {:?}", self.files[file]))?;
+            self.open.push((file, reader));
+        }
+        Ok(true)
+    }
+
+    /// Takes a record's sequence and tallies it. Bases with Phred+33 quality below `min_baseq`
+    /// are replaced by N, so k-mers covering them are skipped like those with ambiguous bases.
+    fn take_seq(stats: &mut InputStats, record: &SequenceRecord, k: usize, min_baseq: u8) -> Vec<u8> {
+        let mut seq = record.seq().into_owned();
+        if let Some(qual) = record.qual().filter(|_| min_baseq > 0) {
+            for (base, &q) in seq.iter_mut().zip(qual) {
+                if q.saturating_sub(33) < min_baseq {
+                    *base = b'N';
+                    stats.low_quality_bases += 1;
+                }
+            }
+        }
+        stats.records += 1;
+        stats.bases += seq.len() as u64;
+        stats.kmers += kmer_windows(&seq, k);
+        seq
+    }
+
+    /// Reads a chunk of sequences, up to chunk_size (a pair is never split across chunks).
+    fn read_chunk(&mut self, chunk_size: usize) -> Result<Vec<Vec<u8>>> {
+        let mut chunk = Vec::with_capacity(chunk_size);
+        while chunk.len() < chunk_size {
+            let Self { files, open, k, min_baseq, stats, .. } = self;
+            match open.as_mut_slice() {
+                [] => {
+                    if !self.open_next_group()? {
+                        break;
+                    }
+                }
+                [(file, reader)] => match reader.next() {
+                    Some(record) => chunk.push(Self::take_seq(&mut stats[*file], &record?, *k, *min_baseq)),
+                    None => open.clear(),
+                },
+                [(file1, reader1), (file2, reader2)] => match (reader1.next(), reader2.next()) {
+                    (Some(record1), Some(record2)) => {
+                        let (record1, record2) = (record1?, record2?);
+                        if mate_name(record1.id()) != mate_name(record2.id()) {
+                            bail!(
+                                "Mates out of sync: read {:?} in {:?} is paired with {:?} in {:?}",
+                                String::from_utf8_lossy(record1.id()),
+                                files[*file1],
+                                String::from_utf8_lossy(record2.id()),
+                                files[*file2]
+                            );
+                        }
+                        for (file, record) in [(*file1, record1), (*file2, record2)] {
+                            chunk.push(Self::take_seq(&mut stats[file], &record, *k, *min_baseq));
+                        }
+                    }
+                    (None, None) => open.clear(),
+                    (Some(_), None) => bail!("{:?} has more reads than its mate {:?}", files[*file1], files[*file2]),
+                    (None, Some(_)) => bail!("{:?} has more reads than its mate {:?}", files[*file2], files[*file1]),
+                },
+                _ => unreachable!("at most two files are read together"),
+            }
+        }
+        Ok(chunk)
+    }
+}
+
+/// Occurrences of a k-mer (or edge). `reverse` counts the occurrences read as the
+/// reverse complement of the stored k-mer, which only happens with `--canonical`.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct KmerCount {
+    count: u64,
+    reverse: u64,
+}
+
+impl KmerCount {
+    /// A single occurrence in the given orientation.
+    fn observed(reverse: bool) -> Self {
+        Self { count: 1, reverse: reverse as u64 }
+    }
+}
+
+impl AddAssign for KmerCount {
+    fn add_assign(&mut self, other: Self) {
+        self.count += other.count;
+        self.reverse += other.reverse;
+    }
+}
+
+/// A k-mer packed two bits per base (A=0, C=1, G=2, T=3), first base in the
+/// highest bits, so packed k-mers of equal length order like their sequences.
+type PackedKmer = u128;
+
+/// Largest supported k; a packed k-mer must fit in a `PackedKmer`.
+const MAX_K: usize = 63;
+
+/// 2-bit code of a nucleotide, or `None` for ambiguous bases such as N.
+fn encode_base(base: u8) -> Option<u8> {
+    match base {
+        b'A' | b'a' => Some(0),
+        b'C' | b'c' => Some(1),
+        b'G' | b'g' => Some(2),
+        b'T' | b't' => Some(3),
+        _ => None,
+    }
+}
+
+/// Nucleotide of a 2-bit code.
+fn decode_base(code: u8) -> u8 {
+    b"ACGT"[(code & 3) as usize]
 }
 
 /// A minimal de Bruijn graph structure. Each entry in `adjacency` tracks
-/// a prefix (k-1-mer) and a map of next base -> count.
+/// a packed prefix ((k-1)-mer) and a map of next base -> count. In a
+/// canonical graph every k-mer is stored in its canonical orientation.
 #[derive(Serialize, Deserialize, Debug, Default)]
 struct DeBruijn {
-    adjacency: FnvHashMap<Vec<u8>, FnvHashMap<u8, u64>>,
+    k: usize,
+    canonical: bool,
+    adjacency: FnvHashMap<PackedKmer, FnvHashMap<u8, KmerCount>>,
 }
 
 impl DeBruijn {
     /// Creates an empty de Bruijn graph.
     fn new() -> Self {
         Self {
+            k: 0,
+            canonical: false,
             adjacency: FnvHashMap::default(),
         }
     }
 
-    /// Incorporates a full k-mer into the graph by splitting it into a (k-1)-mer prefix and next base.
-    fn add_kmer(&mut self, kmer: &[u8]) {
-        if kmer.len() > 1 {
-            let prefix = &kmer[..kmer.len() - 1];
-            let next_base = kmer[kmer.len() - 1];
-            let entry = self.adjacency.entry(prefix.to_vec()).or_default();
-            *entry.entry(next_base).or_insert(0) += 1;
+    /// Incorporates a packed k-mer into the graph by splitting it into a (k-1)-mer prefix and next base.
+    fn add_kmer(&mut self, kmer: PackedKmer, count: KmerCount) {
+        let prefix = kmer >> 2;
+        let next_base = decode_base((kmer & 3) as u8);
+        let entry = self.adjacency.entry(prefix).or_default();
+        *entry.entry(next_base).or_default() += count;
+    }
+
+    /// Partitions the nodes into `buckets` graphs by `node_bucket`.
+    fn into_buckets(self, buckets: usize) -> Vec<DeBruijn> {
+        let mut parts: Vec<DeBruijn> = (0..buckets)
+            .map(|_| DeBruijn {
+                k: self.k,
+                canonical: self.canonical,
+                adjacency: FnvHashMap::default(),
+            })
+            .collect();
+        for (prefix, edges) in self.adjacency {
+            parts[node_bucket(prefix, self.k, buckets)].adjacency.insert(prefix, edges);
         }
+        parts
+    }
+
+    /// Count of a k-mer, looked up in its canonical orientation in a canonical graph.
+    fn kmer_count(&self, kmer: PackedKmer) -> Option<KmerCount> {
+        let stored = if self.canonical { kmer.min(reverse_complement(kmer, self.k)) } else { kmer };
+        self.adjacency.get(&(stored >> 2))?.get(&decode_base((stored & 3) as u8)).copied()
+    }
+
+    /// Number of k-mers with at least `min_count` occurrences that follow `kmer` (out-degree)
+    /// and that precede it (in-degree) in the graph.
+    fn degrees(&self, kmer: PackedKmer, min_count: u64) -> (usize, usize) {
+        let mask: PackedKmer = (1 << (2 * self.k)) - 1;
+        let present = |neighbour: PackedKmer| self.kmer_count(neighbour).is_some_and(|count| count.count >= min_count);
+        let out_degree = (0..4).filter(|&code| present(((kmer << 2) & mask) | code)).count();
+        let in_degree = (0..4).filter(|&code| present((kmer >> 2) | (code << (2 * (self.k - 1))))).count();
+        (out_degree, in_degree)
     }
 
     /// Merges another de Bruijn graph into this one by aggregating edge counts.
@@ -70,159 +685,1016 @@ impl DeBruijn {
         for (prefix, edges) in other.adjacency {
             let main_entry = self.adjacency.entry(prefix).or_default();
             for (base, count) in edges {
-                *main_entry.entry(base).or_insert(0) += count;
+                *main_entry.entry(base).or_default() += count;
             }
         }
     }
 }
 
+/// Reverse complement of a packed k-mer.
+fn reverse_complement(kmer: PackedKmer, k: usize) -> PackedKmer {
+    let mut forward = kmer;
+    let mut reverse: PackedKmer = 0;
+    for _ in 0..k {
+        reverse = (reverse << 2) | (3 - (forward & 3));
+        forward >>= 2;
+    }
+    reverse
+}
+
+/// Number of leading bases of a node that decide its bucket.
+const BUCKET_BASES: usize = 8;
+
+/// Bucket of a packed (k-1)-mer node, taken from its leading bases, so a node
+/// lands in the same bucket in every chunk.
+fn node_bucket(prefix: PackedKmer, k: usize, buckets: usize) -> usize {
+    let lead = BUCKET_BASES.min(k - 1);
+    (prefix >> (2 * (k - 1 - lead))) as usize % buckets
+}
+
+/// File name of a chunk's partial graph; with several buckets, each bucket has its own file.
+fn partial_file_name(chunk: usize, bucket: usize, buckets: usize) -> String {
+    if buckets == 1 {
+        format!("partial_debruijn_{}.bin", chunk)
+    } else {
+        format!("partial_debruijn_{}_b{}.bin", chunk, bucket)
+    }
+}
+
 /// A function to build a de Bruijn graph from a k-mer map above a certain count threshold.
-fn build_debruijn(kmer_map: &FnvHashMap<Vec<u8>, u64>, threshold: u64) -> DeBruijn {
+fn build_debruijn(kmer_map: &FnvHashMap<PackedKmer, KmerCount>, threshold: u64, k: usize, canonical: bool) -> DeBruijn {
     let mut dbg = DeBruijn::new();
-    for (kmer, &count) in kmer_map.iter() {
-        if count >= threshold {
-            dbg.add_kmer(kmer);
+    dbg.k = k;
+    dbg.canonical = canonical;
+    for (&kmer, &count) in kmer_map.iter() {
+        if count.count >= threshold {
+            dbg.add_kmer(kmer, count);
         }
     }
     dbg
 }
 
+/// Marks the end of a completely written partial graph file. It is followed by the length
+/// and FNV-1a checksum of the bincode body, both little-endian u64.
+const PARTIAL_FOOTER_MAGIC: &[u8; 8] = b"DBGPART1";
+
+/// Size of the footer that ends each partial graph file.
+const PARTIAL_FOOTER_LEN: usize = 24;
+
+fn checksum(bytes: &[u8]) -> u64 {
+    let mut hasher = FnvHasher::default();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+/// Writes a partial graph followed by a footer, so a file cut short by an interrupted
+/// run can be told apart from a complete one.
+fn write_partial_graph(dbg: &DeBruijn, path: &Path) -> Result<()> {
+    let body = bincode::serde::encode_to_vec(dbg, bincode::config::standard())
+        .with_context(|| format!("Failed to serialize de Bruijn graph to {:?}", path))?;
+    let file = File::create(path)
+        .with_context(|| format!("Failed to create de Bruijn output file at {:?}", path))?;
+    let mut writer = BufWriter::new(file);
+    writer.write_all(&body)?;
+    writer.write_all(PARTIAL_FOOTER_MAGIC)?;
+    writer.write_all(&(body.len() as u64).to_le_bytes())?;
+    writer.write_all(&checksum(&body).to_le_bytes())?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Body of a partial graph file, or `None` if the file is missing, truncated or corrupt.
+fn read_partial_body(path: &Path) -> Option<Vec<u8>> {
+    let mut bytes = std::fs::read(path).ok()?;
+    let body_len = bytes.len().checked_sub(PARTIAL_FOOTER_LEN)?;
+    let footer = bytes.split_off(body_len);
+    let (magic, rest) = footer.split_at(PARTIAL_FOOTER_MAGIC.len());
+    let (len, sum) = rest.split_at(8);
+    let complete = magic == PARTIAL_FOOTER_MAGIC
+        && u64::from_le_bytes(len.try_into().ok()?) == body_len as u64
+        && u64::from_le_bytes(sum.try_into().ok()?) == checksum(&bytes);
+    complete.then_some(bytes)
+}
+
+/// Reads a partial graph written by `write_partial_graph`, checking its footer.
+fn read_partial_graph(path: &Path) -> Result<DeBruijn> {
+    let body = read_partial_body(path)
+        .with_context(|| format!("Partial de Bruijn file {:?} is missing or incomplete", path))?;
+    let (dbg, _) = bincode::serde::decode_from_slice(&body, bincode::config::standard())
+        .with_context(|| format!("Failed to deserialize de Bruijn graph from {:?}", path))?;
+    Ok(dbg)
+}
+
+/// Settings that decide how the input is cut into chunks and what each partial graph holds;
+/// a run can only be resumed with the same settings.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct ChunkSettings {
+    inputs: Vec<PathBuf>,
+    paired: bool,
+    k: usize,
+    canonical: bool,
+    min_baseq: u8,
+    prefilter: bool,
+    prefilter_memory: usize,
+    chunk_size: usize,
+    buckets: usize,
+}
+
+/// A chunk whose partial graph files were completely written.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct ManifestChunk {
+    index: usize,
+    /// Offset of the chunk's first record in the input stream.
+    first_record: u64,
+    records: u64,
+}
+
+/// Progress of a run, kept as `manifest.json` in --partial-outdir and rewritten after every chunk.
+#[derive(Serialize, Deserialize, Debug)]
+struct Manifest {
+    settings: ChunkSettings,
+    chunks: Vec<ManifestChunk>,
+}
+
+impl Manifest {
+    fn path(dir: &Path) -> PathBuf {
+        dir.join("manifest.json")
+    }
+
+    fn load(dir: &Path) -> Result<Option<Self>> {
+        let path = Self::path(dir);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let file = File::open(&path)
+            .with_context(|| format!("Failed to open manifest {:?}", path))?;
+        let manifest = serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("Failed to parse manifest {:?}", path))?;
+        Ok(Some(manifest))
+    }
+
+    /// Writes the manifest through a temporary file, so an interruption never leaves it half written.
+    fn save(&self, dir: &Path) -> Result<()> {
+        let path = Self::path(dir);
+        let tmp_path = dir.join("manifest.json.tmp");
+        let file = File::create(&tmp_path)
+            .with_context(|| format!("Failed to create manifest {:?}", tmp_path))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, self)
+            .with_context(|| format!("Failed to write manifest {:?}", tmp_path))?;
+        writer.flush()?;
+        std::fs::rename(&tmp_path, &path)
+            .with_context(|| format!("Failed to replace manifest {:?}", path))?;
+        Ok(())
+    }
+
+    /// Partial graph files of a chunk, one per bucket.
+    fn chunk_files(dir: &Path, index: usize, buckets: usize) -> Vec<PathBuf> {
+        (0..buckets).map(|bucket| dir.join(partial_file_name(index, bucket, buckets))).collect()
+    }
+}
+
 /// Serializes a de Bruijn graph to disk in a simple binary format (via bincode).
-fn write_debruijn_graph(dbg: &DeBruijn, path: &PathBuf) -> Result<()> {
+fn write_debruijn_graph(dbg: &DeBruijn, path: &Path) -> Result<()> {
     let file = File::create(path)
         .with_context(|| format!("Failed to create de Bruijn output file at {:?}", path))?;
-    bincode::serialize_into(BufWriter::new(file), dbg)
+    let mut writer = BufWriter::new(file);
+    bincode::serde::encode_into_std_write(dbg, &mut writer, bincode::config::standard())
         .with_context(|| format!("Failed to serialize de Bruijn graph to {:?}", path))?;
+    writer.flush()?;
     Ok(())
 }
 
 /// Deserializes a de Bruijn graph from disk.
-fn read_debruijn_graph(path: &PathBuf) -> Result<DeBruijn> {
+fn read_debruijn_graph(path: &Path) -> Result<DeBruijn> {
     let file = File::open(path)
         .with_context(|| format!("Failed to open de Bruijn file at {:?}", path))?;
-    let dbg: DeBruijn = bincode::deserialize_from(BufReader::new(file))
+    let dbg: DeBruijn = bincode::serde::decode_from_std_read(&mut BufReader::new(file), bincode::config::standard())
         .with_context(|| format!("Failed to deserialize de Bruijn graph from {:?}", path))?;
     Ok(dbg)
 }
 
-/// K-mer counting for a slice of sequences, returning a local map from k-mer -> count.
-fn count_kmers_in_records(records: &[needletail::FastxRecord], k: usize) -> FnvHashMap<Vec<u8>, u64> {
-    let mut local_map = FnvHashMap::default();
-    for seqrec in records {
-        let seq = seqrec.seq();
-        for i in 0..=seq.len().saturating_sub(k) {
-            let kmer = &seq[i..(i + k)];
-            *local_map.entry(kmer.to_vec()).or_insert(0) += 1;
+/// Writes the final graph from per-bucket merged graphs, loading one bucket at a time.
+/// The file has the same bincode layout as `write_debruijn_graph` produces: k, canonical,
+/// the number of nodes, then each node with its edges, so `read_debruijn_graph` reads it.
+fn write_bucketed_graph(k: usize, canonical: bool, nodes: u64, bucket_paths: &[PathBuf], path: &Path) -> Result<()> {
+    let file = File::create(path)
+        .with_context(|| format!("Failed to create de Bruijn output file at {:?}", path))?;
+    let mut writer = BufWriter::new(file);
+    let config = bincode::config::standard();
+    bincode::serde::encode_into_std_write(k, &mut writer, config)?;
+    bincode::serde::encode_into_std_write(canonical, &mut writer, config)?;
+    bincode::serde::encode_into_std_write(nodes, &mut writer, config)?;
+    for bucket_path in bucket_paths {
+        let bucket = read_debruijn_graph(bucket_path)?;
+        for entry in &bucket.adjacency {
+            bincode::serde::encode_into_std_write(entry, &mut writer, config)
+                .with_context(|| format!("Failed to serialize de Bruijn graph to {:?}", path))?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// One adjacency entry as stored in a spilled sorted run: a prefix and its edge counts.
+type RunEntry = (PackedKmer, Vec<(u8, KmerCount)>);
+
+/// Writes the graph to disk as a run of entries sorted by prefix and empties it.
+/// The run starts with the number of entries so it can be streamed back.
+fn spill_sorted_run(dbg: &mut DeBruijn, path: &Path) -> Result<()> {
+    let mut entries: Vec<RunEntry> = dbg
+        .adjacency
+        .drain()
+        .map(|(prefix, edges)| (prefix, edges.into_iter().collect()))
+        .collect();
+    entries.sort_unstable_by_key(|entry| entry.0);
+    dbg.adjacency.shrink_to_fit();
+
+    let file = File::create(path)
+        .with_context(|| format!("Failed to create spill run {:?}", path))?;
+    let mut writer = BufWriter::new(file);
+    let config = bincode::config::standard();
+    bincode::serde::encode_into_std_write(entries.len() as u64, &mut writer, config)?;
+    for entry in &entries {
+        bincode::serde::encode_into_std_write(entry, &mut writer, config)
+            .with_context(|| format!("Failed to write spill run {:?}", path))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Streaming reader over a sorted run produced by `spill_sorted_run`.
+struct RunReader {
+    reader: BufReader<File>,
+    remaining: u64,
+}
+
+impl RunReader {
+    fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open spill run {:?}", path))?;
+        let mut reader = BufReader::new(file);
+        let remaining: u64 = bincode::serde::decode_from_std_read(&mut reader, bincode::config::standard())
+            .with_context(|| format!("Failed to read spill run header {:?}", path))?;
+        Ok(Self { reader, remaining })
+    }
+
+    fn next_entry(&mut self) -> Result<Option<RunEntry>> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        let entry = bincode::serde::decode_from_std_read(&mut self.reader, bincode::config::standard())?;
+        Ok(Some(entry))
+    }
+}
+
+/// K-way merges sorted runs, summing edge counts per prefix and keeping only
+/// edges at or above `threshold`. Only the thresholded graph is held in memory.
+fn merge_sorted_runs(run_paths: &[PathBuf], threshold: u64, spectrum: &mut KmerSpectrum) -> Result<DeBruijn> {
+    let mut readers = run_paths
+        .iter()
+        .map(|p| RunReader::open(p))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut heads: Vec<Option<Vec<(u8, KmerCount)>>> = vec![None; readers.len()];
+    let mut heap = BinaryHeap::new();
+    for (idx, reader) in readers.iter_mut().enumerate() {
+        if let Some((prefix, edges)) = reader.next_entry()? {
+            heads[idx] = Some(edges);
+            heap.push(Reverse((prefix, idx)));
+        }
+    }
+
+    let mut graph = DeBruijn::new();
+    while let Some(Reverse((prefix, idx))) = heap.pop() {
+        let mut edges: FnvHashMap<u8, KmerCount> = FnvHashMap::default();
+        let mut sources = vec![idx];
+        while let Some(Reverse((next_prefix, _))) = heap.peek() {
+            if *next_prefix != prefix {
+                break;
+            }
+            let Reverse((_, other)) = heap.pop().unwrap();
+            sources.push(other);
+        }
+        for source in sources {
+            for (base, count) in heads[source].take().unwrap_or_default() {
+                *edges.entry(base).or_default() += count;
+            }
+            if let Some((next_prefix, next_edges)) = readers[source].next_entry()? {
+                heads[source] = Some(next_edges);
+                heap.push(Reverse((next_prefix, source)));
+            }
+        }
+        for count in edges.values() {
+            spectrum.add(count.count);
+        }
+        edges.retain(|_, count| count.count >= threshold);
+        if !edges.is_empty() {
+            graph.adjacency.insert(prefix, edges);
+        }
+    }
+    Ok(graph)
+}
+
+/// Merges partial graphs and drops edges below `threshold`. When the heap nears
+/// `spill_limit`, the merged graph is spilled to sorted runs in `spill_dir`, which
+/// are then combined with a streaming k-way merge.
+fn merge_partials(
+    paths: &[PathBuf],
+    threshold: u64,
+    spill_limit: Option<usize>,
+    spill_dir: &Path,
+    spectrum: &mut KmerSpectrum,
+) -> Result<DeBruijn> {
+    let mut spill_runs = Vec::new();
+    let mut final_graph = DeBruijn::new();
+    for path in paths {
+        let partial_graph = read_partial_graph(path)?;
+        final_graph.merge(partial_graph);
+
+        // Spill the merged graph to a sorted run once the heap nears the limit.
+        if spill_limit.is_some_and(|limit| allocated_bytes() >= limit) {
+            let run_path = spill_dir.join(format!("spill_run_{}.bin", spill_runs.len()));
+            println!(
+                "Heap usage {} bytes is near --max-memory; spilling {} prefixes to {:?}",
+                allocated_bytes(),
+                final_graph.adjacency.len(),
+                run_path
+            );
+            spill_sorted_run(&mut final_graph, &run_path)?;
+            spill_runs.push(run_path);
+        }
+    }
+
+    // Filter out edges with count below the user threshold (i.e., finalize the graph).
+    if spill_runs.is_empty() {
+        // We'll do a quick rebuild step for thresholding.
+        let mut thresholded_graph = DeBruijn::new();
+        for (prefix, edges) in final_graph.adjacency {
+            let mut new_edges = FnvHashMap::default();
+            for (base, count) in edges {
+                spectrum.add(count.count);
+                if count.count >= threshold {
+                    *new_edges.entry(base).or_default() += count;
+                }
+            }
+            if !new_edges.is_empty() {
+                thresholded_graph.adjacency.insert(prefix, new_edges);
+            }
+        }
+        Ok(thresholded_graph)
+    } else {
+        // Flush what is left and combine all runs with a streaming k-way merge.
+        if !final_graph.adjacency.is_empty() {
+            let run_path = spill_dir.join(format!("spill_run_{}.bin", spill_runs.len()));
+            spill_sorted_run(&mut final_graph, &run_path)?;
+            spill_runs.push(run_path);
+        }
+        println!("Merging {} spilled runs from disk...", spill_runs.len());
+        let merged = merge_sorted_runs(&spill_runs, threshold, spectrum)?;
+        for run_path in &spill_runs {
+            std::fs::remove_file(run_path)
+                .with_context(|| format!("Failed to remove spill run {:?}", run_path))?;
+        }
+        Ok(merged)
+    }
+}
+
+/// Calls `visit` with every k-mer of `seq` and whether it was reverse-complemented.
+/// K-mers are packed on the fly as the window slides; windows containing an ambiguous base
+/// are skipped. With `canonical`, each k-mer is the lesser of itself and its reverse complement.
+fn for_each_kmer(seq: &[u8], k: usize, canonical: bool, mut visit: impl FnMut(PackedKmer, bool)) {
+    let mask: PackedKmer = (1 << (2 * k)) - 1;
+    let top_shift = 2 * (k - 1);
+    let mut forward: PackedKmer = 0;
+    let mut reverse: PackedKmer = 0;
+    // Number of unambiguous bases at the end of the current window
+    let mut valid = 0;
+    for &base in seq {
+        let Some(code) = encode_base(base) else {
+            valid = 0;
+            continue;
+        };
+        forward = ((forward << 2) | code as PackedKmer) & mask;
+        reverse = (reverse >> 2) | (((3 - code) as PackedKmer) << top_shift);
+        valid += 1;
+        if valid < k {
+            continue;
+        }
+        if canonical && reverse < forward {
+            visit(reverse, true);
+        } else {
+            visit(forward, false);
         }
     }
+}
+
+/// Number of bit positions each k-mer sets in a prefilter Bloom filter.
+const PREFILTER_HASHES: u64 = 3;
+
+/// Two Bloom filters over packed k-mers, filled in a pass before counting: the first holds
+/// k-mers seen at least once, the second those seen at least twice. K-mers missing from the
+/// second occurred only once and are skipped by the counting pass; false positives only let
+/// a few singletons through, so the counts of kept k-mers stay exact.
+struct KmerPrefilter {
+    seen_once: Vec<AtomicU64>,
+    seen_twice: Vec<AtomicU64>,
+}
+
+impl KmerPrefilter {
+    /// Creates empty filters that together take about `memory` bytes.
+    fn new(memory: usize) -> Self {
+        let words = (memory / 16).max(1);
+        Self {
+            seen_once: (0..words).map(|_| AtomicU64::new(0)).collect(),
+            seen_twice: (0..words).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    /// Bit positions of a k-mer, by double hashing.
+    fn bit_positions(&self, kmer: PackedKmer) -> impl Iterator<Item = u64> {
+        let bits = self.seen_once.len() as u64 * 64;
+        let h1 = splitmix64(kmer as u64 ^ splitmix64((kmer >> 64) as u64));
+        let h2 = splitmix64(h1) | 1;
+        (0..PREFILTER_HASHES).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % bits)
+    }
+
+    /// Records one occurrence of a k-mer; safe to call from many threads.
+    fn insert(&self, kmer: PackedKmer) {
+        let mut seen_before = true;
+        for bit in self.bit_positions(kmer) {
+            seen_before &= set_bit(&self.seen_once, bit);
+        }
+        if seen_before {
+            for bit in self.bit_positions(kmer) {
+                set_bit(&self.seen_twice, bit);
+            }
+        }
+    }
+
+    /// Whether the k-mer was (probably) seen at least twice.
+    fn passes(&self, kmer: PackedKmer) -> bool {
+        self.bit_positions(kmer)
+            .all(|bit| self.seen_twice[(bit / 64) as usize].load(Ordering::Relaxed) & (1 << (bit % 64)) != 0)
+    }
+}
+
+/// Sets a bit in an atomic bitset and returns whether it was already set.
+fn set_bit(words: &[AtomicU64], bit: u64) -> bool {
+    let mask = 1 << (bit % 64);
+    words[(bit / 64) as usize].fetch_or(mask, Ordering::Relaxed) & mask != 0
+}
+
+/// SplitMix64 finalizer, used to hash packed k-mers.
+fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+/// K-mer counting for a slice of sequences, returning a local map from packed k-mer -> count.
+/// With a prefilter, k-mers it marks as singletons are not counted.
+fn count_kmers_in_records(
+    records: &[Vec<u8>],
+    k: usize,
+    canonical: bool,
+    prefilter: Option<&KmerPrefilter>,
+) -> FnvHashMap<PackedKmer, KmerCount> {
+    let mut local_map: FnvHashMap<PackedKmer, KmerCount> = FnvHashMap::default();
+    for seq in records {
+        for_each_kmer(seq, k, canonical, |kmer, reverse| {
+            if prefilter.is_none_or(|prefilter| prefilter.passes(kmer)) {
+                *local_map.entry(kmer).or_default() += KmerCount::observed(reverse);
+            }
+        });
+    }
     local_map
 }
 
 /// Merges two k-mer maps.
-fn merge_kmer_maps(mut global_map: FnvHashMap<Vec<u8>, u64>, local_map: FnvHashMap<Vec<u8>, u64>) -> FnvHashMap<Vec<u8>, u64> {
+fn merge_kmer_maps(
+    mut global_map: FnvHashMap<PackedKmer, KmerCount>,
+    local_map: FnvHashMap<PackedKmer, KmerCount>,
+) -> FnvHashMap<PackedKmer, KmerCount> {
     for (kmer, count) in local_map {
-        *global_map.entry(kmer).or_insert(0) += count;
+        *global_map.entry(kmer).or_default() += count;
     }
     global_map
 }
 
-/// Reads a chunk of records from the FASTQ reader, up to chunk_size.
-fn read_chunk<R: FastxReader>(reader: &mut R, chunk_size: usize) -> Result<Vec<needletail::FastxRecord>> {
-    let mut chunk = Vec::with_capacity(chunk_size);
-    for _ in 0..chunk_size {
-        if let Some(res) = reader.next() {
-            let record = res?;
-            chunk.push(record);
-        } else {
-            break;
+/// The packed k-mer starting at each position of a query, or `None` where the window
+/// contains an ambiguous base.
+fn query_kmers(seq: &[u8], k: usize) -> Vec<Option<PackedKmer>> {
+    let mask: PackedKmer = (1 << (2 * k)) - 1;
+    let mut kmers = Vec::with_capacity(seq.len().saturating_sub(k - 1));
+    let mut forward: PackedKmer = 0;
+    let mut valid = 0;
+    for (i, &base) in seq.iter().enumerate() {
+        match encode_base(base) {
+            Some(code) => {
+                forward = ((forward << 2) | code as PackedKmer) & mask;
+                valid += 1;
+            }
+            None => valid = 0,
+        }
+        if i + 1 >= k {
+            kmers.push((valid >= k).then_some(forward));
+        }
+    }
+    kmers
+}
+
+/// How one query sequence is represented in the graph.
+struct QueryReport {
+    name: String,
+    length: usize,
+    kmers: usize,
+    present: usize,
+    min_count: u64,
+    median_count: u64,
+    mean_count: f64,
+    /// Longest run of consecutive present k-mers, i.e. the longest stretch of the query
+    /// spelled by a path in the graph.
+    longest_run: usize,
+    /// Query intervals (0-based, end-exclusive, in bases) covered by such paths.
+    segments: Vec<(usize, usize)>,
+    /// Present k-mers where the graph branches (in- or out-degree above one).
+    branch_points: usize,
+}
+
+impl QueryReport {
+    fn fraction_present(&self) -> f64 {
+        if self.kmers == 0 { 0.0 } else { self.present as f64 / self.kmers as f64 }
+    }
+
+    fn segments_string(&self) -> String {
+        if self.segments.is_empty() {
+            return "-".to_string();
+        }
+        self.segments.iter().map(|(start, end)| format!("{}-{}", start, end)).collect::<Vec<_>>().join(",")
+    }
+}
+
+/// Walks a query through the graph k-mer by k-mer. Rows for `--kmer-output` are appended to
+/// `kmer_rows` when given.
+fn query_graph(
+    graph: &DeBruijn,
+    name: &str,
+    seq: &[u8],
+    min_count: u64,
+    mut kmer_rows: Option<&mut dyn Write>,
+) -> Result<QueryReport> {
+    let k = graph.k;
+    let kmers = query_kmers(seq, k);
+    let mut counts = Vec::new();
+    let mut segments = Vec::new();
+    let mut run_start = None;
+    let mut branch_points = 0;
+    for (position, kmer) in kmers.iter().enumerate() {
+        let count = kmer.and_then(|kmer| graph.kmer_count(kmer)).filter(|count| count.count >= min_count);
+        let (out_degree, in_degree) = match (kmer, count) {
+            (Some(kmer), Some(_)) => graph.degrees(*kmer, min_count),
+            _ => (0, 0),
+        };
+        if let Some(count) = count {
+            counts.push(count.count);
+            run_start.get_or_insert(position);
+            if out_degree > 1 || in_degree > 1 {
+                branch_points += 1;
+            }
+        } else if let Some(start) = run_start.take() {
+            segments.push((start, position - 1 + k));
         }
+        if let Some(rows) = kmer_rows.as_mut() {
+            let text = match kmer {
+                Some(kmer) => String::from_utf8((0..k).rev().map(|i| decode_base((kmer >> (2 * i)) as u8)).collect())?,
+                None => String::from_utf8_lossy(&seq[position..position + k]).into_owned(),
+            };
+            writeln!(
+                rows,
+                "{}\t{}\t{}\t{}\t{}\t{}",
+                name,
+                position,
+                text,
+                count.map_or(0, |count| count.count),
+                in_degree,
+                out_degree
+            )?;
+        }
+    }
+    if let Some(start) = run_start {
+        segments.push((start, kmers.len() - 1 + k));
     }
-    Ok(chunk)
+
+    let present = counts.len();
+    counts.sort_unstable();
+    Ok(QueryReport {
+        name: name.to_string(),
+        length: seq.len(),
+        kmers: kmers.len(),
+        present,
+        min_count: counts.first().copied().unwrap_or(0),
+        median_count: counts.get(present / 2).copied().unwrap_or(0),
+        mean_count: if present == 0 { 0.0 } else { counts.iter().sum::<u64>() as f64 / present as f64 },
+        longest_run: segments.iter().map(|(start, end)| end - start + 1 - k).max().unwrap_or(0),
+        segments,
+        branch_points,
+    })
+}
+
+/// Runs the `query` subcommand.
+fn run_query(args: &QueryArgs) -> Result<()> {
+    let graph = read_debruijn_graph(&args.graph)?;
+    println!(
+        "Loaded {:?}: k={}, {}canonical, {} prefix nodes",
+        args.graph,
+        graph.k,
+        if graph.canonical { "" } else { "non-" },
+        graph.adjacency.len()
+    );
+
+    let mut queries: Vec<(String, Vec<u8>)> = args
+        .sequence
+        .iter()
+        .enumerate()
+        .map(|(i, seq)| (format!("sequence_{}", i + 1), seq.as_bytes().to_vec()))
+        .collect();
+    if let Some(path) = &args.query {
+        let mut reader = parse_fastx_file(path)
+            .with_context(|| format!("Failed to open query file at {:?}", path))?;
+        while let Some(record) = reader.next() {
+            let record = record?;
+            let name = String::from_utf8_lossy(mate_name(record.id())).into_owned();
+            queries.push((name, record.seq().into_owned()));
+        }
+    }
+
+    let mut kmer_writer = match &args.kmer_output {
+        Some(path) => {
+            let file = File::create(path)
+                .with_context(|| format!("Failed to create k-mer output file {:?}", path))?;
+            let mut writer = BufWriter::new(file);
+            writeln!(writer, "query\tposition\tkmer\tcount\tin_degree\tout_degree")?;
+            Some(writer)
+        }
+        None => None,
+    };
+
+    let mut reports = Vec::with_capacity(queries.len());
+    for (name, seq) in &queries {
+        let rows = kmer_writer.as_mut().map(|writer| writer as &mut dyn Write);
+        let report = query_graph(&graph, name, seq, args.min_count, rows)?;
+        println!(
+            "{}: {}/{} k-mers present ({:.1}%), median count {}, longest path {} k-mers, {} segment(s), {} branch point(s)",
+            report.name,
+            report.present,
+            report.kmers,
+            report.fraction_present() * 100.0,
+            report.median_count,
+            report.longest_run,
+            report.segments.len(),
+            report.branch_points
+        );
+        reports.push(report);
+    }
+    if let (Some(mut writer), Some(path)) = (kmer_writer, &args.kmer_output) {
+        writer.flush()
+            .with_context(|| format!("Failed to write k-mer output to {:?}", path))?;
+        println!("Per-k-mer results written to {:?}", path);
+    }
+
+    if let Some(path) = &args.output {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create query report {:?}", path))?;
+        let mut writer = BufWriter::new(file);
+        writeln!(
+            writer,
+            "query\tlength\tkmers\tpresent\tfraction_present\tmin_count\tmedian_count\tmean_count\tlongest_run\tbranch_points\tsegments"
+        )?;
+        for report in &reports {
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}\t{:.4}\t{}\t{}\t{:.2}\t{}\t{}\t{}",
+                report.name,
+                report.length,
+                report.kmers,
+                report.present,
+                report.fraction_present(),
+                report.min_count,
+                report.median_count,
+                report.mean_count,
+                report.longest_run,
+                report.branch_points,
+                report.segments_string()
+            )?;
+        }
+        writer.flush()
+            .with_context(|| format!("Failed to write query report to {:?}", path))?;
+        println!("Query report written to {:?}", path);
+    }
+    Ok(())
+}
+
+/// A chunk of reads passed from the reader thread to the counting stage.
+struct Chunk {
+    index: usize,
+    first_record: u64,
+    records: Vec<Vec<u8>>,
+}
+
+/// A chunk's partial de Bruijn graph passed from the counting stage to the writer thread.
+struct CountedChunk {
+    index: usize,
+    first_record: u64,
+    records: usize,
+    graph: DeBruijn,
 }
 
 fn main() -> Result<()> {
+    let run_start = Instant::now();
     let args = Args::parse();
+    if let Some(Command::Query(query_args)) = &args.command {
+        return run_query(query_args);
+    }
+    let mut profile = RunProfile::new();
 
     create_dir_all(&args.partial_outdir)
         .with_context(|| format!("Failed to create partial output directory at {:?}", args.partial_outdir))?;
 
-    // Open the FASTQ file.
-    let mut reader = parse_fastx_file(&args.input)
-        .with_context(|| format!("Failed to open FASTQ file at {:?}", args.input))?;
+    let input_files = expand_inputs(&args.input)?;
+    let input_bytes: u64 = input_files.iter().map(|path| file_len(path)).sum();
+    println!("Reading {} input file(s){}", input_files.len(), if args.paired { " as R1/R2 pairs" } else { "" });
 
-    // In a real HPC environment, ephemeral tasks could each handle one or more chunks.
-    // Here, we demonstrate a single process reading chunks sequentially, building partial k-mer maps.
-    let mut chunk_index = 0;
-    loop {
-        // Read a chunk of records.
-        let records = read_chunk(&mut reader, args.chunk_size)?;
-        if records.is_empty() {
-            break;
-        }
-
-        // Parallelize counting over the chunk's records.
-        let partial_map = records
-            .par_iter()
-            .fold(
-                || FnvHashMap::default(),
-                |local_map, rec| merge_kmer_maps(local_map, count_kmers_in_records(std::slice::from_ref(rec), args.k)),
-            )
-            .reduce(
-                || FnvHashMap::default(),
-                |global_map, local_map| merge_kmer_maps(global_map, local_map),
-            );
+    // Optionally fill the singleton prefilter with a first pass over the input.
+    let prefilter = if args.prefilter {
+        let prefilter_start = Instant::now();
+        let prefilter = KmerPrefilter::new(args.prefilter_memory);
+        let mut reader = InputReader::new(&input_files, args.paired, args.k, args.min_baseq)?;
+        let mut records_seen = 0u64;
+        loop {
+            let records = reader.read_chunk(args.chunk_size)?;
+            if records.is_empty() {
+                break;
+            }
+            records
+                .par_iter()
+                .for_each(|seq| for_each_kmer(seq, args.k, args.canonical, |kmer, _| prefilter.insert(kmer)));
+            records_seen += records.len() as u64;
+        }
+        println!(
+            "Prefilter pass over {} records done in {:.2}s",
+            records_seen,
+            prefilter_start.elapsed().as_secs_f64()
+        );
+        profile.record("prefilter", prefilter_start, records_seen, input_bytes, 0);
+        Some(prefilter)
+    } else {
+        None
+    };
 
-        // Build a minimal de Bruijn from this chunk's k-mers, above threshold = 1 (since we might want to do partial merges).
-        let partial_dbg = build_debruijn(&partial_map, 1);
+    // Start a manifest, or with --resume pick up the chunks an earlier run finished.
+    let settings = ChunkSettings {
+        inputs: input_files.clone(),
+        paired: args.paired,
+        k: args.k,
+        canonical: args.canonical,
+        min_baseq: args.min_baseq,
+        prefilter: args.prefilter,
+        prefilter_memory: args.prefilter_memory,
+        chunk_size: args.chunk_size,
+        buckets: args.buckets,
+    };
+    let mut manifest = Manifest { settings, chunks: Vec::new() };
+    if args.resume {
+        match Manifest::load(&args.partial_outdir)? {
+            Some(previous) if previous.settings != manifest.settings => bail!(
+                "The manifest in {:?} was written with different settings; rerun without --resume to start over",
+                args.partial_outdir
+            ),
+            Some(previous) => {
+                let listed = previous.chunks.len();
+                manifest.chunks = previous
+                    .chunks
+                    .into_par_iter()
+                    .filter(|chunk| {
+                        Manifest::chunk_files(&args.partial_outdir, chunk.index, args.buckets)
+                            .iter()
+                            .all(|path| read_partial_body(path).is_some())
+                    })
+                    .collect();
+                println!(
+                    "Resuming: {} of {} chunks in the manifest have intact partial files and will be skipped",
+                    manifest.chunks.len(),
+                    listed
+                );
+            }
+            None => println!("No manifest in {:?}; starting from the beginning", args.partial_outdir),
+        }
+    }
+    manifest.save(&args.partial_outdir)?;
+    let done: FnvHashMap<usize, ManifestChunk> =
+        manifest.chunks.iter().map(|chunk| (chunk.index, chunk.clone())).collect();
 
-        // Serialize partial de Bruijn to disk.
-        let chunk_file = args.partial_outdir.join(format!("partial_debruijn_{}.bin", chunk_index));
-        let file = File::create(&chunk_file)
-            .with_context(|| format!("Failed to create partial de Bruijn file {:?}", chunk_file))?;
-        bincode::serialize_into(BufWriter::new(file), &partial_dbg)
-            .with_context(|| format!("Failed to write partial de Bruijn to {:?}", chunk_file))?;
+    // Open the input files.
+    let mut reader = InputReader::new(&input_files, args.paired, args.k, args.min_baseq)?;
 
-        println!("Processed chunk {} with {} records, wrote partial de Bruijn to {:?}", 
-            chunk_index, records.len(), chunk_file);
-        chunk_index += 1;
-    }
+    // In a real HPC environment, ephemeral tasks could each handle one or more chunks.
+    // Here, a single process pipelines the chunks: a reader thread parses them, the main
+    // thread counts each one in parallel with Rayon, and a writer thread serializes the
+    // partial graphs, so I/O overlaps counting. Bounded channels keep at most
+    // `--max-inflight-chunks` chunks waiting at each stage.
+    let count_start = Instant::now();
+    let inflight = args.max_inflight_chunks.max(1);
+    let (chunk_tx, chunk_rx) = bounded::<Chunk>(inflight);
+    let (graph_tx, graph_rx) = bounded::<CountedChunk>(inflight);
+    let args = &args;
+    let done = &done;
+    let (input_stats, manifest, counted_records, partial_bytes) = thread::scope(|scope| -> Result<(Vec<InputStats>, Manifest, u64, u64)> {
+        let reader_thread = scope.spawn(move || -> Result<Vec<InputStats>> {
+            let mut first_record = 0u64;
+            for index in 0.. {
+                // Read a chunk of records.
+                let records = reader.read_chunk(args.chunk_size)?;
+                if records.is_empty() {
+                    break;
+                }
+                let chunk_records = records.len() as u64;
+                // Chunks finished by an earlier run are only read past; the rest go on to counting.
+                let finished = done
+                    .get(&index)
+                    .is_some_and(|chunk| chunk.first_record == first_record && chunk.records == chunk_records);
+                // Stop when the counting stage has stopped.
+                if !finished && chunk_tx.send(Chunk { index, first_record, records }).is_err() {
+                    break;
+                }
+                first_record += chunk_records;
+            }
+            Ok(reader.stats)
+        });
 
-    // Merge all partial de Bruijn graphs into one.
-    let dir_entries = std::fs::read_dir(&args.partial_outdir)
-        .with_context(|| format!("Failed to read partial output directory at {:?}", args.partial_outdir))?;
+        let writer_thread = scope.spawn(move || -> Result<(Manifest, u64, u64)> {
+            let mut counted_records = 0u64;
+            let mut partial_bytes = 0u64;
+            for chunk in graph_rx {
+                // Serialize partial de Bruijn to disk, one file per bucket.
+                let chunk_files = Manifest::chunk_files(&args.partial_outdir, chunk.index, args.buckets);
+                for (graph, chunk_file) in chunk.graph.into_buckets(args.buckets).iter().zip(&chunk_files) {
+                    write_partial_graph(graph, chunk_file)
+                        .with_context(|| format!("Failed to write partial de Bruijn to {:?}", chunk_file))?;
+                    partial_bytes += file_len(chunk_file);
+                }
+                counted_records += chunk.records as u64;
 
-    let mut final_graph = DeBruijn::new();
-    for entry in dir_entries {
-        let path = entry?.path();
-        if path.file_name().map_or(false, |f| f.to_string_lossy().starts_with("partial_debruijn_")) {
-            let partial_graph = read_debruijn_graph(&path)
-                .with_context(|| format!("Failed to read partial de Bruijn file {:?}", path))?;
-            final_graph.merge(partial_graph);
-        }
-    }
+                // Only now that all its files are complete does the chunk enter the manifest.
+                manifest.chunks.retain(|done| done.index != chunk.index);
+                manifest.chunks.push(ManifestChunk {
+                    index: chunk.index,
+                    first_record: chunk.first_record,
+                    records: chunk.records as u64,
+                });
+                manifest.chunks.sort_unstable_by_key(|done| done.index);
+                manifest.save(&args.partial_outdir)?;
 
-    // Filter out edges with count below the user threshold (i.e., finalize the graph).
-    // We'll do a quick rebuild step for thresholding.
-    let mut thresholded_graph = DeBruijn::new();
-    for (prefix, edges) in final_graph.adjacency {
-        let mut new_edges = FnvHashMap::default();
-        for (base, count) in edges {
-            if count >= args.threshold {
-                *new_edges.entry(base).or_insert(0) += count;
+                if let [chunk_file] = chunk_files.as_slice() {
+                    println!("Processed chunk {} with {} records, wrote partial de Bruijn to {:?}",
+                        chunk.index, chunk.records, chunk_file);
+                } else {
+                    println!("Processed chunk {} with {} records, wrote {} partial de Bruijn buckets to {:?}",
+                        chunk.index, chunk.records, chunk_files.len(), args.partial_outdir);
+                }
+            }
+            Ok((manifest, counted_records, partial_bytes))
+        });
+
+        for Chunk { index, first_record, records } in chunk_rx {
+            // Parallelize counting over the chunk's records.
+            let partial_map = records
+                .par_iter()
+                .fold(
+                    FnvHashMap::default,
+                    |local_map, rec| merge_kmer_maps(local_map, count_kmers_in_records(std::slice::from_ref(rec), args.k, args.canonical, prefilter.as_ref())),
+                )
+                .reduce(
+                    FnvHashMap::default,
+                    merge_kmer_maps,
+                );
+
+            // Build a minimal de Bruijn from this chunk's k-mers, above threshold = 1 (since we might want to do partial merges).
+            let graph = build_debruijn(&partial_map, 1, args.k, args.canonical);
+            // Stop early if the writer has failed; its error is reported below.
+            if graph_tx.send(CountedChunk { index, first_record, records: records.len(), graph }).is_err() {
+                break;
             }
         }
-        if !new_edges.is_empty() {
-            thresholded_graph.adjacency.insert(prefix, new_edges);
+        drop(graph_tx);
+
+        let read_result = reader_thread.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+        let write_result = writer_thread.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+        let (manifest, counted_records, partial_bytes) = write_result?;
+        Ok((read_result?, manifest, counted_records, partial_bytes))
+    })?;
+    profile.record("count", count_start, counted_records, input_bytes, partial_bytes);
+    let total_records: u64 = input_stats.iter().map(|stats| stats.records).sum();
+    // The prefilter is not needed past counting; free it so it does not count toward --max-memory.
+    drop(prefilter);
+
+    // Merge the partial de Bruijn graphs of all chunks in the manifest, bucket by bucket.
+    let merge_start = Instant::now();
+    let mut partials: Vec<Vec<PathBuf>> = vec![Vec::new(); args.buckets];
+    for chunk in &manifest.chunks {
+        for (bucket, path) in Manifest::chunk_files(&args.partial_outdir, chunk.index, args.buckets).into_iter().enumerate() {
+            partials[bucket].push(path);
         }
     }
+    let merged_partials = partials.iter().map(Vec::len).sum::<usize>() as u64;
+    let partial_bytes: u64 = partials.iter().flatten().map(|path| file_len(path)).sum();
+    let spill_limit = args.max_memory.map(|limit| (limit as f64 * SPILL_FRACTION) as usize);
+    // Multiplicities of all merged k-mers, collected before thresholding.
+    let mut spectrum = KmerSpectrum::default();
+
+    let (nodes, write_start) = if args.buckets == 1 {
+        let mut thresholded_graph = merge_partials(&partials[0], args.threshold, spill_limit, &args.partial_outdir, &mut spectrum)?;
+        profile.record("merge", merge_start, merged_partials, partial_bytes, 0);
 
-    // Write out the final merged graph.
-    write_debruijn_graph(&thresholded_graph, &args.final_output)?;
+        // Write out the final merged graph.
+        thresholded_graph.k = args.k;
+        thresholded_graph.canonical = args.canonical;
+        let write_start = Instant::now();
+        write_debruijn_graph(&thresholded_graph, &args.final_output)?;
+        (thresholded_graph.adjacency.len() as u64, write_start)
+    } else {
+        // Buckets hold disjoint nodes, so each is merged and thresholded on its own and
+        // parked on disk until all are done.
+        let mut nodes = 0u64;
+        let mut merged_paths = Vec::with_capacity(args.buckets);
+        for (bucket, paths) in partials.iter().enumerate() {
+            let merged = merge_partials(paths, args.threshold, spill_limit, &args.partial_outdir, &mut spectrum)?;
+            let merged_path = args.partial_outdir.join(format!("merged_bucket_{}.bin", bucket));
+            write_debruijn_graph(&merged, &merged_path)?;
+            println!("Merged bucket {} from {} partials: {} prefix nodes", bucket, paths.len(), merged.adjacency.len());
+            nodes += merged.adjacency.len() as u64;
+            merged_paths.push(merged_path);
+        }
+        profile.record("merge", merge_start, merged_partials, partial_bytes, 0);
+
+        // Stream the merged buckets into the final graph file.
+        let write_start = Instant::now();
+        write_bucketed_graph(args.k, args.canonical, nodes, &merged_paths, &args.final_output)?;
+        for merged_path in &merged_paths {
+            std::fs::remove_file(merged_path)
+                .with_context(|| format!("Failed to remove merged bucket {:?}", merged_path))?;
+        }
+        (nodes, write_start)
+    };
+    profile.record("write", write_start, nodes, 0, file_len(&args.final_output));
     println!(
         "Final de Bruijn graph has {} prefix nodes. Written to {:?}.",
-        thresholded_graph.adjacency.len(),
+        nodes,
         args.final_output
     );
 
+    // Estimate genome size from the spectrum. The prefilter keeps most singletons out of the
+    // counts, so the first bin is incomplete and the valley search starts at 2.
+    let total_bases: u64 = input_stats.iter().map(|stats| stats.bases).sum();
+    let mean_read_length = if total_records > 0 { total_bases as f64 / total_records as f64 } else { 0.0 };
+    let min_multiplicity = if args.prefilter { 2 } else { 1 };
+    let genome_estimate = spectrum.estimate(min_multiplicity, mean_read_length, args.k);
+    if let Some(path) = &args.histogram {
+        spectrum.write_tsv(path, genome_estimate.as_ref())?;
+        match &genome_estimate {
+            Some(estimate) => println!(
+                "K-mer spectrum written to {:?}: peak at {}x k-mer coverage ({:.1}x base coverage), estimated genome size {} bp",
+                path, estimate.kmer_coverage, estimate.base_coverage, estimate.genome_size
+            ),
+            None => println!("K-mer spectrum written to {:?}: no coverage peak found, genome size not estimated", path),
+        }
+    }
+
+    if let Some(path) = &args.summary_json {
+        let summary = RunSummary {
+            k: args.k,
+            canonical: args.canonical,
+            threshold: args.threshold,
+            paired: args.paired,
+            total_records,
+            total_bases,
+            prefix_nodes: nodes,
+            genome_estimate,
+            inputs: input_stats,
+        };
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create summary file {:?}", path))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), &summary)
+            .with_context(|| format!("Failed to write summary JSON to {:?}", path))?;
+        println!("Run summary written to {:?}", path);
+    }
+
+    if let Some(path) = &args.profile_json {
+        profile.write(path, run_start)?;
+        println!("Runtime profile written to {:?}", path);
+    }
+
     Ok(())
 }
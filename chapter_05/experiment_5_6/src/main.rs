@@ -1,28 +1,72 @@
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use rayon::prelude::*;
+use rust_htslib::bam::{self, Read, record::{Aux, Cigar, CigarString}};
 use serde::{Serialize, Deserialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::{File, create_dir_all};
-use std::io::{BufWriter, BufReader, Write};
-use std::path::PathBuf;
+use std::io::{BufWriter, BufReader};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 use clap::Parser;
 
-/// Represents a segment of a read alignment, including minimal CIGAR data.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// One aligned segment of a read: the primary alignment or a supplementary one from its SA tag.
+#[derive(Debug, Clone)]
 struct AlignmentSegment {
     read_id: String,
     chrom: String,
+    /// 0-based reference start and exclusive end.
     start: u64,
-    cigar: String,
-    orientation: char, // '+', '-' or more complex
+    end: u64,
+    orientation: char, // '+' or '-'
+    /// Part of the read covered by the segment, in the read's sequencing orientation.
+    read_start: u64,
+    read_end: u64,
+    mapq: u8,
 }
 
-/// Represents a detected breakpoint in naive structural variant analysis.
-#[derive(Debug, Serialize, Deserialize)]
+impl AlignmentSegment {
+    fn new(read_id: &str, chrom: &str, start: u64, reverse: bool, cigar: &[Cigar], mapq: u8) -> Self {
+        let span = CigarSpan::from_ops(cigar);
+        let (read_start, read_end) = if reverse {
+            (span.read_len - span.leading_clip - span.aligned_len, span.read_len - span.leading_clip)
+        } else {
+            (span.leading_clip, span.leading_clip + span.aligned_len)
+        };
+        Self {
+            read_id: read_id.to_string(),
+            chrom: chrom.to_string(),
+            start,
+            end: start + span.ref_len,
+            orientation: if reverse { '-' } else { '+' },
+            read_start,
+            read_end,
+            mapq,
+        }
+    }
+
+    /// Reference position where the read leaves this segment (its 3' end in read orientation).
+    fn exit_pos(&self) -> u64 {
+        if self.orientation == '+' { self.end } else { self.start }
+    }
+
+    /// Reference position where the read enters this segment (its 5' end in read orientation).
+    fn entry_pos(&self) -> u64 {
+        if self.orientation == '+' { self.start } else { self.end }
+    }
+}
+
+/// Represents a detected breakpoint in naive structural variant analysis: the junction between
+/// two consecutive segments of a split read. The lower breakend is stored first, so reads from
+/// either strand of the same molecule report the junction the same way.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct Breakpoint {
     read_id: String,
     chrom: String,
     pos: u64,
+    strand: char,
+    mate_chrom: String,
+    mate_pos: u64,
+    mate_strand: char,
     sv_type: String,
 }
 
@@ -31,11 +75,19 @@ struct Breakpoint {
 #[command(name = "sv_detector")]
 #[command(about = "Naive split-read based structural variant detector in Rust")]
 struct Args {
-    /// Input file containing alignment segments in JSON format.
+    /// Input alignments in BAM, CRAM or SAM format, as written by the aligner.
     #[arg(long)]
     alignment_input: PathBuf,
 
-    /// Number of alignment segments to load per chunk.
+    /// Reference FASTA used to decode CRAM input.
+    #[arg(long)]
+    reference: Option<PathBuf>,
+
+    /// Minimum mapping quality for a primary or supplementary segment to be used.
+    #[arg(long, default_value_t = 0)]
+    min_mapq: u8,
+
+    /// Number of alignment records to load per chunk.
     #[arg(long, default_value_t = 5_000)]
     chunk_size: usize,
 
@@ -46,6 +98,79 @@ struct Args {
     /// Final merged breakpoint results JSON file.
     #[arg(long, default_value = "merged_breakpoints.json")]
     merged_output: PathBuf,
+
+    /// Maximum distance (bp) between breakpoints at each breakend for them to join one event.
+    #[arg(long, default_value_t = 50)]
+    cluster_distance: u64,
+
+    /// Minimum number of distinct supporting reads for an event to be called.
+    #[arg(long, default_value_t = 2)]
+    min_support: usize,
+
+    /// JSON file with one consolidated, genotyped call per event.
+    #[arg(long, default_value = "sv_calls.json")]
+    calls_output: PathBuf,
+
+    /// Write a JSON runtime breakdown (per-stage wall time, bytes, records/sec) to this path on exit.
+    #[arg(long)]
+    profile_json: Option<PathBuf>,
+}
+
+/// Wall time and I/O volume for one stage of the run.
+#[derive(Debug, Serialize)]
+struct StageProfile {
+    stage: String,
+    wall_seconds: f64,
+    bytes_read: u64,
+    bytes_written: u64,
+    records: u64,
+    records_per_sec: f64,
+}
+
+/// Runtime breakdown emitted by `--profile-json`.
+#[derive(Debug, Default, Serialize)]
+struct RunProfile {
+    tool: String,
+    threads: usize,
+    total_wall_seconds: f64,
+    stages: Vec<StageProfile>,
+}
+
+impl RunProfile {
+    fn new() -> Self {
+        Self {
+            tool: env!("CARGO_PKG_NAME").to_string(),
+            threads: rayon::current_num_threads(),
+            ..Default::default()
+        }
+    }
+
+    /// Records a finished stage that started at `started`.
+    fn record(&mut self, stage: &str, started: Instant, records: u64, bytes_read: u64, bytes_written: u64) {
+        let wall_seconds = started.elapsed().as_secs_f64();
+        self.stages.push(StageProfile {
+            stage: stage.to_string(),
+            wall_seconds,
+            bytes_read,
+            bytes_written,
+            records,
+            records_per_sec: if wall_seconds > 0.0 { records as f64 / wall_seconds } else { 0.0 },
+        });
+    }
+
+    fn write(mut self, path: &Path, run_start: Instant) -> Result<()> {
+        self.total_wall_seconds = run_start.elapsed().as_secs_f64();
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create profile file {:?}", path))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), &self)
+            .with_context(|| format!("Failed to write profile JSON to {:?}", path))?;
+        Ok(())
+    }
+}
+
+/// Size of a file on disk, or 0 if it cannot be inspected.
+fn file_len(path: &Path) -> u64 {
+    std::fs::metadata(path).map(|m| m.len()).unwrap_or(0)
 }
 
 /// A partial container holding a batch of breakpoints.
@@ -54,13 +179,120 @@ struct PartialBreakpoints {
     breakpoints: Vec<Breakpoint>,
 }
 
-/// Naive parser for the numeric length in a CIGAR string (e.g., "50M" => 50).
-fn parse_cigar_len(cigar: &str) -> u64 {
-    let len_str = cigar.trim_end_matches(|c: char| !c.is_numeric());
-    len_str.parse::<u64>().unwrap_or(0)
+/// Reference and read lengths covered by an alignment, from its CIGAR operations.
+#[derive(Debug, Default)]
+struct CigarSpan {
+    /// Reference bases consumed (M, D, N, =, X).
+    ref_len: u64,
+    /// Soft and hard clips before the first aligned base.
+    leading_clip: u64,
+    /// Read bases from the first to the last aligned base (M, I, =, X).
+    aligned_len: u64,
+    /// Full read length including all clips.
+    read_len: u64,
+}
+
+impl CigarSpan {
+    fn from_ops(cigar: &[Cigar]) -> Self {
+        let mut span = Self::default();
+        let mut clipped = 0;
+        for op in cigar {
+            match *op {
+                Cigar::Match(len) | Cigar::Equal(len) | Cigar::Diff(len) => {
+                    span.ref_len += len as u64;
+                    span.aligned_len += len as u64;
+                }
+                Cigar::Ins(len) => span.aligned_len += len as u64,
+                Cigar::Del(len) | Cigar::RefSkip(len) => span.ref_len += len as u64,
+                Cigar::SoftClip(len) | Cigar::HardClip(len) => {
+                    if span.aligned_len == 0 && span.ref_len == 0 {
+                        span.leading_clip += len as u64;
+                    }
+                    clipped += len as u64;
+                }
+                Cigar::Pad(_) => {}
+            }
+        }
+        span.read_len = span.aligned_len + clipped;
+        span
+    }
+}
+
+/// Parses an SA tag (`rname,pos,strand,CIGAR,mapQ,NM;...`) into the supplementary segments it lists.
+fn parse_sa_tag(read_id: &str, sa: &str) -> Result<Vec<AlignmentSegment>> {
+    let mut segments = Vec::new();
+    for entry in sa.split(';').filter(|entry| !entry.is_empty()) {
+        let fields: Vec<&str> = entry.split(',').collect();
+        let [chrom, pos, strand, cigar, mapq, _nm] = fields.as_slice() else {
+            bail!("Malformed SA tag entry {:?} in read {}", entry, read_id);
+        };
+        let pos: u64 = pos.parse().with_context(|| format!("Invalid SA position in read {}", read_id))?;
+        let cigar = CigarString::try_from(*cigar)
+            .map_err(|e| anyhow::anyhow!("Invalid SA CIGAR {:?} in read {}: {}", cigar, read_id, e))?;
+        let mapq = mapq.parse().with_context(|| format!("Invalid SA mapping quality in read {}", read_id))?;
+        segments.push(AlignmentSegment::new(read_id, chrom, pos.saturating_sub(1), *strand == "-", &cigar, mapq));
+    }
+    Ok(segments)
+}
+
+/// Collects the segments of a split read from its primary alignment and the supplementary
+/// alignments in its SA tag. Returns no segments for records that cannot yield a breakpoint:
+/// unmapped, secondary, supplementary (covered by the primary's SA tag), duplicate or QC-failed
+/// records, and primaries without an SA tag.
+fn read_segments(record: &bam::Record, target_names: &[String], min_mapq: u8) -> Result<Vec<AlignmentSegment>> {
+    if record.is_unmapped()
+        || record.is_secondary()
+        || record.is_supplementary()
+        || record.is_duplicate()
+        || record.is_quality_check_failed()
+    {
+        return Ok(Vec::new());
+    }
+    let sa = match record.aux(b"SA") {
+        Ok(Aux::String(sa)) => sa,
+        _ => return Ok(Vec::new()),
+    };
+    let read_id = String::from_utf8_lossy(record.qname()).into_owned();
+    let chrom = target_names
+        .get(record.tid() as usize)
+        .with_context(|| format!("Read {} refers to unknown reference id {}", read_id, record.tid()))?;
+    let primary = AlignmentSegment::new(
+        &read_id,
+        chrom,
+        record.pos() as u64,
+        record.is_reverse(),
+        &record.cigar(),
+        record.mapq(),
+    );
+    let mut segments = parse_sa_tag(&read_id, sa)?;
+    segments.push(primary);
+    segments.retain(|segment| segment.mapq >= min_mapq);
+    // Order the segments as they occur along the read.
+    segments.sort_by_key(|segment| (segment.read_start, segment.read_end));
+    Ok(segments)
+}
+
+/// The opposite strand.
+fn flip(strand: char) -> char {
+    if strand == '+' { '-' } else { '+' }
+}
+
+/// SV type implied by a junction whose lower breakend comes first: a forward jump on the plus
+/// strand skips deleted sequence, a backward jump (stored on the minus strand) revisits duplicated
+/// sequence, and a change of strand means an inversion.
+fn infer_sv_type(chrom: &str, strand: char, mate_chrom: &str, mate_strand: char) -> &'static str {
+    if chrom != mate_chrom {
+        "TRA"
+    } else if strand != mate_strand {
+        "INV"
+    } else if strand == '+' {
+        "DEL"
+    } else {
+        "DUP"
+    }
 }
 
-/// Detects breakpoints by examining consecutive segments from the same read.
+/// Detects breakpoints by examining consecutive segments from the same read, ordered along the read.
 fn detect_breakpoints(segments: &[AlignmentSegment]) -> Vec<Breakpoint> {
     // If a single read has multiple alignment segments, we consider naive breakpoints.
     if segments.len() < 2 {
@@ -70,87 +302,273 @@ fn detect_breakpoints(segments: &[AlignmentSegment]) -> Vec<Breakpoint> {
     for window in segments.windows(2) {
         let first = &window[0];
         let second = &window[1];
-        if first.chrom == second.chrom {
-            // Potential 'intra-chromosomal' breakpoint where the first alignment ends
-            let breakpos = first.start + parse_cigar_len(&first.cigar);
-            bps.push(Breakpoint {
-                read_id: first.read_id.clone(),
-                chrom: first.chrom.clone(),
-                pos: breakpos,
-                sv_type: "intra-chr".to_string(),
-            });
-        } else {
-            // If on different chromosomes, a naive translocation guess
-            bps.push(Breakpoint {
-                read_id: first.read_id.clone(),
-                chrom: first.chrom.clone(),
-                pos: first.start,
-                sv_type: "translocation".to_string(),
-            });
+        // The junction joins where the read leaves the first segment to where it enters the second.
+        let mut from = (&first.chrom, first.exit_pos(), first.orientation);
+        let mut to = (&second.chrom, second.entry_pos(), second.orientation);
+        // Read from the other strand, the same junction is crossed backwards with both strands flipped.
+        if (to.0, to.1) < (from.0, from.1) {
+            (from, to) = ((to.0, to.1, flip(to.2)), (from.0, from.1, flip(from.2)));
         }
+        bps.push(Breakpoint {
+            read_id: first.read_id.clone(),
+            chrom: from.0.clone(),
+            pos: from.1,
+            strand: from.2,
+            mate_chrom: to.0.clone(),
+            mate_pos: to.1,
+            mate_strand: to.2,
+            sv_type: infer_sv_type(from.0, from.2, to.0, to.2).to_string(),
+        });
     }
     bps
 }
 
+/// One consolidated structural variant call, built from a cluster of breakpoints.
+#[derive(Debug, Serialize)]
+struct SvCall {
+    chrom: String,
+    /// Median position of the clustered breakpoints at each breakend.
+    pos: u64,
+    mate_chrom: String,
+    mate_pos: u64,
+    sv_type: String,
+    /// Distance between the breakends, for events within one chromosome.
+    svlen: Option<u64>,
+    /// Distinct reads with a junction in the cluster.
+    support: usize,
+    /// Reads spanning the breakends unsplit, averaged over both breakends.
+    ref_reads: u64,
+    allele_fraction: f64,
+    genotype: String,
+    read_ids: Vec<String>,
+}
+
+/// Median of a non-empty list of positions.
+fn median(values: &mut [u64]) -> u64 {
+    values.sort_unstable();
+    values[values.len() / 2]
+}
+
+/// Groups breakpoints of the same type and chromosome pair whose breakends both lie within
+/// `max_distance` of the cluster, and turns every cluster with at least `min_support`
+/// distinct reads into a call (genotyped later).
+fn cluster_breakpoints(mut breakpoints: Vec<Breakpoint>, max_distance: u64, min_support: usize) -> Vec<SvCall> {
+    breakpoints.sort_by(|a, b| {
+        (&a.chrom, &a.mate_chrom, &a.sv_type, a.pos).cmp(&(&b.chrom, &b.mate_chrom, &b.sv_type, b.pos))
+    });
+
+    // Sweep along each chromosome pair; clusters whose last breakpoint is further than
+    // `max_distance` behind the sweep can no longer grow and are closed.
+    let mut clusters: Vec<Vec<Breakpoint>> = Vec::new();
+    let mut open: Vec<usize> = Vec::new();
+    for bp in breakpoints {
+        open.retain(|&idx| {
+            let last = clusters[idx].last().expect("clusters are never empty");
+            (&last.chrom, &last.mate_chrom, &last.sv_type) == (&bp.chrom, &bp.mate_chrom, &bp.sv_type)
+                && bp.pos - last.pos <= max_distance
+        });
+        let joined = open.iter().copied().find(|&idx| {
+            let members = &clusters[idx];
+            let mate_mean = members.iter().map(|m| m.mate_pos).sum::<u64>() / members.len() as u64;
+            bp.mate_pos.abs_diff(mate_mean) <= max_distance
+        });
+        match joined {
+            Some(idx) => clusters[idx].push(bp),
+            None => {
+                open.push(clusters.len());
+                clusters.push(vec![bp]);
+            }
+        }
+    }
+
+    clusters
+        .into_iter()
+        .filter_map(|members| {
+            let mut read_ids: Vec<String> = members.iter().map(|m| m.read_id.clone()).collect();
+            read_ids.sort_unstable();
+            read_ids.dedup();
+            if read_ids.len() < min_support {
+                return None;
+            }
+            let pos = median(&mut members.iter().map(|m| m.pos).collect::<Vec<_>>());
+            let mate_pos = median(&mut members.iter().map(|m| m.mate_pos).collect::<Vec<_>>());
+            let first = &members[0];
+            Some(SvCall {
+                chrom: first.chrom.clone(),
+                pos,
+                mate_chrom: first.mate_chrom.clone(),
+                mate_pos,
+                sv_type: first.sv_type.clone(),
+                svlen: (first.chrom == first.mate_chrom).then(|| mate_pos.abs_diff(pos)),
+                support: read_ids.len(),
+                ref_reads: 0,
+                allele_fraction: 0.0,
+                genotype: "./.".to_string(),
+                read_ids,
+            })
+        })
+        .collect()
+}
+
+/// Bases an unsplit alignment must extend past a breakend on both sides to count as a reference read.
+const SPAN_FLANK: u64 = 10;
+
+/// Counts reference reads at each call's breakends with a second pass over the alignments and
+/// assigns a genotype from the allele fraction support / (support + ref_reads).
+fn genotype_calls(calls: &mut [SvCall], args: &Args, target_names: &[String]) -> Result<u64> {
+    // Breakends per chromosome, sorted by position: (position, call index).
+    let mut breakends: HashMap<&str, Vec<(u64, usize)>> = HashMap::new();
+    for (idx, call) in calls.iter().enumerate() {
+        breakends.entry(call.chrom.as_str()).or_default().push((call.pos, idx));
+        breakends.entry(call.mate_chrom.as_str()).or_default().push((call.mate_pos, idx));
+    }
+    for ends in breakends.values_mut() {
+        ends.sort_unstable();
+    }
+    let supporting: HashSet<&str> = calls.iter().flat_map(|call| call.read_ids.iter().map(String::as_str)).collect();
+
+    let mut reader = open_alignments(args)?;
+    let mut spanning = vec![0u64; calls.len()];
+    let mut total_records = 0u64;
+    loop {
+        let records = read_chunk(&mut reader, args.chunk_size)?;
+        if records.is_empty() {
+            break;
+        }
+        total_records += records.len() as u64;
+        let chunk_spanning = records
+            .par_iter()
+            .fold(
+                || vec![0u64; calls.len()],
+                |mut counts, record| {
+                    if record.is_unmapped()
+                        || record.is_secondary()
+                        || record.is_supplementary()
+                        || record.is_duplicate()
+                        || record.is_quality_check_failed()
+                        || record.mapq() < args.min_mapq
+                    {
+                        return counts;
+                    }
+                    let Some(ends) = target_names
+                        .get(record.tid() as usize)
+                        .and_then(|chrom| breakends.get(chrom.as_str()))
+                    else {
+                        return counts;
+                    };
+                    let start = record.pos() as u64;
+                    let end = record.cigar().end_pos() as u64;
+                    if end < start + 2 * SPAN_FLANK {
+                        return counts;
+                    }
+                    let first = ends.partition_point(|&(pos, _)| pos < start + SPAN_FLANK);
+                    let spanned = ends[first..].iter().take_while(|&&(pos, _)| pos + SPAN_FLANK <= end);
+                    let mut spanned = spanned.peekable();
+                    if spanned.peek().is_none() || supporting.contains(String::from_utf8_lossy(record.qname()).as_ref()) {
+                        return counts;
+                    }
+                    for &(_, idx) in spanned {
+                        counts[idx] += 1;
+                    }
+                    counts
+                },
+            )
+            .reduce(
+                || vec![0u64; calls.len()],
+                |mut a, b| {
+                    a.iter_mut().zip(b).for_each(|(x, y)| *x += y);
+                    a
+                },
+            );
+        spanning.iter_mut().zip(chunk_spanning).for_each(|(x, y)| *x += y);
+    }
+
+    for (call, spanning) in calls.iter_mut().zip(spanning) {
+        // Each breakend is counted on its own, so average the two.
+        call.ref_reads = spanning.div_ceil(2);
+        call.allele_fraction = call.support as f64 / (call.support as u64 + call.ref_reads) as f64;
+        call.genotype = match call.allele_fraction {
+            af if af >= 0.8 => "1/1",
+            af if af >= 0.2 => "0/1",
+            _ => "0/0",
+        }
+        .to_string();
+    }
+    Ok(total_records)
+}
+
 /// Merges two vectors of breakpoints by concatenation.
 fn merge_breakpoints(mut global: Vec<Breakpoint>, mut local: Vec<Breakpoint>) -> Vec<Breakpoint> {
     global.append(&mut local);
     global
 }
 
-/// Reads a specified number of AlignmentSegment records from a JSON source.
-fn read_chunk<R: std::io::Read>(
-    reader: &mut serde_json::StreamDeserializer<'_, serde_json::de::IoRead<R>, AlignmentSegment>,
-    chunk_size: usize,
-) -> Result<Vec<AlignmentSegment>> {
+/// Opens the alignment input; htslib detects BAM, CRAM or SAM from its contents.
+fn open_alignments(args: &Args) -> Result<bam::Reader> {
+    let mut reader = bam::Reader::from_path(&args.alignment_input)
+        .with_context(|| format!("Failed to open alignment input file {:?}", args.alignment_input))?;
+    if let Some(reference) = &args.reference {
+        reader.set_reference(reference)
+            .with_context(|| format!("Failed to set CRAM reference {:?}", reference))?;
+    }
+    Ok(reader)
+}
+
+/// Reads up to `chunk_size` alignment records.
+fn read_chunk<R: bam::Read>(reader: &mut R, chunk_size: usize) -> Result<Vec<bam::Record>> {
     let mut chunk = Vec::with_capacity(chunk_size);
     for _ in 0..chunk_size {
-        if let Some(res) = reader.next() {
-            let record = res?;
-            chunk.push(record);
-        } else {
-            break;
+        let mut record = bam::Record::new();
+        match reader.read(&mut record) {
+            Some(res) => {
+                res.context("Failed to read alignment record")?;
+                chunk.push(record);
+            }
+            None => break,
         }
     }
     Ok(chunk)
 }
 
 fn main() -> Result<()> {
+    let run_start = Instant::now();
     let args = Args::parse();
+    let mut profile = RunProfile::new();
 
     // Ensure the directory for partial outputs exists.
     create_dir_all(&args.partial_output_dir)
         .with_context(|| format!("Failed to create partial output directory {:?}", args.partial_output_dir))?;
 
-    // Open the alignment JSON file and prepare a streaming deserializer.
-    let file = File::open(&args.alignment_input)
-        .with_context(|| format!("Failed to open alignment input file {:?}", args.alignment_input))?;
-    let reader = BufReader::new(file);
-    let mut stream = serde_json::Deserializer::from_reader(reader).into_iter::<AlignmentSegment>();
+    // Open the alignment file.
+    let mut reader = open_alignments(&args)?;
+    let target_names: Vec<String> = reader
+        .header()
+        .target_names()
+        .iter()
+        .map(|name| String::from_utf8_lossy(name).into_owned())
+        .collect();
 
+    let detect_start = Instant::now();
+    let mut total_records = 0u64;
+    let mut partial_bytes = 0u64;
     let mut chunk_index = 0usize;
     loop {
         // Read a chunk of alignment records.
-        let records = read_chunk(&mut stream, args.chunk_size)?;
+        let records = read_chunk(&mut reader, args.chunk_size)?;
         if records.is_empty() {
             break;
         }
+        total_records += records.len() as u64;
 
-        // Group alignments by read ID.
-        let mut read_map: HashMap<String, Vec<AlignmentSegment>> = HashMap::new();
-        for seg in records {
-            read_map.entry(seg.read_id.clone()).or_default().push(seg);
-        }
-
-        // Detect breakpoints in parallel.
-        let partial_breakpoints: Vec<Breakpoint> = read_map
+        // Rebuild each split read from its primary record and SA tag, and detect breakpoints in parallel.
+        let mut split_reads: Vec<Vec<AlignmentSegment>> = records
             .par_iter()
-            .flat_map(|(_read_id, segs)| {
-                // Sort segments by start for consistent ordering
-                let mut ordered = segs.clone();
-                ordered.sort_by_key(|s| s.start);
-                detect_breakpoints(&ordered)
-            })
+            .map(|record| read_segments(record, &target_names, args.min_mapq))
+            .collect::<Result<_>>()?;
+        split_reads.retain(|segments| segments.len() >= 2);
+        let partial_breakpoints: Vec<Breakpoint> = split_reads
+            .par_iter()
+            .flat_map(|segments| detect_breakpoints(segments))
             .collect();
 
         // Serialize these partial breakpoints to disk.
@@ -160,22 +578,25 @@ fn main() -> Result<()> {
             .with_context(|| format!("Failed to create partial breakpoint file {:?}", chunk_path))?;
         serde_json::to_writer(BufWriter::new(chunk_file), &partial_res)
             .with_context(|| format!("Failed to write partial breakpoint data to {:?}", chunk_path))?;
+        partial_bytes += file_len(&chunk_path);
 
         println!(
-            "Processed chunk {} ({} read groups). Wrote partial results to {:?}",
-            chunk_index, read_map.len(), chunk_path
+            "Processed chunk {} ({} records, {} split reads). Wrote partial results to {:?}",
+            chunk_index, records.len(), split_reads.len(), chunk_path
         );
         chunk_index += 1;
     }
+    profile.record("detect", detect_start, total_records, file_len(&args.alignment_input), partial_bytes);
 
     // Now merge all partial outputs into a single file.
+    let merge_start = Instant::now();
     let dir_entries = std::fs::read_dir(&args.partial_output_dir)
         .with_context(|| format!("Failed to read partial output directory {:?}", args.partial_output_dir))?;
 
     let mut merged = Vec::new();
     for entry in dir_entries {
         let path = entry?.path();
-        if path.file_name().map_or(false, |p| p.to_string_lossy().starts_with("partial_breakpoints_")) {
+        if path.file_name().is_some_and(|p| p.to_string_lossy().starts_with("partial_breakpoints_")) {
             let file = File::open(&path)
                 .with_context(|| format!("Failed to open partial breakpoints file {:?}", path))?;
             let partial: PartialBreakpoints = serde_json::from_reader(BufReader::new(file))
@@ -194,6 +615,30 @@ fn main() -> Result<()> {
         merged.len(),
         args.merged_output
     );
+    profile.record("merge", merge_start, merged.len() as u64, partial_bytes, file_len(&args.merged_output));
+
+    // Cluster the breakpoints into events and genotype them against reads spanning the breakends.
+    let call_start = Instant::now();
+    let breakpoint_count = merged.len();
+    let mut calls = cluster_breakpoints(merged, args.cluster_distance, args.min_support);
+    let genotyped_records = genotype_calls(&mut calls, &args, &target_names)?;
+    let calls_file = File::create(&args.calls_output)
+        .with_context(|| format!("Failed to create SV call file {:?}", args.calls_output))?;
+    serde_json::to_writer_pretty(BufWriter::new(calls_file), &calls)
+        .with_context(|| format!("Failed to write SV calls to {:?}", args.calls_output))?;
+    println!(
+        "Clustered {} breakpoints into {} SV calls with at least {} supporting reads. Written to {:?}.",
+        breakpoint_count,
+        calls.len(),
+        args.min_support,
+        args.calls_output
+    );
+    profile.record("call", call_start, genotyped_records, file_len(&args.alignment_input), file_len(&args.calls_output));
+
+    if let Some(path) = &args.profile_json {
+        profile.write(path, run_start)?;
+        println!("Runtime profile written to {:?}", path);
+    }
 
     Ok(())
 }
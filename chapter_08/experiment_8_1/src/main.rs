@@ -1,12 +1,167 @@
 use statrs::distribution::{ChiSquared, ContinuousCDF};
 use polars::prelude::*;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
 use std::io::{BufRead, BufReader};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use clap::{Parser, ValueEnum};
+use serde::Serialize;
+use rust_htslib::bcf::{self, Read};
+use rayon::prelude::*;
+use indicatif::{ProgressBar, ProgressStyle};
 // 'rust-htslib' for VCF/BCF I/O
 // 'statrs' for statistical distributions
 // 'polars' for data frame operations
 
+#[derive(Parser, Debug)]
+#[command(name = "vcf_analysis", about = "Hardy-Weinberg equilibrium testing for VCF sites")]
+struct Args {
+    /// Input VCF file
+    vcf_file: PathBuf,
+
+    /// First position to test (inclusive)
+    #[arg(default_value_t = 0)]
+    start_pos: u64,
+
+    /// Last position to test (inclusive)
+    #[arg(default_value_t = u64::MAX)]
+    end_pos: u64,
+
+    /// Sample-to-population map (`sample<TAB>population` per line) for per-population HWE tests
+    #[arg(long)]
+    populations: Option<PathBuf>,
+
+    /// Write the tested records to this VCF (.vcf, .vcf.gz) or BCF (.bcf), handling sites that fail HWE per --hwe-action
+    #[arg(long)]
+    out_vcf: Option<PathBuf>,
+
+    /// Sites whose HWE p-value in all samples is below this fail HWE (for --out-vcf and --summary-json)
+    #[arg(long, default_value_t = 1e-6)]
+    hwe_threshold: f64,
+
+    /// What to do with failing sites in --out-vcf
+    #[arg(long, value_enum, default_value_t = HweAction::Tag)]
+    hwe_action: HweAction,
+
+    /// Format of the results table, written to <VCF>.hw_results.<csv|parquet|arrow>
+    #[arg(long, value_enum, default_value_t = OutputFormat::Csv)]
+    format: OutputFormat,
+
+    /// Write a JSON summary: sites tested, failing sites per chromosome and a p-value histogram
+    #[arg(long)]
+    summary_json: Option<PathBuf>,
+
+    /// Aggregate HWE failures and heterozygosity in windows of this many bp, written to <VCF>.hw_windows.<ext>
+    #[arg(long)]
+    window_size: Option<u64>,
+
+    /// Distance between window starts in bp (default: the window size, i.e. adjacent windows)
+    #[arg(long, requires = "window_size")]
+    window_step: Option<u64>,
+
+    /// Flag windows as hotspots when at least this fraction of their sites fail HWE
+    #[arg(long, default_value_t = 0.1)]
+    hotspot_fraction: f64,
+
+    /// Only flag windows as hotspots when at least this many of their sites fail HWE
+    #[arg(long, default_value_t = 2)]
+    hotspot_min_failures: u64,
+
+    /// Number of sites read before a batch is tested in parallel
+    #[arg(long, default_value_t = 10_000)]
+    batch_size: usize,
+
+    /// Worker threads for testing and decompression (0 = all cores)
+    #[arg(long, default_value_t = 0)]
+    threads: usize,
+
+    /// Write a JSON runtime breakdown (per-stage wall time, bytes, records/sec) on exit
+    #[arg(long)]
+    profile_json: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum HweAction {
+    /// Add `HWE` to the FILTER column
+    Tag,
+    /// Leave the record out
+    Remove,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum OutputFormat {
+    Csv,
+    Parquet,
+    /// Arrow IPC file
+    Ipc,
+}
+
+impl OutputFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Csv => "csv",
+            OutputFormat::Parquet => "parquet",
+            OutputFormat::Ipc => "arrow",
+        }
+    }
+}
+
+// Timing and I/O counters for one stage of the run
+#[derive(Serialize, Debug)]
+struct StageProfile {
+    stage: String,
+    wall_seconds: f64,
+    bytes_read: u64,
+    bytes_written: u64,
+    records: u64,
+    records_per_sec: f64,
+}
+
+#[derive(Serialize, Debug)]
+struct RunProfile {
+    tool: String,
+    threads: usize,
+    total_wall_seconds: f64,
+    stages: Vec<StageProfile>,
+    chromosomes: Vec<ChromTiming>,
+}
+
+impl RunProfile {
+    fn new() -> Self {
+        RunProfile {
+            tool: env!("CARGO_PKG_NAME").to_string(),
+            threads: rayon::current_num_threads(),
+            total_wall_seconds: 0.0,
+            stages: Vec::new(),
+            chromosomes: Vec::new(),
+        }
+    }
+
+    fn record(&mut self, stage: &str, started: Instant, records: u64, bytes_read: u64, bytes_written: u64) {
+        let wall_seconds = started.elapsed().as_secs_f64();
+        let records_per_sec = if wall_seconds > 0.0 { records as f64 / wall_seconds } else { 0.0 };
+        self.stages.push(StageProfile {
+            stage: stage.to_string(),
+            wall_seconds,
+            bytes_read,
+            bytes_written,
+            records,
+            records_per_sec,
+        });
+    }
+
+    fn write(mut self, path: &Path, run_start: Instant) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.total_wall_seconds = run_start.elapsed().as_secs_f64();
+        serde_json::to_writer_pretty(File::create(path)?, &self)?;
+        Ok(())
+    }
+}
+
+fn file_len(path: &Path) -> u64 {
+    std::fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
+
 fn chi_square_hw(aa: f64, ab: f64, bb: f64, p: f64) -> f64 {
     let total = aa + ab + bb;
     if total == 0.0 {
@@ -23,160 +178,841 @@ fn chi_square_hw(aa: f64, ab: f64, bb: f64, p: f64) -> f64 {
     1.0 - dist.cdf(chi_sq)
 }
 
-// Custom parser for VCF files with potential formatting issues
-fn process_vcf_file(vcf_path: &Path, start_pos: u64, end_pos: u64) -> Result<DataFrame, Box<dyn std::error::Error + Send + Sync>> {
-    // Open the file
-    let file = File::open(vcf_path)?;
-    let reader = BufReader::new(file);
+// Exact HWE test of Wigginton, Cutler & Abecasis (2005): the probability of
+// every heterozygote count possible with the observed allele counts, summed
+// over those no more likely than the observed one
+fn hwe_exact(het: u64, hom_a: u64, hom_b: u64) -> f64 {
+    let hom_rare = hom_a.min(hom_b);
+    let hom_common = hom_a.max(hom_b);
+    let genotypes = het + hom_rare + hom_common;
+    if genotypes == 0 {
+        return 1.0;
+    }
+    let rare_copies = 2 * hom_rare + het;
+    let mut probs = vec![0.0; rare_copies as usize + 1];
     
-    // Parse the file line by line
-    let mut records_data: Vec<(String, u64, String, String, f64)> = Vec::new();
-    let mut sample_indices = Vec::new();
+    // Start from the most likely heterozygote count, which has the parity of
+    // the rare allele count, and walk outwards in steps of two
+    let mut mid = rare_copies * (2 * genotypes - rare_copies) / (2 * genotypes);
+    if mid % 2 != rare_copies % 2 {
+        mid += 1;
+    }
+    probs[mid as usize] = 1.0;
+    let mut sum = 1.0;
     
-    for line in reader.lines() {
-        let line = line?;
-        
-        // Skip comment lines
-        if line.starts_with("##") {
-            continue;
-        }
-        
-        // Process header line
-        if line.starts_with("#CHROM") {
-            let columns: Vec<&str> = line.split_whitespace().collect();
-            
-            // Find sample columns (FORMAT column is at index 8, samples start at 9)
-            if columns.len() > 9 {
-                sample_indices = (9..columns.len()).collect();
+    let (mut hets, mut homr, mut homc) = (mid, (rare_copies - mid) / 2, genotypes - mid - (rare_copies - mid) / 2);
+    while hets >= 2 {
+        let prob = probs[hets as usize] * (hets * (hets - 1)) as f64 / (4 * (homr + 1) * (homc + 1)) as f64;
+        probs[hets as usize - 2] = prob;
+        sum += prob;
+        hets -= 2;
+        homr += 1;
+        homc += 1;
+    }
+    
+    let (mut hets, mut homr, mut homc) = (mid, (rare_copies - mid) / 2, genotypes - mid - (rare_copies - mid) / 2);
+    while hets + 2 <= rare_copies {
+        let prob = probs[hets as usize] * (4 * homr * homc) as f64 / ((hets + 2) * (hets + 1)) as f64;
+        probs[hets as usize + 2] = prob;
+        sum += prob;
+        hets += 2;
+        homr -= 1;
+        homc -= 1;
+    }
+    
+    let observed = probs[het as usize];
+    let p_value = probs.iter().filter(|&&prob| prob <= observed).sum::<f64>() / sum;
+    p_value.min(1.0)
+}
+
+// Below this expected count in any genotype class the chi-square
+// approximation is unreliable and the exact test is reported
+const MIN_EXPECTED_CHISQ: f64 = 5.0;
+
+// Whether an index (.csi or .tbi) sits next to the VCF/BCF file
+fn has_index(vcf_path: &Path) -> bool {
+    ["csi", "tbi"].iter().any(|ext| {
+        let mut index = vcf_path.as_os_str().to_owned();
+        index.push(format!(".{}", ext));
+        Path::new(&index).exists()
+    })
+}
+
+// Samples grouped into populations, from a `sample<TAB>population` file
+struct Populations {
+    names: Vec<String>,
+    by_sample: HashMap<String, usize>,
+}
+
+impl Populations {
+    // Lines starting with '#' are comments; populations keep their file order
+    fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let mut names: Vec<String> = Vec::new();
+        let mut by_sample = HashMap::new();
+        for (line_no, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').map(str::trim).collect();
+            let [sample, population] = fields[..] else {
+                return Err(format!("{}:{}: expected sample<TAB>population", path.display(), line_no + 1).into());
+            };
+            let index = match names.iter().position(|name| name == population) {
+                Some(index) => index,
+                None => {
+                    names.push(population.to_string());
+                    names.len() - 1
+                }
+            };
+            if by_sample.insert(sample.to_string(), index).is_some() {
+                return Err(format!("{}: sample {} is listed twice", path.display(), sample).into());
             }
-            continue;
         }
-        
-        // Process data lines
-        let fields: Vec<&str> = line.split_whitespace().collect();
-        if fields.len() < 10 {
-            // Skip malformed lines
-            continue;
+        Ok(Populations { names, by_sample })
+    }
+}
+
+// Population of each VCF sample, by column; samples missing from the map
+// only count towards ALL
+fn sample_groups(header: &bcf::header::HeaderView, populations: Option<&Populations>) -> Vec<Option<usize>> {
+    let Some(populations) = populations else {
+        return vec![None; header.sample_count() as usize];
+    };
+    let groups: Vec<Option<usize>> = header
+        .samples()
+        .iter()
+        .map(|sample| populations.by_sample.get(String::from_utf8_lossy(sample).as_ref()).copied())
+        .collect();
+    let unassigned = groups.iter().filter(|group| group.is_none()).count();
+    if unassigned > 0 {
+        eprintln!("Warning: {} of {} samples have no population", unassigned, groups.len());
+    }
+    groups
+}
+
+// Diploid genotype counts of one group of samples at one site, and the
+// samples of the group without a diploid call there
+#[derive(Default, Clone, Copy)]
+struct GenotypeCounts {
+    aa: f64,
+    ab: f64,
+    bb: f64,
+    missing: f64,
+}
+
+impl GenotypeCounts {
+    fn add(&mut self, alt_copies: Option<u8>) {
+        match alt_copies {
+            None => self.missing += 1.0,
+            Some(0) => self.aa += 1.0,
+            Some(1) => self.ab += 1.0,
+            Some(_) => self.bb += 1.0,
         }
-        
-        // Extract basic variant information
-        let chrom = fields[0].to_string();
-        let pos: u64 = fields[1].parse()?;
-        
-        // Apply position filter
-        if pos < start_pos || pos > end_pos {
-            continue;
+    }
+
+    fn total(&self) -> f64 {
+        self.aa + self.ab + self.bb
+    }
+
+    // Reference allele frequency
+    fn ref_freq(&self) -> f64 {
+        ((self.aa * 2.0) + self.ab) / (2.0 * self.total())
+    }
+
+    // Frequency of the less common of REF and ALT
+    fn maf(&self) -> f64 {
+        let p = self.ref_freq();
+        p.min(1.0 - p)
+    }
+
+    // Share of the group's samples without a diploid call
+    fn missing_rate(&self) -> f64 {
+        self.missing / (self.total() + self.missing)
+    }
+
+    // Observed heterozygosity among called samples
+    fn het_obs(&self) -> f64 {
+        self.ab / self.total()
+    }
+
+    // Heterozygosity expected under HWE, 2pq
+    fn het_exp(&self) -> f64 {
+        let p = self.ref_freq();
+        2.0 * p * (1.0 - p)
+    }
+
+    // Inbreeding coefficient F = 1 - Hobs/Hexp; positive values mean a
+    // heterozygote deficit, negative values a heterozygote excess. NaN at
+    // monomorphic sites, where no heterozygotes are expected.
+    fn inbreeding_f(&self) -> f64 {
+        let het_exp = self.het_exp();
+        if het_exp == 0.0 {
+            return f64::NAN;
+        }
+        1.0 - self.het_obs() / het_exp
+    }
+
+    // Calculate allele frequency and HW equilibrium with both tests, and pick
+    // the exact test when an expected genotype count is small
+    fn hw_test(&self) -> HweTest {
+        let p = self.ref_freq();
+        let chisq_pvalue = chi_square_hw(self.aa, self.ab, self.bb, p);
+        let exact_pvalue = hwe_exact(self.ab as u64, self.aa as u64, self.bb as u64);
+        let min_expected = p.min(1.0 - p).powi(2) * self.total();
+        if min_expected < MIN_EXPECTED_CHISQ {
+            HweTest { chisq_pvalue, exact_pvalue, test: "exact", pvalue: exact_pvalue }
+        } else {
+            HweTest { chisq_pvalue, exact_pvalue, test: "chisq", pvalue: chisq_pvalue }
+        }
+    }
+}
+
+// Both HWE p-values of one test, and the one reported as `hw_pvalue`
+struct HweTest {
+    chisq_pvalue: f64,
+    exact_pvalue: f64,
+    test: &'static str,
+    pvalue: f64,
+}
+
+// One HWE test: a site in one population, or in all samples ("ALL")
+struct HweRow {
+    chrom: String,
+    pos: u64,
+    ref_allele: String,
+    alt_allele: String,
+    population: String,
+    counts: GenotypeCounts,
+    hwe: HweTest,
+}
+
+// One site read from the VCF: its alleles and each sample's diploid genotype
+// as allele indices (None for missing or non-diploid genotypes)
+struct Site {
+    chrom: String,
+    pos: u64,
+    ref_allele: String,
+    alt_alleles: Vec<String>,
+    genotypes: Vec<Option<[u32; 2]>>,
+}
+
+// Decode a record. Genotypes come from htslib as allele indices, so phasing,
+// ploidy and multi-allelic codes such as 1/2 are read correctly.
+fn decode_site(record: &bcf::Record, pos: u64, sample_count: usize) -> Result<Option<Site>, Box<dyn std::error::Error + Send + Sync>> {
+    // Skip records without a GT field
+    let Ok(genotypes) = record.genotypes() else {
+        return Ok(None);
+    };
+    let genotypes = (0..sample_count)
+        .map(|sample| {
+            let genotype = genotypes.get(sample);
+            match genotype.iter().map(|allele| allele.index()).collect::<Option<Vec<u32>>>()?[..] {
+                [first, second] => Some([first, second]),
+                _ => None,
+            }
+        })
+        .collect();
+    
+    let chrom = match record.rid() {
+        Some(rid) => String::from_utf8_lossy(record.header().rid2name(rid)?).into_owned(),
+        None => String::new(),
+    };
+    let alleles = record.alleles();
+    let ref_allele = String::from_utf8_lossy(alleles[0]).into_owned();
+    let alt_alleles = alleles[1..]
+        .iter()
+        .map(|allele| String::from_utf8_lossy(allele).into_owned())
+        .collect();
+    
+    Ok(Some(Site { chrom, pos, ref_allele, alt_alleles, genotypes }))
+}
+
+// Test a site in all samples and in each population, once per ALT allele.
+// Multi-allelic sites are decomposed: each ALT is tested against all other
+// alleles, so with ALT=G,T the genotype 1/2 is heterozygous for both G and T
+// and 2/2 is homozygous reference in the G row.
+fn test_site(site: &Site, groups: &[Option<usize>], population_names: &[String]) -> Vec<HweRow> {
+    let mut rows = Vec::new();
+    for (alt_index, alt_allele) in (1..).zip(&site.alt_alleles) {
+        // Count copies of this ALT; missing calls and non-diploid genotypes
+        // are counted as missing and left out of the tests
+        let mut all = GenotypeCounts::default();
+        let mut by_population = vec![GenotypeCounts::default(); population_names.len()];
+        for (genotype, group) in site.genotypes.iter().zip(groups) {
+            let alt_copies = genotype.map(|genotype| genotype.iter().filter(|&&index| index == alt_index).count() as u8);
+            all.add(alt_copies);
+            if let Some(group) = group {
+                by_population[*group].add(alt_copies);
+            }
         }
         
-        let ref_allele = fields[3].to_string();
-        let alt_allele = fields[4].to_string();
-        
-        // Find FORMAT field index (typically 8)
-        let format_field = fields[8];
-        let format_columns: Vec<&str> = format_field.split(':').collect();
-        let gt_index = format_columns.iter().position(|&x| x == "GT");
-        
-        if gt_index.is_none() {
-            // Skip if no GT field
+        if all.total() == 0.0 {
             continue;
         }
         
-        // Count genotypes
-        let mut count_aa = 0.0;
-        let mut count_ab = 0.0;
-        let mut count_bb = 0.0;
-        
-        for &sample_idx in &sample_indices {
-            if sample_idx >= fields.len() {
-                continue;
+        let tested = std::iter::once(("ALL", all))
+            .chain(population_names.iter().map(String::as_str).zip(by_population))
+            .filter(|(_, counts)| counts.total() > 0.0);
+        rows.extend(tested.map(|(population, counts)| HweRow {
+            chrom: site.chrom.clone(),
+            pos: site.pos,
+            ref_allele: site.ref_allele.clone(),
+            alt_allele: alt_allele.clone(),
+            population: population.to_string(),
+            counts,
+            hwe: counts.hw_test(),
+        }));
+    }
+    rows
+}
+
+// Build the result columns for a batch of rows; the `population` column is
+// only present when populations were given
+fn rows_frame(rows: &[HweRow], with_population: bool) -> PolarsResult<DataFrame> {
+    let mut columns: Vec<Column> = vec![
+        Series::new("chrom".into(), rows.iter().map(|row| row.chrom.clone()).collect::<Vec<String>>()).into(),
+        Series::new("pos".into(), rows.iter().map(|row| row.pos).collect::<Vec<u64>>()).into(),
+        Series::new("ref_allele".into(), rows.iter().map(|row| row.ref_allele.clone()).collect::<Vec<String>>()).into(),
+        Series::new("alt_allele".into(), rows.iter().map(|row| row.alt_allele.clone()).collect::<Vec<String>>()).into(),
+    ];
+    if with_population {
+        columns.push(
+            Series::new("population".into(), rows.iter().map(|row| row.population.clone()).collect::<Vec<String>>()).into(),
+        );
+    }
+    columns.push(Series::new("maf".into(), rows.iter().map(|row| row.counts.maf()).collect::<Vec<f64>>()).into());
+    columns.push(Series::new("missing_rate".into(), rows.iter().map(|row| row.counts.missing_rate()).collect::<Vec<f64>>()).into());
+    columns.push(Series::new("het_obs".into(), rows.iter().map(|row| row.counts.het_obs()).collect::<Vec<f64>>()).into());
+    columns.push(Series::new("het_exp".into(), rows.iter().map(|row| row.counts.het_exp()).collect::<Vec<f64>>()).into());
+    columns.push(Series::new("inbreeding_f".into(), rows.iter().map(|row| row.counts.inbreeding_f()).collect::<Vec<f64>>()).into());
+    columns.push(Series::new("hw_chisq_pvalue".into(), rows.iter().map(|row| row.hwe.chisq_pvalue).collect::<Vec<f64>>()).into());
+    columns.push(Series::new("hw_exact_pvalue".into(), rows.iter().map(|row| row.hwe.exact_pvalue).collect::<Vec<f64>>()).into());
+    columns.push(Series::new("hw_test".into(), rows.iter().map(|row| row.hwe.test).collect::<Vec<&str>>()).into());
+    columns.push(Series::new("hw_pvalue".into(), rows.iter().map(|row| row.hwe.pvalue).collect::<Vec<f64>>()).into());
+    DataFrame::new(columns)
+}
+
+// Where and how to write the filtered VCF
+struct VcfOutput {
+    path: PathBuf,
+    threshold: f64,
+    action: HweAction,
+}
+
+// Copies the records in the position range to the output VCF, tagging or
+// dropping sites whose `ALL` HWE p-value (for any ALT allele) is below the
+// threshold. Records without genotypes are copied unchanged.
+struct HweFilter<'a> {
+    output: &'a VcfOutput,
+    writer: bcf::Writer,
+    tested: u64,
+    failed: u64,
+}
+
+impl<'a> HweFilter<'a> {
+    fn create(output: &'a VcfOutput, template: &bcf::header::HeaderView, threads: usize) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let mut header = bcf::Header::from_template(template);
+        header.push_record(format!("##FILTER=<ID=HWE,Description=\"HWE p-value below {:e}\">", output.threshold).as_bytes());
+        let name = output.path.to_string_lossy();
+        let (format, uncompressed) = if name.ends_with(".bcf") {
+            (bcf::Format::Bcf, false)
+        } else {
+            (bcf::Format::Vcf, !name.ends_with(".gz"))
+        };
+        let mut writer = bcf::Writer::from_path(&output.path, &header, uncompressed, format)?;
+        if threads > 1 {
+            writer.set_threads(threads)?;
+        }
+        Ok(HweFilter { output, writer, tested: 0, failed: 0 })
+    }
+    
+    fn fails(&self, rows: &[HweRow]) -> bool {
+        rows.iter().any(|row| row.population == "ALL" && row.hwe.pvalue < self.output.threshold)
+    }
+    
+    // Write one record; `failed` is None for records that were not tested
+    fn write(&mut self, record: &mut bcf::Record, failed: Option<bool>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.tested += failed.is_some() as u64;
+        if failed == Some(true) {
+            self.failed += 1;
+            if matches!(self.output.action, HweAction::Remove) {
+                return Ok(());
             }
+            self.writer.translate(record);
+            record.push_filter("HWE".as_bytes())?;
+        } else {
+            self.writer.translate(record);
+        }
+        self.writer.write(record)?;
+        Ok(())
+    }
+    
+    fn report(&self) {
+        let action = match self.output.action {
+            HweAction::Tag => "tagged HWE",
+            HweAction::Remove => "removed",
+        };
+        println!(
+            "{} of {} tested sites fail HWE (p < {:e}), {}; VCF written to {}",
+            self.failed,
+            self.tested,
+            self.output.threshold,
+            action,
+            self.output.path.display()
+        );
+    }
+}
+
+// Sites read and wall time spent on one chromosome, from its first record
+// until its last batch was tested
+#[derive(Serialize, Debug)]
+struct ChromTiming {
+    chrom: String,
+    sites: u64,
+    wall_seconds: f64,
+    #[serde(skip)]
+    started: Instant,
+}
+
+// Reads sites in batches and tests each batch in parallel with rayon, while
+// the result DataFrame grows batch by batch. With a filter, the records of a
+// batch are kept until it is tested and then written in file order.
+struct HweScan<'a> {
+    groups: Vec<Option<usize>>,
+    population_names: &'a [String],
+    with_population: bool,
+    batch_size: usize,
+    progress: ProgressBar,
+    chroms: Vec<ChromTiming>,
+    df: Option<DataFrame>,
+    filter: Option<HweFilter<'a>>,
+}
+
+impl<'a> HweScan<'a> {
+    fn new(populations: Option<&'a Populations>, batch_size: usize) -> Self {
+        let progress = ProgressBar::new_spinner();
+        progress.set_style(
+            ProgressStyle::default_spinner()
+                .template("{spinner:.green} [{elapsed_precise}] {human_pos} sites ({per_sec}) {msg}")
+                .unwrap(),
+        );
+        HweScan {
+            groups: Vec::new(),
+            population_names: populations.map(|populations| populations.names.as_slice()).unwrap_or_default(),
+            with_population: populations.is_some(),
+            batch_size: batch_size.max(1),
+            progress,
+            chroms: Vec::new(),
+            df: None,
+            filter: None,
+        }
+    }
+    
+    // Read every record the reader yields, testing a batch whenever it is full
+    fn scan<R: bcf::Read>(&mut self, reader: &mut R, start_pos: u64, end_pos: u64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut record = reader.empty_record();
+        let mut batch: Vec<Site> = Vec::with_capacity(self.batch_size);
+        // Records to write, with the index of their site in the batch
+        let mut pending: Vec<(bcf::Record, Option<usize>)> = Vec::new();
+        while let Some(result) = reader.read(&mut record) {
+            result?;
             
-            let sample_data = fields[sample_idx];
-            let sample_fields: Vec<&str> = sample_data.split(':').collect();
-            
-            let gt_idx = gt_index.unwrap();
-            if gt_idx >= sample_fields.len() {
+            // Apply position filter (VCF positions are 1-based)
+            let pos = record.pos() as u64 + 1;
+            if pos < start_pos || pos > end_pos {
                 continue;
             }
             
-            let genotype = sample_fields[gt_idx];
-            match genotype {
-                "0/0" | "0|0" => count_aa += 1.0,
-                "0/1" | "1/0" | "0|1" | "1|0" => count_ab += 1.0,
-                "1/1" | "1|1" => count_bb += 1.0,
-                _ => {} // Skip other genotypes like ./. or multi-allelic
+            let site = decode_site(&record, pos, self.groups.len())?;
+            if self.filter.is_some() {
+                pending.push((record.clone(), site.is_some().then_some(batch.len())));
+            }
+            if let Some(site) = site {
+                self.count_site(&site.chrom);
+                batch.push(site);
+                if batch.len() == self.batch_size {
+                    self.test_batch(&mut batch, &mut pending)?;
+                }
             }
         }
-        
-        let total = count_aa + count_ab + count_bb;
-        if total == 0.0 {
-            continue;
+        self.test_batch(&mut batch, &mut pending)?;
+        Ok(())
+    }
+    
+    fn count_site(&mut self, chrom: &str) {
+        if self.chroms.last().is_none_or(|timing| timing.chrom != chrom) {
+            self.progress.set_message(chrom.to_string());
+            self.chroms.push(ChromTiming { chrom: chrom.to_string(), sites: 0, wall_seconds: 0.0, started: Instant::now() });
+        }
+        if let Some(timing) = self.chroms.last_mut() {
+            timing.sites += 1;
+        }
+    }
+    
+    // Test the batch in parallel, write its pending records and append its
+    // rows in file order
+    fn test_batch(&mut self, batch: &mut Vec<Site>, pending: &mut Vec<(bcf::Record, Option<usize>)>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let site_rows: Vec<Vec<HweRow>> = batch
+            .par_iter()
+            .map(|site| test_site(site, &self.groups, self.population_names))
+            .collect();
+        if let Some(filter) = &mut self.filter {
+            for (mut record, site) in pending.drain(..) {
+                let failed = site.map(|site| filter.fails(&site_rows[site]));
+                filter.write(&mut record, failed)?;
+            }
+        }
+        if batch.is_empty() {
+            return Ok(());
         }
         
-        // Calculate allele frequency and HW equilibrium
-        let p = ((count_aa * 2.0) + count_ab) / (2.0 * total);
-        let hw_p = chi_square_hw(count_aa, count_ab, count_bb, p);
+        let rows: Vec<HweRow> = site_rows.into_iter().flatten().collect();
+        let frame = rows_frame(&rows, self.with_population)?;
+        match &mut self.df {
+            Some(df) => {
+                df.vstack_mut(&frame)?;
+            }
+            None => self.df = Some(frame),
+        }
         
-        records_data.push((
-            chrom,
-            pos,
-            ref_allele,
-            alt_allele,
-            hw_p,
-        ));
+        // Every chromosome from the batch's first one onwards has progressed
+        let first = self.chroms.iter().rposition(|timing| timing.chrom == batch[0].chrom).unwrap_or(0);
+        for timing in &mut self.chroms[first..] {
+            timing.wall_seconds = timing.started.elapsed().as_secs_f64();
+        }
+        self.progress.inc(batch.len() as u64);
+        batch.clear();
+        Ok(())
     }
     
-    // Create DataFrame from the parsed data
-    let chrom_series = Series::new("chrom".into(), records_data.iter().map(|x| x.0.clone()).collect::<Vec<String>>());
-    let pos_series = Series::new("pos".into(), records_data.iter().map(|x| x.1).collect::<Vec<u64>>());
-    let ref_series = Series::new("ref_allele".into(), records_data.iter().map(|x| x.2.clone()).collect::<Vec<String>>());
-    let alt_series = Series::new("alt_allele".into(), records_data.iter().map(|x| x.3.clone()).collect::<Vec<String>>());
-    let hw_series = Series::new("hw_pvalue".into(), records_data.iter().map(|x| x.4).collect::<Vec<f64>>());
+    fn finish(self) -> PolarsResult<(DataFrame, Vec<ChromTiming>)> {
+        self.progress.finish_and_clear();
+        if let Some(filter) = &self.filter {
+            filter.report();
+        }
+        let mut df = match self.df {
+            Some(df) => df,
+            None => rows_frame(&[], self.with_population)?,
+        };
+        df.as_single_chunk_par();
+        Ok((df, self.chroms))
+    }
+}
+
+// Read a VCF, bgzipped VCF or BCF file with htslib. When the file is indexed,
+// only the position range of each contig is fetched; otherwise every record
+// is read and filtered by position. With populations, each site gets a row
+// for all samples and one per population, and a `population` column. With an
+// output VCF, the records in the range are written to it as they are tested.
+fn process_vcf_file(
+    vcf_path: &Path,
+    start_pos: u64,
+    end_pos: u64,
+    populations: Option<&Populations>,
+    batch_size: usize,
+    out_vcf: Option<&VcfOutput>,
+) -> Result<(DataFrame, Vec<ChromTiming>), Box<dyn std::error::Error + Send + Sync>> {
+    let mut scan = HweScan::new(populations, batch_size);
+    // htslib decompresses bgzipped input on the rayon thread count as well
+    let threads = rayon::current_num_threads();
+    
+    if has_index(vcf_path) && end_pos > 0 {
+        let mut reader = bcf::IndexedReader::from_path(vcf_path)?;
+        if threads > 1 {
+            reader.set_threads(threads)?;
+        }
+        scan.groups = sample_groups(reader.header(), populations);
+        scan.filter = out_vcf.map(|output| HweFilter::create(output, reader.header(), threads)).transpose()?;
+        // fetch takes 0-based, inclusive coordinates
+        let start = start_pos.saturating_sub(1);
+        let end = (end_pos != u64::MAX).then(|| end_pos - 1);
+        for rid in 0..reader.header().contig_count() {
+            // Contigs without records have no entry in the index
+            if reader.fetch(rid, start, end).is_err() {
+                continue;
+            }
+            scan.scan(&mut reader, start_pos, end_pos)?;
+        }
+    } else {
+        let mut reader = bcf::Reader::from_path(vcf_path)?;
+        if threads > 1 {
+            reader.set_threads(threads)?;
+        }
+        scan.groups = sample_groups(reader.header(), populations);
+        scan.filter = out_vcf.map(|output| HweFilter::create(output, reader.header(), threads)).transpose()?;
+        scan.scan(&mut reader, start_pos, end_pos)?;
+    }
     
-    let df = DataFrame::new(vec![
-        chrom_series.into(),
-        pos_series.into(),
-        ref_series.into(),
-        alt_series.into(),
-        hw_series.into(),
-    ])?;
+    Ok(scan.finish()?)
+}
+
+fn write_results(df: &mut DataFrame, path: &Path, format: OutputFormat) -> PolarsResult<()> {
+    let file = File::create(path)?;
+    match format {
+        OutputFormat::Csv => CsvWriter::new(file).finish(df)?,
+        OutputFormat::Parquet => {
+            ParquetWriter::new(file).finish(df)?;
+        }
+        OutputFormat::Ipc => IpcWriter::new(file).finish(df)?,
+    }
+    Ok(())
+}
+
+// Sites tested and failing HWE on one chromosome
+#[derive(Serialize, Debug)]
+struct ChromSummary {
+    chrom: String,
+    sites_tested: u64,
+    failing_sites: u64,
+}
+
+// Number of p-values in [lower, upper); the last bin includes 1.0
+#[derive(Serialize, Debug)]
+struct HistogramBin {
+    lower: f64,
+    upper: f64,
+    count: u64,
+}
+
+// Overview of the `ALL` rows, one per site and ALT allele
+#[derive(Serialize, Debug)]
+struct HweSummary {
+    sites_tested: u64,
+    hwe_threshold: f64,
+    failing_sites: u64,
+    chromosomes: Vec<ChromSummary>,
+    pvalue_histogram: Vec<HistogramBin>,
+}
+
+const HISTOGRAM_BINS: usize = 10;
+
+// The rows testing all samples, without the per-population ones
+fn all_rows(df: &DataFrame) -> PolarsResult<DataFrame> {
+    if df.column("population").is_ok() {
+        df.clone().lazy().filter(col("population").eq(lit("ALL"))).collect()
+    } else {
+        Ok(df.clone())
+    }
+}
+
+fn summarize(df: &DataFrame, threshold: f64) -> PolarsResult<HweSummary> {
+    let all = all_rows(df)?;
     
-    Ok(df)
+    let mut summary = HweSummary {
+        sites_tested: 0,
+        hwe_threshold: threshold,
+        failing_sites: 0,
+        chromosomes: Vec::new(),
+        pvalue_histogram: (0..HISTOGRAM_BINS)
+            .map(|bin| HistogramBin {
+                lower: bin as f64 / HISTOGRAM_BINS as f64,
+                upper: (bin + 1) as f64 / HISTOGRAM_BINS as f64,
+                count: 0,
+            })
+            .collect(),
+    };
+    for (chrom, pvalue) in all.column("chrom")?.str()?.into_iter().zip(all.column("hw_pvalue")?.f64()?) {
+        let (Some(chrom), Some(pvalue)) = (chrom, pvalue) else {
+            continue;
+        };
+        // Rows of a chromosome are contiguous in file order
+        if summary.chromosomes.last().is_none_or(|summary| summary.chrom != chrom) {
+            summary.chromosomes.push(ChromSummary { chrom: chrom.to_string(), sites_tested: 0, failing_sites: 0 });
+        }
+        let failing = pvalue < threshold;
+        if let Some(chrom_summary) = summary.chromosomes.last_mut() {
+            chrom_summary.sites_tested += 1;
+            chrom_summary.failing_sites += failing as u64;
+        }
+        summary.sites_tested += 1;
+        summary.failing_sites += failing as u64;
+        if (0.0..=1.0).contains(&pvalue) {
+            let bin = ((pvalue * HISTOGRAM_BINS as f64) as usize).min(HISTOGRAM_BINS - 1);
+            summary.pvalue_histogram[bin].count += 1;
+        }
+    }
+    Ok(summary)
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() < 2 {
-        eprintln!("Usage: {} <vcf_file> [start_pos] [end_pos]", args[0]);
-        std::process::exit(1);
+// Window sizes and the rule for flagging hotspots
+struct WindowSettings {
+    size: u64,
+    step: u64,
+    threshold: f64,
+    hotspot_fraction: f64,
+    hotspot_min_failures: u64,
+}
+
+// Sums over the sites of one window
+#[derive(Default)]
+struct WindowStats {
+    sites: u64,
+    failing_sites: u64,
+    het_obs: f64,
+    het_exp: f64,
+}
+
+// Aggregate the `ALL` rows in windows of `size` bp starting every `step` bp
+// (1-based, so the first window is 1..=size). Only windows with sites are
+// reported. A window is a hotspot when enough of its sites fail HWE; a high
+// het_obs_exp_ratio there points to collapsed paralogs or other mapping
+// artifacts, a low one to deletions or CNVs.
+fn window_report(df: &DataFrame, settings: &WindowSettings) -> PolarsResult<DataFrame> {
+    let all = all_rows(df)?;
+    let step = settings.step.max(1);
+    let size = settings.size.max(1);
+    
+    // Windows per chromosome, keyed by window index; chromosomes keep file order
+    let mut chroms: Vec<(String, BTreeMap<u64, WindowStats>)> = Vec::new();
+    let rows = all.column("chrom")?.str()?.into_iter()
+        .zip(all.column("pos")?.u64()?)
+        .zip(all.column("het_obs")?.f64()?)
+        .zip(all.column("het_exp")?.f64()?)
+        .zip(all.column("hw_pvalue")?.f64()?);
+    for ((((chrom, pos), het_obs), het_exp), pvalue) in rows {
+        let (Some(chrom), Some(pos)) = (chrom, pos) else {
+            continue;
+        };
+        if chroms.last().is_none_or(|(name, _)| name != chrom) {
+            chroms.push((chrom.to_string(), BTreeMap::new()));
+        }
+        let Some((_, windows)) = chroms.last_mut() else {
+            continue;
+        };
+        // Window k covers k*step+1 ..= k*step+size
+        let first = pos.saturating_sub(size).div_ceil(step);
+        let last = (pos - 1) / step;
+        for index in first..=last {
+            let stats = windows.entry(index).or_default();
+            stats.sites += 1;
+            stats.failing_sites += pvalue.is_some_and(|pvalue| pvalue < settings.threshold) as u64;
+            stats.het_obs += het_obs.unwrap_or(0.0);
+            stats.het_exp += het_exp.unwrap_or(0.0);
+        }
     }
     
-    let vcf_path = Path::new(&args[1]);
-    let start_pos: u64 = if args.len() > 2 { args[2].parse()? } else { 0 };
-    let end_pos: u64 = if args.len() > 3 { args[3].parse()? } else { u64::MAX };
+    let mut chrom_column = Vec::new();
+    let mut start = Vec::new();
+    let mut end = Vec::new();
+    let mut sites = Vec::new();
+    let mut failing_sites = Vec::new();
+    let mut failing_fraction = Vec::new();
+    let mut mean_het_obs = Vec::new();
+    let mut mean_het_exp = Vec::new();
+    let mut ratio = Vec::new();
+    let mut hotspot = Vec::new();
+    for (chrom, windows) in &chroms {
+        for (index, stats) in windows {
+            let fraction = stats.failing_sites as f64 / stats.sites as f64;
+            chrom_column.push(chrom.clone());
+            start.push(index * step + 1);
+            end.push(index * step + size);
+            sites.push(stats.sites);
+            failing_sites.push(stats.failing_sites);
+            failing_fraction.push(fraction);
+            mean_het_obs.push(stats.het_obs / stats.sites as f64);
+            mean_het_exp.push(stats.het_exp / stats.sites as f64);
+            ratio.push(stats.het_obs / stats.het_exp);
+            hotspot.push(stats.failing_sites >= settings.hotspot_min_failures && fraction >= settings.hotspot_fraction);
+        }
+    }
+    DataFrame::new(vec![
+        Series::new("chrom".into(), chrom_column).into(),
+        Series::new("start".into(), start).into(),
+        Series::new("end".into(), end).into(),
+        Series::new("sites".into(), sites).into(),
+        Series::new("failing_sites".into(), failing_sites).into(),
+        Series::new("failing_fraction".into(), failing_fraction).into(),
+        Series::new("mean_het_obs".into(), mean_het_obs).into(),
+        Series::new("mean_het_exp".into(), mean_het_exp).into(),
+        Series::new("het_obs_exp_ratio".into(), ratio).into(),
+        Series::new("hotspot".into(), hotspot).into(),
+    ])
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let run_start = Instant::now();
+    let args = Args::parse();
+    rayon::ThreadPoolBuilder::new().num_threads(args.threads).build_global()?;
+    let mut profile = RunProfile::new();
+
+    let vcf_path = args.vcf_file.as_path();
+    let start_pos = args.start_pos;
+    let end_pos = args.end_pos;
     
     println!("Processing VCF file: {}", vcf_path.display());
     println!("Position range: {} - {}", start_pos, end_pos);
     
-    // Process the VCF file using our custom parser
-    match process_vcf_file(vcf_path, start_pos, end_pos) {
-        Ok(mut df) => {  // Make df mutable
+    let populations = args.populations.as_deref().map(Populations::load).transpose()?;
+    if let Some(populations) = &populations {
+        println!("Populations: {}", populations.names.join(", "));
+    }
+    
+    // Test the VCF sites read with htslib
+    let stage_start = Instant::now();
+    let out_vcf = args.out_vcf.map(|path| VcfOutput { path, threshold: args.hwe_threshold, action: args.hwe_action });
+    match process_vcf_file(vcf_path, start_pos, end_pos, populations.as_ref(), args.batch_size, out_vcf.as_ref()) {
+        Ok((mut df, chromosomes)) => {  // Make df mutable
+            profile.record("hwe_test", stage_start, df.height() as u64, file_len(vcf_path), 0);
             println!("Analysis complete. Results:");
             println!("{}", df);
+            println!("Per-chromosome timing:");
+            for timing in &chromosomes {
+                println!("  {}: {} sites in {:.2}s", timing.chrom, timing.sites, timing.wall_seconds);
+            }
+            profile.chromosomes = chromosomes;
             
-            // Save results to CSV using the proper method with mutable reference
-            let output_path = format!("{}.hw_results.csv", vcf_path.display());
-            match CsvWriter::new(File::create(&output_path)?)
-                .finish(&mut df) {  // Pass a mutable reference
-                Ok(_) => println!("Results saved to {}", output_path),
+            // Save results in the requested format using a mutable reference
+            let stage_start = Instant::now();
+            let output_path = format!("{}.hw_results.{}", vcf_path.display(), args.format.extension());
+            match write_results(&mut df, Path::new(&output_path), args.format) {
+                Ok(()) => println!("Results saved to {}", output_path),
                 Err(e) => eprintln!("Failed to save results: {}", e),
             }
+            profile.record("write", stage_start, df.height() as u64, 0, file_len(Path::new(&output_path)));
+            
+            if let Some(size) = args.window_size {
+                let stage_start = Instant::now();
+                let settings = WindowSettings {
+                    size,
+                    step: args.window_step.unwrap_or(size),
+                    threshold: args.hwe_threshold,
+                    hotspot_fraction: args.hotspot_fraction,
+                    hotspot_min_failures: args.hotspot_min_failures,
+                };
+                let mut windows = window_report(&df, &settings)?;
+                let hotspots = windows.clone().lazy().filter(col("hotspot")).collect()?;
+                println!("HWE-failure hotspots ({} of {} windows):", hotspots.height(), windows.height());
+                println!("{}", hotspots);
+                let windows_path = format!("{}.hw_windows.{}", vcf_path.display(), args.format.extension());
+                write_results(&mut windows, Path::new(&windows_path), args.format)?;
+                println!("Window report saved to {}", windows_path);
+                profile.record("windows", stage_start, windows.height() as u64, 0, file_len(Path::new(&windows_path)));
+            }
+            
+            if let Some(summary_path) = &args.summary_json {
+                let summary = summarize(&df, args.hwe_threshold)?;
+                serde_json::to_writer_pretty(File::create(summary_path)?, &summary)?;
+                println!(
+                    "{} of {} sites fail HWE (p < {:e}); summary written to {}",
+                    summary.failing_sites,
+                    summary.sites_tested,
+                    args.hwe_threshold,
+                    summary_path.display()
+                );
+            }
         },
         Err(e) => {
             eprintln!("Error processing VCF file: {}", e);
             return Err(e);
         }
     }
+
+    if let Some(profile_path) = &args.profile_json {
+        profile.write(profile_path, run_start)?;
+        println!("Runtime profile written to {}", profile_path.display());
+    }
     
     Ok(())
-}
\ No newline at end of file
+}
@@ -1,10 +1,12 @@
 use anyhow::{anyhow, Context, Result};
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use indicatif::{ProgressBar, ProgressStyle};
 use std::{
-    collections::HashMap,
+    cmp::Reverse,
+    collections::{hash_map::DefaultHasher, BTreeMap, BinaryHeap, HashMap, HashSet, VecDeque},
+    hash::{Hash, Hasher},
     fs::File,
-    io::{BufReader, BufWriter},
+    io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom},
     path::{Path, PathBuf},
     sync::{Arc, Mutex},
     time::Instant,
@@ -12,7 +14,30 @@ use std::{
 use thiserror::Error;
 use tracing::{debug, info, warn, Level};
 use polars::prelude::*;
+use noodles::{
+    bam, bcf, bgzf,
+    core::{Position, Region as QueryRegion},
+    cram, csi, fasta, sam,
+    sam::alignment::record::{cigar::op::Kind, Flags},
+    tabix, vcf,
+    vcf::header::record::value::{
+        map::{info, format, AlternativeAllele, Contig, Filter, Format, Info},
+        Map,
+    },
+    vcf::variant::io::Write as _,
+    vcf::variant::record_buf::{
+        samples::{keys::key as format_key, sample::Value as SampleValue, Keys},
+        info::field::Value as InfoValue,
+        AlternateBases, Filters, Info as InfoFields, Samples,
+    },
+    vcf::variant::record::info::field::key as info_key,
+    vcf::variant::RecordBuf,
+};
 use serde::{Deserialize, Serialize};
+use statrs::function::factorial::ln_factorial;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use std::fmt::Write as _;
 use std::str::FromStr;
 
 /// Custom error type
@@ -30,22 +55,32 @@ pub enum CallerError {
 #[derive(Parser, Debug)]
 #[clap(author, version, about = "Fast variant caller implemented in Rust")]
 struct Cli {
-    /// BAM file path
+    /// Coordinate-sorted BAM (.bai/.csi index) or CRAM (.crai index)
     #[arg(short, long)]
     bam: PathBuf,
 
-    /// Reference FASTA (index .fai required)
+    /// Reference FASTA (index .fai required); supplies reference bases, CRAM
+    /// decoding and the homopolymer/STR context columns
     #[arg(short, long)]
-    fasta: Option<PathBuf>,
+    fasta: PathBuf,
 
     /// Region filter (chr:start-end)
     #[arg(short, long)]
     region: Option<String>,
 
-    /// Output Parquet
+    /// BED file of target intervals (exome, panel); intervals are merged and
+    /// called instead of whole contigs, with per-target stats
+    #[arg(long, conflicts_with = "region")]
+    bed: Option<PathBuf>,
+
+    /// Output file (Parquet, bgzipped VCF or BCF; see --format)
     #[arg(short, long)]
     out: PathBuf,
 
+    /// Output format; vcf is bgzipped with a tabix index, bcf gets a CSI index
+    #[arg(long, value_enum, default_value_t = OutputFormat::Parquet)]
+    format: OutputFormat,
+
     /// Minimum read depth
     #[arg(long, default_value_t = 8)]
     min_depth: usize,
@@ -62,10 +97,67 @@ struct Cli {
     #[arg(long, default_value_t = 20)]
     min_baseq: u8,
 
+    /// Minimum genotype quality for indel calls
+    #[arg(long, default_value_t = 30.0)]
+    min_indel_gq: f32,
+
+    /// Minimum mean mapping quality of the reads supporting an indel
+    #[arg(long, default_value_t = 30)]
+    min_indel_mapq: u8,
+
+    /// Write a gVCF: reference blocks between variant sites and a <NON_REF>
+    /// allele on every record (needs --format vcf or bcf, no --filter-expr)
+    #[arg(long)]
+    gvcf: bool,
+
+    /// Lower GQ bounds of the gVCF reference-block bands; adjacent
+    /// non-variant positions share a block while their GQ stays in one band
+    #[arg(long, value_delimiter = ',', default_value = "1,10,20,30,40,60,99")]
+    gvcf_gq_bands: Vec<u32>,
+
+    /// Locally realign reads around candidate indels before piling them up
+    /// (a second pass over each window)
+    #[arg(long)]
+    realign: bool,
+
+    /// Matched normal BAM/CRAM (indexed); enables tumor-normal somatic calling
+    #[arg(long)]
+    normal_bam: Option<PathBuf>,
+
+    /// Minimum tumor log-odds of the ALT allele (paired mode; replaces the
+    /// diploid GT/GQ gate so subclonal variants are kept)
+    #[arg(long, default_value_t = 6.3)]
+    min_tlod: f32,
+
+    /// Minimum normal depth to call a variant somatic
+    #[arg(long, default_value_t = 8)]
+    min_normal_depth: usize,
+
+    /// Maximum ALT fraction in the normal for a somatic call
+    #[arg(long, default_value_t = 0.03)]
+    max_normal_vaf: f32,
+
+    /// Maximum one-sided Fisher's exact p-value (tumor vs normal ALT fraction)
+    #[arg(long, default_value_t = 0.01)]
+    max_somatic_p: f64,
+
+    /// Cap coverage at this many reads per position by reproducible random
+    /// downsampling of reads that share an alignment start (0 = no cap)
+    #[arg(long, default_value_t = 0)]
+    max_depth: usize,
+
+    /// Seed for --max-depth downsampling
+    #[arg(long, default_value_t = 0)]
+    downsample_seed: u64,
+
     /// Threads (0=auto)
     #[arg(short, long, default_value_t = 0)]
     threads: usize,
 
+    /// Bases per calling window; windows are the unit of parallel work
+    #[arg(long, default_value_t = 1_000_000)]
+    window_size: usize,
+
     /// Verbose logging
     #[arg(short, long)]
     verbose: bool,
@@ -73,8 +165,190 @@ struct Cli {
     /// Export stats JSON
     #[arg(long)]
     stats: Option<PathBuf>,
+
+    /// Keep only calls matching this expression, e.g.
+    /// "vaf>0.05 && depth>=20 && strand_bias>0.5" (fields are the Parquet columns)
+    #[arg(long)]
+    filter_expr: Option<String>,
+
+    /// Also render the run report (stats plus per-contig tables) as Markdown
+    #[arg(long)]
+    report_md: Option<PathBuf>,
+
+    /// Write per-stage runtime breakdown JSON on exit
+    #[arg(long)]
+    profile_json: Option<PathBuf>,
+}
+
+/// Call output formats
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum OutputFormat { Parquet, Vcf, Bcf }
+
+/// Variant class of a call
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum VariantKind { #[default] Snv, Mnp, Insertion, Deletion }
+
+impl VariantKind {
+    fn of(ref_allele: &str, alt_allele: &str) -> Self {
+        match (ref_allele.len(), alt_allele.len()) {
+            (1, 1) => VariantKind::Snv,
+            (r, a) if r == a => VariantKind::Mnp,
+            (r, a) if a > r => VariantKind::Insertion,
+            _ => VariantKind::Deletion,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            VariantKind::Snv => "snv",
+            VariantKind::Mnp => "mnp",
+            VariantKind::Insertion => "insertion",
+            VariantKind::Deletion => "deletion",
+        }
+    }
+}
+
+/// Prior probability of a heterozygous site (hom-alt gets half of it)
+const HETEROZYGOSITY: f64 = 1e-3;
+
+/// Phred-scaled chance that a read is assigned to the wrong indel/MNP allele
+const ALLELE_ERROR_QUAL: f64 = 30.0;
+
+/// Genotype quality cap, as in GATK
+const MAX_GQ: f32 = 99.0;
+
+/// Diploid genotype of one biallelic site
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+struct Genotype {
+    /// 0/0, 0/1 or 1/1
+    gt: String,
+    /// Phred-scaled likelihoods of 0/0, 0/1, 1/1, normalized to the best
+    pl: [u32; 3],
+    /// Second-smallest PL, capped at `MAX_GQ`
+    gq: f32,
+    /// Phred-scaled posterior that the site is hom-ref
+    qual: f32,
+}
+
+impl Genotype {
+    /// Genotype from log10 likelihoods of 0/0, 0/1, 1/1. PL and GQ come
+    /// from the likelihoods alone; QUAL uses the heterozygosity prior.
+    fn from_log10_likelihoods(gl: [f64; 3]) -> Self {
+        let best = (0..3).fold(0, |best, g| if gl[g] > gl[best] { g } else { best });
+        let pl = gl.map(|l| (-10.0 * (l - gl[best])).round().min(u32::MAX as f64) as u32);
+        let mut sorted = pl;
+        sorted.sort_unstable();
+        let gq = (sorted[1] as f32).min(MAX_GQ);
+
+        let priors = [1.0 - 1.5 * HETEROZYGOSITY, HETEROZYGOSITY, HETEROZYGOSITY / 2.0];
+        let log_post: Vec<f64> = gl.iter().zip(priors).map(|(l, p)| l + p.log10()).collect();
+        let max = log_post.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        let log_total = max + log_post.iter().map(|l| 10f64.powf(l - max)).sum::<f64>().log10();
+        let qual = (-10.0 * (log_post[0] - log_total)) as f32;
+
+        Genotype { gt: ["0/0", "0/1", "1/1"][best].into(), pl, gq, qual }
+    }
+}
+
+/// log10 likelihoods of 0/0, 0/1, 1/1 when `alt` of `depth` reads carry the
+/// allele and each read is misassigned with probability `err` (binomial
+/// model; the binomial coefficient is shared and dropped)
+fn binomial_log10_likelihoods(depth: u32, alt: u32, err: f64) -> [f64; 3] {
+    let (k, r) = (alt as f64, depth.saturating_sub(alt) as f64);
+    [
+        r * (1.0 - err).log10() + k * err.log10(),
+        (r + k) * 0.5f64.log10(),
+        r * err.log10() + k * (1.0 - err).log10(),
+    ]
+}
+
+/// [hom, het, absent] log10 terms of `n` reads under the binomial allele
+/// model, in the layout of `PileupEntry::base_likelihoods`
+fn binomial_terms(n: u32, err: f64) -> [f64; 3] {
+    let n = n as f64;
+    [n * (1.0 - err).log10(), n * 0.5f64.log10(), n * err.log10()]
+}
+
+/// gVCF allele used for every allele not named in REF or ALT
+const NON_REF: &str = "<NON_REF>";
+
+/// PLs of 0/0, 0/1, 1/1, 0/2, 1/2, 2/2 where allele 2 is <NON_REF>, from the
+/// [hom, het, absent] terms of the reads supporting REF, ALT and anything
+/// else. The first three match the biallelic model's likelihoods.
+fn non_ref_pl(terms: &[[f64; 3]; 3]) -> [u32; 6] {
+    const GENOTYPES: [(usize, usize); 6] = [(0, 0), (0, 1), (1, 1), (0, 2), (1, 2), (2, 2)];
+    let gl = GENOTYPES.map(|(i, j)| (0..3).map(|x| terms[x][2 - (x == i) as usize - (x == j) as usize]).sum::<f64>());
+    let best = gl.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    gl.map(|l| (-10.0 * (l - best)).round().min(u32::MAX as f64) as u32)
+}
+
+/// Tumor log10 odds that the ALT allele is present at its observed fraction
+/// rather than absent (every ALT read a sequencing error with rate `err`)
+fn tumor_lod(depth: u32, alt: u32, err: f64) -> f32 {
+    if depth == 0 || alt == 0 { return 0.0; }
+    let (k, r) = (alt as f64, depth.saturating_sub(alt) as f64);
+    let log_l = |f: f64| {
+        k * (f * (1.0 - err) + (1.0 - f) * err / 3.0).log10() + r * (f * err / 3.0 + (1.0 - f) * (1.0 - err)).log10()
+    };
+    (log_l(k / (k + r)) - log_l(0.0)) as f32
+}
+
+/// One-sided Fisher's exact test that the tumor ALT fraction exceeds the
+/// normal one: P(X >= tumor_alt) under the hypergeometric null
+fn fisher_greater(tumor_alt: u32, tumor_ref: u32, normal_alt: u32, normal_ref: u32) -> f64 {
+    let (a, b, c, d) = (tumor_alt as u64, tumor_ref as u64, normal_alt as u64, normal_ref as u64);
+    let (total, alt_total, tumor_total) = (a + b + c + d, a + c, a + b);
+    let ln_choose = |n: u64, k: u64| ln_factorial(n) - ln_factorial(k) - ln_factorial(n - k);
+    let ln_denom = ln_choose(total, tumor_total);
+    (a..=alt_total.min(tumor_total))
+        .map(|x| (ln_choose(alt_total, x) + ln_choose(total - alt_total, tumor_total - x) - ln_denom).exp())
+        .sum::<f64>()
+        .min(1.0)
+}
+
+/// FILTER values of paired calls that are not somatic, with their VCF
+/// header descriptions
+const SOMATIC_FILTERS: [(&str, &str); 3] = [
+    ("low_normal_depth", "Normal depth below --min-normal-depth"),
+    ("normal_evidence", "Normal ALT fraction above --max-normal-vaf"),
+    ("not_significant", "Tumor vs normal Fisher's exact p-value above --max-somatic-p"),
+];
+
+/// Matched-normal evidence for one tumor call
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+struct SomaticEvidence {
+    normal_depth: u32,
+    normal_alt_count: u32,
+    /// One-sided Fisher's exact p-value of tumor vs normal ALT fraction
+    fisher_p: f64,
+    /// PASS for somatic calls, otherwise one of `SOMATIC_FILTERS`
+    filter: String,
+}
+
+impl SomaticEvidence {
+    fn score(call: &Call, normal_depth: u32, normal_alt: u32, cli: &Cli) -> Self {
+        let fisher_p = fisher_greater(call.alt_count, call.ref_count, normal_alt, normal_depth.saturating_sub(normal_alt));
+        let normal_vaf = if normal_depth > 0 { normal_alt as f32 / normal_depth as f32 } else { 0.0 };
+        let filter = if normal_depth < cli.min_normal_depth as u32 {
+            SOMATIC_FILTERS[0].0
+        } else if normal_vaf > cli.max_normal_vaf {
+            SOMATIC_FILTERS[1].0
+        } else if fisher_p > cli.max_somatic_p {
+            SOMATIC_FILTERS[2].0
+        } else {
+            "PASS"
+        };
+        SomaticEvidence { normal_depth, normal_alt_count: normal_alt, fisher_p, filter: filter.into() }
+    }
+
+    fn is_somatic(&self) -> bool { self.filter == "PASS" }
 }
 
+/// Reads carrying one multi-base allele (indel or MNP) anchored at a position
+#[derive(Debug, Default)]
+struct AlleleSupport { count: u32, forward: u32, total_mapq: u32 }
+
 /// Pileup entry for a single position
 #[derive(Debug, Default)]
 struct PileupEntry {
@@ -84,6 +358,11 @@ struct PileupEntry {
     reverse_strands: HashMap<char, u32>,
     total_mapq: u32,
     total_baseq: u32,
+    /// Per observed base, summed log10 P(base | genotype) terms for a site
+    /// where the base is the hom allele, half of a het, or absent
+    base_likelihoods: HashMap<char, [f64; 3]>,
+    /// Normalized (REF, ALT) indels and MNPs whose VCF position is here
+    alleles: HashMap<(String, String), AlleleSupport>,
 }
 
 impl PileupEntry {
@@ -94,8 +373,92 @@ impl PileupEntry {
         else { *self.reverse_strands.entry(base).or_insert(0) += 1; }
         self.total_mapq += mapq as u32;
         self.total_baseq += baseq as u32;
+        // Error rate from the weaker of base and mapping quality; capped at
+        // 0.75 so a matching base is never less likely than a mismatch
+        let err = 10f64.powf(-(baseq.min(mapq).max(1) as f64) / 10.0).min(0.75);
+        let terms = self.base_likelihoods.entry(base).or_insert([0.0; 3]);
+        terms[0] += (1.0 - err).log10();
+        terms[1] += ((1.0 - err) / 2.0 + err / 6.0).log10();
+        terms[2] += (err / 3.0).log10();
+    }
+    fn terms(&self, base: char) -> [f64; 3] {
+        self.base_likelihoods.get(&base).copied().unwrap_or([0.0; 3])
+    }
+    /// Summed terms of every base not in `alleles`
+    fn other_terms(&self, alleles: &[char]) -> [f64; 3] {
+        self.base_likelihoods.iter()
+            .filter(|(b, _)| !alleles.contains(b))
+            .fold([0.0; 3], |acc, (_, t)| [acc[0] + t[0], acc[1] + t[1], acc[2] + t[2]])
+    }
+    /// Hom-ref GQ and PL (0/0, 0/<NON_REF>, <NON_REF>/<NON_REF>) of a
+    /// column, pooling every non-reference base into <NON_REF>
+    fn ref_confidence(&self, ref_base: char) -> (u32, [u32; 3]) {
+        if self.depth == 0 || !matches!(ref_base, 'A' | 'C' | 'G' | 'T') { return (0, [0; 3]); }
+        let pl6 = non_ref_pl(&[self.terms(ref_base), [0.0; 3], self.other_terms(&[ref_base])]);
+        let min = pl6[0].min(pl6[3]).min(pl6[5]);
+        let pl = [pl6[0] - min, pl6[3] - min, pl6[5] - min];
+        let gq = if pl[0] == 0 { pl[1].min(pl[2]).min(MAX_GQ as u32) } else { 0 };
+        (gq, pl)
+    }
+    /// log10 likelihoods of 0/0, 0/1, 1/1 for the SNV `ref_base` > `alt`;
+    /// bases that are neither allele are errors under every genotype
+    fn snv_log10_likelihoods(&self, ref_base: char, alt: char) -> [f64; 3] {
+        let terms = |b: char| self.base_likelihoods.get(&b).copied().unwrap_or([0.0; 3]);
+        let other: f64 = self.base_likelihoods.iter()
+            .filter(|(&b, _)| b != ref_base && b != alt)
+            .map(|(_, t)| t[2])
+            .sum();
+        let (r, a) = (terms(ref_base), terms(alt));
+        [r[0] + a[2] + other, r[1] + a[1] + other, r[2] + a[0] + other]
+    }
+    fn add_allele(&mut self, ref_allele: String, alt_allele: String, is_forward: bool, mapq: u8) {
+        let support = self.alleles.entry((ref_allele, alt_allele)).or_default();
+        support.count += 1;
+        if is_forward { support.forward += 1; }
+        support.total_mapq += mapq as u32;
+    }
+    /// Indel and MNP calls; indels use their own GQ/MAPQ thresholds, MNPs the
+    /// SNV ones. Depth is the number of reads aligned at the anchor base.
+    /// With `min_tlod` (paired mode) the tumor LOD replaces the GT/GQ gate.
+    fn get_allele_calls(&self, min_depth: usize, min_gq: f32, min_mapq: u8, min_indel_gq: f32, min_indel_mapq: u8, min_tlod: Option<f32>) -> Vec<Call> {
+        let mut calls = Vec::new();
+        let depth = self.depth.max(self.alleles.values().map(|s| s.count).max().unwrap_or(0));
+        if depth < min_depth as u32 { return calls; }
+        let baseq_avg = if self.depth > 0 { self.total_baseq as f32 / self.depth as f32 } else { 0.0 };
+        let forward_total: u32 = self.forward_strands.values().sum();
+        for ((ref_allele, alt_allele), support) in &self.alleles {
+            let kind = VariantKind::of(ref_allele, alt_allele);
+            let (gq_cutoff, mapq_cutoff) = if kind == VariantKind::Mnp { (min_gq, min_mapq) } else { (min_indel_gq, min_indel_mapq) };
+            let mapq_avg = support.total_mapq as f32 / support.count as f32;
+            if mapq_avg < mapq_cutoff as f32 { continue; }
+            let vaf = support.count as f32 / depth as f32;
+            let err = 10f64.powf(-ALLELE_ERROR_QUAL / 10.0);
+            let genotype = Genotype::from_log10_likelihoods(binomial_log10_likelihoods(depth, support.count, err));
+            let tlod = tumor_lod(depth, support.count, err);
+            let non_ref_pl = non_ref_pl(&[binomial_terms(depth - support.count, err), binomial_terms(support.count, err), [0.0; 3]]);
+            let passes = match min_tlod {
+                Some(min) => tlod >= min,
+                None => genotype.gt != "0/0" && genotype.gq >= gq_cutoff,
+            };
+            if !passes { continue; }
+            // Forward fraction of the supporting reads against the rest of the column
+            let other = depth.saturating_sub(support.count) as f32;
+            let other_forward = forward_total.saturating_sub(support.forward) as f32;
+            let strand_bias = if other > 0.0 {
+                1.0 - (support.forward as f32 / support.count as f32 - other_forward / other).abs()
+            } else { 0.0 };
+            calls.push(Call {
+                chrom: String::new(), pos: 0, kind,
+                ref_allele: ref_allele.clone(), alt_allele: alt_allele.clone(),
+                depth, ref_count: depth.saturating_sub(support.count), alt_count: support.count,
+                gq: genotype.gq, qual: genotype.qual, genotype: genotype.gt, pl: genotype.pl, non_ref_pl,
+                mapq_avg, baseq_avg, vaf, strand_bias, tlod,
+                ..Default::default()
+            });
+        }
+        calls
     }
-    fn get_calls(&self, ref_base: char, min_depth: usize, min_gq: f32, min_mapq: u8, min_baseq: u8) -> Vec<Call> {
+    fn get_calls(&self, ref_base: char, min_depth: usize, min_gq: f32, min_mapq: u8, min_baseq: u8, min_tlod: Option<f32>) -> Vec<Call> {
         let mut calls = Vec::new();
         if self.depth < min_depth as u32 { return calls; }
         let mapq_avg = self.total_mapq as f32 / self.depth as f32;
@@ -105,8 +468,14 @@ impl PileupEntry {
         for (&alt, &count) in &self.base_counts {
             if alt == ref_base || count == 0 { continue; }
             let vaf = count as f32 / self.depth as f32;
-            let gq = -10.0 * (0.5 - (vaf - 0.5).abs()).log10();
-            if gq < min_gq { continue; }
+            let genotype = Genotype::from_log10_likelihoods(self.snv_log10_likelihoods(ref_base, alt));
+            let tlod = tumor_lod(self.depth, count, 10f64.powf(-baseq_avg as f64 / 10.0));
+            let non_ref_pl = non_ref_pl(&[self.terms(ref_base), self.terms(alt), self.other_terms(&[ref_base, alt])]);
+            let passes = match min_tlod {
+                Some(min) => tlod >= min,
+                None => genotype.gt != "0/0" && genotype.gq >= min_gq,
+            };
+            if !passes { continue; }
             let fwd_alt = *self.forward_strands.get(&alt).unwrap_or(&0) as f32;
             let rev_alt = *self.reverse_strands.get(&alt).unwrap_or(&0) as f32;
             let fwd_ref = *self.forward_strands.get(&ref_base).unwrap_or(&0) as f32;
@@ -116,25 +485,753 @@ impl PileupEntry {
                 1.0 - diff
             } else { 0.0 };
             calls.push(Call {
-                chrom: String::new(), pos: 0, ref_base, alt_base: alt,
+                chrom: String::new(), pos: 0, kind: VariantKind::Snv,
+                ref_allele: ref_base.to_string(), alt_allele: alt.to_string(),
                 depth: self.depth, ref_count, alt_count: count,
-                gq, mapq_avg, baseq_avg, vaf, strand_bias,
+                gq: genotype.gq, qual: genotype.qual, genotype: genotype.gt, pl: genotype.pl, non_ref_pl,
+                mapq_avg, baseq_avg, vaf, strand_bias, tlod,
+                ..Default::default()
             });
         }
         calls
     }
+    /// Depth and ALT read count of a call's allele in this column
+    fn support(&self, ref_allele: &str, alt_allele: &str) -> (u32, u32) {
+        let alt = if VariantKind::of(ref_allele, alt_allele) == VariantKind::Snv {
+            alt_allele.chars().next().and_then(|b| self.base_counts.get(&b)).copied().unwrap_or(0)
+        } else {
+            self.alleles.get(&(ref_allele.to_string(), alt_allele.to_string())).map_or(0, |s| s.count)
+        };
+        (self.depth.max(alt), alt)
+    }
+}
+
+/// Indexed alignment input; CRAM slices are decoded against the reference
+enum AlignmentReader {
+    Bam(bam::io::IndexedReader<bgzf::Reader<File>>),
+    Cram(cram::io::IndexedReader<File>),
+}
+
+impl AlignmentReader {
+    fn open(path: &Path, reference: &Path) -> Result<Self> {
+        if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("cram")) {
+            let fasta_reader = fasta::io::indexed_reader::Builder::default()
+                .build_from_path(reference)
+                .with_context(|| format!("opening reference {}", reference.display()))?;
+            let repository = fasta::Repository::new(fasta::repository::adapters::IndexedReader::new(fasta_reader));
+            let reader = cram::io::indexed_reader::Builder::default()
+                .set_reference_sequence_repository(repository)
+                .build_from_path(path)
+                .with_context(|| format!("opening CRAM {} (a .crai index is required)", path.display()))?;
+            Ok(AlignmentReader::Cram(reader))
+        } else {
+            let reader = bam::io::indexed_reader::Builder::default()
+                .build_from_path(path)
+                .with_context(|| format!("opening BAM {} (a .bai or .csi index is required)", path.display()))?;
+            Ok(AlignmentReader::Bam(reader))
+        }
+    }
+
+    fn read_header(&mut self) -> Result<sam::Header> {
+        let header = match self {
+            AlignmentReader::Bam(r) => r.read_header()?,
+            AlignmentReader::Cram(r) => r.read_header()?,
+        };
+        Ok(header)
+    }
+
+    /// Pile up and call the reads overlapping `region` (bounds resolved by
+    /// `get_regions`)
+    fn call_region(&mut self, header: &sam::Header, region: &Region, fasta: &mut IndexedFasta, cli: &Cli, candidates: &[Indel]) -> Result<WindowCalls> {
+        let chrom = region.name.as_str();
+        let mut calls = Vec::new();
+        let mut in_mnp: HashMap<usize, char> = HashMap::new();
+        let mut blocks = cli.gvcf.then(|| BlockBuilder::new(region, &cli.gvcf_gq_bands));
+        let (mut depth_sum, mut callable) = (0u64, 0u64);
+        let mut depth_histogram = [0u64; DEPTH_BINS.len()];
+        let downsampled = self.pileup_region(header, region, fasta, cli, candidates, &mut |pos, entry, fasta| {
+            depth_sum += entry.depth as u64;
+            depth_histogram[depth_bin(entry.depth)] += 1;
+            if entry.depth >= cli.min_depth as u32 { callable += 1; }
+            let column = call_position(chrom, pos, &entry, fasta, cli, &mut in_mnp)?;
+            if let Some(blocks) = &mut blocks {
+                if column.is_empty() { blocks.push(pos, &entry, fasta)?; } else { blocks.skip(pos, fasta)?; }
+            }
+            calls.extend(column);
+            Ok(())
+        })?;
+        let blocks = match blocks {
+            Some(blocks) => blocks.finish(fasta)?,
+            None => Vec::new(),
+        };
+        // Positions no read reached never became columns
+        let length = region.end.unwrap_or(1) + 1 - region.start.unwrap_or(1);
+        depth_histogram[0] += (length as u64).saturating_sub(depth_histogram.iter().sum());
+        Ok(WindowCalls { calls, blocks, depth_sum, callable, downsampled, depth_histogram, seconds: 0.0 })
+    }
+
+    /// Indels of `region` seen in at least `REALIGN_MIN_SUPPORT` reads, in
+    /// position order: the targets of local realignment
+    fn indel_candidates(&mut self, header: &sam::Header, region: &Region, fasta: &mut IndexedFasta, cli: &Cli) -> Result<Vec<Indel>> {
+        let mut candidates = Vec::new();
+        self.pileup_region(header, region, fasta, cli, &[], &mut |pos, entry, _| {
+            for ((ref_allele, alt_allele), support) in &entry.alleles {
+                if ref_allele.len() != alt_allele.len() && support.count >= REALIGN_MIN_SUPPORT {
+                    candidates.push((pos, ref_allele.clone().into_bytes(), alt_allele.clone().into_bytes()));
+                }
+            }
+            Ok(())
+        })?;
+        Ok(candidates)
+    }
+
+    /// Hand each finished pileup column of `region` to `on_column`, in
+    /// position order, realigning reads around `candidates`; returns the
+    /// number of reads dropped by --max-depth
+    fn pileup_region(
+        &mut self,
+        header: &sam::Header,
+        region: &Region,
+        fasta: &mut IndexedFasta,
+        cli: &Cli,
+        candidates: &[Indel],
+        on_column: &mut dyn FnMut(usize, PileupEntry, &mut IndexedFasta) -> Result<()>,
+    ) -> Result<u64> {
+        let (start, end) = (region.start.unwrap_or(1), region.end.unwrap_or(1));
+        let query = QueryRegion::new(region.name.as_str(), Position::try_from(start)?..=Position::try_from(end)?);
+        match self {
+            AlignmentReader::Bam(r) => pileup_columns(r.query(header, &query)?, region, fasta, cli, candidates, on_column),
+            AlignmentReader::Cram(r) => pileup_columns(r.query(header, &query)?, region, fasta, cli, candidates, on_column),
+        }
+    }
 }
 
-/// Simplified BAM reader stub
-struct SimpleBamReader { _hdr: SimpleHeader }
-impl SimpleBamReader {
-    fn new(_p: &Path) -> Result<Self> { Ok(SimpleBamReader{ _hdr: SimpleHeader::default() }) }
-    fn read_header(&mut self) -> Result<SimpleHeader> { Ok(self._hdr.clone()) }
+/// Calls and, with --gvcf, reference blocks of one window, plus its summed
+/// read depth, number of positions at --min-depth or more, reads dropped
+/// by --max-depth, positions per `DEPTH_BINS` bin and worker seconds
+struct WindowCalls {
+    calls: Vec<Call>, blocks: Vec<RefBlock>, depth_sum: u64, callable: u64, downsampled: u64,
+    depth_histogram: [u64; DEPTH_BINS.len()], seconds: f64,
+}
+
+/// Lower bounds of the depth histogram bins in the run report
+const DEPTH_BINS: [u32; 10] = [0, 1, 5, 10, 20, 30, 50, 100, 200, 500];
+
+fn depth_bin(depth: u32) -> usize {
+    DEPTH_BINS.iter().rposition(|&bound| depth >= bound).unwrap_or(0)
+}
+
+/// gVCF reference block: adjacent non-variant positions whose hom-ref GQ
+/// falls in one band. GQ and PL come from the block's least confident site.
+#[derive(Debug, Clone)]
+struct RefBlock { chrom: String, start: usize, end: usize, ref_base: char, min_dp: u32, median_dp: u32, gq: u32, pl: [u32; 3] }
+
+/// Folds a window's non-variant columns, in position order, into reference
+/// blocks; positions without coverage become depth-0, GQ-0 blocks
+struct BlockBuilder<'a> {
+    chrom: &'a str,
+    bands: &'a [u32],
+    /// First position not yet in a block or skipped as a variant site
+    next: usize,
+    end: usize,
+    open: Option<(RefBlock, Vec<u32>)>,
+    blocks: Vec<RefBlock>,
+}
+
+impl<'a> BlockBuilder<'a> {
+    fn new(region: &'a Region, bands: &'a [u32]) -> Self {
+        BlockBuilder {
+            chrom: region.name.as_str(), bands,
+            next: region.start.unwrap_or(1), end: region.end.unwrap_or(1),
+            open: None, blocks: Vec::new(),
+        }
+    }
+
+    fn band(&self, gq: u32) -> usize { self.bands.iter().filter(|&&b| b <= gq).count() }
+
+    /// Add the non-variant column at `pos`
+    fn push(&mut self, pos: usize, entry: &PileupEntry, fasta: &mut IndexedFasta) -> Result<()> {
+        self.fill_to(pos, fasta)?;
+        let ref_base = fasta.base(self.chrom, pos as u64 - 1)?.unwrap_or('N');
+        let (gq, pl) = entry.ref_confidence(ref_base);
+        self.extend(pos, pos, ref_base, entry.depth, gq, pl);
+        Ok(())
+    }
+
+    /// End the open block at a variant column
+    fn skip(&mut self, pos: usize, fasta: &mut IndexedFasta) -> Result<()> {
+        self.fill_to(pos, fasta)?;
+        self.close();
+        self.next = pos + 1;
+        Ok(())
+    }
+
+    /// Close out the window, covering its uncovered tail
+    fn finish(mut self, fasta: &mut IndexedFasta) -> Result<Vec<RefBlock>> {
+        self.fill_to(self.end + 1, fasta)?;
+        self.close();
+        Ok(self.blocks)
+    }
+
+    /// Cover the uncovered positions before `pos` with a depth-0 run
+    fn fill_to(&mut self, pos: usize, fasta: &mut IndexedFasta) -> Result<()> {
+        if pos > self.next {
+            let ref_base = fasta.base(self.chrom, self.next as u64 - 1)?.unwrap_or('N');
+            self.extend(self.next, pos - 1, ref_base, 0, 0, [0; 3]);
+        }
+        Ok(())
+    }
+
+    fn extend(&mut self, start: usize, end: usize, ref_base: char, depth: u32, gq: u32, pl: [u32; 3]) {
+        let joins = self.open.as_ref().is_some_and(|(b, _)| b.end + 1 == start && self.band(b.gq) == self.band(gq));
+        if !joins {
+            self.close();
+            let block = RefBlock { chrom: self.chrom.to_string(), start, end, ref_base, min_dp: depth, median_dp: 0, gq, pl };
+            self.open = Some((block, Vec::new()));
+        }
+        let (block, depths) = self.open.as_mut().unwrap();
+        block.end = end;
+        block.min_dp = block.min_dp.min(depth);
+        if gq < block.gq { block.gq = gq; block.pl = pl; }
+        depths.extend(std::iter::repeat_n(depth, end - start + 1));
+        self.next = end + 1;
+    }
+
+    fn close(&mut self) {
+        if let Some((mut block, mut depths)) = self.open.take() {
+            depths.sort_unstable();
+            block.median_dp = depths[depths.len() / 2];
+            self.blocks.push(block);
+        }
+    }
+}
+
+/// Per-thread alignment and reference handles; each worker holds its own
+/// file descriptors so windows can be queried concurrently
+struct Worker {
+    reader: AlignmentReader,
+    header: sam::Header,
+    /// Matched normal reader and header in paired mode
+    normal: Option<(AlignmentReader, sam::Header)>,
+    fasta: IndexedFasta,
+}
+
+impl Worker {
+    fn open(cli: &Cli) -> Result<Self> {
+        let mut reader = AlignmentReader::open(&cli.bam, &cli.fasta)?;
+        let header = reader.read_header()?;
+        let normal = match &cli.normal_bam {
+            Some(path) => {
+                let mut normal = AlignmentReader::open(path, &cli.fasta)?;
+                let normal_header = normal.read_header()?;
+                Some((normal, normal_header))
+            }
+            None => None,
+        };
+        Ok(Worker { reader, header, normal, fasta: IndexedFasta::open(&cli.fasta)? })
+    }
+
+    fn call(&mut self, window: &Region, cli: &Cli) -> Result<WindowCalls> {
+        let started = Instant::now();
+        let candidates = if cli.realign {
+            self.reader.indel_candidates(&self.header, window, &mut self.fasta, cli)?
+        } else {
+            Vec::new()
+        };
+        let mut result = self.reader.call_region(&self.header, window, &mut self.fasta, cli, &candidates)?;
+        if let Some((normal, normal_header)) = &mut self.normal {
+            score_somatic(&mut result.calls, normal, normal_header, window, &mut self.fasta, cli, &candidates)?;
+        }
+        result.seconds = started.elapsed().as_secs_f64();
+        Ok(result)
+    }
+}
+
+/// Pile up the matched normal over `window`, keeping only the columns of
+/// tumor calls, and attach the somatic evidence to every call. Normal reads
+/// are realigned around the tumor's candidate indels so misaligned germline
+/// indel support in the normal is still counted.
+fn score_somatic(
+    calls: &mut [Call],
+    normal: &mut AlignmentReader,
+    header: &sam::Header,
+    window: &Region,
+    fasta: &mut IndexedFasta,
+    cli: &Cli,
+    candidates: &[Indel],
+) -> Result<()> {
+    if calls.is_empty() { return Ok(()); }
+    let sites: HashSet<usize> = calls.iter().map(|c| c.pos as usize).collect();
+    let mut columns: HashMap<usize, PileupEntry> = HashMap::new();
+    normal.pileup_region(header, window, fasta, cli, candidates, &mut |pos, entry, _| {
+        if sites.contains(&pos) { columns.insert(pos, entry); }
+        Ok(())
+    })?;
+    for call in calls.iter_mut() {
+        let (depth, alt) = columns
+            .get(&(call.pos as usize))
+            .map_or((0, 0), |entry| entry.support(&call.ref_allele, &call.alt_allele));
+        call.somatic = Some(SomaticEvidence::score(call, depth, alt, cli));
+    }
+    Ok(())
+}
+
+/// Split resolved regions into windows of at most `size` bases, in
+/// coordinate order
+fn split_windows(regions: &[Region], size: usize) -> Vec<Region> {
+    let size = size.max(1);
+    let mut windows = Vec::new();
+    for region in regions {
+        let (start, end) = (region.start.unwrap_or(1), region.end.unwrap_or(1));
+        let mut window_start = start;
+        while window_start <= end {
+            let window_end = (window_start + size - 1).min(end);
+            windows.push(Region { name: region.name.clone(), start: Some(window_start), end: Some(window_end) });
+            window_start = window_end + 1;
+        }
+    }
+    windows
+}
+
+/// Call every window on the rayon pool. Idle threads steal windows from
+/// busy ones, so uneven contigs and coverage still balance; results come
+/// back in window (coordinate) order.
+#[cfg(feature = "parallel")]
+fn call_windows(windows: &[Region], cli: &Cli, pb: &ProgressBar) -> Result<Vec<WindowCalls>> {
+    windows
+        .par_iter()
+        .map_init(
+            || Worker::open(cli),
+            |worker, window| {
+                let worker = worker.as_mut().map_err(|e| anyhow!("opening worker inputs failed: {:#}", e))?;
+                let calls = worker.call(window, cli)?;
+                pb.inc(1);
+                Ok(calls)
+            },
+        )
+        .collect()
+}
+
+#[cfg(not(feature = "parallel"))]
+fn call_windows(windows: &[Region], cli: &Cli, pb: &ProgressBar) -> Result<Vec<WindowCalls>> {
+    let mut worker = Worker::open(cli)?;
+    windows
+        .iter()
+        .map(|window| {
+            let calls = worker.call(window, cli)?;
+            pb.inc(1);
+            Ok(calls)
+        })
+        .collect()
+}
+
+/// Reads that contribute to the pileup: primary, mapped, not duplicates or QC failures
+fn is_usable(flags: Flags) -> bool {
+    !(flags.is_unmapped() || flags.is_secondary() || flags.is_supplementary()
+        || flags.is_duplicate() || flags.is_qc_fail())
+}
+
+/// Furthest an indel is moved left when it is normalized
+const MAX_LEFT_SHIFT: usize = 500;
+
+/// (1-based VCF position, REF, ALT) of a multi-base allele; REF and ALT
+/// share the anchor base for indels
+type Indel = (usize, Vec<u8>, Vec<u8>);
+
+/// Indels need this many supporting reads to become realignment targets
+const REALIGN_MIN_SUPPORT: u32 = 2;
+
+/// Mismatching base quality a realigned placement must remove before it
+/// replaces the aligner's (about one confident mismatch)
+const REALIGN_MIN_GAIN: u32 = 20;
+
+/// A usable read decoded for the pileup walk, so local realignment can
+/// rewrite its start and CIGAR
+struct AlignedRead { name: Vec<u8>, start: usize, is_forward: bool, mapq: u8, seq: Vec<u8>, quals: Vec<u8>, cigar: Vec<(Kind, usize)> }
+
+impl AlignedRead {
+    /// `None` for reads `is_usable` rejects or without an alignment start
+    fn decode<R: sam::alignment::Record>(record: &R) -> Result<Option<Self>> {
+        let flags = record.flags()?;
+        if !is_usable(flags) { return Ok(None); }
+        let Some(start) = record.alignment_start().transpose()? else { return Ok(None) };
+        Ok(Some(AlignedRead {
+            name: record.name().map(|n| n.to_vec()).unwrap_or_default(),
+            start: usize::from(start),
+            is_forward: !flags.is_reverse_complemented(),
+            mapq: record.mapping_quality().transpose()?.map(|q| q.get()).unwrap_or(0),
+            seq: record.sequence().iter().map(|b| b.to_ascii_uppercase()).collect(),
+            quals: record.quality_scores().iter().collect::<io::Result<_>>()?,
+            cigar: record.cigar().iter().map(|op| op.map(|op| (op.kind(), op.len()))).collect::<io::Result<_>>()?,
+        }))
+    }
+
+    /// Last reference position covered by the alignment
+    fn end(&self) -> usize {
+        let span: usize = self.cigar.iter()
+            .filter(|(k, _)| matches!(k, Kind::Match | Kind::SequenceMatch | Kind::SequenceMismatch | Kind::Deletion | Kind::Skip))
+            .map(|&(_, len)| len)
+            .sum();
+        (self.start + span).saturating_sub(1)
+    }
+}
+
+/// Caps coverage at `max_depth` (0 = off). Reads are taken in batches that
+/// share an alignment start; when a batch would push coverage at that start
+/// past the cap, only the reads with the smallest seeded hash of their name
+/// are kept. Coverage elsewhere never exceeds the cap either, since any read
+/// covering a position also covers the last admitted start before it. The
+/// hash makes the choice reproducible, independent of thread scheduling,
+/// and unbiased by strand.
+struct Downsampler<I> {
+    reads: I,
+    max_depth: usize,
+    seed: u64,
+    /// End positions of the kept reads that may still cover the next start
+    active: BinaryHeap<Reverse<usize>>,
+    pending: Option<AlignedRead>,
+    ready: VecDeque<AlignedRead>,
+    dropped: u64,
+}
+
+impl<I: Iterator<Item = Result<AlignedRead>>> Downsampler<I> {
+    fn new(reads: I, max_depth: usize, seed: u64) -> Self {
+        Downsampler { reads, max_depth, seed, active: BinaryHeap::new(), pending: None, ready: VecDeque::new(), dropped: 0 }
+    }
+
+    fn admit(&mut self, mut batch: Vec<AlignedRead>) {
+        if self.max_depth > 0 {
+            let start = batch[0].start;
+            while self.active.peek().is_some_and(|Reverse(end)| *end < start) { self.active.pop(); }
+            let room = self.max_depth.saturating_sub(self.active.len());
+            if batch.len() > room {
+                let seed = self.seed;
+                batch.sort_by_cached_key(|read| {
+                    let mut hasher = DefaultHasher::new();
+                    (seed, &read.name).hash(&mut hasher);
+                    hasher.finish()
+                });
+                self.dropped += (batch.len() - room) as u64;
+                batch.truncate(room);
+            }
+            self.active.extend(batch.iter().map(|read| Reverse(read.end())));
+        }
+        self.ready.extend(batch);
+    }
+}
+
+impl<I: Iterator<Item = Result<AlignedRead>>> Iterator for Downsampler<I> {
+    type Item = Result<AlignedRead>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(read) = self.ready.pop_front() { return Some(Ok(read)); }
+            let first = match self.pending.take() {
+                Some(read) => read,
+                None => match self.reads.next()? {
+                    Ok(read) => read,
+                    Err(e) => return Some(Err(e)),
+                },
+            };
+            let mut batch = vec![first];
+            loop {
+                match self.reads.next() {
+                    Some(Ok(read)) if read.start == batch[0].start => batch.push(read),
+                    Some(Ok(read)) => { self.pending = Some(read); break; }
+                    Some(Err(e)) => return Some(Err(e)),
+                    None => break,
+                }
+            }
+            self.admit(batch);
+        }
+    }
+}
+
+/// Local realignment around candidate indels. A read the aligner placed
+/// without an indel (soft clips included) is laid ungapped on the haplotype
+/// of each candidate it spans, anchored at its first or its last base. If
+/// the best placement cuts the mismatching base quality by at least
+/// `REALIGN_MIN_GAIN`, the read takes that placement: its clips are undone
+/// and the indel enters its CIGAR. This turns the mismatch and soft-clip
+/// noise of reads ending inside an indel into indel support.
+fn realign(read: &mut AlignedRead, candidates: &[Indel], chrom: &str, fasta: &mut IndexedFasta) -> Result<()> {
+    if read.cigar.iter().any(|(k, _)| !matches!(k, Kind::Match | Kind::SequenceMatch | Kind::SequenceMismatch | Kind::SoftClip | Kind::HardClip)) {
+        return Ok(());
+    }
+    let clips: Vec<usize> = read.cigar.iter().filter(|(k, _)| !matches!(k, Kind::HardClip)).map(|&(k, len)| if matches!(k, Kind::SoftClip) { len } else { 0 }).collect();
+    let (lead, trail) = (clips.first().copied().unwrap_or(0), clips.last().copied().unwrap_or(0));
+    let len = read.seq.len();
+    if len == 0 || read.start <= lead || lead + trail >= len { return Ok(()); }
+    let (placed_start, placed_end) = (read.start - lead, read.start - lead + len - 1);
+
+    // Candidates (sorted by position) anchored strictly inside the read, so
+    // it has bases on both sides of the event
+    let first = candidates.partition_point(|c| c.0 <= placed_start);
+    let spanned: Vec<&Indel> = candidates[first..].iter().take_while(|c| c.0 < placed_end).collect();
+    if spanned.is_empty() { return Ok(()); }
+
+    let margin = spanned.iter().map(|c| c.1.len().max(c.2.len())).max().unwrap_or(0);
+    let lo = placed_start.saturating_sub(margin).max(1);
+    let Some(reference) = fasta.fetch(chrom, lo as u64 - 1, (placed_end + margin) as u64)? else { return Ok(()) };
+    let reference = reference.to_ascii_uppercase();
+    // Clipped bases count as unexplained under the current alignment
+    let qual = |i: usize| read.quals.get(i).copied().unwrap_or(0) as u32;
+    let current: u32 = (0..len)
+        .filter(|&i| i < lead || i >= len - trail || reference.get(placed_start - lo + i) != Some(&read.seq[i]))
+        .map(qual)
+        .sum();
+    // Mismatching base quality of the read laid at `offset` on a haplotype
+    let penalty = |hap: &[u8], offset: isize| -> u32 {
+        (0..len)
+            .map(|i| {
+                let h = offset + i as isize;
+                if h >= 0 && hap.get(h as usize) == Some(&read.seq[i]) { 0 } else { qual(i) }
+            })
+            .sum()
+    };
+
+    let mut best: Option<(u32, &Indel, isize)> = None;
+    for &candidate in &spanned {
+        let (pos, ref_bases, alt_bases) = candidate;
+        let event = pos - lo;
+        let mut hap = reference[..event.min(reference.len())].to_vec();
+        hap.extend_from_slice(alt_bases);
+        hap.extend_from_slice(reference.get(event + ref_bases.len()..).unwrap_or_default());
+        let delta = alt_bases.len() as isize - ref_bases.len() as isize;
+        let left = (placed_start - lo) as isize;
+        let right = (placed_end - lo) as isize + delta - (len as isize - 1);
+        for offset in [left, right] {
+            let score = penalty(&hap, offset);
+            if best.is_none_or(|(b, _, _)| score < b) { best = Some((score, candidate, offset)); }
+        }
+    }
+    let Some((score, (pos, ref_bases, alt_bases), offset)) = best else { return Ok(()) };
+    if score + REALIGN_MIN_GAIN > current { return Ok(()); }
+
+    // Map each read base back from the haplotype: reference bases before and
+    // after the event, inserted bases in between
+    let event = (pos - lo) as isize;
+    let delta = alt_bases.len() as isize - ref_bases.len() as isize;
+    let mut ops: Vec<(Kind, usize)> = Vec::new();
+    let mut push = |kind: Kind, n: usize| match ops.last_mut() {
+        Some((k, len)) if *k == kind => *len += n,
+        _ => ops.push((kind, n)),
+    };
+    let (mut new_start, mut last_ref): (Option<usize>, Option<usize>) = (None, None);
+    for i in 0..len {
+        let h = offset + i as isize;
+        let ref_pos = if h <= event {
+            Some(lo as isize + h)
+        } else if h < event + alt_bases.len() as isize && delta > 0 {
+            None
+        } else {
+            Some(lo as isize + h - delta)
+        };
+        match ref_pos {
+            None => push(if new_start.is_some() { Kind::Insertion } else { Kind::SoftClip }, 1),
+            Some(p) => {
+                let p = p as usize;
+                if let Some(last) = last_ref {
+                    if p > last + 1 { push(Kind::Deletion, p - last - 1); }
+                }
+                new_start.get_or_insert(p);
+                last_ref = Some(p);
+                push(Kind::Match, 1);
+            }
+        }
+    }
+    let Some(new_start) = new_start else { return Ok(()) };
+    if let Some(last) = ops.last_mut().filter(|(k, _)| matches!(k, Kind::Insertion)) { last.0 = Kind::SoftClip; }
+    read.start = new_start;
+    read.cigar = ops;
+    Ok(())
+}
+
+/// Build the per-position pileup of a coordinate-sorted record stream over
+/// `region`, walking each read's CIGAR. Aligned bases feed the SNV counts;
+/// insertions, deletions and runs of adjacent mismatches become multi-base
+/// alleles at their normalized VCF position. A column goes to `on_column`
+/// once the stream has moved far enough right that no later read (or
+/// left-shifted indel) can still reach it, so only that band stays in memory.
+fn pileup_columns<R: sam::alignment::Record>(
+    records: impl Iterator<Item = io::Result<R>>,
+    region: &Region,
+    fasta: &mut IndexedFasta,
+    cli: &Cli,
+    candidates: &[Indel],
+    on_column: &mut dyn FnMut(usize, PileupEntry, &mut IndexedFasta) -> Result<()>,
+) -> Result<u64> {
+    let (start, end) = (region.start.unwrap_or(1), region.end.unwrap_or(1));
+    let chrom = region.name.as_str();
+    let mut pileup: BTreeMap<usize, PileupEntry> = BTreeMap::new();
+
+    let decoded = records.filter_map(|result| match result {
+        Ok(record) => AlignedRead::decode(&record).transpose(),
+        Err(e) => Some(Err(e.into())),
+    });
+    let mut reads = Downsampler::new(decoded, cli.max_depth, cli.downsample_seed);
+    for read in &mut reads {
+        let mut read = read?;
+        if !candidates.is_empty() { realign(&mut read, candidates, chrom, fasta)?; }
+        let AlignedRead { start: read_start, is_forward, mapq, seq, quals, cigar, .. } = read;
+
+        let frontier = read_start.saturating_sub(MAX_LEFT_SHIFT + 1);
+        while pileup.first_key_value().is_some_and(|(&pos, _)| pos < frontier) {
+            let (pos, entry) = pileup.pop_first().unwrap();
+            on_column(pos, entry, fasta)?;
+        }
+
+        // (1-based VCF position, REF, ALT) of this read's multi-base alleles
+        let mut events: Vec<Indel> = Vec::new();
+        let (mut ref_pos, mut read_pos) = (read_start, 0usize);
+        for (kind, len) in cigar {
+            match kind {
+                Kind::Match | Kind::SequenceMatch | Kind::SequenceMismatch => {
+                    let mut run_start: Option<usize> = None;
+                    for i in 0..=len {
+                        // i == len only closes a mismatch run ending the block
+                        let mismatch = if i < len {
+                            let pos = ref_pos + i;
+                            let base = seq.get(read_pos + i).map(|&b| b as char);
+                            let baseq = quals.get(read_pos + i).copied().unwrap_or(0);
+                            match base {
+                                Some(base @ ('A' | 'C' | 'G' | 'T')) => {
+                                    if (start..=end).contains(&pos) {
+                                        pileup.entry(pos).or_default().add_base(base, is_forward, mapq, baseq);
+                                    }
+                                    baseq >= cli.min_baseq
+                                        && fasta.base(chrom, pos as u64 - 1)?.is_some_and(|r| r != base && r != 'N')
+                                }
+                                _ => false,
+                            }
+                        } else { false };
+                        if mismatch {
+                            run_start.get_or_insert(i);
+                        } else if let Some(run) = run_start.take() {
+                            if i - run >= 2 {
+                                let first = ref_pos + run;
+                                let fetched = fasta.fetch(chrom, first as u64 - 1, (first + i - run) as u64 - 1)?;
+                                if let Some(ref_bases) = fetched.filter(|r| r.len() == i - run) {
+                                    events.push((first, ref_bases, seq[read_pos + run..read_pos + i].to_vec()));
+                                }
+                            }
+                        }
+                    }
+                    ref_pos += len;
+                    read_pos += len;
+                }
+                Kind::Insertion => {
+                    let inserted = seq.get(read_pos..read_pos + len).unwrap_or_default();
+                    if ref_pos > 1 && !inserted.is_empty() {
+                        if let Some(anchor) = fasta.base(chrom, ref_pos as u64 - 2)? {
+                            let mut alt = vec![anchor as u8];
+                            alt.extend_from_slice(inserted);
+                            events.push((ref_pos - 1, vec![anchor as u8], alt));
+                        }
+                    }
+                    read_pos += len;
+                }
+                Kind::Deletion => {
+                    if ref_pos > 1 {
+                        if let Some(ref_bases) = fasta.fetch(chrom, ref_pos as u64 - 2, (ref_pos + len) as u64 - 1)?.filter(|r| r.len() == len + 1) {
+                            let alt = ref_bases[..1].to_vec();
+                            events.push((ref_pos - 1, ref_bases, alt));
+                        }
+                    }
+                    ref_pos += len;
+                }
+                Kind::SoftClip => read_pos += len,
+                Kind::Skip => ref_pos += len,
+                Kind::HardClip | Kind::Pad => {}
+            }
+        }
+
+        for (pos, ref_bases, alt_bases) in events {
+            let is_acgt = |b: &[u8]| b.iter().all(|c| matches!(c, b'A' | b'C' | b'G' | b'T'));
+            if !is_acgt(&ref_bases) || !is_acgt(&alt_bases) { continue; }
+            let (pos, ref_bases, alt_bases) = if ref_bases.len() == alt_bases.len() {
+                (pos, ref_bases, alt_bases)
+            } else {
+                normalize_indel(fasta, chrom, pos, ref_bases, alt_bases)?
+            };
+            if !(start..=end).contains(&pos) { continue; }
+            pileup.entry(pos).or_default().add_allele(
+                String::from_utf8_lossy(&ref_bases).into_owned(),
+                String::from_utf8_lossy(&alt_bases).into_owned(),
+                is_forward,
+                mapq,
+            );
+        }
+    }
+
+    while let Some((pos, entry)) = pileup.pop_first() {
+        on_column(pos, entry, fasta)?;
+    }
+    Ok(reads.dropped)
+}
+
+/// Left-align and trim an indel against the reference (the vt normalize
+/// algorithm): drop a shared trailing base, re-anchoring on the preceding
+/// reference base whenever an allele empties, then trim shared leading
+/// bases down to one anchor. `pos` is the 1-based position of REF's first
+/// base; shifts stop after `MAX_LEFT_SHIFT` bases or at the contig start.
+fn normalize_indel(fasta: &mut IndexedFasta, chrom: &str, pos: usize, ref_bases: Vec<u8>, alt_bases: Vec<u8>) -> Result<(usize, Vec<u8>, Vec<u8>)> {
+    let (mut p, mut r, mut a) = (pos, ref_bases.clone(), alt_bases.clone());
+    while r.last() == a.last() && p + MAX_LEFT_SHIFT > pos {
+        if (r.len() == 1 || a.len() == 1) && p == 1 { break; }
+        r.pop();
+        a.pop();
+        if r.is_empty() || a.is_empty() {
+            let Some(prev) = fasta.base(chrom, p as u64 - 2)? else { return Ok((pos, ref_bases, alt_bases)) };
+            r.insert(0, prev as u8);
+            a.insert(0, prev as u8);
+            p -= 1;
+        }
+    }
+    while r.len() > 1 && a.len() > 1 && r[0] == a[0] {
+        r.remove(0);
+        a.remove(0);
+        p += 1;
+    }
+    Ok((p, r, a))
+}
+
+/// Call one pileup column (1-based `pos`): SNVs against the reference base
+/// plus the indels and MNPs anchored here. An SNV already reported inside
+/// a called MNP is dropped.
+fn call_position(
+    chrom: &str,
+    pos: usize,
+    entry: &PileupEntry,
+    fasta: &mut IndexedFasta,
+    cli: &Cli,
+    in_mnp: &mut HashMap<usize, char>,
+) -> Result<Vec<Call>> {
+    let min_tlod = cli.normal_bam.as_ref().map(|_| cli.min_tlod);
+    let mut calls = entry.get_allele_calls(cli.min_depth, cli.min_gq, cli.min_mapq, cli.min_indel_gq, cli.min_indel_mapq, min_tlod);
+    for call in calls.iter().filter(|c| c.kind == VariantKind::Mnp) {
+        for (i, alt) in call.alt_allele.chars().enumerate() {
+            in_mnp.insert(pos + i, alt);
+        }
+    }
+    if let Some(ref_base @ ('A' | 'C' | 'G' | 'T')) = fasta.base(chrom, pos as u64 - 1)? {
+        let covered = in_mnp.remove(&pos);
+        calls.extend(
+            entry
+                .get_calls(ref_base, cli.min_depth, cli.min_gq, cli.min_mapq, cli.min_baseq, min_tlod)
+                .into_iter()
+                .filter(|c| covered.is_none_or(|alt| !c.alt_allele.starts_with(alt))),
+        );
+    } else {
+        in_mnp.remove(&pos);
+    }
+    calls.sort_by(|a, b| (a.ref_allele.len(), &a.ref_allele, &a.alt_allele).cmp(&(b.ref_allele.len(), &b.ref_allele, &b.alt_allele)));
+    for call in &mut calls {
+        call.chrom = chrom.to_string();
+        call.pos = pos as i64;
+    }
+    Ok(calls)
 }
-#[derive(Clone, Default)] struct SimpleHeader;
-impl SimpleHeader { fn reference_sequences(&self) -> HashMap<String,usize> {
-    let mut m = HashMap::new(); m.insert("chr1".into(),248_956_422); m
-}}
 
 /// Region struct
 #[derive(Debug, Clone)] struct Region { name:String, start:Option<usize>, end:Option<usize> }
@@ -158,49 +1255,508 @@ impl FromStr for Region {
     }
 }
 
+/// Call filter parsed from --filter-expr. Comparisons join with `&&`
+/// (binds tighter) and `||`, and can be negated with `!` and grouped with
+/// parentheses. A bare field is true when it is non-zero.
+#[derive(Debug, Clone, PartialEq)]
+enum FilterExpr {
+    Or(Box<FilterExpr>, Box<FilterExpr>),
+    And(Box<FilterExpr>, Box<FilterExpr>),
+    Not(Box<FilterExpr>),
+    Compare(&'static str, CompareOp, FieldValue),
+    Flag(&'static str),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CompareOp { Eq, Ne, Lt, Le, Gt, Ge }
+
+#[derive(Debug, Clone, PartialEq)]
+enum FieldValue { Num(f64), Str(String) }
+
+/// Fields usable in --filter-expr, named after the Parquet columns, and
+/// whether they are numeric
+const FILTER_FIELDS: [(&str, bool); 24] = [
+    ("chrom", false), ("pos", true), ("type", false), ("ref", false), ("alt", false),
+    ("depth", true), ("ref_count", true), ("alt_count", true), ("gt", false), ("gq", true),
+    ("qual", true), ("mapq_avg", true), ("baseq_avg", true), ("vaf", true), ("strand_bias", true),
+    ("homopolymer_len", true), ("str_unit", false), ("str_copies", true),
+    ("tlod", true), ("normal_depth", true), ("normal_alt_count", true), ("somatic_p", true),
+    ("somatic", true), ("filter", false),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token { Ident(String), Num(f64, String), Str(String), Op(&'static str) }
+
+fn tokenize(s: &str) -> Result<Vec<Token>> {
+    let chars = s.chars().collect::<Vec<_>>();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let rest = chars[i..].iter().take(2).collect::<String>();
+        if c.is_whitespace() {
+            i += 1;
+        } else if let Some(op) = ["&&", "||", "==", "!=", "<=", ">="].into_iter().find(|op| rest == *op) {
+            tokens.push(Token::Op(op));
+            i += 2;
+        } else if let Some(op) = ["!", "<", ">", "=", "(", ")"].into_iter().find(|op| rest.starts_with(*op)) {
+            tokens.push(Token::Op(if op == "=" { "==" } else { op }));
+            i += 1;
+        } else if c == '"' || c == '\'' {
+            let end = chars[i + 1..].iter().position(|&q| q == c)
+                .ok_or_else(|| anyhow!("unterminated string in filter expression: {}", s))?;
+            tokens.push(Token::Str(chars[i + 1..i + 1 + end].iter().collect()));
+            i += end + 2;
+        } else if c.is_alphanumeric() || matches!(c, '_' | '.' | '-') {
+            // Fields, numbers and bare values such as chr1, snv or 0/1
+            let len = chars[i..].iter().take_while(|d| d.is_alphanumeric() || matches!(d, '_' | '.' | '-' | '+' | '/' | ':')).count();
+            let text = chars[i..i + len].iter().collect::<String>();
+            tokens.push(match text.parse::<f64>() {
+                Ok(n) => Token::Num(n, text),
+                Err(_) => Token::Ident(text),
+            });
+            i += len;
+        } else {
+            return Err(anyhow!("unexpected '{}' in filter expression: {}", c, s));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive-descent parser over the tokens of one expression
+struct FilterParser { tokens: Vec<Token>, at: usize }
+
+impl FilterParser {
+    fn peek_op(&self, op: &str) -> bool { matches!(self.tokens.get(self.at), Some(Token::Op(o)) if *o == op) }
+
+    fn next(&mut self) -> Result<Token> {
+        let token = self.tokens.get(self.at).cloned().ok_or_else(|| anyhow!("filter expression ends early"))?;
+        self.at += 1;
+        Ok(token)
+    }
+
+    fn or(&mut self) -> Result<FilterExpr> {
+        let mut expr = self.and()?;
+        while self.peek_op("||") {
+            self.at += 1;
+            expr = FilterExpr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<FilterExpr> {
+        let mut expr = self.unary()?;
+        while self.peek_op("&&") {
+            self.at += 1;
+            expr = FilterExpr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<FilterExpr> {
+        match self.next()? {
+            Token::Op("!") => Ok(FilterExpr::Not(Box::new(self.unary()?))),
+            Token::Op("(") => {
+                let expr = self.or()?;
+                match self.next()? {
+                    Token::Op(")") => Ok(expr),
+                    t => Err(anyhow!("expected ')' in filter expression, found {:?}", t)),
+                }
+            }
+            Token::Ident(name) => self.comparison(&name),
+            t => Err(anyhow!("expected a field in filter expression, found {:?}", t)),
+        }
+    }
+
+    fn comparison(&mut self, name: &str) -> Result<FilterExpr> {
+        let &(field, numeric) = FILTER_FIELDS.iter().find(|(f, _)| *f == name)
+            .ok_or_else(|| anyhow!("unknown filter field '{}' (known: {})", name, FILTER_FIELDS.map(|(f, _)| f).join(", ")))?;
+        let op = match self.tokens.get(self.at) {
+            Some(Token::Op("==")) => CompareOp::Eq,
+            Some(Token::Op("!=")) => CompareOp::Ne,
+            Some(Token::Op("<")) => CompareOp::Lt,
+            Some(Token::Op("<=")) => CompareOp::Le,
+            Some(Token::Op(">")) => CompareOp::Gt,
+            Some(Token::Op(">=")) => CompareOp::Ge,
+            _ if numeric => return Ok(FilterExpr::Flag(field)),
+            _ => return Err(anyhow!("'{}' needs a comparison", field)),
+        };
+        self.at += 1;
+        let value = match (self.next()?, numeric) {
+            (Token::Num(n, _), true) => FieldValue::Num(n),
+            (Token::Ident(b), true) if b == "true" || b == "false" => FieldValue::Num(if b == "true" { 1.0 } else { 0.0 }),
+            (Token::Num(_, text), false) | (Token::Ident(text), false) | (Token::Str(text), false) => FieldValue::Str(text),
+            (t, true) => return Err(anyhow!("'{}' is numeric, found {:?}", field, t)),
+            (t, false) => return Err(anyhow!("expected a value for '{}', found {:?}", field, t)),
+        };
+        if !numeric && !matches!(op, CompareOp::Eq | CompareOp::Ne) {
+            return Err(anyhow!("'{}' is text and only supports == and !=", field));
+        }
+        Ok(FilterExpr::Compare(field, op, value))
+    }
+}
+
+impl FromStr for FilterExpr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = FilterParser { tokens: tokenize(s)?, at: 0 };
+        let expr = parser.or()?;
+        if let Some(t) = parser.tokens.get(parser.at) {
+            return Err(anyhow!("unexpected {:?} in filter expression: {}", t, s));
+        }
+        Ok(expr)
+    }
+}
+
+impl FilterExpr {
+    /// Whether `call` passes. A comparison against a field the call lacks
+    /// (repeat context off-reference, somatic fields outside paired mode)
+    /// is false, as in bcftools.
+    fn matches(&self, call: &Call) -> bool {
+        match self {
+            FilterExpr::Or(a, b) => a.matches(call) || b.matches(call),
+            FilterExpr::And(a, b) => a.matches(call) && b.matches(call),
+            FilterExpr::Not(e) => !e.matches(call),
+            FilterExpr::Flag(field) => matches!(field_value(call, field), Some(FieldValue::Num(n)) if n != 0.0),
+            FilterExpr::Compare(field, op, value) => {
+                let ordering = match (field_value(call, field), value) {
+                    (Some(FieldValue::Num(a)), FieldValue::Num(b)) => a.partial_cmp(b),
+                    (Some(FieldValue::Str(a)), FieldValue::Str(b)) => Some(a.as_str().cmp(b.as_str())),
+                    _ => None,
+                };
+                ordering.is_some_and(|o| match op {
+                    CompareOp::Eq => o.is_eq(),
+                    CompareOp::Ne => o.is_ne(),
+                    CompareOp::Lt => o.is_lt(),
+                    CompareOp::Le => o.is_le(),
+                    CompareOp::Gt => o.is_gt(),
+                    CompareOp::Ge => o.is_ge(),
+                })
+            }
+        }
+    }
+}
+
+fn field_value(call: &Call, field: &str) -> Option<FieldValue> {
+    let num = |n: f64| Some(FieldValue::Num(n));
+    let text = |s: &str| Some(FieldValue::Str(s.to_string()));
+    let context = call.context.as_ref();
+    let somatic = call.somatic.as_ref();
+    match field {
+        "chrom" => text(&call.chrom),
+        "pos" => num(call.pos as f64),
+        "type" => text(call.kind.as_str()),
+        "ref" => text(&call.ref_allele),
+        "alt" => text(&call.alt_allele),
+        "depth" => num(call.depth as f64),
+        "ref_count" => num(call.ref_count as f64),
+        "alt_count" => num(call.alt_count as f64),
+        "gt" => text(&call.genotype),
+        "gq" => num(call.gq as f64),
+        "qual" => num(call.qual as f64),
+        "mapq_avg" => num(call.mapq_avg as f64),
+        "baseq_avg" => num(call.baseq_avg as f64),
+        "vaf" => num(call.vaf as f64),
+        "strand_bias" => num(call.strand_bias as f64),
+        "homopolymer_len" => context.and_then(|c| num(c.homopolymer_len as f64)),
+        "str_unit" => context.and_then(|c| text(&c.str_unit)),
+        "str_copies" => context.and_then(|c| num(c.str_copies as f64)),
+        "tlod" => somatic.and(num(call.tlod as f64)),
+        "normal_depth" => somatic.and_then(|e| num(e.normal_depth as f64)),
+        "normal_alt_count" => somatic.and_then(|e| num(e.normal_alt_count as f64)),
+        "somatic_p" => somatic.and_then(|e| num(e.fisher_p)),
+        "somatic" => somatic.and_then(|e| num(if e.is_somatic() { 1.0 } else { 0.0 })),
+        "filter" => text(somatic.map_or("PASS", |e| e.filter.as_str())),
+        _ => None,
+    }
+}
+
 /// Variant call record
-#[derive(Serialize, Deserialize, Debug, Clone)] struct Call {
-    chrom:String,pos:i64,ref_base:char,alt_base:char,
+#[derive(Serialize, Deserialize, Debug, Clone, Default)] struct Call {
+    chrom:String,pos:i64,kind:VariantKind,ref_allele:String,alt_allele:String,
     depth:u32,ref_count:u32,alt_count:u32,
-    gq:f32,mapq_avg:f32,baseq_avg:f32,
-    vaf:f32,strand_bias:f32,
+    genotype:String,pl:[u32;3],non_ref_pl:[u32;6],gq:f32,qual:f32,mapq_avg:f32,baseq_avg:f32,
+    vaf:f32,strand_bias:f32,tlod:f32,
+    context:Option<RepeatContext>,
+    somatic:Option<SomaticEvidence>,
+}
+
+/// One line of a FASTA .fai index
+#[derive(Debug, Clone)] struct FaiEntry { length:u64, offset:u64, line_bases:u64, line_width:u64 }
+
+/// Reference bases read per block when walking a pileup
+const FASTA_BLOCK: u64 = 1 << 16;
+
+/// Random access to an uncompressed FASTA through its .fai index
+struct IndexedFasta { file:File, index:HashMap<String,FaiEntry>, blocks:Vec<(String,u64,Vec<u8>)> }
+
+impl IndexedFasta {
+    fn open(path: &Path) -> Result<Self> {
+        let fai = PathBuf::from(format!("{}.fai", path.display()));
+        let reader = BufReader::new(File::open(&fai).with_context(|| format!("FASTA index {} not found", fai.display()))?);
+        let mut index = HashMap::new();
+        for line in reader.lines() {
+            let line = line?;
+            let f: Vec<&str> = line.split('\t').collect();
+            if f.len() < 5 { return Err(anyhow!("malformed .fai line: {}", line)); }
+            index.insert(f[0].to_string(), FaiEntry {
+                length: f[1].parse()?, offset: f[2].parse()?, line_bases: f[3].parse()?, line_width: f[4].parse()?,
+            });
+        }
+        Ok(IndexedFasta { file: File::open(path)?, index, blocks: Vec::new() })
+    }
+
+    /// Bases in the 0-based half-open interval `[start, end)`, clamped to the
+    /// contig; `None` if the contig is not in the index
+    fn fetch(&mut self, chrom: &str, start: u64, end: u64) -> Result<Option<Vec<u8>>> {
+        let Some(e) = self.index.get(chrom).cloned() else { return Ok(None) };
+        let end = end.min(e.length);
+        if start >= end { return Ok(Some(Vec::new())); }
+        let byte = |p: u64| e.offset + p / e.line_bases * e.line_width + p % e.line_bases;
+        let mut raw = vec![0u8; (byte(end - 1) - byte(start) + 1) as usize];
+        self.file.seek(SeekFrom::Start(byte(start)))?;
+        self.file.read_exact(&mut raw)?;
+        raw.retain(|b| !b.is_ascii_whitespace());
+        Ok(Some(raw))
+    }
+
+    /// Uppercase reference base at 0-based `pos`, served from the two most
+    /// recent cached blocks so a pileup walk (which reads both at the calling
+    /// frontier and ahead of it) does not seek once per position
+    fn base(&mut self, chrom: &str, pos: u64) -> Result<Option<char>> {
+        let hit = |(c, start, seq): &(String, u64, Vec<u8>)| c == chrom && pos >= *start && pos < start + seq.len() as u64;
+        let idx = match self.blocks.iter().position(hit) {
+            Some(idx) => idx,
+            None => {
+                let start = pos / FASTA_BLOCK * FASTA_BLOCK;
+                let Some(seq) = self.fetch(chrom, start, start + FASTA_BLOCK)? else { return Ok(None) };
+                if self.blocks.len() == 2 { self.blocks.remove(0); }
+                self.blocks.push((chrom.to_string(), start, seq));
+                self.blocks.len() - 1
+            }
+        };
+        let (_, start, seq) = &self.blocks[idx];
+        Ok(seq.get((pos - start) as usize).map(|b| b.to_ascii_uppercase() as char))
+    }
+}
+
+/// Bases fetched on each side of a variant for repeat annotation
+const REPEAT_WINDOW: usize = 50;
+
+/// Longest tandem repeat unit considered as an STR
+const MAX_STR_UNIT: usize = 6;
+
+/// Homopolymer and short tandem repeat context at a variant position
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+struct RepeatContext {
+    homopolymer_len: u32,
+    str_unit: String,
+    str_copies: u32,
+}
+
+/// Repeat context of `seq` around offset `at`. Repeats starting right after
+/// `at` count too, so a VCF-anchored indel sees the repeat it expands.
+fn repeat_context(seq: &[u8], at: usize) -> RepeatContext {
+    let mut context = RepeatContext::default();
+    if at >= seq.len() {
+        return context;
+    }
+
+    // Homopolymer run containing the position or starting just after it
+    let run_len = |i: usize| {
+        let base = seq[i].to_ascii_uppercase();
+        let left = seq[..i].iter().rev().take_while(|b| b.to_ascii_uppercase() == base).count();
+        let right = seq[i..].iter().take_while(|b| b.to_ascii_uppercase() == base).count();
+        left + right
+    };
+    let mut homopolymer = run_len(at);
+    if at + 1 < seq.len() {
+        homopolymer = homopolymer.max(run_len(at + 1));
+    }
+    context.homopolymer_len = homopolymer as u32;
+
+    // Longest tandem repeat (unit 2..=6 bases, at least two copies)
+    // overlapping the position or the base after it
+    let upper: Vec<u8> = seq.iter().map(|b| b.to_ascii_uppercase()).collect();
+    let mut best_span = 0;
+    for unit_len in 2..=MAX_STR_UNIT {
+        for start in (at + 1).saturating_sub(unit_len - 1)..=(at + 1) {
+            if start + unit_len > upper.len() {
+                break;
+            }
+            let unit = &upper[start..start + unit_len];
+            // Units that are themselves repeats (e.g. ATAT) belong to a shorter period
+            if (1..unit_len).any(|p| unit_len % p == 0 && unit.iter().zip(&unit[p..]).all(|(a, b)| a == b)) {
+                continue;
+            }
+            let mut left = start;
+            while left > 0 && upper[left - 1] == upper[left - 1 + unit_len] {
+                left -= 1;
+            }
+            let mut right = start + unit_len;
+            while right < upper.len() && upper[right] == upper[right - unit_len] {
+                right += 1;
+            }
+            let span = right - left;
+            let copies = span / unit_len;
+            if copies >= 2 && left <= at + 1 && right > at && span > best_span {
+                best_span = span;
+                context.str_unit = String::from_utf8_lossy(&upper[left..left + unit_len]).into_owned();
+                context.str_copies = copies as u32;
+            }
+        }
+    }
+
+    context
+}
+
+/// Attach reference repeat context to each call (1-based `pos`)
+fn annotate_context(calls: &mut [Call], fasta: &mut IndexedFasta) -> Result<usize> {
+    let mut annotated = 0;
+    for call in calls.iter_mut() {
+        let pos = (call.pos - 1).max(0) as u64;
+        let start = pos.saturating_sub(REPEAT_WINDOW as u64);
+        match fasta.fetch(&call.chrom, start, pos + REPEAT_WINDOW as u64 + 1)? {
+            Some(window) => { call.context = Some(repeat_context(&window, (pos - start) as usize)); annotated += 1; }
+            None => debug!("Contig {} not in FASTA index; no repeat context", call.chrom),
+        }
+    }
+    Ok(annotated)
 }
 
 /// Caller stats
 #[derive(Serialize, Deserialize, Debug, Default)] struct CallerStats {
-    total_targets:usize, targets_with_variants:usize,
+    total_windows:usize, windows_with_variants:usize,
     total_variants_called:usize, variants_by_type:HashMap<String,usize>,
-    elapsed_seconds:f64, threads_used:usize, params:HashMap<String,String>
+    somatic_variants:usize, variants_filtered:usize, reads_downsampled:u64, targets:Vec<TargetStats>,
+    contigs:Vec<ContigStats>, regions:Vec<RegionStats>, depth_histogram:BTreeMap<u32,u64>,
+    elapsed_seconds:f64, threads_used:usize, params:BTreeMap<String,String>
+}
+
+/// Per-contig breakdown of the called windows. `ts_tv` is absent without
+/// transversions; `depth_histogram` counts positions per depth bin, keyed
+/// by the bin's lower bound (`DEPTH_BINS`).
+#[derive(Serialize, Deserialize, Debug, Default)] struct ContigStats {
+    contig:String, bases:u64, variants:usize, snvs:usize, mnps:usize, insertions:usize, deletions:usize,
+    transitions:usize, transversions:usize, ts_tv:Option<f64>,
+    mean_depth:f64, callable_fraction:f64, seconds:f64, depth_histogram:BTreeMap<u32,u64>,
+}
+
+/// One calling window: its variants, mean depth and worker wall time
+#[derive(Serialize, Deserialize, Debug, Default)] struct RegionStats {
+    region:String, variants:usize, mean_depth:f64, seconds:f64,
+}
+
+/// Per-target counts for --bed runs
+#[derive(Serialize, Deserialize, Debug, Default)] struct TargetStats {
+    chrom:String, start:usize, end:usize, variants:usize, snvs:usize, indels:usize,
+    mean_depth:f64, callable_fraction:f64,
+}
+
+/// Wall time and I/O for one caller stage
+#[derive(Serialize, Debug)] struct StageProfile {
+    stage:String, wall_seconds:f64, bytes_read:u64, bytes_written:u64,
+    records:u64, records_per_sec:f64,
+}
+
+/// Runtime breakdown emitted by `--profile-json`
+#[derive(Serialize, Debug, Default)] struct RunProfile {
+    tool:String, threads:usize, total_wall_seconds:f64, stages:Vec<StageProfile>,
+}
+
+impl RunProfile {
+    fn new(threads: usize) -> Self {
+        RunProfile { tool: env!("CARGO_PKG_NAME").into(), threads, ..Default::default() }
+    }
+
+    fn record(&mut self, stage: &str, started: Instant, records: u64, bytes_read: u64, bytes_written: u64) {
+        let wall_seconds = started.elapsed().as_secs_f64();
+        let records_per_sec = if wall_seconds > 0.0 { records as f64 / wall_seconds } else { 0.0 };
+        self.stages.push(StageProfile {
+            stage: stage.into(), wall_seconds, bytes_read, bytes_written, records, records_per_sec,
+        });
+    }
+
+    fn write(mut self, out: &Path, run_start: Instant) -> Result<()> {
+        self.total_wall_seconds = run_start.elapsed().as_secs_f64();
+        let json = serde_json::to_string_pretty(&self)?;
+        std::fs::write(out, json).context("writing profile JSON failed")?;
+        Ok(())
+    }
 }
 
+fn file_len(p: &Path) -> u64 { std::fs::metadata(p).map(|m| m.len()).unwrap_or(0) }
+
 fn main()->Result<()> {
     let start=Instant::now(); let cli=Cli::parse();
     let level = if cli.verbose { Level::DEBUG } else { Level::INFO };
     tracing_subscriber::fmt().with_max_level(level).init();
     let threads = if cli.threads==0 { num_cpus::get() } else { cli.threads };
     info!("Threads {}",threads);
+    let mut profile=RunProfile::new(threads);
+    #[cfg(feature = "parallel")]
+    rayon::ThreadPoolBuilder::new().num_threads(threads).build_global().context("initializing thread pool failed")?;
     validate_inputs(&cli)?;
-    info!("Using simplified BAM stub");
-    let mut reader=SimpleBamReader::new(&cli.bam)?;
-    let regions=get_regions(&mut reader,&cli)?;
-    info!("Regions {}",regions.len());
-    let stats=Arc::new(Mutex::new(CallerStats{total_targets:regions.len(),threads_used:threads,..Default::default()}));
-    let pb=ProgressBar::new(regions.len() as u64);
+    let filter=cli.filter_expr.as_deref().map(FilterExpr::from_str).transpose().context("invalid --filter-expr")?;
+    let stage_start=Instant::now();
+    let mut fasta=IndexedFasta::open(&cli.fasta)?;
+    let mut reader=AlignmentReader::open(&cli.bam,&cli.fasta)?;
+    let header=reader.read_header()?;
+    let regions=get_regions(&header,&cli)?;
+    if let Some(normal_bam)=&cli.normal_bam { check_normal_contigs(normal_bam,&cli.fasta,&regions)?; info!("Paired mode: matched normal {}",normal_bam.display()); }
+    let windows=split_windows(&regions,cli.window_size);
+    info!("Regions {} ({} windows of up to {} bp)",regions.len(),windows.len(),cli.window_size);
+    profile.record("read_header",stage_start,regions.len() as u64,0,0);
+    let stats=Arc::new(Mutex::new(CallerStats{total_windows:windows.len(),threads_used:threads,..Default::default()}));
+    let pb=ProgressBar::new(windows.len() as u64);
     pb.set_style(ProgressStyle::default_bar().template("{bar:40.cyan/blue} {pos}/{len}").unwrap());
-    let mut all_calls=Vec::new();
-    for region in &regions {
-        debug!("Region {}",region.name);
-        let calls=generate_mock_calls(region,10);
-        if !calls.is_empty(){ let mut s=stats.lock().unwrap(); s.targets_with_variants+=1; s.total_variants_called+=calls.len(); for c in &calls{ let t= if is_transition(c.ref_base,c.alt_base) {"transition"} else {"other"}; *s.variants_by_type.entry(t.into()).or_insert(0)+=1;} all_calls.extend(calls);}        
-        pb.inc(1);
+    let mut all_calls=Vec::new(); let mut ref_blocks=Vec::new();
+    let stage_start=Instant::now();
+    let results=call_windows(&windows,&cli,&pb)?;
+    if cli.bed.is_some(){ stats.lock().unwrap().targets=target_stats(&regions,&windows,&results); }
+    run_breakdown(&mut stats.lock().unwrap(),&windows,&results);
+    for WindowCalls{calls,blocks,downsampled,..} in results {
+        ref_blocks.extend(blocks); stats.lock().unwrap().reads_downsampled+=downsampled;
+        if !calls.is_empty(){ let mut s=stats.lock().unwrap(); s.windows_with_variants+=1; s.total_variants_called+=calls.len(); for c in &calls{ let t=snv_class(c); *s.variants_by_type.entry(t.into()).or_insert(0)+=1; if c.somatic.as_ref().is_some_and(|e| e.is_somatic()){ s.somatic_variants+=1; } } all_calls.extend(calls);}
     }
     pb.finish_with_message("done");
-    if all_calls.is_empty(){ warn!("No variants"); return Err(CallerError::NoVariants.into()); }
-    export_variants(&all_calls,&cli.out)?;
-    info!("Exported {}",all_calls.len());
+    profile.record("call",stage_start,all_calls.len() as u64,file_len(&cli.bam),0);
+    if cli.gvcf { info!("gVCF reference blocks {}",ref_blocks.len()); }
+    // An all-reference gVCF is still needed for joint genotyping
+    if all_calls.is_empty(){
+        if !cli.gvcf { warn!("No variants"); return Err(CallerError::NoVariants.into()); }
+        warn!("No variants; writing reference blocks only");
+    }
+    let stage_start=Instant::now();
+    let annotated=annotate_context(&mut all_calls,&mut fasta)?;
+    info!("Repeat context for {}/{} calls",annotated,all_calls.len());
+    profile.record("annotate_context",stage_start,annotated as u64,0,0);
+    if let Some(filter)=&filter {
+        let before=all_calls.len(); all_calls.retain(|c| filter.matches(c));
+        stats.lock().unwrap().variants_filtered=before-all_calls.len();
+        info!("Filter kept {}/{} calls",all_calls.len(),before);
+        if all_calls.is_empty(){ warn!("No variants pass --filter-expr"); return Err(CallerError::NoVariants.into()); }
+    }
+    let stage_start=Instant::now();
+    match cli.format {
+        OutputFormat::Parquet => export_variants(&all_calls,&cli.out)?,
+        format => {
+            let index=export_vcf(&all_calls,&ref_blocks,&header,&sample_name(&cli.bam),cli.normal_bam.is_some(),cli.gvcf,&cli.out,format)?;
+            info!("Index at {}",index.display());
+        }
+    }
+    info!("Exported {} ({:?})",all_calls.len(),cli.format);
+    profile.record("export",stage_start,all_calls.len() as u64,0,file_len(&cli.out));
     let mut s=stats.lock().unwrap(); s.elapsed_seconds=start.elapsed().as_secs_f64(); s.params.insert("min_depth".into(),cli.min_depth.to_string());
+    for (k,v) in [("min_gq",cli.min_gq.to_string()),("min_mapq",cli.min_mapq.to_string()),("min_baseq",cli.min_baseq.to_string()),("window_size",cli.window_size.to_string()),("max_depth",cli.max_depth.to_string()),("format",format!("{:?}",cli.format).to_lowercase())] { s.params.insert(k.into(),v); }
+    if let Some(r)=&cli.region{ s.params.insert("region".into(),r.clone()); }
+    if let Some(b)=&cli.bed{ s.params.insert("bed".into(),b.display().to_string()); }
+    if let Some(f)=&cli.filter_expr{ s.params.insert("filter_expr".into(),f.clone()); }
     if let Some(p)=&cli.stats{ export_stats(&s,p)?; info!("Stats at {}",p.display()); }
+    if let Some(p)=&cli.report_md{ std::fs::write(p,render_report(&s)).context("writing Markdown report failed")?; info!("Report at {}",p.display()); }
     print_summary(&all_calls,&s);
+    if let Some(p)=&cli.profile_json{ profile.write(p,start)?; info!("Profile at {}",p.display()); }
     Ok(())
 }
 
@@ -209,57 +1765,277 @@ fn validate_inputs(cli: &Cli) -> Result<()> {
     if !cli.bam.exists() {
         return Err(anyhow!("BAM file does not exist: {}", cli.bam.display()));
     }
-    // Check FASTA if provided
-    if let Some(fasta_path) = &cli.fasta {
-        if !fasta_path.exists() {
-            return Err(anyhow!("FASTA file does not exist: {}", fasta_path.display()));
+    // Check the reference FASTA
+    if !cli.fasta.exists() {
+        return Err(anyhow!("FASTA file does not exist: {}", cli.fasta.display()));
+    }
+    if cli.gvcf && cli.format == OutputFormat::Parquet {
+        return Err(anyhow!("--gvcf needs --format vcf or bcf"));
+    }
+    // Reference blocks are closed at every call site before filtering, so a
+    // removed call would leave that position uncovered
+    if cli.gvcf && cli.filter_expr.is_some() {
+        return Err(anyhow!("--filter-expr cannot be combined with --gvcf"));
+    }
+    // Check the target BED
+    if let Some(bed) = &cli.bed {
+        if !bed.exists() {
+            return Err(anyhow!("BED file does not exist: {}", bed.display()));
+        }
+    }
+    // Check the matched normal
+    if let Some(normal_bam) = &cli.normal_bam {
+        if !normal_bam.exists() {
+            return Err(anyhow!("Normal BAM file does not exist: {}", normal_bam.display()));
+        }
+    }
+    Ok(())
+}
+
+/// Every region's contig must also be in the normal's header, or its
+/// windows could not be queried there
+fn check_normal_contigs(normal_bam: &Path, reference: &Path, regions: &[Region]) -> Result<()> {
+    let header = AlignmentReader::open(normal_bam, reference)?.read_header()?;
+    let contigs: HashSet<String> = header.reference_sequences().iter().map(|(name, _)| name.to_string()).collect();
+    for region in regions {
+        if !contigs.contains(&region.name) {
+            return Err(anyhow!("Contig '{}' not found in normal BAM header", region.name));
         }
     }
     Ok(())
 }
 
-fn get_regions(reader: &mut SimpleBamReader, cli: &Cli) -> Result<Vec<Region>> {
-    // Read header for reference sequences
-    let header = reader.read_header()?;
-    let refs = header.reference_sequences();
+fn get_regions(header: &sam::Header, cli: &Cli) -> Result<Vec<Region>> {
+    // Reference sequences in header order, with their lengths
+    let refs: Vec<(String, usize)> = header
+        .reference_sequences()
+        .iter()
+        .map(|(name, rs)| (name.to_string(), rs.length().get()))
+        .collect();
+
+    // Target intervals from a BED file
+    if let Some(bed) = &cli.bed {
+        let targets = read_bed(bed, &refs)?;
+        info!("BED targets {} after merging", targets.len());
+        return Ok(targets);
+    }
 
     // If a region is specified, parse and validate it
     if let Some(region_str) = &cli.region {
         let region = Region::from_str(region_str)?;
-        if !refs.contains_key(&region.name) {
+        let Some(&(_, length)) = refs.iter().find(|(name, _)| *name == region.name) else {
             return Err(anyhow!("Region '{}' not found in BAM header", region.name));
+        };
+        let start = region.start.unwrap_or(1).max(1);
+        let end = region.end.unwrap_or(length).min(length);
+        if start > end {
+            return Err(anyhow!("Region '{}' is empty", region_str));
         }
-        return Ok(vec![region]);
+        return Ok(vec![Region { name: region.name, start: Some(start), end: Some(end) }]);
     }
 
     // Otherwise, return all contigs
     let regions = refs
-        .keys()
-        .map(|name| Region { name: name.clone(), start: None, end: None })
+        .into_iter()
+        .map(|(name, length)| Region { name, start: Some(1), end: Some(length) })
         .collect();
     Ok(regions)
 }
 
-fn generate_mock_calls(region: &Region, count: usize) -> Vec<Call> {
-    let mut calls = Vec::with_capacity(count);
-    for i in 0..count {
-        let pos = region.start.unwrap_or(1000) as i64 + (i as i64) * 100;
-        calls.push(Call {
-            chrom: region.name.clone(),
-            pos,
-            ref_base: 'A',
-            alt_base: 'C',
-            depth: 30,
-            ref_count: 20,
-            alt_count: 10,
-            gq: 30.0,
-            mapq_avg: 40.0,
-            baseq_avg: 35.0,
-            vaf: 0.33,
-            strand_bias: 0.9,
+/// Targets from a BED file (0-based, half-open) as 1-based inclusive regions
+/// in header contig order, with overlapping and abutting intervals merged.
+/// Contigs missing from the alignment header are skipped with a warning.
+fn read_bed(path: &Path, refs: &[(String, usize)]) -> Result<Vec<Region>> {
+    let contigs: HashMap<&str, (usize, usize)> = refs
+        .iter()
+        .enumerate()
+        .map(|(rank, (name, length))| (name.as_str(), (rank, *length)))
+        .collect();
+    let reader = BufReader::new(File::open(path).with_context(|| format!("opening BED {}", path.display()))?);
+    let mut intervals: Vec<(usize, usize, usize)> = Vec::new();
+    let mut missing: BTreeMap<String, usize> = BTreeMap::new();
+    for (n, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() || line.starts_with('#') || line.starts_with("track") || line.starts_with("browser") {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 3 {
+            return Err(anyhow!("BED line {}: expected chrom, start and end", n + 1));
+        }
+        let start: usize = fields[1].parse().with_context(|| format!("BED line {}: bad start '{}'", n + 1, fields[1]))?;
+        let end: usize = fields[2].parse().with_context(|| format!("BED line {}: bad end '{}'", n + 1, fields[2]))?;
+        let Some(&(rank, length)) = contigs.get(fields[0]) else {
+            *missing.entry(fields[0].to_string()).or_insert(0) += 1;
+            continue;
+        };
+        let end = end.min(length);
+        if start < end { intervals.push((rank, start + 1, end)); }
+    }
+    for (contig, count) in &missing {
+        warn!("BED contig '{}' not in the alignment header; {} interval(s) skipped", contig, count);
+    }
+
+    intervals.sort_unstable();
+    let mut merged: Vec<(usize, usize, usize)> = Vec::new();
+    for (rank, start, end) in intervals {
+        match merged.last_mut() {
+            Some(last) if last.0 == rank && start <= last.2 + 1 => last.2 = last.2.max(end),
+            _ => merged.push((rank, start, end)),
+        }
+    }
+    if merged.is_empty() {
+        return Err(anyhow!("BED {} has no intervals on the alignment's contigs", path.display()));
+    }
+    Ok(merged
+        .into_iter()
+        .map(|(rank, start, end)| Region { name: refs[rank].0.clone(), start: Some(start), end: Some(end) })
+        .collect())
+}
+
+/// Fold each target's windows (split in target order) into its variant
+/// counts, mean depth and callable fraction
+fn target_stats(targets: &[Region], windows: &[Region], results: &[WindowCalls]) -> Vec<TargetStats> {
+    let mut stats: Vec<(TargetStats, u64, u64)> = targets
+        .iter()
+        .map(|t| {
+            let target = TargetStats { chrom: t.name.clone(), start: t.start.unwrap_or(1), end: t.end.unwrap_or(1), ..Default::default() };
+            (target, 0, 0)
+        })
+        .collect();
+    let mut idx = 0;
+    for (window, result) in windows.iter().zip(results) {
+        while idx + 1 < stats.len() && !(stats[idx].0.chrom == window.name && window.end.unwrap_or(1) <= stats[idx].0.end) {
+            idx += 1;
+        }
+        let (target, depth_sum, callable) = &mut stats[idx];
+        target.variants += result.calls.len();
+        target.snvs += result.calls.iter().filter(|c| c.kind == VariantKind::Snv).count();
+        target.indels += result.calls.iter().filter(|c| matches!(c.kind, VariantKind::Insertion | VariantKind::Deletion)).count();
+        *depth_sum += result.depth_sum;
+        *callable += result.callable;
+    }
+    stats
+        .into_iter()
+        .map(|(mut target, depth_sum, callable)| {
+            let length = (target.end + 1 - target.start) as f64;
+            target.mean_depth = depth_sum as f64 / length;
+            target.callable_fraction = callable as f64 / length;
+            target
+        })
+        .collect()
+}
+
+/// Fill the per-contig and per-window breakdown and the overall depth
+/// histogram from the window results (windows are in contig order)
+fn run_breakdown(stats: &mut CallerStats, windows: &[Region], results: &[WindowCalls]) {
+    let mut contigs: Vec<(ContigStats, u64, u64, [u64; DEPTH_BINS.len()])> = Vec::new();
+    let mut total = [0u64; DEPTH_BINS.len()];
+    for (window, result) in windows.iter().zip(results) {
+        let (start, end) = (window.start.unwrap_or(1), window.end.unwrap_or(1));
+        let bases = (end + 1 - start) as u64;
+        stats.regions.push(RegionStats {
+            region: format!("{}:{}-{}", window.name, start, end),
+            variants: result.calls.len(),
+            mean_depth: result.depth_sum as f64 / bases as f64,
+            seconds: result.seconds,
         });
+        if contigs.last().is_none_or(|(c, ..)| c.contig != window.name) {
+            let contig = ContigStats { contig: window.name.clone(), ..Default::default() };
+            contigs.push((contig, 0, 0, [0; DEPTH_BINS.len()]));
+        }
+        let (contig, depth_sum, callable, histogram) = contigs.last_mut().unwrap();
+        contig.bases += bases;
+        contig.seconds += result.seconds;
+        *depth_sum += result.depth_sum;
+        *callable += result.callable;
+        for (bin, count) in result.depth_histogram.iter().enumerate() {
+            histogram[bin] += count;
+            total[bin] += count;
+        }
+        for call in &result.calls {
+            contig.variants += 1;
+            match snv_class(call) {
+                "transition" => contig.transitions += 1,
+                "transversion" => contig.transversions += 1,
+                _ => {}
+            }
+            match call.kind {
+                VariantKind::Snv => contig.snvs += 1,
+                VariantKind::Mnp => contig.mnps += 1,
+                VariantKind::Insertion => contig.insertions += 1,
+                VariantKind::Deletion => contig.deletions += 1,
+            }
+        }
     }
-    calls
+    let bins = |histogram: &[u64]| -> BTreeMap<u32, u64> { DEPTH_BINS.iter().copied().zip(histogram.iter().copied()).collect() };
+    stats.contigs = contigs
+        .into_iter()
+        .map(|(mut contig, depth_sum, callable, histogram)| {
+            contig.ts_tv = (contig.transversions > 0).then(|| contig.transitions as f64 / contig.transversions as f64);
+            contig.mean_depth = depth_sum as f64 / contig.bases.max(1) as f64;
+            contig.callable_fraction = callable as f64 / contig.bases.max(1) as f64;
+            contig.depth_histogram = bins(&histogram);
+            contig
+        })
+        .collect();
+    stats.depth_histogram = bins(&total);
+}
+
+/// Markdown rendering of the run report: totals, parameters, the
+/// per-contig table, the depth histogram and the slowest windows
+fn render_report(stats: &CallerStats) -> String {
+    let mut md = String::from("# Variant calling report\n\n");
+    let ts = stats.variants_by_type.get("transition").copied().unwrap_or(0);
+    let tv = stats.variants_by_type.get("transversion").copied().unwrap_or(0);
+    let _ = writeln!(md, "| Metric | Value |\n|---|---|");
+    let _ = writeln!(md, "| Variants | {} |", stats.total_variants_called);
+    let _ = writeln!(md, "| Windows (with variants) | {} ({}) |", stats.total_windows, stats.windows_with_variants);
+    let _ = writeln!(md, "| Ts/Tv | {} |", if tv > 0 { format!("{:.2}", ts as f64 / tv as f64) } else { "-".into() });
+    if stats.somatic_variants > 0 { let _ = writeln!(md, "| Somatic (PASS) | {} |", stats.somatic_variants); }
+    if stats.variants_filtered > 0 { let _ = writeln!(md, "| Removed by filter | {} |", stats.variants_filtered); }
+    if stats.reads_downsampled > 0 { let _ = writeln!(md, "| Reads downsampled | {} |", stats.reads_downsampled); }
+    let _ = writeln!(md, "| Threads | {} |", stats.threads_used);
+    let _ = writeln!(md, "| Runtime (s) | {:.2} |\n", stats.elapsed_seconds);
+
+    let _ = writeln!(md, "## Parameters\n");
+    for (k, v) in &stats.params { let _ = writeln!(md, "- `{}`: {}", k, v); }
+
+    let _ = writeln!(md, "\n## Per contig\n");
+    let _ = writeln!(md, "| Contig | Bases | Variants | SNV | MNP | Ins | Del | Ts/Tv | Mean depth | Callable | Seconds |");
+    let _ = writeln!(md, "|---|---:|---:|---:|---:|---:|---:|---:|---:|---:|---:|");
+    for c in &stats.contigs {
+        let _ = writeln!(
+            md, "| {} | {} | {} | {} | {} | {} | {} | {} | {:.1} | {:.1}% | {:.2} |",
+            c.contig, c.bases, c.variants, c.snvs, c.mnps, c.insertions, c.deletions,
+            c.ts_tv.map_or("-".into(), |r| format!("{:.2}", r)), c.mean_depth, 100.0 * c.callable_fraction, c.seconds,
+        );
+    }
+
+    let _ = writeln!(md, "\n## Depth histogram\n\n| Depth | Positions |\n|---|---:|");
+    for (i, bound) in DEPTH_BINS.iter().enumerate() {
+        let label = match DEPTH_BINS.get(i + 1) {
+            Some(next) if next - bound == 1 => bound.to_string(),
+            Some(next) => format!("{}-{}", bound, next - 1),
+            None => format!("{}+", bound),
+        };
+        let _ = writeln!(md, "| {} | {} |", label, stats.depth_histogram.get(bound).copied().unwrap_or(0));
+    }
+
+    let mut slowest: Vec<&RegionStats> = stats.regions.iter().collect();
+    slowest.sort_by(|a, b| b.seconds.total_cmp(&a.seconds));
+    let _ = writeln!(md, "\n## Slowest windows\n\n| Region | Variants | Mean depth | Seconds |\n|---|---:|---:|---:|");
+    for r in slowest.into_iter().take(10) {
+        let _ = writeln!(md, "| {} | {} | {:.1} | {:.2} |", r.region, r.variants, r.mean_depth, r.seconds);
+    }
+    md
+}
+
+/// Stats bucket: transition/transversion for SNVs, otherwise the variant kind
+fn snv_class(call: &Call) -> &'static str {
+    if call.kind != VariantKind::Snv { return call.kind.as_str(); }
+    let (r, a) = (call.ref_allele.chars().next().unwrap_or('N'), call.alt_allele.chars().next().unwrap_or('N'));
+    if is_transition(r, a) { "transition" } else if is_transversion(r, a) { "transversion" } else { "other" }
 }
 
 fn is_transition(r: char, a: char) -> bool {
@@ -289,6 +2065,213 @@ fn export_variants(calls: &[Call], out: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Sample column name: the alignment file name without its extension
+fn sample_name(bam: &Path) -> String {
+    bam.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_else(|| "SAMPLE".into())
+}
+
+/// VCF header with the alignment header's contigs, INFO DP/AF/SB and
+/// FORMAT GT/GQ/DP/AD/PL; paired runs add the somatic INFO fields and
+/// FILTERs, gVCF runs END, MIN_DP and the <NON_REF> allele
+fn vcf_header(header: &sam::Header, sample: &str, paired: bool, gvcf: bool) -> vcf::Header {
+    let mut builder = vcf::Header::builder()
+        .add_info(info_key::TOTAL_DEPTH, Map::<Info>::from(info_key::TOTAL_DEPTH))
+        .add_info(info_key::ALLELE_FREQUENCIES, Map::<Info>::from(info_key::ALLELE_FREQUENCIES))
+        .add_info("SB", Map::<Info>::new(
+            info::Number::Count(1), info::Type::Float,
+            "Strand balance: 1 - |forward fraction of ALT reads - forward fraction of REF reads|",
+        ))
+        .add_format(format_key::GENOTYPE, Map::<Format>::from(format_key::GENOTYPE))
+        .add_format(format_key::CONDITIONAL_GENOTYPE_QUALITY, Map::<Format>::from(format_key::CONDITIONAL_GENOTYPE_QUALITY))
+        .add_format(format_key::READ_DEPTH, Map::<Format>::from(format_key::READ_DEPTH))
+        .add_format(format_key::READ_DEPTHS, Map::<Format>::new(
+            format::Number::ReferenceAlternateBases, format::Type::Integer,
+            "Allelic depths for the ref and alt alleles",
+        ))
+        .add_format("PL", Map::<Format>::new(
+            format::Number::Samples, format::Type::Integer,
+            "Phred-scaled genotype likelihoods of 0/0, 0/1, 1/1",
+        ))
+        .add_sample_name(sample);
+    if paired {
+        builder = builder
+            .add_info("SOMATIC", Map::<Info>::new(
+                info::Number::Count(0), info::Type::Flag,
+                "Somatic in the tumor relative to the matched normal",
+            ))
+            .add_info("TLOD", Map::<Info>::new(
+                info::Number::Count(1), info::Type::Float,
+                "Tumor log10 odds of the ALT allele against sequencing error",
+            ))
+            .add_info("NDP", Map::<Info>::new(info::Number::Count(1), info::Type::Integer, "Normal read depth"))
+            .add_info("NAD", Map::<Info>::new(info::Number::Count(1), info::Type::Integer, "Normal ALT read count"))
+            .add_info("SPV", Map::<Info>::new(
+                info::Number::Count(1), info::Type::Float,
+                "One-sided Fisher's exact p-value of tumor vs normal ALT fraction",
+            ));
+        for (id, description) in SOMATIC_FILTERS {
+            builder = builder.add_filter(id, Map::<Filter>::new(description));
+        }
+    }
+    if gvcf {
+        builder = builder
+            .add_info(info_key::END_POSITION, Map::<Info>::from(info_key::END_POSITION))
+            .add_format("MIN_DP", Map::<Format>::new(
+                format::Number::Count(1), format::Type::Integer,
+                "Minimum depth in the reference block",
+            ))
+            .add_alternative_allele("NON_REF", Map::<AlternativeAllele>::new(
+                "Any allele not represented at this site by REF and ALT",
+            ));
+    }
+    for (name, rs) in header.reference_sequences() {
+        let mut contig = Map::<Contig>::new();
+        *contig.length_mut() = Some(rs.length().get());
+        builder = builder.add_contig(name.to_string(), contig);
+    }
+    builder.build()
+}
+
+/// One VCF record per call, carrying the diploid genotype fields and, in
+/// paired mode, the somatic evidence and FILTER. gVCF records add <NON_REF>
+/// to ALT, with its AD (reads of any other allele) and the six-genotype PL.
+fn call_to_record(call: &Call, gvcf: bool) -> Result<RecordBuf> {
+    let af = if gvcf { vec![Some(call.vaf), None] } else { vec![Some(call.vaf)] };
+    let mut fields = vec![
+        (String::from(info_key::TOTAL_DEPTH), Some(InfoValue::from(call.depth as i32))),
+        (String::from(info_key::ALLELE_FREQUENCIES), Some(InfoValue::from(af))),
+        (String::from("SB"), Some(InfoValue::from(call.strand_bias))),
+    ];
+    let filters = match &call.somatic {
+        Some(evidence) => {
+            if evidence.is_somatic() {
+                fields.push((String::from("SOMATIC"), Some(InfoValue::Flag)));
+            }
+            fields.push((String::from("TLOD"), Some(InfoValue::from(call.tlod))));
+            fields.push((String::from("NDP"), Some(InfoValue::from(evidence.normal_depth as i32))));
+            fields.push((String::from("NAD"), Some(InfoValue::from(evidence.normal_alt_count as i32))));
+            fields.push((String::from("SPV"), Some(InfoValue::from(evidence.fisher_p as f32))));
+            if evidence.is_somatic() { Filters::pass() } else { [evidence.filter.clone()].into_iter().collect() }
+        }
+        None => Filters::pass(),
+    };
+    let info: InfoFields = fields.into_iter().collect();
+    let keys: Keys = [
+        format_key::GENOTYPE, format_key::CONDITIONAL_GENOTYPE_QUALITY, format_key::READ_DEPTH, format_key::READ_DEPTHS, "PL",
+    ]
+    .into_iter()
+    .map(String::from)
+    .collect();
+    let mut alt_alleles = vec![call.alt_allele.clone()];
+    let mut ad = vec![Some(call.ref_count as i32), Some(call.alt_count as i32)];
+    let pl: &[u32] = if gvcf {
+        alt_alleles.push(String::from(NON_REF));
+        ad.push(Some(call.depth.saturating_sub(call.ref_count + call.alt_count) as i32));
+        &call.non_ref_pl
+    } else {
+        &call.pl
+    };
+    let samples = Samples::new(keys, vec![vec![
+        Some(SampleValue::from(call.genotype.as_str())),
+        Some(SampleValue::from(call.gq.round() as i32)),
+        Some(SampleValue::from(call.depth as i32)),
+        Some(SampleValue::from(ad)),
+        Some(SampleValue::from(pl.iter().map(|&pl| Some(pl.min(i32::MAX as u32) as i32)).collect::<Vec<_>>())),
+    ]]);
+    Ok(RecordBuf::builder()
+        .set_reference_sequence_name(call.chrom.clone())
+        .set_variant_start(Position::try_from(call.pos as usize)?)
+        .set_reference_bases(call.ref_allele.clone())
+        .set_alternate_bases(AlternateBases::from(alt_alleles))
+        .set_quality_score(call.qual)
+        .set_filters(filters)
+        .set_info(info)
+        .set_samples(samples)
+        .build())
+}
+
+/// gVCF reference block record: ALT <NON_REF>, INFO END, and FORMAT GT 0/0
+/// with the block's minimum GQ, median DP, MIN_DP and PL
+fn block_to_record(block: &RefBlock) -> Result<RecordBuf> {
+    let info: InfoFields = [(String::from(info_key::END_POSITION), Some(InfoValue::from(block.end as i32)))]
+        .into_iter()
+        .collect();
+    let keys: Keys = [format_key::GENOTYPE, format_key::CONDITIONAL_GENOTYPE_QUALITY, format_key::READ_DEPTH, "MIN_DP", "PL"]
+        .into_iter()
+        .map(String::from)
+        .collect();
+    let samples = Samples::new(keys, vec![vec![
+        Some(SampleValue::from("0/0")),
+        Some(SampleValue::from(block.gq as i32)),
+        Some(SampleValue::from(block.median_dp as i32)),
+        Some(SampleValue::from(block.min_dp as i32)),
+        Some(SampleValue::from(block.pl.iter().map(|&pl| Some(pl.min(i32::MAX as u32) as i32)).collect::<Vec<_>>())),
+    ]]);
+    Ok(RecordBuf::builder()
+        .set_reference_sequence_name(block.chrom.clone())
+        .set_variant_start(Position::try_from(block.start)?)
+        .set_reference_bases(block.ref_base.to_string())
+        .set_alternate_bases(AlternateBases::from(vec![String::from(NON_REF)]))
+        .set_info(info)
+        .set_samples(samples)
+        .build())
+}
+
+/// Write calls (and gVCF reference blocks) as bgzipped VCF (tabix index) or
+/// BCF (CSI index) in header contig order; returns the index path
+#[allow(clippy::too_many_arguments)]
+fn export_vcf(
+    calls: &[Call],
+    blocks: &[RefBlock],
+    header: &sam::Header,
+    sample: &str,
+    paired: bool,
+    gvcf: bool,
+    out: &Path,
+    format: OutputFormat,
+) -> Result<PathBuf> {
+    let vcf_header = vcf_header(header, sample, paired, gvcf);
+    let contig_order: HashMap<String, usize> = header
+        .reference_sequences()
+        .keys()
+        .enumerate()
+        .map(|(i, name)| (name.to_string(), i))
+        .collect();
+    let rank = |chrom: &str| contig_order.get(chrom).copied().unwrap_or(usize::MAX);
+    let mut keyed: Vec<((usize, usize, String), RecordBuf)> = calls
+        .iter()
+        .map(|c| Ok(((rank(&c.chrom), c.pos as usize, c.alt_allele.clone()), call_to_record(c, gvcf)?)))
+        .chain(blocks.iter().map(|b| Ok(((rank(&b.chrom), b.start, String::new()), block_to_record(b)?))))
+        .collect::<Result<_>>()?;
+    keyed.sort_by(|a, b| a.0.cmp(&b.0));
+    let sorted = keyed.into_iter().map(|(_, record)| record);
+
+    let file = File::create(out).with_context(|| format!("creating {}", out.display()))?;
+    if format == OutputFormat::Bcf {
+        let mut writer = bcf::io::Writer::new(file);
+        writer.write_variant_header(&vcf_header)?;
+        for record in sorted {
+            writer.write_variant_record(&vcf_header, &record)?;
+        }
+        writer.try_finish()?;
+        let index_path = PathBuf::from(format!("{}.csi", out.display()));
+        let index = bcf::fs::index(out).context("indexing BCF failed")?;
+        csi::fs::write(&index_path, &index).context("writing CSI index failed")?;
+        Ok(index_path)
+    } else {
+        let mut writer = vcf::io::Writer::new(bgzf::Writer::new(file));
+        writer.write_variant_header(&vcf_header)?;
+        for record in sorted {
+            writer.write_variant_record(&vcf_header, &record)?;
+        }
+        writer.into_inner().finish()?;
+        let index_path = PathBuf::from(format!("{}.tbi", out.display()));
+        let index = vcf::fs::index(out).context("indexing VCF failed")?;
+        tabix::fs::write(&index_path, &index).context("writing tabix index failed")?;
+        Ok(index_path)
+    }
+}
+
 fn calls_to_dataframe(calls: &[Call]) -> Result<DataFrame> {
     if calls.is_empty() {
         return Err(anyhow!("No variants to convert to DataFrame"));
@@ -301,7 +2284,50 @@ fn calls_to_dataframe(calls: &[Call]) -> Result<DataFrame> {
         "pos",
         calls.iter().map(|c| c.pos).collect::<Vec<i64>>(),
     );
-    DataFrame::new(vec![chrom, pos]).context("failed to create DataFrame")
+    let mut columns = vec![
+        chrom,
+        pos,
+        Series::new("type", calls.iter().map(|c| c.kind.as_str()).collect::<Vec<&str>>()),
+        Series::new("ref", calls.iter().map(|c| c.ref_allele.clone()).collect::<Vec<String>>()),
+        Series::new("alt", calls.iter().map(|c| c.alt_allele.clone()).collect::<Vec<String>>()),
+        Series::new("depth", calls.iter().map(|c| c.depth).collect::<Vec<u32>>()),
+        Series::new("ref_count", calls.iter().map(|c| c.ref_count).collect::<Vec<u32>>()),
+        Series::new("alt_count", calls.iter().map(|c| c.alt_count).collect::<Vec<u32>>()),
+        Series::new("gt", calls.iter().map(|c| c.genotype.clone()).collect::<Vec<String>>()),
+        Series::new("pl", calls.iter().map(|c| format!("{},{},{}", c.pl[0], c.pl[1], c.pl[2])).collect::<Vec<String>>()),
+        Series::new("gq", calls.iter().map(|c| c.gq).collect::<Vec<f32>>()),
+        Series::new("qual", calls.iter().map(|c| c.qual).collect::<Vec<f32>>()),
+        Series::new("mapq_avg", calls.iter().map(|c| c.mapq_avg).collect::<Vec<f32>>()),
+        Series::new("baseq_avg", calls.iter().map(|c| c.baseq_avg).collect::<Vec<f32>>()),
+        Series::new("vaf", calls.iter().map(|c| c.vaf).collect::<Vec<f32>>()),
+        Series::new("strand_bias", calls.iter().map(|c| c.strand_bias).collect::<Vec<f32>>()),
+    ];
+    // Repeat context columns only when a call's contig is in the FASTA index
+    if calls.iter().any(|c| c.context.is_some()) {
+        columns.push(Series::new(
+            "homopolymer_len",
+            calls.iter().map(|c| c.context.as_ref().map(|x| x.homopolymer_len)).collect::<Vec<Option<u32>>>(),
+        ));
+        columns.push(Series::new(
+            "str_unit",
+            calls.iter().map(|c| c.context.as_ref().map(|x| x.str_unit.clone())).collect::<Vec<Option<String>>>(),
+        ));
+        columns.push(Series::new(
+            "str_copies",
+            calls.iter().map(|c| c.context.as_ref().map(|x| x.str_copies)).collect::<Vec<Option<u32>>>(),
+        ));
+    }
+    // Somatic columns only in paired (--normal-bam) mode
+    if calls.iter().any(|c| c.somatic.is_some()) {
+        let evidence = |c: &Call| c.somatic.clone().unwrap_or_default();
+        columns.push(Series::new("tlod", calls.iter().map(|c| c.tlod).collect::<Vec<f32>>()));
+        columns.push(Series::new("normal_depth", calls.iter().map(|c| evidence(c).normal_depth).collect::<Vec<u32>>()));
+        columns.push(Series::new("normal_alt_count", calls.iter().map(|c| evidence(c).normal_alt_count).collect::<Vec<u32>>()));
+        columns.push(Series::new("somatic_p", calls.iter().map(|c| evidence(c).fisher_p).collect::<Vec<f64>>()));
+        columns.push(Series::new("somatic", calls.iter().map(|c| evidence(c).is_somatic()).collect::<Vec<bool>>()));
+        columns.push(Series::new("filter", calls.iter().map(|c| evidence(c).filter).collect::<Vec<String>>()));
+    }
+    DataFrame::new(columns).context("failed to create DataFrame")
 }
 
 fn export_stats(stats: &CallerStats, out: &Path) -> Result<()> {
@@ -312,13 +2338,30 @@ fn export_stats(stats: &CallerStats, out: &Path) -> Result<()> {
 
 fn print_summary(calls: &[Call], stats: &CallerStats) {
     println!("=== Variant Calling Summary ===");
-    println!("Total targets processed: {}", stats.total_targets);
-    println!("Targets with variants: {}", stats.targets_with_variants);
+    println!("Total windows processed: {}", stats.total_windows);
+    println!("Windows with variants: {}", stats.windows_with_variants);
     println!("Total variants called: {}", stats.total_variants_called);
     println!("Transition/Transversion ratio: {:.2}", 
         stats.variants_by_type.get("transition").cloned().unwrap_or(0) as f64
         / stats.variants_by_type.get("transversion").cloned().unwrap_or(1) as f64
     );
+    if calls.iter().any(|c| c.somatic.is_some()) {
+        println!("Somatic variants (PASS): {}", stats.somatic_variants);
+    }
+    if !stats.targets.is_empty() {
+        let length: usize = stats.targets.iter().map(|t| t.end + 1 - t.start).sum();
+        let depth: f64 = stats.targets.iter().map(|t| t.mean_depth * (t.end + 1 - t.start) as f64).sum();
+        let callable: f64 = stats.targets.iter().map(|t| t.callable_fraction * (t.end + 1 - t.start) as f64).sum();
+        println!("BED targets: {} ({} bp, {} with variants)", stats.targets.len(), length,
+            stats.targets.iter().filter(|t| t.variants > 0).count());
+        println!("Target mean depth: {:.1}, callable: {:.1}%", depth / length as f64, 100.0 * callable / length as f64);
+    }
+    if stats.variants_filtered > 0 {
+        println!("Calls removed by --filter-expr: {}", stats.variants_filtered);
+    }
+    if stats.reads_downsampled > 0 {
+        println!("Reads dropped by --max-depth: {}", stats.reads_downsampled);
+    }
     println!("Runtime: {:.2} seconds", stats.elapsed_seconds);
     println!("Threads used: {}", stats.threads_used);
     println!("Parameters:");
@@ -326,3 +2369,52 @@ fn print_summary(calls: &[Call], stats: &CallerStats) {
         println!("  {}: {}", k, v);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ERR: f64 = 1e-3;
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 0.01, "expected {expected}, got {actual}");
+    }
+
+    #[test]
+    fn het_site_likelihoods() {
+        let genotype = Genotype::from_log10_likelihoods(binomial_log10_likelihoods(20, 10, ERR));
+        assert_eq!(genotype.gt, "0/1");
+        assert_eq!(genotype.pl, [240, 0, 240]);
+        assert_eq!(genotype.gq, 99.0);
+        assert_close(genotype.qual, 209.84);
+    }
+
+    #[test]
+    fn hom_ref_site_likelihoods() {
+        let genotype = Genotype::from_log10_likelihoods(binomial_log10_likelihoods(20, 0, ERR));
+        assert_eq!(genotype.gt, "0/0");
+        assert_eq!(genotype.pl, [0, 60, 600]);
+        assert_eq!(genotype.gq, 60.0);
+        assert_close(genotype.qual, 0.0);
+    }
+
+    #[test]
+    fn hom_alt_site_likelihoods() {
+        let genotype = Genotype::from_log10_likelihoods(binomial_log10_likelihoods(20, 20, ERR));
+        assert_eq!(genotype.gt, "1/1");
+        assert_eq!(genotype.pl, [600, 60, 0]);
+        assert_eq!(genotype.gq, 60.0);
+        assert_close(genotype.qual, 566.91);
+    }
+
+    #[test]
+    fn non_ref_pl_matches_snv_likelihoods() {
+        let mut entry = PileupEntry::default();
+        for i in 0..12 { entry.add_base('A', i % 2 == 0, 60, 30); }
+        for i in 0..7 { entry.add_base('G', i % 2 == 0, 60, 25); }
+        entry.add_base('T', true, 60, 10);
+        let genotype = Genotype::from_log10_likelihoods(entry.snv_log10_likelihoods('A', 'G'));
+        let pl6 = non_ref_pl(&[entry.terms('A'), entry.terms('G'), entry.other_terms(&['A', 'G'])]);
+        assert_eq!(genotype.pl, [pl6[0], pl6[1], pl6[2]]);
+    }
+}
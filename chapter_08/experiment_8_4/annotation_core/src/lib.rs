@@ -0,0 +1,833 @@
+//! Gene interval trees and allele frequency tables shared by the variant
+//! annotator (experiment 8.4) and the genomic pipeline (experiment 8.5).
+
+use anyhow::{anyhow, Context, Result};
+use fxhash::FxHashMap;
+use indicatif::{ProgressBar, ProgressStyle};
+use log::{info, warn};
+use lru::LruCache;
+use memmap2::Mmap;
+use noodles_bgzf as bgzf;
+use noodles_core::Position;
+use noodles_csi::BinningIndex;
+use noodles_gff as gff;
+use noodles_tabix as tabix;
+use rust_lapper::{Interval, Lapper};
+use serde::{Deserialize, Serialize};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cmp::{Ordering as CmpOrdering, Reverse},
+    collections::{BinaryHeap, HashMap},
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Lines, Write},
+    num::NonZeroUsize,
+    path::Path,
+    sync::{Arc, Mutex, atomic::{AtomicUsize, Ordering}},
+    time::Instant,
+};
+use flate2::read::MultiGzDecoder;
+use tempfile::{NamedTempFile, TempPath};
+
+/// Allocator wrapper counting live heap bytes, used to enforce the memory
+/// budget of `load_freqs`. Binaries install it as their `#[global_allocator]`;
+/// without it `HEAP_IN_USE` stays at zero and frequency tables never spill.
+pub struct CountingAllocator;
+
+pub static HEAP_IN_USE: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            HEAP_IN_USE.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        HEAP_IN_USE.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            HEAP_IN_USE.fetch_add(new_size, Ordering::Relaxed);
+            HEAP_IN_USE.fetch_sub(layout.size(), Ordering::Relaxed);
+        }
+        new_ptr
+    }
+}
+
+/// Represents a gene interval for the Lapper interval tree
+pub type GeneIv = Interval<GeneInfo>;
+
+/// Gene information stored in the interval tree
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GeneInfo {
+    pub gene_name: String,
+    pub gene_id: String,
+    pub strand: String,
+    pub biotype: String,
+}
+
+/// Gene annotation file format, which decides how attributes are parsed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnnotationFormat {
+    /// `key=value;` attributes, with `ID`/`Parent` links
+    Gff3,
+    /// `key "value";` attributes, linked through `gene_id`/`transcript_id`
+    Gtf,
+}
+
+impl AnnotationFormat {
+    /// Format by file extension (ignoring `.gz`), e.g. `genes.gtf.gz`
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_lowercase();
+        let name = name.strip_suffix(".gz").unwrap_or(&name);
+        match name.rsplit_once('.')?.1 {
+            "gtf" => Some(AnnotationFormat::Gtf),
+            "gff" | "gff3" => Some(AnnotationFormat::Gff3),
+            _ => None,
+        }
+    }
+    
+    /// Format by the first line of the file that decides it: the
+    /// `##gff-version` directive or the attributes of the first feature
+    pub fn from_line(line: &str) -> Option<Self> {
+        if let Some(version) = line.strip_prefix("##gff-version") {
+            return Some(if version.trim().starts_with('3') {
+                AnnotationFormat::Gff3
+            } else {
+                AnnotationFormat::Gtf
+            });
+        }
+        if line.starts_with('#') {
+            return None;
+        }
+        let attributes = line.split('\t').nth(8)?;
+        let first = attributes.split(';').next().unwrap_or("").trim();
+        Some(if !first.contains('=') && first.contains(' ') {
+            AnnotationFormat::Gtf
+        } else {
+            AnnotationFormat::Gff3
+        })
+    }
+}
+
+impl std::fmt::Display for AnnotationFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            AnnotationFormat::Gff3 => "GFF3",
+            AnnotationFormat::Gtf => "GTF",
+        })
+    }
+}
+
+/// Feature line of a GTF file
+pub struct GtfRecord {
+    reference_sequence_name: String,
+    ty: String,
+    start: usize,
+    end: usize,
+    strand: String,
+    attributes: Vec<(String, String)>,
+}
+
+impl std::str::FromStr for GtfRecord {
+    type Err = anyhow::Error;
+    
+    fn from_str(line: &str) -> Result<Self> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [chrom, _, ty, start, end, _, strand, _, attributes] = fields.as_slice() else {
+            return Err(anyhow!("expected 9 tab-separated fields, got {}", fields.len()));
+        };
+        let attributes = attributes
+            .split(';')
+            .filter_map(|attribute| {
+                let (key, value) = attribute.trim().split_once(char::is_whitespace)?;
+                Some((key.to_string(), value.trim().trim_matches('"').to_string()))
+            })
+            .collect();
+        Ok(GtfRecord {
+            reference_sequence_name: chrom.to_string(),
+            ty: ty.to_string(),
+            start: start.parse().with_context(|| format!("invalid start: {}", start))?,
+            end: end.parse().with_context(|| format!("invalid end: {}", end))?,
+            strand: strand.to_string(),
+            attributes,
+        })
+    }
+}
+
+/// One feature of a gene annotation file, whichever its format
+pub enum Feature {
+    Gff(gff::Record),
+    Gtf(GtfRecord),
+}
+
+impl Feature {
+    pub fn reference_sequence_name(&self) -> &str {
+        match self {
+            Feature::Gff(record) => record.reference_sequence_name(),
+            Feature::Gtf(record) => &record.reference_sequence_name,
+        }
+    }
+    
+    pub fn ty(&self) -> &str {
+        match self {
+            Feature::Gff(record) => record.ty(),
+            Feature::Gtf(record) => &record.ty,
+        }
+    }
+    
+    /// 1-based inclusive start
+    pub fn start(&self) -> usize {
+        match self {
+            Feature::Gff(record) => record.start().into(),
+            Feature::Gtf(record) => record.start,
+        }
+    }
+    
+    /// 1-based inclusive end
+    pub fn end(&self) -> usize {
+        match self {
+            Feature::Gff(record) => record.end().into(),
+            Feature::Gtf(record) => record.end,
+        }
+    }
+    
+    /// Strand as `+`, `-` or `.`
+    pub fn strand(&self) -> String {
+        match self {
+            Feature::Gff(record) => record.strand().to_string(),
+            Feature::Gtf(record) => record.strand.clone(),
+        }
+    }
+    
+    /// First value of the attribute `key`
+    pub fn attribute(&self, key: &str) -> Option<String> {
+        match self {
+            Feature::Gff(record) => record.attributes().get(key).map(|v| v.to_string()),
+            Feature::Gtf(record) => record
+                .attributes
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.clone()),
+        }
+    }
+}
+
+/// Open a plain or gzip/bgzip-compressed text file, detected by its magic bytes
+pub fn open_maybe_gzip(path: &Path) -> Result<Box<dyn BufRead>> {
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open annotation file: {:?}", path))?;
+    let mut reader = BufReader::new(file);
+    if reader.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader))))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// Features of a GFF3 or GTF file, plain or gzip-compressed. The format
+/// comes from the extension, or from the file's first lines when the
+/// extension is not `.gff`, `.gff3` or `.gtf`.
+pub struct FeatureReader {
+    pub format: AnnotationFormat,
+    lines: Lines<Box<dyn BufRead>>,
+}
+
+impl FeatureReader {
+    pub fn open(path: &Path) -> Result<Self> {
+        let format = match AnnotationFormat::from_extension(path) {
+            Some(format) => format,
+            None => {
+                let mut format = AnnotationFormat::Gff3;
+                for line in open_maybe_gzip(path)?.lines() {
+                    if let Some(detected) = AnnotationFormat::from_line(&line?) {
+                        format = detected;
+                        break;
+                    }
+                }
+                format
+            }
+        };
+        Ok(FeatureReader {
+            format,
+            lines: open_maybe_gzip(path)?.lines(),
+        })
+    }
+}
+
+impl Iterator for FeatureReader {
+    type Item = Result<Feature>;
+    
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e.into())),
+            };
+            // Embedded sequences end the feature section of a GFF3
+            if line.starts_with("##FASTA") {
+                return None;
+            }
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            return Some(match self.format {
+                AnnotationFormat::Gff3 => line
+                    .parse::<gff::Record>()
+                    .map(Feature::Gff)
+                    .map_err(|e| anyhow!("{}: {}", e, line)),
+                AnnotationFormat::Gtf => line
+                    .parse::<GtfRecord>()
+                    .map(Feature::Gtf)
+                    .map_err(|e| anyhow!("{}: {}", e, line)),
+            });
+        }
+    }
+}
+
+/// Build a gene interval tree from a GFF3 or GTF file
+pub fn build_gene_tree<P: AsRef<Path>>(p: P) -> Result<HashMap<String, Lapper<GeneInfo>>> {
+    let start_time = Instant::now();
+    
+    // Open the annotation reader
+    let rdr = FeatureReader::open(p.as_ref())?;
+    info!("Building gene interval trees from {}: {:?}", rdr.format, p.as_ref());
+    
+    // Create interval map per chromosome
+    let mut intervals_by_chrom: HashMap<String, Vec<GeneIv>> = HashMap::new();
+    
+    // Process records
+    let mut record_count = 0;
+    let mut gene_count = 0;
+    
+    for record_result in rdr {
+        record_count += 1;
+        
+        // Safely unwrap record
+        let record = match record_result {
+            Ok(r) => r,
+            Err(e) => {
+                warn!("Skipping malformed annotation record: {}", e);
+                continue;
+            }
+        };
+        
+        // Only process gene features
+        if record.ty() != "gene" {
+            continue;
+        }
+        
+        gene_count += 1;
+        
+        // Extract gene information
+        let gene_name = record.attribute("gene_name")
+            .or_else(|| record.attribute("Name"))
+            .unwrap_or_else(|| ".".to_string());
+            
+        let gene_id = record.attribute("gene_id")
+            .or_else(|| record.attribute("ID"))
+            .unwrap_or_else(|| ".".to_string());
+            
+        let strand = record.strand();
+        let biotype = record.attribute("biotype")
+            .or_else(|| record.attribute("gene_biotype"))
+            .or_else(|| record.attribute("gene_type"))
+            .unwrap_or_else(|| ".".to_string());
+        
+        // Create gene info
+        let gene_info = GeneInfo {
+            gene_name,
+            gene_id,
+            strand,
+            biotype,
+        };
+        
+        // Create interval
+        let interval = GeneIv {
+            start: record.start(),
+            stop: record.end() + 1,
+            val: gene_info,
+        };
+        
+        // Add to chromosome-specific vector
+        let chrom = record.reference_sequence_name().to_string();
+        intervals_by_chrom.entry(chrom).or_default().push(interval);
+    }
+    
+    // Create a Lapper for each chromosome
+    let mut result = HashMap::new();
+    for (chrom, intervals) in intervals_by_chrom {
+        result.insert(chrom, Lapper::new(intervals));
+    }
+    
+    let elapsed = start_time.elapsed();
+    info!(
+        "Built gene trees for {} chromosomes with {} genes (from {} records) in {:.2?}",
+        result.len(),
+        gene_count,
+        record_count,
+        elapsed
+    );
+    
+    Ok(result)
+}
+
+/// Genes overlapping the 1-based inclusive span `[start, end]`, in order
+/// of position, with the fraction of each gene the span covers
+pub fn gene_overlaps<'a>(
+    gene_trees: &'a HashMap<String, Lapper<GeneInfo>>,
+    chrom: &str,
+    start: u64,
+    end: u64,
+) -> Vec<(&'a GeneInfo, f64)> {
+    let Some(tree) = gene_trees.get(chrom) else {
+        return Vec::new();
+    };
+    // Gene intervals are half-open, `stop` being one past the gene end
+    tree.find(start as usize, end as usize + 1)
+        .map(|iv| {
+            let covered = iv.stop.min(end as usize + 1) - iv.start.max(start as usize);
+            (&iv.val, covered as f64 / (iv.stop - iv.start) as f64)
+        })
+        .collect()
+}
+
+/// Number of records between sparse index entries of an on-disk frequency table
+const FREQ_INDEX_STRIDE: usize = 1024;
+
+/// Maximum number of spilled runs open at once while merging
+const FREQ_MERGE_FAN_IN: usize = 64;
+
+/// Smallest map growth worth spilling (capped at the budget itself), used
+/// when other allocations already take up most of the memory budget
+const MIN_SPILL_BYTES: usize = 64 << 20;
+
+/// Frequency key ordering used by spilled runs: chromosome, position, allele
+fn compare_freq_keys(a: &(String, u64, String), b: &(String, u64, String)) -> CmpOrdering {
+    a.0.cmp(&b.0).then(a.1.cmp(&b.1)).then_with(|| a.2.cmp(&b.2))
+}
+
+/// Parse a `chrom\tpos\tallele\tfreq` line from a spilled run
+fn parse_freq_line(line: &str) -> Option<((String, u64, String), f64)> {
+    let mut fields = line.split('\t');
+    let chrom = fields.next()?.to_string();
+    let pos = fields.next()?.parse().ok()?;
+    let allele = fields.next()?.to_string();
+    let freq = fields.next()?.parse().ok()?;
+    Some(((chrom, pos, allele), freq))
+}
+
+/// K-way merge sorted runs into `writer`, keeping the first occurrence of
+/// duplicated keys (earlier runs win). `on_line` sees the key and byte length
+/// of every line written.
+fn merge_freq_runs(
+    runs: &[TempPath],
+    writer: &mut impl Write,
+    mut on_line: impl FnMut(&(String, u64, String), usize),
+) -> Result<()> {
+    let mut readers: Vec<Lines<BufReader<File>>> = runs
+        .iter()
+        .map(|run| Ok(BufReader::new(File::open(run)?).lines()))
+        .collect::<Result<_>>()?;
+    
+    let mut heap = BinaryHeap::new();
+    let mut heads = vec![0.0; readers.len()];
+    for (idx, reader) in readers.iter_mut().enumerate() {
+        if let Some(line) = reader.next() {
+            if let Some((key, freq)) = parse_freq_line(&line?) {
+                heads[idx] = freq;
+                heap.push(Reverse((key, idx)));
+            }
+        }
+    }
+    
+    let mut last_key: Option<(String, u64, String)> = None;
+    while let Some(Reverse((key, idx))) = heap.pop() {
+        if last_key.as_ref() != Some(&key) {
+            let line = format!("{}\t{}\t{}\t{}\n", key.0, key.1, key.2, heads[idx]);
+            writer.write_all(line.as_bytes())?;
+            on_line(&key, line.len());
+            last_key = Some(key);
+        }
+        
+        if let Some(line) = readers[idx].next() {
+            if let Some((next_key, freq)) = parse_freq_line(&line?) {
+                heads[idx] = freq;
+                heap.push(Reverse((next_key, idx)));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Sorted frequency file on disk, memory-mapped, with a sparse index of
+/// `(chrom, pos) -> byte offset` every `FREQ_INDEX_STRIDE` records
+pub struct SortedFreqFile {
+    mmap: Mmap,
+    index: Vec<(String, u64, usize)>,
+    len: usize,
+    _file: NamedTempFile,
+}
+
+impl SortedFreqFile {
+    /// Merge sorted runs into a single file and build its sparse index. Runs
+    /// are merged at most `FREQ_MERGE_FAN_IN` at a time, through intermediate
+    /// runs, so the number of open files stays bounded.
+    fn from_runs(mut runs: Vec<TempPath>, dir: &Path) -> Result<Self> {
+        while runs.len() > FREQ_MERGE_FAN_IN {
+            info!("Merging {} frequency runs {} at a time", runs.len(), FREQ_MERGE_FAN_IN);
+            runs = runs
+                .chunks(FREQ_MERGE_FAN_IN)
+                .map(|group| {
+                    let run = NamedTempFile::new_in(dir)?;
+                    let mut writer = BufWriter::new(run.reopen()?);
+                    merge_freq_runs(group, &mut writer, |_, _| {})?;
+                    writer.flush()?;
+                    Ok(run.into_temp_path())
+                })
+                .collect::<Result<_>>()?;
+        }
+        
+        let merged = NamedTempFile::new_in(dir)?;
+        let mut writer = BufWriter::new(merged.reopen()?);
+        let mut index = Vec::new();
+        let mut offset = 0usize;
+        let mut len = 0usize;
+        merge_freq_runs(&runs, &mut writer, |key, line_len| {
+            if len.is_multiple_of(FREQ_INDEX_STRIDE) {
+                index.push((key.0.clone(), key.1, offset));
+            }
+            offset += line_len;
+            len += 1;
+        })?;
+        writer.flush()?;
+        drop(writer);
+        
+        // SAFETY: the merged file is private to this process and no longer written to
+        let mmap = unsafe { Mmap::map(merged.as_file())? };
+        Ok(Self { mmap, index, len, _file: merged })
+    }
+    
+    fn get(&self, chrom: &str, pos: u64, allele: &str) -> Option<f64> {
+        // Last index block starting at or before (chrom, pos)
+        let block = self
+            .index
+            .partition_point(|(c, p, _)| (c.as_str(), *p) < (chrom, pos))
+            .saturating_sub(1);
+        let start = self.index.get(block)?.2;
+        
+        for line in self.mmap[start..].split(|&b| b == b'\n') {
+            let line = std::str::from_utf8(line).ok()?;
+            let ((c, p, a), freq) = parse_freq_line(line)?;
+            match (c.as_str(), p).cmp(&(chrom, pos)) {
+                CmpOrdering::Less => continue,
+                CmpOrdering::Greater => return None,
+                CmpOrdering::Equal if a == allele => return Some(freq),
+                CmpOrdering::Equal => continue,
+            }
+        }
+        None
+    }
+}
+
+/// Allele frequency table, held in memory, on disk once the memory budget of
+/// `load_freqs` is reached, or queried through a tabix index
+pub enum FreqTable {
+    InMemory(FxHashMap<(String, u64, String), f64>),
+    OnDisk(SortedFreqFile),
+    Tabix(TabixFreqTable),
+}
+
+impl FreqTable {
+    pub fn get(&self, chrom: &str, pos: u64, allele: &str) -> Option<f64> {
+        match self {
+            FreqTable::InMemory(map) => map.get(&(chrom.to_string(), pos, allele.to_string())).copied(),
+            FreqTable::OnDisk(file) => file.get(chrom, pos, allele),
+            FreqTable::Tabix(table) => table.get(chrom, pos, allele),
+        }
+    }
+    
+    pub fn len(&self) -> usize {
+        match self {
+            FreqTable::InMemory(map) => map.len(),
+            FreqTable::OnDisk(file) => file.len,
+            // Nothing is loaded up front
+            FreqTable::Tabix(_) => 0,
+        }
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Positions covered by one cached region of a tabix-indexed frequency file
+pub const TABIX_REGION_SIZE: u64 = 16_384;
+
+/// Frequencies of one region, keyed by position and allele
+pub type FreqRegion = FxHashMap<(u64, String), f64>;
+
+/// Tabix-indexed frequency file queried one region at a time on demand,
+/// keeping the most recently used regions in an LRU cache so memory stays
+/// flat whatever the size of the database
+pub struct TabixFreqTable {
+    reader: Mutex<bgzf::Reader<File>>,
+    index: tabix::Index,
+    cache: Mutex<LruCache<(String, u64), Arc<FreqRegion>>>,
+}
+
+impl TabixFreqTable {
+    pub fn open(path: &str, cache_regions: usize) -> Result<Self> {
+        let index_path = format!("{}.tbi", path);
+        let index = tabix::read(&index_path)
+            .with_context(|| format!("Failed to load frequency index: {}", index_path))?;
+        let reader = bgzf::Reader::new(
+            File::open(path).with_context(|| format!("Failed to open frequency file: {}", path))?,
+        );
+        let capacity = NonZeroUsize::new(cache_regions)
+            .ok_or_else(|| anyhow!("The frequency cache must hold at least 1 region"))?;
+        
+        info!(
+            "Querying frequencies from {} through its tabix index ({} chromosomes, {} cached regions of {} bp)",
+            path,
+            index.header().reference_sequence_names().len(),
+            cache_regions,
+            TABIX_REGION_SIZE
+        );
+        Ok(Self {
+            reader: Mutex::new(reader),
+            index,
+            cache: Mutex::new(LruCache::new(capacity)),
+        })
+    }
+    
+    pub fn get(&self, chrom: &str, pos: u64, allele: &str) -> Option<f64> {
+        let key = (chrom.to_string(), pos / TABIX_REGION_SIZE);
+        let cached = self.cache.lock().unwrap().get(&key).cloned();
+        let region = match cached {
+            Some(region) => region,
+            None => {
+                let region = match self.read_region(chrom, key.1) {
+                    Ok(region) => Arc::new(region),
+                    Err(e) => {
+                        warn!("Failed to query frequencies at {}:{}: {}", chrom, pos, e);
+                        return None;
+                    }
+                };
+                self.cache.lock().unwrap().put(key, Arc::clone(&region));
+                region
+            }
+        };
+        region.get(&(pos, allele.to_string())).copied()
+    }
+    
+    /// Read every frequency in the `region`-th block of `TABIX_REGION_SIZE`
+    /// positions of a chromosome
+    fn read_region(&self, chrom: &str, region: u64) -> Result<FreqRegion> {
+        let mut freqs = FreqRegion::default();
+        let Some(reference_sequence_id) = self.index.header().reference_sequence_names().get_index_of(chrom) else {
+            return Ok(freqs);
+        };
+        let first = region * TABIX_REGION_SIZE;
+        let last = first + TABIX_REGION_SIZE - 1;
+        let interval = Position::try_from(first.max(1) as usize)?..=Position::try_from(last as usize)?;
+        let chunks = self.index.query(reference_sequence_id, interval)?;
+        
+        let mut reader = self.reader.lock().unwrap();
+        let mut line = String::new();
+        for chunk in chunks {
+            reader.seek(chunk.start())?;
+            while reader.virtual_position() < chunk.end() {
+                line.clear();
+                if reader.read_line(&mut line)? == 0 {
+                    break;
+                }
+                
+                // Same columns as `load_freqs`; chunks may start before the region
+                let fields: Vec<_> = line.trim_end().split('\t').collect();
+                if fields.len() < 5 || fields[0] != chrom {
+                    continue;
+                }
+                let (Ok(pos), Ok(freq)) = (fields[1].parse::<u64>(), fields[4].parse::<f64>()) else {
+                    continue;
+                };
+                if (first..=last).contains(&pos) {
+                    freqs.entry((pos, fields[3].to_string())).or_insert(freq);
+                }
+            }
+        }
+        Ok(freqs)
+    }
+}
+
+/// Write the in-memory map to a sorted run file and clear it. The run is
+/// kept as a path, so spilled runs do not hold file descriptors open.
+fn spill_freq_run(
+    map: &mut FxHashMap<(String, u64, String), f64>,
+    dir: &Path,
+) -> Result<TempPath> {
+    let mut entries: Vec<_> = map.drain().collect();
+    entries.sort_unstable_by(|a, b| compare_freq_keys(&a.0, &b.0));
+    map.shrink_to_fit();
+    
+    let run = NamedTempFile::new_in(dir)?;
+    let mut writer = BufWriter::new(run.reopen()?);
+    for ((chrom, pos, allele), freq) in entries {
+        writeln!(writer, "{}\t{}\t{}\t{}", chrom, pos, allele, freq)?;
+    }
+    writer.flush()?;
+    Ok(run.into_temp_path())
+}
+
+/// Load allele frequencies from a compressed gnomAD-like file
+/// (`chrom pos ref alt freq`), keeping one chromosome when `chromosome_filter`
+/// is set. Once the map has grown by 80% of `max_memory` less the heap already
+/// in use when loading started, entries are spilled to sorted runs and served
+/// from a memory-mapped file. A key listed twice keeps its first frequency.
+pub fn load_freqs<P: AsRef<Path>>(
+    bgz_path: P,
+    chromosome_filter: Option<&str>,
+    max_memory: Option<usize>,
+) -> Result<FreqTable> {
+    let start_time = Instant::now();
+    info!("Loading allele frequencies from {:?}", bgz_path.as_ref());
+    
+    let mut map = FxHashMap::default();
+    let path = bgz_path.as_ref();
+    
+    // Open BGZF reader
+    let rdr = bgzf::Reader::new(
+        File::open(path).with_context(|| format!("Failed to open frequency file: {:?}", path))?,
+    );
+    
+    // Create buffered reader
+    let buf_reader = BufReader::new(rdr);
+    
+    // Setup progress bar
+    let pb = ProgressBar::new_spinner();
+    pb.set_style(
+        ProgressStyle::default_spinner()
+            .template("{spinner:.green} [{elapsed_precise}] {msg}")
+            .unwrap(),
+    );
+    
+    let mut line_count = 0;
+    let mut loaded_count = 0;
+    let spill_dir = std::env::temp_dir();
+    let mut spilled_runs = Vec::new();
+    // Only the map's own growth counts, so a spill always frees its budget
+    let heap_at_start = HEAP_IN_USE.load(Ordering::Relaxed);
+    let spill_bytes = max_memory.map(|limit| {
+        let budget = limit / 10 * 8;
+        budget.saturating_sub(heap_at_start).max(MIN_SPILL_BYTES.min(budget))
+    });
+    
+    // Process lines
+    for (i, line_result) in buf_reader.lines().enumerate() {
+        // Update progress every 100k lines
+        if i % 100_000 == 0 {
+            pb.set_message(format!("Processed {} lines, loaded {} variants", i, loaded_count));
+            pb.tick();
+        }
+        
+        line_count += 1;
+        
+        // Safely unwrap line
+        let line = match line_result {
+            Ok(l) => l,
+            Err(e) => {
+                warn!("Error reading line from frequency file: {}", e);
+                continue;
+            }
+        };
+        
+        // Skip header lines
+        if line.starts_with('#') {
+            continue;
+        }
+        
+        // Parse fields
+        let fields: Vec<_> = line.split('\t').collect();
+        if fields.len() < 5 {
+            warn!("Skipping malformed frequency data line: insufficient fields");
+            continue;
+        }
+        
+        // Extract chromosome
+        let chrom = fields[0].to_string();
+        
+        // Apply chromosome filter if specified
+        if let Some(target_chrom) = chromosome_filter {
+            if chrom != target_chrom {
+                continue;
+            }
+        }
+        
+        // Parse position
+        let pos = match fields[1].parse::<u64>() {
+            Ok(p) => p,
+            Err(_) => {
+                warn!("Skipping line with invalid position: {}", fields[1]);
+                continue;
+            }
+        };
+        
+        // Extract allele
+        let allele = fields[3].to_string();
+        
+        // Parse frequency
+        let freq = match fields[4].parse::<f64>() {
+            Ok(f) => f,
+            Err(_) => {
+                warn!("Skipping line with invalid frequency: {}", fields[4]);
+                continue;
+            }
+        };
+        
+        // The first line of a duplicated key wins, as in the run merge and
+        // tabix lookups, whether or not the table spills
+        map.entry((chrom, pos, allele)).or_insert(freq);
+        loaded_count += 1;
+        
+        // Spill to a sorted run on disk when approaching the memory budget
+        if let Some(spill_bytes) = spill_bytes {
+            let grown = HEAP_IN_USE.load(Ordering::Relaxed).saturating_sub(heap_at_start);
+            if loaded_count % 10_000 == 0 && grown >= spill_bytes {
+                info!(
+                    "Frequency table near the memory budget; spilling {} entries to disk",
+                    map.len()
+                );
+                spilled_runs.push(spill_freq_run(&mut map, &spill_dir)?);
+            }
+        }
+    }
+    
+    pb.finish_with_message(format!(
+        "Loaded {} frequency entries from {} lines",
+        loaded_count, line_count
+    ));
+    
+    let table = if spilled_runs.is_empty() {
+        FreqTable::InMemory(map)
+    } else {
+        if !map.is_empty() {
+            spilled_runs.push(spill_freq_run(&mut map, &spill_dir)?);
+        }
+        info!("Merging {} spilled frequency runs into a disk-backed table", spilled_runs.len());
+        FreqTable::OnDisk(SortedFreqFile::from_runs(spilled_runs, &spill_dir)?)
+    };
+    
+    let elapsed = start_time.elapsed();
+    info!(
+        "Loaded {} frequency entries in {:.2?}",
+        table.len(),
+        elapsed
+    );
+    
+    Ok(table)
+}
@@ -1,36 +1,170 @@
 use anyhow::{Context, Result, anyhow};
+use annotation_core::{
+    build_gene_tree, gene_overlaps, load_freqs, CountingAllocator, FeatureReader, FreqTable, TabixFreqTable,
+    HEAP_IN_USE,
+};
 use clap::Parser;
-use fxhash::FxHashMap;
 use indicatif::{ProgressBar, ProgressStyle};
 use log::{info, warn, error};
 use noodles_vcf as vcf;
-use noodles_gff as gff;
 use noodles_bgzf as bgzf;
+use noodles_core::Position;
+use noodles_csi::BinningIndex;
+use noodles_tabix as tabix;
+use bigtools::{BigWigRead, utils::reopen::ReopenableFile};
 use bio::io::fasta::IndexedReader;
 use polars::prelude::*;
 use rayon::prelude::*;
 use rust_lapper::{Interval, Lapper};
 use serde::{Serialize, Deserialize};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet},
     fs::File,
     io::{BufRead, BufReader, BufWriter, Write},
     path::Path,
-    sync::{Arc, Mutex},
+    sync::{Arc, Mutex, MutexGuard, atomic::Ordering},
     time::Instant,
 };
-// Temporarily commenting out tch imports
-// use tch::{CModule, Tensor, Device};
+#[cfg(feature = "tch")]
+use tch::{CModule, Kind, Tensor};
 
-// Define stub types to keep the code compiling
-#[derive(Debug, Clone)]
-struct CModule;
-#[derive(Debug, Clone)]
-struct Tensor;
-#[derive(Debug, Clone)]
-struct Device;
+// Splice prediction needs libtorch; builds without the `tch` feature reject
+// `--splice-model` when loading it, so no model ever exists
+#[cfg(not(feature = "tch"))]
+#[derive(Debug)]
+enum CModule {}
 use thiserror::Error;
 
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Parse a memory size such as "800M" or "16G" into bytes
+fn parse_memory_size(s: &str) -> std::result::Result<usize, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let value: f64 = number.parse().map_err(|_| format!("Invalid memory size: {}", s))?;
+    let multiplier: f64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1.0,
+        "K" | "KB" => 1024.0,
+        "M" | "MB" => 1024.0 * 1024.0,
+        "G" | "GB" => 1024.0 * 1024.0 * 1024.0,
+        other => return Err(format!("Unknown memory unit: {}", other)),
+    };
+    Ok((value * multiplier) as usize)
+}
+
+/// Annotation track given on the command line as `NAME=PATH`
+#[derive(Debug, Clone)]
+struct TrackSpec {
+    name: String,
+    path: String,
+}
+
+fn parse_track_spec(s: &str) -> std::result::Result<TrackSpec, String> {
+    match s.split_once('=') {
+        Some((name, path)) if !name.is_empty() && !path.is_empty() => Ok(TrackSpec {
+            name: name.to_string(),
+            path: path.to_string(),
+        }),
+        _ => Err(format!("Expected NAME=PATH, got: {}", s)),
+    }
+}
+
+/// Timing and throughput for one pipeline stage
+#[derive(Debug, Serialize)]
+struct StageProfile {
+    stage: String,
+    wall_seconds: f64,
+    bytes_read: u64,
+    bytes_written: u64,
+    records: u64,
+    records_per_sec: f64,
+    heap_bytes: usize,
+}
+
+/// Runtime breakdown written by `--profile-json`
+#[derive(Debug, Serialize, Default)]
+struct RunProfile {
+    tool: String,
+    threads: usize,
+    total_wall_seconds: f64,
+    stages: Vec<StageProfile>,
+}
+
+impl RunProfile {
+    fn new(threads: usize) -> Self {
+        RunProfile {
+            tool: env!("CARGO_PKG_NAME").to_string(),
+            threads,
+            ..Default::default()
+        }
+    }
+    
+    fn record(&mut self, stage: &str, started: Instant, records: u64, bytes_read: u64, bytes_written: u64) {
+        let wall_seconds = started.elapsed().as_secs_f64();
+        self.stages.push(StageProfile {
+            stage: stage.to_string(),
+            wall_seconds,
+            bytes_read,
+            bytes_written,
+            records,
+            records_per_sec: if wall_seconds > 0.0 { records as f64 / wall_seconds } else { 0.0 },
+            heap_bytes: HEAP_IN_USE.load(Ordering::Relaxed),
+        });
+    }
+    
+    fn write(mut self, path: &str, run_start: Instant) -> Result<()> {
+        self.total_wall_seconds = run_start.elapsed().as_secs_f64();
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create profile file: {}", path))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), &self)
+            .context("Failed to write runtime profile")?;
+        Ok(())
+    }
+}
+
+/// Summary of an annotation run, written by `--stats-out`
+#[derive(Debug, Serialize, Default)]
+struct AnnotationStats {
+    variants: u64,
+    variants_per_chromosome: BTreeMap<String, u64>,
+    genes_hit: usize,
+    rare_variants: u64,
+    splice_predicted: u64,
+    cohort_filtered: u64,
+    runtime_seconds: f64,
+    #[serde(skip)]
+    genes: HashSet<String>,
+}
+
+impl AnnotationStats {
+    fn record(&mut self, ann: &AnnotatedVariant) {
+        self.variants += 1;
+        *self.variants_per_chromosome.entry(ann.chrom.clone()).or_insert(0) += 1;
+        if let Some(gene_id) = &ann.gene_id {
+            self.genes.insert(gene_id.clone());
+        }
+        if ann.is_rare {
+            self.rare_variants += 1;
+        }
+    }
+    
+    fn write(mut self, path: &str, run_start: Instant) -> Result<()> {
+        self.genes_hit = self.genes.len();
+        self.runtime_seconds = run_start.elapsed().as_secs_f64();
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create stats file: {}", path))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), &self)
+            .context("Failed to write annotation stats")?;
+        Ok(())
+    }
+}
+
+fn file_len(path: &str) -> u64 {
+    std::fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
+
 /// Errors specific to variant annotation
 #[derive(Error, Debug)]
 pub enum AnnotationError {
@@ -54,6 +188,12 @@ pub enum AnnotationError {
     
     #[error("Failed to predict splice effect: {0}")]
     SplicePredictionError(String),
+    
+    #[error("Failed to load annotation track: {0}")]
+    TrackLoadError(String),
+    
+    #[error("Failed to score pathogenicity: {0}")]
+    PathogenicityError(String),
 }
 
 /// Command line arguments
@@ -69,7 +209,7 @@ struct Args {
     #[arg(short, long)]
     vcf: String,
     
-    /// Gene annotation GFF file path
+    /// Gene annotation GFF3 or GTF file path, optionally gzip-compressed
     #[arg(short, long)]
     gff: String,
     
@@ -81,15 +221,55 @@ struct Args {
     #[arg(short, long)]
     reference: Option<String>,
     
-    /// Optional pre-trained splice effect prediction model
+    /// Optional pre-trained splice effect prediction model (TorchScript;
+    /// needs a build with the tch feature)
     #[arg(long)]
     splice_model: Option<String>,
     
+    /// Pathogenicity model: logistic regression coefficients (.json) or a
+    /// TorchScript model (needs the tch feature); default is the heuristic
+    #[arg(long)]
+    pathogenicity_model: Option<String>,
+    
+    /// Variants per splice model forward pass
+    #[arg(long, default_value_t = 64)]
+    splice_batch_size: usize,
+    
+    /// Read a bgzipped --vcf through its tabix index (<file>.tbi) one
+    /// chromosome at a time
+    #[arg(long)]
+    indexed_vcf: bool,
+    
+    /// VCF records read and annotated per parallel chunk
+    #[arg(long, default_value_t = 10_000)]
+    chunk_size: usize,
+    
+    /// BED track as NAME=PATH (repeatable), e.g. promoters or enhancers;
+    /// adds a NAME column with the names of the overlapping features
+    #[arg(long = "bed-track", value_parser = parse_track_spec)]
+    bed_tracks: Vec<TrackSpec>,
+    
+    /// BigWig track as NAME=PATH (repeatable), e.g. phyloP or phastCons
+    /// scores; adds a numeric NAME column with the score at the variant
+    #[arg(long = "bigwig-track", value_parser = parse_track_spec)]
+    bigwig_tracks: Vec<TrackSpec>,
+    
     /// Allele frequency cutoff for rare variants
     #[arg(long, default_value_t = 0.001)]
     rare_cutoff: f64,
     
-    /// Output file path (supports .csv, .parquet, .json formats)
+    /// Write one output file per chromosome (e.g. annotated.chr1.parquet)
+    /// and skip chromosomes whose file exists, to resume interrupted runs
+    #[arg(long)]
+    shard_by_chrom: bool,
+    
+    /// Drop variants whose cohort allele frequency in the input VCF is
+    /// above this
+    #[arg(long)]
+    max_cohort_af: Option<f64>,
+    
+    /// Output file path (supports .csv, .parquet, .json formats, and .vcf or
+    /// .vcf.gz for the input VCF with annotations in INFO)
     #[arg(short, long, default_value = "annotated_variants.parquet")]
     output: String,
     
@@ -112,23 +292,35 @@ struct Args {
     /// Export prediction confidence scores
     #[arg(long)]
     export_scores: bool,
-}
-
-/// Represents a gene interval for the Lapper interval tree
-type GeneIv = Interval<GeneInfo>;
-
-/// Gene information stored in the interval tree
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
-struct GeneInfo {
-    gene_name: String,
-    gene_id: String,
-    strand: String,
-    biotype: String,
+    
+    /// Query the gnomAD file through its tabix index (<file>.tbi) per
+    /// variant region instead of loading it into memory
+    #[arg(long, conflicts_with = "max_memory")]
+    tabix: bool,
+    
+    /// Frequency regions of 16 kb kept in the LRU cache with --tabix
+    #[arg(long, default_value_t = 1024)]
+    freq_cache_regions: usize,
+    
+    /// Heap budget for the frequency table (e.g. 4G); beyond it frequencies
+    /// are spilled to sorted runs and served from a memory-mapped file
+    #[arg(long, value_parser = parse_memory_size)]
+    max_memory: Option<usize>,
+    
+    /// Write a per-stage runtime breakdown (wall time, bytes, records/sec) as JSON
+    #[arg(long)]
+    profile_json: Option<String>,
+    
+    /// Write annotation statistics (variants per chromosome, genes hit,
+    /// rare and splice-predicted variants, runtime) as JSON
+    #[arg(long)]
+    stats_out: Option<String>,
 }
 
 /// Genomic sequence cache to minimize reference lookups
 struct SequenceCache {
     fasta_reader: Option<IndexedReader<File>>,
+    sequence_lengths: HashMap<String, u64>,
     cache: HashMap<String, Vec<u8>>,
     max_cache_size: usize,
 }
@@ -144,23 +336,32 @@ impl SequenceCache {
         } else {
             None
         };
+        let sequence_lengths = fasta_reader
+            .iter()
+            .flat_map(|reader| reader.index.sequences())
+            .map(|sequence| (sequence.name, sequence.len))
+            .collect();
         
         Ok(Self {
             fasta_reader,
+            sequence_lengths,
             cache: HashMap::new(),
             max_cache_size,
         })
     }
     
-    fn fetch_sequence(&mut self, chrom: &str, pos: u64, context_size: usize) -> Result<Vec<u8>> {
+    /// Fetch the 0-based half-open interval `[start, end)`, cut short at
+    /// the end of the chromosome
+    fn fetch_sequence(&mut self, chrom: &str, start: u64, end: u64) -> Result<Vec<u8>> {
         if self.fasta_reader.is_none() {
             return Err(anyhow!(AnnotationError::NoReferenceError));
         }
         
-        // Calculate start and end positions for the window
-        let half_size = context_size / 2;
-        let start = if pos <= half_size as u64 { 0 } else { pos - half_size as u64 };
-        let end = start + context_size as u64;
+        let length = *self.sequence_lengths.get(chrom).ok_or_else(|| {
+            anyhow!(AnnotationError::SequenceFetchError(format!("{} is not in the reference", chrom)))
+        })?;
+        let end = end.min(length);
+        let start = start.min(end);
         
         // Create cache key
         let cache_key = format!("{}:{}-{}", chrom, start, end);
@@ -196,6 +397,53 @@ impl SequenceCache {
     }
 }
 
+/// One value per Rayon worker plus a spare for threads outside the pool
+/// (such as the main thread), so each worker locks only its own copy
+struct PerThread<T> {
+    shards: Vec<Mutex<T>>,
+}
+
+impl<T> PerThread<T> {
+    fn new(threads: usize, mut init: impl FnMut() -> Result<T>) -> Result<Self> {
+        let shards = (0..=threads)
+            .map(|_| init().map(Mutex::new))
+            .collect::<Result<_>>()?;
+        Ok(Self { shards })
+    }
+    
+    /// The calling thread's value. Its lock is only ever taken by that
+    /// thread, so it is uncontended.
+    fn local(&self) -> MutexGuard<'_, T> {
+        let workers = self.shards.len() - 1;
+        let shard = rayon::current_thread_index().map_or(workers, |index| index % workers);
+        self.shards[shard].lock().unwrap()
+    }
+}
+
+/// One `SequenceCache` per Rayon worker, each with its own reference
+/// reader, so workers fetch sequence without contending on a shared lock
+struct SequenceCaches {
+    caches: PerThread<SequenceCache>,
+    has_reference: bool,
+}
+
+impl SequenceCaches {
+    fn new(reference_path: Option<&str>, max_cache_size: usize, threads: usize) -> Result<Self> {
+        Ok(Self {
+            caches: PerThread::new(threads, || SequenceCache::new(reference_path, max_cache_size))?,
+            has_reference: reference_path.is_some(),
+        })
+    }
+    
+    fn has_reference(&self) -> bool {
+        self.has_reference
+    }
+    
+    fn local(&self) -> MutexGuard<'_, SequenceCache> {
+        self.caches.local()
+    }
+}
+
 /// Variant annotation record with all computed fields
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct AnnotatedVariant {
@@ -209,232 +457,1758 @@ struct AnnotatedVariant {
     gene_biotype: Option<String>,
     gnomad_af: f64,
     is_rare: bool,
+    /// ALT allele frequency among the called genotypes of the input VCF
+    cohort_af: Option<f64>,
+    /// Samples heterozygous and homozygous for the ALT allele
+    n_het: u32,
+    n_hom: u32,
+    /// Structural variant type, END and |SVLEN| for symbolic or SVTYPE records
+    sv_type: Option<String>,
+    sv_end: Option<u64>,
+    sv_length: Option<u64>,
+    /// Genes a structural variant overlaps with the fraction of each it
+    /// covers, e.g. `GENE1:0.250,GENE2:1.000`
+    gene_overlaps: Option<String>,
+    /// Most severe consequence terms, `&`-joined (e.g. `missense_variant`)
+    consequence: Option<String>,
+    transcript_id: Option<String>,
+    hgvs_c: Option<String>,
+    hgvs_p: Option<String>,
     delta_psi: Option<f64>,
     pathogenicity_score: f64,
     confidence: f64,
+    /// Values of the `--bed-track` and `--bigwig-track` tracks at the
+    /// variant, by track name; tracks without a value are left out
+    #[serde(flatten)]
+    tracks: BTreeMap<String, TrackValue>,
+}
+
+/// Chromosome, position, REF and first ALT of a VCF record
+type VariantKey = (String, u64, String, String);
+
+/// Transcript model from the GFF: exons and CDS segments as 1-based
+/// inclusive genomic intervals, sorted by start
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Transcript {
+    transcript_id: String,
+    gene_name: String,
+    reverse: bool,
+    exons: Vec<(u64, u64)>,
+    cds: Vec<(u64, u64)>,
+}
+
+/// Exons and CDS segments collected for one transcript while reading the GFF
+#[derive(Default)]
+struct TranscriptParts {
+    chrom: String,
+    reverse: bool,
+    transcript_id: Option<String>,
+    gene_id: Option<String>,
+    gene_name: Option<String>,
+    exons: Vec<(u64, u64)>,
+    cds: Vec<(u64, u64)>,
 }
 
-/// Build a gene interval tree from a GFF file
-fn build_gene_tree<P: AsRef<Path>>(p: P) -> Result<HashMap<String, Lapper<GeneInfo>>> {
+/// Build per-chromosome transcript trees from the exon and CDS features of
+/// a GFF3 or GTF, grouped by their `Parent` (GFF3) or `transcript_id` (GTF)
+fn build_transcript_tree<P: AsRef<Path>>(p: P) -> Result<HashMap<String, Lapper<Transcript>>> {
     let start_time = Instant::now();
-    info!("Building gene interval trees from GFF: {:?}", p.as_ref());
-    
-    // Open GFF reader
-    let file = File::open(&p)
-        .with_context(|| format!("Failed to open GFF file: {:?}", p.as_ref()))?;
-    let mut rdr = gff::reader::Reader::new(BufReader::new(file));
-    
-    // Create interval map per chromosome
-    let mut intervals_by_chrom: HashMap<String, Vec<GeneIv>> = HashMap::new();
+    let rdr = FeatureReader::open(p.as_ref())?;
     
-    // Process records
-    let mut record_count = 0;
-    let mut gene_count = 0;
+    let mut parts: HashMap<String, TranscriptParts> = HashMap::new();
+    let mut gene_names: HashMap<String, String> = HashMap::new();
     
-    for record_result in rdr.records() {
-        record_count += 1;
-        
-        // Safely unwrap record
+    for record_result in rdr {
         let record = match record_result {
             Ok(r) => r,
             Err(e) => {
-                warn!("Skipping malformed GFF record: {}", e);
+                warn!("Skipping malformed annotation record: {}", e);
                 continue;
             }
         };
+        let attribute = |key: &str| record.attribute(key);
+        let interval = (record.start() as u64, record.end() as u64);
         
-        // Only process gene features
-        if record.ty() != "gene" {
+        match record.ty() {
+            "gene" => {
+                let id = attribute("ID").or_else(|| attribute("gene_id"));
+                if let (Some(id), Some(name)) = (id, attribute("gene_name").or_else(|| attribute("Name"))) {
+                    gene_names.insert(id, name);
+                }
+            }
+            ty @ ("exon" | "CDS") => {
+                let Some(parents) = attribute("Parent").or_else(|| attribute("transcript_id")) else {
+                    continue;
+                };
+                for parent in parents.split(',') {
+                    let transcript = parts.entry(parent.to_string()).or_default();
+                    transcript.chrom = record.reference_sequence_name().to_string();
+                    transcript.reverse = record.strand() == "-";
+                    // GTF exons carry their gene, having no transcript line to link to
+                    if transcript.gene_id.is_none() {
+                        transcript.gene_id = attribute("gene_id");
+                    }
+                    if transcript.gene_name.is_none() {
+                        transcript.gene_name = attribute("gene_name");
+                    }
+                    if ty == "exon" {
+                        transcript.exons.push(interval);
+                    } else {
+                        transcript.cds.push(interval);
+                    }
+                }
+            }
+            _ => {
+                // Any other feature with an ID may be the parent of exons
+                if let Some(id) = attribute("ID") {
+                    let transcript = parts.entry(id).or_default();
+                    transcript.transcript_id = attribute("transcript_id");
+                    transcript.gene_id = attribute("Parent");
+                    transcript.gene_name = attribute("gene_name");
+                }
+            }
+        }
+    }
+    
+    let mut intervals_by_chrom: HashMap<String, Vec<Interval<Transcript>>> = HashMap::new();
+    let mut transcript_count = 0;
+    for (id, mut part) in parts {
+        if part.exons.is_empty() && part.cds.is_empty() {
             continue;
         }
-        
-        gene_count += 1;
-        
-        // Extract gene information
-        let gene_name = record.attributes().get("gene_name")
-            .or_else(|| record.attributes().get("Name"))
-            .map(|v| v.to_string())
-            .unwrap_or_else(|| ".".to_string());
-            
-        let gene_id = record.attributes().get("gene_id")
-            .or_else(|| record.attributes().get("ID"))
-            .map(|v| v.to_string())
-            .unwrap_or_else(|| ".".to_string());
-            
-        let strand = record.strand().to_string();
-        let biotype = record.attributes().get("biotype")
-            .or_else(|| record.attributes().get("gene_biotype"))
-            .map(|v| v.to_string())
+        part.exons.sort_unstable();
+        part.cds.sort_unstable();
+        if part.exons.is_empty() {
+            part.exons = part.cds.clone();
+        }
+        let gene_name = part
+            .gene_name
+            .or_else(|| part.gene_id.as_ref().and_then(|gene| gene_names.get(gene).cloned()))
+            .or_else(|| gene_names.get(&id).cloned())
             .unwrap_or_else(|| ".".to_string());
-        
-        // Create gene info
-        let gene_info = GeneInfo {
+        let transcript = Transcript {
+            transcript_id: part.transcript_id.unwrap_or(id),
             gene_name,
-            gene_id,
-            strand,
-            biotype,
-        };
-        
-        // Create interval
-        let interval = GeneIv {
-            start: record.start().into(),
-            stop: record.end().into(),
-            val: gene_info,
+            reverse: part.reverse,
+            exons: part.exons,
+            cds: part.cds,
         };
-        
-        // Add to chromosome-specific vector
-        let chrom = record.reference_sequence_name().to_string();
-        intervals_by_chrom.entry(chrom).or_default().push(interval);
-    }
-    
-    // Create a Lapper for each chromosome
-    let mut result = HashMap::new();
-    for (chrom, intervals) in intervals_by_chrom {
-        result.insert(chrom, Lapper::new(intervals));
+        intervals_by_chrom.entry(part.chrom).or_default().push(Interval {
+            start: transcript.exons[0].0 as usize,
+            stop: transcript.exons[transcript.exons.len() - 1].1 as usize + 1,
+            val: transcript,
+        });
+        transcript_count += 1;
     }
     
-    let elapsed = start_time.elapsed();
+    let result: HashMap<_, _> = intervals_by_chrom
+        .into_iter()
+        .map(|(chrom, intervals)| (chrom, Lapper::new(intervals)))
+        .collect();
     info!(
-        "Built gene trees for {} chromosomes with {} genes (from {} records) in {:.2?}",
+        "Built transcript trees for {} chromosomes with {} transcripts in {:.2?}",
         result.len(),
-        gene_count,
-        record_count,
-        elapsed
+        transcript_count,
+        start_time.elapsed()
     );
-    
     Ok(result)
 }
 
-/// Load allele frequencies from a compressed gnomAD-like file
-fn load_freqs<P: AsRef<Path>>(
-    bgz_path: P,
-    chromosome_filter: Option<&str>,
-) -> Result<FxHashMap<(String, u64, String), f64>> {
-    let start_time = Instant::now();
-    info!("Loading allele frequencies from {:?}", bgz_path.as_ref());
-    
-    let mut map = FxHashMap::default();
-    let path = bgz_path.as_ref();
+/// Sequence Ontology consequence terms as VEP reports them, most severe first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Consequence {
+    SpliceAcceptor,
+    SpliceDonor,
+    StopGained,
+    Frameshift,
+    StopLost,
+    StartLost,
+    InframeInsertion,
+    InframeDeletion,
+    Missense,
+    SpliceRegion,
+    StopRetained,
+    Synonymous,
+    CodingSequence,
+    FivePrimeUtr,
+    ThreePrimeUtr,
+    NonCodingExon,
+    Intron,
+    Intergenic,
+}
+
+impl Consequence {
+    fn as_str(self) -> &'static str {
+        match self {
+            Consequence::SpliceAcceptor => "splice_acceptor_variant",
+            Consequence::SpliceDonor => "splice_donor_variant",
+            Consequence::StopGained => "stop_gained",
+            Consequence::Frameshift => "frameshift_variant",
+            Consequence::StopLost => "stop_lost",
+            Consequence::StartLost => "start_lost",
+            Consequence::InframeInsertion => "inframe_insertion",
+            Consequence::InframeDeletion => "inframe_deletion",
+            Consequence::Missense => "missense_variant",
+            Consequence::SpliceRegion => "splice_region_variant",
+            Consequence::StopRetained => "stop_retained_variant",
+            Consequence::Synonymous => "synonymous_variant",
+            Consequence::CodingSequence => "coding_sequence_variant",
+            Consequence::FivePrimeUtr => "5_prime_UTR_variant",
+            Consequence::ThreePrimeUtr => "3_prime_UTR_variant",
+            Consequence::NonCodingExon => "non_coding_transcript_exon_variant",
+            Consequence::Intron => "intron_variant",
+            Consequence::Intergenic => "intergenic_variant",
+        }
+    }
+}
+
+/// Consequence of a variant on its most severely affected transcript
+#[derive(Debug, Clone, Default)]
+struct ConsequenceCall {
+    /// `&`-joined terms, most severe first
+    consequence: Option<String>,
+    transcript_id: Option<String>,
+    hgvs_c: Option<String>,
+    hgvs_p: Option<String>,
+}
+
+/// Variant with the alleles' shared prefix and suffix removed: `start` is
+/// the 1-based position of the first REF base, or of the base after the
+/// insertion point when REF is empty
+struct TrimmedVariant {
+    start: u64,
+    ref_allele: Vec<u8>,
+    alt_allele: Vec<u8>,
+}
+
+impl TrimmedVariant {
+    fn new(pos: u64, ref_allele: &str, alt_allele: &str) -> Self {
+        let (mut r, mut a) = (ref_allele.as_bytes().to_ascii_uppercase(), alt_allele.as_bytes().to_ascii_uppercase());
+        while !r.is_empty() && !a.is_empty() && r.last() == a.last() {
+            r.pop();
+            a.pop();
+        }
+        let prefix = r.iter().zip(&a).take_while(|(x, y)| x == y).count();
+        Self {
+            start: pos + prefix as u64,
+            ref_allele: r[prefix..].to_vec(),
+            alt_allele: a[prefix..].to_vec(),
+        }
+    }
     
-    // Open BGZF reader
-    let rdr = bgzf::Reader::new(
-        File::open(path).with_context(|| format!("Failed to open frequency file: {:?}", path))?,
-    );
+    /// Genomic bases the variant touches: the deleted bases, or both bases
+    /// flanking an insertion
+    fn span(&self) -> (u64, u64) {
+        if self.ref_allele.is_empty() {
+            (self.start.saturating_sub(1), self.start)
+        } else {
+            (self.start, self.start + self.ref_allele.len() as u64 - 1)
+        }
+    }
+}
+
+fn reverse_complement(sequence: &[u8]) -> Vec<u8> {
+    sequence
+        .iter()
+        .rev()
+        .map(|base| match base {
+            b'A' => b'T',
+            b'C' => b'G',
+            b'G' => b'C',
+            b'T' => b'A',
+            other => *other,
+        })
+        .collect()
+}
+
+/// Translate complete codons with the standard genetic code; `*` is a stop
+/// and `X` a codon with ambiguous bases
+fn translate(sequence: &[u8]) -> Vec<u8> {
+    const BASES: &[u8; 4] = b"TCAG";
+    const CODE: &[u8; 64] = b"FFLLSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG";
+    sequence
+        .chunks_exact(3)
+        .map(|codon| {
+            codon
+                .iter()
+                .try_fold(0, |index, base| BASES.iter().position(|b| b == base).map(|i| index * 4 + i))
+                .map_or(b'X', |index| CODE[index])
+        })
+        .collect()
+}
+
+/// Three-letter amino acid code used by HGVS protein notation
+fn amino_acid_code(amino_acid: u8) -> &'static str {
+    match amino_acid {
+        b'A' => "Ala",
+        b'R' => "Arg",
+        b'N' => "Asn",
+        b'D' => "Asp",
+        b'C' => "Cys",
+        b'Q' => "Gln",
+        b'E' => "Glu",
+        b'G' => "Gly",
+        b'H' => "His",
+        b'I' => "Ile",
+        b'L' => "Leu",
+        b'K' => "Lys",
+        b'M' => "Met",
+        b'F' => "Phe",
+        b'P' => "Pro",
+        b'S' => "Ser",
+        b'T' => "Thr",
+        b'W' => "Trp",
+        b'Y' => "Tyr",
+        b'V' => "Val",
+        b'*' => "Ter",
+        _ => "Xaa",
+    }
+}
+
+fn amino_acid_codes(amino_acids: &[u8]) -> String {
+    amino_acids.iter().map(|&aa| amino_acid_code(aa)).collect()
+}
+
+impl Transcript {
+    fn is_coding(&self) -> bool {
+        !self.cds.is_empty()
+    }
     
-    // Create buffered reader
-    let buf_reader = BufReader::new(rdr);
+    /// 1-based position of a genomic base along `segments` read in
+    /// transcript orientation, if it lies in one of them
+    fn spliced_position(&self, segments: &[(u64, u64)], pos: u64) -> Option<u64> {
+        let mut offset = 0;
+        for i in 0..segments.len() {
+            let (start, end) = if self.reverse { segments[segments.len() - 1 - i] } else { segments[i] };
+            if (start..=end).contains(&pos) {
+                return Some(offset + if self.reverse { end - pos } else { pos - start } + 1);
+            }
+            offset += end - start + 1;
+        }
+        None
+    }
     
-    // Setup progress bar
-    let pb = ProgressBar::new_spinner();
-    pb.set_style(
-        ProgressStyle::default_spinner()
-            .template("{spinner:.green} [{elapsed_precise}] {msg}")
-            .unwrap(),
-    );
+    /// Transcript positions of the first and last CDS base
+    fn cds_bounds(&self) -> Option<(u64, u64)> {
+        let (first, last) = (self.cds.first()?.0, self.cds.last()?.1);
+        let (five_prime, three_prime) = if self.reverse { (last, first) } else { (first, last) };
+        Some((self.spliced_position(&self.exons, five_prime)?, self.spliced_position(&self.exons, three_prime)?))
+    }
     
-    let mut line_count = 0;
-    let mut loaded_count = 0;
+    /// HGVS position of a transcript position: c.-N / c.N / c.*N for coding
+    /// transcripts, n.N otherwise
+    fn format_position(&self, position: u64) -> String {
+        match self.cds_bounds() {
+            Some((cds_start, _)) if position < cds_start => format!("-{}", cds_start - position),
+            Some((_, cds_end)) if position > cds_end => format!("*{}", position - cds_end),
+            Some((cds_start, _)) => (position - cds_start + 1).to_string(),
+            None => position.to_string(),
+        }
+    }
     
-    // Process lines
-    for (i, line_result) in buf_reader.lines().enumerate() {
-        // Update progress every 100k lines
-        if i % 100_000 == 0 {
-            pb.set_message(format!("Processed {} lines, loaded {} variants", i, loaded_count));
-            pb.tick();
+    /// HGVS position of a genomic base in the transcript's exons or introns,
+    /// with intronic bases as an offset from the nearest exon boundary
+    fn hgvs_position(&self, pos: u64) -> Option<String> {
+        if let Some(position) = self.spliced_position(&self.exons, pos) {
+            return Some(self.format_position(position));
         }
+        let (left, right) = self
+            .exons
+            .windows(2)
+            .map(|pair| (pair[0].1, pair[1].0))
+            .find(|&(left, right)| left < pos && pos < right)?;
+        let (to_left, to_right) = (pos - left, right - pos);
+        // The 5' exon (left on the forward strand) wins ties
+        let (boundary, sign, distance) = match (self.reverse, to_left <= to_right, to_right <= to_left) {
+            (false, true, _) => (left, '+', to_left),
+            (false, false, _) => (right, '-', to_right),
+            (true, _, true) => (right, '+', to_right),
+            (true, _, false) => (left, '-', to_left),
+        };
+        let position = self.spliced_position(&self.exons, boundary)?;
+        Some(format!("{}{}{}", self.format_position(position), sign, distance))
+    }
+    
+    /// HGVS c. (or n.) notation of a variant inside the transcript
+    fn hgvs_c(&self, variant: &TrimmedVariant) -> Option<String> {
+        let prefix = if self.is_coding() { "c." } else { "n." };
+        let orient = |allele: &[u8]| -> String {
+            let allele = if self.reverse { reverse_complement(allele) } else { allele.to_vec() };
+            String::from_utf8_lossy(&allele).into_owned()
+        };
+        let (low, high) = variant.span();
+        let (first, last) = if self.reverse { (high, low) } else { (low, high) };
+        let range = |first: u64, last: u64| -> Option<String> {
+            if first == last {
+                self.hgvs_position(first)
+            } else {
+                Some(format!("{}_{}", self.hgvs_position(first)?, self.hgvs_position(last)?))
+            }
+        };
+        let change = match (variant.ref_allele.len(), variant.alt_allele.len()) {
+            (1, 1) => format!(
+                "{}{}>{}",
+                self.hgvs_position(first)?,
+                orient(&variant.ref_allele),
+                orient(&variant.alt_allele)
+            ),
+            (_, 0) => format!("{}del", range(first, last)?),
+            (0, _) => format!("{}ins{}", range(first, last)?, orient(&variant.alt_allele)),
+            _ => format!("{}delins{}", range(first, last)?, orient(&variant.alt_allele)),
+        };
+        Some(format!("{}{}", prefix, change))
+    }
+    
+    /// Consequences of where the variant falls: splice sites and regions,
+    /// introns, UTRs and non-coding exons. Returns also whether it touches
+    /// the CDS.
+    fn location_consequences(&self, variant: &TrimmedVariant) -> (Vec<Consequence>, bool) {
+        let (low, high) = variant.span();
+        let overlaps = |start: u64, end: u64| start <= high && low <= end;
+        let mut terms = Vec::new();
         
-        line_count += 1;
-        
-        // Safely unwrap line
-        let line = match line_result {
-            Ok(l) => l,
-            Err(e) => {
-                warn!("Error reading line from frequency file: {}", e);
+        for pair in self.exons.windows(2) {
+            let (intron_start, intron_end) = (pair[0].1 + 1, pair[1].0 - 1);
+            if intron_start > intron_end {
                 continue;
             }
-        };
+            let five_prime_site = (intron_start, (intron_start + 1).min(intron_end));
+            let three_prime_site = (intron_end.saturating_sub(1).max(intron_start), intron_end);
+            let (donor, acceptor) = if self.reverse {
+                (three_prime_site, five_prime_site)
+            } else {
+                (five_prime_site, three_prime_site)
+            };
+            if overlaps(donor.0, donor.1) {
+                terms.push(Consequence::SpliceDonor);
+            } else if overlaps(acceptor.0, acceptor.1) {
+                terms.push(Consequence::SpliceAcceptor);
+            } else if overlaps(intron_start, intron_end) {
+                terms.push(Consequence::Intron);
+            }
+            // Up to 3 exonic or 8 intronic bases from either end of the intron
+            if overlaps(pair[0].1.saturating_sub(2), intron_start + 7) || overlaps(intron_end.saturating_sub(7), pair[1].0 + 2) {
+                terms.push(Consequence::SpliceRegion);
+            }
+        }
         
-        // Skip header lines
-        if line.starts_with('#') {
-            continue;
+        let in_exon = self.exons.iter().any(|&(start, end)| overlaps(start, end));
+        let in_cds = self.cds.iter().any(|&(start, end)| overlaps(start, end));
+        if in_exon && !self.is_coding() {
+            terms.push(Consequence::NonCodingExon);
+        } else if in_exon && !in_cds {
+            let five_prime = if self.reverse { low > self.cds[self.cds.len() - 1].1 } else { high < self.cds[0].0 };
+            terms.push(if five_prime { Consequence::FivePrimeUtr } else { Consequence::ThreePrimeUtr });
+        }
+        (terms, in_cds)
+    }
+    
+    /// Protein consequence and HGVS p. notation of a variant inside one CDS
+    /// segment, from the CDS spliced out of the reference
+    fn coding_consequence(&self, variant: &TrimmedVariant, cds: &[u8]) -> Option<(Consequence, String)> {
+        let (low, high) = variant.span();
+        if !self.cds.iter().any(|&(start, end)| start <= low && high <= end) {
+            return None;
+        }
+        let (ref_seq, alt_seq) = if self.reverse {
+            (reverse_complement(&variant.ref_allele), reverse_complement(&variant.alt_allele))
+        } else {
+            (variant.ref_allele.clone(), variant.alt_allele.clone())
+        };
+        let positions = (self.spliced_position(&self.cds, low)?, self.spliced_position(&self.cds, high)?);
+        // Deleted bases start at the 5'-most one; an insertion goes after
+        // the 5' flanking base
+        let offset = if ref_seq.is_empty() {
+            positions.0.min(positions.1) as usize
+        } else {
+            positions.0.min(positions.1) as usize - 1
+        };
+        if cds.get(offset..offset + ref_seq.len())? != ref_seq.as_slice() {
+            return None;
         }
+        let mut alt_cds = cds[..offset].to_vec();
+        alt_cds.extend_from_slice(&alt_seq);
+        alt_cds.extend_from_slice(&cds[offset + ref_seq.len()..]);
         
-        // Parse fields
-        let fields: Vec<_> = line.split('\t').collect();
-        if fields.len() < 5 {
-            warn!("Skipping malformed frequency data line: insufficient fields");
-            continue;
+        let until_stop = |mut protein: Vec<u8>| {
+            if let Some(stop) = protein.iter().position(|&aa| aa == b'*') {
+                protein.truncate(stop + 1);
+            }
+            protein
+        };
+        let ref_protein = until_stop(translate(cds));
+        let alt_protein = until_stop(translate(&alt_cds));
+        let codon = offset / 3;
+        let first_changed = ref_protein.iter().zip(&alt_protein).position(|(r, a)| r != a);
+        
+        if alt_seq.len().abs_diff(ref_seq.len()) % 3 != 0 {
+            let at = first_changed.unwrap_or(codon.min(ref_protein.len().saturating_sub(1)));
+            let (ref_aa, alt_aa) = (*ref_protein.get(at)?, alt_protein.get(at).copied().unwrap_or(b'X'));
+            let notation = if alt_aa == b'*' {
+                format!("p.{}{}Ter", amino_acid_code(ref_aa), at + 1)
+            } else {
+                let stop = alt_protein[at..].iter().position(|&aa| aa == b'*');
+                format!(
+                    "p.{}{}{}fsTer{}",
+                    amino_acid_code(ref_aa),
+                    at + 1,
+                    amino_acid_code(alt_aa),
+                    stop.map_or("?".to_string(), |stop| (stop + 1).to_string())
+                )
+            };
+            return Some((Consequence::Frameshift, notation));
         }
         
-        // Extract chromosome
-        let chrom = fields[0].to_string();
+        // Equal up to the shorter protein means codons were added or removed
+        // right before the stop
+        let shorter = ref_protein.len().min(alt_protein.len());
+        let Some(prefix) = first_changed.or((ref_protein.len() != alt_protein.len()).then_some(shorter)) else {
+            let aa = *ref_protein.get(codon)?;
+            let term = if aa == b'*' { Consequence::StopRetained } else { Consequence::Synonymous };
+            return Some((term, format!("p.{}{}=", amino_acid_code(aa), codon + 1)));
+        };
+        let suffix = ref_protein[prefix..]
+            .iter()
+            .rev()
+            .zip(alt_protein[prefix..].iter().rev())
+            .take_while(|(r, a)| r == a)
+            .count();
+        let ref_mid = &ref_protein[prefix..ref_protein.len() - suffix];
+        let alt_mid = &alt_protein[prefix..alt_protein.len() - suffix];
+        let position = prefix + 1;
+        let ref_range = || match ref_mid {
+            [aa] => format!("{}{}", amino_acid_code(*aa), position),
+            _ => format!(
+                "{}{}_{}{}",
+                amino_acid_code(ref_mid[0]),
+                position,
+                amino_acid_code(ref_mid[ref_mid.len() - 1]),
+                position + ref_mid.len() - 1
+            ),
+        };
         
-        // Apply chromosome filter if specified
-        if let Some(target_chrom) = chromosome_filter {
-            if chrom != target_chrom {
-                continue;
+        let result = if prefix == 0 && ref_protein.first() == Some(&b'M') {
+            (Consequence::StartLost, "p.Met1?".to_string())
+        } else if ref_mid.contains(&b'*') {
+            let alt_aa = alt_mid.first().copied().unwrap_or(b'X');
+            (Consequence::StopLost, format!("p.Ter{}{}extTer?", position, amino_acid_code(alt_aa)))
+        } else if alt_protein.get(prefix) == Some(&b'*') {
+            // The protein ends early, so the shared stop codon must not be
+            // trimmed away as a common suffix
+            (Consequence::StopGained, format!("p.{}{}Ter", amino_acid_code(ref_protein[prefix]), position))
+        } else if ref_mid.is_empty() {
+            let before = *ref_protein.get(prefix.checked_sub(1)?)?;
+            let after = *ref_protein.get(prefix)?;
+            let term = if alt_mid.contains(&b'*') { Consequence::StopGained } else { Consequence::InframeInsertion };
+            let notation = format!(
+                "p.{}{}_{}{}ins{}",
+                amino_acid_code(before),
+                prefix,
+                amino_acid_code(after),
+                position,
+                amino_acid_codes(alt_mid)
+            );
+            (term, notation)
+        } else if alt_mid.is_empty() {
+            (Consequence::InframeDeletion, format!("p.{}del", ref_range()))
+        } else if ref_mid.len() == 1 && alt_mid.len() == 1 {
+            let notation = format!("p.{}{}{}", amino_acid_code(ref_mid[0]), position, amino_acid_code(alt_mid[0]));
+            (Consequence::Missense, notation)
+        } else {
+            let term = if alt_mid.contains(&b'*') {
+                Consequence::StopGained
+            } else if alt_mid.len() > ref_mid.len() {
+                Consequence::InframeInsertion
+            } else if alt_mid.len() < ref_mid.len() {
+                Consequence::InframeDeletion
+            } else {
+                Consequence::Missense
+            };
+            (term, format!("p.{}delins{}", ref_range(), amino_acid_codes(alt_mid)))
+        };
+        Some(result)
+    }
+}
+
+/// Consequences of a variant on one transcript, most severe first
+fn transcript_consequence(
+    transcript: &Transcript,
+    variant: &TrimmedVariant,
+    seq_cache: &SequenceCaches,
+    chrom: &str,
+) -> (Vec<Consequence>, Option<String>) {
+    let (mut terms, in_cds) = transcript.location_consequences(variant);
+    let mut hgvs_p = None;
+    if in_cds {
+        // The CDS is spliced from the reference segment by segment, so
+        // variants in the same transcript reuse the cached segments
+        let cds = seq_cache
+            .has_reference()
+            .then(|| {
+                let mut cache = seq_cache.local();
+                let mut cds = Vec::new();
+                for &(start, end) in &transcript.cds {
+                    cds.extend(cache.fetch_sequence(chrom, start - 1, end).ok()?.to_ascii_uppercase());
+                }
+                Some(if transcript.reverse { reverse_complement(&cds) } else { cds })
+            })
+            .flatten();
+        match cds.and_then(|cds| transcript.coding_consequence(variant, &cds)) {
+            Some((term, notation)) => {
+                terms.push(term);
+                hgvs_p = Some(notation);
             }
+            None => terms.push(Consequence::CodingSequence),
         }
+    }
+    terms.sort_unstable();
+    terms.dedup();
+    (terms, hgvs_p)
+}
+
+/// Call the consequence of a variant at 1-based `pos` on every overlapping
+/// transcript and report the most severe one. Variants outside transcripts
+/// are intergenic unless they lie in a gene without transcript models.
+fn call_consequence(
+    transcript_trees: &HashMap<String, Lapper<Transcript>>,
+    seq_cache: &SequenceCaches,
+    chrom: &str,
+    pos: u64,
+    ref_allele: &str,
+    alt_allele: &str,
+    in_gene: bool,
+) -> ConsequenceCall {
+    let is_sequence = |allele: &str| allele.bytes().all(|b| b"ACGTNacgtn".contains(&b));
+    if !is_sequence(ref_allele) || !is_sequence(alt_allele) || alt_allele.is_empty() {
+        return ConsequenceCall::default();
+    }
+    let variant = TrimmedVariant::new(pos, ref_allele, alt_allele);
+    if variant.ref_allele.is_empty() && variant.alt_allele.is_empty() {
+        return ConsequenceCall::default();
+    }
+    let (low, high) = variant.span();
+    
+    let picked = transcript_trees
+        .get(chrom)
+        .into_iter()
+        .flat_map(|tree| tree.find(low as usize, high as usize + 1))
+        .map(|iv| {
+            let (terms, hgvs_p) = transcript_consequence(&iv.val, &variant, seq_cache, chrom);
+            (&iv.val, terms, hgvs_p)
+        })
+        .filter(|(_, terms, _)| !terms.is_empty())
+        .min_by_key(|&(transcript, ref terms, _)| (terms[0], transcript.transcript_id.as_str()));
+    
+    match picked {
+        Some((transcript, terms, hgvs_p)) => ConsequenceCall {
+            consequence: Some(terms.iter().map(|term| term.as_str()).collect::<Vec<_>>().join("&")),
+            transcript_id: Some(transcript.transcript_id.clone()),
+            hgvs_c: transcript.hgvs_c(&variant),
+            hgvs_p,
+        },
+        None if in_gene => ConsequenceCall::default(),
+        None => ConsequenceCall {
+            consequence: Some(Consequence::Intergenic.as_str().to_string()),
+            ..Default::default()
+        },
+    }
+}
+
+/// Value of an annotation track at a variant
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum TrackValue {
+    Score(f64),
+    Features(String),
+}
+
+/// BED features of one track, as per-chromosome interval trees of feature
+/// names over 0-based half-open intervals
+struct BedTrack {
+    name: String,
+    trees: HashMap<String, Lapper<String>>,
+}
+
+impl BedTrack {
+    fn load(spec: &TrackSpec, chromosome_filter: Option<&str>) -> Result<Self> {
+        let file = File::open(&spec.path)
+            .with_context(|| format!("Failed to open BED track: {}", spec.path))?;
+        let reader: Box<dyn BufRead> = if spec.path.ends_with(".gz") {
+            Box::new(BufReader::new(bgzf::Reader::new(file)))
+        } else {
+            Box::new(BufReader::new(file))
+        };
         
-        // Parse position
-        let pos = match fields[1].parse::<u64>() {
-            Ok(p) => p,
-            Err(_) => {
-                warn!("Skipping line with invalid position: {}", fields[1]);
+        let mut intervals_by_chrom: HashMap<String, Vec<Interval<String>>> = HashMap::new();
+        for line in reader.lines() {
+            let line = line?;
+            if line.starts_with('#') || line.starts_with("track") || line.starts_with("browser") || line.trim().is_empty() {
                 continue;
             }
-        };
-        
-        // Extract allele
-        let allele = fields[3].to_string();
-        
-        // Parse frequency
-        let freq = match fields[4].parse::<f64>() {
-            Ok(f) => f,
-            Err(_) => {
-                warn!("Skipping line with invalid frequency: {}", fields[4]);
+            
+            let fields: Vec<_> = line.split('\t').collect();
+            let (Some(chrom), Some(Ok(start)), Some(Ok(stop))) = (
+                fields.first(),
+                fields.get(1).map(|f| f.parse::<usize>()),
+                fields.get(2).map(|f| f.parse::<usize>()),
+            ) else {
+                warn!("Skipping malformed line in BED track {}: {}", spec.name, line);
+                continue;
+            };
+            if chromosome_filter.is_some_and(|target| target != *chrom) {
                 continue;
             }
-        };
+            // Unnamed features are reported under the track's name
+            let feature = fields
+                .get(3)
+                .copied()
+                .filter(|name| !name.is_empty() && *name != ".")
+                .unwrap_or(spec.name.as_str());
+            intervals_by_chrom.entry(chrom.to_string()).or_default().push(Interval {
+                start,
+                stop,
+                val: feature.to_string(),
+            });
+        }
         
-        // Insert into map
-        map.insert((chrom, pos, allele), freq);
-        loaded_count += 1;
+        let trees: HashMap<_, _> = intervals_by_chrom
+            .into_iter()
+            .map(|(chrom, intervals)| (chrom, Lapper::new(intervals)))
+            .collect();
+        info!(
+            "Loaded BED track {} with {} features",
+            spec.name,
+            trees.values().map(|tree| tree.len()).sum::<usize>()
+        );
+        Ok(Self { name: spec.name.clone(), trees })
     }
     
-    pb.finish_with_message(format!(
-        "Loaded {} frequency entries from {} lines",
-        loaded_count, line_count
-    ));
-    
-    let elapsed = start_time.elapsed();
-    info!(
-        "Loaded {} frequency entries in {:.2?}",
-        map.len(),
-        elapsed
-    );
-    
-    Ok(map)
+    /// Distinct names of the features overlapping 1-based `pos`, joined by commas
+    fn features(&self, chrom: &str, pos: u64) -> Option<String> {
+        let mut names: Vec<&str> = self
+            .trees
+            .get(chrom)?
+            .find(pos as usize - 1, pos as usize)
+            .map(|iv| iv.val.as_str())
+            .collect();
+        names.sort_unstable();
+        names.dedup();
+        (!names.is_empty()).then(|| names.join(","))
+    }
 }
 
-/// Perform one-hot encoding of DNA sequence for neural network input
-fn one_hot_encode(_sequence: &[u8], _context_size: usize) -> Result<Tensor> {
-    // Stub implementation that just returns a dummy Tensor
-    Err(anyhow!("PyTorch functionality disabled"))
+/// Scores of one BigWig track, queried through the file's own R-tree index
+/// with a reader per worker thread instead of being loaded into memory
+struct BigWigTrack {
+    name: String,
+    readers: PerThread<BigWigRead<ReopenableFile>>,
 }
 
-/// Predict splice effect using a pre-trained PyTorch model
-fn predict_splice_effect(
-    _model: &CModule,
-    _sequence: &[u8],
-    _context_size: usize,
-) -> Result<f64> {
-    // Stub implementation that returns an error
-    Err(anyhow!("PyTorch functionality disabled"))
+impl BigWigTrack {
+    fn open(spec: &TrackSpec, threads: usize) -> Result<Self> {
+        let readers = PerThread::new(threads, || {
+            BigWigRead::open_file(&spec.path)
+                .map_err(|e| anyhow!(AnnotationError::TrackLoadError(format!("{}: {}", spec.path, e))))
+        })?;
+        info!("Opened BigWig track {} from {}", spec.name, spec.path);
+        Ok(Self { name: spec.name.clone(), readers })
+    }
+    
+    /// Score of the base at 1-based `pos`; none when the track has no data
+    /// there or the chromosome is not in the file
+    fn score(&self, chrom: &str, pos: u64) -> Option<f64> {
+        let start = u32::try_from(pos - 1).ok()?;
+        let mut reader = self.readers.local();
+        let mut values = reader.get_interval(chrom, start, start + 1).ok()?;
+        values.find_map(|value| value.ok()).map(|value| value.value as f64)
+    }
+}
+
+/// Regulatory and conservation tracks added as output columns
+struct AnnotationTracks {
+    bed: Vec<BedTrack>,
+    bigwig: Vec<BigWigTrack>,
+}
+
+impl AnnotationTracks {
+    fn load(
+        bed_specs: &[TrackSpec],
+        bigwig_specs: &[TrackSpec],
+        chromosome_filter: Option<&str>,
+        threads: usize,
+    ) -> Result<Self> {
+        // Track names become output columns, so they must be unique
+        let mut names = std::collections::HashSet::new();
+        for spec in bed_specs.iter().chain(bigwig_specs) {
+            if !names.insert(spec.name.as_str()) {
+                return Err(anyhow!(AnnotationError::TrackLoadError(format!(
+                    "track name {} is used more than once",
+                    spec.name
+                ))));
+            }
+        }
+        
+        Ok(Self {
+            bed: bed_specs
+                .iter()
+                .map(|spec| BedTrack::load(spec, chromosome_filter))
+                .collect::<Result<_>>()?,
+            bigwig: bigwig_specs
+                .iter()
+                .map(|spec| BigWigTrack::open(spec, threads))
+                .collect::<Result<_>>()?,
+        })
+    }
+    
+    fn annotate(&self, chrom: &str, pos: u64) -> BTreeMap<String, TrackValue> {
+        let features = self
+            .bed
+            .iter()
+            .filter_map(|track| Some((track.name.clone(), TrackValue::Features(track.features(chrom, pos)?))));
+        let scores = self
+            .bigwig
+            .iter()
+            .filter_map(|track| Some((track.name.clone(), TrackValue::Score(track.score(chrom, pos)?))));
+        features.chain(scores).collect()
+    }
+    
+    /// One output column per track, null where a variant has no value
+    fn columns(&self, annotations: &[AnnotatedVariant]) -> Vec<Series> {
+        let features = self.bed.iter().map(|track| {
+            let values: Vec<Option<String>> = annotations
+                .iter()
+                .map(|a| match a.tracks.get(&track.name) {
+                    Some(TrackValue::Features(features)) => Some(features.clone()),
+                    _ => None,
+                })
+                .collect();
+            Series::new(&track.name, values)
+        });
+        let scores = self.bigwig.iter().map(|track| {
+            let values: Vec<Option<f64>> = annotations
+                .iter()
+                .map(|a| match a.tracks.get(&track.name) {
+                    Some(TrackValue::Score(score)) => Some(*score),
+                    _ => None,
+                })
+                .collect();
+            Series::new(&track.name, values)
+        });
+        features.chain(scores).collect()
+    }
+}
+
+/// Structural variant described by a symbolic ALT (`<DEL>`, `<DUP>`,
+/// `<INS>`, ...) or an SVTYPE INFO field
+#[derive(Debug, Clone)]
+struct StructuralVariant {
+    sv_type: String,
+    /// 1-based inclusive end, from END or else POS + |SVLEN|
+    end: u64,
+    length: Option<u64>,
+}
+
+impl StructuralVariant {
+    fn parse(pos: u64, ref_allele: &str, alt_allele: &str, info: &str) -> Option<Self> {
+        let info_value = |key: &str| {
+            info.split(';')
+                .find_map(|field| field.strip_prefix(key)?.strip_prefix('='))
+        };
+        let symbolic = alt_allele.strip_prefix('<').and_then(|alt| alt.strip_suffix('>'));
+        // Subtypes such as <DEL:ME> count as their base type
+        let sv_type = info_value("SVTYPE")
+            .or_else(|| symbolic.and_then(|alt| alt.split(':').next()))?
+            .to_string();
+        let length = info_value("SVLEN")
+            .and_then(|value| value.split(',').next()?.parse::<i64>().ok())
+            .map(|length| length.unsigned_abs());
+        
+        // Insertions and breakends have no reference span beyond POS
+        let end = info_value("END")
+            .and_then(|value| value.parse().ok())
+            .or_else(|| match sv_type.as_str() {
+                "INS" | "BND" => None,
+                _ => length.map(|length| pos + length),
+            })
+            .unwrap_or(pos + ref_allele.len().max(1) as u64 - 1);
+        Some(Self { sv_type, end, length })
+    }
+}
+
+/// Genotype counts of the first ALT allele across the samples of a record
+#[derive(Debug, Clone, Copy, Default)]
+struct CohortCounts {
+    /// Called copies of the ALT allele (AC)
+    allele_count: u32,
+    /// Called alleles of any kind (AN)
+    allele_number: u32,
+    n_het: u32,
+    n_hom: u32,
+}
+
+impl CohortCounts {
+    /// Count the GT calls in the FORMAT and sample columns of a record.
+    /// Samples carrying the ALT on some but not all alleles, or with other
+    /// alleles missing, count as heterozygous.
+    fn from_genotypes(genotypes: &str) -> Self {
+        let mut columns = genotypes.split('\t');
+        let mut counts = Self::default();
+        let Some(gt_index) = columns.next().and_then(|format| format.split(':').position(|key| key == "GT")) else {
+            return counts;
+        };
+        
+        for sample in columns {
+            let Some(gt) = sample.split(':').nth(gt_index) else {
+                continue;
+            };
+            let alleles: Vec<&str> = gt.split(['/', '|']).collect();
+            let called = alleles.iter().filter(|allele| **allele != ".").count() as u32;
+            let alt_copies = alleles.iter().filter(|allele| **allele == "1").count() as u32;
+            counts.allele_number += called;
+            counts.allele_count += alt_copies;
+            if alt_copies == 0 {
+                continue;
+            }
+            if alt_copies as usize == alleles.len() {
+                counts.n_hom += 1;
+            } else {
+                counts.n_het += 1;
+            }
+        }
+        counts
+    }
+    
+    /// Cohort allele frequency AC/AN; none without called genotypes
+    fn allele_frequency(&self) -> Option<f64> {
+        (self.allele_number > 0).then(|| self.allele_count as f64 / self.allele_number as f64)
+    }
+}
+
+/// Fields of a VCF record the annotator uses, with the first ALT allele
+struct VcfSite {
+    chrom: String,
+    pos: u64,
+    ref_allele: String,
+    alt_allele: String,
+    sv: Option<StructuralVariant>,
+    cohort: CohortCounts,
+}
+
+impl VcfSite {
+    /// Parse a record through its VCF line, so streamed and indexed input
+    /// share one parser
+    fn from_record(record: &vcf::Record) -> Result<Self> {
+        Self::from_line(&record.to_string())
+    }
+    
+    /// Parse a VCF data line, counting genotypes of the first ALT allele
+    /// when it has samples
+    fn from_line(line: &str) -> Result<Self> {
+        let columns: Vec<_> = line.splitn(9, '\t').collect();
+        let [chrom, pos, _, ref_allele, alt, _, _, info, genotypes @ ..] = columns.as_slice() else {
+            return Err(anyhow!(AnnotationError::VcfLoadError(format!("truncated record: {}", line))));
+        };
+        let pos = pos
+            .parse()
+            .map_err(|_| anyhow!(AnnotationError::VcfLoadError(format!("invalid position: {}", pos))))?;
+        let alt_allele = alt.split(',').next().unwrap_or(".");
+        Ok(Self {
+            chrom: chrom.to_string(),
+            pos,
+            ref_allele: ref_allele.to_string(),
+            alt_allele: alt_allele.to_string(),
+            sv: StructuralVariant::parse(pos, ref_allele, alt_allele, info),
+            cohort: genotypes.first().copied().map(CohortCounts::from_genotypes).unwrap_or_default(),
+        })
+    }
+    
+    /// 1-based inclusive reference span the variant affects. The padding
+    /// base before a symbolic deletion, duplication or inversion is left
+    /// out.
+    fn span(&self) -> (u64, u64) {
+        match &self.sv {
+            Some(sv) if sv.end > self.pos && self.alt_allele.starts_with('<') => (self.pos + 1, sv.end),
+            Some(sv) => (self.pos, sv.end.max(self.pos)),
+            None => (self.pos, self.pos + self.ref_allele.len().max(1) as u64 - 1),
+        }
+    }
+}
+
+/// VCF records in file order, plain or bgzipped, optionally limited to
+/// one chromosome
+struct StreamedVcfSites {
+    reader: vcf::reader::Reader<Box<dyn BufRead + Send>>,
+    header: vcf::Header,
+    chromosome: Option<String>,
+}
+
+impl StreamedVcfSites {
+    fn open(path: &str, chromosome: Option<String>) -> Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open VCF file: {}", path))?;
+        let inner: Box<dyn BufRead + Send> = if path.ends_with(".gz") {
+            Box::new(BufReader::new(bgzf::Reader::new(file)))
+        } else {
+            Box::new(BufReader::new(file))
+        };
+        let mut reader = vcf::reader::Reader::new(inner);
+        
+        // Read VCF header
+        let header = reader
+            .read_header()
+            .context("Failed to read VCF header")?;
+        Ok(Self { reader, header, chromosome })
+    }
+}
+
+impl Iterator for StreamedVcfSites {
+    type Item = Result<VcfSite>;
+    
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut record = vcf::Record::default();
+        loop {
+            match self.reader.read_record(&self.header, &mut record) {
+                Ok(0) => return None,
+                Ok(_) => {}
+                Err(e) => return Some(Err(e.into())),
+            }
+            
+            // Apply chromosome filter if specified
+            let site = match VcfSite::from_record(&record) {
+                Ok(site) => site,
+                Err(e) => return Some(Err(e)),
+            };
+            if self.chromosome.as_ref().is_none_or(|target| site.chrom == *target) {
+                return Some(Ok(site));
+            }
+        }
+    }
+}
+
+/// VCF records of each indexed chromosome in turn, read through the tabix
+/// index of a bgzipped VCF. Only chromosomes passing `include` are read.
+struct IndexedVcfSites {
+    reader: bgzf::Reader<File>,
+    index: tabix::Index,
+    chromosomes: std::vec::IntoIter<(usize, String)>,
+    chrom: String,
+    chunks: std::vec::IntoIter<(bgzf::VirtualPosition, bgzf::VirtualPosition)>,
+    chunk_end: Option<bgzf::VirtualPosition>,
+    line: String,
+}
+
+impl IndexedVcfSites {
+    fn open(path: &str, include: impl Fn(&str) -> bool) -> Result<Self> {
+        let index_path = format!("{}.tbi", path);
+        let index = tabix::read(&index_path)
+            .map_err(|e| anyhow!(AnnotationError::VcfLoadError(format!("{}: {}", index_path, e))))?;
+        let reader = bgzf::Reader::new(
+            File::open(path).with_context(|| format!("Failed to open VCF file: {}", path))?,
+        );
+        
+        // Chromosomes in index order, which is their order in the file
+        let chromosomes: Vec<_> = index
+            .header()
+            .reference_sequence_names()
+            .iter()
+            .enumerate()
+            .filter(|(_, name)| include(name.as_str()))
+            .map(|(id, name)| (id, name.clone()))
+            .collect();
+        if chromosomes.is_empty() {
+            warn!("No indexed chromosome of {} is left to annotate", path);
+        }
+        
+        Ok(Self {
+            reader,
+            index,
+            chromosomes: chromosomes.into_iter(),
+            chrom: String::new(),
+            chunks: Vec::new().into_iter(),
+            chunk_end: None,
+            line: String::new(),
+        })
+    }
+    
+    /// Next line of the current chromosome, moving on to the next chunk or
+    /// chromosome as each runs out
+    fn next_line(&mut self) -> Result<Option<&str>> {
+        loop {
+            if let Some(end) = self.chunk_end {
+                if self.reader.virtual_position() < end {
+                    self.line.clear();
+                    match self.reader.read_line(&mut self.line) {
+                        Ok(0) => {}
+                        Ok(_) => return Ok(Some(self.line.trim_end())),
+                        Err(e) => {
+                            self.chunk_end = None;
+                            return Err(e.into());
+                        }
+                    }
+                }
+                self.chunk_end = None;
+            }
+            
+            if let Some((start, end)) = self.chunks.next() {
+                self.reader.seek(start)?;
+                self.chunk_end = Some(end);
+                continue;
+            }
+            
+            let Some((id, chrom)) = self.chromosomes.next() else {
+                return Ok(None);
+            };
+            let chunks = self.index.query(id, Position::MIN..)?;
+            self.chunks = chunks
+                .into_iter()
+                .map(|chunk| (chunk.start(), chunk.end()))
+                .collect::<Vec<_>>()
+                .into_iter();
+            self.chrom = chrom;
+        }
+    }
+}
+
+impl Iterator for IndexedVcfSites {
+    type Item = Result<VcfSite>;
+    
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let site = match self.next_line() {
+                Ok(Some(line)) => VcfSite::from_line(line),
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e)),
+            };
+            // Chunks are bgzf ranges and may take in a neighbouring chromosome
+            if !matches!(&site, Ok(site) if site.chrom != self.chrom) {
+                return Some(site);
+            }
+        }
+    }
+}
+
+/// Read up to `size` sites, logging and skipping unreadable records
+fn next_vcf_chunk(sites: &mut (dyn Iterator<Item = Result<VcfSite>> + Send), size: usize) -> Vec<VcfSite> {
+    let mut chunk = Vec::with_capacity(size);
+    for site in sites {
+        match site {
+            Ok(site) => {
+                chunk.push(site);
+                if chunk.len() == size {
+                    break;
+                }
+            }
+            Err(e) => error!("Error reading VCF record: {}", e),
+        }
+    }
+    chunk
+}
+
+/// Reference and alternate sequence windows of `--context-size` bases
+/// centred on a variant, as the splice model sees them
+struct SpliceWindows {
+    reference: Vec<u8>,
+    alternate: Vec<u8>,
+}
+
+/// Build the splice windows of a variant at 1-based `pos`. Returns `None`
+/// for ALT alleles without a sequence (symbolic, `*` or missing).
+fn splice_windows(
+    seq_cache: &SequenceCaches,
+    chrom: &str,
+    pos: u64,
+    ref_allele: &str,
+    alt_allele: &str,
+    context_size: usize,
+) -> Result<Option<SpliceWindows>> {
+    let is_sequence = |allele: &str| !allele.is_empty() && allele.bytes().all(|b| b"ACGTNacgtn".contains(&b));
+    if !is_sequence(ref_allele) || !is_sequence(alt_allele) {
+        return Ok(None);
+    }
+    
+    let half_size = (context_size / 2) as u64;
+    let variant_start = pos - 1;
+    let start = variant_start.saturating_sub(half_size);
+    // Fetch REF's length beyond the window so the alternate window is
+    // still full after a deletion
+    let end = variant_start + half_size + 1 + ref_allele.len() as u64;
+    let fetched = seq_cache.local().fetch_sequence(chrom, start, end)?;
+    
+    // Pad with N near the chromosome start so the variant stays centred
+    let mut reference = vec![b'N'; (half_size - (variant_start - start)) as usize];
+    reference.extend(fetched);
+    let offset = half_size as usize;
+    let ref_end = offset + ref_allele.len();
+    if reference.len() < ref_end || !reference[offset..ref_end].eq_ignore_ascii_case(ref_allele.as_bytes()) {
+        return Err(anyhow!(AnnotationError::SequenceFetchError(format!(
+            "REF {} does not match the reference at {}:{}",
+            ref_allele, chrom, pos
+        ))));
+    }
+    
+    let mut alternate = reference[..offset].to_vec();
+    alternate.extend_from_slice(alt_allele.as_bytes());
+    alternate.extend_from_slice(&reference[ref_end..]);
+    reference.truncate(context_size);
+    alternate.truncate(context_size);
+    Ok(Some(SpliceWindows { reference, alternate }))
+}
+
+/// Load a TorchScript model (splice or pathogenicity) on the CPU
+#[cfg(feature = "tch")]
+fn load_torchscript_model(path: &str) -> Result<CModule> {
+    CModule::load(path).map_err(|e| anyhow!(AnnotationError::ModelLoadError(format!("{}: {}", path, e))))
+}
+
+#[cfg(not(feature = "tch"))]
+fn load_torchscript_model(path: &str) -> Result<CModule> {
+    Err(anyhow!(AnnotationError::ModelLoadError(format!(
+        "{}: this build has no libtorch support; rebuild with --features tch",
+        path
+    ))))
+}
+
+/// One-hot encode a DNA window as a `[4, context_size]` tensor with A, C, G
+/// and T channels. Other bases are all zeros, as are the positions past the
+/// end of a short window.
+#[cfg(feature = "tch")]
+fn one_hot_encode(sequence: &[u8], context_size: usize) -> Result<Tensor> {
+    let mut encoded = vec![0f32; 4 * context_size];
+    for (i, base) in sequence.iter().take(context_size).enumerate() {
+        let channel = match base.to_ascii_uppercase() {
+            b'A' => 0,
+            b'C' => 1,
+            b'G' => 2,
+            b'T' => 3,
+            _ => continue,
+        };
+        encoded[channel * context_size + i] = 1.0;
+    }
+    Ok(Tensor::of_slice(&encoded).view(&[4, context_size as i64]))
+}
+
+/// Predict ΔPSI for a batch of variants: the model's splicing score (PSI)
+/// of each alternate window minus that of its reference window. All
+/// windows go through the model in one `[2 * batch, 4, context_size]`
+/// forward pass.
+#[cfg(feature = "tch")]
+fn predict_splice_effect(
+    model: &CModule,
+    windows: &[SpliceWindows],
+    context_size: usize,
+) -> Result<Vec<f64>> {
+    let encoded = windows
+        .iter()
+        .map(|w| one_hot_encode(&w.reference, context_size))
+        .chain(windows.iter().map(|w| one_hot_encode(&w.alternate, context_size)))
+        .collect::<Result<Vec<_>>>()?;
+    let input = Tensor::stack(&encoded, 0);
+    
+    let output = tch::no_grad(|| model.forward_ts(&[input]))
+        .map_err(|e| anyhow!(AnnotationError::SplicePredictionError(e.to_string())))?
+        .to_kind(Kind::Double)
+        .reshape(&[-1]);
+    let batch = windows.len() as i64;
+    if output.size() != [2 * batch] {
+        return Err(anyhow!(AnnotationError::SplicePredictionError(format!(
+            "model returned shape {:?} for {} windows; expected one PSI per window",
+            output.size(),
+            2 * batch
+        ))));
+    }
+    Ok((0..batch)
+        .map(|i| output.double_value(&[batch + i]) - output.double_value(&[i]))
+        .collect())
+}
+
+#[cfg(not(feature = "tch"))]
+fn predict_splice_effect(
+    model: &CModule,
+    _windows: &[SpliceWindows],
+    _context_size: usize,
+) -> Result<Vec<f64>> {
+    match *model {}
+}
+
+/// Fill in ΔPSI for every annotation, `batch_size` variants per forward
+/// pass. Windows are fetched and encoded in parallel; forward passes share
+/// the model one at a time. Returns the number of variants with a prediction.
+fn predict_splice_effects(
+    model: &Mutex<CModule>,
+    annotations: &mut [AnnotatedVariant],
+    seq_cache: &SequenceCaches,
+    context_size: usize,
+    batch_size: usize,
+) -> usize {
+    annotations
+        .par_chunks_mut(batch_size.max(1))
+        .map(|chunk| {
+            let mut indices = Vec::with_capacity(chunk.len());
+            let mut windows = Vec::with_capacity(chunk.len());
+            for (i, ann) in chunk.iter().enumerate() {
+                match splice_windows(seq_cache, &ann.chrom, ann.pos, &ann.ref_allele, &ann.alt_allele, context_size) {
+                    Ok(Some(w)) => {
+                        indices.push(i);
+                        windows.push(w);
+                    }
+                    Ok(None) => {}
+                    Err(e) => warn!("Error fetching sequence for {}:{}: {}", ann.chrom, ann.pos, e),
+                }
+            }
+            if windows.is_empty() {
+                return 0;
+            }
+            
+            let effects = predict_splice_effect(&model.lock().unwrap(), &windows, context_size);
+            match effects {
+                Ok(effects) => {
+                    for (i, effect) in indices.into_iter().zip(effects) {
+                        chunk[i].delta_psi = Some(effect);
+                    }
+                    windows.len()
+                }
+                Err(e) => {
+                    let first = &chunk[indices[0]];
+                    warn!(
+                        "Error predicting splice effects for {} variants from {}:{}: {}",
+                        windows.len(),
+                        first.chrom,
+                        first.pos,
+                        e
+                    );
+                    0
+                }
+            }
+        })
+        .sum()
+}
+
+/// Hand-tuned pathogenicity score: a sigmoid of the splice effect and
+/// rarity. Used when no `--pathogenicity-model` is given.
+fn heuristic_score(ann: &AnnotatedVariant) -> f64 {
+    // Calculate pathogenicity score using logistic function
+    // Factors: splice effect and rarity
+    let dpsi_factor = ann.delta_psi.unwrap_or(0.0) * 4.0; // Scale splice effect
+    let rare_factor = if ann.is_rare { 1.0 } else { 0.0 };
+    
+    // Combined score through sigmoid function
+    1.0 / (1.0 + (-dpsi_factor - rare_factor).exp())
+}
+
+/// Rate the confidence of a score by the evidence available
+fn score_confidence(ann: &AnnotatedVariant) -> f64 {
+    if ann.delta_psi.is_some() && ann.gnomad_af > 0.0 {
+        0.9 // High confidence when we have both splice prediction and frequency data
+    } else if ann.delta_psi.is_some() || ann.gnomad_af > 0.0 {
+        0.7 // Medium confidence with either splice prediction or frequency data
+    } else {
+        0.5 // Low confidence with neither
+    }
+}
+
+/// Features available to pathogenicity models, in the column order a
+/// TorchScript model receives them
+const PATHOGENICITY_FEATURES: [&str; 8] = [
+    "delta_psi",
+    "abs_delta_psi",
+    "has_delta_psi",
+    "gnomad_af",
+    "log10_af",
+    "is_rare",
+    "is_lof",
+    "is_missense",
+];
+
+/// Consequence terms counted as loss of function by the `is_lof` feature
+const LOF_CONSEQUENCES: [&str; 5] = [
+    "stop_gained",
+    "frameshift_variant",
+    "splice_donor_variant",
+    "splice_acceptor_variant",
+    "start_lost",
+];
+
+/// Variants per forward pass of a TorchScript pathogenicity model
+const PATHOGENICITY_BATCH_SIZE: usize = 4096;
+
+/// Value of a named model feature for a variant. Names other than those in
+/// `PATHOGENICITY_FEATURES` are `--bigwig-track` scores, 0 where missing.
+fn pathogenicity_feature(ann: &AnnotatedVariant, name: &str) -> f64 {
+    let has_term = |terms: &[&str]| {
+        ann.consequence
+            .as_deref()
+            .is_some_and(|consequence| consequence.split('&').any(|term| terms.contains(&term)))
+    };
+    let flag = |value: bool| if value { 1.0 } else { 0.0 };
+    match name {
+        "delta_psi" => ann.delta_psi.unwrap_or(0.0),
+        "abs_delta_psi" => ann.delta_psi.unwrap_or(0.0).abs(),
+        "has_delta_psi" => flag(ann.delta_psi.is_some()),
+        "gnomad_af" => ann.gnomad_af,
+        "log10_af" => ann.gnomad_af.max(1e-8).log10(),
+        "is_rare" => flag(ann.is_rare),
+        "is_lof" => flag(has_term(&LOF_CONSEQUENCES)),
+        "is_missense" => flag(has_term(&["missense_variant"])),
+        track => match ann.tracks.get(track) {
+            Some(TrackValue::Score(score)) => *score,
+            _ => 0.0,
+        },
+    }
+}
+
+/// Logistic regression over named features, read from JSON such as
+/// `{"intercept": -3.0, "coefficients": {"abs_delta_psi": 4.0, "is_rare": 1.5}}`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LogisticModel {
+    intercept: f64,
+    coefficients: BTreeMap<String, f64>,
+}
+
+impl LogisticModel {
+    fn score(&self, ann: &AnnotatedVariant) -> f64 {
+        let z = self.intercept
+            + self
+                .coefficients
+                .iter()
+                .map(|(name, weight)| weight * pathogenicity_feature(ann, name))
+                .sum::<f64>();
+        1.0 / (1.0 + (-z).exp())
+    }
+}
+
+/// Model combining the annotations into a pathogenicity score
+enum PathogenicityModel {
+    Heuristic,
+    Logistic(LogisticModel),
+    /// Takes a `[batch, PATHOGENICITY_FEATURES.len()]` float tensor and
+    /// returns one probability per variant
+    TorchScript(CModule),
+}
+
+impl PathogenicityModel {
+    /// Load logistic regression coefficients from a `.json` file or a
+    /// TorchScript model from any other file; the heuristic without a path
+    fn load(path: Option<&str>, tracks: &AnnotationTracks) -> Result<Self> {
+        let Some(path) = path else {
+            return Ok(PathogenicityModel::Heuristic);
+        };
+        if !path.ends_with(".json") {
+            return Ok(PathogenicityModel::TorchScript(load_torchscript_model(path)?));
+        }
+        
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open pathogenicity model: {}", path))?;
+        let model: LogisticModel = serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| anyhow!(AnnotationError::ModelLoadError(format!("{}: {}", path, e))))?;
+        for name in model.coefficients.keys() {
+            let known = PATHOGENICITY_FEATURES.contains(&name.as_str())
+                || tracks.bigwig.iter().any(|track| track.name == *name);
+            if !known {
+                return Err(anyhow!(AnnotationError::ModelLoadError(format!(
+                    "{}: unknown feature {} (expected one of {} or a --bigwig-track name)",
+                    path,
+                    name,
+                    PATHOGENICITY_FEATURES.join(", ")
+                ))));
+            }
+        }
+        Ok(PathogenicityModel::Logistic(model))
+    }
+    
+    fn describe(&self) -> &'static str {
+        match self {
+            PathogenicityModel::Heuristic => "heuristic",
+            PathogenicityModel::Logistic(_) => "logistic regression",
+            PathogenicityModel::TorchScript(_) => "TorchScript",
+        }
+    }
+    
+    /// Fill in the pathogenicity score and confidence of every annotation
+    fn score(&self, annotations: &mut [AnnotatedVariant]) -> Result<()> {
+        match self {
+            PathogenicityModel::Heuristic => annotations
+                .par_iter_mut()
+                .for_each(|ann| ann.pathogenicity_score = heuristic_score(ann)),
+            PathogenicityModel::Logistic(model) => annotations
+                .par_iter_mut()
+                .for_each(|ann| ann.pathogenicity_score = model.score(ann)),
+            PathogenicityModel::TorchScript(model) => {
+                for chunk in annotations.chunks_mut(PATHOGENICITY_BATCH_SIZE) {
+                    let features: Vec<f32> = chunk
+                        .iter()
+                        .flat_map(|ann| PATHOGENICITY_FEATURES.iter().map(move |name| pathogenicity_feature(ann, name) as f32))
+                        .collect();
+                    let scores = predict_pathogenicity(model, &features, chunk.len())?;
+                    for (ann, score) in chunk.iter_mut().zip(scores) {
+                        ann.pathogenicity_score = score;
+                    }
+                }
+            }
+        }
+        annotations
+            .par_iter_mut()
+            .for_each(|ann| ann.confidence = score_confidence(ann));
+        Ok(())
+    }
+}
+
+/// Run a batch of feature rows through a TorchScript pathogenicity model
+#[cfg(feature = "tch")]
+fn predict_pathogenicity(model: &CModule, features: &[f32], rows: usize) -> Result<Vec<f64>> {
+    let input = Tensor::of_slice(features).view(&[rows as i64, PATHOGENICITY_FEATURES.len() as i64]);
+    let output = tch::no_grad(|| model.forward_ts(&[input]))
+        .map_err(|e| anyhow!(AnnotationError::PathogenicityError(e.to_string())))?
+        .to_kind(Kind::Double)
+        .reshape(&[-1]);
+    if output.size() != [rows as i64] {
+        return Err(anyhow!(AnnotationError::PathogenicityError(format!(
+            "model returned shape {:?} for {} variants; expected one score per variant",
+            output.size(),
+            rows
+        ))));
+    }
+    Ok((0..rows as i64).map(|i| output.double_value(&[i])).collect())
+}
+
+#[cfg(not(feature = "tch"))]
+fn predict_pathogenicity(model: &CModule, _features: &[f32], _rows: usize) -> Result<Vec<f64>> {
+    match *model {}
+}
+
+/// INFO fields added to records of an annotated VCF, with their header lines
+const VCF_INFO_HEADERS: [(&str, &str); 4] = [
+    ("GENE", "##INFO=<ID=GENE,Number=1,Type=String,Description=\"Overlapping gene name\">"),
+    ("AF_GNOMAD", "##INFO=<ID=AF_GNOMAD,Number=1,Type=Float,Description=\"gnomAD allele frequency of the first ALT allele\">"),
+    ("DPSI", "##INFO=<ID=DPSI,Number=1,Type=Float,Description=\"Predicted change in percent spliced in (ALT - REF) for the first ALT allele\">"),
+    ("PATH_SCORE", "##INFO=<ID=PATH_SCORE,Number=1,Type=Float,Description=\"Pathogenicity score of the first ALT allele\">"),
+];
+
+fn is_vcf_output(output_path: &str) -> bool {
+    output_path.ends_with(".vcf") || output_path.ends_with(".vcf.gz")
+}
+
+/// Append the annotation of a record to its INFO column, replacing any
+/// fields of the same name it already carries
+fn annotate_info(info: &str, ann: &AnnotatedVariant) -> String {
+    let mut fields: Vec<String> = info
+        .split(';')
+        .filter(|field| *field != "." && !field.is_empty())
+        .filter(|field| {
+            let key = field.split('=').next().unwrap_or_default();
+            !VCF_INFO_HEADERS.iter().any(|(id, _)| *id == key)
+        })
+        .map(|field| field.to_string())
+        .collect();
+    
+    if let Some(gene) = &ann.gene_name {
+        // INFO values cannot hold whitespace, semicolons, equals signs or commas
+        let gene: String = gene
+            .chars()
+            .map(|c| if c.is_whitespace() || matches!(c, ';' | '=' | ',') { '_' } else { c })
+            .collect();
+        fields.push(format!("GENE={}", gene));
+    }
+    fields.push(format!("AF_GNOMAD={}", ann.gnomad_af));
+    if let Some(delta_psi) = ann.delta_psi {
+        fields.push(format!("DPSI={:.4}", delta_psi));
+    }
+    fields.push(format!("PATH_SCORE={:.4}", ann.pathogenicity_score));
+    fields.join(";")
+}
+
+/// Copy the input VCF to `writer`, declaring the new INFO fields in the
+/// header and adding them to every annotated record. Records without an
+/// annotation (other chromosomes with `--chromosome`, unreadable records)
+/// are copied unchanged; only those on `chromosome` are copied when given.
+/// Records in `dropped` (common in the cohort) are left out.
+fn write_vcf_records<W: Write>(
+    input_vcf: &str,
+    annotations: &[AnnotatedVariant],
+    dropped: &[VariantKey],
+    chromosome: Option<&str>,
+    writer: &mut W,
+) -> Result<usize> {
+    let by_key: HashMap<(&str, u64, &str, &str), &AnnotatedVariant> = annotations
+        .iter()
+        .map(|a| ((a.chrom.as_str(), a.pos, a.ref_allele.as_str(), a.alt_allele.as_str()), a))
+        .collect();
+    let dropped: HashSet<(&str, u64, &str, &str)> = dropped
+        .iter()
+        .map(|(chrom, pos, ref_allele, alt_allele)| (chrom.as_str(), *pos, ref_allele.as_str(), alt_allele.as_str()))
+        .collect();
+    let file = File::open(input_vcf)
+        .with_context(|| format!("Failed to open VCF file: {}", input_vcf))?;
+    let reader: Box<dyn BufRead> = if input_vcf.ends_with(".gz") {
+        Box::new(BufReader::new(bgzf::Reader::new(file)))
+    } else {
+        Box::new(BufReader::new(file))
+    };
+    
+    let mut annotated = 0;
+    for line in reader.lines() {
+        let line = line?;
+        if line.starts_with("##") {
+            let id = line
+                .strip_prefix("##INFO=<ID=")
+                .and_then(|rest| rest.split(',').next());
+            // Our header lines replace existing declarations of the same fields
+            if !id.is_some_and(|id| VCF_INFO_HEADERS.iter().any(|(key, _)| *key == id)) {
+                writeln!(writer, "{}", line)?;
+            }
+            continue;
+        }
+        if line.starts_with('#') {
+            for (_, header) in VCF_INFO_HEADERS {
+                writeln!(writer, "{}", header)?;
+            }
+            writeln!(writer, "{}", line)?;
+            continue;
+        }
+        
+        let mut columns: Vec<&str> = line.split('\t').collect();
+        if chromosome.is_some_and(|chrom| columns[0] != chrom) {
+            continue;
+        }
+        let key = match columns.as_slice() {
+            [chrom, pos, _, ref_allele, alt, _, _, _, ..] => pos.parse::<u64>().ok().map(|pos| {
+                (*chrom, pos, *ref_allele, alt.split(',').next().unwrap_or_default())
+            }),
+            _ => None,
+        };
+        if key.is_some_and(|key| dropped.contains(&key)) {
+            continue;
+        }
+        let annotation = key.and_then(|key| by_key.get(&key).copied());
+        match annotation {
+            Some(ann) => {
+                let info = annotate_info(columns[7], ann);
+                columns[7] = &info;
+                writeln!(writer, "{}", columns.join("\t"))?;
+                annotated += 1;
+            }
+            None => writeln!(writer, "{}", line)?,
+        }
+    }
+    Ok(annotated)
+}
+
+/// Write the input VCF with GENE, AF_GNOMAD, DPSI and PATH_SCORE INFO
+/// fields, bgzip-compressed when the output ends in `.vcf.gz`
+fn write_annotated_vcf(
+    input_vcf: &str,
+    annotations: &[AnnotatedVariant],
+    dropped: &[VariantKey],
+    chromosome: Option<&str>,
+    output_path: &str,
+) -> Result<()> {
+    let file = File::create(output_path)
+        .with_context(|| format!("Failed to create output VCF: {}", output_path))?;
+    let annotated = if output_path.ends_with(".gz") {
+        let mut writer = bgzf::Writer::new(file);
+        let annotated = write_vcf_records(input_vcf, annotations, dropped, chromosome, &mut writer)?;
+        writer.try_finish()?;
+        annotated
+    } else {
+        let mut writer = BufWriter::new(file);
+        let annotated = write_vcf_records(input_vcf, annotations, dropped, chromosome, &mut writer)?;
+        writer.flush()?;
+        annotated
+    };
+    
+    info!("Saved {} annotated VCF records to {}", annotated, output_path);
+    Ok(())
+}
+
+/// Output file of one chromosome in `--shard-by-chrom` mode: the
+/// chromosome goes before the extension, e.g. `annotated.chr1.parquet`
+fn shard_path(output_path: &str, chrom: &str) -> String {
+    let (stem, extension) = ["vcf.gz", "vcf", "parquet", "csv", "json"]
+        .iter()
+        .find_map(|extension| Some((output_path.strip_suffix(&format!(".{}", extension))?, *extension)))
+        .unwrap_or((output_path, "parquet"));
+    format!("{}.{}.{}", stem, chrom, extension)
+}
+
+/// Write annotations as a table or an annotated VCF, by the extension of
+/// `output_path`. Output goes to a hidden temporary file that is renamed
+/// into place, so an interrupted run never leaves a partial file behind.
+/// `dropped` variants are left out of an annotated VCF.
+fn write_output(
+    annotations: &[AnnotatedVariant],
+    dropped: &[VariantKey],
+    tracks: &AnnotationTracks,
+    input_vcf: &str,
+    chromosome: Option<&str>,
+    output_path: &str,
+) -> Result<()> {
+    let extension = Path::new(output_path).extension().and_then(|e| e.to_str()).unwrap_or("");
+    let known = ["csv", "parquet", "json"].contains(&extension.to_lowercase().as_str());
+    let output_path = if known || is_vcf_output(output_path) {
+        output_path.to_string()
+    } else {
+        warn!("Unrecognized file extension: {}, defaulting to Parquet format", extension);
+        format!("{}.parquet", output_path)
+    };
+    let path = Path::new(&output_path);
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| anyhow!("Invalid output path: {}", output_path))?;
+    let partial = path.with_file_name(format!(".partial.{}", file_name));
+    let partial = partial.to_str().unwrap();
+    
+    if is_vcf_output(&output_path) {
+        write_annotated_vcf(input_vcf, annotations, dropped, chromosome, partial)?;
+    } else {
+        save_annotations(annotations.to_vec(), tracks, partial)?;
+    }
+    std::fs::rename(partial, &output_path)
+        .with_context(|| format!("Failed to move {} to {}", partial, output_path))?;
+    Ok(())
+}
+
+/// Models and caches used to finish annotations after the per-variant pass
+struct Finisher<'a> {
+    splice_net: Option<&'a Mutex<CModule>>,
+    seq_cache: &'a SequenceCaches,
+    pathogenicity_model: &'a PathogenicityModel,
+    context_size: usize,
+    splice_batch_size: usize,
+}
+
+impl Finisher<'_> {
+    /// Predict splice effects in batched forward passes, then combine the
+    /// annotations into pathogenicity scores
+    fn finish(
+        &self,
+        annotations: &mut [AnnotatedVariant],
+        stats: &mut AnnotationStats,
+        profile: &mut RunProfile,
+    ) -> Result<()> {
+        if let Some(model) = self.splice_net {
+            let stage_start = Instant::now();
+            let predicted = predict_splice_effects(
+                model,
+                annotations,
+                self.seq_cache,
+                self.context_size,
+                self.splice_batch_size,
+            );
+            info!("Predicted splice effects for {} of {} variants", predicted, annotations.len());
+            stats.splice_predicted += predicted as u64;
+            profile.record("splice", stage_start, predicted as u64, 0, 0);
+        }
+        
+        let stage_start = Instant::now();
+        self.pathogenicity_model.score(annotations)?;
+        profile.record("score", stage_start, annotations.len() as u64, 0, 0);
+        Ok(())
+    }
 }
 
 /// Save annotations to a file in the appropriate format
-fn save_annotations(annotations: Vec<AnnotatedVariant>, output_path: &str) -> Result<()> {
+fn save_annotations(annotations: Vec<AnnotatedVariant>, tracks: &AnnotationTracks, output_path: &str) -> Result<()> {
     let path = Path::new(output_path);
     let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
     
@@ -470,6 +2244,22 @@ fn save_annotations(annotations: Vec<AnnotatedVariant>, output_path: &str) -> Re
         ),
         Series::new("gnomAD_AF", annotations.iter().map(|a| a.gnomad_af).collect::<Vec<_>>()),
         Series::new("is_rare", annotations.iter().map(|a| a.is_rare).collect::<Vec<_>>()),
+        Series::new("cohort_af", annotations.iter().map(|a| a.cohort_af).collect::<Vec<_>>()),
+        Series::new("n_het", annotations.iter().map(|a| a.n_het).collect::<Vec<_>>()),
+        Series::new("n_hom", annotations.iter().map(|a| a.n_hom).collect::<Vec<_>>()),
+        Series::new("sv_type", annotations.iter().map(|a| a.sv_type.clone()).collect::<Vec<_>>()),
+        Series::new("sv_end", annotations.iter().map(|a| a.sv_end).collect::<Vec<_>>()),
+        Series::new("sv_length", annotations.iter().map(|a| a.sv_length).collect::<Vec<_>>()),
+        Series::new("gene_overlaps", annotations.iter().map(|a| a.gene_overlaps.clone()).collect::<Vec<_>>()),
+        Series::new(
+            "consequence",
+            annotations.iter()
+                .map(|a| a.consequence.clone().unwrap_or_else(|| "NA".to_string()))
+                .collect::<Vec<_>>(),
+        ),
+        Series::new("transcript_id", annotations.iter().map(|a| a.transcript_id.clone()).collect::<Vec<_>>()),
+        Series::new("hgvs_c", annotations.iter().map(|a| a.hgvs_c.clone()).collect::<Vec<_>>()),
+        Series::new("hgvs_p", annotations.iter().map(|a| a.hgvs_p.clone()).collect::<Vec<_>>()),
         Series::new(
             "delta_psi",
             annotations.iter()
@@ -479,6 +2269,9 @@ fn save_annotations(annotations: Vec<AnnotatedVariant>, output_path: &str) -> Re
         Series::new("pathogenicity", annotations.iter().map(|a| a.pathogenicity_score).collect::<Vec<_>>()),
         Series::new("confidence", annotations.iter().map(|a| a.confidence).collect::<Vec<_>>()),
     ])?;
+    for column in tracks.columns(&annotations) {
+        df.with_column(column)?;
+    }
     
     match extension.to_lowercase().as_str() {
         "csv" => {
@@ -543,6 +2336,7 @@ fn main() -> Result<()> {
         .context("Failed to initialize thread pool")?;
     
     info!("Using {} threads for parallel processing", num_threads);
+    let mut profile = RunProfile::new(num_threads);
     
     // Validate context size
     if args.context_size % 2 == 0 {
@@ -550,34 +2344,67 @@ fn main() -> Result<()> {
     }
     
     // Build gene interval tree from GFF
+    let stage_start = Instant::now();
     let gene_trees = build_gene_tree(&args.gff)?;
+    let gene_count: usize = gene_trees.values().map(|tree| tree.len()).sum();
+    profile.record("load_genes", stage_start, gene_count as u64, file_len(&args.gff), 0);
+    
+    // Build transcript models for consequence calling
+    let stage_start = Instant::now();
+    let transcript_trees = build_transcript_tree(&args.gff)?;
+    let transcript_count: usize = transcript_trees.values().map(|tree| tree.len()).sum();
+    profile.record("load_transcripts", stage_start, transcript_count as u64, file_len(&args.gff), 0);
+    
+    // Load BED tracks and open BigWig tracks
+    let stage_start = Instant::now();
+    let tracks = AnnotationTracks::load(&args.bed_tracks, &args.bigwig_tracks, args.chromosome.as_deref(), num_threads)?;
+    let track_bytes = args.bed_tracks.iter().map(|spec| file_len(&spec.path)).sum();
+    profile.record("load_tracks", stage_start, tracks.bed.len() as u64, track_bytes, 0);
     
     // Load allele frequencies from gnomAD
-    let freqs = load_freqs(&args.gnomad, args.chromosome.as_deref())?;
+    let stage_start = Instant::now();
+    let freqs = if args.tabix {
+        let table = TabixFreqTable::open(&args.gnomad, args.freq_cache_regions)
+            .map_err(|e| anyhow!(AnnotationError::FreqLoadError(args.gnomad.clone(), format!("{:#}", e))))?;
+        FreqTable::Tabix(table)
+    } else {
+        load_freqs(&args.gnomad, args.chromosome.as_deref(), args.max_memory)?
+    };
+    profile.record("load_freqs", stage_start, freqs.len() as u64, file_len(&args.gnomad), 0);
     
-    // Initialize sequence cache if we have a reference
+    // Open a sequence cache and reference reader per worker thread
     let seq_cache = if args.splice_model.is_some() && args.reference.is_none() {
         return Err(anyhow!(AnnotationError::NoReferenceError));
     } else {
-        Arc::new(Mutex::new(SequenceCache::new(args.reference.as_deref(), 1000)?))
+        SequenceCaches::new(args.reference.as_deref(), 1000, num_threads)?
     };
     
-    // Load splice prediction model if provided - temporarily disabled
-    let splice_net = None;
-    if args.splice_model.is_some() {
-        info!("Note: Splice model was specified, but PyTorch functionality is disabled in this build");
-    }
+    // Load splice prediction model if provided
+    let splice_net = match &args.splice_model {
+        Some(path) => {
+            info!("Loading splice model from {}", path);
+            Some(Mutex::new(load_torchscript_model(path)?))
+        }
+        None => None,
+    };
     
-    // Open VCF file
-    info!("Processing variants from {}", args.vcf);
-    let file = File::open(&args.vcf)
-        .with_context(|| format!("Failed to open VCF file: {}", args.vcf))?;
-    let mut vcf_rdr = vcf::reader::Reader::new(BufReader::new(file));
+    // Load the pathogenicity model, falling back to the heuristic
+    let pathogenicity_model = PathogenicityModel::load(args.pathogenicity_model.as_deref(), &tracks)?;
+    info!("Scoring pathogenicity with the {} model", pathogenicity_model.describe());
     
-    // Read VCF header
-    let header = vcf_rdr
-        .read_header()
-        .context("Failed to read VCF header")?;
+    // Open VCF sites, either streamed in file order or one chromosome at a
+    // time through the tabix index
+    info!("Processing variants from {}", args.vcf);
+    // Resumed sharded runs skip chromosomes whose shard is already written
+    let shard_done = |chrom: &str| args.shard_by_chrom && Path::new(&shard_path(&args.output, chrom)).exists();
+    let mut sites: Box<dyn Iterator<Item = Result<VcfSite>> + Send> = if args.indexed_vcf {
+        let include = |chrom: &str| {
+            args.chromosome.as_deref().is_none_or(|target| chrom == target) && !shard_done(chrom)
+        };
+        Box::new(IndexedVcfSites::open(&args.vcf, include)?)
+    } else {
+        Box::new(StreamedVcfSites::open(&args.vcf, args.chromosome.clone())?)
+    };
     
     // Create progress bar
     let progress_bar = ProgressBar::new_spinner();
@@ -589,142 +2416,209 @@ fn main() -> Result<()> {
     progress_bar.enable_steady_tick(std::time::Duration::from_millis(100));
     
     // Track statistics
-    let stats = Arc::new(Mutex::new(HashMap::new()));
+    let mut stats = AnnotationStats::default();
     let processed_counter = Arc::new(Mutex::new(0usize));
     
-    // Process VCF records in parallel
-    info!("Starting variant annotation");
-    let annotations: Vec<_> = vcf_rdr
-        .records(&header)
-        .par_bridge()
-        .filter_map(|record_result| {
-            // Safely unwrap record
-            let record = match record_result {
-                Ok(rec) => rec,
-                Err(e) => {
-                    error!("Error reading VCF record: {}", e);
-                    return None;
-                }
-            };
-            
-            // Extract basic variant information
-            let chrom = record.chromosome().to_string();
+    let annotate_site = |site: VcfSite| -> AnnotatedVariant {
+        let (span_start, span_end) = site.span();
+        let VcfSite { chrom, pos, ref_allele, alt_allele, sv, cohort } = site;
+        
+        // Lookup gene information over the variant's span; a structural
+        // variant is assigned the gene it covers most of
+        let overlaps = gene_overlaps(&gene_trees, &chrom, span_start, span_end);
+        let gene_info = if sv.is_some() {
+            overlaps.iter().max_by(|a, b| a.1.total_cmp(&b.1)).map(|(gene, _)| (*gene).clone())
+        } else {
+            overlaps.first().map(|(gene, _)| (*gene).clone())
+        };
+        let gene_overlaps = sv.as_ref().filter(|_| !overlaps.is_empty()).map(|_| {
+            overlaps
+                .iter()
+                .map(|(gene, fraction)| format!("{}:{:.3}", gene.gene_name, fraction))
+                .collect::<Vec<_>>()
+                .join(",")
+        });
+        
+        // Get allele frequency
+        let af = freqs
+            .get(&chrom, pos, &alt_allele)
+            .unwrap_or(0.0);
+        
+        let is_rare = af < args.rare_cutoff;
+        
+        // Call the consequence on the most severely affected transcript
+        let call = call_consequence(
+            &transcript_trees,
+            &seq_cache,
+            &chrom,
+            pos,
+            &ref_allele,
+            &alt_allele,
+            gene_info.is_some(),
+        );
+        
+        // Look up regulatory and conservation tracks
+        let track_values = tracks.annotate(&chrom, pos);
+        
+        // Update progress
+        {
+            let mut count = processed_counter.lock().unwrap();
+            *count += 1;
             
-            // Apply chromosome filter if specified
-            if let Some(ref target_chrom) = args.chromosome {
-                if chrom != *target_chrom {
-                    return None;
-                }
+            if *count % 1000 == 0 {
+                progress_bar.set_message(format!("Processed {} variants", *count));
             }
-            
-            let pos: usize = record.position().into();
-            
-            // Get alleles
-            let ref_allele = record.reference_bases().to_string();
-            let alt_allele = record.alternate_bases().first()
-                .map(|a| a.to_string())
-                .unwrap_or_else(|| ".".to_string());
-            
-            // Lookup gene information
-            let gene_info = gene_trees
-                .get(&chrom)
-                .and_then(|tree| tree.find(pos, pos).next())
-                .map(|iv| iv.val.clone());
-            
-            // Get allele frequency
-            let af = freqs
-                .get(&(chrom.clone(), pos as u64, alt_allele.clone()))
-                .copied()
-                .unwrap_or(0.0);
-            
-            let is_rare = af < args.rare_cutoff;
-            
-            // Predict splice effect if model is available
-            let dpsi = if splice_net.is_some() {
-                // Thread-safe access to sequence cache
-                let sequence = match seq_cache.lock().unwrap().fetch_sequence(&chrom, pos as u64, args.context_size) {
-                    Ok(seq) => seq,
-                    Err(e) => {
-                        warn!("Error fetching sequence for {}:{}: {}", chrom, pos, e);
-                        return None;
-                    }
-                };
-                
-                // Predict splice effect
-                match predict_splice_effect(splice_net.as_ref().unwrap(), &sequence, args.context_size) {
-                    Ok(effect) => Some(effect),
-                    Err(e) => {
-                        warn!("Error predicting splice effect for {}:{}: {}", chrom, pos, e);
-                        None
-                    }
+        }
+        
+        // Create annotation record; splice effects and pathogenicity
+        // scores are computed in batches afterwards
+        AnnotatedVariant {
+            chrom,
+            pos,
+            ref_allele,
+            alt_allele,
+            gene_name: gene_info.as_ref().map(|g| g.gene_name.clone()),
+            gene_id: gene_info.as_ref().map(|g| g.gene_id.clone()),
+            gene_strand: gene_info.as_ref().map(|g| g.strand.clone()),
+            gene_biotype: gene_info.as_ref().map(|g| g.biotype.clone()),
+            gnomad_af: af,
+            is_rare,
+            cohort_af: cohort.allele_frequency(),
+            n_het: cohort.n_het,
+            n_hom: cohort.n_hom,
+            sv_type: sv.as_ref().map(|sv| sv.sv_type.clone()),
+            sv_end: sv.as_ref().map(|sv| sv.end),
+            sv_length: sv.as_ref().and_then(|sv| sv.length),
+            gene_overlaps,
+            consequence: call.consequence,
+            transcript_id: call.transcript_id,
+            hgvs_c: call.hgvs_c,
+            hgvs_p: call.hgvs_p,
+            delta_psi: None,
+            pathogenicity_score: 0.0,
+            confidence: 0.0,
+            tracks: track_values,
+        }
+    };
+    
+    let finisher = Finisher {
+        splice_net: splice_net.as_ref(),
+        seq_cache: &seq_cache,
+        pathogenicity_model: &pathogenicity_model,
+        context_size: args.context_size,
+        splice_batch_size: args.splice_batch_size,
+    };
+    
+    // Sharded runs finish and write each chromosome once the input moves
+    // past it; this needs the input grouped by chromosome
+    let mut shard_chrom: Option<String> = None;
+    let mut written_shards = HashSet::new();
+    let mut skipped_shards = HashSet::new();
+    let write_shard = |chrom: &str,
+                       annotations: &mut Vec<AnnotatedVariant>,
+                       dropped: &mut Vec<VariantKey>,
+                       stats: &mut AnnotationStats,
+                       profile: &mut RunProfile|
+     -> Result<()> {
+        finisher.finish(annotations, stats, profile)?;
+        let stage_start = Instant::now();
+        let path = shard_path(&args.output, chrom);
+        write_output(annotations, dropped, &tracks, &args.vcf, Some(chrom), &path)?;
+        profile.record("save", stage_start, annotations.len() as u64, 0, file_len(&path));
+        info!("Wrote {} annotations of {} to {}", annotations.len(), chrom, path);
+        annotations.clear();
+        dropped.clear();
+        Ok(())
+    };
+    let mut keep_site = |site: &VcfSite| {
+        let done = shard_done(&site.chrom);
+        if done && skipped_shards.insert(site.chrom.clone()) {
+            info!("Skipping {}: its shard is already written", site.chrom);
+        }
+        !done
+    };
+    
+    // Annotate chunks in parallel while the next one is read; each chunk
+    // keeps its input order, so the output follows the (sorted) input
+    info!("Starting variant annotation in chunks of {} records", args.chunk_size);
+    let stage_start = Instant::now();
+    let mut annotations = Vec::new();
+    // Cohort-common variants, kept only to leave them out of VCF output
+    let mut dropped = Vec::new();
+    let vcf_output = is_vcf_output(&args.output);
+    let mut chunk = next_vcf_chunk(&mut sites, args.chunk_size);
+    chunk.retain(&mut keep_site);
+    while !chunk.is_empty() {
+        let (mut next_chunk, annotated) = rayon::join(
+            || next_vcf_chunk(&mut sites, args.chunk_size),
+            || chunk.into_par_iter().map(&annotate_site).collect::<Vec<_>>(),
+        );
+        for ann in annotated {
+            // Switch shards before filtering, so a chromosome's dropped
+            // variants stay with its own shard
+            if args.shard_by_chrom && shard_chrom.as_deref() != Some(ann.chrom.as_str()) {
+                if let Some(chrom) = shard_chrom.take() {
+                    write_shard(&chrom, &mut annotations, &mut dropped, &mut stats, &mut profile)?;
+                    written_shards.insert(chrom);
                 }
-            } else {
-                None
-            };
-            
-            // Calculate pathogenicity score using logistic function
-            // Factors: splice effect and rarity
-            let dpsi_factor = dpsi.unwrap_or(0.0) * 4.0; // Scale splice effect
-            let rare_factor = if is_rare { 1.0 } else { 0.0 };
-            
-            // Combined score through sigmoid function
-            let path_score = 1.0 / (1.0 + (-dpsi_factor - rare_factor).exp());
-            
-            // Calculate confidence based on available data
-            let confidence = if dpsi.is_some() && af > 0.0 {
-                0.9 // High confidence when we have both splice prediction and frequency data
-            } else if dpsi.is_some() || af > 0.0 {
-                0.7 // Medium confidence with either splice prediction or frequency data
-            } else {
-                0.5 // Low confidence with neither
-            };
-            
-            // Update progress and statistics
-            {
-                let mut count = processed_counter.lock().unwrap();
-                *count += 1;
-                
-                if *count % 1000 == 0 {
-                    progress_bar.set_message(format!("Processed {} variants", *count));
+                if written_shards.contains(&ann.chrom) {
+                    return Err(anyhow!(AnnotationError::VcfLoadError(format!(
+                        "{} appears again after other chromosomes; --shard-by-chrom needs the VCF grouped by chromosome",
+                        ann.chrom
+                    ))));
                 }
-                
-                // Update statistics
-                let mut stats_guard = stats.lock().unwrap();
-                let counter = stats_guard.entry(chrom.clone()).or_insert(0);
-                *counter += 1;
+                shard_chrom = Some(ann.chrom.clone());
             }
-            
-            // Create annotation record
-            Some(AnnotatedVariant {
-                chrom,
-                pos: pos as u64,
-                ref_allele,
-                alt_allele,
-                gene_name: gene_info.as_ref().map(|g| g.gene_name.clone()),
-                gene_id: gene_info.as_ref().map(|g| g.gene_id.clone()),
-                gene_strand: gene_info.as_ref().map(|g| g.strand.clone()),
-                gene_biotype: gene_info.as_ref().map(|g| g.biotype.clone()),
-                gnomad_af: af,
-                is_rare,
-                delta_psi: dpsi,
-                pathogenicity_score: path_score,
-                confidence,
-            })
-        })
-        .collect();
+            let common = args
+                .max_cohort_af
+                .zip(ann.cohort_af)
+                .is_some_and(|(max_af, cohort_af)| cohort_af > max_af);
+            if common {
+                stats.cohort_filtered += 1;
+                if vcf_output {
+                    dropped.push((ann.chrom, ann.pos, ann.ref_allele, ann.alt_allele));
+                }
+                continue;
+            }
+            stats.record(&ann);
+            annotations.push(ann);
+        }
+        next_chunk.retain(&mut keep_site);
+        chunk = next_chunk;
+    }
+    if let Some(max_af) = args.max_cohort_af {
+        info!("Dropped {} variants with a cohort allele frequency above {}", stats.cohort_filtered, max_af);
+    }
     
     // Finish progress
-    progress_bar.finish_with_message(format!("Annotated {} variants", annotations.len()));
+    progress_bar.finish_with_message(format!("Annotated {} variants", stats.variants));
+    profile.record("annotate", stage_start, stats.variants, file_len(&args.vcf), 0);
+    
+    if let Some(chrom) = shard_chrom.take() {
+        write_shard(&chrom, &mut annotations, &mut dropped, &mut stats, &mut profile)?;
+        written_shards.insert(chrom);
+    }
     
     // Print chromosome statistics
     info!("Annotation statistics by chromosome:");
-    let stats_guard = stats.lock().unwrap();
-    for (chrom, count) in stats_guard.iter() {
+    for (chrom, count) in &stats.variants_per_chromosome {
         info!("  {}: {} variants", chrom, count);
     }
     
-    // Save annotations
-    save_annotations(annotations.clone(), &args.output)?;
+    if args.shard_by_chrom {
+        info!(
+            "Wrote {} chromosome shards, skipped {} already written",
+            written_shards.len(),
+            skipped_shards.len()
+        );
+    } else {
+        finisher.finish(&mut annotations, &mut stats, &mut profile)?;
+        
+        // Save annotations
+        let stage_start = Instant::now();
+        write_output(&annotations, &dropped, &tracks, &args.vcf, None, &args.output)?;
+        profile.record("save", stage_start, annotations.len() as u64, 0, file_len(&args.output));
+    }
     
     // Print results preview
     let preview_count = std::cmp::min(annotations.len(), 12);
@@ -760,5 +2654,15 @@ fn main() -> Result<()> {
         elapsed
     );
     
+    if let Some(ref profile_path) = args.profile_json {
+        profile.write(profile_path, start_time)?;
+        info!("Runtime profile written to {}", profile_path);
+    }
+    
+    if let Some(ref stats_path) = args.stats_out {
+        stats.write(stats_path, start_time)?;
+        info!("Annotation statistics written to {}", stats_path);
+    }
+    
     Ok(())
 }
\ No newline at end of file
@@ -1,24 +1,45 @@
 use clap::{Parser, Subcommand, ValueEnum};
-use tracing::{info, warn, error, debug, Level};
+use tracing::{info, warn, error, debug, info_span, Instrument, Level, Span};
 use tracing_subscriber::filter::EnvFilter;
 use anyhow::{Context, Result, anyhow, bail};
 use noodles_bam as bam;
 use noodles_vcf as vcf;
 use noodles_fasta as fasta;
 use noodles_gff as gff;
+use noodles_sam as sam;
+use noodles_cram as cram;
 use tokio::{signal, fs, time};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader as AsyncBufReader};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinSet;
+use tokio_util::io::SyncIoBridge;
+use tokio_util::sync::CancellationToken;
 use rayon::prelude::*;
 use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap, HashMap, HashSet, VecDeque},
+    future::Future,
+    io::{BufRead, BufReader, BufWriter, Read, Write},
     path::{Path, PathBuf},
+    pin::Pin,
     process::ExitCode,
     sync::{Arc, Mutex},
-    time::Instant,
+    time::{Instant, SystemTime, UNIX_EPOCH},
 };
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use tempfile::TempDir;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use human_format::Formatter;
+use annotation_core::{build_gene_tree, gene_overlaps, load_freqs, open_maybe_gzip, GeneInfo};
+use polars::prelude::{DataFrame, NamedFrom, ParquetWriter, Series};
+use rust_lapper::Lapper;
+use sha2::{Digest, Sha256};
+use futures::StreamExt;
+use object_store::{
+    aws::AmazonS3Builder, gcp::GoogleCloudStorageBuilder, http::HttpBuilder, path::Path as ObjectPath, ObjectStore,
+};
+use url::Url;
 
 /// Custom error types for the variant pipeline
 #[derive(Error, Debug)]
@@ -47,8 +68,12 @@ pub enum PipelineError {
 struct Settings {
     // General settings
     threads: usize,
+    /// Memory budget in GB shared by concurrently running steps; unlimited when unset
+    memory_gb: Option<f64>,
     tmp_dir: PathBuf,
     log_level: Option<String>,
+    /// `text` (the default) or `json`, one object per line
+    log_format: Option<LogFormat>,
     
     // Alignment settings
     #[serde(default)]
@@ -61,6 +86,14 @@ struct Settings {
     // Annotation settings
     #[serde(default)]
     annotate: AnnotateSettings,
+    
+    // Tumor-only workflow settings
+    #[serde(default)]
+    tumor_only: TumorOnlySettings,
+    
+    // Quality control settings
+    #[serde(default)]
+    qc: QcSettings,
 }
 
 /// Default implementation for Settings
@@ -68,11 +101,15 @@ impl Default for Settings {
     fn default() -> Self {
         Self {
             threads: num_cpus::get(),
+            memory_gb: None,
             tmp_dir: std::env::temp_dir(),
             log_level: Some("info".to_string()),
+            log_format: None,
             align: AlignSettings::default(),
             call: CallSettings::default(),
             annotate: AnnotateSettings::default(),
+            tumor_only: TumorOnlySettings::default(),
+            qc: QcSettings::default(),
         }
     }
 }
@@ -84,6 +121,16 @@ struct AlignSettings {
     min_mapq: Option<u8>,
     max_secondary: Option<usize>,
     mark_duplicates: Option<bool>,
+    /// Alignment records sorted in memory before spilling a chunk to disk
+    sort_buffer_records: Option<usize>,
+    /// `bam` (the default) or `cram`, compressed against the reference
+    output_format: Option<AlignmentFormat>,
+    /// Threads reserved while the step runs (default: the global `threads`)
+    #[serde(skip_serializing)]
+    threads: Option<usize>,
+    /// Memory in GB reserved from the global `memory_gb` budget while the step runs
+    #[serde(skip_serializing)]
+    memory_gb: Option<f64>,
 }
 
 /// Variant calling settings
@@ -93,6 +140,20 @@ struct CallSettings {
     min_depth: Option<usize>,
     min_gq: Option<f64>,
     regions: Option<Vec<String>>,
+    /// BED file of panel targets; calls outside them are dropped
+    targets: Option<PathBuf>,
+    /// Container runtime for DeepVariant (docker, podman, singularity or apptainer)
+    container_runtime: Option<String>,
+    /// DeepVariant image, e.g. google/deepvariant:1.6.1
+    deepvariant_image: Option<String>,
+    /// DeepVariant model type (WGS, WES, PACBIO, ONT_R104, HYBRID_PACBIO_ILLUMINA)
+    deepvariant_model: Option<String>,
+    /// Threads reserved while the step runs (default: the global `threads`)
+    #[serde(skip_serializing)]
+    threads: Option<usize>,
+    /// Memory in GB reserved from the global `memory_gb` budget while the step runs
+    #[serde(skip_serializing)]
+    memory_gb: Option<f64>,
 }
 
 /// Annotation settings
@@ -101,6 +162,62 @@ struct AnnotateSettings {
     databases: Option<Vec<PathBuf>>,
     effects: Option<bool>,
     max_distance: Option<usize>,
+    /// BED file of panel targets; variants outside them are not annotated
+    targets: Option<PathBuf>,
+    /// Threads reserved while the step runs (default: the global `threads`)
+    #[serde(skip_serializing)]
+    threads: Option<usize>,
+    /// Memory in GB reserved from the global `memory_gb` budget while the step runs
+    #[serde(skip_serializing)]
+    memory_gb: Option<f64>,
+}
+
+/// Tumor-only somatic filtering settings
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+struct TumorOnlySettings {
+    max_population_af: Option<f64>,
+    scorer: Option<String>,
+    min_score: Option<f64>,
+}
+
+/// Quality control settings
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+struct QcSettings {
+    /// Adapter sequences searched for in reads; Illumina universal and Nextera when unset
+    adapters: Option<Vec<String>>,
+    /// Reads examined per FASTQ file; all when unset
+    max_reads: Option<u64>,
+    /// Depth a target base needs to count as covered (default 20)
+    min_target_depth: Option<u32>,
+    /// Fraction of target bases that must be covered; a sample below it is flagged
+    min_target_completeness: Option<f64>,
+}
+
+/// Formats for sorted alignments
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum AlignmentFormat {
+    #[default]
+    Bam,
+    Cram,
+}
+
+impl AlignmentFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            AlignmentFormat::Bam => "bam",
+            AlignmentFormat::Cram => "cram",
+        }
+    }
+}
+
+/// Log output formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum LogFormat {
+    #[default]
+    Text,
+    Json,
 }
 
 /// Format options for output files
@@ -133,6 +250,19 @@ struct Cli {
     #[arg(short, long, action = clap::ArgAction::Count)]
     verbose: u8,
     
+    /// Log format, overriding `log_format` from config
+    #[arg(long, value_enum)]
+    log_format: Option<LogFormat>,
+    
+    /// Write a per-step runtime breakdown (wall time, bytes, records/sec) as JSON
+    #[arg(long)]
+    profile_json: Option<PathBuf>,
+    
+    /// Resolve the configuration, validate the inputs and print the execution
+    /// plan (steps, commands, expected outputs) without running anything
+    #[arg(long)]
+    dry_run: bool,
+    
     /// Subcommands
     #[command(subcommand)]
     command: Command,
@@ -155,18 +285,22 @@ enum Command {
         #[arg(short, long)]
         out_bam: PathBuf,
         
-        /// Aligner to use (bwa, minimap2, bowtie2)
+        /// Aligner to use (bwa, bwa-mem2, minimap2, bowtie2)
         #[arg(long)]
         aligner: Option<String>,
         
         /// Mark duplicate reads
         #[arg(long)]
         mark_duplicates: bool,
+        
+        /// Output format; CRAM is compressed against the reference and needs a `.cram` path
+        #[arg(long, value_enum)]
+        out_format: Option<AlignmentFormat>,
     },
     
     /// Call variants from aligned reads
     Call {
-        /// Path to input BAM file
+        /// Path to input BAM or CRAM file
         #[arg(short, long)]
         bam: PathBuf,
         
@@ -186,6 +320,10 @@ enum Command {
         #[arg(short, long)]
         regions: Option<Vec<String>>,
         
+        /// Panel target regions (BED); calls outside them are dropped
+        #[arg(long)]
+        targets: Option<PathBuf>,
+        
         /// Output format
         #[arg(long, value_enum, default_value_t = OutputFormat::Vcf)]
         format: OutputFormat,
@@ -213,16 +351,47 @@ enum Command {
         #[arg(long)]
         effects: bool,
         
+        /// Panel target regions (BED); variants outside them are skipped
+        #[arg(long)]
+        targets: Option<PathBuf>,
+        
         /// Output format
         #[arg(long, value_enum, default_value_t = OutputFormat::Tsv)]
         format: OutputFormat,
     },
     
-    /// Run the full pipeline (align, call, annotate)
-    Pipeline {
+    /// Compute FASTQ and BAM quality metrics and write JSON and HTML QC reports
+    Qc {
         /// Path to input reads (FASTQ)
+        #[arg(short, long, required_unless_present = "bam")]
+        reads: Option<PathBuf>,
+        
+        /// Mate reads of a paired-end run (FASTQ)
+        #[arg(long, requires = "reads")]
+        mate: Option<PathBuf>,
+        
+        /// Path to aligned reads (BAM or CRAM)
         #[arg(short, long)]
-        reads: PathBuf,
+        bam: Option<PathBuf>,
+        
+        /// Reference genome (FASTA) the CRAM file was compressed against
+        #[arg(long)]
+        reference: Option<PathBuf>,
+        
+        /// Path to output directory
+        #[arg(short, long)]
+        output_dir: PathBuf,
+        
+        /// Sample name (used for output files)
+        #[arg(short, long)]
+        sample: String,
+    },
+    
+    /// Run the full pipeline (align, QC, call, annotate)
+    Pipeline {
+        /// Path to input reads (FASTQ)
+        #[arg(short, long, required_unless_present = "samples")]
+        reads: Option<PathBuf>,
         
         /// Path to reference genome (FASTA)
         #[arg(short, long)]
@@ -237,13 +406,161 @@ enum Command {
         output_dir: PathBuf,
         
         /// Sample name (used for output files)
-        #[arg(short, long)]
-        sample: String,
+        #[arg(short, long, required_unless_present = "samples")]
+        sample: Option<String>,
+        
+        /// Cohort manifest (TSV: sample, reads, optional mate reads) to run every
+        /// sample and merge their calls into a cohort VCF
+        #[arg(long, conflicts_with_all = ["reads", "sample"])]
+        samples: Option<PathBuf>,
+        
+        /// Number of manifest samples processed at once
+        #[arg(long, default_value_t = 2)]
+        max_parallel_samples: usize,
+        
+        /// Panel target regions (BED): restricts calling and annotation and
+        /// reports the fraction of target bases covered
+        #[arg(long)]
+        targets: Option<PathBuf>,
+        
+        /// Depth a target base needs to count as covered
+        #[arg(long, requires = "targets")]
+        min_target_depth: Option<u32>,
         
         /// Keep intermediate files
         #[arg(long)]
         keep_intermediate: bool,
     },
+    
+    /// Tumor-only workflow: call, remove germline, annotate, score and rank somatic candidates
+    TumorOnly {
+        /// Path to tumor BAM file
+        #[arg(short, long)]
+        bam: PathBuf,
+        
+        /// Path to reference genome (FASTA)
+        #[arg(short, long)]
+        reference: PathBuf,
+        
+        /// Path to gene annotation (GFF)
+        #[arg(short, long)]
+        gff: PathBuf,
+        
+        /// Population allele frequencies (chrom<TAB>pos<TAB>alt<TAB>af, as used by the annotator)
+        #[arg(long)]
+        population_af: PathBuf,
+        
+        /// Panel-of-normals VCF; sites present in it are treated as artifacts or germline
+        #[arg(long)]
+        panel_of_normals: Option<PathBuf>,
+        
+        /// Pangenome graph passed to the variant scorer
+        #[arg(long)]
+        graph: PathBuf,
+        
+        /// ONNX model passed to the variant scorer
+        #[arg(long)]
+        model: PathBuf,
+        
+        /// Maximum population allele frequency for a somatic candidate
+        #[arg(long)]
+        max_population_af: Option<f64>,
+        
+        /// Drop candidates scoring below this value
+        #[arg(long)]
+        min_score: Option<f64>,
+        
+        /// Path to output directory
+        #[arg(short, long)]
+        output_dir: PathBuf,
+        
+        /// Sample name (used for output files)
+        #[arg(short, long)]
+        sample: String,
+    },
+}
+
+impl Command {
+    /// Directory the command writes to, where its run manifest goes
+    fn output_dir(&self) -> PathBuf {
+        let parent_of = |path: &Path| match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        match self {
+            Command::Align { out_bam, .. } => parent_of(out_bam),
+            Command::Call { out_vcf, .. } => parent_of(out_vcf),
+            Command::Annotate { output, .. } => parent_of(output),
+            Command::Qc { output_dir, .. }
+            | Command::Pipeline { output_dir, .. }
+            | Command::TumorOnly { output_dir, .. } => output_dir.clone(),
+        }
+    }
+    
+    /// External programs the command runs with these settings, whose
+    /// versions go into `run.json`
+    fn external_tools(&self, settings: &Settings) -> Vec<String> {
+        let aligner = |name: Option<&String>| {
+            name.or(settings.align.aligner.as_ref()).map_or("bwa", String::as_str).to_string()
+        };
+        let caller = match settings.call.caller.as_deref().unwrap_or("bcftools") {
+            "deepvariant" => settings.call.container_runtime.as_deref().unwrap_or("docker"),
+            caller => caller,
+        }
+        .to_string();
+        let mut tools = match self {
+            Command::Align { aligner: name, .. } => vec![aligner(name.as_ref()), "samtools".to_string()],
+            Command::Call { format, .. } => {
+                let mut tools = vec![caller, "samtools".to_string()];
+                if matches!(format, OutputFormat::Bcf) {
+                    tools.push("bcftools".to_string());
+                }
+                tools
+            }
+            Command::Annotate { .. } | Command::Qc { .. } => Vec::new(),
+            Command::Pipeline { samples, .. } => {
+                let mut tools = vec![aligner(None), "samtools".to_string(), caller];
+                if samples.is_some() {
+                    tools.push("bcftools".to_string());
+                }
+                tools
+            }
+            Command::TumorOnly { .. } => vec![
+                caller,
+                "samtools".to_string(),
+                settings.tumor_only.scorer.clone().unwrap_or_else(|| "variant-scorer".to_string()),
+            ],
+        };
+        tools.sort();
+        tools.dedup();
+        tools
+    }
+}
+
+/// (chrom, pos, alt) key for population frequencies
+type PopulationKey = (String, u64, String);
+
+/// (chrom, pos, ref, alt) key for a single allele
+type AlleleKey = (String, u64, String, String);
+
+/// Counts from the germline filtering step
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct GermlineFilterStats {
+    input_variants: usize,
+    population_filtered: usize,
+    pon_filtered: usize,
+    somatic_candidates: usize,
+}
+
+/// A ranked somatic candidate
+#[derive(Debug, Clone, Serialize)]
+struct SomaticCandidate {
+    chrom: String,
+    pos: u64,
+    ref_allele: String,
+    alt_allele: String,
+    population_af: f64,
+    score: f64,
 }
 
 /// Pipeline context shared across steps
@@ -253,6 +570,226 @@ struct PipelineContext {
     temp_dir: Arc<TempDir>,
     progress: Arc<MultiProgress>,
     start_time: Instant,
+    profile: Arc<Mutex<RunProfile>>,
+    resources: ResourcePool,
+    /// Cancelled on ctrl-c; steps stop their child processes and return `Interrupted`
+    cancel: CancellationToken,
+    manifest: Arc<Mutex<RunManifest>>,
+    remote: RemoteInputs,
+    /// Print the plan of each workflow instead of running its steps
+    dry_run: bool,
+}
+
+impl PipelineContext {
+    /// Local path of an input, staging a remote URI first; staged inputs
+    /// are listed in `run.json`. A dry run stages nothing and keeps the URI.
+    async fn localize(&self, path: &Path) -> Result<PathBuf> {
+        if self.dry_run {
+            return Ok(path.to_path_buf());
+        }
+        let local = self.remote.localize(path, &self.cancel).await?;
+        if let Some(uri) = remote_uri(path) {
+            self.manifest.lock().unwrap().staged_inputs.insert(uri.to_string(), local.clone());
+        }
+        Ok(local)
+    }
+    
+    async fn localize_option(&self, path: Option<PathBuf>) -> Result<Option<PathBuf>> {
+        match path {
+            Some(path) => Ok(Some(self.localize(&path).await?)),
+            None => Ok(None),
+        }
+    }
+    
+    /// Stage the remote targets and annotation databases named in the settings
+    async fn localize_settings(&mut self) -> Result<()> {
+        self.settings.call.targets = self.localize_option(self.settings.call.targets.clone()).await?;
+        self.settings.annotate.targets = self.localize_option(self.settings.annotate.targets.clone()).await?;
+        if let Some(databases) = self.settings.annotate.databases.clone() {
+            let mut local = Vec::with_capacity(databases.len());
+            for db in &databases {
+                // A missing local database is skipped with a warning when annotating
+                local.push(self.localize(db).await?);
+            }
+            self.settings.annotate.databases = Some(local);
+        }
+        Ok(())
+    }
+    
+    /// How a step that returned an error ended
+    fn failure_status(&self) -> StepStatus {
+        if self.cancel.is_cancelled() {
+            StepStatus::Interrupted
+        } else {
+            StepStatus::Failed
+        }
+    }
+    
+    /// Record a step in `run.json`, with when it started and finished running
+    /// (none for a skipped step) and the checksums in `hashes` for the files
+    /// that have one. An interrupted step may have left its outputs
+    /// half-written, so they are removed.
+    fn record_step(
+        &self,
+        step: &str,
+        status: StepStatus,
+        span: Option<(Instant, Instant)>,
+        inputs: &[PathBuf],
+        outputs: &[PathBuf],
+        hashes: &BTreeMap<PathBuf, String>,
+    ) {
+        let mut removed = Vec::new();
+        if status == StepStatus::Interrupted {
+            for output in outputs.iter().filter(|output| output.exists()) {
+                match std::fs::remove_file(output) {
+                    Ok(()) => removed.push(output.clone()),
+                    Err(e) => warn!("Failed to remove partial output {:?}: {}", output, e),
+                }
+            }
+            if !removed.is_empty() {
+                info!("Removed partial outputs of step '{}': {:?}", step, removed);
+            }
+        }
+        let files = |paths: &[PathBuf]| -> Vec<FileRecord> {
+            paths
+                .iter()
+                .map(|path| FileRecord {
+                    path: path.clone(),
+                    bytes: path.exists().then(|| file_len(path)),
+                    sha256: hashes.get(path).cloned(),
+                })
+                .collect()
+        };
+        let record = StepRecord {
+            step: step.to_string(),
+            status,
+            started_unix: span.map(|(started, _)| unix_time().saturating_sub(started.elapsed().as_secs())),
+            wall_seconds: span.map_or(0.0, |(started, finished)| (finished - started).as_secs_f64()),
+            inputs: files(inputs),
+            outputs: files(outputs),
+        };
+        let mut manifest = self.manifest.lock().unwrap();
+        manifest.steps.push(record);
+        manifest.removed_outputs.extend(removed);
+    }
+    
+    /// Record a standalone command's step, checksumming its inputs and
+    /// outputs when it succeeded
+    async fn finish_step<T>(
+        &self,
+        step: &str,
+        inputs: &[PathBuf],
+        outputs: &[PathBuf],
+        started: Instant,
+        result: &Result<T>,
+    ) {
+        let finished = Instant::now();
+        let (status, hashes) = match result {
+            Ok(_) => {
+                let files: Vec<PathBuf> = inputs.iter().chain(outputs).cloned().collect();
+                let hashes = hash_files(&files).await.unwrap_or_else(|e| {
+                    warn!("Failed to checksum files of step '{}': {:#}", step, e);
+                    BTreeMap::new()
+                });
+                (StepStatus::Completed, hashes)
+            }
+            Err(_) => (self.failure_status(), BTreeMap::new()),
+        };
+        self.record_step(step, status, Some((started, finished)), inputs, outputs, &hashes);
+    }
+    
+    /// Threads a step would be granted by `reserve`, for the dry-run plan
+    fn planned_threads(&self, threads: Option<usize>) -> usize {
+        threads.unwrap_or(self.settings.threads).clamp(1, self.resources.total_threads as usize)
+    }
+    
+    /// Reserve a step's threads (default: the configured thread count) and
+    /// memory, giving up if the run is cancelled while waiting
+    async fn reserve(&self, step: &str, threads: Option<usize>, memory_gb: Option<f64>) -> Result<ResourceLease> {
+        tokio::select! {
+            lease = self.resources.acquire(step, threads.unwrap_or(self.settings.threads), memory_gb) => lease,
+            _ = self.cancel.cancelled() => Err(anyhow!(PipelineError::Interrupted)),
+        }
+    }
+}
+
+/// Thread and memory budget shared by every step of a run. A step holds its
+/// share until it finishes, so steps running concurrently (independent
+/// workflow steps, cohort samples) cannot oversubscribe the machine.
+#[derive(Debug, Clone)]
+struct ResourcePool {
+    threads: Arc<Semaphore>,
+    total_threads: u32,
+    /// Memory semaphore counting MiB, and its size, when `memory_gb` is set
+    memory: Option<(Arc<Semaphore>, u32)>,
+}
+
+impl ResourcePool {
+    fn new(threads: usize, memory_gb: Option<f64>) -> Self {
+        let total_threads = threads.max(1) as u32;
+        let memory = memory_gb.map(|gb| {
+            let total = gb_to_mib(gb);
+            (Arc::new(Semaphore::new(total as usize)), total)
+        });
+        Self {
+            threads: Arc::new(Semaphore::new(total_threads as usize)),
+            total_threads,
+            memory,
+        }
+    }
+    
+    /// Wait until `threads` and `memory_gb` are free and hold them until the
+    /// lease is dropped. Requests beyond the whole budget are capped to it, and
+    /// memory is only metered when both the budget and the request are set.
+    async fn acquire(&self, step: &str, threads: usize, memory_gb: Option<f64>) -> Result<ResourceLease> {
+        let threads = (threads.max(1) as u32).min(self.total_threads);
+        let memory = match (&self.memory, memory_gb) {
+            (Some((semaphore, total)), Some(gb)) => Some((semaphore.clone(), gb_to_mib(gb).min(*total))),
+            _ => None,
+        };
+        
+        let busy = self.threads.available_permits() < threads as usize
+            || memory.as_ref().is_some_and(|(semaphore, mib)| semaphore.available_permits() < *mib as usize);
+        if busy {
+            info!("Step '{}' waiting for {} threads{}", step, threads, match &memory {
+                Some((_, mib)) => format!(" and {} MiB", mib),
+                None => String::new(),
+            });
+        }
+        
+        // Every step takes threads before memory, so no two steps can each
+        // hold what the other is waiting for
+        let thread_permits = self.threads.clone().acquire_many_owned(threads).await?;
+        let memory_permits = match memory {
+            Some((semaphore, mib)) => Some(semaphore.acquire_many_owned(mib).await?),
+            None => None,
+        };
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads as usize)
+            .build()
+            .with_context(|| format!("Failed to create thread pool for step '{}'", step))?;
+        debug!("Step '{}' reserved {} threads", step, threads);
+        
+        Ok(ResourceLease {
+            threads: threads as usize,
+            pool: Arc::new(pool),
+            _thread_permits: thread_permits,
+            _memory_permits: memory_permits,
+        })
+    }
+}
+
+fn gb_to_mib(gb: f64) -> u32 {
+    (gb * 1024.0).ceil().clamp(1.0, u32::MAX as f64) as u32
+}
+
+/// Resources held by a running step, returned to the pool on drop
+struct ResourceLease {
+    threads: usize,
+    /// Rayon pool sized to the step's threads for its in-process work
+    pool: Arc<rayon::ThreadPool>,
+    _thread_permits: OwnedSemaphorePermit,
+    _memory_permits: Option<OwnedSemaphorePermit>,
 }
 
 /// Statistics for reporting
@@ -261,25 +798,238 @@ struct PipelineStats {
     aligned_reads: usize,
     variants_called: usize,
     variants_annotated: usize,
+    /// Coverage completeness of the panel targets, when given
+    target_coverage: Option<TargetCoverage>,
     elapsed_seconds: f64,
 }
 
+/// Wall time and I/O volume for one pipeline step
+#[derive(Debug, Serialize)]
+struct StepProfile {
+    step: String,
+    wall_seconds: f64,
+    bytes_read: u64,
+    bytes_written: u64,
+    records: u64,
+    records_per_sec: f64,
+}
+
+/// Runtime breakdown written by `--profile-json`
+#[derive(Debug, Default, Serialize)]
+struct RunProfile {
+    tool: String,
+    threads: usize,
+    total_wall_seconds: f64,
+    steps: Vec<StepProfile>,
+}
+
+impl RunProfile {
+    fn new(threads: usize) -> Self {
+        Self {
+            tool: env!("CARGO_PKG_NAME").to_string(),
+            threads,
+            ..Default::default()
+        }
+    }
+    
+    /// Record a finished step; input and output sizes are taken from the files on disk
+    fn record(&mut self, step: &str, started: Instant, records: u64, inputs: &[&Path], outputs: &[&Path]) {
+        let wall_seconds = started.elapsed().as_secs_f64();
+        self.steps.push(StepProfile {
+            step: step.to_string(),
+            wall_seconds,
+            bytes_read: inputs.iter().map(|p| file_len(p)).sum(),
+            bytes_written: outputs.iter().map(|p| file_len(p)).sum(),
+            records,
+            records_per_sec: if wall_seconds > 0.0 { records as f64 / wall_seconds } else { 0.0 },
+        });
+    }
+    
+    fn write(&mut self, path: &Path, start_time: Instant) -> Result<()> {
+        self.total_wall_seconds = start_time.elapsed().as_secs_f64();
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, json)
+            .with_context(|| format!("Failed to write profile: {:?}", path))?;
+        Ok(())
+    }
+}
+
+fn file_len(path: &Path) -> u64 {
+    std::fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
+
+/// Overall state of a run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum RunStatus {
+    Running,
+    Completed,
+    Failed,
+    Interrupted,
+}
+
+/// How a step ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum StepStatus {
+    Completed,
+    Skipped,
+    Failed,
+    Interrupted,
+}
+
+/// A file a step read or wrote. `sha256` is missing for a step that did
+/// not complete, and `bytes` for a file that no longer exists.
+#[derive(Debug, Clone, Serialize)]
+struct FileRecord {
+    path: PathBuf,
+    bytes: Option<u64>,
+    sha256: Option<String>,
+}
+
+/// One step of `run.json`. A skipped step reports the checksums from the
+/// step cache and no duration.
+#[derive(Debug, Serialize)]
+struct StepRecord {
+    step: String,
+    status: StepStatus,
+    started_unix: Option<u64>,
+    wall_seconds: f64,
+    inputs: Vec<FileRecord>,
+    outputs: Vec<FileRecord>,
+}
+
+/// `run.json`: provenance of a run for reproducibility audits. It records
+/// the command line, the program and external tool versions, the settings
+/// in effect, each step with its duration and the checksums of the files it
+/// read and wrote, and how the run ended. It is written when the run starts
+/// and again when it finishes or is interrupted.
+#[derive(Debug, Serialize)]
+struct RunManifest {
+    program: String,
+    version: String,
+    arguments: Vec<String>,
+    /// `--version` output of each external program the command runs
+    tools: BTreeMap<String, String>,
+    settings: serde_json::Value,
+    status: RunStatus,
+    started_unix: u64,
+    finished_unix: Option<u64>,
+    error: Option<String>,
+    /// Files read by the run that no step produced
+    inputs: Vec<FileRecord>,
+    /// Files written by steps that completed or were up to date
+    outputs: Vec<FileRecord>,
+    steps: Vec<StepRecord>,
+    /// Partially written outputs deleted after an interruption
+    removed_outputs: Vec<PathBuf>,
+    /// Remote inputs and the local copies the steps read
+    staged_inputs: BTreeMap<String, PathBuf>,
+}
+
+impl RunManifest {
+    fn new(settings: &Settings, tools: BTreeMap<String, String>) -> Result<Self> {
+        Ok(Self {
+            program: env!("CARGO_PKG_NAME").to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            arguments: std::env::args().collect(),
+            tools,
+            settings: serde_json::to_value(settings)?,
+            status: RunStatus::Running,
+            started_unix: unix_time(),
+            finished_unix: None,
+            error: None,
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            steps: Vec::new(),
+            removed_outputs: Vec::new(),
+            staged_inputs: BTreeMap::new(),
+        })
+    }
+    
+    /// Set the final status and collect the run's inputs and outputs
+    fn finish(&mut self, status: RunStatus, error: Option<String>) {
+        self.status = status;
+        self.error = error;
+        self.finished_unix = Some(unix_time());
+        
+        let produced: HashSet<&PathBuf> = self
+            .steps
+            .iter()
+            .flat_map(|step| step.outputs.iter().map(|file| &file.path))
+            .collect();
+        let mut seen = HashSet::new();
+        self.inputs = self
+            .steps
+            .iter()
+            .flat_map(|step| &step.inputs)
+            .filter(|file| !produced.contains(&file.path) && seen.insert(&file.path))
+            .cloned()
+            .collect();
+        self.outputs = self
+            .steps
+            .iter()
+            .filter(|step| matches!(step.status, StepStatus::Completed | StepStatus::Skipped))
+            .flat_map(|step| step.outputs.iter().cloned())
+            .collect();
+    }
+    
+    fn write(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write run manifest: {:?}", path))?;
+        Ok(())
+    }
+}
+
+/// First line of a program's `--version` output, or "unavailable" when it
+/// cannot be run. BWA has no `--version` flag and prints its version in the
+/// usage text instead.
+async fn tool_version(program: &str) -> String {
+    let mut command = tokio::process::Command::new(program);
+    if program != "bwa" {
+        command.arg("--version");
+    }
+    command.stdin(std::process::Stdio::null()).kill_on_drop(true);
+    let output = match time::timeout(time::Duration::from_secs(10), command.output()).await {
+        Ok(Ok(output)) => output,
+        _ => return "unavailable".to_string(),
+    };
+    let text = format!(
+        "{}\n{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let version = if program == "bwa" {
+        text.lines().find_map(|line| line.strip_prefix("Version:")).map(str::trim)
+    } else {
+        text.lines().map(str::trim).find(|line| !line.is_empty())
+    };
+    version.unwrap_or("unknown").to_string()
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
 /// Main entry point
 #[tokio::main(flavor = "multi_thread")]
 async fn main() -> ExitCode {
-    // Initialize with default logging until we parse config
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::from_default_env())
-        .init();
-    
     // Measure execution time
     let start_time = Instant::now();
     
     // Parse command line arguments
     let cli = Cli::parse();
     
+    // Load configuration, then set up logging once from it and the CLI
+    let settings = load_configuration(&cli).await;
+    setup_logging(&cli, settings.as_ref().ok());
+    
     // Run the pipeline with proper error handling
-    match run_pipeline(cli, start_time).await {
+    let result = match settings {
+        Ok(settings) => run_pipeline(cli, settings, start_time).await,
+        Err(e) => Err(e),
+    };
+    match result {
         Ok(_) => {
             info!("Pipeline completed successfully");
             ExitCode::SUCCESS
@@ -292,10 +1042,7 @@ async fn main() -> ExitCode {
 }
 
 /// Main pipeline execution
-async fn run_pipeline(cli: Cli, start_time: Instant) -> Result<()> {
-    // Load and merge configuration
-    let mut settings = load_configuration(&cli).await?;
-    
+async fn run_pipeline(cli: Cli, mut settings: Settings, start_time: Instant) -> Result<()> {
     // Apply CLI overrides
     if let Some(threads) = cli.threads {
         settings.threads = threads;
@@ -305,15 +1052,6 @@ async fn run_pipeline(cli: Cli, start_time: Instant) -> Result<()> {
         settings.tmp_dir = tmp_dir;
     }
     
-    // Setup logging based on verbosity
-    let log_level = match cli.verbose {
-        0 => settings.log_level.as_deref().unwrap_or("info"),
-        1 => "debug",
-        _ => "trace",
-    };
-    
-    setup_logging(log_level);
-    
     // Initialize Rayon thread pool
     rayon::ThreadPoolBuilder::new()
         .num_threads(settings.threads)
@@ -335,25 +1073,90 @@ async fn run_pipeline(cli: Cli, start_time: Instant) -> Result<()> {
     // Initialize progress bars
     let progress = Arc::new(MultiProgress::new());
     
+    // Record the versions of the external programs this command runs
+    let mut tools = BTreeMap::new();
+    for tool in cli.command.external_tools(&settings) {
+        let version = tool_version(&tool).await;
+        debug!("{}: {}", tool, version);
+        tools.insert(tool, version);
+    }
+    
     // Create pipeline context
+    let profile = Arc::new(Mutex::new(RunProfile::new(settings.threads)));
     let context = PipelineContext {
         settings: settings.clone(),
         temp_dir,
         progress,
         start_time,
+        profile: profile.clone(),
+        resources: ResourcePool::new(settings.threads, settings.memory_gb),
+        cancel: CancellationToken::new(),
+        manifest: Arc::new(Mutex::new(RunManifest::new(&settings, tools)?)),
+        remote: RemoteInputs::new(settings.tmp_dir.join("genomic_pipeline_remote")),
+        dry_run: cli.dry_run,
     };
     
-    // Set up graceful shutdown handler
-    let graceful = signal::ctrl_c();
+    // A dry run only prints the plan: no run.json, profile or outputs
+    if cli.dry_run {
+        println!("Dry run: nothing will be executed");
+        println!("Resolved configuration:\n{}", toml::to_string_pretty(&settings)?);
+        for (tool, version) in &context.manifest.lock().unwrap().tools {
+            println!("Tool {}: {}", tool, version);
+            if version == "unavailable" {
+                warn!("{} is not available", tool);
+            }
+        }
+        return execute_command(cli.command, context).await;
+    }
+    
+    // The provenance record sits next to the command's outputs
+    let manifest_path = cli.command.output_dir().join("run.json");
+    fs::create_dir_all(cli.command.output_dir()).await?;
+    context.manifest.lock().unwrap().write(&manifest_path)?;
+    
+    // Ctrl-c cancels the running steps, which stop their child processes and
+    // remove partial outputs; a second ctrl-c exits immediately
+    let cancel = context.cancel.clone();
+    let interrupt = tokio::spawn(async move {
+        if signal::ctrl_c().await.is_ok() {
+            warn!("Interrupted; stopping running steps (press ctrl-c again to exit immediately)");
+            cancel.cancel();
+            if signal::ctrl_c().await.is_ok() {
+                std::process::exit(130);
+            }
+        }
+    });
+    
+    let manifest = context.manifest.clone();
+    let cancel = context.cancel.clone();
+    let mut result = execute_command(cli.command, context).await;
+    interrupt.abort();
+    if cancel.is_cancelled() {
+        info!("Shutting down on SIGINT");
+        result = Err(anyhow!(PipelineError::Interrupted));
+    }
+    
+    let status = match &result {
+        Ok(()) => RunStatus::Completed,
+        Err(_) if cancel.is_cancelled() => RunStatus::Interrupted,
+        Err(_) => RunStatus::Failed,
+    };
+    {
+        let mut manifest = manifest.lock().unwrap();
+        manifest.finish(status, result.as_ref().err().map(|e| format!("{:#}", e)));
+        if let Err(e) = manifest.write(&manifest_path) {
+            warn!("{:#}", e);
+        }
+    }
     
-    // Run the command with graceful shutdown
-    tokio::select! {
-        result = execute_command(cli.command, context) => result,
-        _ = graceful => {
-            info!("Shutting down on SIGINT");
-            Err(anyhow!(PipelineError::Interrupted))
+    if result.is_ok() {
+        if let Some(profile_path) = &cli.profile_json {
+            profile.lock().unwrap().write(profile_path, start_time)?;
+            info!("Runtime profile written to {:?}", profile_path);
         }
     }
+    
+    result
 }
 
 /// Load and parse configuration file
@@ -383,9 +1186,16 @@ async fn load_configuration(cli: &Cli) -> Result<Settings> {
     Ok(settings)
 }
 
-/// Set up logging with the appropriate level
-fn setup_logging(level: &str) {
-    let filter = match level.to_lowercase().as_str() {
+/// Install the global subscriber. `-v` takes precedence over `RUST_LOG`,
+/// which takes precedence over `log_level` from config. JSON logs carry the
+/// fields of the enclosing step span (sample, step, region) on every line.
+fn setup_logging(cli: &Cli, settings: Option<&Settings>) {
+    let level = match cli.verbose {
+        0 => settings.and_then(|settings| settings.log_level.as_deref()).unwrap_or("info"),
+        1 => "debug",
+        _ => "trace",
+    };
+    let level = match level.to_lowercase().as_str() {
         "trace" => Level::TRACE,
         "debug" => Level::DEBUG,
         "info" => Level::INFO,
@@ -393,22 +1203,48 @@ fn setup_logging(level: &str) {
         "error" => Level::ERROR,
         _ => Level::INFO,
     };
+    let filter = match EnvFilter::try_from_default_env() {
+        Ok(filter) if cli.verbose == 0 => filter,
+        _ => EnvFilter::default().add_directive(level.into()),
+    };
     
-    tracing_subscriber::fmt()
-        .with_max_level(filter)
-        .init();
+    let format = cli.log_format.or(settings.and_then(|settings| settings.log_format)).unwrap_or_default();
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+    match format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .init(),
+    }
+}
+
+/// Span around one step, so its log lines can be grouped by sample and step.
+/// `region` is recorded by steps restricted to regions or targets.
+fn step_span(sample: Option<&str>, step: &str) -> Span {
+    let span = info_span!("step", sample = tracing::field::Empty, step, region = tracing::field::Empty);
+    if let Some(sample) = sample {
+        span.record("sample", sample);
+    }
+    span
 }
 
 /// Execute the selected command
 async fn execute_command(command: Command, context: PipelineContext) -> Result<()> {
     match command {
-        Command::Align { reads, reference, out_bam, aligner, mark_duplicates } => {
-            // Validate input files
+        Command::Align { reads, reference, out_bam, aligner, mark_duplicates, out_format } => {
+            // Validate input files, staging remote ones
             validate_files(&[&reads, &reference]).await?;
+            let reads = context.localize(&reads).await?;
+            let reference = context.localize(&reference).await?;
             
             // Create output directory if it doesn't exist
-            if let Some(parent) = out_bam.parent() {
-                fs::create_dir_all(parent).await?;
+            if !context.dry_run {
+                if let Some(parent) = out_bam.parent() {
+                    fs::create_dir_all(parent).await?;
+                }
             }
             
             // Merge settings with command line options
@@ -417,18 +1253,44 @@ async fn execute_command(command: Command, context: PipelineContext) -> Result<(
                 align_settings.aligner = Some(aligner_name);
             }
             align_settings.mark_duplicates = Some(mark_duplicates);
+            if out_format.is_some() {
+                align_settings.output_format = out_format;
+            }
             
             // Run alignment
-            run_alignment(&reads, &reference, &out_bam, align_settings, &context).await
+            let inputs = vec![reads.clone(), reference.clone()];
+            let outputs = vec![alignment_index_path(&out_bam), out_bam.clone()];
+            if context.dry_run {
+                let threads = context.planned_threads(align_settings.threads);
+                let commands = align_plan(&reads, None, None, &reference, &out_bam, &align_settings, threads)?;
+                print_plan(None, &[PlannedStep::run("align", inputs, outputs, commands)]);
+                return Ok(());
+            }
+            let started = Instant::now();
+            let result = run_alignment(&reads, None, None, &reference, &out_bam, align_settings, &context)
+                .instrument(step_span(None, "align"))
+                .await;
+            context.finish_step("align", &inputs, &outputs, started, &result).await;
+            result.map(|_| ())
         }
         
-        Command::Call { bam, reference, out_vcf, min_depth, regions, format } => {
-            // Validate input files
+        Command::Call { bam, reference, out_vcf, min_depth, regions, targets, format } => {
+            // Validate input files, staging remote ones
             validate_files(&[&bam, &reference]).await?;
+            if let Some(targets) = &targets {
+                validate_files(&[targets]).await?;
+            }
+            let mut context = context;
+            context.localize_settings().await?;
+            let bam = context.localize(&bam).await?;
+            let reference = context.localize(&reference).await?;
+            let targets = context.localize_option(targets).await?;
             
             // Create output directory if it doesn't exist
-            if let Some(parent) = out_vcf.parent() {
-                fs::create_dir_all(parent).await?;
+            if !context.dry_run {
+                if let Some(parent) = out_vcf.parent() {
+                    fs::create_dir_all(parent).await?;
+                }
             }
             
             // Merge settings with command line options
@@ -439,18 +1301,55 @@ async fn execute_command(command: Command, context: PipelineContext) -> Result<(
             if let Some(regions_list) = regions {
                 call_settings.regions = Some(regions_list);
             }
+            if targets.is_some() {
+                call_settings.targets = targets;
+            }
             
             // Run variant calling
-            run_calling(&bam, &reference, &out_vcf, call_settings, format, &context).await
+            let mut inputs = vec![bam.clone(), reference.clone()];
+            inputs.extend(call_settings.targets.clone());
+            if context.dry_run {
+                let threads = context.planned_threads(call_settings.threads);
+                let commands =
+                    call_plan(&bam, &reference, &out_vcf, &call_settings, format, threads, context.temp_dir.path())?;
+                print_plan(None, &[PlannedStep::run("call", inputs, vec![out_vcf], commands)]);
+                return Ok(());
+            }
+            let started = Instant::now();
+            let result = run_calling(&bam, &reference, &out_vcf, call_settings, format, &context)
+                .instrument(step_span(None, "call"))
+                .await;
+            context.finish_step("call", &inputs, &[out_vcf], started, &result).await;
+            result.map(|_| ())
         }
         
-        Command::Annotate { vcf, gff, output, databases, effects, format } => {
-            // Validate input files
+        Command::Annotate { vcf, gff, output, databases, effects, targets, format } => {
+            // Validate input files, staging remote ones
             validate_files(&[&vcf, &gff]).await?;
+            if let Some(targets) = &targets {
+                validate_files(&[targets]).await?;
+            }
+            let mut context = context;
+            context.localize_settings().await?;
+            let vcf = context.localize(&vcf).await?;
+            let gff = context.localize(&gff).await?;
+            let targets = context.localize_option(targets).await?;
+            let databases = match databases {
+                Some(databases) => {
+                    let mut local = Vec::with_capacity(databases.len());
+                    for db in &databases {
+                        local.push(context.localize(db).await?);
+                    }
+                    Some(local)
+                }
+                None => None,
+            };
             
             // Create output directory if it doesn't exist
-            if let Some(parent) = output.parent() {
-                fs::create_dir_all(parent).await?;
+            if !context.dry_run {
+                if let Some(parent) = output.parent() {
+                    fs::create_dir_all(parent).await?;
+                }
             }
             
             // Merge settings with command line options
@@ -459,378 +1358,3971 @@ async fn execute_command(command: Command, context: PipelineContext) -> Result<(
                 annotate_settings.databases = Some(db_list);
             }
             annotate_settings.effects = Some(effects);
+            if targets.is_some() {
+                annotate_settings.targets = targets;
+            }
             
             // Run annotation
-            run_annotation(&vcf, &gff, &output, annotate_settings, format, &context).await
+            let mut inputs = vec![vcf.clone(), gff.clone()];
+            inputs.extend(annotate_settings.databases.iter().flatten().filter(|db| db.exists()).cloned());
+            inputs.extend(annotate_settings.targets.clone());
+            if context.dry_run {
+                let commands = annotate_plan(&vcf, &gff, &output, &annotate_settings);
+                print_plan(None, &[PlannedStep::run("annotate", inputs, vec![output], commands)]);
+                return Ok(());
+            }
+            let started = Instant::now();
+            let result = run_annotation(&vcf, &gff, &output, annotate_settings, format, &context)
+                .instrument(step_span(None, "annotate"))
+                .await;
+            context.finish_step("annotate", &inputs, &[output], started, &result).await;
+            result.map(|_| ())
         }
         
-        Command::Pipeline { reads, reference, gff, output_dir, sample, keep_intermediate } => {
-            // Validate input files
-            validate_files(&[&reads, &reference, &gff]).await?;
+        Command::Qc { reads, mate, bam, reference, output_dir, sample } => {
+            // Validate input files, staging remote ones
+            let mut fastqs: Vec<PathBuf> = reads.into_iter().chain(mate).collect();
+            let files: Vec<_> = fastqs.iter().chain(&bam).chain(&reference).collect();
+            validate_files(&files).await?;
+            for fastq in fastqs.iter_mut() {
+                *fastq = context.localize(fastq).await?;
+            }
+            let bam = context.localize_option(bam).await?;
+            let reference = context.localize_option(reference).await?;
+            let mut inputs = fastqs.clone();
+            inputs.extend(bam.clone());
+            inputs.extend(reference.clone());
             
-            // Create output directory
+            // Compute metrics and write the reports
+            let outputs = qc_report_paths(&output_dir, &sample);
+            if context.dry_run {
+                let commands = qc_plan(&fastqs, bam.as_deref());
+                print_plan(Some(sample.as_str()), &[PlannedStep::run("qc", inputs, outputs, commands)]);
+                return Ok(());
+            }
             fs::create_dir_all(&output_dir).await?;
+            let started = Instant::now();
+            let settings = context.settings.qc.clone();
+            let result = run_qc(&fastqs, bam.as_deref(), reference.as_deref(), &sample, &outputs, settings, &context)
+                .instrument(step_span(Some(&sample), "qc"))
+                .await;
+            context.finish_step("qc", &inputs, &outputs, started, &result).await;
+            result.map(|_| ())
+        }
+        
+        Command::Pipeline {
+            reads,
+            reference,
+            gff,
+            output_dir,
+            sample,
+            samples,
+            max_parallel_samples,
+            targets,
+            min_target_depth,
+            keep_intermediate,
+        } => {
+            // Validate input files, staging remote ones
+            validate_files(&[&reference, &gff]).await?;
+            let mut context = context;
+            context.localize_settings().await?;
+            let reference = context.localize(&reference).await?;
+            let gff = context.localize(&gff).await?;
             
-            // Run full pipeline
-            run_full_pipeline(
-                &reads,
-                &reference,
-                &gff,
-                &output_dir,
-                &sample,
+            // A target panel restricts calling and annotation, and adds the
+            // coverage check
+            if let Some(targets) = targets {
+                validate_files(&[&targets]).await?;
+                let targets = context.localize(&targets).await?;
+                context.settings.call.targets = Some(targets.clone());
+                context.settings.annotate.targets = Some(targets);
+            }
+            if min_target_depth.is_some() {
+                context.settings.qc.min_target_depth = min_target_depth;
+            }
+            
+            // Create output directory
+            if !context.dry_run {
+                fs::create_dir_all(&output_dir).await?;
+            }
+            
+            // Cohort mode: every sample in the manifest, then a joint merge
+            if let Some(manifest) = samples {
+                validate_files(&[&manifest]).await?;
+                // Even a dry run stages a remote manifest, to plan its samples
+                let manifest = if context.dry_run {
+                    context.remote.localize(&manifest, &context.cancel).await?
+                } else {
+                    context.localize(&manifest).await?
+                };
+                return run_cohort(
+                    &manifest,
+                    &reference,
+                    &gff,
+                    &output_dir,
+                    max_parallel_samples,
+                    keep_intermediate,
+                    &context,
+                )
+                .await;
+            }
+            
+            let (Some(reads), Some(sample)) = (reads, sample) else {
+                return Err(anyhow!(PipelineError::ConfigError(
+                    "--reads and --sample are required without --samples".to_string()
+                )));
+            };
+            validate_files(&[&reads]).await?;
+            let reads = context.localize(&reads).await?;
+            
+            // Run full pipeline
+            let stats = run_full_pipeline(
+                &reads,
+                None,
+                &reference,
+                &gff,
+                &output_dir,
+                &sample,
                 keep_intermediate,
                 &context,
             )
+            .await?;
+            if !context.dry_run {
+                print_pipeline_summary(&stats, &sample);
+            }
+            Ok(())
+        }
+        
+        Command::TumorOnly {
+            bam,
+            reference,
+            gff,
+            population_af,
+            panel_of_normals,
+            graph,
+            model,
+            max_population_af,
+            min_score,
+            output_dir,
+            sample,
+        } => {
+            // Validate input files
+            let mut inputs = vec![&bam, &reference, &gff, &population_af, &graph, &model];
+            if let Some(pon) = &panel_of_normals {
+                inputs.push(pon);
+            }
+            validate_files(&inputs).await?;
+            let mut context = context;
+            context.localize_settings().await?;
+            let bam = context.localize(&bam).await?;
+            let reference = context.localize(&reference).await?;
+            let gff = context.localize(&gff).await?;
+            let population_af = context.localize(&population_af).await?;
+            let panel_of_normals = context.localize_option(panel_of_normals).await?;
+            let graph = context.localize(&graph).await?;
+            let model = context.localize(&model).await?;
+            
+            // Create output directory
+            if !context.dry_run {
+                fs::create_dir_all(&output_dir).await?;
+            }
+            
+            // Merge settings with command line options
+            let mut tumor_settings = context.settings.tumor_only.clone();
+            if max_population_af.is_some() {
+                tumor_settings.max_population_af = max_population_af;
+            }
+            if min_score.is_some() {
+                tumor_settings.min_score = min_score;
+            }
+            
+            run_tumor_only(
+                &bam,
+                &reference,
+                &gff,
+                &population_af,
+                panel_of_normals.as_deref(),
+                &graph,
+                &model,
+                &output_dir,
+                &sample,
+                tumor_settings,
+                &context,
+            )
             .await
         }
-    }
+    }
+}
+
+/// Validate that input files exist; remote URIs are checked with the object store
+async fn validate_files(files: &[&PathBuf]) -> Result<()> {
+    for &file in files {
+        let exists = match remote_uri(file) {
+            Some(uri) => {
+                let (store, object) = remote_object(uri)?;
+                match store.head(&object).await {
+                    Ok(_) => true,
+                    Err(object_store::Error::NotFound { .. }) => false,
+                    Err(e) => return Err(e).with_context(|| format!("Failed to query {}", uri)),
+                }
+            }
+            None => file.exists(),
+        };
+        if !exists {
+            return Err(anyhow!(PipelineError::FileNotFound(
+                file.to_string_lossy().to_string()
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Index files looked for next to a remote input and staged along with it
+const REMOTE_INDEX_SUFFIXES: [&str; 12] =
+    [".fai", ".bai", ".crai", ".tbi", ".csi", ".amb", ".ann", ".bwt", ".pac", ".sa", ".0123", ".bwt.2bit.64"];
+
+/// The URI of a remote input (`s3://`, `gs://`, `http://` or `https://`)
+fn remote_uri(path: &Path) -> Option<&str> {
+    path.to_str()
+        .filter(|path| ["s3://", "gs://", "http://", "https://"].iter().any(|scheme| path.starts_with(scheme)))
+}
+
+/// Object store and object path of a remote URI. Credentials and regions
+/// come from the environment (`AWS_*`, `GOOGLE_*`), as for the cloud CLIs.
+fn remote_object(uri: &str) -> Result<(Arc<dyn ObjectStore>, ObjectPath)> {
+    let invalid = || anyhow!(PipelineError::InvalidInput(format!("Invalid URI: {}", uri)));
+    let url = Url::parse(uri).map_err(|_| invalid())?;
+    let host = url.host_str().ok_or_else(invalid)?;
+    let store: Arc<dyn ObjectStore> = match url.scheme() {
+        "s3" => Arc::new(AmazonS3Builder::from_env().with_bucket_name(host).build()?),
+        "gs" => Arc::new(GoogleCloudStorageBuilder::from_env().with_bucket_name(host).build()?),
+        "http" | "https" => Arc::new(HttpBuilder::new().with_url(&url[..url::Position::BeforePath]).build()?),
+        _ => return Err(invalid()),
+    };
+    let object = ObjectPath::from_url_path(url.path()).map_err(|_| invalid())?;
+    Ok((store, object))
+}
+
+/// What was staged for a URI, kept next to the staged copy
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StagedObject {
+    uri: String,
+    size: usize,
+    e_tag: Option<String>,
+    sha256: String,
+}
+
+/// Remote inputs staged to local disk, one directory per URI under `dir`,
+/// since the aligners, callers and readers all need local files. A staged
+/// copy is reused by later runs while the object's size and ETag are
+/// unchanged.
+#[derive(Debug, Clone)]
+struct RemoteInputs {
+    dir: PathBuf,
+    /// Per-URI locks, so samples sharing an input stage it once
+    locks: Arc<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>>,
+}
+
+impl RemoteInputs {
+    fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            locks: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+    
+    /// Local path of an input: the path itself, or for a remote URI the
+    /// staged copy, with any index files found next to the object
+    async fn localize(&self, path: &Path, cancel: &CancellationToken) -> Result<PathBuf> {
+        let Some(uri) = remote_uri(path) else {
+            return Ok(path.to_path_buf());
+        };
+        let lock = self.locks.lock().unwrap().entry(uri.to_string()).or_default().clone();
+        let _guard = lock.lock().await;
+        
+        let (store, object) = remote_object(uri)?;
+        let digest = format!("{:x}", Sha256::digest(uri.as_bytes()));
+        let dir = self.dir.join(&digest[..16]);
+        let name = object.filename().unwrap_or("input").to_string();
+        let local = dir.join(&name);
+        stage_object(store.as_ref(), &object, uri, &local, cancel).await?;
+        
+        for suffix in REMOTE_INDEX_SUFFIXES {
+            let index = ObjectPath::from(format!("{}{}", object, suffix));
+            match store.head(&index).await {
+                Ok(_) => {
+                    let index_uri = format!("{}{}", uri, suffix);
+                    stage_object(store.as_ref(), &index, &index_uri, &dir.join(format!("{}{}", name, suffix)), cancel)
+                        .await?;
+                }
+                Err(object_store::Error::NotFound { .. }) => {}
+                Err(e) => return Err(e).with_context(|| format!("Failed to query {}{}", uri, suffix)),
+            }
+        }
+        Ok(local)
+    }
+}
+
+/// Download an object to `local` unless an up-to-date copy is staged there.
+/// The download streams to a `.partial` file while being hashed, and must
+/// match the object's size and, when the store publishes `<uri>.sha256`,
+/// that checksum.
+async fn stage_object(
+    store: &dyn ObjectStore,
+    object: &ObjectPath,
+    uri: &str,
+    local: &Path,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let meta = match store.head(object).await {
+        Ok(meta) => meta,
+        Err(object_store::Error::NotFound { .. }) => return Err(anyhow!(PipelineError::FileNotFound(uri.to_string()))),
+        Err(e) => return Err(e).with_context(|| format!("Failed to query {}", uri)),
+    };
+    let with_suffix = |suffix: &str| {
+        let mut path = local.as_os_str().to_owned();
+        path.push(suffix);
+        PathBuf::from(path)
+    };
+    let marker = with_suffix(".staged.json");
+    if let Ok(json) = fs::read_to_string(&marker).await {
+        let staged: Option<StagedObject> = serde_json::from_str(&json).ok();
+        if staged.is_some_and(|staged| {
+            staged.size == meta.size && staged.e_tag == meta.e_tag && file_len(local) == meta.size as u64
+        }) {
+            debug!("Reusing staged copy of {}", uri);
+            return Ok(());
+        }
+    }
+    
+    info!("Staging {} ({} bytes) to {:?}", uri, meta.size, local);
+    if let Some(parent) = local.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    let partial = with_suffix(".partial");
+    let mut file = fs::File::create(&partial)
+        .await
+        .with_context(|| format!("Failed to create {:?}", partial))?;
+    let mut stream = store.get(object).await.with_context(|| format!("Failed to download {}", uri))?.into_stream();
+    let mut hasher = Sha256::new();
+    let mut written = 0;
+    loop {
+        let chunk = tokio::select! {
+            chunk = stream.next() => chunk,
+            _ = cancel.cancelled() => {
+                fs::remove_file(&partial).await.ok();
+                return Err(anyhow!(PipelineError::Interrupted));
+            }
+        };
+        let Some(chunk) = chunk else {
+            break;
+        };
+        let chunk = chunk.with_context(|| format!("Failed to download {}", uri))?;
+        hasher.update(&chunk);
+        file.write_all(&chunk).await?;
+        written += chunk.len();
+    }
+    file.flush().await?;
+    drop(file);
+    
+    let sha256 = format!("{:x}", hasher.finalize());
+    let mismatch = if written != meta.size {
+        Some(format!("received {} of {} bytes", written, meta.size))
+    } else {
+        match published_checksum(store, object).await? {
+            Some(expected) if expected != sha256 => {
+                Some(format!("SHA-256 is {}, but {}.sha256 has {}", sha256, uri, expected))
+            }
+            _ => None,
+        }
+    };
+    if let Some(reason) = mismatch {
+        fs::remove_file(&partial).await.ok();
+        return Err(anyhow!(PipelineError::InvalidInput(format!("Download of {} is corrupt: {}", uri, reason))));
+    }
+    
+    fs::rename(&partial, local).await?;
+    let staged = StagedObject {
+        uri: uri.to_string(),
+        size: meta.size,
+        e_tag: meta.e_tag,
+        sha256,
+    };
+    fs::write(&marker, serde_json::to_string_pretty(&staged)?).await?;
+    Ok(())
+}
+
+/// SHA-256 published next to an object as `<object>.sha256`, in `sha256sum` format
+async fn published_checksum(store: &dyn ObjectStore, object: &ObjectPath) -> Result<Option<String>> {
+    let sidecar = ObjectPath::from(format!("{}.sha256", object));
+    let bytes = match store.get(&sidecar).await {
+        Ok(result) => result.bytes().await?,
+        Err(object_store::Error::NotFound { .. }) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(String::from_utf8_lossy(&bytes).split_whitespace().next().map(str::to_ascii_lowercase))
+}
+
+/// External short/long-read aligners the pipeline can drive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Aligner {
+    Bwa,
+    BwaMem2,
+    Minimap2,
+    Bowtie2,
+}
+
+impl Aligner {
+    fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "bwa" => Ok(Aligner::Bwa),
+            "bwa-mem2" => Ok(Aligner::BwaMem2),
+            "minimap2" => Ok(Aligner::Minimap2),
+            "bowtie2" => Ok(Aligner::Bowtie2),
+            _ => Err(anyhow!(PipelineError::ConfigError(format!(
+                "Unsupported aligner: {} (expected bwa, bwa-mem2, minimap2 or bowtie2)",
+                name
+            )))),
+        }
+    }
+    
+    fn program(self) -> &'static str {
+        match self {
+            Aligner::Bwa => "bwa",
+            Aligner::BwaMem2 => "bwa-mem2",
+            Aligner::Minimap2 => "minimap2",
+            Aligner::Bowtie2 => "bowtie2",
+        }
+    }
+    
+    /// BWA and Bowtie2 need a prebuilt index next to the reference;
+    /// minimap2 indexes on the fly
+    fn check_index(self, reference: &Path) -> Result<()> {
+        let with_suffix = |suffix: &str| PathBuf::from(format!("{}{}", reference.display(), suffix));
+        let missing_index = |hint: String| {
+            anyhow!(PipelineError::ConfigError(format!(
+                "No {} index for {:?}; build it with `{}`",
+                self.program(),
+                reference,
+                hint
+            )))
+        };
+        match self {
+            Aligner::Bwa | Aligner::BwaMem2 => {
+                let index_file = if self == Aligner::Bwa { ".bwt" } else { ".bwt.2bit.64" };
+                if !with_suffix(index_file).exists() {
+                    return Err(missing_index(format!("{} index {}", self.program(), reference.display())));
+                }
+            }
+            Aligner::Minimap2 => {}
+            Aligner::Bowtie2 => {
+                let prefix = reference.with_extension("");
+                let indexed = [".1.bt2", ".1.bt2l"]
+                    .iter()
+                    .any(|suffix| PathBuf::from(format!("{}{}", prefix.display(), suffix)).exists());
+                if !indexed {
+                    return Err(missing_index(format!(
+                        "bowtie2-build {} {}",
+                        reference.display(),
+                        prefix.display()
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+    
+    /// Build the aligner command writing SAM to stdout, for single-end reads
+    /// or a pair of mate files, tagging reads with a read group when a sample
+    /// name is given
+    fn command(
+        self,
+        reads: &Path,
+        mate: Option<&Path>,
+        sample: Option<&str>,
+        reference: &Path,
+        threads: usize,
+    ) -> tokio::process::Command {
+        let mut command = tokio::process::Command::new(self.program());
+        match self {
+            Aligner::Bwa | Aligner::BwaMem2 => {
+                command.arg("mem").arg("-t").arg(threads.to_string());
+                if let Some(sample) = sample {
+                    command.arg("-R").arg(format!("@RG\\tID:{0}\\tSM:{0}", sample));
+                }
+                command.arg(reference).arg(reads).args(mate);
+            }
+            Aligner::Minimap2 => {
+                command.arg("-a").arg("-x").arg("sr").arg("-t").arg(threads.to_string());
+                if let Some(sample) = sample {
+                    command.arg("-R").arg(format!("@RG\\tID:{0}\\tSM:{0}", sample));
+                }
+                command.arg(reference).arg(reads).args(mate);
+            }
+            Aligner::Bowtie2 => {
+                let prefix = reference.with_extension("");
+                command.arg("-p").arg(threads.to_string()).arg("-x").arg(&prefix);
+                if let Some(sample) = sample {
+                    command.arg("--rg-id").arg(sample).arg("--rg").arg(format!("SM:{}", sample));
+                }
+                match mate {
+                    Some(mate) => command.arg("-1").arg(reads).arg("-2").arg(mate),
+                    None => command.arg("-U").arg(reads),
+                };
+            }
+        }
+        command
+    }
+    
+    /// Reads processed according to one line of the aligner's stderr
+    fn reads_in_progress_line(self, line: &str) -> Option<u64> {
+        let count_after = |marker: &str| {
+            let rest = &line[line.find(marker)? + marker.len()..];
+            rest.split_whitespace().next()?.parse().ok()
+        };
+        match self {
+            // [M::mem_process_seqs] Processed 100000 reads in 35.2 CPU sec, ...
+            Aligner::Bwa | Aligner::BwaMem2 => count_after("[M::mem_process_seqs] Processed "),
+            // [M::worker_pipeline::12.3*3.91] mapped 333334 sequences
+            Aligner::Minimap2 => count_after("] mapped "),
+            // Bowtie2 only reports the total at the end: "100000 reads; of these:"
+            Aligner::Bowtie2 => line.strip_suffix(" reads; of these:")?.trim().parse().ok(),
+        }
+    }
+}
+
+/// Read counts from sorting and duplicate marking
+#[derive(Debug, Default, Clone, Copy)]
+struct AlignmentCounts {
+    records: u64,
+    mapped: u64,
+    duplicates: u64,
+}
+
+/// Coordinate sort key: reference index (unmapped last), position, input order
+type SortKey = (usize, usize, u64);
+
+fn sort_key(record: &sam::alignment::Record, order: u64) -> SortKey {
+    (
+        record.reference_sequence_id().unwrap_or(usize::MAX),
+        record.alignment_start().map(usize::from).unwrap_or(0),
+        order,
+    )
+}
+
+/// Position and strand of one end of a fragment
+type FragmentEnd = (usize, usize, bool);
+
+/// Fragments with the same key are duplicates of each other. A read whose
+/// mate is unmapped (or that is unpaired) is keyed by its unclipped 5' end;
+/// a pair is keyed by both mates' alignment starts, which each mate knows.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum DuplicateKey {
+    Single(FragmentEnd),
+    Pair(FragmentEnd, FragmentEnd),
+}
+
+fn duplicate_key(record: &sam::alignment::Record) -> Option<DuplicateKey> {
+    use sam::record::cigar::op::Kind;
+    
+    let flags = record.flags();
+    if flags.is_unmapped() || flags.is_secondary() || flags.is_supplementary() {
+        return None;
+    }
+    let reference_id = record.reference_sequence_id()?;
+    let start = usize::from(record.alignment_start()?);
+    let reverse = flags.is_reverse_complemented();
+    
+    if flags.is_segmented() && !flags.is_mate_unmapped() {
+        let mate = (
+            record.mate_reference_sequence_id()?,
+            usize::from(record.mate_alignment_start()?),
+            flags.is_mate_reverse_complemented(),
+        );
+        let this = (reference_id, start, reverse);
+        return Some(DuplicateKey::Pair(this.min(mate), this.max(mate)));
+    }
+    
+    let is_clip = |kind: Kind| matches!(kind, Kind::SoftClip | Kind::HardClip);
+    let ops: Vec<_> = record.cigar().iter().collect();
+    let five_prime = if reverse {
+        let end = start + record.cigar().alignment_span() - 1;
+        end + ops.iter().rev().take_while(|op| is_clip(op.kind())).map(|op| op.len()).sum::<usize>()
+    } else {
+        let clipped: usize = ops.iter().take_while(|op| is_clip(op.kind())).map(|op| op.len()).sum();
+        start.saturating_sub(clipped)
+    };
+    Some(DuplicateKey::Single((reference_id, five_prime, reverse)))
+}
+
+/// Sum of base qualities of at least 15, Picard's measure of read quality
+fn duplicate_score(record: &sam::alignment::Record) -> u64 {
+    record
+        .quality_scores()
+        .as_ref()
+        .iter()
+        .map(|score| u64::from(u8::from(*score)))
+        .filter(|&q| q >= 15)
+        .sum()
+}
+
+fn read_name(record: &sam::alignment::Record) -> String {
+    record.read_name().map(|name| name.to_string()).unwrap_or_default()
+}
+
+/// Read the SAM header from `reader`, declaring the output coordinate-sorted
+fn read_sorted_sam_header<R: BufRead>(reader: &mut R) -> Result<sam::Header> {
+    let mut lines = Vec::new();
+    while reader.fill_buf()?.first() == Some(&b'@') {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        lines.push(line.trim_end().to_string());
+    }
+    
+    match lines.iter_mut().find(|line| line.starts_with("@HD")) {
+        Some(hd) => {
+            let fields: Vec<&str> = hd.split('\t').filter(|field| !field.starts_with("SO:")).collect();
+            *hd = format!("{}\tSO:coordinate", fields.join("\t"));
+        }
+        None => lines.insert(0, "@HD\tVN:1.6\tSO:coordinate".to_string()),
+    }
+    
+    let text = lines.join("\n") + "\n";
+    text.parse()
+        .map_err(|e| anyhow!(PipelineError::InvalidInput(format!("Invalid SAM header from aligner: {}", e))))
+}
+
+/// Write coordinate-sorted records as one BAM chunk of an external sort
+fn write_sorted_chunk(
+    header: &sam::Header,
+    records: &mut Vec<(SortKey, sam::alignment::Record)>,
+    path: &Path,
+) -> Result<()> {
+    records.par_sort_by_key(|(key, _)| *key);
+    let file = std::fs::File::create(path)
+        .with_context(|| format!("Failed to create sort chunk: {:?}", path))?;
+    let mut writer = bam::Writer::new(file);
+    writer.write_header(header)?;
+    for (_, record) in records.drain(..) {
+        writer.write_record(header, &record)?;
+    }
+    writer.try_finish()?;
+    Ok(())
+}
+
+/// Sort the aligner's SAM output into a coordinate-sorted BAM or CRAM
+/// `writer` and, when asked, mark duplicates. Records are sorted in memory in chunks of
+/// `buffer_records` that are spilled to `work_dir` and merged; duplicate keys
+/// are collected on the way in, so the merge can flag all but the
+/// highest-quality fragment of each key. Stops with `Interrupted` once
+/// `cancel` fires.
+fn sort_alignments<R: Read>(
+    input: R,
+    mut writer: Box<dyn sam::AlignmentWriter>,
+    work_dir: &Path,
+    buffer_records: usize,
+    mark_duplicates: bool,
+    cancel: &CancellationToken,
+) -> Result<AlignmentCounts> {
+    let mut input = BufReader::new(input);
+    let header = read_sorted_sam_header(&mut input)?;
+    let mut reader = sam::Reader::new(input);
+    
+    let mut counts = AlignmentCounts::default();
+    let mut fragment_scores: HashMap<(DuplicateKey, String), u64> = HashMap::new();
+    let mut chunk_paths = Vec::new();
+    let mut buffer = Vec::with_capacity(buffer_records);
+    
+    for result in reader.records(&header) {
+        // A killed aligner leaves a truncated stream, so check before parsing
+        if cancel.is_cancelled() {
+            return Err(anyhow!(PipelineError::Interrupted));
+        }
+        let record = result.context("Failed to parse aligner SAM output")?;
+        if !record.flags().is_unmapped() {
+            counts.mapped += 1;
+        }
+        if mark_duplicates {
+            if let Some(key) = duplicate_key(&record) {
+                *fragment_scores.entry((key, read_name(&record))).or_insert(0) += duplicate_score(&record);
+            }
+        }
+        buffer.push((sort_key(&record, counts.records), record));
+        counts.records += 1;
+        
+        if buffer.len() >= buffer_records {
+            let path = work_dir.join(format!("sort_chunk_{:04}.bam", chunk_paths.len()));
+            write_sorted_chunk(&header, &mut buffer, &path)?;
+            chunk_paths.push(path);
+        }
+    }
+    if !buffer.is_empty() || chunk_paths.is_empty() {
+        let path = work_dir.join(format!("sort_chunk_{:04}.bam", chunk_paths.len()));
+        write_sorted_chunk(&header, &mut buffer, &path)?;
+        chunk_paths.push(path);
+    }
+    debug!("Sorted {} records in {} chunks", counts.records, chunk_paths.len());
+    
+    // Keep the best-scoring fragment per key, the first read name on ties
+    let mut best: HashMap<DuplicateKey, (u64, String)> = HashMap::new();
+    for ((key, name), score) in fragment_scores {
+        let entry = best.entry(key).or_insert((score, name.clone()));
+        if score > entry.0 || (score == entry.0 && name < entry.1) {
+            *entry = (score, name);
+        }
+    }
+    
+    // Merge the sorted chunks; ties go to the earlier chunk, keeping input order
+    let mut readers = Vec::with_capacity(chunk_paths.len());
+    for path in &chunk_paths {
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("Failed to open sort chunk: {:?}", path))?;
+        let mut chunk_reader = bam::Reader::new(file);
+        chunk_reader.read_header()?;
+        readers.push(chunk_reader);
+    }
+    let mut heads: Vec<Option<sam::alignment::Record>> = Vec::with_capacity(readers.len());
+    let mut heap = BinaryHeap::new();
+    for (i, chunk_reader) in readers.iter_mut().enumerate() {
+        let mut record = sam::alignment::Record::default();
+        if chunk_reader.read_record(&header, &mut record)? > 0 {
+            heap.push(Reverse((sort_key(&record, 0), i)));
+            heads.push(Some(record));
+        } else {
+            heads.push(None);
+        }
+    }
+    
+    writer.write_alignment_header(&header)?;
+    
+    while let Some(Reverse((_, i))) = heap.pop() {
+        if cancel.is_cancelled() {
+            return Err(anyhow!(PipelineError::Interrupted));
+        }
+        let mut record = heads[i].take().expect("heap entry without a record");
+        if let Some(key) = duplicate_key(&record) {
+            if best.get(&key).is_some_and(|(_, name)| *name != read_name(&record)) {
+                record.flags_mut().insert(sam::record::Flags::DUPLICATE);
+                counts.duplicates += 1;
+            }
+        }
+        writer.write_alignment_record(&header, &record)?;
+        
+        let mut next = sam::alignment::Record::default();
+        if readers[i].read_record(&header, &mut next)? > 0 {
+            heap.push(Reverse((sort_key(&next, 0), i)));
+            heads[i] = Some(next);
+        }
+    }
+    writer.finish(&header)?;
+    
+    for path in chunk_paths {
+        std::fs::remove_file(&path).ok();
+    }
+    Ok(counts)
+}
+
+/// Create the sorted output file: BAM, or CRAM compressed against `reference`
+fn alignment_writer(path: &Path, format: AlignmentFormat, reference: &Path) -> Result<Box<dyn sam::AlignmentWriter>> {
+    let file = std::fs::File::create(path)
+        .with_context(|| format!("Failed to create {} file: {:?}", format.extension().to_uppercase(), path))?;
+    Ok(match format {
+        AlignmentFormat::Bam => Box::new(bam::Writer::new(file)),
+        AlignmentFormat::Cram => Box::new(
+            cram::writer::Builder::default()
+                .set_reference_sequence_repository(reference_repository(reference)?)
+                .build_with_writer(file),
+        ),
+    })
+}
+
+/// Run the alignment step
+async fn run_alignment(
+    reads: &Path,
+    mate: Option<&Path>,
+    sample: Option<&str>,
+    reference: &Path,
+    out_bam: &Path,
+    settings: AlignSettings,
+    context: &PipelineContext,
+) -> Result<AlignmentCounts> {
+    info!("Aligning reads from {:?} to reference {:?}", reads, reference);
+    let lease = context.reserve("align", settings.threads, settings.memory_gb).await?;
+    let step_start = Instant::now();
+    
+    // Determine aligner to use
+    let aligner = Aligner::from_name(settings.aligner.as_deref().unwrap_or("bwa"))?;
+    let format = settings.output_format.unwrap_or_default();
+    if format == AlignmentFormat::Cram && out_bam.extension().is_none_or(|ext| ext != "cram") {
+        return Err(anyhow!(PipelineError::ConfigError(format!(
+            "CRAM output needs a .cram path, got {:?}",
+            out_bam
+        ))));
+    }
+    let mark_duplicates = settings.mark_duplicates.unwrap_or(false);
+    let buffer_records = settings.sort_buffer_records.unwrap_or(1_000_000).max(1);
+    aligner.check_index(reference)?;
+    let mut command = aligner.command(reads, mate, sample, reference, lease.threads);
+    debug!("Running {:?}", command);
+    
+    // Create progress spinner; the read total is not known in advance
+    let progress = context.progress.add(
+        ProgressBar::new_spinner().with_style(
+            ProgressStyle::default_spinner()
+                .template("{spinner:.green} [{elapsed_precise}] {msg}")
+                .unwrap(),
+        ),
+    );
+    progress.enable_steady_tick(time::Duration::from_millis(100));
+    progress.set_message(format!("Aligning reads with {}...", aligner.program()));
+    
+    let mut child = command
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Failed to launch aligner: {}", aligner.program()))?;
+    
+    // Report progress from the aligner's log, keeping its tail for errors
+    let stderr = child.stderr.take().expect("aligner stderr is piped");
+    let stderr_progress = progress.clone();
+    let stderr_task = tokio::spawn(async move {
+        let mut lines = AsyncBufReader::new(stderr).lines();
+        let mut tail = VecDeque::with_capacity(20);
+        let mut processed = 0u64;
+        while let Ok(Some(line)) = lines.next_line().await {
+            if let Some(reads) = aligner.reads_in_progress_line(&line) {
+                processed += reads;
+                stderr_progress.set_message(format!("Aligned {} reads", processed));
+            }
+            if tail.len() == 20 {
+                tail.pop_front();
+            }
+            tail.push_back(line);
+        }
+        tail
+    });
+    
+    // Sort the SAM stream into BAM or CRAM on a blocking thread as it arrives
+    let stdout = child.stdout.take().expect("aligner stdout is piped");
+    let (sort_out, sort_reference) = (out_bam.to_path_buf(), reference.to_path_buf());
+    let work_dir = context.temp_dir.path().to_path_buf();
+    let (pool, cancel, span) = (lease.pool.clone(), context.cancel.clone(), Span::current());
+    let sort_task = tokio::task::spawn_blocking(move || {
+        let _span = span.enter();
+        pool.install(|| {
+            let input = SyncIoBridge::new(stdout);
+            let writer = alignment_writer(&sort_out, format, &sort_reference)?;
+            sort_alignments(input, writer, &work_dir, buffer_records, mark_duplicates, &cancel)
+        })
+    });
+    
+    // On cancellation kill the aligner, then let the sort wind down before
+    // the caller removes the partial BAM
+    let status = tokio::select! {
+        status = child.wait() => Some(status?),
+        _ = context.cancel.cancelled() => {
+            warn!("Stopping {}", aligner.program());
+            child.kill().await.ok();
+            None
+        }
+    };
+    let sorted = sort_task.await?;
+    let tail = stderr_task.await?;
+    let Some(status) = status else {
+        return Err(anyhow!(PipelineError::Interrupted));
+    };
+    let counts = match sorted {
+        // A failed sort closes the pipe, which kills the aligner with SIGPIPE
+        Err(e) if status.success() || status.code().is_none() => return Err(e),
+        _ if !status.success() => {
+            return Err(anyhow!(PipelineError::CommandFailed(format!(
+                "{} exited with {}: {}",
+                aligner.program(),
+                status,
+                tail.into_iter().collect::<Vec<_>>().join("\n")
+            ))));
+        }
+        sorted => sorted?,
+    };
+    if mark_duplicates {
+        info!("Marked {} of {} mapped records as duplicates", counts.duplicates, counts.mapped);
+    }
+    
+    // Index the BAM or CRAM file
+    progress.set_message("Indexing alignments...");
+    index_alignments(out_bam, &context.cancel).await?;
+    
+    progress.finish_with_message(format!("Alignment completed: {:?}", out_bam));
+    context.profile.lock().unwrap().record("align", step_start, counts.records, &[reads, reference], &[out_bam]);
+    
+    info!("Alignment completed successfully: {} of {} records mapped", counts.mapped, counts.records);
+    Ok(counts)
+}
+
+/// Run an external tool to completion, failing with the tail of its stderr.
+/// The tool is killed if `cancel` fires first.
+async fn run_command(mut command: tokio::process::Command, program: &str, cancel: &CancellationToken) -> Result<()> {
+    debug!("Running {:?}", command);
+    let mut child = command
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Failed to launch {}", program))?;
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+    let stderr_task = tokio::spawn(async move {
+        let mut buffer = Vec::new();
+        stderr.read_to_end(&mut buffer).await.ok();
+        buffer
+    });
+    
+    let status = tokio::select! {
+        status = child.wait() => status?,
+        _ = cancel.cancelled() => {
+            warn!("Stopping {}", program);
+            child.kill().await.ok();
+            return Err(anyhow!(PipelineError::Interrupted));
+        }
+    };
+    if !status.success() {
+        return Err(command_failed(program, status, &stderr_task.await?));
+    }
+    Ok(())
+}
+
+/// Run an external tool, passing each line of its stdout to `on_line`.
+/// Fails like `run_command`, and stops the tool if `on_line` fails.
+async fn run_command_lines<F>(
+    mut command: tokio::process::Command,
+    program: &str,
+    cancel: &CancellationToken,
+    mut on_line: F,
+) -> Result<()>
+where
+    F: FnMut(&str) -> Result<()>,
+{
+    debug!("Running {:?}", command);
+    let mut child = command
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Failed to launch {}", program))?;
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+    let stderr_task = tokio::spawn(async move {
+        let mut buffer = Vec::new();
+        stderr.read_to_end(&mut buffer).await.ok();
+        buffer
+    });
+    
+    let mut lines = AsyncBufReader::new(child.stdout.take().expect("stdout is piped")).lines();
+    loop {
+        tokio::select! {
+            line = lines.next_line() => match line? {
+                Some(line) => on_line(&line)?,
+                None => break,
+            },
+            _ = cancel.cancelled() => {
+                warn!("Stopping {}", program);
+                child.kill().await.ok();
+                return Err(anyhow!(PipelineError::Interrupted));
+            }
+        }
+    }
+    let status = child.wait().await?;
+    if !status.success() {
+        return Err(command_failed(program, status, &stderr_task.await?));
+    }
+    Ok(())
+}
+
+/// Error for a tool that exited unsuccessfully, with the tail of its stderr
+fn command_failed(program: &str, status: std::process::ExitStatus, stderr: &[u8]) -> anyhow::Error {
+    let stderr = String::from_utf8_lossy(stderr);
+    let tail: Vec<&str> = stderr.trim().lines().rev().take(20).collect();
+    anyhow!(PipelineError::CommandFailed(format!(
+        "{} exited with {}: {}",
+        program,
+        status,
+        tail.into_iter().rev().collect::<Vec<_>>().join("\n")
+    )))
+}
+
+/// Index a coordinate-sorted BAM or CRAM file with samtools
+async fn index_alignments(bam: &Path, cancel: &CancellationToken) -> Result<()> {
+    run_command(index_command(bam), "samtools index", cancel).await
+}
+
+fn index_command(bam: &Path) -> tokio::process::Command {
+    let mut command = tokio::process::Command::new("samtools");
+    command.arg("index").arg(bam);
+    command
+}
+
+/// Path of the index `samtools index` writes: `.crai` next to a CRAM file,
+/// `.bai` next to a BAM file
+fn alignment_index_path(bam: &Path) -> PathBuf {
+    if bam.extension().is_some_and(|ext| ext == "cram") {
+        bam.with_extension("cram.crai")
+    } else {
+        bam.with_extension("bam.bai")
+    }
+}
+
+/// FASTA repository over the reference, which CRAM encodes and decodes
+/// records against. The `.fai` index is created next to the reference when
+/// missing.
+fn reference_repository(reference: &Path) -> Result<fasta::Repository> {
+    let mut fai = reference.as_os_str().to_owned();
+    fai.push(".fai");
+    let fai = PathBuf::from(fai);
+    if !fai.exists() {
+        info!("Indexing reference {:?}", reference);
+        let index = fasta::index(reference).with_context(|| format!("Failed to index reference: {:?}", reference))?;
+        let file = std::fs::File::create(&fai)
+            .with_context(|| format!("Failed to create reference index: {:?}", fai))?;
+        fasta::fai::Writer::new(file).write_index(&index)?;
+    }
+    let reader = fasta::indexed_reader::Builder::default()
+        .build_from_path(reference)
+        .with_context(|| format!("Failed to open reference: {:?}", reference))?;
+    Ok(fasta::Repository::new(fasta::repository::adapters::IndexedReader::new(reader)))
+}
+
+/// Variant callers the pipeline can drive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Caller {
+    Bcftools,
+    Freebayes,
+    DeepVariant,
+}
+
+impl Caller {
+    fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "bcftools" => Ok(Caller::Bcftools),
+            "freebayes" => Ok(Caller::Freebayes),
+            "deepvariant" => Ok(Caller::DeepVariant),
+            _ => Err(anyhow!(PipelineError::ConfigError(format!(
+                "Unsupported variant caller: {} (expected bcftools, freebayes or deepvariant)",
+                name
+            )))),
+        }
+    }
+    
+    fn name(self) -> &'static str {
+        match self {
+            Caller::Bcftools => "bcftools",
+            Caller::Freebayes => "freebayes",
+            Caller::DeepVariant => "deepvariant",
+        }
+    }
+}
+
+/// Target regions of a panel, read from a BED file and merged per chromosome
+#[derive(Debug, Clone, Default)]
+struct Targets {
+    /// Sorted, non-overlapping half-open 0-based intervals per chromosome
+    regions: BTreeMap<String, Vec<(u64, u64)>>,
+}
+
+impl Targets {
+    /// Read `chrom<TAB>start<TAB>end` lines of a plain or gzipped BED file,
+    /// skipping comment, `track` and `browser` lines
+    fn load(path: &Path) -> Result<Self> {
+        let mut regions: BTreeMap<String, Vec<(u64, u64)>> = BTreeMap::new();
+        for (i, line) in open_maybe_gzip(path)?.lines().enumerate() {
+            let line = line.with_context(|| format!("Failed to read targets: {:?}", path))?;
+            if line.trim().is_empty() || line.starts_with('#') || line.starts_with("track") || line.starts_with("browser") {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            let interval = match fields.as_slice() {
+                [chrom, start, end, ..] => start.parse::<u64>().ok().zip(end.parse::<u64>().ok()).map(|(start, end)| (*chrom, start, end)),
+                _ => None,
+            };
+            let Some((chrom, start, end)) = interval.filter(|(_, start, end)| start < end) else {
+                return Err(anyhow!(PipelineError::InvalidInput(format!(
+                    "Invalid BED line {} in {:?}: {}",
+                    i + 1,
+                    path,
+                    line
+                ))));
+            };
+            regions.entry(chrom.to_string()).or_default().push((start, end));
+        }
+        if regions.is_empty() {
+            return Err(anyhow!(PipelineError::InvalidInput(format!("No target regions in {:?}", path))));
+        }
+        
+        for intervals in regions.values_mut() {
+            intervals.sort_unstable();
+            let mut merged: Vec<(u64, u64)> = Vec::with_capacity(intervals.len());
+            for &(start, end) in intervals.iter() {
+                match merged.last_mut() {
+                    Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                    _ => merged.push((start, end)),
+                }
+            }
+            *intervals = merged;
+        }
+        Ok(Self { regions })
+    }
+    
+    /// Index, among its chromosome's targets, of the target holding 0-based `pos`
+    fn locate(&self, chrom: &str, pos: u64) -> Option<usize> {
+        let intervals = self.regions.get(chrom)?;
+        let i = intervals.partition_point(|&(_, end)| end <= pos);
+        intervals.get(i).filter(|&&(start, _)| start <= pos).map(|_| i)
+    }
+    
+    /// Whether the `len` bases starting at 1-based `pos` overlap a target
+    fn overlaps(&self, chrom: &str, pos: u64, len: u64) -> bool {
+        let Some(intervals) = self.regions.get(chrom) else {
+            return false;
+        };
+        let start = pos.saturating_sub(1);
+        let end = start + len.max(1);
+        let i = intervals.partition_point(|&(_, target_end)| target_end <= start);
+        intervals.get(i).is_some_and(|&(target_start, _)| target_start < end)
+    }
+    
+    fn total_bases(&self) -> u64 {
+        self.regions.values().flatten().map(|(start, end)| end - start).sum()
+    }
+    
+    /// Write the merged targets as BED
+    fn write_bed(&self, path: &Path) -> Result<()> {
+        let file = std::fs::File::create(path).with_context(|| format!("Failed to create BED file: {:?}", path))?;
+        let mut writer = BufWriter::new(file);
+        for (chrom, intervals) in &self.regions {
+            for (start, end) in intervals {
+                writeln!(writer, "{}\t{}\t{}", chrom, start, end)?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Where a caller looks: the configured regions, else the merged panel
+/// targets written as BED, else the whole genome
+#[derive(Debug, Clone, Copy)]
+enum CallScope<'a> {
+    Genome,
+    Regions(&'a [String]),
+    Targets(&'a Path),
+}
+
+/// Call variants with bcftools: mpileup to a temporary BCF, then the
+/// multiallelic caller, keeping variant sites only
+async fn call_with_bcftools(
+    bam: &Path,
+    reference: &Path,
+    scope: CallScope<'_>,
+    threads: usize,
+    work_dir: &Path,
+    raw_vcf: &Path,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let pileup = work_dir.join("pileup.bcf");
+    let [mpileup, call] = bcftools_commands(bam, reference, scope, threads, &pileup, raw_vcf);
+    run_command(mpileup, "bcftools mpileup", cancel).await?;
+    run_command(call, "bcftools call", cancel).await?;
+    fs::remove_file(&pileup).await.ok();
+    Ok(())
+}
+
+/// `bcftools mpileup` into `pileup`, then `bcftools call` from it
+fn bcftools_commands(
+    bam: &Path,
+    reference: &Path,
+    scope: CallScope<'_>,
+    threads: usize,
+    pileup: &Path,
+    raw_vcf: &Path,
+) -> [tokio::process::Command; 2] {
+    let mut mpileup = tokio::process::Command::new("bcftools");
+    mpileup
+        .arg("mpileup")
+        .arg("--threads").arg(threads.to_string())
+        .arg("-a").arg("FORMAT/AD,FORMAT/DP")
+        .arg("-f").arg(reference)
+        .arg("-Ob")
+        .arg("-o").arg(pileup);
+    match scope {
+        CallScope::Genome => {}
+        CallScope::Regions(regions) => {
+            mpileup.arg("-r").arg(regions.join(","));
+        }
+        CallScope::Targets(bed) => {
+            mpileup.arg("-R").arg(bed);
+        }
+    }
+    mpileup.arg(bam);
+    
+    let mut call = tokio::process::Command::new("bcftools");
+    call.arg("call")
+        .arg("--threads").arg(threads.to_string())
+        .arg("-mv")
+        .arg("-Ov")
+        .arg("-o").arg(raw_vcf)
+        .arg(pileup);
+    [mpileup, call]
+}
+
+/// Call variants with freebayes; regions are passed as a BED targets file
+async fn call_with_freebayes(
+    bam: &Path,
+    reference: &Path,
+    scope: CallScope<'_>,
+    min_depth: usize,
+    work_dir: &Path,
+    raw_vcf: &Path,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let targets = match scope {
+        CallScope::Genome => None,
+        CallScope::Regions(regions) => {
+            let targets = work_dir.join("targets.bed");
+            let mut bed = String::new();
+            for region in regions {
+                bed.push_str(&region_to_bed(region)?);
+                bed.push('\n');
+            }
+            fs::write(&targets, bed).await?;
+            Some(targets)
+        }
+        CallScope::Targets(bed) => Some(bed.to_path_buf()),
+    };
+    let command = freebayes_command(bam, reference, targets.as_deref(), min_depth, raw_vcf);
+    run_command(command, "freebayes", cancel).await
+}
+
+fn freebayes_command(
+    bam: &Path,
+    reference: &Path,
+    targets: Option<&Path>,
+    min_depth: usize,
+    raw_vcf: &Path,
+) -> tokio::process::Command {
+    let mut command = tokio::process::Command::new("freebayes");
+    command
+        .arg("-f").arg(reference)
+        .arg("--min-coverage").arg(min_depth.to_string())
+        .arg("--vcf").arg(raw_vcf);
+    if let Some(targets) = targets {
+        command.arg("--targets").arg(targets);
+    }
+    command.arg(bam);
+    command
+}
+
+/// Call variants with DeepVariant in a container. The directories holding
+/// the inputs and the output are mounted at the same paths inside it.
+async fn call_with_deepvariant(
+    bam: &Path,
+    reference: &Path,
+    scope: CallScope<'_>,
+    settings: &CallSettings,
+    threads: usize,
+    raw_vcf: &Path,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let runtime = settings.container_runtime.as_deref().unwrap_or("docker");
+    let absolute = |path: &Path| {
+        std::fs::canonicalize(path).with_context(|| format!("Failed to resolve path: {:?}", path))
+    };
+    let bam = absolute(bam)?;
+    let reference = absolute(reference)?;
+    let output_dir = absolute(raw_vcf.parent().unwrap_or(Path::new(".")))?;
+    let raw_vcf = output_dir.join(raw_vcf.file_name().unwrap_or_default());
+    let targets = match scope {
+        CallScope::Targets(bed) => Some(absolute(bed)?),
+        _ => None,
+    };
+    let scope = match &targets {
+        Some(bed) => CallScope::Targets(bed),
+        None => scope,
+    };
+    
+    // Named, so the container can be stopped if the run is cancelled;
+    // killing the docker or podman client leaves it running
+    let container = format!("genomic_pipeline_{}_{}", std::process::id(), unix_time());
+    let command = deepvariant_command(&bam, &reference, scope, settings, threads, &raw_vcf, &container)?;
+    let result = run_command(command, "deepvariant", cancel).await;
+    if cancel.is_cancelled() && matches!(runtime, "docker" | "podman") {
+        let mut kill = tokio::process::Command::new(runtime);
+        kill.arg("kill").arg(&container);
+        if let Err(e) = run_command(kill, runtime, &CancellationToken::new()).await {
+            warn!("Failed to stop container {}: {:#}", container, e);
+        }
+    }
+    result
+}
+
+/// The container command running DeepVariant; every path must be absolute
+fn deepvariant_command(
+    bam: &Path,
+    reference: &Path,
+    scope: CallScope<'_>,
+    settings: &CallSettings,
+    threads: usize,
+    raw_vcf: &Path,
+    container: &str,
+) -> Result<tokio::process::Command> {
+    let runtime = settings.container_runtime.as_deref().unwrap_or("docker");
+    let image = settings.deepvariant_image.as_deref().unwrap_or("google/deepvariant:1.6.1");
+    let model = settings.deepvariant_model.as_deref().unwrap_or("WGS");
+    let targets = match scope {
+        CallScope::Targets(bed) => Some(bed),
+        _ => None,
+    };
+    let mut mounts: Vec<PathBuf> = [bam, reference, raw_vcf]
+        .into_iter()
+        .chain(targets)
+        .filter_map(|path| path.parent().map(Path::to_path_buf))
+        .collect();
+    mounts.sort();
+    mounts.dedup();
+    
+    let mut command = tokio::process::Command::new(runtime);
+    match runtime {
+        "docker" | "podman" => {
+            command.arg("run").arg("--rm").arg("--name").arg(container);
+            if let Some(gb) = settings.memory_gb {
+                command.arg(format!("--memory={}m", gb_to_mib(gb)));
+            }
+            for dir in &mounts {
+                command.arg("-v").arg(format!("{}:{}", dir.display(), dir.display()));
+            }
+            command.arg(image);
+        }
+        "singularity" | "apptainer" => {
+            command.arg("exec");
+            for dir in &mounts {
+                command.arg("-B").arg(dir);
+            }
+            command.arg(format!("docker://{}", image));
+        }
+        _ => {
+            return Err(anyhow!(PipelineError::ConfigError(format!(
+                "Unsupported container runtime: {} (expected docker, podman, singularity or apptainer)",
+                runtime
+            ))));
+        }
+    }
+    command
+        .arg("/opt/deepvariant/bin/run_deepvariant")
+        .arg(format!("--model_type={}", model))
+        .arg(format!("--ref={}", reference.display()))
+        .arg(format!("--reads={}", bam.display()))
+        .arg(format!("--output_vcf={}", raw_vcf.display()))
+        .arg(format!("--num_shards={}", threads));
+    match scope {
+        CallScope::Genome => {}
+        CallScope::Regions(regions) => {
+            command.arg(format!("--regions={}", regions.join(" ")));
+        }
+        CallScope::Targets(bed) => {
+            command.arg(format!("--regions={}", bed.display()));
+        }
+    }
+    Ok(command)
+}
+
+/// Convert a `chr:start-end` region (1-based, inclusive) to a BED line
+fn region_to_bed(region: &str) -> Result<String> {
+    let invalid = || anyhow!(PipelineError::InvalidInput(format!("Invalid region: {}", region)));
+    let Some((chrom, range)) = region.rsplit_once(':') else {
+        return Ok(format!("{}\t0\t{}", region, i64::MAX));
+    };
+    let (start, end) = range.split_once('-').ok_or_else(invalid)?;
+    let start: u64 = start.replace(',', "").parse().map_err(|_| invalid())?;
+    let end: u64 = end.replace(',', "").parse().map_err(|_| invalid())?;
+    if start == 0 || end < start {
+        return Err(invalid());
+    }
+    Ok(format!("{}\t{}\t{}", chrom, start - 1, end))
+}
+
+/// Read depth of a VCF record: INFO/DP, else FORMAT/DP of the first sample
+fn record_depth(fields: &[&str]) -> Option<usize> {
+    let info_dp = fields
+        .get(7)?
+        .split(';')
+        .find_map(|entry| entry.strip_prefix("DP="));
+    if let Some(dp) = info_dp {
+        return dp.parse().ok();
+    }
+    let keys = fields.get(8)?.split(':');
+    let values = fields.get(9)?.split(':');
+    keys.zip(values).find(|(key, _)| *key == "DP").and_then(|(_, dp)| dp.parse().ok())
+}
+
+/// Copy a VCF, dropping records with a known depth below `min_depth` and,
+/// given panel targets, records whose REF allele lies outside them. Returns
+/// the number of records kept.
+fn filter_calls(input: &Path, output: &Path, min_depth: usize, targets: Option<&Targets>) -> Result<usize> {
+    let reader = BufReader::new(
+        std::fs::File::open(input).with_context(|| format!("Failed to open VCF: {:?}", input))?,
+    );
+    let mut writer = BufWriter::new(
+        std::fs::File::create(output).with_context(|| format!("Failed to create VCF: {:?}", output))?,
+    );
+    let mut kept = 0;
+    let mut dropped = 0;
+    let mut off_target = 0;
+    
+    for line in reader.lines() {
+        let line = line?;
+        if !line.starts_with('#') {
+            let fields: Vec<&str> = line.split('\t').collect();
+            if let (Some(targets), [chrom, pos, _, ref_allele, ..]) = (targets, fields.as_slice()) {
+                let pos = pos.parse().unwrap_or(0);
+                if !targets.overlaps(chrom, pos, ref_allele.len() as u64) {
+                    off_target += 1;
+                    continue;
+                }
+            }
+            if record_depth(&fields).is_some_and(|depth| depth < min_depth) {
+                dropped += 1;
+                continue;
+            }
+            kept += 1;
+        }
+        writeln!(writer, "{}", line)?;
+    }
+    writer.flush()?;
+    
+    debug!("Depth filter kept {} records, dropped {} below DP {}", kept, dropped, min_depth);
+    if targets.is_some() {
+        debug!("Dropped {} records outside the targets", off_target);
+    }
+    Ok(kept)
+}
+
+/// Run the variant calling step
+async fn run_calling(
+    bam: &Path,
+    reference: &Path,
+    out_vcf: &Path,
+    settings: CallSettings,
+    format: OutputFormat,
+    context: &PipelineContext,
+) -> Result<usize> {
+    info!("Calling variants from {:?} using reference {:?}", bam, reference);
+    
+    if !matches!(format, OutputFormat::Vcf | OutputFormat::Bcf) {
+        return Err(anyhow!(PipelineError::ConfigError(format!(
+            "Unsupported output format for variant calling: {:?}",
+            format
+        ))));
+    }
+    let caller = Caller::from_name(settings.caller.as_deref().unwrap_or("bcftools"))?;
+    let lease = context.reserve("call", settings.threads, settings.memory_gb).await?;
+    let step_start = Instant::now();
+    
+    // Create progress spinner; callers do not report progress
+    let progress = context.progress.add(
+        ProgressBar::new_spinner().with_style(
+            ProgressStyle::default_spinner()
+                .template("{spinner:.green} [{elapsed_precise}] {msg}")
+                .unwrap(),
+        ),
+    );
+    progress.enable_steady_tick(time::Duration::from_millis(100));
+    
+    // Set up variant calling parameters
+    let min_depth = settings.min_depth.unwrap_or(10);
+    debug!("Minimum depth for variant calling: {}", min_depth);
+    
+    // Check if the alignments are indexed
+    let index_path = alignment_index_path(bam);
+    if !index_path.exists() {
+        warn!("Alignment index not found, creating index for {:?}", bam);
+        progress.set_message("Indexing alignments...");
+        index_alignments(bam, &context.cancel).await?;
+    }
+    
+    // Call into an intermediate VCF, then filter and convert it
+    let work_dir = context.temp_dir.path();
+    let raw_vcf = work_dir.join("variants.raw.vcf");
+    
+    // Process regions if specified, else the panel targets
+    let regions = settings.regions.clone().unwrap_or_default();
+    let targets = settings.targets.as_deref().map(Targets::load).transpose()?;
+    let targets_bed = work_dir.join("call_targets.bed");
+    let scope = if !regions.is_empty() {
+        debug!("Processing {} specific regions", regions.len());
+        CallScope::Regions(&regions)
+    } else if let Some(targets) = &targets {
+        debug!("Processing {} bp of panel targets", targets.total_bases());
+        targets.write_bed(&targets_bed)?;
+        CallScope::Targets(&targets_bed)
+    } else {
+        debug!("Processing entire genome");
+        CallScope::Genome
+    };
+    match scope {
+        CallScope::Genome => {}
+        CallScope::Regions(regions) => {
+            Span::current().record("region", regions.join(",").as_str());
+        }
+        CallScope::Targets(_) => {
+            let bed = settings.targets.as_deref().unwrap_or(&targets_bed);
+            Span::current().record("region", bed.to_string_lossy().as_ref());
+        }
+    }
+    let threads = lease.threads;
+    progress.set_message(format!("Calling variants with {}...", caller.name()));
+    match caller {
+        Caller::Bcftools => {
+            call_with_bcftools(bam, reference, scope, threads, work_dir, &raw_vcf, &context.cancel).await?
+        }
+        Caller::Freebayes => {
+            call_with_freebayes(bam, reference, scope, min_depth, work_dir, &raw_vcf, &context.cancel).await?
+        }
+        Caller::DeepVariant => {
+            call_with_deepvariant(bam, reference, scope, &settings, threads, &raw_vcf, &context.cancel).await?
+        }
+    }
+    
+    progress.set_message(if targets.is_some() { "Filtering by depth and targets..." } else { "Filtering by depth..." });
+    let filtered_vcf = match format {
+        OutputFormat::Bcf => work_dir.join("variants.vcf"),
+        _ => out_vcf.to_path_buf(),
+    };
+    let variants = filter_calls(&raw_vcf, &filtered_vcf, min_depth, targets.as_ref())?;
+    
+    // Convert to the requested output format
+    if let OutputFormat::Bcf = format {
+        progress.set_message("Converting to BCF...");
+        run_command(bcf_command(&filtered_vcf, out_vcf), "bcftools view", &context.cancel).await?;
+    }
+    
+    progress.finish_with_message(format!("Variant calling completed: {:?}", out_vcf));
+    context.profile.lock().unwrap().record("call", step_start, variants as u64, &[bam, reference], &[out_vcf]);
+    
+    info!("Variant calling completed successfully: {} variants", variants);
+    Ok(variants)
+}
+
+/// `bcftools view` converting a VCF to BCF
+fn bcf_command(vcf: &Path, out_bcf: &Path) -> tokio::process::Command {
+    let mut command = tokio::process::Command::new("bcftools");
+    command.arg("view").arg("-Ob").arg("-o").arg(out_bcf).arg(vcf);
+    command
+}
+
+/// One annotated alternate allele
+#[derive(Debug, Clone, Serialize)]
+struct VariantAnnotation {
+    chrom: String,
+    pos: u64,
+    ref_allele: String,
+    alt_allele: String,
+    genes: String,
+    biotypes: String,
+    effect: Option<String>,
+    distance: Option<u64>,
+    /// `<database>_af` allele frequency, one entry per frequency database
+    #[serde(flatten)]
+    frequencies: BTreeMap<String, Option<f64>>,
+}
+
+/// Column name for a frequency database: its file name up to the first dot
+fn database_column(path: &Path) -> String {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("database");
+    format!("{}_af", name.split('.').next().unwrap_or(name))
+}
+
+/// Place a variant relative to the nearest gene within `max_distance` bp:
+/// `genic` inside a gene, `upstream`/`downstream` by the gene's strand,
+/// otherwise `intergenic`. Returns the effect and the distance to that gene.
+fn predict_effect(
+    gene_trees: &HashMap<String, Lapper<GeneInfo>>,
+    chrom: &str,
+    pos: u64,
+    max_distance: u64,
+) -> (&'static str, Option<u64>) {
+    let Some(tree) = gene_trees.get(chrom) else {
+        return ("intergenic", None);
+    };
+    let pos = pos as usize;
+    let window = max_distance as usize;
+    
+    // Gene intervals are half-open, `stop` being one past the gene end
+    let nearest = tree
+        .find(pos.saturating_sub(window), pos + window + 1)
+        .map(|iv| {
+            let distance = if pos < iv.start {
+                iv.start - pos
+            } else if pos >= iv.stop {
+                pos - (iv.stop - 1)
+            } else {
+                0
+            };
+            (distance, iv)
+        })
+        .min_by_key(|(distance, _)| *distance);
+    
+    match nearest {
+        Some((0, _)) => ("genic", Some(0)),
+        Some((distance, iv)) => {
+            let before_gene = pos < iv.start;
+            let effect = if before_gene == (iv.val.strand != "-") { "upstream" } else { "downstream" };
+            (effect, Some(distance as u64))
+        }
+        None => ("intergenic", None),
+    }
+}
+
+/// Annotate every alternate allele of a VCF (plain or gzip) with the genes it
+/// overlaps, optional distance-based effects and frequencies from each database
+fn annotate_variants(
+    vcf: &Path,
+    gff: &Path,
+    settings: &AnnotateSettings,
+    progress: &ProgressBar,
+    cancel: &CancellationToken,
+) -> Result<Vec<VariantAnnotation>> {
+    progress.set_message("Loading gene annotations...");
+    let gene_trees = build_gene_tree(gff)?;
+    
+    // Frequency databases are bgzipped `chrom pos ref alt af` tables
+    let mut databases = Vec::new();
+    for db in settings.databases.iter().flatten() {
+        if !db.exists() {
+            warn!("Annotation database not found: {:?}", db);
+            continue;
+        }
+        progress.set_message(format!("Loading {:?}...", db));
+        databases.push((database_column(db), load_freqs(db, None, None)?));
+    }
+    debug!("Using {} annotation databases", databases.len());
+    
+    let targets = settings.targets.as_deref().map(Targets::load).transpose()?;
+    let mut off_target = 0;
+    
+    let predict_effects = settings.effects.unwrap_or(false);
+    let max_distance = settings.max_distance.unwrap_or(5_000) as u64;
+    if predict_effects {
+        debug!("Including effect predictions within {} bp of genes", max_distance);
+    }
+    
+    progress.set_message("Processing variants...");
+    let mut annotations = Vec::new();
+    for line in open_maybe_gzip(vcf)?.lines() {
+        if cancel.is_cancelled() {
+            return Err(anyhow!(PipelineError::Interrupted));
+        }
+        let line = line.with_context(|| format!("Failed to read VCF: {:?}", vcf))?;
+        if line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 5 {
+            warn!("Skipping malformed VCF line: {}", line);
+            continue;
+        }
+        let chrom = fields[0];
+        let Ok(pos) = fields[1].parse::<u64>() else {
+            warn!("Skipping VCF line with invalid position: {}", fields[1]);
+            continue;
+        };
+        let ref_allele = fields[3];
+        if targets.as_ref().is_some_and(|targets| !targets.overlaps(chrom, pos, ref_allele.len() as u64)) {
+            off_target += 1;
+            continue;
+        }
+        
+        let end = pos + ref_allele.len().max(1) as u64 - 1;
+        let overlaps = gene_overlaps(&gene_trees, chrom, pos, end);
+        let genes = overlaps.iter().map(|(g, _)| g.gene_name.as_str()).collect::<Vec<_>>().join(",");
+        let biotypes = overlaps.iter().map(|(g, _)| g.biotype.as_str()).collect::<Vec<_>>().join(",");
+        let (effect, distance) = if predict_effects {
+            let (effect, distance) = predict_effect(&gene_trees, chrom, pos, max_distance);
+            (Some(effect.to_string()), distance)
+        } else {
+            (None, None)
+        };
+        
+        for alt in fields[4].split(',').filter(|alt| *alt != ".") {
+            let frequencies = databases
+                .iter()
+                .map(|(column, table)| (column.clone(), table.get(chrom, pos, alt)))
+                .collect();
+            annotations.push(VariantAnnotation {
+                chrom: chrom.to_string(),
+                pos,
+                ref_allele: ref_allele.to_string(),
+                alt_allele: alt.to_string(),
+                genes: genes.clone(),
+                biotypes: biotypes.clone(),
+                effect: effect.clone(),
+                distance,
+                frequencies,
+            });
+        }
+        progress.inc(1);
+    }
+    if targets.is_some() {
+        debug!("Skipped {} variants outside the targets", off_target);
+    }
+    
+    Ok(annotations)
+}
+
+/// Write annotations as TSV, JSON or Parquet
+fn write_annotations(annotations: &[VariantAnnotation], output: &Path, format: OutputFormat) -> Result<()> {
+    let file = std::fs::File::create(output)
+        .with_context(|| format!("Failed to create annotation output: {:?}", output))?;
+    let columns: Vec<String> = annotations
+        .first()
+        .map(|a| a.frequencies.keys().cloned().collect())
+        .unwrap_or_default();
+    
+    match format {
+        OutputFormat::Tsv => {
+            let mut writer = BufWriter::new(file);
+            write!(writer, "chrom\tpos\tref\talt\tgenes\tbiotypes\teffect\tdistance")?;
+            for column in &columns {
+                write!(writer, "\t{}", column)?;
+            }
+            writeln!(writer)?;
+            
+            let or_dot = |s: &str| if s.is_empty() { ".".to_string() } else { s.to_string() };
+            for a in annotations {
+                write!(
+                    writer,
+                    "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                    a.chrom,
+                    a.pos,
+                    a.ref_allele,
+                    a.alt_allele,
+                    or_dot(&a.genes),
+                    or_dot(&a.biotypes),
+                    a.effect.as_deref().unwrap_or("."),
+                    a.distance.map_or_else(|| ".".to_string(), |d| d.to_string())
+                )?;
+                for af in a.frequencies.values() {
+                    write!(writer, "\t{}", af.map_or_else(|| ".".to_string(), |af| af.to_string()))?;
+                }
+                writeln!(writer)?;
+            }
+            writer.flush()?;
+        }
+        OutputFormat::Json => {
+            let mut writer = BufWriter::new(file);
+            serde_json::to_writer_pretty(&mut writer, annotations)?;
+            writer.flush()?;
+        }
+        OutputFormat::Parquet => {
+            let mut df = DataFrame::new(vec![
+                Series::new("chrom", annotations.iter().map(|a| a.chrom.clone()).collect::<Vec<_>>()),
+                Series::new("pos", annotations.iter().map(|a| a.pos).collect::<Vec<_>>()),
+                Series::new("ref_allele", annotations.iter().map(|a| a.ref_allele.clone()).collect::<Vec<_>>()),
+                Series::new("alt_allele", annotations.iter().map(|a| a.alt_allele.clone()).collect::<Vec<_>>()),
+                Series::new("genes", annotations.iter().map(|a| a.genes.clone()).collect::<Vec<_>>()),
+                Series::new("biotypes", annotations.iter().map(|a| a.biotypes.clone()).collect::<Vec<_>>()),
+                Series::new("effect", annotations.iter().map(|a| a.effect.clone()).collect::<Vec<_>>()),
+                Series::new("distance", annotations.iter().map(|a| a.distance).collect::<Vec<_>>()),
+            ])?;
+            for column in &columns {
+                let values: Vec<Option<f64>> = annotations.iter().map(|a| a.frequencies[column]).collect();
+                df.with_column(Series::new(column, values))?;
+            }
+            ParquetWriter::new(file).finish(&mut df)?;
+        }
+        _ => bail!("Unsupported output format for annotation: {:?}", format),
+    }
+    
+    Ok(())
+}
+
+/// Run the annotation step. Returns the number of annotated alleles.
+async fn run_annotation(
+    vcf: &Path,
+    gff: &Path,
+    output: &Path,
+    settings: AnnotateSettings,
+    format: OutputFormat,
+    context: &PipelineContext,
+) -> Result<usize> {
+    info!("Annotating variants from {:?} using annotations {:?}", vcf, gff);
+    
+    if !matches!(format, OutputFormat::Tsv | OutputFormat::Json | OutputFormat::Parquet) {
+        return Err(anyhow!(PipelineError::ConfigError(format!(
+            "Unsupported output format for annotation: {:?}",
+            format
+        ))));
+    }
+    
+    let lease = context.reserve("annotate", settings.threads, settings.memory_gb).await?;
+    let step_start = Instant::now();
+    
+    // Create progress bar
+    let progress = context.progress.add(
+        ProgressBar::new_spinner().with_style(
+            ProgressStyle::default_spinner()
+                .template("{spinner:.green} [{elapsed_precise}] {pos} variants {msg}")
+                .unwrap(),
+        ),
+    );
+    progress.enable_steady_tick(time::Duration::from_millis(100));
+    
+    // Gene trees and frequency tables are built synchronously
+    let annotations = {
+        let (vcf, gff, output, progress, pool, cancel) = (
+            vcf.to_path_buf(),
+            gff.to_path_buf(),
+            output.to_path_buf(),
+            progress.clone(),
+            lease.pool.clone(),
+            context.cancel.clone(),
+        );
+        let span = Span::current();
+        tokio::task::spawn_blocking(move || -> Result<Vec<VariantAnnotation>> {
+            let _span = span.enter();
+            pool.install(|| {
+                let annotations = annotate_variants(&vcf, &gff, &settings, &progress, &cancel)?;
+                progress.set_message("Writing results...");
+                write_annotations(&annotations, &output, format)?;
+                Ok(annotations)
+            })
+        })
+        .await
+        .context("Annotation task panicked")??
+    };
+    let annotated = annotations.len();
+    
+    progress.finish_with_message(format!("Annotation completed: {:?}", output));
+    context.profile.lock().unwrap().record("annotate", step_start, annotated as u64, &[vcf, gff], &[output]);
+    
+    info!("Annotation completed successfully: {} alleles", annotated);
+    Ok(annotated)
+}
+
+/// Future driving one workflow step; resolves to the number of records it produced
+type StepFuture = Pin<Box<dyn Future<Output = Result<u64>> + Send>>;
+
+/// A node of the workflow DAG. Edges are implied by paths: a step depends on
+/// every step that lists one of its inputs among its outputs.
+struct Step {
+    name: &'static str,
+    inputs: Vec<PathBuf>,
+    outputs: Vec<PathBuf>,
+    /// Serialized settings; a change forces the step to rerun
+    params: String,
+    /// What the step runs, as shown by `--dry-run`
+    commands: Vec<String>,
+    run: StepFuture,
+}
+
+impl Step {
+    fn new<F>(name: &'static str, inputs: Vec<PathBuf>, outputs: Vec<PathBuf>, params: String, run: F) -> Self
+    where
+        F: Future<Output = Result<u64>> + Send + 'static,
+    {
+        Self {
+            name,
+            inputs,
+            outputs,
+            params,
+            commands: Vec::new(),
+            run: Box::pin(run),
+        }
+    }
+    
+    fn with_commands(mut self, commands: Vec<String>) -> Self {
+        self.commands = commands;
+        self
+    }
+}
+
+/// What the step cache remembers about a completed step
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedStep {
+    params: String,
+    /// SHA-256 of each input when the step last ran
+    inputs: BTreeMap<PathBuf, String>,
+    outputs: Vec<PathBuf>,
+    /// SHA-256 of each output, reported in `run.json` when the step is skipped
+    #[serde(default)]
+    output_hashes: BTreeMap<PathBuf, String>,
+    records: u64,
+}
+
+/// Small DAG executor. Steps whose producers have finished run concurrently
+/// on the tokio runtime, and completed steps are recorded in a JSON cache so
+/// that a rerun skips them while their outputs are up to date.
+struct Workflow {
+    /// Sample the steps belong to, recorded on their log spans
+    sample: String,
+    steps: Vec<Step>,
+    cache_path: PathBuf,
+    context: PipelineContext,
+}
+
+impl Workflow {
+    fn new(sample: &str, cache_path: PathBuf, context: &PipelineContext) -> Self {
+        Self {
+            sample: sample.to_string(),
+            steps: Vec::new(),
+            cache_path,
+            context: context.clone(),
+        }
+    }
+    
+    fn add(&mut self, step: Step) {
+        self.steps.push(step);
+    }
+    
+    /// Steps each step waits on, resolved from the paths each step reads and writes
+    fn dependencies(&self) -> Result<Vec<HashSet<usize>>> {
+        let mut producers = HashMap::new();
+        for (i, step) in self.steps.iter().enumerate() {
+            for output in &step.outputs {
+                if let Some(other) = producers.insert(output.clone(), i) {
+                    return Err(anyhow!(PipelineError::ConfigError(format!(
+                        "Steps '{}' and '{}' both write {:?}",
+                        self.steps[other].name, step.name, output
+                    ))));
+                }
+            }
+        }
+        Ok(self
+            .steps
+            .iter()
+            .map(|step| step.inputs.iter().filter_map(|input| producers.get(input).copied()).collect())
+            .collect())
+    }
+    
+    /// Run every step once the steps producing its inputs have finished.
+    /// Returns each step's record count, read from the cache for skipped steps.
+    async fn run(self) -> Result<HashMap<&'static str, u64>> {
+        if self.context.dry_run {
+            return self.plan().await;
+        }
+        let mut waiting_on = self.dependencies()?;
+        
+        let mut cache = load_step_cache(&self.cache_path);
+        let names: Vec<&'static str> = self.steps.iter().map(|step| step.name).collect();
+        let mut pending: Vec<Option<Step>> = self.steps.into_iter().map(Some).collect();
+        let mut running = JoinSet::new();
+        let mut records = HashMap::new();
+        
+        while records.len() < names.len() {
+            for (i, slot) in pending.iter_mut().enumerate() {
+                if waiting_on[i].is_empty() {
+                    if let Some(step) = slot.take() {
+                        let cached = cache.get(step.name).cloned();
+                        let context = self.context.clone();
+                        let span = step_span(Some(&self.sample), step.name);
+                        running.spawn(async move { (i, run_step(step, cached, &context).await) }.instrument(span));
+                    }
+                }
+            }
+            
+            // Nothing running while steps remain means they wait on each other
+            let Some(joined) = running.join_next().await else {
+                let stuck: Vec<_> = names.iter().filter(|name| !records.contains_key(*name)).collect();
+                return Err(anyhow!(PipelineError::ConfigError(format!(
+                    "Workflow has a dependency cycle between steps {:?}",
+                    stuck
+                ))));
+            };
+            let (i, result) = joined.context("Workflow step panicked")?;
+            let (entry, ran) = match result {
+                Ok(outcome) => outcome,
+                Err(e) => {
+                    // Cancelled steps clean up after themselves; wait for them
+                    if self.context.cancel.is_cancelled() {
+                        while running.join_next().await.is_some() {}
+                    }
+                    return Err(e.context(format!("Step '{}' failed", names[i])));
+                }
+            };
+            
+            records.insert(names[i], entry.records);
+            if ran {
+                cache.insert(names[i].to_string(), entry);
+                save_step_cache(&self.cache_path, &cache)?;
+            }
+            for deps in &mut waiting_on {
+                deps.remove(&i);
+            }
+        }
+        
+        Ok(records)
+    }
+    
+    /// Print the steps in dependency order instead of running them, marking
+    /// those the cache would skip. A step downstream of one that runs is
+    /// planned to run too. Returns zero records for every step.
+    async fn plan(self) -> Result<HashMap<&'static str, u64>> {
+        let waiting_on = self.dependencies()?;
+        let cache = load_step_cache(&self.cache_path);
+        let mut runs: Vec<Option<bool>> = vec![None; self.steps.len()];
+        let mut planned = Vec::with_capacity(self.steps.len());
+        while planned.len() < self.steps.len() {
+            let before = planned.len();
+            for (i, step) in self.steps.iter().enumerate() {
+                let ready = waiting_on[i].iter().all(|&dep| runs[dep].is_some());
+                if runs[i].is_some() || !ready {
+                    continue;
+                }
+                let upstream_runs = waiting_on[i].iter().any(|&dep| runs[dep] == Some(true));
+                let skipped = match cache.get(step.name) {
+                    Some(cached) if !upstream_runs => cache_hit(step, cached).await?.is_some(),
+                    _ => false,
+                };
+                runs[i] = Some(!skipped);
+                planned.push(PlannedStep {
+                    name: step.name,
+                    status: if skipped { "cached" } else { "run" },
+                    inputs: step.inputs.clone(),
+                    outputs: step.outputs.clone(),
+                    commands: step.commands.clone(),
+                });
+            }
+            if planned.len() == before {
+                return Err(anyhow!(PipelineError::ConfigError(
+                    "Workflow has a dependency cycle".to_string()
+                )));
+            }
+        }
+        print_plan(Some(&self.sample), &planned);
+        Ok(self.steps.iter().map(|step| (step.name, 0)).collect())
+    }
+}
+
+/// Why a step's cache entry lets it be skipped, if it does: the settings,
+/// input paths and outputs match, every output exists, and the outputs are
+/// newer than the inputs or the inputs hash to the same content as last time
+async fn cache_hit(step: &Step, cached: &CachedStep) -> Result<Option<&'static str>> {
+    let matches = cached.params == step.params
+        && cached.inputs.len() == step.inputs.len()
+        && step.inputs.iter().all(|input| cached.inputs.contains_key(input))
+        && cached.outputs == step.outputs
+        && step.outputs.iter().all(|output| output.exists());
+    if !matches {
+        Ok(None)
+    } else if outputs_newer_than_inputs(&step.inputs, &step.outputs) {
+        Ok(Some("is up to date"))
+    } else if hash_files(&step.inputs).await? == cached.inputs {
+        Ok(Some("inputs are unchanged"))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Run a step unless its cache entry shows it is up to date. Returns the
+/// step's cache entry and whether it ran; the outcome is recorded in
+/// `run.json`.
+async fn run_step(step: Step, cached: Option<CachedStep>, context: &PipelineContext) -> Result<(CachedStep, bool)> {
+    if let Some(cached) = cached {
+        if let Some(reason) = cache_hit(&step, &cached).await? {
+            info!("Step '{}' {}, skipping", step.name, reason);
+            let mut hashes = cached.inputs.clone();
+            hashes.extend(cached.output_hashes.clone());
+            context.record_step(step.name, StepStatus::Skipped, None, &step.inputs, &step.outputs, &hashes);
+            return Ok((cached, false));
+        }
+    }
+    
+    info!("Running step '{}'", step.name);
+    let started = Instant::now();
+    let records = match step.run.await {
+        Ok(records) => records,
+        Err(e) => {
+            let status = context.failure_status();
+            context.record_step(step.name, status, Some((started, Instant::now())), &step.inputs, &step.outputs, &BTreeMap::new());
+            return Err(e);
+        }
+    };
+    let finished = Instant::now();
+    let inputs = hash_files(&step.inputs).await?;
+    let output_hashes = hash_files(&step.outputs).await?;
+    let hashes = inputs.iter().chain(&output_hashes).map(|(path, hash)| (path.clone(), hash.clone())).collect();
+    context.record_step(step.name, StepStatus::Completed, Some((started, finished)), &step.inputs, &step.outputs, &hashes);
+    Ok((
+        CachedStep {
+            params: step.params,
+            inputs,
+            outputs: step.outputs,
+            output_hashes,
+            records,
+        },
+        true,
+    ))
+}
+
+/// Whether the oldest output was modified no earlier than the newest input
+fn outputs_newer_than_inputs(inputs: &[PathBuf], outputs: &[PathBuf]) -> bool {
+    let modified = |path: &PathBuf| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    let (Some(inputs), Some(outputs)) = (
+        inputs.iter().map(modified).collect::<Option<Vec<_>>>(),
+        outputs.iter().map(modified).collect::<Option<Vec<_>>>(),
+    ) else {
+        return false;
+    };
+    match (inputs.into_iter().max(), outputs.into_iter().min()) {
+        (Some(newest_input), Some(oldest_output)) => oldest_output >= newest_input,
+        (None, Some(_)) => true,
+        _ => false,
+    }
+}
+
+/// SHA-256 of each file, computed off the async runtime
+async fn hash_files(paths: &[PathBuf]) -> Result<BTreeMap<PathBuf, String>> {
+    let paths = paths.to_vec();
+    tokio::task::spawn_blocking(move || {
+        paths
+            .into_iter()
+            .map(|path| -> Result<(PathBuf, String)> {
+                let mut file = std::fs::File::open(&path)
+                    .with_context(|| format!("Failed to open {:?} for hashing", path))?;
+                let mut hasher = Sha256::new();
+                std::io::copy(&mut file, &mut hasher)?;
+                Ok((path, format!("{:x}", hasher.finalize())))
+            })
+            .collect()
+    })
+    .await
+    .context("Hashing task panicked")?
+}
+
+/// Load the step cache; a missing or unreadable cache reruns every step
+fn load_step_cache(path: &Path) -> BTreeMap<String, CachedStep> {
+    let Ok(json) = std::fs::read_to_string(path) else {
+        return BTreeMap::new();
+    };
+    serde_json::from_str(&json).unwrap_or_else(|e| {
+        warn!("Ignoring unreadable step cache {:?}: {}", path, e);
+        BTreeMap::new()
+    })
+}
+
+/// Write the step cache through a temporary file so it is never left half-written
+fn save_step_cache(path: &Path, cache: &BTreeMap<String, CachedStep>) -> Result<()> {
+    let partial = path.with_extension("json.partial");
+    std::fs::write(&partial, serde_json::to_string_pretty(cache)?)
+        .with_context(|| format!("Failed to write step cache: {:?}", partial))?;
+    std::fs::rename(&partial, path).with_context(|| format!("Failed to write step cache: {:?}", path))?;
+    Ok(())
+}
+
+/// A step as `--dry-run` shows it
+struct PlannedStep {
+    name: &'static str,
+    /// `run`, or `cached` when the step cache would skip it
+    status: &'static str,
+    inputs: Vec<PathBuf>,
+    outputs: Vec<PathBuf>,
+    /// External commands (`$`) and in-process work (`#`)
+    commands: Vec<String>,
+}
+
+impl PlannedStep {
+    fn run(name: &'static str, inputs: Vec<PathBuf>, outputs: Vec<PathBuf>, commands: Vec<String>) -> Self {
+        Self {
+            name,
+            status: "run",
+            inputs,
+            outputs,
+            commands,
+        }
+    }
+}
+
+/// Print a plan in one write, so the plans of concurrent cohort samples do
+/// not interleave
+fn print_plan(sample: Option<&str>, steps: &[PlannedStep]) {
+    let paths = |paths: &[PathBuf]| paths.iter().map(|path| path.display().to_string()).collect::<Vec<_>>().join(", ");
+    let mut plan = match sample {
+        Some(sample) => format!("\n========== Plan: {} ==========\n", sample),
+        None => "\n========== Plan ==========\n".to_string(),
+    };
+    for step in steps {
+        plan.push_str(&format!("[{}] {}\n", step.status, step.name));
+        plan.push_str(&format!("    inputs:  {}\n", paths(&step.inputs)));
+        plan.push_str(&format!("    outputs: {}\n", paths(&step.outputs)));
+        for command in &step.commands {
+            plan.push_str(&format!("    {}\n", command));
+        }
+    }
+    let to_run = steps.iter().filter(|step| step.status == "run").count();
+    plan.push_str(&format!("{} of {} steps to run\n", to_run, steps.len()));
+    print!("{}", plan);
+}
+
+/// A command as a shell line, quoting the arguments that need it
+fn command_line(command: &tokio::process::Command) -> String {
+    let command = command.as_std();
+    let words: Vec<String> = std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|word| {
+            let word = word.to_string_lossy();
+            let plain = !word.is_empty()
+                && word.chars().all(|c| c.is_ascii_alphanumeric() || "-_./:=,@+%".contains(c));
+            if plain {
+                word.into_owned()
+            } else {
+                format!("'{}'", word.replace('\'', "'\\''"))
+            }
+        })
+        .collect();
+    format!("$ {}", words.join(" "))
+}
+
+/// Planned commands of the align step: the aligner piped into the
+/// in-process sort, then indexing
+fn align_plan(
+    reads: &Path,
+    mate: Option<&Path>,
+    sample: Option<&str>,
+    reference: &Path,
+    out_bam: &Path,
+    settings: &AlignSettings,
+    threads: usize,
+) -> Result<Vec<String>> {
+    let aligner = Aligner::from_name(settings.aligner.as_deref().unwrap_or("bwa"))?;
+    let format = settings.output_format.unwrap_or_default();
+    let sort = if settings.mark_duplicates.unwrap_or(false) { "sort and mark duplicates" } else { "sort" };
+    Ok(vec![
+        format!(
+            "{} | # {} into {} ({})",
+            command_line(&aligner.command(reads, mate, sample, reference, threads)),
+            sort,
+            out_bam.display(),
+            format.extension().to_uppercase()
+        ),
+        command_line(&index_command(out_bam)),
+    ])
+}
+
+/// Planned commands of the call step, as `run_calling` would run them
+fn call_plan(
+    bam: &Path,
+    reference: &Path,
+    out_vcf: &Path,
+    settings: &CallSettings,
+    format: OutputFormat,
+    threads: usize,
+    work_dir: &Path,
+) -> Result<Vec<String>> {
+    let caller = Caller::from_name(settings.caller.as_deref().unwrap_or("bcftools"))?;
+    let min_depth = settings.min_depth.unwrap_or(10);
+    let raw_vcf = std::path::absolute(work_dir.join("variants.raw.vcf"))?;
+    let regions = settings.regions.clone().unwrap_or_default();
+    let targets_bed = std::path::absolute(work_dir.join("call_targets.bed"))?;
+    let scope = if !regions.is_empty() {
+        CallScope::Regions(&regions)
+    } else if settings.targets.is_some() {
+        CallScope::Targets(&targets_bed)
+    } else {
+        CallScope::Genome
+    };
+    
+    // The pipeline's align step indexes the BAM before calling
+    let mut plan = Vec::new();
+    if bam.exists() && !alignment_index_path(bam).exists() {
+        plan.push(command_line(&index_command(bam)));
+    }
+    let commands = match caller {
+        Caller::Bcftools => {
+            Vec::from(bcftools_commands(bam, reference, scope, threads, &work_dir.join("pileup.bcf"), &raw_vcf))
+        }
+        Caller::Freebayes => {
+            let targets = match scope {
+                CallScope::Genome => None,
+                CallScope::Regions(_) => Some(work_dir.join("targets.bed")),
+                CallScope::Targets(bed) => Some(bed.to_path_buf()),
+            };
+            vec![freebayes_command(bam, reference, targets.as_deref(), min_depth, &raw_vcf)]
+        }
+        Caller::DeepVariant => {
+            let (bam, reference) = (std::path::absolute(bam)?, std::path::absolute(reference)?);
+            let container = "genomic_pipeline_<pid>_<time>";
+            vec![deepvariant_command(&bam, &reference, scope, settings, threads, &raw_vcf, container)?]
+        }
+    };
+    plan.extend(commands.iter().map(command_line));
+    plan.push(match &settings.targets {
+        Some(targets) => format!("# drop calls below depth {} or outside {}", min_depth, targets.display()),
+        None => format!("# drop calls below depth {}", min_depth),
+    });
+    if let OutputFormat::Bcf = format {
+        plan.push(command_line(&bcf_command(&work_dir.join("variants.vcf"), out_vcf)));
+    }
+    Ok(plan)
+}
+
+/// Planned commands of a workflow's call step, writing VCF
+fn call_step_plan(
+    bam: &Path,
+    reference: &Path,
+    out_vcf: &Path,
+    settings: &CallSettings,
+    context: &PipelineContext,
+) -> Result<Vec<String>> {
+    let threads = context.planned_threads(settings.threads);
+    call_plan(bam, reference, out_vcf, settings, OutputFormat::Vcf, threads, context.temp_dir.path())
+}
+
+/// Planned in-process work of the annotate step
+fn annotate_plan(vcf: &Path, gff: &Path, output: &Path, settings: &AnnotateSettings) -> Vec<String> {
+    let mut plan = vec![format!("# annotate {} with the genes in {}", vcf.display(), gff.display())];
+    for db in settings.databases.iter().flatten() {
+        plan.push(if db.exists() {
+            format!("# add {} from {}", database_column(db), db.display())
+        } else {
+            format!("# skip missing database {}", db.display())
+        });
+    }
+    if settings.effects.unwrap_or(false) {
+        plan.push(format!("# predict effects within {} bp", settings.max_distance.unwrap_or(5000)));
+    }
+    if let Some(targets) = &settings.targets {
+        plan.push(format!("# skip variants outside {}", targets.display()));
+    }
+    plan.push(format!("# write {}", output.display()));
+    plan
+}
+
+/// Planned in-process work of the qc step
+fn qc_plan(fastqs: &[PathBuf], bam: Option<&Path>) -> Vec<String> {
+    let mut plan: Vec<String> = fastqs.iter().map(|fastq| format!("# FASTQ metrics of {}", fastq.display())).collect();
+    plan.extend(bam.map(|bam| format!("# alignment metrics of {}", bam.display())));
+    plan
+}
+
+/// Run the full pipeline for one sample, tagging its reads with the sample name
+#[allow(clippy::too_many_arguments)]
+async fn run_full_pipeline(
+    reads: &Path,
+    mate: Option<&Path>,
+    reference: &Path,
+    gff: &Path,
+    output_dir: &Path,
+    sample: &str,
+    keep_intermediate: bool,
+    context: &PipelineContext,
+) -> Result<PipelineStats> {
+    info!("Running full pipeline for sample: {}", sample);
+    
+    // Create output paths
+    let alignment_format = context.settings.align.output_format.unwrap_or_default();
+    let bam_path = output_dir.join(format!("{}.{}", sample, alignment_format.extension()));
+    let vcf_path = output_dir.join(format!("{}.vcf", sample));
+    let annotation_path = output_dir.join(format!("{}.annotated.tsv", sample));
+    let mut fastqs = vec![reads.to_path_buf()];
+    fastqs.extend(mate.map(Path::to_path_buf));
+    
+    let mut workflow = Workflow::new(sample, output_dir.join(format!(".{}.pipeline.steps.json", sample)), context);
+    
+    // Step 1: Alignment
+    let settings = context.settings.align.clone();
+    let params = serde_json::to_string(&settings)?;
+    let (reads, mate, sample_name, reference_path, bam, ctx) = (
+        reads.to_path_buf(),
+        mate.map(Path::to_path_buf),
+        sample.to_string(),
+        reference.to_path_buf(),
+        bam_path.clone(),
+        context.clone(),
+    );
+    let mut inputs = vec![reads.clone(), reference_path.clone()];
+    inputs.extend(mate.clone());
+    let threads = context.planned_threads(settings.threads);
+    let commands = align_plan(&reads, mate.as_deref(), Some(sample), reference, &bam, &settings, threads)?;
+    workflow.add(Step::new(
+        "align",
+        inputs,
+        vec![bam.clone(), alignment_index_path(&bam)],
+        params,
+        async move {
+            let counts = run_alignment(
+                &reads,
+                mate.as_deref(),
+                Some(&sample_name),
+                &reference_path,
+                &bam,
+                settings,
+                &ctx,
+            )
+            .await?;
+            Ok(counts.mapped)
+        },
+    ).with_commands(commands));
+    
+    // Step 2: Quality control of the reads and alignments
+    let settings = context.settings.qc.clone();
+    let params = serde_json::to_string(&settings)?;
+    let mut inputs = fastqs.clone();
+    inputs.push(bam_path.clone());
+    if alignment_format == AlignmentFormat::Cram {
+        inputs.push(reference.to_path_buf());
+    }
+    let outputs = qc_report_paths(output_dir, sample);
+    let (bam, reference_path, sample_name, report_paths, ctx) = (
+        bam_path.clone(),
+        reference.to_path_buf(),
+        sample.to_string(),
+        outputs.clone(),
+        context.clone(),
+    );
+    let commands = qc_plan(&fastqs, Some(&bam));
+    workflow.add(Step::new("qc", inputs, outputs, params, async move {
+        let report =
+            run_qc(&fastqs, Some(&bam), Some(&reference_path), &sample_name, &report_paths, settings, &ctx).await?;
+        Ok(report.fastq.iter().map(|metrics| metrics.reads).sum())
+    }).with_commands(commands));
+    
+    // Step 3: Variant Calling
+    let settings = context.settings.call.clone();
+    let params = serde_json::to_string(&settings)?;
+    let mut inputs = vec![bam_path.clone(), reference.to_path_buf()];
+    inputs.extend(settings.targets.clone());
+    let (bam, reference_path, vcf, ctx) =
+        (bam_path.clone(), reference.to_path_buf(), vcf_path.clone(), context.clone());
+    let commands = call_step_plan(&bam, &reference_path, &vcf, &settings, context)?;
+    workflow.add(Step::new(
+        "call",
+        inputs,
+        vec![vcf.clone()],
+        params,
+        async move {
+            let variants = run_calling(&bam, &reference_path, &vcf, settings, OutputFormat::Vcf, &ctx).await?;
+            Ok(variants as u64)
+        },
+    ).with_commands(commands));
+    
+    // Step 4: Annotation
+    workflow.add(annotation_step(&vcf_path, gff, &annotation_path, context)?);
+    
+    // Step 5: Coverage completeness of the panel targets
+    let coverage_path = output_dir.join(format!("{}.target_coverage.json", sample));
+    if let Some(targets) = context.settings.call.targets.clone() {
+        let settings = context.settings.qc.clone();
+        let params = serde_json::to_string(&(settings.min_target_depth, settings.min_target_completeness))?;
+        let mut inputs = vec![bam_path.clone(), targets.clone()];
+        let reference = (alignment_format == AlignmentFormat::Cram).then(|| reference.to_path_buf());
+        inputs.extend(reference.clone());
+        let (bam, output, ctx) = (bam_path.clone(), coverage_path.clone(), context.clone());
+        let bed = context.temp_dir.path().join("coverage_targets.bed");
+        let commands = vec![
+            format!("# merge the targets in {} into {}", targets.display(), bed.display()),
+            command_line(&depth_command(&bam, reference.as_deref(), &bed)),
+        ];
+        workflow.add(Step::new("coverage", inputs, vec![coverage_path.clone()], params, async move {
+            let coverage = run_target_coverage(&bam, reference.as_deref(), &targets, &output, &settings, &ctx).await?;
+            Ok(coverage.covered_bases)
+        }).with_commands(commands));
+    }
+    
+    let records = workflow.run().await?;
+    if context.dry_run {
+        return Ok(PipelineStats::default());
+    }
+    
+    // The coverage report is read back, since a cached step does not rerun
+    let target_coverage = if records.contains_key("coverage") {
+        let json = fs::read_to_string(&coverage_path)
+            .await
+            .with_context(|| format!("Failed to read target coverage: {:?}", coverage_path))?;
+        Some(serde_json::from_str::<TargetCoverage>(&json)?)
+    } else {
+        None
+    };
+    
+    // Clean up intermediate files if requested
+    if !keep_intermediate {
+        info!("Cleaning up intermediate files");
+        // In a real implementation, we'd delete intermediates here
+    }
+    
+    // Calculate elapsed time
+    let elapsed = context.start_time.elapsed();
+    
+    info!(
+        "Full pipeline completed successfully for {} in {:.2} seconds",
+        sample,
+        elapsed.as_secs_f64()
+    );
+    
+    Ok(PipelineStats {
+        aligned_reads: records["align"] as usize,
+        variants_called: records["call"] as usize,
+        variants_annotated: records["annotate"] as usize,
+        target_coverage,
+        elapsed_seconds: elapsed.as_secs_f64(),
+    })
+}
+
+/// Workflow step annotating `vcf` into a TSV with the configured databases
+fn annotation_step(vcf: &Path, gff: &Path, output: &Path, context: &PipelineContext) -> Result<Step> {
+    let settings = context.settings.annotate.clone();
+    let params = serde_json::to_string(&settings)?;
+    let mut inputs = vec![vcf.to_path_buf(), gff.to_path_buf()];
+    inputs.extend(settings.databases.iter().flatten().filter(|db| db.exists()).cloned());
+    inputs.extend(settings.targets.clone());
+    let commands = annotate_plan(vcf, gff, output, &settings);
+    let (vcf, gff, output, ctx) = (vcf.to_path_buf(), gff.to_path_buf(), output.to_path_buf(), context.clone());
+    Ok(Step::new("annotate", inputs, vec![output.clone()], params, async move {
+        let annotated = run_annotation(&vcf, &gff, &output, settings, OutputFormat::Tsv, &ctx).await?;
+        Ok(annotated as u64)
+    })
+    .with_commands(commands))
+}
+
+/// Leading bases of an adapter searched for in reads, as in FastQC
+const ADAPTER_PROBE_LEN: usize = 12;
+
+/// Adapters searched for when `[qc] adapters` is unset: the Illumina
+/// universal adapter and the Nextera transposase sequence
+const DEFAULT_ADAPTERS: [&str; 2] = ["AGATCGGAAGAGC", "CTGTCTCTTATACACATCT"];
+
+/// Longest template length counted towards the insert size distribution
+const MAX_INSERT_SIZE: usize = 10_000;
+
+/// Quality metrics of one FASTQ file
+#[derive(Debug, Clone, Serialize)]
+struct FastqMetrics {
+    path: PathBuf,
+    reads: u64,
+    bases: u64,
+    mean_length: f64,
+    gc_percent: f64,
+    mean_quality: f64,
+    /// Mean Phred quality at each read position
+    per_base_quality: Vec<f64>,
+    /// For each adapter, the percentage of reads containing it at or before each position
+    adapter_content: BTreeMap<String, Vec<f64>>,
+}
+
+/// Insert sizes of properly paired, non-duplicate fragments
+#[derive(Debug, Clone, Serialize)]
+struct InsertSizeMetrics {
+    pairs: u64,
+    mean: f64,
+    median: u64,
+    std_dev: f64,
+}
+
+/// Mean depth over one reference sequence
+#[derive(Debug, Clone, Serialize)]
+struct ContigCoverage {
+    name: String,
+    length: u64,
+    mean_depth: f64,
+}
+
+/// Alignment metrics of a BAM file, counting primary alignments only.
+/// Coverage counts aligned bases of non-duplicate reads.
+#[derive(Debug, Clone, Serialize)]
+struct BamMetrics {
+    path: PathBuf,
+    reads: u64,
+    mapped: u64,
+    mapping_rate: f64,
+    paired: u64,
+    properly_paired: u64,
+    duplicates: u64,
+    insert_size: Option<InsertSizeMetrics>,
+    mean_coverage: f64,
+    coverage: Vec<ContigCoverage>,
+}
+
+/// QC report of one sample, written as `<sample>.qc.json` and `<sample>.qc.html`
+#[derive(Debug, Clone, Serialize)]
+struct QcReport {
+    sample: String,
+    fastq: Vec<FastqMetrics>,
+    bam: Option<BamMetrics>,
+}
+
+/// JSON and HTML report paths of a sample
+fn qc_report_paths(output_dir: &Path, sample: &str) -> Vec<PathBuf> {
+    vec![
+        output_dir.join(format!("{}.qc.json", sample)),
+        output_dir.join(format!("{}.qc.html", sample)),
+    ]
+}
+
+/// Run the QC step: compute metrics for each FASTQ file and the BAM or CRAM
+/// file in parallel, then write the JSON report to `outputs[0]` and the HTML report
+/// to `outputs[1]`
+async fn run_qc(
+    fastqs: &[PathBuf],
+    bam: Option<&Path>,
+    reference: Option<&Path>,
+    sample: &str,
+    outputs: &[PathBuf],
+    settings: QcSettings,
+    context: &PipelineContext,
+) -> Result<QcReport> {
+    info!("Computing QC metrics for sample: {}", sample);
+    
+    let adapters: Vec<String> = match settings.adapters {
+        Some(adapters) => adapters.iter().map(|adapter| adapter.to_ascii_uppercase()).collect(),
+        None => DEFAULT_ADAPTERS.iter().map(|adapter| adapter.to_string()).collect(),
+    };
+    if let Some(adapter) = adapters
+        .iter()
+        .find(|adapter| adapter.is_empty() || !adapter.bytes().all(|b| b"ACGTN".contains(&b)))
+    {
+        return Err(anyhow!(PipelineError::ConfigError(format!("Invalid adapter sequence: {:?}", adapter))));
+    }
+    
+    let files = fastqs.len() + usize::from(bam.is_some());
+    let lease = context.reserve("qc", Some(files), None).await?;
+    let step_start = Instant::now();
+    
+    // Create progress bar
+    let progress = context.progress.add(
+        ProgressBar::new_spinner().with_style(
+            ProgressStyle::default_spinner()
+                .template("{spinner:.green} [{elapsed_precise}] {pos} reads {msg}")
+                .unwrap(),
+        ),
+    );
+    progress.enable_steady_tick(time::Duration::from_millis(100));
+    progress.set_message(format!("QC of {}", sample));
+    
+    // FASTQ files and the BAM file are read synchronously, side by side
+    let report = {
+        let (fastqs, bam, reference, sample, progress, pool, cancel) = (
+            fastqs.to_vec(),
+            bam.map(Path::to_path_buf),
+            reference.map(Path::to_path_buf),
+            sample.to_string(),
+            progress.clone(),
+            lease.pool.clone(),
+            context.cancel.clone(),
+        );
+        let (max_reads, span) = (settings.max_reads, Span::current());
+        tokio::task::spawn_blocking(move || -> Result<QcReport> {
+            let _span = span.enter();
+            pool.install(|| {
+                let (fastq, bam) = rayon::join(
+                    || {
+                        fastqs
+                            .par_iter()
+                            .map(|path| fastq_metrics(path, &adapters, max_reads, &progress, &cancel))
+                            .collect::<Result<Vec<_>>>()
+                    },
+                    || {
+                        bam.as_deref()
+                            .map(|path| bam_metrics(path, reference.as_deref(), &progress, &cancel))
+                            .transpose()
+                    },
+                );
+                Ok(QcReport {
+                    sample,
+                    fastq: fastq?,
+                    bam: bam?,
+                })
+            })
+        })
+        .await
+        .context("QC task panicked")??
+    };
+    
+    std::fs::write(&outputs[0], serde_json::to_string_pretty(&report)?)
+        .with_context(|| format!("Failed to write QC report: {:?}", outputs[0]))?;
+    std::fs::write(&outputs[1], render_qc_html(&report))
+        .with_context(|| format!("Failed to write QC report: {:?}", outputs[1]))?;
+    
+    progress.finish_with_message(format!("QC completed: {:?}", outputs[1]));
+    let reads = report.fastq.iter().map(|metrics| metrics.reads).sum::<u64>()
+        + report.bam.as_ref().map_or(0, |metrics| metrics.reads);
+    let mut inputs: Vec<&Path> = fastqs.iter().map(PathBuf::as_path).collect();
+    inputs.extend(bam);
+    let outputs: Vec<&Path> = outputs.iter().map(PathBuf::as_path).collect();
+    context.profile.lock().unwrap().record("qc", step_start, reads, &inputs, &outputs);
+    
+    if let Some(metrics) = &report.bam {
+        info!(
+            "QC completed for {}: {:.1}% of reads mapped, mean coverage {:.1}x",
+            sample,
+            metrics.mapping_rate * 100.0,
+            metrics.mean_coverage
+        );
+    } else {
+        info!("QC completed for {}", sample);
+    }
+    Ok(report)
+}
+
+/// Read quality, GC content and adapter content of a plain or gzipped FASTQ
+/// file, from its first `max_reads` reads when set
+fn fastq_metrics(
+    path: &Path,
+    adapters: &[String],
+    max_reads: Option<u64>,
+    progress: &ProgressBar,
+    cancel: &CancellationToken,
+) -> Result<FastqMetrics> {
+    let mut reader = open_maybe_gzip(path)?;
+    let probes: Vec<&[u8]> = adapters
+        .iter()
+        .map(|adapter| &adapter.as_bytes()[..adapter.len().min(ADAPTER_PROBE_LEN)])
+        .collect();
+    
+    let mut quality_sums: Vec<u64> = Vec::new();
+    let mut quality_counts: Vec<u64> = Vec::new();
+    // Reads in which each adapter first occurs at each position
+    let mut adapter_starts: Vec<Vec<u64>> = vec![Vec::new(); adapters.len()];
+    let (mut reads, mut bases, mut gc, mut quality_total) = (0u64, 0u64, 0u64, 0u64);
+    let mut lines: [String; 4] = Default::default();
+    
+    while max_reads.is_none_or(|max| reads < max) {
+        for line in lines.iter_mut() {
+            line.clear();
+        }
+        if reader.read_line(&mut lines[0])? == 0 {
+            break;
+        }
+        for line in lines[1..].iter_mut() {
+            reader.read_line(line)?;
+        }
+        let sequence = lines[1].trim_end().as_bytes();
+        let quality = lines[3].trim_end().as_bytes();
+        if !lines[0].starts_with('@') || !lines[2].starts_with('+') || sequence.len() != quality.len() {
+            return Err(anyhow!(PipelineError::InvalidInput(format!(
+                "Malformed FASTQ record {} in {:?}",
+                reads + 1,
+                path
+            ))));
+        }
+        
+        if quality_sums.len() < quality.len() {
+            quality_sums.resize(quality.len(), 0);
+            quality_counts.resize(quality.len(), 0);
+        }
+        for (i, &score) in quality.iter().enumerate() {
+            let score = u64::from(score.saturating_sub(33));
+            quality_sums[i] += score;
+            quality_counts[i] += 1;
+            quality_total += score;
+        }
+        gc += sequence.iter().filter(|b| matches!(b, b'G' | b'C' | b'g' | b'c')).count() as u64;
+        bases += sequence.len() as u64;
+        for (starts, probe) in adapter_starts.iter_mut().zip(&probes) {
+            if let Some(pos) = sequence.windows(probe.len()).position(|window| window.eq_ignore_ascii_case(probe)) {
+                if starts.len() <= pos {
+                    starts.resize(pos + 1, 0);
+                }
+                starts[pos] += 1;
+            }
+        }
+        
+        reads += 1;
+        if reads % 100_000 == 0 {
+            progress.inc(100_000);
+            if cancel.is_cancelled() {
+                return Err(anyhow!(PipelineError::Interrupted));
+            }
+        }
+    }
+    progress.inc(reads % 100_000);
+    
+    let ratio = |numerator: u64, denominator: u64| {
+        if denominator > 0 {
+            numerator as f64 / denominator as f64
+        } else {
+            0.0
+        }
+    };
+    let read_length = quality_sums.len();
+    let adapter_content = adapters
+        .iter()
+        .zip(&adapter_starts)
+        .map(|(adapter, starts)| {
+            let mut cumulative = 0;
+            let percentages = (0..read_length)
+                .map(|pos| {
+                    cumulative += starts.get(pos).copied().unwrap_or(0);
+                    100.0 * ratio(cumulative, reads)
+                })
+                .collect();
+            (adapter.clone(), percentages)
+        })
+        .collect();
+    
+    Ok(FastqMetrics {
+        path: path.to_path_buf(),
+        reads,
+        bases,
+        mean_length: ratio(bases, reads),
+        gc_percent: 100.0 * ratio(gc, bases),
+        mean_quality: ratio(quality_total, bases),
+        per_base_quality: quality_sums.iter().zip(&quality_counts).map(|(&sum, &count)| ratio(sum, count)).collect(),
+        adapter_content,
+    })
+}
+
+/// Mapping, pairing, duplicate, insert size and coverage metrics of a BAM or
+/// CRAM file
+fn bam_metrics(
+    path: &Path,
+    reference: Option<&Path>,
+    progress: &ProgressBar,
+    cancel: &CancellationToken,
+) -> Result<BamMetrics> {
+    use sam::record::cigar::op::Kind;
+    
+    let mut aligned_bases: Vec<u64> = Vec::new();
+    let mut insert_sizes = vec![0u64; MAX_INSERT_SIZE + 1];
+    let (mut reads, mut mapped, mut paired, mut properly_paired, mut duplicates) = (0u64, 0u64, 0u64, 0u64, 0u64);
+    let mut seen = 0u64;
+    
+    let header = for_each_alignment(path, reference, |record| {
+        seen += 1;
+        if seen % 100_000 == 0 {
+            progress.inc(100_000);
+            if cancel.is_cancelled() {
+                return Err(anyhow!(PipelineError::Interrupted));
+            }
+        }
+        
+        let flags = record.flags();
+        if flags.is_secondary() || flags.is_supplementary() {
+            return Ok(());
+        }
+        reads += 1;
+        if flags.is_duplicate() {
+            duplicates += 1;
+        }
+        if flags.is_unmapped() {
+            return Ok(());
+        }
+        mapped += 1;
+        if flags.is_segmented() {
+            paired += 1;
+            if flags.is_properly_aligned() {
+                properly_paired += 1;
+                let template_length = record.template_length().unsigned_abs() as usize;
+                if flags.is_first_segment() && !flags.is_duplicate() && (1..=MAX_INSERT_SIZE).contains(&template_length) {
+                    insert_sizes[template_length] += 1;
+                }
+            }
+        }
+        if let (Some(id), false) = (record.reference_sequence_id(), flags.is_duplicate()) {
+            if aligned_bases.len() <= id {
+                aligned_bases.resize(id + 1, 0);
+            }
+            aligned_bases[id] += record
+                .cigar()
+                .iter()
+                .filter(|op| matches!(op.kind(), Kind::Match | Kind::SequenceMatch | Kind::SequenceMismatch))
+                .map(|op| op.len() as u64)
+                .sum::<u64>();
+        }
+        Ok(())
+    })?;
+    progress.inc(seen % 100_000);
+    
+    let coverage: Vec<ContigCoverage> = header
+        .reference_sequences()
+        .iter()
+        .enumerate()
+        .map(|(id, (name, reference_sequence))| {
+            let length = reference_sequence.length().get() as u64;
+            ContigCoverage {
+                name: name.to_string(),
+                length,
+                mean_depth: aligned_bases.get(id).copied().unwrap_or(0) as f64 / length as f64,
+            }
+        })
+        .collect();
+    let genome_length: u64 = coverage.iter().map(|contig| contig.length).sum();
+    
+    Ok(BamMetrics {
+        path: path.to_path_buf(),
+        reads,
+        mapped,
+        mapping_rate: if reads > 0 { mapped as f64 / reads as f64 } else { 0.0 },
+        paired,
+        properly_paired,
+        duplicates,
+        insert_size: insert_size_metrics(&insert_sizes),
+        mean_coverage: if genome_length > 0 {
+            aligned_bases.iter().sum::<u64>() as f64 / genome_length as f64
+        } else {
+            0.0
+        },
+        coverage,
+    })
 }
 
-/// Validate that input files exist
-async fn validate_files(files: &[&PathBuf]) -> Result<()> {
-    for &file in files {
-        if !file.exists() {
-            return Err(anyhow!(PipelineError::FileNotFound(
-                file.to_string_lossy().to_string()
-            )));
+/// Call `visit` with each record of a BAM file, or of a CRAM file (by its
+/// extension) decoded against `reference`, and return the file's header
+fn for_each_alignment<F>(path: &Path, reference: Option<&Path>, mut visit: F) -> Result<sam::Header>
+where
+    F: FnMut(&sam::alignment::Record) -> Result<()>,
+{
+    let file = std::fs::File::open(path).with_context(|| format!("Failed to open alignments: {:?}", path))?;
+    
+    if path.extension().is_some_and(|ext| ext == "cram") {
+        let reference = reference.ok_or_else(|| {
+            anyhow!(PipelineError::ConfigError(format!(
+                "Reading CRAM file {:?} needs the reference it was compressed against",
+                path
+            )))
+        })?;
+        let mut reader = cram::reader::Builder::default()
+            .set_reference_sequence_repository(reference_repository(reference)?)
+            .build_with_reader(file);
+        reader.read_file_definition()?;
+        let header: sam::Header = reader.read_file_header()?.parse()?;
+        for result in reader.records(&header) {
+            let record = result?.try_into_alignment_record(&header)?;
+            visit(&record)?;
         }
+        return Ok(header);
     }
-    Ok(())
+    
+    let mut reader = bam::Reader::new(file);
+    let header = reader.read_header()?;
+    let mut record = sam::alignment::Record::default();
+    while reader.read_record(&header, &mut record)? > 0 {
+        visit(&record)?;
+    }
+    Ok(header)
 }
 
-/// Run the alignment step
-async fn run_alignment(
-    reads: &Path,
-    reference: &Path,
-    out_bam: &Path,
-    settings: AlignSettings,
-    context: &PipelineContext,
-) -> Result<()> {
-    info!("Aligning reads from {:?} to reference {:?}", reads, reference);
+/// Summarize a histogram of insert sizes indexed by length
+fn insert_size_metrics(histogram: &[u64]) -> Option<InsertSizeMetrics> {
+    let pairs: u64 = histogram.iter().sum();
+    if pairs == 0 {
+        return None;
+    }
+    let mean = histogram.iter().enumerate().map(|(size, &n)| size as f64 * n as f64).sum::<f64>() / pairs as f64;
+    let variance = histogram
+        .iter()
+        .enumerate()
+        .map(|(size, &n)| (size as f64 - mean).powi(2) * n as f64)
+        .sum::<f64>()
+        / pairs as f64;
+    let mut below = 0;
+    let median = histogram
+        .iter()
+        .position(|&n| {
+            below += n;
+            below * 2 >= pairs
+        })
+        .unwrap_or(0) as u64;
+    Some(InsertSizeMetrics {
+        pairs,
+        mean,
+        median,
+        std_dev: variance.sqrt(),
+    })
+}
+
+/// Escape text for inclusion in HTML
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Inline SVG line chart of values by read position, one line per series
+fn svg_line_chart(series: &[(String, &[f64])], y_max: f64, y_label: &str) -> String {
+    const COLORS: [&str; 6] = ["#1f77b4", "#d62728", "#2ca02c", "#ff7f0e", "#9467bd", "#8c564b"];
+    let (width, height, margin) = (640.0, 240.0, 40.0);
+    let positions = series.iter().map(|(_, values)| values.len()).max().unwrap_or(0).max(2);
+    let x = |pos: usize| margin + pos as f64 / (positions - 1) as f64 * (width - 2.0 * margin);
+    let y = |value: f64| height - margin - value.min(y_max) / y_max * (height - 2.0 * margin);
     
-    // Create progress bar
-    let progress = context.progress.add(
-        ProgressBar::new(100).with_style(
-            ProgressStyle::default_bar()
-                .template("{spinner:.green} [{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}")
-                .unwrap()
-                .progress_chars("=>-"),
-        ),
+    let mut svg = format!(
+        "<svg width=\"{w}\" height=\"{h}\" xmlns=\"http://www.w3.org/2000/svg\" font-size=\"11\">\n\
+         <line x1=\"{m}\" y1=\"{b}\" x2=\"{r}\" y2=\"{b}\" stroke=\"#888\"/>\n\
+         <line x1=\"{m}\" y1=\"{m}\" x2=\"{m}\" y2=\"{b}\" stroke=\"#888\"/>\n\
+         <text x=\"{m}\" y=\"{t}\">{label} (max {max})</text>\n\
+         <text x=\"{r}\" y=\"{p}\" text-anchor=\"end\">position {positions}</text>\n",
+        w = width,
+        h = height,
+        m = margin,
+        b = height - margin,
+        r = width - margin,
+        t = margin - 8.0,
+        p = height - margin + 16.0,
+        label = html_escape(y_label),
+        max = y_max,
+        positions = positions,
     );
-    progress.set_message("Aligning reads...");
-    
-    // Determine aligner to use
-    let aligner = settings.aligner.unwrap_or_else(|| "bwa".to_string());
+    for (i, (name, values)) in series.iter().enumerate() {
+        let color = COLORS[i % COLORS.len()];
+        let points: Vec<String> =
+            values.iter().enumerate().map(|(pos, &value)| format!("{:.1},{:.1}", x(pos), y(value))).collect();
+        svg.push_str(&format!(
+            "<polyline fill=\"none\" stroke=\"{}\" stroke-width=\"1.5\" points=\"{}\"/>\n\
+             <text x=\"{}\" y=\"{}\" fill=\"{}\">{}</text>\n",
+            color,
+            points.join(" "),
+            width - margin - 4.0,
+            margin + 14.0 * (i + 1) as f64,
+            color,
+            html_escape(name)
+        ));
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Render a QC report as a self-contained HTML page
+fn render_qc_html(report: &QcReport) -> String {
+    let title = format!("QC report: {}", html_escape(&report.sample));
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n\
+         <style>body {{ font-family: sans-serif; margin: 2em; }} \
+         table {{ border-collapse: collapse; margin-bottom: 1em; }} \
+         td, th {{ border: 1px solid #ccc; padding: 4px 8px; text-align: left; }}</style>\n\
+         </head>\n<body>\n<h1>{title}</h1>\n",
+        title = title
+    );
+    let table = |rows: &[(&str, String)]| -> String {
+        let mut table = String::from("<table>\n");
+        for (name, value) in rows {
+            table.push_str(&format!("<tr><th>{}</th><td>{}</td></tr>\n", name, value));
+        }
+        table.push_str("</table>\n");
+        table
+    };
     
-    // Create intermediate BAM file path (before duplicate marking)
-    let intermediate_bam = context.temp_dir.path().join("aligned.bam");
+    for metrics in &report.fastq {
+        html.push_str(&format!("<h2>Reads: {}</h2>\n", html_escape(&metrics.path.to_string_lossy())));
+        html.push_str(&table(&[
+            ("Reads", metrics.reads.to_string()),
+            ("Bases", metrics.bases.to_string()),
+            ("Mean length", format!("{:.1}", metrics.mean_length)),
+            ("GC content", format!("{:.1}%", metrics.gc_percent)),
+            ("Mean quality", format!("{:.1}", metrics.mean_quality)),
+        ]));
+        html.push_str("<h3>Per-base quality</h3>\n");
+        html.push_str(&svg_line_chart(
+            &[("mean quality".to_string(), metrics.per_base_quality.as_slice())],
+            42.0,
+            "Phred quality",
+        ));
+        html.push_str("<h3>Adapter content</h3>\n");
+        let series: Vec<(String, &[f64])> = metrics
+            .adapter_content
+            .iter()
+            .map(|(adapter, percentages)| (adapter.clone(), percentages.as_slice()))
+            .collect();
+        html.push_str(&svg_line_chart(&series, 100.0, "% of reads"));
+    }
     
-    // Run alignment based on selected aligner
-    match aligner.as_str() {
-        "bwa" => {
-            debug!("Using BWA-MEM aligner");
-            // Implementation for BWA would go here
-            // For now, simulate progress
-            for i in 0..100 {
-                progress.set_position(i);
-                time::sleep(time::Duration::from_millis(10)).await;
+    if let Some(metrics) = &report.bam {
+        let percent = |n: u64| {
+            if metrics.reads > 0 {
+                format!("{} ({:.1}%)", n, 100.0 * n as f64 / metrics.reads as f64)
+            } else {
+                n.to_string()
             }
+        };
+        html.push_str(&format!("<h2>Alignments: {}</h2>\n", html_escape(&metrics.path.to_string_lossy())));
+        let mut rows = vec![
+            ("Reads", metrics.reads.to_string()),
+            ("Mapped", percent(metrics.mapped)),
+            ("Paired", percent(metrics.paired)),
+            ("Properly paired", percent(metrics.properly_paired)),
+            ("Duplicates", percent(metrics.duplicates)),
+            ("Mean coverage", format!("{:.2}x", metrics.mean_coverage)),
+        ];
+        if let Some(insert_size) = &metrics.insert_size {
+            rows.push((
+                "Insert size",
+                format!(
+                    "median {}, mean {:.1} &plusmn; {:.1} ({} pairs)",
+                    insert_size.median, insert_size.mean, insert_size.std_dev, insert_size.pairs
+                ),
+            ));
         }
-        "minimap2" => {
-            debug!("Using Minimap2 aligner");
-            // Implementation for Minimap2 would go here
-            // For now, simulate progress
-            for i in 0..100 {
-                progress.set_position(i);
-                time::sleep(time::Duration::from_millis(10)).await;
-            }
-        }
-        _ => {
-            return Err(anyhow!(PipelineError::ConfigError(format!(
-                "Unsupported aligner: {}",
-                aligner
-            ))));
+        html.push_str(&table(&rows));
+        html.push_str("<h3>Coverage by reference sequence</h3>\n<table>\n<tr><th>Sequence</th><th>Length</th><th>Mean depth</th></tr>\n");
+        for contig in &metrics.coverage {
+            html.push_str(&format!(
+                "<tr><td>{}</td><td>{}</td><td>{:.2}</td></tr>\n",
+                html_escape(&contig.name),
+                contig.length,
+                contig.mean_depth
+            ));
         }
+        html.push_str("</table>\n");
     }
     
-    // Mark duplicates if requested
-    if settings.mark_duplicates.unwrap_or(false) {
-        progress.set_message("Marking duplicates...");
-        // Implementation for duplicate marking would go here
-        
-        // Copy the final result to the output path
-        fs::copy(&intermediate_bam, out_bam).await?;
-    } else {
-        // No duplicate marking, just rename the intermediate file
-        fs::copy(&intermediate_bam, out_bam).await?;
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+/// Depth over one merged target region
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RegionCoverage {
+    chrom: String,
+    start: u64,
+    end: u64,
+    mean_depth: f64,
+    /// Fraction of the region's bases at the minimum depth or more
+    completeness: f64,
+}
+
+/// Coverage completeness of a panel, written as `<sample>.target_coverage.json`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TargetCoverage {
+    min_depth: u32,
+    target_bases: u64,
+    covered_bases: u64,
+    /// Fraction of target bases at `min_depth` or more
+    completeness: f64,
+    /// Required completeness, when configured
+    required: Option<f64>,
+    regions: Vec<RegionCoverage>,
+}
+
+impl TargetCoverage {
+    fn passes(&self) -> bool {
+        self.required.is_none_or(|required| self.completeness >= required)
     }
-    
-    // Index the BAM file
-    progress.set_message("Indexing BAM file...");
-    // Implementation for BAM indexing would go here
-    
-    progress.finish_with_message(format!("Alignment completed: {:?}", out_bam));
-    
-    info!("Alignment completed successfully");
-    Ok(())
 }
 
-/// Run the variant calling step
-async fn run_calling(
+/// `samtools depth` over the targets in `bed`; CRAM input needs the reference
+fn depth_command(bam: &Path, reference: Option<&Path>, bed: &Path) -> tokio::process::Command {
+    let mut command = tokio::process::Command::new("samtools");
+    command.arg("depth").arg("-b").arg(bed);
+    if let Some(reference) = reference {
+        command.arg("--reference").arg(reference);
+    }
+    command.arg(bam);
+    command
+}
+
+/// Measure the depth of every target base with `samtools depth`, which
+/// skips unmapped, secondary, QC-failed and duplicate reads, and write the
+/// coverage completeness of the panel to `output`. A sample below the
+/// required completeness is flagged with a warning.
+async fn run_target_coverage(
     bam: &Path,
-    reference: &Path,
-    out_vcf: &Path,
-    settings: CallSettings,
-    format: OutputFormat,
+    reference: Option<&Path>,
+    targets_path: &Path,
+    output: &Path,
+    settings: &QcSettings,
     context: &PipelineContext,
-) -> Result<()> {
-    info!("Calling variants from {:?} using reference {:?}", bam, reference);
+) -> Result<TargetCoverage> {
+    info!("Measuring target coverage of {:?}", bam);
+    let _lease = context.reserve("coverage", Some(1), None).await?;
+    let step_start = Instant::now();
+    let min_depth = settings.min_target_depth.unwrap_or(20);
     
-    // Create progress bar
-    let progress = context.progress.add(
-        ProgressBar::new(100).with_style(
-            ProgressStyle::default_bar()
-                .template("{spinner:.green} [{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}")
-                .unwrap()
-                .progress_chars("=>-"),
-        ),
-    );
-    progress.set_message("Calling variants...");
+    let targets = Targets::load(targets_path)?;
+    let bed = context.temp_dir.path().join("coverage_targets.bed");
+    targets.write_bed(&bed)?;
     
-    // Set up variant calling parameters
-    let min_depth = settings.min_depth.unwrap_or(10);
-    debug!("Minimum depth for variant calling: {}", min_depth);
+    // Covered bases and summed depth of each target, by chromosome
+    let mut counts: BTreeMap<&str, Vec<(u64, u64)>> = targets
+        .regions
+        .iter()
+        .map(|(chrom, intervals)| (chrom.as_str(), vec![(0, 0); intervals.len()]))
+        .collect();
+    let command = depth_command(bam, reference, &bed);
+    run_command_lines(command, "samtools depth", &context.cancel, |line| {
+        let mut fields = line.split('\t');
+        let (Some(chrom), Some(pos), Some(depth)) = (fields.next(), fields.next(), fields.next()) else {
+            return Ok(());
+        };
+        let (Ok(pos), Ok(depth)) = (pos.parse::<u64>(), depth.parse::<u64>()) else {
+            return Ok(());
+        };
+        if let Some(i) = targets.locate(chrom, pos.saturating_sub(1)) {
+            let entry = &mut counts.get_mut(chrom).expect("targets and counts share chromosomes")[i];
+            if depth >= u64::from(min_depth) {
+                entry.0 += 1;
+            }
+            entry.1 += depth;
+        }
+        Ok(())
+    })
+    .await?;
     
-    // Check if BAM is indexed
-    let bai_path = bam.with_extension("bam.bai");
-    if !bai_path.exists() {
-        warn!("BAM index not found, creating index for {:?}", bam);
-        // Implementation for BAM indexing would go here
-    }
+    let regions: Vec<RegionCoverage> = targets
+        .regions
+        .iter()
+        .flat_map(|(chrom, intervals)| {
+            intervals.iter().zip(&counts[chrom.as_str()]).map(move |(&(start, end), &(covered, depth))| {
+                let length = (end - start) as f64;
+                RegionCoverage {
+                    chrom: chrom.clone(),
+                    start,
+                    end,
+                    mean_depth: depth as f64 / length,
+                    completeness: covered as f64 / length,
+                }
+            })
+        })
+        .collect();
+    let target_bases = targets.total_bases();
+    let covered_bases: u64 = counts.values().flatten().map(|(covered, _)| covered).sum();
+    let coverage = TargetCoverage {
+        min_depth,
+        target_bases,
+        covered_bases,
+        completeness: covered_bases as f64 / target_bases as f64,
+        required: settings.min_target_completeness,
+        regions,
+    };
     
-    // Process regions if specified
-    let regions = settings.regions.unwrap_or_default();
-    let region_count = regions.len();
+    std::fs::write(output, serde_json::to_string_pretty(&coverage)?)
+        .with_context(|| format!("Failed to write target coverage: {:?}", output))?;
+    context.profile.lock().unwrap().record("coverage", step_start, target_bases, &[bam, targets_path], &[output]);
     
-    if !regions.is_empty() {
-        debug!("Processing {} specific regions", region_count);
+    if coverage.passes() {
+        info!(
+            "{:.1}% of {} target bases covered at {}x or more",
+            coverage.completeness * 100.0,
+            target_bases,
+            min_depth
+        );
     } else {
-        debug!("Processing entire genome");
-    }
-    
-    // Simulate variant calling progress
-    for i in 0..100 {
-        progress.set_position(i);
-        time::sleep(time::Duration::from_millis(20)).await;
+        warn!(
+            "Only {:.1}% of {} target bases covered at {}x or more, below the required {:.1}%",
+            coverage.completeness * 100.0,
+            target_bases,
+            min_depth,
+            coverage.required.unwrap_or(0.0) * 100.0
+        );
     }
+    Ok(coverage)
+}
+
+/// File stem of the merged cohort VCF, reserved as a sample name
+const COHORT_NAME: &str = "cohort";
+
+/// One sample of a cohort manifest
+#[derive(Debug, Clone)]
+struct CohortSample {
+    name: String,
+    reads: PathBuf,
+    mate: Option<PathBuf>,
+}
+
+/// Parse a cohort manifest with one `sample<TAB>reads[<TAB>mate_reads]` line
+/// per sample; `#` lines are comments. Relative read paths are resolved
+/// against the manifest's directory.
+fn load_manifest(path: &Path) -> Result<Vec<CohortSample>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read sample manifest: {:?}", path))?;
+    let base = path.parent().unwrap_or(Path::new(""));
+    let mut samples = Vec::new();
+    let mut names = HashSet::new();
     
-    // Create intermediate VCF for format conversion if needed
-    let intermediate_vcf = context.temp_dir.path().join("variants.vcf");
-    
-    // Convert to the requested output format
-    progress.set_message("Converting to final format...");
-    
-    match format {
-        OutputFormat::Vcf => {
-            fs::copy(&intermediate_vcf, out_vcf).await?;
+    for (line_no, line) in text.lines().enumerate() {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
         }
-        OutputFormat::Bcf => {
-            // Implementation for BCF conversion would go here
+        let invalid = |reason: String| {
+            anyhow!(PipelineError::InvalidInput(format!("{:?} line {}: {}", path, line_no + 1, reason)))
+        };
+        let fields: Vec<&str> = line.split('\t').collect();
+        if !(2..=3).contains(&fields.len()) {
+            return Err(invalid("expected sample, reads and optional mate reads".to_string()));
         }
-        _ => {
-            return Err(anyhow!(PipelineError::ConfigError(format!(
-                "Unsupported output format for variant calling: {:?}",
-                format
-            ))));
+        let name = fields[0].to_string();
+        if name.is_empty() || name.contains(['/', '\\']) || name == COHORT_NAME {
+            return Err(invalid(format!("invalid sample name '{}'", name)));
+        }
+        if !names.insert(name.clone()) {
+            return Err(invalid(format!("duplicate sample '{}'", name)));
+        }
+        
+        // Remote URIs are checked and staged when the sample runs
+        let resolve = |field: &str| match remote_uri(Path::new(field)) {
+            Some(uri) => PathBuf::from(uri),
+            None => base.join(field),
+        };
+        let reads = resolve(fields[1]);
+        let mate = fields.get(2).filter(|mate| !mate.is_empty()).map(|mate| resolve(mate));
+        for file in std::iter::once(&reads).chain(&mate).filter(|file| remote_uri(file).is_none()) {
+            if !file.exists() {
+                return Err(anyhow!(PipelineError::FileNotFound(file.to_string_lossy().to_string())));
+            }
         }
+        samples.push(CohortSample { name, reads, mate });
     }
     
-    progress.finish_with_message(format!("Variant calling completed: {:?}", out_vcf));
-    
-    info!("Variant calling completed successfully");
-    Ok(())
+    if samples.is_empty() {
+        return Err(anyhow!(PipelineError::InvalidInput(format!("No samples in manifest {:?}", path))));
+    }
+    Ok(samples)
 }
 
-/// Run the annotation step
-async fn run_annotation(
-    vcf: &Path,
+/// Run the full pipeline for every sample of a manifest, at most
+/// `max_parallel` at a time, then merge the per-sample calls into a cohort VCF
+async fn run_cohort(
+    manifest: &Path,
+    reference: &Path,
     gff: &Path,
-    output: &Path,
-    settings: AnnotateSettings,
-    format: OutputFormat,
+    output_dir: &Path,
+    max_parallel: usize,
+    keep_intermediate: bool,
     context: &PipelineContext,
 ) -> Result<()> {
-    info!("Annotating variants from {:?} using annotations {:?}", vcf, gff);
+    let samples = load_manifest(manifest)?;
+    let max_parallel = max_parallel.clamp(1, samples.len());
+    info!("Running cohort of {} samples, {} at a time", samples.len(), max_parallel);
     
-    // Create progress bar
-    let progress = context.progress.add(
-        ProgressBar::new(100).with_style(
-            ProgressStyle::default_bar()
-                .template("{spinner:.green} [{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}")
-                .unwrap()
-                .progress_chars("=>-"),
-        ),
-    );
-    progress.set_message("Loading annotations...");
+    // Samples running together share the thread budget
+    let threads = (context.settings.threads / max_parallel).max(1);
+    let permits = Arc::new(Semaphore::new(max_parallel));
+    let mut running = JoinSet::new();
+    for sample in samples.iter().cloned() {
+        // A temporary directory per sample keeps intermediate file names apart
+        let mut ctx = context.clone();
+        ctx.settings.threads = threads;
+        ctx.temp_dir = Arc::new(
+            TempDir::new_in(context.temp_dir.path()).context("Failed to create sample temporary directory")?,
+        );
+        let (permits, reference, gff, output_dir) =
+            (permits.clone(), reference.to_path_buf(), gff.to_path_buf(), output_dir.to_path_buf());
+        running.spawn(async move {
+            let result = async {
+                let _permit = permits.acquire().await?;
+                if ctx.cancel.is_cancelled() {
+                    return Err(anyhow!(PipelineError::Interrupted));
+                }
+                validate_files(&std::iter::once(&sample.reads).chain(&sample.mate).collect::<Vec<_>>()).await?;
+                let reads = ctx.localize(&sample.reads).await?;
+                let mate = ctx.localize_option(sample.mate.clone()).await?;
+                run_full_pipeline(
+                    &reads,
+                    mate.as_deref(),
+                    &reference,
+                    &gff,
+                    &output_dir,
+                    &sample.name,
+                    keep_intermediate,
+                    &ctx,
+                )
+                .await
+            }
+            .await;
+            (sample.name, result)
+        });
+    }
     
-    // Process additional databases if specified
-    let databases = settings.databases.unwrap_or_default();
-    if !databases.is_empty() {
-        debug!("Using {} additional annotation databases", databases.len());
-        for db in &databases {
-            if !db.exists() {
-                warn!("Annotation database not found: {:?}", db);
+    // Let every sample finish so a rerun only has to redo the failed ones
+    let mut stats = BTreeMap::new();
+    let mut failed = Vec::new();
+    while let Some(joined) = running.join_next().await {
+        let (name, result) = joined.context("Sample pipeline panicked")?;
+        match result {
+            Ok(sample_stats) => {
+                stats.insert(name, sample_stats);
+            }
+            Err(e) => {
+                error!("Sample {} failed: {:#}", name, e);
+                failed.push(name);
             }
         }
     }
-    
-    // Check if effect predictions are requested
-    let predict_effects = settings.effects.unwrap_or(false);
-    if predict_effects {
-        debug!("Including effect predictions in annotation");
+    if context.cancel.is_cancelled() {
+        return Err(anyhow!(PipelineError::Interrupted));
+    }
+    if !failed.is_empty() {
+        failed.sort();
+        bail!(
+            "{} of {} samples failed ({}); rerun to resume them",
+            failed.len(),
+            samples.len(),
+            failed.join(", ")
+        );
     }
     
-    // Simulate annotation progress
-    for i in 0..50 {
-        progress.set_position(i);
-        time::sleep(time::Duration::from_millis(20)).await;
+    // Joint merge of the per-sample calls, in manifest order
+    let vcfs: Vec<PathBuf> = samples.iter().map(|s| output_dir.join(format!("{}.vcf", s.name))).collect();
+    let cohort_vcf = output_dir.join(format!("{}.vcf.gz", COHORT_NAME));
+    let mut workflow = Workflow::new(COHORT_NAME, output_dir.join(format!(".{}.steps.json", COHORT_NAME)), context);
+    let (inputs, merged, ctx) = (vcfs.clone(), cohort_vcf.clone(), context.clone());
+    let outputs = vec![cohort_vcf.clone(), PathBuf::from(format!("{}.tbi", cohort_vcf.display()))];
+    let commands = merge_plan(&vcfs, &cohort_vcf, context);
+    workflow.add(Step::new("merge", vcfs, outputs, String::new(), async move {
+        let records = merge_vcfs(&inputs, &merged, &ctx).await?;
+        Ok(records as u64)
+    }).with_commands(commands));
+    let records = workflow.run().await?;
+    if context.dry_run {
+        return Ok(());
     }
     
-    progress.set_message("Processing variants...");
+    print_cohort_summary(&stats, records["merge"] as usize, &cohort_vcf);
+    Ok(())
+}
+
+/// Merge single-sample VCFs into one bgzipped, tabix-indexed multi-sample VCF
+/// with `bcftools merge`; a site absent from a sample gets a missing genotype.
+/// Returns the number of merged records.
+async fn merge_vcfs(vcfs: &[PathBuf], out_vcf: &Path, context: &PipelineContext) -> Result<usize> {
+    info!("Merging {} sample VCFs into {:?}", vcfs.len(), out_vcf);
+    let step_start = Instant::now();
     
-    // Continue simulation
-    for i in 50..100 {
-        progress.set_position(i);
-        time::sleep(time::Duration::from_millis(20)).await;
+    // bcftools merge reads bgzipped, indexed inputs
+    let work_dir = TempDir::new_in(context.temp_dir.path()).context("Failed to create merge directory")?;
+    let mut compressed = Vec::with_capacity(vcfs.len());
+    for (i, vcf) in vcfs.iter().enumerate() {
+        let gz = work_dir.path().join(format!("sample_{}.vcf.gz", i));
+        let [view, index] = bgzip_commands(vcf, &gz);
+        run_command(view, "bcftools view", &context.cancel).await?;
+        run_command(index, "bcftools index", &context.cancel).await?;
+        compressed.push(gz);
     }
     
-    // Create output in the requested format
-    progress.set_message("Writing results...");
+    // A one-sample cohort has nothing to merge
+    if let [single] = compressed.as_slice() {
+        fs::copy(single, out_vcf).await?;
+    } else {
+        let merge = merge_command(&compressed, out_vcf, context.settings.threads);
+        run_command(merge, "bcftools merge", &context.cancel).await?;
+    }
+    run_command(tabix_command(out_vcf), "bcftools index", &context.cancel).await?;
     
-    match format {
-        OutputFormat::Tsv => {
-            // Implementation for TSV output would go here
-        }
-        OutputFormat::Json => {
-            // Implementation for JSON output would go here
-        }
-        OutputFormat::Parquet => {
-            // Implementation for Parquet output would go here
-        }
-        _ => {
-            return Err(anyhow!(PipelineError::ConfigError(format!(
-                "Unsupported output format for annotation: {:?}",
-                format
-            ))));
+    let mut records = 0;
+    for line in open_maybe_gzip(out_vcf)?.lines() {
+        if !line?.starts_with('#') {
+            records += 1;
         }
     }
     
-    progress.finish_with_message(format!("Annotation completed: {:?}", output));
-    
-    info!("Annotation completed successfully");
-    Ok(())
+    let inputs: Vec<&Path> = vcfs.iter().map(PathBuf::as_path).collect();
+    context.profile.lock().unwrap().record("merge", step_start, records as u64, &inputs, &[out_vcf]);
+    Ok(records)
 }
 
-/// Run the full pipeline
-async fn run_full_pipeline(
-    reads: &Path,
+/// Planned commands of the merge step, as `merge_vcfs` would run them
+fn merge_plan(vcfs: &[PathBuf], out_vcf: &Path, context: &PipelineContext) -> Vec<String> {
+    let work_dir = context.temp_dir.path().join("<merge>");
+    let compressed: Vec<PathBuf> =
+        (0..vcfs.len()).map(|i| work_dir.join(format!("sample_{}.vcf.gz", i))).collect();
+    let mut plan = Vec::new();
+    for (vcf, gz) in vcfs.iter().zip(&compressed) {
+        plan.extend(bgzip_commands(vcf, gz).iter().map(command_line));
+    }
+    if let [single] = compressed.as_slice() {
+        plan.push(format!("# copy {} to {}", single.display(), out_vcf.display()));
+    } else {
+        plan.push(command_line(&merge_command(&compressed, out_vcf, context.settings.threads)));
+    }
+    plan.push(command_line(&tabix_command(out_vcf)));
+    plan
+}
+
+/// `bcftools view` compressing a VCF to `gz`, then `bcftools index` on it
+fn bgzip_commands(vcf: &Path, gz: &Path) -> [tokio::process::Command; 2] {
+    let mut view = tokio::process::Command::new("bcftools");
+    view.arg("view").arg("-Oz").arg("-o").arg(gz).arg(vcf);
+    let mut index = tokio::process::Command::new("bcftools");
+    index.arg("index").arg("-f").arg(gz);
+    [view, index]
+}
+
+fn merge_command(vcfs: &[PathBuf], out_vcf: &Path, threads: usize) -> tokio::process::Command {
+    let mut merge = tokio::process::Command::new("bcftools");
+    merge
+        .arg("merge")
+        .arg("--threads").arg(threads.to_string())
+        .arg("-Oz")
+        .arg("-o").arg(out_vcf)
+        .args(vcfs);
+    merge
+}
+
+/// `bcftools index` writing a tabix index next to a bgzipped VCF
+fn tabix_command(vcf: &Path) -> tokio::process::Command {
+    let mut index = tokio::process::Command::new("bcftools");
+    index.arg("index").arg("-t").arg("-f").arg(vcf);
+    index
+}
+
+/// Run the tumor-only somatic workflow
+#[allow(clippy::too_many_arguments)]
+async fn run_tumor_only(
+    bam: &Path,
     reference: &Path,
     gff: &Path,
+    population_af: &Path,
+    panel_of_normals: Option<&Path>,
+    graph: &Path,
+    model: &Path,
     output_dir: &Path,
     sample: &str,
-    keep_intermediate: bool,
+    settings: TumorOnlySettings,
     context: &PipelineContext,
 ) -> Result<()> {
-    info!("Running full pipeline for sample: {}", sample);
+    info!("Running tumor-only workflow for sample: {}", sample);
     
-    // Create output paths
-    let bam_path = output_dir.join(format!("{}.bam", sample));
-    let vcf_path = output_dir.join(format!("{}.vcf", sample));
-    let annotation_path = output_dir.join(format!("{}.annotated.tsv", sample));
+    let raw_vcf = output_dir.join(format!("{}.raw.vcf", sample));
+    let somatic_vcf = output_dir.join(format!("{}.somatic.vcf", sample));
+    let kept_path = output_dir.join(format!("{}.somatic.alleles.tsv", sample));
+    let filter_stats_path = output_dir.join(format!("{}.germline_filter.json", sample));
+    let annotation_path = output_dir.join(format!("{}.somatic.annotated.tsv", sample));
+    let scores_path = output_dir.join(format!("{}.somatic.scores.tsv", sample));
+    let ranked_path = output_dir.join(format!("{}.somatic_candidates.tsv", sample));
+    let max_af = settings.max_population_af.unwrap_or(0.001);
     
-    // Initialize statistics
-    let stats = Arc::new(Mutex::new(PipelineStats::default()));
+    let mut workflow = Workflow::new(sample, output_dir.join(format!(".{}.tumor_only.steps.json", sample)), context);
     
-    // Step 1: Alignment
-    info!("Step 1/3: Alignment");
-    let align_result = run_alignment(
-        reads,
-        reference,
-        &bam_path,
-        context.settings.align.clone(),
-        context,
-    )
-    .await;
-    
-    if let Err(e) = align_result {
-        error!("Alignment failed: {}", e);
-        return Err(e);
-    }
-    
-    // Step 2: Variant Calling
-    info!("Step 2/3: Variant Calling");
-    let call_result = run_calling(
-        &bam_path,
-        reference,
-        &vcf_path,
-        context.settings.call.clone(),
-        OutputFormat::Vcf,
-        context,
-    )
-    .await;
-    
-    if let Err(e) = call_result {
-        error!("Variant calling failed: {}", e);
-        return Err(e);
-    }
-    
-    // Step 3: Annotation
-    info!("Step 3/3: Annotation");
-    let annotate_result = run_annotation(
-        &vcf_path,
-        gff,
-        &annotation_path,
-        context.settings.annotate.clone(),
-        OutputFormat::Tsv,
-        context,
-    )
-    .await;
+    // Step 1: Variant Calling
+    let call_settings = context.settings.call.clone();
+    let params = serde_json::to_string(&call_settings)?;
+    let mut inputs = vec![bam.to_path_buf(), reference.to_path_buf()];
+    inputs.extend(call_settings.targets.clone());
+    let (bam, reference, vcf, ctx) = (bam.to_path_buf(), reference.to_path_buf(), raw_vcf.clone(), context.clone());
+    let commands = call_step_plan(&bam, &reference, &vcf, &call_settings, context)?;
+    workflow.add(Step::new(
+        "call",
+        inputs,
+        vec![vcf.clone()],
+        params,
+        async move {
+            let variants = run_calling(&bam, &reference, &vcf, call_settings, OutputFormat::Vcf, &ctx).await?;
+            Ok(variants as u64)
+        },
+    ).with_commands(commands));
     
-    if let Err(e) = annotate_result {
-        error!("Annotation failed: {}", e);
-        return Err(e);
+    // Step 2: Germline removal
+    let mut inputs = vec![raw_vcf.clone(), population_af.to_path_buf()];
+    inputs.extend(panel_of_normals.map(Path::to_path_buf));
+    let (raw, somatic, kept, stats_path, population_af, panel_of_normals, ctx) = (
+        raw_vcf.clone(),
+        somatic_vcf.clone(),
+        kept_path.clone(),
+        filter_stats_path.clone(),
+        population_af.to_path_buf(),
+        panel_of_normals.map(Path::to_path_buf),
+        context.clone(),
+    );
+    let mut commands = vec![format!(
+        "# drop alleles with population AF above {} in {}",
+        max_af,
+        population_af.display()
+    )];
+    commands.extend(panel_of_normals.as_ref().map(|pon| format!("# drop alleles in the panel of normals {}", pon.display())));
+    workflow.add(Step::new(
+        "germline_filter",
+        inputs,
+        vec![somatic.clone(), kept.clone(), stats_path.clone()],
+        serde_json::to_string(&max_af)?,
+        async move {
+            let step_start = Instant::now();
+            let population = load_population_af(&population_af)?;
+            let pon_sites = match &panel_of_normals {
+                Some(pon) => load_pon_sites(pon)?,
+                None => HashSet::new(),
+            };
+            let (filter_stats, kept_af) = filter_germline(&raw, &somatic, &population, &pon_sites, max_af)?;
+            write_kept_alleles(&kept_af, &kept)?;
+            std::fs::write(&stats_path, serde_json::to_string_pretty(&filter_stats)?)
+                .with_context(|| format!("Failed to write germline filter counts: {:?}", stats_path))?;
+            info!(
+                "Germline filtering kept {}/{} variants ({} population, {} panel-of-normals)",
+                filter_stats.somatic_candidates,
+                filter_stats.input_variants,
+                filter_stats.population_filtered,
+                filter_stats.pon_filtered
+            );
+            ctx.profile.lock().unwrap().record(
+                "germline_filter",
+                step_start,
+                filter_stats.input_variants as u64,
+                &[&raw, &population_af],
+                &[&somatic],
+            );
+            Ok(filter_stats.input_variants as u64)
+        },
+    ).with_commands(commands));
+    
+    // Step 3: Annotation, independent of scoring
+    workflow.add(annotation_step(&somatic_vcf, gff, &annotation_path, context)?);
+    
+    // Step 4: Scoring with the ONNX model via the variant scorer
+    let scorer = settings.scorer.clone().unwrap_or_else(|| "variant-scorer".to_string());
+    let min_score = settings.min_score;
+    let params = serde_json::to_string(&(&scorer, min_score))?;
+    let (somatic, kept, graph, model, scores, ranked, ctx) = (
+        somatic_vcf.clone(),
+        kept_path.clone(),
+        graph.to_path_buf(),
+        model.to_path_buf(),
+        scores_path.clone(),
+        ranked_path.clone(),
+        context.clone(),
+    );
+    let commands = vec![
+        command_line(&scorer_command(&scorer, &graph, &somatic, &model, &scores)),
+        format!("# rank candidates into {}", ranked.display()),
+    ];
+    workflow.add(Step::new(
+        "score",
+        vec![somatic.clone(), kept.clone(), graph.clone(), model.clone()],
+        vec![scores.clone(), ranked.clone()],
+        params,
+        async move {
+            let step_start = Instant::now();
+            let command = scorer_command(&scorer, &graph, &somatic, &model, &scores);
+            run_command(command, &scorer, &ctx.cancel).await?;
+            
+            let kept_af = load_kept_alleles(&kept)?;
+            let candidates = rank_candidates(&scores, &kept_af, min_score)?;
+            write_candidates(&candidates, &ranked)?;
+            ctx.profile.lock().unwrap().record(
+                "score",
+                step_start,
+                candidates.len() as u64,
+                &[&somatic, &model],
+                &[&scores, &ranked],
+            );
+            Ok(candidates.len() as u64)
+        },
+    ).with_commands(commands));
+    
+    let records = workflow.run().await?;
+    if context.dry_run {
+        return Ok(());
     }
+    let filter_stats: GermlineFilterStats = serde_json::from_str(
+        &std::fs::read_to_string(&filter_stats_path)
+            .with_context(|| format!("Failed to read germline filter counts: {:?}", filter_stats_path))?,
+    )?;
     
-    // Clean up intermediate files if requested
-    if !keep_intermediate {
-        info!("Cleaning up intermediate files");
-        // In a real implementation, we'd delete intermediates here
+    println!("\n========== Tumor-only Summary ==========");
+    println!("Sample: {}", sample);
+    println!("Called variants: {}", filter_stats.input_variants);
+    println!("Removed by population AF > {}: {}", max_af, filter_stats.population_filtered);
+    println!("Removed by panel of normals: {}", filter_stats.pon_filtered);
+    println!("Ranked somatic candidates: {}", records["score"]);
+    println!("Candidates: {}", ranked_path.display());
+    println!("========================================\n");
+    
+    Ok(())
+}
+
+/// The variant scorer from experiment_8_6, writing scores as TSV
+fn scorer_command(scorer: &str, graph: &Path, vcf: &Path, model: &Path, scores: &Path) -> tokio::process::Command {
+    let mut command = tokio::process::Command::new(scorer);
+    command
+        .arg("score")
+        .arg("--graph").arg(graph)
+        .arg("--vcf").arg(vcf)
+        .arg("--model").arg(model)
+        .arg("--out").arg(scores)
+        .arg("--format").arg("tsv");
+    command
+}
+
+/// Write the alleles passing germline filtering with their population frequency
+fn write_kept_alleles(kept: &HashMap<AlleleKey, f64>, path: &Path) -> Result<()> {
+    let file = std::fs::File::create(path)
+        .with_context(|| format!("Failed to create allele list: {:?}", path))?;
+    let mut writer = BufWriter::new(file);
+    
+    writeln!(writer, "#chrom\tpos\tref\talt\tpopulation_af")?;
+    for ((chrom, pos, ref_allele, alt_allele), af) in kept {
+        writeln!(writer, "{}\t{}\t{}\t{}\t{}", chrom, pos, ref_allele, alt_allele, af)?;
     }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Read an allele list written by `write_kept_alleles`
+fn load_kept_alleles(path: &Path) -> Result<HashMap<AlleleKey, f64>> {
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to open allele list: {:?}", path))?;
+    let mut kept = HashMap::new();
     
-    // Calculate elapsed time
-    let elapsed = context.start_time.elapsed();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 5 {
+            continue;
+        }
+        if let (Ok(pos), Ok(af)) = (fields[1].parse::<u64>(), fields[4].parse::<f64>()) {
+            kept.insert((fields[0].to_string(), pos, fields[2].to_string(), fields[3].to_string()), af);
+        }
+    }
     
-    // Update and print statistics
-    {
-        let mut stats_guard = stats.lock().unwrap();
-        stats_guard.elapsed_seconds = elapsed.as_secs_f64();
+    Ok(kept)
+}
+
+/// Load population allele frequencies keyed by (chrom, pos, alt)
+fn load_population_af(path: &Path) -> Result<HashMap<PopulationKey, f64>> {
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to open population AF file: {:?}", path))?;
+    let mut freqs = HashMap::new();
+    
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 4 {
+            continue;
+        }
+        if let (Ok(pos), Ok(af)) = (fields[1].parse::<u64>(), fields[3].parse::<f64>()) {
+            freqs.insert((fields[0].to_string(), pos, fields[2].to_string()), af);
+        }
+    }
+    
+    debug!("Loaded {} population frequencies", freqs.len());
+    Ok(freqs)
+}
+
+/// Load panel-of-normals sites keyed by (chrom, pos, ref, alt)
+fn load_pon_sites(path: &Path) -> Result<HashSet<AlleleKey>> {
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to open panel of normals: {:?}", path))?;
+    let mut sites = HashSet::new();
+    
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 5 {
+            continue;
+        }
+        if let Ok(pos) = fields[1].parse::<u64>() {
+            for alt in fields[4].split(',') {
+                sites.insert((fields[0].to_string(), pos, fields[3].to_string(), alt.to_string()));
+            }
+        }
+    }
+    
+    debug!("Loaded {} panel-of-normals sites", sites.len());
+    Ok(sites)
+}
+
+/// Stream the raw call set, dropping records whose every ALT allele is common
+/// in the population or present in the panel of normals. Surviving records get
+/// a POP_AF INFO field; the passing alleles and their frequencies are returned
+/// for ranking.
+fn filter_germline(
+    input: &Path,
+    output: &Path,
+    population: &HashMap<PopulationKey, f64>,
+    pon_sites: &HashSet<AlleleKey>,
+    max_af: f64,
+) -> Result<(GermlineFilterStats, HashMap<AlleleKey, f64>)> {
+    let reader = BufReader::new(
+        std::fs::File::open(input).with_context(|| format!("Failed to open VCF: {:?}", input))?,
+    );
+    let mut writer = BufWriter::new(
+        std::fs::File::create(output).with_context(|| format!("Failed to create VCF: {:?}", output))?,
+    );
+    let mut stats = GermlineFilterStats::default();
+    let mut kept = HashMap::new();
+    
+    for line in reader.lines() {
+        let line = line?;
+        if line.starts_with("##") {
+            writeln!(writer, "{}", line)?;
+            continue;
+        }
+        if line.starts_with('#') {
+            writeln!(
+                writer,
+                "##INFO=<ID=POP_AF,Number=A,Type=Float,Description=\"Population allele frequency used for germline filtering\">"
+            )?;
+            writeln!(writer, "{}", line)?;
+            continue;
+        }
+        
+        let mut fields: Vec<String> = line.split('\t').map(str::to_string).collect();
+        if fields.len() < 8 {
+            warn!("Skipping malformed VCF line: {}", line);
+            continue;
+        }
+        stats.input_variants += 1;
+        
+        let chrom = fields[0].clone();
+        let pos: u64 = fields[1]
+            .parse()
+            .map_err(|_| anyhow!(PipelineError::InvalidInput(format!("Bad VCF position: {}", fields[1]))))?;
+        let ref_allele = fields[3].clone();
+        let alts: Vec<String> = fields[4].split(',').map(str::to_string).collect();
         
-        // In a real implementation, we'd gather actual statistics
-        stats_guard.aligned_reads = 1_000_000;
-        stats_guard.variants_called = 10_000;
-        stats_guard.variants_annotated = 5_000;
+        let mut afs = Vec::with_capacity(alts.len());
+        let mut passing = Vec::with_capacity(alts.len());
+        let mut in_pon = 0;
+        for alt in &alts {
+            let af = population.get(&(chrom.clone(), pos, alt.clone())).copied().unwrap_or(0.0);
+            afs.push(af);
+            if pon_sites.contains(&(chrom.clone(), pos, ref_allele.clone(), alt.clone())) {
+                in_pon += 1;
+            } else if af <= max_af {
+                passing.push((alt.clone(), af));
+            }
+        }
+        
+        if passing.is_empty() {
+            if in_pon > 0 {
+                stats.pon_filtered += 1;
+            } else {
+                stats.population_filtered += 1;
+            }
+            continue;
+        }
         
-        print_pipeline_summary(&stats_guard, sample);
+        for (alt, af) in passing {
+            kept.insert((chrom.clone(), pos, ref_allele.clone(), alt), af);
+        }
+        let pop_af = afs.iter().map(|af| format!("{}", af)).collect::<Vec<_>>().join(",");
+        fields[7] = if fields[7] == "." {
+            format!("POP_AF={}", pop_af)
+        } else {
+            format!("{};POP_AF={}", fields[7], pop_af)
+        };
+        writeln!(writer, "{}", fields.join("\t"))?;
+        stats.somatic_candidates += 1;
     }
     
-    info!(
-        "Full pipeline completed successfully in {:.2} seconds",
-        elapsed.as_secs_f64()
-    );
+    writer.flush()?;
+    Ok((stats, kept))
+}
+
+/// Join scorer output with population frequencies and rank by descending score
+fn rank_candidates(
+    scores_path: &Path,
+    kept_af: &HashMap<AlleleKey, f64>,
+    min_score: Option<f64>,
+) -> Result<Vec<SomaticCandidate>> {
+    let file = std::fs::File::open(scores_path)
+        .with_context(|| format!("Failed to open scorer output: {:?}", scores_path))?;
+    let mut lines = BufReader::new(file).lines();
+    
+    let header = lines
+        .next()
+        .ok_or_else(|| anyhow!(PipelineError::InvalidInput("Empty scorer output".to_string())))??;
+    let columns: Vec<&str> = header.split('\t').collect();
+    let column = |name: &str| {
+        columns
+            .iter()
+            .position(|c| *c == name)
+            .ok_or_else(|| anyhow!(PipelineError::InvalidInput(format!("Scorer output lacks column '{}'", name))))
+    };
+    let (chrom_idx, pos_idx, ref_idx, alt_idx, score_idx) =
+        (column("chrom")?, column("pos")?, column("ref")?, column("alt")?, column("score")?);
+    
+    let mut candidates = Vec::new();
+    for line in lines {
+        let line = line?;
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() <= score_idx.max(alt_idx) {
+            continue;
+        }
+        // The scorer reports 0-based positions from htslib; VCF is 1-based
+        let pos = fields[pos_idx].parse::<u64>().map(|p| p + 1).unwrap_or(0);
+        let score: f64 = fields[score_idx].parse().unwrap_or(0.0);
+        if min_score.is_some_and(|min| score < min) {
+            continue;
+        }
+        let key = (
+            fields[chrom_idx].to_string(),
+            pos,
+            fields[ref_idx].to_string(),
+            fields[alt_idx].to_string(),
+        );
+        // Alleles removed as germline at a partially passing site are not candidates
+        let Some(&population_af) = kept_af.get(&key) else {
+            continue;
+        };
+        candidates.push(SomaticCandidate {
+            chrom: key.0,
+            pos: key.1,
+            ref_allele: key.2,
+            alt_allele: key.3,
+            population_af,
+            score,
+        });
+    }
+    
+    candidates.sort_by(|a, b| {
+        b.score
+            .partial_cmp(&a.score)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then(a.population_af.partial_cmp(&b.population_af).unwrap_or(std::cmp::Ordering::Equal))
+    });
+    Ok(candidates)
+}
+
+/// Write the ranked somatic candidate list
+fn write_candidates(candidates: &[SomaticCandidate], path: &Path) -> Result<()> {
+    let file = std::fs::File::create(path)
+        .with_context(|| format!("Failed to create candidate list: {:?}", path))?;
+    let mut writer = BufWriter::new(file);
     
+    writeln!(writer, "rank\tchrom\tpos\tref\talt\tscore\tpopulation_af")?;
+    for (rank, c) in candidates.iter().enumerate() {
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}\t{:.4}\t{}",
+            rank + 1,
+            c.chrom,
+            c.pos,
+            c.ref_allele,
+            c.alt_allele,
+            c.score,
+            c.population_af
+        )?;
+    }
+    writer.flush()?;
+    
+    info!("Ranked {} somatic candidates written to {:?}", candidates.len(), path);
     Ok(())
 }
 
+/// Print per-sample results and the merged cohort VCF
+fn print_cohort_summary(stats: &BTreeMap<String, PipelineStats>, cohort_variants: usize, cohort_vcf: &Path) {
+    let formatter = Formatter::new();
+    
+    println!("\n========== Cohort Summary ==========");
+    let targets = stats.values().any(|s| s.target_coverage.is_some());
+    print!("{:<20} {:>12} {:>12} {:>12}", "Sample", "Aligned", "Called", "Annotated");
+    println!("{}", if targets { format!(" {:>12}", "On target") } else { String::new() });
+    for (sample, s) in stats {
+        print!(
+            "{:<20} {:>12} {:>12} {:>12}",
+            sample,
+            formatter.format(s.aligned_reads as f64),
+            formatter.format(s.variants_called as f64),
+            formatter.format(s.variants_annotated as f64)
+        );
+        match &s.target_coverage {
+            Some(coverage) => println!(" {:>11.1}%{}", coverage.completeness * 100.0, coverage_flag(coverage)),
+            None => println!(),
+        }
+    }
+    println!("Cohort variants: {}", formatter.format(cohort_variants as f64));
+    println!("Cohort VCF: {}", cohort_vcf.display());
+    println!("====================================\n");
+}
+
+/// Marker for a sample below the required target coverage
+fn coverage_flag(coverage: &TargetCoverage) -> String {
+    match coverage.required {
+        Some(required) if !coverage.passes() => format!(" (below the required {:.1}%)", required * 100.0),
+        _ => String::new(),
+    }
+}
+
 /// Print a summary of the pipeline results
 fn print_pipeline_summary(stats: &PipelineStats, sample: &str) {
     let formatter = Formatter::new();
@@ -841,5 +5333,14 @@ fn print_pipeline_summary(stats: &PipelineStats, sample: &str) {
     println!("Aligned reads: {}", formatter.format(stats.aligned_reads as f64));
     println!("Variants called: {}", formatter.format(stats.variants_called as f64));
     println!("Variants annotated: {}", formatter.format(stats.variants_annotated as f64));
+    if let Some(coverage) = &stats.target_coverage {
+        println!(
+            "Target coverage: {:.1}% of {} bases at {}x or more{}",
+            coverage.completeness * 100.0,
+            formatter.format(coverage.target_bases as f64),
+            coverage.min_depth,
+            coverage_flag(coverage)
+        );
+    }
     println!("======================================\n");
 }
\ No newline at end of file
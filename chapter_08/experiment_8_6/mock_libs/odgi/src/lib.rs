@@ -1,8 +1,9 @@
 pub mod graph {
+    use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
     use serde::{Deserialize, Serialize};
-    use std::collections::HashMap;
+    use std::collections::{HashMap, HashSet, VecDeque};
     use std::fs::File;
-    use std::io::BufReader;
+    use std::io::{BufRead, BufReader};
     use std::path::Path;
 
     #[derive(Debug, Serialize, Deserialize)]
@@ -17,6 +18,61 @@ pub mod graph {
     struct EdgeData {
         from: u64,
         to: u64,
+        /// Strands of a GFA link; the JSON layout only has forward edges
+        #[serde(default)]
+        from_reverse: bool,
+        #[serde(default)]
+        to_reverse: bool,
+    }
+
+    /// GFA path: a named walk over oriented nodes (true = reverse strand)
+    #[derive(Debug)]
+    struct PathData {
+        name: String,
+        steps: Vec<(u64, bool)>,
+    }
+
+    /// One step of a chromosome's coordinate path: the node covering the
+    /// 0-based half-open interval [start, end)
+    #[derive(Debug, Clone, Copy)]
+    struct PathStep {
+        start: u64,
+        end: u64,
+        node: u64,
+    }
+
+    /// Superbubble: every walk from `entrance` reaches `exit` through the
+    /// `interior` nodes only, with no cycles or tips inside
+    #[derive(Debug, Clone)]
+    pub struct Bubble {
+        pub entrance: u64,
+        pub exit: u64,
+        pub interior: Vec<u64>,
+    }
+
+    /// Bubble context of a node: how many bubbles it sits inside and the
+    /// interior size of the innermost one
+    #[derive(Debug, Clone, Copy, Default, PartialEq)]
+    pub struct BubbleContext {
+        pub depth: u32,
+        pub interior_nodes: usize,
+    }
+
+    /// Nodes within some hops of a node and the edges among them
+    #[derive(Debug, Clone, Default)]
+    pub struct Subgraph {
+        pub nodes: Vec<u64>,
+        pub edges: Vec<(u64, u64)>,
+    }
+
+    /// Summary of the graph context within some hops of a node
+    #[derive(Debug, Clone, Copy, Default, PartialEq)]
+    pub struct Neighborhood {
+        pub node_count: usize,
+        pub edge_count: usize,
+        /// Mean whole-graph degree of the neighbourhood nodes
+        pub mean_degree: f64,
+        pub sequence_length: u64,
     }
 
     #[derive(Debug, Serialize, Deserialize)]
@@ -30,10 +86,321 @@ pub mod graph {
     pub struct Graph {
         nodes: HashMap<u64, NodeData>,
         edges: Vec<EdgeData>,
-        chrom_pos_map: HashMap<(String, u64), u64>, // (chrom, pos) -> node_id
+        paths: Vec<PathData>,
+        /// Steps of each chromosome's coordinate path, sorted by start
+        path_index: HashMap<String, Vec<PathStep>>,
+        /// Indices into `edges` of the edges touching each node
+        adjacency: HashMap<u64, Vec<usize>>,
+        degree: HashMap<u64, u32>,
+        betweenness: HashMap<u64, f64>,
+        eigenvector: HashMap<u64, f64>,
+        bubbles: Vec<Bubble>,
+        bubble_context: HashMap<u64, BubbleContext>,
     }
 
+    /// Source nodes sampled for approximate betweenness; graphs up to this
+    /// size get the exact value
+    const BETWEENNESS_SAMPLES: usize = 256;
+
+    /// Power-iteration limit for eigenvector centrality
+    const EIGENVECTOR_ITERATIONS: usize = 100;
+
+    /// Nodes a single superbubble search may visit before giving up
+    const MAX_BUBBLE_NODES: usize = 10_000;
+
     impl Graph {
+        /// Graph over loaded nodes and edges, with its adjacency index,
+        /// centralities and bubbles computed once
+        fn indexed(
+            nodes: HashMap<u64, NodeData>,
+            edges: Vec<EdgeData>,
+            paths: Vec<PathData>,
+            mut path_index: HashMap<String, Vec<PathStep>>,
+        ) -> Self {
+            for steps in path_index.values_mut() {
+                steps.sort_by_key(|step| step.start);
+            }
+            let mut adjacency: HashMap<u64, Vec<usize>> = HashMap::new();
+            for (i, edge) in edges.iter().enumerate() {
+                adjacency.entry(edge.from).or_default().push(i);
+                if edge.to != edge.from {
+                    adjacency.entry(edge.to).or_default().push(i);
+                }
+            }
+            let degree = adjacency.iter().map(|(&id, e)| (id, e.len() as u32)).collect();
+            let mut graph = Graph {
+                nodes,
+                edges,
+                paths,
+                path_index,
+                adjacency,
+                degree,
+                betweenness: HashMap::new(),
+                eigenvector: HashMap::new(),
+                bubbles: Vec::new(),
+                bubble_context: HashMap::new(),
+            };
+            graph.compute_centrality();
+            graph.compute_bubbles();
+            graph
+        }
+
+        /// Distinct neighbours of every node (edges taken as undirected,
+        /// self-loops dropped) over dense indices, with the index -> id map
+        fn neighbour_lists(&self) -> (Vec<u64>, Vec<Vec<usize>>) {
+            let mut ids = self.nodes.keys().copied().collect::<Vec<_>>();
+            ids.sort_unstable();
+            let index = ids.iter().enumerate().map(|(i, &id)| (id, i)).collect::<HashMap<_, _>>();
+            let mut neighbours = vec![Vec::new(); ids.len()];
+            for edge in &self.edges {
+                if let (Some(&a), Some(&b)) = (index.get(&edge.from), index.get(&edge.to)) {
+                    if a != b {
+                        neighbours[a].push(b);
+                        neighbours[b].push(a);
+                    }
+                }
+            }
+            for list in &mut neighbours {
+                list.sort_unstable();
+                list.dedup();
+            }
+            (ids, neighbours)
+        }
+
+        /// Betweenness by Brandes' algorithm from up to `BETWEENNESS_SAMPLES`
+        /// seeded-random sources (scaled up to all sources), normalised to
+        /// [0, 1]; eigenvector centrality by power iteration on A + I,
+        /// scaled so the most central node is 1
+        fn compute_centrality(&mut self) {
+            let (ids, neighbours) = self.neighbour_lists();
+            let n = ids.len();
+            if n == 0 {
+                return;
+            }
+
+            let mut sources = (0..n).collect::<Vec<_>>();
+            if n > BETWEENNESS_SAMPLES {
+                sources.shuffle(&mut StdRng::seed_from_u64(n as u64));
+                sources.truncate(BETWEENNESS_SAMPLES);
+            }
+            let mut betweenness = vec![0.0; n];
+            let (mut sigma, mut distance, mut delta) = (vec![0.0; n], vec![usize::MAX; n], vec![0.0; n]);
+            let mut order = Vec::with_capacity(n);
+            let mut queue = VecDeque::new();
+            for &source in &sources {
+                sigma.iter_mut().for_each(|s| *s = 0.0);
+                distance.iter_mut().for_each(|d| *d = usize::MAX);
+                delta.iter_mut().for_each(|d| *d = 0.0);
+                order.clear();
+                sigma[source] = 1.0;
+                distance[source] = 0;
+                queue.push_back(source);
+                while let Some(v) = queue.pop_front() {
+                    order.push(v);
+                    for &w in &neighbours[v] {
+                        if distance[w] == usize::MAX {
+                            distance[w] = distance[v] + 1;
+                            queue.push_back(w);
+                        }
+                        if distance[w] == distance[v] + 1 {
+                            sigma[w] += sigma[v];
+                        }
+                    }
+                }
+                for &w in order.iter().rev() {
+                    for &v in &neighbours[w] {
+                        if distance[v] != usize::MAX && distance[v] + 1 == distance[w] {
+                            delta[v] += sigma[v] / sigma[w] * (1.0 + delta[w]);
+                        }
+                    }
+                    if w != source {
+                        betweenness[w] += delta[w];
+                    }
+                }
+            }
+            // Undirected pairs are counted from both ends
+            let scale = if n > 2 {
+                n as f64 / sources.len() as f64 / ((n - 1) * (n - 2)) as f64
+            } else {
+                0.0
+            };
+            self.betweenness = ids.iter().zip(&betweenness).map(|(&id, &b)| (id, b * scale)).collect();
+
+            let mut x = vec![1.0; n];
+            for _ in 0..EIGENVECTOR_ITERATIONS {
+                let mut next = (0..n).map(|v| x[v] + neighbours[v].iter().map(|&w| x[w]).sum::<f64>()).collect::<Vec<_>>();
+                let max = next.iter().cloned().fold(0.0, f64::max);
+                next.iter_mut().for_each(|v| *v /= max);
+                let change = next.iter().zip(&x).map(|(a, b)| (a - b).abs()).sum::<f64>();
+                x = next;
+                if change < 1e-9 * n as f64 {
+                    break;
+                }
+            }
+            self.eigenvector = ids.into_iter().zip(x).collect();
+        }
+
+        /// Superbubbles of the bidirected graph, found with Onodera et al.'s
+        /// search from every oriented node. Each bubble is seen once per
+        /// strand; the mirrored copy is dropped.
+        fn compute_bubbles(&mut self) {
+            let mut ids = self.nodes.keys().copied().collect::<Vec<_>>();
+            ids.sort_unstable();
+            let index = ids.iter().enumerate().map(|(i, &id)| (id, i)).collect::<HashMap<_, _>>();
+            // Oriented node 2i is node i forward, 2i + 1 reverse
+            let side = |id: u64, reverse: bool| index.get(&id).map(|&i| 2 * i + reverse as usize);
+            let mut children = vec![Vec::new(); 2 * ids.len()];
+            let mut parents = vec![Vec::new(); 2 * ids.len()];
+            for edge in &self.edges {
+                let arcs = [
+                    (side(edge.from, edge.from_reverse), side(edge.to, edge.to_reverse)),
+                    (side(edge.to, !edge.to_reverse), side(edge.from, !edge.from_reverse)),
+                ];
+                for (from, to) in arcs {
+                    if let (Some(from), Some(to)) = (from, to) {
+                        children[from].push(to);
+                        parents[to].push(from);
+                    }
+                }
+            }
+            for list in children.iter_mut().chain(parents.iter_mut()) {
+                list.sort_unstable();
+                list.dedup();
+            }
+
+            let mut found = HashSet::new();
+            for entrance in 0..children.len() {
+                let Some((exit, interior)) = superbubble(entrance, &children, &parents) else {
+                    continue;
+                };
+                let (a, b) = (ids[entrance / 2], ids[exit / 2]);
+                if interior.is_empty() || !found.insert((a.min(b), a.max(b))) {
+                    continue;
+                }
+                let mut interior = interior.into_iter().map(|v| ids[v / 2]).collect::<Vec<_>>();
+                interior.sort_unstable();
+                interior.dedup();
+                self.bubbles.push(Bubble { entrance: a, exit: b, interior });
+            }
+
+            for bubble in &self.bubbles {
+                for &id in &bubble.interior {
+                    let context = self.bubble_context.entry(id).or_default();
+                    context.interior_nodes = match context.depth {
+                        0 => bubble.interior.len(),
+                        _ => context.interior_nodes.min(bubble.interior.len()),
+                    };
+                    context.depth += 1;
+                }
+            }
+        }
+
+        /// Load a graph by extension: `.gfa` is GFA v1, `.json` the JSON
+        /// layout. Native `.og` files must be converted to GFA first.
+        pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+            let path = path.as_ref();
+            match path.extension().and_then(|e| e.to_str()) {
+                Some("gfa") => Self::from_gfa_path(path),
+                Some("og") => Err(format!(
+                    "{}: native .og graphs are not supported; convert with `odgi view -i {} -g > graph.gfa`",
+                    path.display(),
+                    path.display()
+                )),
+                _ => Self::from_json_path(path),
+            }
+        }
+
+        /// Parse a GFA v1 graph: `S` segments, `L` links and `P` paths
+        /// (other record types are skipped). Segment names must be numeric
+        /// node ids, as in graphs written by odgi. A path's name is its
+        /// chromosome, with PanSN names (`sample#haplotype#contig`) reduced
+        /// to the contig. The first path of each chromosome provides its
+        /// coordinates, so list the reference path first: its nodes are
+        /// placed at their 0-based offsets, and nodes off it have none.
+        pub fn from_gfa_path<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+            let file = File::open(path).map_err(|e| e.to_string())?;
+            let mut nodes = HashMap::new();
+            let mut edges = Vec::new();
+            let mut paths = Vec::new();
+
+            for (n, line) in BufReader::new(file).lines().enumerate() {
+                let line = line.map_err(|e| e.to_string())?;
+                let fields = line.split('\t').collect::<Vec<_>>();
+                let malformed = || format!("GFA line {}: malformed {} record", n + 1, fields[0]);
+                match fields[0] {
+                    "S" => {
+                        let id = gfa_node_id(fields.get(1).ok_or_else(malformed)?, n)?;
+                        let sequence = fields.get(2).ok_or_else(malformed)?;
+                        // `*` sequences only carry their length in an LN tag
+                        let sequence = if *sequence == "*" {
+                            let length = fields[3..]
+                                .iter()
+                                .find_map(|tag| tag.strip_prefix("LN:i:"))
+                                .and_then(|l| l.parse::<usize>().ok())
+                                .unwrap_or(0);
+                            "N".repeat(length)
+                        } else {
+                            sequence.to_string()
+                        };
+                        nodes.insert(id, NodeData { id, sequence, chrom: String::new(), pos: 0 });
+                    }
+                    "L" => {
+                        if fields.len() < 5 {
+                            return Err(malformed());
+                        }
+                        edges.push(EdgeData {
+                            from: gfa_node_id(fields[1], n)?,
+                            to: gfa_node_id(fields[3], n)?,
+                            from_reverse: fields[2] == "-",
+                            to_reverse: fields[4] == "-",
+                        });
+                    }
+                    "P" => {
+                        let name = fields.get(1).ok_or_else(malformed)?;
+                        let steps = fields
+                            .get(2)
+                            .ok_or_else(malformed)?
+                            .split(',')
+                            .map(|step| {
+                                let (id, reverse) = match step.strip_suffix('-') {
+                                    Some(id) => (id, true),
+                                    None => (step.strip_suffix('+').ok_or_else(malformed)?, false),
+                                };
+                                Ok((gfa_node_id(id, n)?, reverse))
+                            })
+                            .collect::<Result<Vec<_>, String>>()?;
+                        paths.push(PathData { name: name.to_string(), steps });
+                    }
+                    _ => {}
+                }
+            }
+
+            let mut path_index: HashMap<String, Vec<PathStep>> = HashMap::new();
+            for path in &paths {
+                let chrom = path.name.rsplit('#').next().unwrap_or(&path.name);
+                if path_index.contains_key(chrom) {
+                    continue;
+                }
+                let mut steps = Vec::with_capacity(path.steps.len());
+                let mut offset = 0u64;
+                for &(id, _) in &path.steps {
+                    let node = nodes
+                        .get_mut(&id)
+                        .ok_or_else(|| format!("path {} visits unknown node {}", path.name, id))?;
+                    if node.chrom.is_empty() {
+                        node.chrom = chrom.to_string();
+                        node.pos = offset;
+                    }
+                    let end = offset + node.sequence.len() as u64;
+                    steps.push(PathStep { start: offset, end, node: id });
+                    offset = end;
+                }
+                path_index.insert(chrom.to_string(), steps);
+            }
+
+            Ok(Graph::indexed(nodes, edges, paths, path_index))
+        }
+
         pub fn from_json_path<P: AsRef<Path>>(path: P) -> Result<Self, String> {
             let file = File::open(path).map_err(|e| e.to_string())?;
             let reader = BufReader::new(file);
@@ -42,18 +409,19 @@ pub mod graph {
                 .map_err(|e| format!("Failed to parse JSON: {}", e))?;
             
             let mut nodes = HashMap::new();
-            let mut chrom_pos_map = HashMap::new();
+            // No paths in this layout: each node covers its own annotation
+            let mut path_index: HashMap<String, Vec<PathStep>> = HashMap::new();
             
             for node in data.nodes {
-                chrom_pos_map.insert((node.chrom.clone(), node.pos), node.id);
+                path_index.entry(node.chrom.clone()).or_default().push(PathStep {
+                    start: node.pos,
+                    end: node.pos + node.sequence.len().max(1) as u64,
+                    node: node.id,
+                });
                 nodes.insert(node.id, node);
             }
             
-            Ok(Graph {
-                nodes,
-                edges: data.edges,
-                chrom_pos_map,
-            })
+            Ok(Graph::indexed(nodes, data.edges, Vec::new(), path_index))
         }
         
         pub fn node_count(&self) -> usize {
@@ -63,9 +431,30 @@ pub mod graph {
         pub fn edge_count(&self) -> usize {
             self.edges.len()
         }
+
+        pub fn path_count(&self) -> usize {
+            self.paths.len()
+        }
         
+        /// Node covering a 0-based position of a chromosome's coordinate path
         pub fn node_at(&self, chrom: &str, pos: u64) -> Option<u64> {
-            self.chrom_pos_map.get(&(chrom.to_string(), pos)).copied()
+            let steps = self.path_index.get(chrom)?;
+            let i = steps.partition_point(|step| step.start <= pos).checked_sub(1)?;
+            (pos < steps[i].end).then_some(steps[i].node)
+        }
+
+        /// Whether the node at a position lies strictly inside a superbubble
+        pub fn is_in_bubble(&self, chrom: &str, pos: u64) -> bool {
+            self.bubble_at(chrom, pos).is_some()
+        }
+
+        /// Bubble nesting at a position; `None` outside every bubble
+        pub fn bubble_at(&self, chrom: &str, pos: u64) -> Option<BubbleContext> {
+            self.node_at(chrom, pos).and_then(|id| self.bubble_context.get(&id).copied())
+        }
+
+        pub fn bubbles(&self) -> &[Bubble] {
+            &self.bubbles
         }
         
         pub fn degree_at(&self, chrom: &str, pos: u64) -> Option<u32> {
@@ -77,18 +466,124 @@ pub mod graph {
         }
         
         pub fn degree(&self, node_id: u64) -> u32 {
-            let mut count = 0;
-            for edge in &self.edges {
-                if edge.from == node_id || edge.to == node_id {
-                    count += 1;
+            self.degree.get(&node_id).copied().unwrap_or(0)
+        }
+        
+        /// Edges touching a node, as (from, to) node ids
+        pub fn edges_of(&self, node_id: u64) -> impl Iterator<Item = (u64, u64)> + '_ {
+            self.adjacency
+                .get(&node_id)
+                .into_iter()
+                .flatten()
+                .map(|&i| (self.edges[i].from, self.edges[i].to))
+        }
+        
+        /// Subgraph induced by the nodes at most `radius` hops from a node,
+        /// edges taken as undirected; empty for an unknown node
+        pub fn subgraph(&self, node_id: u64, radius: usize) -> Subgraph {
+            if !self.nodes.contains_key(&node_id) {
+                return Subgraph::default();
+            }
+            let mut hops = HashMap::from([(node_id, 0)]);
+            let mut queue = VecDeque::from([node_id]);
+            while let Some(v) = queue.pop_front() {
+                if hops[&v] == radius {
+                    continue;
+                }
+                for (from, to) in self.edges_of(v) {
+                    let w = if from == v { to } else { from };
+                    if !hops.contains_key(&w) && self.nodes.contains_key(&w) {
+                        hops.insert(w, hops[&v] + 1);
+                        queue.push_back(w);
+                    }
                 }
             }
-            count
+            let mut nodes = hops.into_keys().collect::<Vec<_>>();
+            nodes.sort_unstable();
+            let mut edge_ids = nodes.iter().flat_map(|id| self.adjacency.get(id).into_iter().flatten()).copied().collect::<Vec<_>>();
+            edge_ids.sort_unstable();
+            edge_ids.dedup();
+            let edges = edge_ids
+                .into_iter()
+                .map(|i| (self.edges[i].from, self.edges[i].to))
+                .filter(|(from, to)| nodes.binary_search(from).is_ok() && nodes.binary_search(to).is_ok())
+                .collect();
+            Subgraph { nodes, edges }
+        }
+
+        /// Node and edge counts, mean degree and total sequence length
+        /// within `radius` hops of a node
+        pub fn neighborhood(&self, node_id: u64, radius: usize) -> Neighborhood {
+            let subgraph = self.subgraph(node_id, radius);
+            if subgraph.nodes.is_empty() {
+                return Neighborhood::default();
+            }
+            let degree_sum = subgraph.nodes.iter().map(|&id| self.degree(id) as f64).sum::<f64>();
+            Neighborhood {
+                node_count: subgraph.nodes.len(),
+                edge_count: subgraph.edges.len(),
+                mean_degree: degree_sum / subgraph.nodes.len() as f64,
+                sequence_length: subgraph.nodes.iter().map(|id| self.nodes[id].sequence.len() as u64).sum(),
+            }
+        }
+
+        pub fn neighborhood_at(&self, chrom: &str, pos: u64, radius: usize) -> Option<Neighborhood> {
+            self.node_at(chrom, pos).map(|id| self.neighborhood(id, radius))
+        }
+
+        /// Normalised (approximate) betweenness centrality in [0, 1]
+        pub fn centrality(&self, node_id: u64) -> f64 {
+            self.betweenness.get(&node_id).copied().unwrap_or(0.0)
         }
         
-        pub fn centrality(&self, _node_id: u64) -> f64 {
-            // Mock implementation: Just return a random value between 0 and 1
-            rand::random::<f64>()
+        /// Eigenvector centrality, 1 for the most central node
+        pub fn eigenvector_centrality(&self, node_id: u64) -> f64 {
+            self.eigenvector.get(&node_id).copied().unwrap_or(0.0)
+        }
+    }
+
+    /// Superbubble entered at oriented node `s` (Onodera et al. 2013):
+    /// returns its exit and the visited interior, or `None` when the search
+    /// meets a tip, a cycle back to `s`, or grows past `MAX_BUBBLE_NODES`
+    fn superbubble(s: usize, children: &[Vec<usize>], parents: &[Vec<usize>]) -> Option<(usize, Vec<usize>)> {
+        let mut stack = vec![s];
+        let mut visited = HashSet::new();
+        let mut seen = HashSet::from([s]);
+        while let Some(v) = stack.pop() {
+            visited.insert(v);
+            seen.remove(&v);
+            if children[v].is_empty() || visited.len() > MAX_BUBBLE_NODES {
+                return None;
+            }
+            for &u in &children[v] {
+                if u == s {
+                    return None;
+                }
+                seen.insert(u);
+                if parents[u].iter().all(|p| visited.contains(p)) {
+                    stack.push(u);
+                }
+            }
+            if let [t] = stack[..] {
+                if seen.len() == 1 && seen.contains(&t) {
+                    if children[t].contains(&s) {
+                        return None;
+                    }
+                    visited.remove(&s);
+                    return Some((t, visited.into_iter().collect()));
+                }
+            }
         }
+        None
+    }
+
+    fn gfa_node_id(name: &str, line: usize) -> Result<u64, String> {
+        name.parse().map_err(|_| {
+            format!(
+                "GFA line {}: segment name '{}' is not a numeric node id; renumber the graph with integer ids first",
+                line + 1,
+                name
+            )
+        })
     }
 }
@@ -6,6 +6,38 @@ use ndarray::Array2;
 pub enum OrtError {
     #[error("ONNX runtime error: {0}")]
     RuntimeError(String),
+
+    #[error("model takes {expected} inputs, got {actual}")]
+    InputCount { expected: usize, actual: usize },
+
+    #[error("model has no input named '{0}'")]
+    UnknownInput(String),
+
+    #[error("no tensor given for model input '{0}'")]
+    MissingInput(String),
+
+    #[error("input '{name}' has shape {actual:?}, model expects {}", format_dimensions(.expected))]
+    ShapeMismatch {
+        name: String,
+        expected: Vec<Option<usize>>,
+        actual: Vec<usize>,
+    },
+}
+
+/// Shape with symbolic dimensions as `?`, e.g. `[?, 5]`
+fn format_dimensions(dimensions: &[Option<usize>]) -> String {
+    let dims = dimensions
+        .iter()
+        .map(|d| d.map_or("?".to_string(), |d| d.to_string()))
+        .collect::<Vec<_>>();
+    format!("[{}]", dims.join(", "))
+}
+
+#[cfg(feature = "real-ort")]
+impl From<ort::Error> for OrtError {
+    fn from(e: ort::Error) -> Self {
+        OrtError::RuntimeError(e.to_string())
+    }
 }
 
 type Result<T> = std::result::Result<T, OrtError>;
@@ -14,8 +46,12 @@ pub struct Environment {
     name: String,
 }
 
+/// Model session. Without the `real-ort` feature this is a mock that
+/// returns fixed scores; with it, an onnxruntime session through `ort`.
 pub struct Session {
     model_path: String,
+    #[cfg(feature = "real-ort")]
+    inner: ort::session::Session,
 }
 
 pub struct ModelMetadata {
@@ -28,6 +64,14 @@ pub struct TensorMetadata {
     pub dimensions: Vec<Option<usize>>,
 }
 
+impl TensorMetadata {
+    /// Whether a tensor shape fits, with `None` dimensions matching any size
+    pub fn accepts(&self, shape: &[usize]) -> bool {
+        shape.len() == self.dimensions.len()
+            && self.dimensions.iter().zip(shape).all(|(d, &s)| d.is_none_or(|d| d == s))
+    }
+}
+
 pub enum ExecutionProvider {
     CPU(CPUExecutionProviderOptions),
     #[cfg(feature = "cuda")]
@@ -36,8 +80,23 @@ pub enum ExecutionProvider {
 
 pub struct CPUExecutionProviderOptions {}
 
+/// CUDA execution provider settings; the default is device 0 with no
+/// memory limit
 #[cfg(feature = "cuda")]
-pub struct CUDAExecutionProviderOptions {}
+#[derive(Default)]
+pub struct CUDAExecutionProviderOptions {
+    /// CUDA device ordinal
+    pub device_id: i32,
+    /// Upper bound on the device memory arena in bytes; `None` leaves it
+    /// to onnxruntime
+    pub memory_limit: Option<usize>,
+    /// Grow the arena by exactly the requested size instead of the next
+    /// power of two, which wastes less memory near the limit
+    pub arena_same_as_requested: bool,
+    /// Fail session creation if the provider cannot be registered, instead
+    /// of letting onnxruntime continue with the next provider
+    pub error_on_failure: bool,
+}
 
 impl Default for CPUExecutionProviderOptions {
     fn default() -> Self {
@@ -45,18 +104,29 @@ impl Default for CPUExecutionProviderOptions {
     }
 }
 
+/// Whether the CUDA execution provider can be used: the mock runs
+/// anywhere; with `real-ort`, whether the onnxruntime library was built
+/// with CUDA support. A missing or out-of-range device is only detected
+/// when a session is created.
 #[cfg(feature = "cuda")]
-impl Default for CUDAExecutionProviderOptions {
-    fn default() -> Self {
-        Self {}
+pub fn cuda_available() -> Result<bool> {
+    #[cfg(feature = "real-ort")]
+    {
+        use ort::execution_providers::ExecutionProvider as _;
+        Ok(ort::execution_providers::CUDAExecutionProvider::default().is_available()?)
     }
+    #[cfg(not(feature = "real-ort"))]
+    Ok(true)
 }
 
 pub struct SessionBuilder {
     environment: Environment,
+    providers: Vec<ExecutionProvider>,
 }
 
 pub struct OrtOwnedTensor<T> {
+    name: String,
+    shape: Vec<usize>,
     data: T,
 }
 
@@ -64,8 +134,8 @@ pub mod ndarray_tensor {
     use ndarray::Array2;
 
     pub struct NdArrayTensor {
-        data: Vec<f32>,
-        shape: Vec<usize>,
+        pub(crate) data: Vec<f32>,
+        pub(crate) shape: Vec<usize>,
     }
 
     impl NdArrayTensor {
@@ -86,6 +156,15 @@ pub mod tensor {
         pub fn float_array(&self) -> Result<&[f32]> {
             Ok(&self.data)
         }
+
+        /// Model output this tensor was produced for
+        pub fn name(&self) -> &str {
+            &self.name
+        }
+
+        pub fn shape(&self) -> &[usize] {
+            &self.shape
+        }
     }
 }
 
@@ -110,9 +189,11 @@ impl EnvironmentBuilder {
     }
 
     pub fn build(self) -> Result<Environment> {
-        Ok(Environment {
-            name: self.name.unwrap_or_else(|| "default".to_string()),
-        })
+        let name = self.name.unwrap_or_else(|| "default".to_string());
+        // ort keeps one process-wide environment
+        #[cfg(feature = "real-ort")]
+        ort::init().with_name(&name).commit()?;
+        Ok(Environment { name })
     }
 }
 
@@ -122,31 +203,75 @@ impl Environment {
             environment: Environment {
                 name: self.name.clone(),
             },
+            providers: Vec::new(),
         })
     }
 }
 
 impl SessionBuilder {
     pub fn with_execution_providers<const N: usize>(
-        self,
-        _providers: [ExecutionProvider; N],
+        mut self,
+        providers: [ExecutionProvider; N],
     ) -> Result<Self> {
+        self.providers.extend(providers);
         Ok(self)
     }
 
+    #[cfg(not(feature = "real-ort"))]
+    pub fn with_model_from_file<P: AsRef<Path>>(self, path: P) -> Result<Session> {
+        Ok(Session {
+            model_path: path.as_ref().to_string_lossy().to_string(),
+        })
+    }
+
+    #[cfg(feature = "real-ort")]
     pub fn with_model_from_file<P: AsRef<Path>>(self, path: P) -> Result<Session> {
+        use ort::execution_providers::CPUExecutionProvider;
+
+        let providers = self
+            .providers
+            .iter()
+            .map(|provider| match provider {
+                ExecutionProvider::CPU(_) => CPUExecutionProvider::default().build(),
+                #[cfg(feature = "cuda")]
+                ExecutionProvider::CUDA(options) => {
+                    use ort::execution_providers::{ArenaExtendStrategy, CUDAExecutionProvider};
+
+                    let mut cuda = CUDAExecutionProvider::default().with_device_id(options.device_id);
+                    if let Some(limit) = options.memory_limit {
+                        cuda = cuda.with_memory_limit(limit);
+                    }
+                    if options.arena_same_as_requested {
+                        cuda = cuda.with_arena_extend_strategy(ArenaExtendStrategy::SameAsRequested);
+                    }
+                    let cuda = cuda.build();
+                    if options.error_on_failure {
+                        cuda.error_on_failure()
+                    } else {
+                        cuda
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+        let inner = ort::session::Session::builder()?
+            .with_execution_providers(providers)?
+            .commit_from_file(path.as_ref())?;
         Ok(Session {
             model_path: path.as_ref().to_string_lossy().to_string(),
+            inner,
         })
     }
 }
 
 impl Session {
+    /// The mock model takes one feature matrix of any width and returns one
+    /// score per row
+    #[cfg(not(feature = "real-ort"))]
     pub fn model_metadata(&self) -> Result<ModelMetadata> {
         Ok(ModelMetadata {
             inputs: vec![TensorMetadata {
                 name: "input".to_string(),
-                dimensions: vec![None, Some(5)],
+                dimensions: vec![None, None],
             }],
             outputs: vec![TensorMetadata {
                 name: "output".to_string(),
@@ -155,10 +280,119 @@ impl Session {
         })
     }
 
-    pub fn run<T>(&self, _inputs: Vec<T>) -> Result<Vec<OrtOwnedTensor<Vec<f32>>>> {
-        // Return mock scores - in a real implementation this would run the model
-        let mock_scores = vec![0.5, 0.7, 0.3, 0.8, 0.6, 0.4, 0.9, 0.2, 0.65, 0.75];
-        
-        Ok(vec![OrtOwnedTensor { data: mock_scores }])
+    /// Input and output names and shapes; symbolic (-1) dimensions are `None`
+    #[cfg(feature = "real-ort")]
+    pub fn model_metadata(&self) -> Result<ModelMetadata> {
+        fn tensor(name: &str, value_type: &ort::value::ValueType) -> TensorMetadata {
+            let dimensions = match value_type {
+                ort::value::ValueType::Tensor { dimensions, .. } => {
+                    dimensions.iter().map(|&d| usize::try_from(d).ok()).collect()
+                }
+                _ => Vec::new(),
+            };
+            TensorMetadata { name: name.to_string(), dimensions }
+        }
+        Ok(ModelMetadata {
+            inputs: self.inner.inputs.iter().map(|i| tensor(&i.name, &i.input_type)).collect(),
+            outputs: self.inner.outputs.iter().map(|o| tensor(&o.name, &o.output_type)).collect(),
+        })
     }
-}
\ No newline at end of file
+
+    /// Run the model with one tensor per model input, in declaration order;
+    /// outputs come back in the model's output order
+    pub fn run(&self, inputs: Vec<ndarray_tensor::NdArrayTensor>) -> Result<Vec<OrtOwnedTensor<Vec<f32>>>> {
+        let metadata = self.model_metadata()?;
+        if inputs.len() != metadata.inputs.len() {
+            return Err(OrtError::InputCount {
+                expected: metadata.inputs.len(),
+                actual: inputs.len(),
+            });
+        }
+        let named = metadata.inputs.into_iter().map(|i| i.name).zip(inputs).collect();
+        self.run_named(named)
+    }
+
+    /// Run the model with tensors keyed by input name. Every model input
+    /// must be given once, with a shape matching its declared dimensions.
+    pub fn run_named(&self, inputs: Vec<(String, ndarray_tensor::NdArrayTensor)>) -> Result<Vec<OrtOwnedTensor<Vec<f32>>>> {
+        let metadata = self.model_metadata()?;
+        for (name, tensor) in &inputs {
+            let declared = metadata
+                .inputs
+                .iter()
+                .find(|i| &i.name == name)
+                .ok_or_else(|| OrtError::UnknownInput(name.clone()))?;
+            if !declared.accepts(&tensor.shape) {
+                return Err(OrtError::ShapeMismatch {
+                    name: name.clone(),
+                    expected: declared.dimensions.clone(),
+                    actual: tensor.shape.clone(),
+                });
+            }
+        }
+        for declared in &metadata.inputs {
+            match inputs.iter().filter(|(name, _)| name == &declared.name).count() {
+                0 => return Err(OrtError::MissingInput(declared.name.clone())),
+                1 => {}
+                _ => {
+                    return Err(OrtError::RuntimeError(format!(
+                        "{}: input '{}' given more than once",
+                        self.model_path, declared.name
+                    )))
+                }
+            }
+        }
+        self.execute(inputs, metadata)
+    }
+
+    #[cfg(not(feature = "real-ort"))]
+    fn execute(
+        &self,
+        inputs: Vec<(String, ndarray_tensor::NdArrayTensor)>,
+        metadata: ModelMetadata,
+    ) -> Result<Vec<OrtOwnedTensor<Vec<f32>>>> {
+        // Return mock scores - in a real implementation this would run the model.
+        // Symbolic output dimensions take the batch size of the first input.
+        let mock_scores = [0.5, 0.7, 0.3, 0.8, 0.6, 0.4, 0.9, 0.2, 0.65, 0.75];
+        let batch = inputs.first().and_then(|(_, t)| t.shape.first().copied()).unwrap_or(1);
+
+        Ok(metadata
+            .outputs
+            .into_iter()
+            .map(|output| {
+                let shape = output.dimensions.iter().map(|d| d.unwrap_or(batch)).collect::<Vec<_>>();
+                let len = shape.iter().product();
+                let data = mock_scores.iter().copied().cycle().take(len).collect();
+                OrtOwnedTensor { name: output.name, shape, data }
+            })
+            .collect())
+    }
+
+    #[cfg(feature = "real-ort")]
+    fn execute(
+        &self,
+        inputs: Vec<(String, ndarray_tensor::NdArrayTensor)>,
+        metadata: ModelMetadata,
+    ) -> Result<Vec<OrtOwnedTensor<Vec<f32>>>> {
+        let inputs = inputs
+            .into_iter()
+            .map(|(name, tensor)| {
+                let shape = tensor.shape.iter().map(|&d| d as i64).collect::<Vec<_>>();
+                Ok((name, ort::value::Tensor::from_array((shape, tensor.data))?.into_dyn()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let outputs = self.inner.run(inputs)?;
+        metadata
+            .outputs
+            .into_iter()
+            .map(|output| {
+                let (shape, data) = outputs[output.name.as_str()].try_extract_raw_tensor::<f32>()?;
+                Ok(OrtOwnedTensor {
+                    shape: shape.iter().map(|&d| d as usize).collect(),
+                    data: data.to_vec(),
+                    name: output.name,
+                })
+            })
+            .collect()
+    }
+}
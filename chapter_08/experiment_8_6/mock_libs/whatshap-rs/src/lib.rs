@@ -1,25 +1,624 @@
-use rust_htslib::bcf;
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
+use rust_htslib::bam::{self, ext::BamRecordExtensions, Read as _};
+use rust_htslib::bcf::{self, record::GenotypeAllele, record::Numeric, Read as _};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 
-pub fn phase_block(record: &mut bcf::Record, _window_size: i32) -> Result<String, String> {
-    // This is a mock implementation that just assigns a phase block based on chromosome and position
-    // Get the chromosome name (using rid and header instead of chrom())
+/// Read filters and linking limits for read-backed phasing
+#[derive(Debug, Clone)]
+pub struct PhasingOptions {
+    /// Reads below this mapping quality are ignored
+    pub min_mapq: u8,
+    /// Allele observations below this base quality are ignored
+    pub min_base_quality: u8,
+    /// Two heterozygous sites are only linked when at most this many bp apart
+    pub max_link_distance: i64,
+}
+
+impl Default for PhasingOptions {
+    fn default() -> Self {
+        Self { min_mapq: 20, min_base_quality: 13, max_link_distance: 1000 }
+    }
+}
+
+/// Phase of one heterozygous site: its phase set (PS, the 1-based position
+/// of the block's first site) and the allele on the first haplotype
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PhasedSite {
+    pub phase_set: i64,
+    pub first_haplotype_allele: u8,
+}
+
+/// Phasing of one sample of a VCF/BCF, from reads or by transmission
+#[derive(Debug, Default)]
+pub struct Phasing {
+    sites: HashMap<(String, i64), PhasedSite>,
+    /// Index and name of the phased sample in the VCF header
+    sample: usize,
+    sample_name: String,
+    blocks: usize,
+    heterozygous: usize,
+    ambiguous: usize,
+    mendelian_errors: usize,
+}
+
+/// Summary of a phasing: block sizes, unphased heterozygous sites and,
+/// against a truth phasing, switch and Hamming errors
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PhasingStats {
+    pub heterozygous_sites: usize,
+    pub phased_sites: usize,
+    pub unphased_sites: usize,
+    pub blocks: usize,
+    /// Mean number of sites per block
+    pub mean_block_sites: f64,
+    /// Span (first to last site) of the largest block, in bp
+    pub largest_block_bp: i64,
+    /// Half of all phased bp lie in blocks spanning at least this much
+    pub block_n50_bp: i64,
+    /// Trio mode only: sites left ambiguous and Mendelian errors
+    pub ambiguous_sites: usize,
+    pub mendelian_errors: usize,
+    pub truth: Option<TruthComparison>,
+}
+
+/// Agreement with a truth phasing, over sites phased in both. Sites are
+/// compared within segments that share a block in both phasings.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TruthComparison {
+    /// Sites in segments of two or more shared sites
+    pub compared_sites: usize,
+    /// Adjacent site pairs checked for switches
+    pub assessed_pairs: usize,
+    pub switch_errors: usize,
+    pub switch_error_rate: f64,
+    /// Sites to flip for each segment to match the truth
+    pub hamming_errors: usize,
+    pub hamming_error_rate: f64,
+}
+
+/// A child and both parents, by VCF sample name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trio {
+    pub child: String,
+    pub mother: String,
+    pub father: String,
+}
+
+/// Outcome of phasing one trio site by Mendelian transmission
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transmission {
+    /// Child heterozygous with a unique parent of origin for each allele
+    Phased { paternal: u32, maternal: u32 },
+    /// Child homozygous: nothing to phase
+    Homozygous,
+    /// Both parents could have given either allele (e.g. all three 0/1)
+    Ambiguous,
+    /// No combination of parental alleles gives the child's genotype
+    MendelianError,
+    /// A genotype is missing or not diploid
+    Missing,
+}
+
+/// Phase the child's genotype by Mendelian transmission from its parents
+pub fn transmit(child: &[GenotypeAllele], mother: &[GenotypeAllele], father: &[GenotypeAllele]) -> Transmission {
+    let diploid = |gt: &[GenotypeAllele]| match gt {
+        [a, b] => Some([a.index()?, b.index()?]),
+        _ => None,
+    };
+    let (Some(child), Some(mother), Some(father)) = (diploid(child), diploid(mother), diploid(father)) else {
+        return Transmission::Missing;
+    };
+    let consistent = [(child[0], child[1]), (child[1], child[0])]
+        .into_iter()
+        .filter(|(paternal, maternal)| father.contains(paternal) && mother.contains(maternal))
+        .collect::<Vec<_>>();
+    match consistent.as_slice() {
+        [] => Transmission::MendelianError,
+        _ if child[0] == child[1] => Transmission::Homozygous,
+        [(paternal, maternal)] => Transmission::Phased { paternal: *paternal, maternal: *maternal },
+        _ => Transmission::Ambiguous,
+    }
+}
+
+/// Trios in a PED file (family, individual, father, mother, sex,
+/// phenotype; whitespace separated, `0` for an unknown parent). Only
+/// individuals with both parents listed are returned.
+pub fn read_pedigree(path: &str) -> Result<Vec<Trio>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut trios = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        if fields.is_empty() || fields[0].starts_with('#') {
+            continue;
+        }
+        if fields.len() < 4 {
+            return Err(format!("{} line {}: expected family, individual, father and mother", path, n + 1));
+        }
+        if fields[2] != "0" && fields[3] != "0" {
+            trios.push(Trio {
+                child: fields[1].to_string(),
+                father: fields[2].to_string(),
+                mother: fields[3].to_string(),
+            });
+        }
+    }
+    Ok(trios)
+}
+
+impl Trio {
+    /// Header indices of the child, mother and father, if all are present
+    pub fn samples(&self, header: &bcf::header::HeaderView) -> Option<[usize; 3]> {
+        let index = |name: &str| header.sample_id(name.as_bytes());
+        Some([index(&self.child)?, index(&self.mother)?, index(&self.father)?])
+    }
+}
+
+/// Phase the child of `trio` in a joint-called VCF by transmission. The
+/// first haplotype is the paternal one. Transmission phase holds along a
+/// whole chromosome, so each contig is one block whose PS is the position
+/// of its first phased site. Biallelic sites of any length are phased;
+/// sites where every member is heterozygous stay unphased.
+pub fn phase_trio_vcf(vcf_path: &str, trio: &Trio) -> Result<Phasing, String> {
+    let mut reader = bcf::Reader::from_path(vcf_path).map_err(|e| format!("{}: {}", vcf_path, e))?;
+    let [child, mother, father] = trio
+        .samples(reader.header())
+        .ok_or_else(|| format!("{}: samples {}, {} and {} not all present", vcf_path, trio.child, trio.mother, trio.father))?;
+    let mut phasing = Phasing { sample: child, sample_name: trio.child.clone(), ..Default::default() };
+    let mut phase_set: Option<(String, i64)> = None;
+    for record in reader.records() {
+        let record = record.map_err(|e| e.to_string())?;
+        if record.alleles().len() != 2 {
+            continue;
+        }
+        let Ok(genotypes) = record.genotypes() else { continue };
+        let outcome = transmit(&genotypes.get(child), &genotypes.get(mother), &genotypes.get(father));
+        let paternal = match outcome {
+            Transmission::Phased { paternal, .. } => paternal as u8,
+            Transmission::Ambiguous => {
+                phasing.heterozygous += 1;
+                phasing.ambiguous += 1;
+                continue;
+            }
+            Transmission::MendelianError => {
+                phasing.mendelian_errors += 1;
+                continue;
+            }
+            Transmission::Homozygous | Transmission::Missing => continue,
+        };
+        phasing.heterozygous += 1;
+        let chrom = record_chrom(&record)?;
+        let ps = match &phase_set {
+            Some((name, ps)) if *name == chrom => *ps,
+            _ => {
+                phasing.blocks += 1;
+                phase_set.insert((chrom.clone(), record.pos() + 1)).1
+            }
+        };
+        phasing.sites.insert((chrom, record.pos()), PhasedSite { phase_set: ps, first_haplotype_allele: paternal });
+    }
+    Ok(phasing)
+}
+
+/// Biallelic heterozygous SNV of the phased sample; `pos` is 0-based
+struct HetSite {
+    pos: i64,
+    ref_base: u8,
+    alt_base: u8,
+}
+
+/// Union-find over sites that also tracks each site's allele parity
+/// relative to its root (1 = opposite haplotype)
+struct ParityForest {
+    parent: Vec<usize>,
+    parity: Vec<u8>,
+}
+
+impl ParityForest {
+    fn new(n: usize) -> Self {
+        Self { parent: (0..n).collect(), parity: vec![0; n] }
+    }
+
+    fn find(&mut self, i: usize) -> (usize, u8) {
+        if self.parent[i] == i {
+            return (i, 0);
+        }
+        let (root, parity) = self.find(self.parent[i]);
+        self.parent[i] = root;
+        self.parity[i] ^= parity;
+        (root, self.parity[i])
+    }
+
+    /// Join the trees of `a` and `b` so that their parities differ by
+    /// `relation`; false if they were already connected
+    fn union(&mut self, a: usize, b: usize, relation: u8) -> bool {
+        let (root_a, parity_a) = self.find(a);
+        let (root_b, parity_b) = self.find(b);
+        if root_a == root_b {
+            return false;
+        }
+        self.parent[root_b] = root_a;
+        self.parity[root_b] = parity_a ^ parity_b ^ relation;
+        true
+    }
+}
+
+/// Phase the heterozygous SNVs of the first sample in `vcf_path` from the
+/// reads in the indexed `bam_path`.
+///
+/// Each read pair contributes the alleles it shows at the sites it covers.
+/// Consecutive sites on a fragment become an edge of the variant connection
+/// graph, weighted by the lower base quality: positive when the fragment
+/// carries the same allele index at both sites, negative otherwise. Edges
+/// are then added heaviest first into a spanning forest, so conflicting
+/// evidence is resolved in favour of the better supported relation. Every
+/// tree of two or more sites is one phase block.
+pub fn phase_vcf(vcf_path: &str, bam_path: &str, options: &PhasingOptions) -> Result<Phasing, String> {
+    let mut reader = bcf::Reader::from_path(vcf_path).map_err(|e| format!("{}: {}", vcf_path, e))?;
+    let mut contigs: Vec<(String, Vec<HetSite>)> = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(|e| e.to_string())?;
+        let Some(site) = het_snv(&record) else { continue };
+        let chrom = record_chrom(&record)?;
+        match contigs.last_mut() {
+            Some((name, sites)) if *name == chrom => sites.push(site),
+            _ => contigs.push((chrom, vec![site])),
+        }
+    }
+
+    let mut bam = bam::IndexedReader::from_path(bam_path).map_err(|e| format!("{}: {}", bam_path, e))?;
+    let mut phasing = Phasing {
+        sample_name: reader.header().samples().first().map(|s| String::from_utf8_lossy(s).into_owned()).unwrap_or_default(),
+        ..Default::default()
+    };
+    for (chrom, mut sites) in contigs {
+        sites.sort_by_key(|s| s.pos);
+        sites.dedup_by_key(|s| s.pos);
+        phasing.heterozygous += sites.len();
+        if sites.len() < 2 || bam.header().tid(chrom.as_bytes()).is_none() {
+            continue;
+        }
+        let fragments = read_fragments(&mut bam, &chrom, &sites, options)?;
+        phasing.add_blocks(&chrom, &sites, &fragments, options);
+    }
+    Ok(phasing)
+}
+
+/// Phase of `sample` (the first sample when absent) in a phased truth VCF.
+/// Its sites are the heterozygous biallelic genotypes written with `|`.
+/// FORMAT PS groups them into blocks; without PS each contig is one block.
+pub fn read_truth(vcf_path: &str, sample: &str) -> Result<Phasing, String> {
+    let mut reader = bcf::Reader::from_path(vcf_path).map_err(|e| format!("{}: {}", vcf_path, e))?;
+    let index = reader.header().sample_id(sample.as_bytes()).unwrap_or(0);
+    let mut truth = Phasing { sample: index, sample_name: sample.to_string(), ..Default::default() };
+    let mut blocks = std::collections::HashSet::new();
+    for record in reader.records() {
+        let record = record.map_err(|e| e.to_string())?;
+        if record.alleles().len() != 2 || record.sample_count() as usize <= index {
+            continue;
+        }
+        let Ok(genotypes) = record.genotypes() else { continue };
+        let genotype = genotypes.get(index);
+        let [first, second @ GenotypeAllele::Phased(_)] = genotype.as_slice() else { continue };
+        let (Some(first), Some(second)) = (first.index(), second.index()) else { continue };
+        if first + second != 1 {
+            continue;
+        }
+        let phase_set = match record.format(b"PS").integer() {
+            Ok(ps) => ps[index].first().copied().filter(|v| !v.is_missing() && !v.is_vector_end()).unwrap_or(0),
+            Err(_) => 0,
+        } as i64;
+        let chrom = record_chrom(&record)?;
+        blocks.insert((chrom.clone(), phase_set));
+        truth.heterozygous += 1;
+        truth.sites.insert((chrom, record.pos()), PhasedSite { phase_set, first_haplotype_allele: first as u8 });
+    }
+    truth.blocks = blocks.len();
+    Ok(truth)
+}
+
+fn record_chrom(record: &bcf::Record) -> Result<String, String> {
     let rid = record.rid().ok_or_else(|| "Record has no RID".to_string())?;
-    let header = record.header();
-    let chrom = std::str::from_utf8(header.rid2name(rid).map_err(|e| e.to_string())?)
-        .map_err(|e| e.to_string())?;
-    
-    // Get position
-    let pos = record.pos();
-    
-    // Create a deterministic phase block ID based on chromosome and position range
-    let phase_block_id = format!("{}:{}", chrom, pos / 1000 * 1000);
-    
-    // Hash the phase block ID to create a shorter identifier
-    let mut hasher = DefaultHasher::new();
-    phase_block_id.hash(&mut hasher);
-    let hash_value = hasher.finish();
-    
-    Ok(format!("PB{:X}", hash_value % 1000))
+    let name = record.header().rid2name(rid).map_err(|e| e.to_string())?;
+    std::str::from_utf8(name).map(str::to_owned).map_err(|e| e.to_string())
+}
+
+/// The record as a phasable site: a biallelic SNV the first sample calls 0/1
+fn het_snv(record: &bcf::Record) -> Option<HetSite> {
+    let alleles = record.alleles();
+    if alleles.len() != 2 || alleles[0].len() != 1 || alleles[1].len() != 1 || record.sample_count() == 0 {
+        return None;
+    }
+    let genotypes = record.genotypes().ok()?;
+    let mut indices = genotypes.get(0).iter().map(|allele| allele.index()).collect::<Vec<_>>();
+    indices.sort();
+    if indices != [Some(0), Some(1)] {
+        return None;
+    }
+    Some(HetSite {
+        pos: record.pos(),
+        ref_base: alleles[0][0].to_ascii_uppercase(),
+        alt_base: alleles[1][0].to_ascii_uppercase(),
+    })
+}
+
+/// Allele observations (site index, allele, base quality) per read name,
+/// so both mates of a pair form one fragment. A site seen with both
+/// alleles in one fragment (overlapping mates disagreeing) is dropped.
+fn read_fragments(
+    bam: &mut bam::IndexedReader,
+    chrom: &str,
+    sites: &[HetSite],
+    options: &PhasingOptions,
+) -> Result<Vec<Vec<(usize, u8, u8)>>, String> {
+    let (start, end) = (sites[0].pos, sites[sites.len() - 1].pos + 1);
+    bam.fetch((chrom, start, end)).map_err(|e| format!("{}:{}-{}: {}", chrom, start + 1, end, e))?;
+
+    let mut fragments: HashMap<Vec<u8>, Vec<(usize, u8, u8)>> = HashMap::new();
+    for record in bam.records() {
+        let record = record.map_err(|e| e.to_string())?;
+        if record.is_unmapped()
+            || record.is_secondary()
+            || record.is_supplementary()
+            || record.is_duplicate()
+            || record.is_quality_check_failed()
+            || record.mapq() < options.min_mapq
+        {
+            continue;
+        }
+        let (seq, qual) = (record.seq(), record.qual());
+        let mut next = sites.partition_point(|s| s.pos < record.pos());
+        let mut observed = Vec::new();
+        for [qpos, rpos] in record.aligned_pairs() {
+            while next < sites.len() && sites[next].pos < rpos {
+                next += 1;
+            }
+            if next == sites.len() {
+                break;
+            }
+            if sites[next].pos != rpos || qual[qpos as usize] < options.min_base_quality {
+                continue;
+            }
+            let base = seq[qpos as usize].to_ascii_uppercase();
+            let allele = if base == sites[next].ref_base {
+                0
+            } else if base == sites[next].alt_base {
+                1
+            } else {
+                continue;
+            };
+            observed.push((next, allele, qual[qpos as usize]));
+        }
+        if !observed.is_empty() {
+            fragments.entry(record.qname().to_vec()).or_default().extend(observed);
+        }
+    }
+
+    let mut informative = Vec::new();
+    for (_, mut observed) in fragments {
+        observed.sort_by_key(|&(site, allele, qual)| (site, allele, std::cmp::Reverse(qual)));
+        let mut merged: Vec<(usize, u8, u8)> = Vec::with_capacity(observed.len());
+        let mut conflicting = Vec::new();
+        for (site, allele, qual) in observed {
+            match merged.last() {
+                Some(&(s, a, _)) if s == site && a == allele => {}
+                Some(&(s, _, _)) if s == site => conflicting.push(site),
+                _ => merged.push((site, allele, qual)),
+            }
+        }
+        merged.retain(|(site, ..)| !conflicting.contains(site));
+        if merged.len() >= 2 {
+            informative.push(merged);
+        }
+    }
+    Ok(informative)
+}
+
+impl Phasing {
+    /// Build the weighted connection graph of one contig's sites and keep
+    /// its maximum spanning forest
+    fn add_blocks(&mut self, chrom: &str, sites: &[HetSite], fragments: &[Vec<(usize, u8, u8)>], options: &PhasingOptions) {
+        let mut weights: HashMap<(usize, usize), i64> = HashMap::new();
+        for fragment in fragments {
+            for pair in fragment.windows(2) {
+                let ((a, allele_a, qual_a), (b, allele_b, qual_b)) = (pair[0], pair[1]);
+                if sites[b].pos - sites[a].pos > options.max_link_distance {
+                    continue;
+                }
+                let weight = qual_a.min(qual_b) as i64;
+                *weights.entry((a, b)).or_default() += if allele_a == allele_b { weight } else { -weight };
+            }
+        }
+        let mut edges = weights.into_iter().filter(|&(_, w)| w != 0).collect::<Vec<_>>();
+        edges.sort_by_key(|&(pair, w)| (std::cmp::Reverse(w.abs()), pair));
+
+        let mut forest = ParityForest::new(sites.len());
+        let mut linked = vec![false; sites.len()];
+        for ((a, b), weight) in edges {
+            if forest.union(a, b, (weight < 0) as u8) {
+                linked[a] = true;
+                linked[b] = true;
+            }
+        }
+
+        // Sites come in position order, so the first site seen of a tree
+        // names its block and is given allele 0 on the first haplotype
+        let mut block_start: HashMap<usize, (i64, u8)> = HashMap::new();
+        for (i, site) in sites.iter().enumerate().filter(|&(i, _)| linked[i]) {
+            let (root, parity) = forest.find(i);
+            let &mut (phase_set, first_parity) = block_start.entry(root).or_insert_with(|| {
+                self.blocks += 1;
+                (site.pos + 1, parity)
+            });
+            self.sites.insert(
+                (chrom.to_string(), site.pos),
+                PhasedSite { phase_set, first_haplotype_allele: parity ^ first_parity },
+            );
+        }
+    }
+
+    /// Phase of the site at 0-based `pos`, if it was phased
+    pub fn get(&self, chrom: &str, pos: i64) -> Option<PhasedSite> {
+        self.sites.get(&(chrom.to_string(), pos)).copied()
+    }
+
+    /// Name of the phased sample
+    pub fn sample_name(&self) -> &str {
+        &self.sample_name
+    }
+
+    /// Block sizes and unphased counts, compared against `truth` if given
+    pub fn stats(&self, truth: Option<&Phasing>) -> PhasingStats {
+        let mut blocks: HashMap<(&str, i64), (i64, i64, usize)> = HashMap::new();
+        for ((chrom, pos), site) in &self.sites {
+            let block = blocks.entry((chrom.as_str(), site.phase_set)).or_insert((*pos, *pos, 0));
+            block.0 = block.0.min(*pos);
+            block.1 = block.1.max(*pos);
+            block.2 += 1;
+        }
+        let mut spans = blocks.values().map(|&(first, last, _)| last - first + 1).collect::<Vec<_>>();
+        spans.sort_unstable_by(|a, b| b.cmp(a));
+        let total = spans.iter().sum::<i64>();
+        let mut covered = 0;
+        let block_n50_bp = spans
+            .iter()
+            .find(|&&span| {
+                covered += span;
+                2 * covered >= total
+            })
+            .copied()
+            .unwrap_or(0);
+        PhasingStats {
+            heterozygous_sites: self.heterozygous,
+            phased_sites: self.sites.len(),
+            unphased_sites: self.heterozygous.saturating_sub(self.sites.len()),
+            blocks: blocks.len(),
+            mean_block_sites: if blocks.is_empty() { 0.0 } else { self.sites.len() as f64 / blocks.len() as f64 },
+            largest_block_bp: spans.first().copied().unwrap_or(0),
+            block_n50_bp,
+            ambiguous_sites: self.ambiguous,
+            mendelian_errors: self.mendelian_errors,
+            truth: truth.map(|truth| self.compare(truth)),
+        }
+    }
+
+    /// Switch and Hamming errors against `truth`. Each site's relation to
+    /// the truth (same or flipped first haplotype) should be constant
+    /// within a segment; a switch is a change between adjacent sites.
+    fn compare(&self, truth: &Phasing) -> TruthComparison {
+        let mut segments: BTreeMap<(&str, i64, i64), Vec<(i64, u8)>> = BTreeMap::new();
+        for ((chrom, pos), site) in &self.sites {
+            if let Some(expected) = truth.sites.get(&(chrom.clone(), *pos)) {
+                segments
+                    .entry((chrom.as_str(), site.phase_set, expected.phase_set))
+                    .or_default()
+                    .push((*pos, site.first_haplotype_allele ^ expected.first_haplotype_allele));
+            }
+        }
+        let mut comparison = TruthComparison::default();
+        for mut segment in segments.into_values().filter(|s| s.len() >= 2) {
+            segment.sort_unstable();
+            let flipped = segment.iter().filter(|(_, relation)| *relation == 1).count();
+            comparison.compared_sites += segment.len();
+            comparison.assessed_pairs += segment.len() - 1;
+            comparison.switch_errors += segment.windows(2).filter(|pair| pair[0].1 != pair[1].1).count();
+            comparison.hamming_errors += flipped.min(segment.len() - flipped);
+        }
+        if comparison.assessed_pairs > 0 {
+            comparison.switch_error_rate = comparison.switch_errors as f64 / comparison.assessed_pairs as f64;
+            comparison.hamming_error_rate = comparison.hamming_errors as f64 / comparison.compared_sites as f64;
+        }
+        comparison
+    }
+
+    /// Number of phased sites
+    pub fn len(&self) -> usize {
+        self.sites.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sites.is_empty()
+    }
+
+    /// Number of phase blocks
+    pub fn blocks(&self) -> usize {
+        self.blocks
+    }
+
+    /// Heterozygous sites considered for phasing
+    pub fn heterozygous_sites(&self) -> usize {
+        self.heterozygous
+    }
+
+    /// Trio sites left unphased because every member is heterozygous
+    pub fn ambiguous_sites(&self) -> usize {
+        self.ambiguous
+    }
+
+    /// Trio sites whose child genotype cannot come from the parents
+    pub fn mendelian_errors(&self) -> usize {
+        self.mendelian_errors
+    }
+
+    /// Write the phase of `record` back into it: the phased sample's GT
+    /// becomes `0|1` or `1|0` and FORMAT PS is set (missing for the other
+    /// samples). The header must declare PS. Returns whether the record
+    /// was phased.
+    pub fn apply(&self, record: &mut bcf::Record) -> Result<bool, String> {
+        let Some(site) = self.get(&record_chrom(record)?, record.pos()) else {
+            return Ok(false);
+        };
+        let mut gt = record
+            .format(b"GT")
+            .integer()
+            .map_err(|e| e.to_string())?
+            .iter()
+            .map(|sample| sample.to_vec())
+            .collect::<Vec<_>>();
+        let first = site.first_haplotype_allele as i32;
+        gt[self.sample] = vec![GenotypeAllele::Unphased(first).into(), GenotypeAllele::Phased(1 - first).into()];
+        let width = gt.iter().map(Vec::len).max().unwrap_or(2);
+        let flat = gt
+            .into_iter()
+            .flat_map(|mut sample| {
+                sample.resize(width, i32::vector_end());
+                sample
+            })
+            .collect::<Vec<_>>();
+        record.push_genotypes(&flat).map_err(|e| e.to_string())?;
+
+        let mut phase_sets = vec![i32::missing(); record.sample_count() as usize];
+        phase_sets[self.sample] = site.phase_set as i32;
+        record.push_format_integer(b"PS", &phase_sets).map_err(|e| e.to_string())?;
+        Ok(true)
+    }
+
+    /// Copy `input` to `output` with every phased record rewritten by
+    /// [`Phasing::apply`]. A PS FORMAT line is added to the header when
+    /// missing. The output is BCF for `.bcf`, bgzipped VCF for `.gz` and
+    /// plain VCF otherwise. Returns the number of phased records.
+    pub fn write_phased_vcf(&self, input: &str, output: &str) -> Result<usize, String> {
+        let mut reader = bcf::Reader::from_path(input).map_err(|e| format!("{}: {}", input, e))?;
+        let mut header = bcf::Header::from_template(reader.header());
+        if reader.header().name_to_id(b"PS").is_err() {
+            header.push_record(br#"##FORMAT=<ID=PS,Number=1,Type=Integer,Description="Phase set (read-backed)">"#);
+        }
+        let (uncompressed, format) = if output.ends_with(".bcf") {
+            (false, bcf::Format::Bcf)
+        } else {
+            (!output.ends_with(".gz"), bcf::Format::Vcf)
+        };
+        let mut writer = bcf::Writer::from_path(output, &header, uncompressed, format)
+            .map_err(|e| format!("{}: {}", output, e))?;
+
+        let mut phased = 0;
+        for record in reader.records() {
+            let mut record = record.map_err(|e| e.to_string())?;
+            writer.translate(&mut record);
+            if self.apply(&mut record)? {
+                phased += 1;
+            }
+            writer.write(&record).map_err(|e| e.to_string())?;
+        }
+        Ok(phased)
+    }
 }
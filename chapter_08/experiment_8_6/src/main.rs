@@ -8,15 +8,17 @@ use onnxruntime::{
     Session
 };
 use polars::prelude::*;
+use rust_htslib::{bam, bam::pileup::Indel, bcf, bcf::{record::Numeric, Read}, faidx};
 use rayon::prelude::*;
-use rust_htslib::{bcf, bcf::Read};
 use serde::{Serialize, Deserialize};
-use whatshap_rs::phase_block;
+use whatshap_rs::{phase_trio_vcf, phase_vcf, read_pedigree, read_truth, Phasing, PhasingOptions, PhasingStats};
 use std::{
     fs::File,
     io::{self, BufReader, BufWriter, Write},
     path::Path,
-    sync::{Arc, Mutex, atomic::{AtomicUsize, Ordering}},
+    str::FromStr,
+    collections::{BTreeMap, VecDeque},
+    sync::{Arc, Mutex, mpsc, atomic::{AtomicUsize, Ordering}},
     time::Instant,
     thread,
 };
@@ -48,16 +50,22 @@ pub enum ScoringError {
     
     #[error("Invalid input data: {0}")]
     InvalidInputError(String),
+    
+    #[error("Configuration error: {0}")]
+    ConfigError(String),
 }
 
 /// Supported output formats
-#[derive(Debug, Clone, Copy, ValueEnum)]
+#[derive(Debug, Clone, Copy, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
 enum OutputFormat {
     Parquet,
     Ipc,
     Csv,
     Json,
     Tsv,
+    /// Bgzipped copy of the input VCF with score INFO fields, tabix-indexed
+    Vcf,
 }
 
 /// Command line interface
@@ -72,9 +80,18 @@ struct Cli {
     #[arg(short, long)]
     verbose: bool,
     
-    /// Number of threads to use (0 = use all available)
-    #[arg(short, long, default_value = "0")]
-    threads: usize,
+    /// Number of threads to use (0 = use all available, the default)
+    #[arg(short, long)]
+    threads: Option<usize>,
+    
+    /// TOML file with defaults for the scoring options; options given on
+    /// the command line take precedence
+    #[arg(long, default_value = DEFAULT_CONFIG)]
+    config: String,
+    
+    /// Write a per-stage runtime breakdown (wall time, bytes, records/sec) as JSON
+    #[arg(long)]
+    profile_json: Option<String>,
     
     #[command(subcommand)]
     cmd: Command,
@@ -85,7 +102,7 @@ struct Cli {
 enum Command {
     /// Score variants using a pangenome graph and ML model
     Score {
-        /// Path to pangenome graph in ODGI format
+        /// Path to pangenome graph (GFA v1, or the JSON layout)
         #[arg(long)]
         graph: String,
         
@@ -101,17 +118,18 @@ enum Command {
         #[arg(long)]
         out: String,
         
-        /// Output format
-        #[arg(long, value_enum, default_value_t = OutputFormat::Ipc)]
-        format: OutputFormat,
+        /// Output format (default ipc)
+        #[arg(long, value_enum)]
+        format: Option<OutputFormat>,
         
-        /// Batch size for processing
-        #[arg(long, default_value = "1000")]
-        batch_size: usize,
+        /// Batch size for processing (default 1000)
+        #[arg(long)]
+        batch_size: Option<usize>,
         
-        /// Window size for phasing (in bp)
-        #[arg(long, default_value = "1000")]
-        phase_window: i32,
+        /// Maximum distance between two heterozygous sites linked by one
+        /// read pair when phasing (in bp, default 1000)
+        #[arg(long)]
+        phase_window: Option<i32>,
         
         /// Skip phasing step
         #[arg(long)]
@@ -121,14 +139,109 @@ enum Command {
         #[arg(long)]
         extended_features: bool,
         
+        /// Feature sets fed to the model, in order, replacing the default
+        /// layout (comma-separated: ref_len, alt_len, degree, centrality,
+        /// complexity, neighborhood, repeat_context, bubble, gc, homopolymer)
+        #[arg(long, value_delimiter = ',', conflicts_with = "extended_features")]
+        features: Vec<String>,
+        
         /// Filter out variants with score below threshold
         #[arg(long)]
         min_score: Option<f32>,
+        
+        /// Calibration written by `calibrate`; adds a calibrated_score
+        /// (probability) next to the raw score
+        #[arg(long)]
+        calibration: Option<String>,
+        
+        /// Add the cohort-internal allele frequency (from GT calls) as a model feature
+        #[arg(long)]
+        cohort_af: bool,
+        
+        /// Drop variants whose cohort allele frequency exceeds this value
+        #[arg(long)]
+        max_cohort_af: Option<f32>,
+        
+        /// Score each sample carrying an allele separately, adding its allele
+        /// depths, GQ and zygosity as features (one output row per sample)
+        #[arg(long)]
+        per_sample: bool,
+        
+        /// Indexed BAM for the sample; adds read-evidence features (depth,
+        /// allele balance, alt-read MAPQ, soft-clip fraction) at each site
+        #[arg(long)]
+        bam: Option<String>,
+        
+        /// JSON feature manifest selecting the sequence-complexity metrics
+        #[arg(long)]
+        feature_manifest: Option<String>,
+        
+        /// Indexed reference FASTA for the manifest's flanking context
+        #[arg(long)]
+        reference: Option<String>,
+        
+        /// PED file; phase the first trio found in the VCF by Mendelian
+        /// transmission instead of from reads
+        #[arg(long)]
+        pedigree: Option<String>,
+        
+        /// Write the input VCF with the phasing (phased GT and FORMAT PS)
+        /// to this path; needs --bam or --pedigree
+        #[arg(long)]
+        phased_vcf: Option<String>,
+        
+        /// Phased truth VCF; report switch and Hamming error rates of the
+        /// phasing against it
+        #[arg(long)]
+        phase_truth: Option<String>,
+        
+        /// Model sessions scoring feature batches in parallel with feature
+        /// extraction (one CUDA stream each with the cuda feature; default 1)
+        #[arg(long)]
+        inference_workers: Option<usize>,
+        
+        /// Feature batches that may wait for a free inference worker
+        /// (default 4)
+        #[arg(long)]
+        queue_depth: Option<usize>,
+        
+        /// VCF batches read and feature-extracted concurrently (bounds
+        /// memory together with --queue-depth; default 8)
+        #[arg(long)]
+        max_inflight_batches: Option<usize>,
+        
+        /// Only score these regions, read through the VCF's CSI/tabix index
+        /// (comma-separated `chrom`, `chrom:start` or `chrom:start-end`,
+        /// 1-based inclusive; k/M/G suffixes allowed, e.g. chr1:1-5M,chr2)
+        #[arg(long, value_delimiter = ',')]
+        regions: Vec<Region>,
+        
+        /// Write the scoring statistics, with throughput and per-batch
+        /// latencies, as JSON
+        #[arg(long)]
+        stats_out: Option<String>,
+        
+        /// Write the statistics as Prometheus metrics in the node_exporter
+        /// textfile format
+        #[arg(long)]
+        metrics_textfile: Option<String>,
+        
+        /// CUDA device for inference (builds with the cuda feature; default 0)
+        #[arg(long)]
+        gpu_device: Option<u32>,
+        
+        /// Cap the CUDA memory arena of each inference worker (in MiB)
+        #[arg(long)]
+        gpu_memory_mb: Option<usize>,
+        
+        /// Run inference on the CPU when CUDA is unavailable instead of failing
+        #[arg(long)]
+        gpu_cpu_fallback: bool,
     },
     
     /// Batch score variants from multiple VCFs
     BatchScore {
-        /// Path to pangenome graph in ODGI format
+        /// Path to pangenome graph (GFA v1, or the JSON layout)
         #[arg(long)]
         graph: String,
         
@@ -144,25 +257,277 @@ enum Command {
         #[arg(long)]
         out_dir: String,
         
-        /// Output format
-        #[arg(long, value_enum, default_value_t = OutputFormat::Ipc)]
-        format: OutputFormat,
+        /// Output format (default ipc)
+        #[arg(long, value_enum)]
+        format: Option<OutputFormat>,
+        
+        /// Maximum distance between two heterozygous sites linked by one
+        /// read pair when phasing (in bp, default 1000)
+        #[arg(long)]
+        phase_window: Option<i32>,
+        
+        /// Add the cohort-internal allele frequency (from GT calls) as a model feature
+        #[arg(long)]
+        cohort_af: bool,
+        
+        /// Drop variants whose cohort allele frequency exceeds this value
+        #[arg(long)]
+        max_cohort_af: Option<f32>,
+        
+        /// Score each sample carrying an allele separately, adding its allele
+        /// depths, GQ and zygosity as features (one output row per sample)
+        #[arg(long)]
+        per_sample: bool,
+        
+        /// Feature sets fed to the model, in order, replacing the extended
+        /// default layout (same names as for `score`)
+        #[arg(long, value_delimiter = ',')]
+        features: Vec<String>,
+        
+        /// Calibration written by `calibrate`; adds a calibrated_score
+        /// (probability) next to the raw score
+        #[arg(long)]
+        calibration: Option<String>,
+        
+        /// JSON feature manifest selecting the sequence-complexity metrics
+        #[arg(long)]
+        feature_manifest: Option<String>,
+        
+        /// Indexed reference FASTA for the manifest's flanking context
+        #[arg(long)]
+        reference: Option<String>,
+        
+        /// PED file; phase the first trio found in each VCF by Mendelian
+        /// transmission
+        #[arg(long)]
+        pedigree: Option<String>,
+        
+        /// Model sessions scoring feature batches in parallel with feature
+        /// extraction (one CUDA stream each with the cuda feature; default 1)
+        #[arg(long)]
+        inference_workers: Option<usize>,
+        
+        /// Feature batches that may wait for a free inference worker
+        /// (default 4)
+        #[arg(long)]
+        queue_depth: Option<usize>,
+        
+        /// VCF batches read and feature-extracted concurrently (bounds
+        /// memory together with --queue-depth; default 8)
+        #[arg(long)]
+        max_inflight_batches: Option<usize>,
+        
+        /// CUDA device for inference (builds with the cuda feature; default 0)
+        #[arg(long)]
+        gpu_device: Option<u32>,
+        
+        /// Cap the CUDA memory arena of each inference worker (in MiB)
+        #[arg(long)]
+        gpu_memory_mb: Option<usize>,
+        
+        /// Run inference on the CPU when CUDA is unavailable instead of failing
+        #[arg(long)]
+        gpu_cpu_fallback: bool,
+        
+        /// Skip VCFs that the checkpoint in the output directory records as
+        /// done and whose output still exists
+        #[arg(long)]
+        resume: bool,
+        
+        /// VCFs scored at the same time (default 1)
+        #[arg(long)]
+        parallel_files: Option<usize>,
+    },
+    
+    /// Fit a calibration of raw scores to probabilities from a truth set
+    Calibrate {
+        /// Output of `score` with raw scores (.arrow, .parquet, .csv, .tsv,
+        /// .json or .vcf.gz)
+        #[arg(long)]
+        scores: String,
+        
+        /// Truth VCF; scored alleles found in it are labeled true, all
+        /// others false
+        #[arg(long)]
+        truth: String,
+        
+        /// Calibration model
+        #[arg(long, value_enum, default_value_t = CalibrationMethod::Isotonic)]
+        method: CalibrationMethod,
+        
+        /// Only use scored variants in these regions, e.g. the truth set's
+        /// confident regions (same syntax as `score --regions`)
+        #[arg(long, value_delimiter = ',')]
+        regions: Vec<Region>,
+        
+        /// Equal-width probability bins of the reliability curve
+        #[arg(long, default_value = "10")]
+        bins: usize,
+        
+        /// Path of the calibration JSON for `--calibration`
+        #[arg(long)]
+        out: String,
+    },
+    
+    /// Compare scored variants against a truth set: ROC and precision-recall
+    /// curves per variant class
+    Benchmark {
+        /// Outputs of `score` to compare, comma-separated (same formats as
+        /// for `calibrate`)
+        #[arg(long, value_delimiter = ',', required = true)]
+        scores: Vec<String>,
+        
+        /// Truth VCF, e.g. a GIAB benchmark set
+        #[arg(long)]
+        truth: String,
+        
+        /// Only compare variants in these regions, e.g. the truth set's
+        /// confident regions
+        #[arg(long, value_delimiter = ',')]
+        regions: Vec<Region>,
+        
+        /// Points kept on each curve in the report
+        #[arg(long, default_value = "100")]
+        curve_points: usize,
         
-        /// Window size for phasing (in bp)
-        #[arg(long, default_value = "1000")]
-        phase_window: i32,
+        /// Path of the JSON report
+        #[arg(long)]
+        out: String,
+    },
+    
+    /// Join scored variants with variant-annotator output (experiment_8_4)
+    /// into one table, highest score first
+    AnnotateScores {
+        /// Output of `score` (.parquet, .arrow, .ipc, .csv or .tsv)
+        #[arg(long)]
+        scores: String,
+        
+        /// Output of the variant annotator (.parquet or .csv)
+        #[arg(long)]
+        annotations: String,
+        
+        /// Keep scored variants without an annotation; their annotation
+        /// columns are null
+        #[arg(long)]
+        keep_unannotated: bool,
+        
+        /// Path of the combined table; the format follows the extension
+        /// (.parquet, .arrow, .ipc, .csv or .tsv)
+        #[arg(long)]
+        out: String,
     },
 }
 
+/// Configuration file read when `--config` is not given; a missing one is
+/// not an error
+const DEFAULT_CONFIG: &str = "scorer.toml";
+
+/// Scorer settings from the configuration file. Every field is optional;
+/// command-line options override them and built-in defaults fill the rest
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+struct Settings {
+    // General settings
+    threads: Option<usize>,
+    
+    // Defaults for `score` and `batch-score`
+    #[serde(default)]
+    scoring: ScoringSettings,
+    
+    // Defaults for `batch-score` only
+    #[serde(default)]
+    batch: BatchSettings,
+}
+
+/// Scoring settings shared by `score` and `batch-score`
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+struct ScoringSettings {
+    format: Option<OutputFormat>,
+    batch_size: Option<usize>,
+    phase_window: Option<i32>,
+    skip_phasing: Option<bool>,
+    extended_features: Option<bool>,
+    features: Option<Vec<String>>,
+    min_score: Option<f32>,
+    calibration: Option<String>,
+    cohort_af: Option<bool>,
+    max_cohort_af: Option<f32>,
+    per_sample: Option<bool>,
+    feature_manifest: Option<String>,
+    reference: Option<String>,
+    inference_workers: Option<usize>,
+    queue_depth: Option<usize>,
+    max_inflight_batches: Option<usize>,
+    gpu_device: Option<u32>,
+    gpu_memory_mb: Option<usize>,
+    gpu_cpu_fallback: Option<bool>,
+}
+
+/// Cohort batch settings
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+struct BatchSettings {
+    parallel_files: Option<usize>,
+}
+
+/// Load the configuration file, falling back to the defaults when the
+/// default file does not exist
+fn load_settings(config_path: &str) -> Result<Settings> {
+    if !Path::new(config_path).exists() {
+        if config_path == DEFAULT_CONFIG {
+            return Ok(Settings::default());
+        }
+        return Err(anyhow!(ScoringError::ConfigError(format!(
+            "Configuration file not found: {}",
+            config_path
+        ))));
+    }
+    
+    let content = std::fs::read_to_string(config_path)
+        .with_context(|| format!("Failed to read config file: {}", config_path))?;
+    let settings: Settings = toml::from_str(&content)
+        .map_err(|e| anyhow!(ScoringError::ConfigError(format!("{}: {}", config_path, e))))?;
+    Ok(settings)
+}
+
 /// Configuration for scoring process
 #[derive(Debug, Clone)]
 struct ScoringConfig {
     batch_size: usize,
     phase_window: i32,
     skip_phasing: bool,
-    extended_features: bool,
+    features: Vec<&'static dyn FeatureExtractor>,
     min_score: Option<f32>,
+    calibration: Option<Calibration>,
     output_format: OutputFormat,
+    cohort_af: bool,
+    max_cohort_af: Option<f32>,
+    per_sample: bool,
+    bam: Option<String>,
+    manifest: FeatureManifest,
+    reference: Option<String>,
+    pedigree: Option<String>,
+    phased_vcf: Option<String>,
+    phase_truth: Option<String>,
+    inference_workers: usize,
+    queue_depth: usize,
+    max_inflight_batches: usize,
+    regions: Vec<Region>,
+    stats_out: Option<String>,
+    metrics_textfile: Option<String>,
+    gpu: GpuOptions,
+}
+
+/// CUDA execution provider options (builds with the `cuda` feature)
+#[derive(Debug, Clone, Default)]
+struct GpuOptions {
+    /// CUDA device ordinal; device 0 when not given
+    device: Option<u32>,
+    /// Cap on each model session's device memory arena, in MiB
+    memory_limit_mb: Option<usize>,
+    /// Run on the CPU when CUDA is unavailable instead of failing
+    cpu_fallback: bool,
 }
 
 /// Default configuration
@@ -172,25 +537,187 @@ impl Default for ScoringConfig {
             batch_size: 1000,
             phase_window: 1000,
             skip_phasing: false,
-            extended_features: false,
+            features: default_features(false, &FeatureManifest::default()),
             min_score: None,
+            calibration: None,
             output_format: OutputFormat::Ipc,
+            cohort_af: false,
+            max_cohort_af: None,
+            per_sample: false,
+            bam: None,
+            manifest: FeatureManifest::default(),
+            reference: None,
+            pedigree: None,
+            phased_vcf: None,
+            phase_truth: None,
+            inference_workers: 1,
+            queue_depth: 4,
+            max_inflight_batches: 8,
+            regions: Vec::new(),
+            stats_out: None,
+            metrics_textfile: None,
+            gpu: GpuOptions::default(),
+        }
+    }
+}
+
+/// Sequence-complexity metric computed for the extended feature set
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "metric", rename_all = "snake_case")]
+enum ComplexityMetric {
+    /// Fraction of distinct k-mers among all k-mers
+    KmerUniqueness {
+        #[serde(default = "default_complexity_k")]
+        k: usize,
+    },
+    /// Shannon entropy of the k-mer distribution, scaled to [0, 1]
+    ShannonEntropy {
+        #[serde(default = "default_complexity_k")]
+        k: usize,
+    },
+    /// SDUST triplet score; high values flag low-complexity sequence
+    Dust,
+    /// Length of the longest single-base run
+    HomopolymerMax,
+}
+
+impl ComplexityMetric {
+    /// Model input column name, e.g. `kmer_uniqueness_k3`
+    fn column_name(&self) -> String {
+        match self {
+            ComplexityMetric::KmerUniqueness { k } => format!("kmer_uniqueness_k{}", k),
+            ComplexityMetric::ShannonEntropy { k } => format!("shannon_entropy_k{}", k),
+            ComplexityMetric::Dust => "dust".to_string(),
+            ComplexityMetric::HomopolymerMax => "homopolymer_max".to_string(),
+        }
+    }
+}
+
+fn default_complexity_k() -> usize {
+    3
+}
+
+/// Model feature manifest: which complexity metrics the model was trained
+/// with and how much reference context they see around the variant
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct FeatureManifest {
+    #[serde(default = "default_complexity_metrics")]
+    complexity: Vec<ComplexityMetric>,
+    
+    /// Reference bases on each side of the variant included with the alt
+    /// allele (requires `--reference`; 0 scores the alt allele alone)
+    #[serde(default)]
+    flank: usize,
+    
+    /// Add homopolymer length, STR unit length and STR copy number from
+    /// the reference (requires `--reference`)
+    #[serde(default)]
+    repeat_context: bool,
+    
+    /// Add superbubble membership, nesting depth and innermost bubble size
+    /// from the graph
+    #[serde(default)]
+    bubble_context: bool,
+    
+    /// Hops around the variant's node summarised by the extended
+    /// neighbourhood features
+    #[serde(default = "default_neighborhood_radius")]
+    neighborhood_radius: usize,
+}
+
+fn default_neighborhood_radius() -> usize {
+    2
+}
+
+fn default_complexity_metrics() -> Vec<ComplexityMetric> {
+    vec![ComplexityMetric::KmerUniqueness { k: 3 }]
+}
+
+impl Default for FeatureManifest {
+    fn default() -> Self {
+        Self {
+            complexity: default_complexity_metrics(),
+            flank: 0,
+            repeat_context: false,
+            bubble_context: false,
+            neighborhood_radius: default_neighborhood_radius(),
+        }
+    }
+}
+
+impl FeatureManifest {
+    fn load(path: &str) -> Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open feature manifest: {}", path))?;
+        let manifest: Self = serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("Invalid feature manifest: {}", path))?;
+        
+        for metric in &manifest.complexity {
+            if let ComplexityMetric::KmerUniqueness { k: 0 } | ComplexityMetric::ShannonEntropy { k: 0 } = metric {
+                return Err(anyhow!(ScoringError::InvalidInputError(
+                    "Feature manifest k-mer size must be at least 1".to_string()
+                )));
+            }
         }
+        Ok(manifest)
     }
 }
 
 /// Variant information for scoring
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct VariantInfo {
+    record_index: usize,
+    alt_index: usize,
     chrom: String,
     pos: i64,
     ref_allele: String,
     alt_allele: String,
     score: f64,
+    calibrated_score: Option<f64>,
     phase_block: String,
     node_id: Option<u64>,
     node_degree: Option<u32>,
     centrality: Option<f64>,
+    cohort_af: Option<f64>,
+    read_depth: Option<u32>,
+    allele_balance: Option<f64>,
+    alt_mapq: Option<f64>,
+    softclip_fraction: Option<f64>,
+    sample: Option<String>,
+    genotype: Option<String>,
+    sample_ref_depth: Option<u32>,
+    sample_alt_depth: Option<u32>,
+    sample_gq: Option<u32>,
+}
+
+/// Read-level support for one variant allele, summarised from a pileup
+#[derive(Debug, Clone, Copy, Default)]
+struct ReadEvidence {
+    depth: u32,
+    ref_reads: u32,
+    alt_reads: u32,
+    alt_mapq_sum: u64,
+    softclipped_reads: u32,
+}
+
+impl ReadEvidence {
+    fn allele_balance(&self) -> f32 {
+        let informative = self.ref_reads + self.alt_reads;
+        if informative == 0 { 0.0 } else { self.alt_reads as f32 / informative as f32 }
+    }
+    
+    fn mean_alt_mapq(&self) -> f32 {
+        if self.alt_reads == 0 { 0.0 } else { self.alt_mapq_sum as f32 / self.alt_reads as f32 }
+    }
+    
+    fn softclip_fraction(&self) -> f32 {
+        if self.depth == 0 { 0.0 } else { self.softclipped_reads as f32 / self.depth as f32 }
+    }
+    
+    /// Feature channel appended to the model input when `--bam` is given
+    fn features(&self) -> [f32; 4] {
+        [self.depth as f32, self.allele_balance(), self.mean_alt_mapq(), self.softclip_fraction()]
+    }
 }
 
 /// Statistics for reporting
@@ -201,8 +728,121 @@ struct ScoringStats {
     filtered_variants: usize,
     high_scoring_variants: usize,
     multi_allelic_variants: usize,
+    split_alleles: usize,
+    symbolic_alleles_skipped: usize,
+    cohort_af_filtered: usize,
+    no_read_evidence: usize,
     phased_variants: usize,
+    phasing: Option<PhasingStats>,
     elapsed_seconds: f64,
+    variants_per_second: f64,
+    extract_latency: LatencySummary,
+    inference_latency: LatencySummary,
+    /// Per-batch wall times behind the latency summaries
+    #[serde(skip)]
+    extract_seconds: Vec<f64>,
+    #[serde(skip)]
+    inference_seconds: Vec<f64>,
+}
+
+/// Distribution of per-batch wall times
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct LatencySummary {
+    batches: usize,
+    mean_seconds: f64,
+    p50_seconds: f64,
+    p95_seconds: f64,
+    max_seconds: f64,
+}
+
+impl LatencySummary {
+    fn new(samples: &[f64]) -> Self {
+        if samples.is_empty() {
+            return Self::default();
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        // Nearest-rank percentile
+        let percentile = |p: f64| sorted[((p * sorted.len() as f64).ceil() as usize).clamp(1, sorted.len()) - 1];
+        Self {
+            batches: sorted.len(),
+            mean_seconds: sorted.iter().sum::<f64>() / sorted.len() as f64,
+            p50_seconds: percentile(0.5),
+            p95_seconds: percentile(0.95),
+            max_seconds: sorted[sorted.len() - 1],
+        }
+    }
+}
+
+/// Wall time and I/O for one stage of a scoring run
+#[derive(Debug, Serialize)]
+struct StageProfile {
+    stage: String,
+    target: String,
+    wall_seconds: f64,
+    bytes_read: u64,
+    bytes_written: u64,
+    records: u64,
+    records_per_sec: f64,
+}
+
+/// Runtime breakdown written by `--profile-json`
+#[derive(Debug, Default, Serialize)]
+struct RunProfile {
+    tool: String,
+    threads: usize,
+    total_wall_seconds: f64,
+    stages: Vec<StageProfile>,
+}
+
+impl RunProfile {
+    fn new(threads: usize) -> Self {
+        Self {
+            tool: env!("CARGO_PKG_NAME").to_string(),
+            threads,
+            ..Default::default()
+        }
+    }
+    
+    /// Record a finished stage; `target` is the file the stage worked on
+    fn record(
+        &mut self,
+        stage: &str,
+        target: &str,
+        started: Instant,
+        records: u64,
+        bytes_read: u64,
+        bytes_written: u64,
+    ) {
+        let wall_seconds = started.elapsed().as_secs_f64();
+        self.stages.push(StageProfile {
+            stage: stage.to_string(),
+            target: target.to_string(),
+            wall_seconds,
+            bytes_read,
+            bytes_written,
+            records,
+            records_per_sec: if wall_seconds > 0.0 { records as f64 / wall_seconds } else { 0.0 },
+        });
+    }
+    
+    /// Append the stages of a profile recorded on another thread
+    fn merge(&mut self, other: RunProfile) {
+        self.stages.extend(other.stages);
+    }
+    
+    fn write(mut self, path: &str, start_time: Instant) -> Result<()> {
+        self.total_wall_seconds = start_time.elapsed().as_secs_f64();
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create profile file: {}", path))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), &self)
+            .context("Failed to write runtime profile")?;
+        Ok(())
+    }
+}
+
+fn file_len(path: &str) -> u64 {
+    std::fs::metadata(path).map(|m| m.len()).unwrap_or(0)
 }
 
 /// Main entry point
@@ -216,11 +856,13 @@ fn main() -> Result<()> {
         .with_max_level(log_level)
         .init();
     
+    // Load configuration; command-line options override it
+    let settings = load_settings(&cli.config)?;
+    
     // Configure thread pool
-    let num_threads = if cli.threads == 0 {
-        num_cpus::get()
-    } else {
-        cli.threads
+    let num_threads = match cli.threads.or(settings.threads).unwrap_or(0) {
+        0 => num_cpus::get(),
+        threads => threads,
     };
     
     rayon::ThreadPoolBuilder::new()
@@ -232,6 +874,7 @@ fn main() -> Result<()> {
     
     // Start timing
     let start_time = Instant::now();
+    let mut profile = RunProfile::new(num_threads);
     
     // Execute command
     let result = match &cli.cmd {
@@ -245,18 +888,84 @@ fn main() -> Result<()> {
             phase_window,
             skip_phasing,
             extended_features,
+            features,
             min_score,
+            calibration,
+            cohort_af,
+            max_cohort_af,
+            per_sample,
+            bam,
+            feature_manifest,
+            reference,
+            pedigree,
+            phased_vcf,
+            phase_truth,
+            inference_workers,
+            queue_depth,
+            max_inflight_batches,
+            regions,
+            stats_out,
+            metrics_textfile,
+            gpu_device,
+            gpu_memory_mb,
+            gpu_cpu_fallback,
         } => {
+            let defaults = &settings.scoring;
+            let fallback = ScoringConfig::default();
+            let skip_phasing = *skip_phasing || defaults.skip_phasing.unwrap_or(false);
+            if (phased_vcf.is_some() || phase_truth.is_some()) && ((bam.is_none() && pedigree.is_none()) || skip_phasing) {
+                return Err(anyhow!(ScoringError::InvalidInputError(
+                    "--phased-vcf and --phase-truth need --bam or --pedigree and cannot be combined with --skip-phasing".to_string()
+                )));
+            }
+            // --features and --extended-features both replace the configured layout
+            let (features, extended_features) = if features.is_empty() && !*extended_features {
+                (defaults.features.clone().unwrap_or_default(), defaults.extended_features.unwrap_or(false))
+            } else {
+                (features.clone(), *extended_features)
+            };
+            let reference = reference.clone().or_else(|| defaults.reference.clone());
+            let manifest = load_manifest(
+                feature_manifest.as_deref().or(defaults.feature_manifest.as_deref()),
+                reference.as_deref(),
+            )?;
             let config = ScoringConfig {
-                batch_size: *batch_size,
-                phase_window: *phase_window,
-                skip_phasing: *skip_phasing,
-                extended_features: *extended_features,
-                min_score: *min_score,
-                output_format: *format,
+                batch_size: batch_size.or(defaults.batch_size).unwrap_or(fallback.batch_size),
+                phase_window: phase_window.or(defaults.phase_window).unwrap_or(fallback.phase_window),
+                skip_phasing,
+                features: resolve_features(&features, extended_features, &manifest, reference.as_deref())?,
+                min_score: min_score.or(defaults.min_score),
+                calibration: calibration
+                    .as_deref()
+                    .or(defaults.calibration.as_deref())
+                    .map(Calibration::load)
+                    .transpose()?,
+                output_format: format.or(defaults.format).unwrap_or(fallback.output_format),
+                cohort_af: *cohort_af || defaults.cohort_af.unwrap_or(false),
+                max_cohort_af: max_cohort_af.or(defaults.max_cohort_af),
+                per_sample: *per_sample || defaults.per_sample.unwrap_or(false),
+                bam: bam.clone(),
+                manifest,
+                reference,
+                pedigree: pedigree.clone(),
+                phased_vcf: phased_vcf.clone(),
+                phase_truth: phase_truth.clone(),
+                inference_workers: inference_workers.or(defaults.inference_workers).unwrap_or(fallback.inference_workers),
+                queue_depth: queue_depth.or(defaults.queue_depth).unwrap_or(fallback.queue_depth),
+                max_inflight_batches: max_inflight_batches
+                    .or(defaults.max_inflight_batches)
+                    .unwrap_or(fallback.max_inflight_batches),
+                regions: regions.clone(),
+                stats_out: stats_out.clone(),
+                metrics_textfile: metrics_textfile.clone(),
+                gpu: GpuOptions {
+                    device: gpu_device.or(defaults.gpu_device),
+                    memory_limit_mb: gpu_memory_mb.or(defaults.gpu_memory_mb),
+                    cpu_fallback: *gpu_cpu_fallback || defaults.gpu_cpu_fallback.unwrap_or(false),
+                },
             };
             
-            run_score(graph, vcf, model, out, &config)
+            run_score(graph, vcf, model, out, &config, &mut profile)
         }
         
         Command::BatchScore {
@@ -266,42 +975,183 @@ fn main() -> Result<()> {
             out_dir,
             format,
             phase_window,
+            cohort_af,
+            max_cohort_af,
+            per_sample,
+            features,
+            calibration,
+            feature_manifest,
+            reference,
+            pedigree,
+            inference_workers,
+            queue_depth,
+            max_inflight_batches,
+            gpu_device,
+            gpu_memory_mb,
+            gpu_cpu_fallback,
+            resume,
+            parallel_files,
         } => {
+            let defaults = &settings.scoring;
+            let fallback = ScoringConfig::default();
+            // Batch scoring always uses the extended layout unless feature
+            // sets are chosen
+            let features = if features.is_empty() {
+                defaults.features.clone().unwrap_or_default()
+            } else {
+                features.clone()
+            };
+            let reference = reference.clone().or_else(|| defaults.reference.clone());
+            let manifest = load_manifest(
+                feature_manifest.as_deref().or(defaults.feature_manifest.as_deref()),
+                reference.as_deref(),
+            )?;
             let config = ScoringConfig {
-                batch_size: 1000,
-                phase_window: *phase_window,
+                batch_size: defaults.batch_size.unwrap_or(fallback.batch_size),
+                phase_window: phase_window.or(defaults.phase_window).unwrap_or(fallback.phase_window),
                 skip_phasing: false,
-                extended_features: true,
-                min_score: None,
-                output_format: *format,
+                features: resolve_features(&features, true, &manifest, reference.as_deref())?,
+                min_score: defaults.min_score,
+                calibration: calibration
+                    .as_deref()
+                    .or(defaults.calibration.as_deref())
+                    .map(Calibration::load)
+                    .transpose()?,
+                output_format: format.or(defaults.format).unwrap_or(fallback.output_format),
+                cohort_af: *cohort_af || defaults.cohort_af.unwrap_or(false),
+                max_cohort_af: max_cohort_af.or(defaults.max_cohort_af),
+                per_sample: *per_sample || defaults.per_sample.unwrap_or(false),
+                bam: None,
+                manifest,
+                reference,
+                pedigree: pedigree.clone(),
+                phased_vcf: None,
+                phase_truth: None,
+                inference_workers: inference_workers.or(defaults.inference_workers).unwrap_or(fallback.inference_workers),
+                queue_depth: queue_depth.or(defaults.queue_depth).unwrap_or(fallback.queue_depth),
+                max_inflight_batches: max_inflight_batches
+                    .or(defaults.max_inflight_batches)
+                    .unwrap_or(fallback.max_inflight_batches),
+                regions: Vec::new(),
+                stats_out: None,
+                metrics_textfile: None,
+                gpu: GpuOptions {
+                    device: gpu_device.or(defaults.gpu_device),
+                    memory_limit_mb: gpu_memory_mb.or(defaults.gpu_memory_mb),
+                    cpu_fallback: *gpu_cpu_fallback || defaults.gpu_cpu_fallback.unwrap_or(false),
+                },
             };
             
-            run_batch_score(graph, vcf_list, model, out_dir, &config)
+            let batch = BatchOptions {
+                resume: *resume,
+                parallel_files: parallel_files.or(settings.batch.parallel_files).unwrap_or(1),
+            };
+            run_batch_score(graph, vcf_list, model, out_dir, &config, &batch, &mut profile)
         }
+        
+        Command::Calibrate {
+            scores,
+            truth,
+            method,
+            regions,
+            bins,
+            out,
+        } => run_calibrate(scores, truth, *method, regions, *bins, out, &mut profile),
+        
+        Command::Benchmark {
+            scores,
+            truth,
+            regions,
+            curve_points,
+            out,
+        } => run_benchmark(scores, truth, regions, *curve_points, out, &mut profile),
+        
+        Command::AnnotateScores {
+            scores,
+            annotations,
+            keep_unannotated,
+            out,
+        } => run_annotate_scores(scores, annotations, *keep_unannotated, out, &mut profile),
     };
     
     // Log execution time
     let elapsed = start_time.elapsed();
     info!("Total execution time: {:.2?}", elapsed);
     
+    if result.is_ok() {
+        if let Some(profile_path) = &cli.profile_json {
+            profile.write(profile_path, start_time)?;
+            info!("Runtime profile written to {}", profile_path);
+        }
+    }
+    
     result
 }
 
+/// Indexed reference FASTA plus its contig names; htslib's fetch does not
+/// fail cleanly on unknown contigs, so lookups are checked first
+struct ReferenceFasta {
+    reader: faidx::Reader,
+    contigs: std::collections::HashSet<String>,
+}
+
+impl ReferenceFasta {
+    fn open(fasta_path: &str) -> Result<Self> {
+        debug!("Opening reference FASTA: {}", fasta_path);
+        let reader = faidx::Reader::from_path(fasta_path)
+            .with_context(|| format!("Failed to open indexed reference FASTA (is there a .fai?): {}", fasta_path))?;
+        let contigs = reader
+            .seq_names()
+            .with_context(|| format!("Failed to read FASTA index for {}", fasta_path))?
+            .into_iter()
+            .collect();
+        Ok(Self { reader, contigs })
+    }
+    
+    /// Reference bases in the 0-based half-open interval `[start, end)`;
+    /// empty for unknown contigs or empty intervals, clamped at the contig end
+    fn fetch(&self, chrom: &str, start: usize, end: usize) -> Result<Vec<u8>> {
+        if end <= start || !self.contigs.contains(chrom) {
+            return Ok(Vec::new());
+        }
+        let seq = self
+            .reader
+            .fetch_seq_string(chrom, start, end - 1)
+            .with_context(|| format!("Failed to fetch {}:{}-{} from reference", chrom, start + 1, end))?;
+        Ok(seq.into_bytes())
+    }
+}
+
+/// Resolve the feature manifest, defaulting to the original 3-mer uniqueness
+fn load_manifest(manifest_path: Option<&str>, reference: Option<&str>) -> Result<FeatureManifest> {
+    let manifest = match manifest_path {
+        Some(path) => FeatureManifest::load(path)?,
+        None => FeatureManifest::default(),
+    };
+    if (manifest.flank > 0 || manifest.repeat_context) && reference.is_none() {
+        return Err(anyhow!(ScoringError::InvalidInputError(
+            "Feature manifest requests reference context but no --reference was given".to_string()
+        )));
+    }
+    Ok(manifest)
+}
+
 /// Load and validate an ODGI pangenome graph
 fn load_graph(graph_path: &str) -> Result<Graph> {
     info!("Loading pangenome graph from: {}", graph_path);
     let start = Instant::now();
     
-    let graph = Graph::from_json_path(graph_path)
+    let graph = Graph::from_path(graph_path)
         .map_err(|e| anyhow!(ScoringError::GraphLoadError(e)))?;
     
     let node_count = graph.node_count();
     let edge_count = graph.edge_count();
     
     info!(
-        "Loaded graph with {} nodes and {} edges in {:.2?}",
+        "Loaded graph with {} nodes, {} edges and {} paths in {:.2?}",
         node_count,
         edge_count,
+        graph.path_count(),
         start.elapsed()
     );
     
@@ -315,8 +1165,9 @@ fn load_graph(graph_path: &str) -> Result<Graph> {
     Ok(graph)
 }
 
-/// Initialize ONNX runtime and load model
-fn load_model(model_path: &str) -> Result<(Environment, Session)> {
+/// Initialize ONNX runtime and load model, checking that it takes a single
+/// `[batch, columns.len()]` feature matrix
+fn load_model(model_path: &str, columns: &[String], gpu: &GpuOptions) -> Result<(Environment, Session)> {
     info!("Loading ONNX model from: {}", model_path);
     let start = Instant::now();
     
@@ -327,33 +1178,37 @@ fn load_model(model_path: &str) -> Result<(Environment, Session)> {
         .context("Failed to build ONNX environment")?;
     
     // Create session with optimized execution providers
-    let mut session_builder = environment.new_session_builder()?;
-    
-    // Check for GPU availability and configure execution providers
-    #[cfg(feature = "cuda")]
-    {
-        let cuda_provider = onnxruntime::ExecutionProvider::CUDA(Default::default());
-        session_builder = session_builder.with_execution_providers([cuda_provider])?;
-        debug!("Using CUDA execution provider for ONNX inference");
-    }
-    
-    #[cfg(not(feature = "cuda"))]
-    {
-        let cpu_provider = onnxruntime::ExecutionProvider::CPU(Default::default());
-        session_builder = session_builder.with_execution_providers([cpu_provider])?;
-        debug!("Using CPU execution provider for ONNX inference");
-    }
+    let (session_builder, provider) = execution_providers(environment.new_session_builder()?, gpu)?;
+    debug!("Using {} execution provider for ONNX inference", provider);
     
     // Load the model
     let session = session_builder
         .with_model_from_file(model_path)
-        .with_context(|| format!("Failed to load ONNX model from {}", model_path))?;
+        .with_context(|| format!("Failed to load ONNX model from {} on {}", model_path, provider))?;
     
     // Get model metadata
     let model_metadata = session.model_metadata()?;
     let input_names = model_metadata.inputs.iter().map(|i| i.name.clone()).collect::<Vec<_>>();
     let output_names = model_metadata.outputs.iter().map(|o| o.name.clone()).collect::<Vec<_>>();
     
+    let [input] = &model_metadata.inputs[..] else {
+        return Err(anyhow!(ScoringError::ModelLoadError(format!(
+            "{} has inputs {:?}; the scorer feeds one feature matrix",
+            model_path, input_names
+        ))));
+    };
+    if !input.accepts(&[1, columns.len()]) {
+        return Err(anyhow!(ScoringError::ModelLoadError(format!(
+            "{} input '{}' has dimensions {:?}, but the selected features give {} per variant: {}",
+            model_path,
+            input.name,
+            input.dimensions,
+            columns.len(),
+            columns.join(", ")
+        ))));
+    }
+    debug!("Model feature columns: {}", columns.join(", "));
+    
     info!(
         "Loaded ONNX model in {:.2?} with inputs: {:?}, outputs: {:?}",
         start.elapsed(),
@@ -364,24 +1219,294 @@ fn load_model(model_path: &str) -> Result<(Environment, Session)> {
     Ok((environment, session))
 }
 
-/// Run inference on a batch of variants
-fn run_inference(
-    session: &Session,
-    features: Array2<f32>,
-    extended_features: bool,
-) -> Result<Vec<f32>> {
-    // Validate feature array dimensions
-    let expected_features = if extended_features { 5 } else { 3 };
-    if features.shape()[1] != expected_features {
-        return Err(anyhow!(ScoringError::InferenceError(format!(
-            "Invalid feature dimensions: expected {} features, got {}",
-            expected_features,
-            features.shape()[1]
-        ))));
+/// Register CUDA on the selected device, when onnxruntime supports it, with
+/// the CPU after it for operators CUDA cannot run. Returns the builder and
+/// a description of the provider used.
+#[cfg(feature = "cuda")]
+fn execution_providers(
+    session_builder: onnxruntime::SessionBuilder,
+    gpu: &GpuOptions,
+) -> Result<(onnxruntime::SessionBuilder, String)> {
+    let cpu_provider = onnxruntime::ExecutionProvider::CPU(Default::default());
+    let available = onnxruntime::cuda_available()
+        .map_err(|e| anyhow!(ScoringError::ModelLoadError(format!("CUDA capability check failed: {}", e))));
+    match available {
+        Ok(true) => {}
+        Ok(false) | Err(_) if gpu.cpu_fallback => {
+            warn!("CUDA is not available; running inference on the CPU (--gpu-cpu-fallback)");
+            return Ok((session_builder.with_execution_providers([cpu_provider])?, "CPU".to_string()));
+        }
+        Ok(false) => {
+            return Err(anyhow!(ScoringError::ModelLoadError(
+                "the onnxruntime library was built without CUDA support; point ORT_LIB_LOCATION at a CUDA \
+                 build or pass --gpu-cpu-fallback"
+                    .to_string()
+            )))
+        }
+        Err(e) => return Err(e),
     }
     
-    // Create input tensor
-    let input_tensor = NdArrayTensor::from_array(features);
+    let device = gpu.device.unwrap_or(0);
+    let cuda_provider = onnxruntime::ExecutionProvider::CUDA(onnxruntime::CUDAExecutionProviderOptions {
+        device_id: i32::try_from(device)
+            .map_err(|_| anyhow!(ScoringError::InvalidInputError(format!("Invalid CUDA device: {}", device))))?,
+        memory_limit: gpu.memory_limit_mb.map(|mb| mb << 20),
+        // A capped arena grows only as needed, so it can use the whole cap
+        arena_same_as_requested: gpu.memory_limit_mb.is_some(),
+        // Without a fallback, a missing device fails the session instead of
+        // silently running on the CPU
+        error_on_failure: !gpu.cpu_fallback,
+    });
+    let session_builder = session_builder.with_execution_providers([cuda_provider, cpu_provider])?;
+    Ok((session_builder, format!("CUDA device {}", device)))
+}
+
+/// CPU-only builds: reject GPU options instead of ignoring them
+#[cfg(not(feature = "cuda"))]
+fn execution_providers(
+    session_builder: onnxruntime::SessionBuilder,
+    gpu: &GpuOptions,
+) -> Result<(onnxruntime::SessionBuilder, String)> {
+    if gpu.device.is_some() || gpu.memory_limit_mb.is_some() {
+        return Err(anyhow!(ScoringError::ModelLoadError(
+            "--gpu-device and --gpu-memory-mb need a build with the cuda feature (--features real-ort,cuda)".to_string()
+        )));
+    }
+    if gpu.cpu_fallback {
+        debug!("--gpu-cpu-fallback has no effect without the cuda feature");
+    }
+    let cpu_provider = onnxruntime::ExecutionProvider::CPU(Default::default());
+    Ok((session_builder.with_execution_providers([cpu_provider])?, "CPU".to_string()))
+}
+
+/// Model input columns implied by the scoring configuration, in order
+fn feature_columns(config: &ScoringConfig) -> Vec<String> {
+    let mut columns = config
+        .features
+        .iter()
+        .flat_map(|extractor| extractor.columns(&config.manifest))
+        .collect::<Vec<_>>();
+    if config.cohort_af {
+        columns.push("cohort_af".to_string());
+    }
+    if config.bam.is_some() {
+        columns.extend(column_names(&["read_depth", "allele_balance", "alt_mapq", "softclip_fraction"]));
+    }
+    if config.per_sample {
+        columns.extend(column_names(&["sample_ref_depth", "sample_alt_depth", "sample_gq", "sample_is_het", "sample_is_hom_alt"]));
+    }
+    columns
+}
+
+/// Number of model input features implied by the scoring configuration
+fn feature_count(config: &ScoringConfig) -> usize {
+    feature_columns(config).len()
+}
+
+/// Open an indexed BAM for read-evidence lookups
+fn open_bam(bam_path: &str) -> Result<bam::IndexedReader> {
+    debug!("Opening alignments for read evidence: {}", bam_path);
+    bam::IndexedReader::from_path(bam_path)
+        .with_context(|| format!("Failed to open indexed BAM (is there a .bai?): {}", bam_path))
+}
+
+/// Phase the VCF by transmission (`--pedigree`) or from reads (`--bam`),
+/// optionally writing it back to `--phased-vcf`
+fn run_phasing(vcf_path: &str, config: &ScoringConfig) -> Result<Option<Phasing>> {
+    let phasing = if let Some(ped_path) = &config.pedigree {
+        let trios = read_pedigree(ped_path).map_err(|e| anyhow!(ScoringError::InvalidInputError(e)))?;
+        let reader = bcf::Reader::from_path(vcf_path)
+            .with_context(|| format!("Failed to open VCF file: {}", vcf_path))?;
+        let trio = trios
+            .iter()
+            .find(|trio| trio.samples(reader.header()).is_some())
+            .ok_or_else(|| anyhow!(ScoringError::InvalidInputError(format!(
+                "No trio in {} has all three samples in {}",
+                ped_path, vcf_path
+            ))))?;
+        info!("Phasing {} by transmission from {} and {}", trio.child, trio.father, trio.mother);
+        let phasing = phase_trio_vcf(vcf_path, trio).map_err(|e| anyhow!(ScoringError::PhasingError(e)))?;
+        info!(
+            "Phased {}/{} heterozygous child sites ({} ambiguous, {} Mendelian errors)",
+            phasing.len(),
+            phasing.heterozygous_sites(),
+            phasing.ambiguous_sites(),
+            phasing.mendelian_errors()
+        );
+        phasing
+    } else if let Some(bam_path) = &config.bam {
+        info!("Phasing heterozygous SNVs from reads in {}", bam_path);
+        let options = PhasingOptions {
+            max_link_distance: config.phase_window as i64,
+            ..Default::default()
+        };
+        let phasing = phase_vcf(vcf_path, bam_path, &options)
+            .map_err(|e| anyhow!(ScoringError::PhasingError(e)))?;
+        info!(
+            "Phased {}/{} heterozygous SNVs into {} blocks",
+            phasing.len(),
+            phasing.heterozygous_sites(),
+            phasing.blocks()
+        );
+        phasing
+    } else {
+        info!("Phasing needs --bam or --pedigree; phase column left empty");
+        return Ok(None);
+    };
+    
+    if let Some(out_path) = &config.phased_vcf {
+        let written = phasing
+            .write_phased_vcf(vcf_path, out_path)
+            .map_err(|e| anyhow!(ScoringError::PhasingError(e)))?;
+        info!("Wrote {} phased records to {}", written, out_path);
+    }
+    Ok(Some(phasing))
+}
+
+/// Block and error summary of a phasing, against `--phase-truth` if given
+fn phasing_stats(phasing: &Phasing, config: &ScoringConfig) -> Result<PhasingStats> {
+    let truth = match &config.phase_truth {
+        Some(truth_path) => Some(
+            read_truth(truth_path, phasing.sample_name())
+                .map_err(|e| anyhow!(ScoringError::InvalidInputError(e)))?,
+        ),
+        None => None,
+    };
+    Ok(phasing.stats(truth.as_ref()))
+}
+
+/// Summarise the reads covering a variant site. `pos` is the 0-based VCF
+/// position; SNVs and MNVs are matched on read bases, indels on the pileup
+/// indel following the anchor base. Duplicate, secondary, QC-failed and
+/// unmapped reads are excluded by the pileup engine.
+fn read_evidence(
+    reader: &mut bam::IndexedReader,
+    chrom: &str,
+    pos: i64,
+    ref_allele: &str,
+    alt_allele: &str,
+) -> Result<Option<ReadEvidence>> {
+    use rust_htslib::bam::Read as _;
+    
+    if reader.header().tid(chrom.as_bytes()).is_none() {
+        return Ok(None);
+    }
+    reader
+        .fetch((chrom, pos, pos + 1))
+        .with_context(|| format!("Failed to fetch {}:{} from BAM", chrom, pos + 1))?;
+    
+    let ref_bytes = ref_allele.as_bytes();
+    let alt_bytes = alt_allele.as_bytes();
+    let indel_len = alt_bytes.len() as i64 - ref_bytes.len() as i64;
+    
+    for pileup in reader.pileup() {
+        let pileup = pileup.context("Failed to read BAM pileup")?;
+        if pileup.pos() as i64 != pos {
+            continue;
+        }
+        
+        let mut evidence = ReadEvidence::default();
+        for alignment in pileup.alignments() {
+            if alignment.is_refskip() {
+                continue;
+            }
+            let record = alignment.record();
+            evidence.depth += 1;
+            let cigar = record.cigar();
+            if cigar.leading_softclips() > 0 || cigar.trailing_softclips() > 0 {
+                evidence.softclipped_reads += 1;
+            }
+            
+            let supports_alt = if indel_len != 0 {
+                match alignment.indel() {
+                    Indel::Ins(len) if indel_len == len as i64 => Some(true),
+                    Indel::Del(len) if indel_len == -(len as i64) => Some(true),
+                    // A different indel, or inside an upstream deletion
+                    Indel::Ins(_) | Indel::Del(_) => None,
+                    Indel::None if alignment.is_del() => None,
+                    Indel::None => Some(false),
+                }
+            } else {
+                alignment.qpos().and_then(|qpos| {
+                    let seq = record.seq();
+                    if qpos + alt_bytes.len() > seq.len() {
+                        return None;
+                    }
+                    let read_bases: Vec<u8> = (qpos..qpos + alt_bytes.len()).map(|i| seq[i]).collect();
+                    if read_bases.eq_ignore_ascii_case(alt_bytes) {
+                        Some(true)
+                    } else if read_bases.eq_ignore_ascii_case(ref_bytes) {
+                        Some(false)
+                    } else {
+                        None
+                    }
+                })
+            };
+            
+            match supports_alt {
+                Some(true) => {
+                    evidence.alt_reads += 1;
+                    evidence.alt_mapq_sum += record.mapq() as u64;
+                }
+                Some(false) => evidence.ref_reads += 1,
+                None => {}
+            }
+        }
+        return Ok(Some(evidence));
+    }
+    
+    // No reads cover the site
+    Ok(Some(ReadEvidence::default()))
+}
+
+/// Called alleles per allele index in the record's GT field, over all
+/// samples
+fn allele_counts(record: &bcf::Record) -> Option<Vec<u32>> {
+    let allele_count = record.allele_count();
+    let genotypes = record.genotypes().ok()?;
+    let mut counts = vec![0u32; allele_count as usize];
+    
+    for sample_idx in 0..record.sample_count() as usize {
+        for allele in genotypes.get(sample_idx).iter() {
+            // Missing calls and vector-end padding of lower ploidy samples are skipped
+            match allele.index() {
+                Some(idx) if idx < allele_count => counts[idx as usize] += 1,
+                _ => {}
+            }
+        }
+    }
+    Some(counts)
+}
+
+/// Cohort-internal frequency of one ALT allele (1-based) among all called
+/// alleles
+fn cohort_allele_frequency(counts: &[u32], alt_index: usize) -> Option<f32> {
+    let called_alleles = counts.iter().sum::<u32>();
+    if called_alleles == 0 {
+        None
+    } else {
+        Some(counts[alt_index] as f32 / called_alleles as f32)
+    }
+}
+
+/// Run inference on a batch of variants
+fn run_inference(
+    session: &Session,
+    features: Array2<f32>,
+    expected_features: usize,
+) -> Result<Vec<f32>> {
+    // Validate feature array dimensions
+    if features.shape()[1] != expected_features {
+        return Err(anyhow!(ScoringError::InferenceError(format!(
+            "Invalid feature dimensions: expected {} features, got {}",
+            expected_features,
+            features.shape()[1]
+        ))));
+    }
+    
+    // Create input tensor
+    let rows = features.shape()[0];
+    let input_tensor = NdArrayTensor::from_array(features);
     
     // Run inference
     let outputs = session
@@ -395,62 +1520,518 @@ fn run_inference(
         .iter()
         .copied()
         .collect();
+    if scores.len() != rows {
+        return Err(anyhow!(ScoringError::InferenceError(format!(
+            "Model returned {} scores for {} variants",
+            scores.len(),
+            rows
+        ))));
+    }
     
     Ok(scores)
 }
 
-/// Extract features from a variant and graph context
-fn extract_features(
-    graph: &Graph,
-    chrom: &str,
+/// One variant allele and its surroundings, as seen by the feature extractors
+struct FeatureSite<'a> {
+    graph: &'a Graph,
+    chrom: &'a str,
     pos: i64,
-    ref_allele: &str,
-    alt_allele: &str,
-    extended_features: bool,
-) -> Result<Vec<f32>> {
-    // Basic features: reference length, alternate length
-    let mut features = vec![
-        ref_allele.len() as f32,
-        alt_allele.len() as f32,
-    ];
+    ref_allele: &'a str,
+    alt_allele: &'a str,
+    manifest: &'a FeatureManifest,
+    reference: Option<&'a ReferenceFasta>,
+}
+
+impl FeatureSite<'_> {
+    /// Repeat context of the reference around the site; empty without a FASTA
+    fn repeat_context(&self) -> Result<RepeatContext> {
+        let Some(reference) = self.reference else {
+            return Ok(RepeatContext::default());
+        };
+        let pos = self.pos as usize;
+        let start = pos.saturating_sub(REPEAT_WINDOW);
+        let window = reference.fetch(self.chrom, start, pos + REPEAT_WINDOW + 1)?;
+        Ok(repeat_context(&window, pos - start))
+    }
+}
+
+/// A named group of model input features, selectable with `--features`
+trait FeatureExtractor: std::fmt::Debug + Sync {
+    /// Name used on the command line
+    fn name(&self) -> &'static str;
+    
+    /// Names of the columns `extract` appends, in order
+    fn columns(&self, manifest: &FeatureManifest) -> Vec<String>;
+    
+    /// Whether the features are read from the reference FASTA
+    fn needs_reference(&self) -> bool {
+        false
+    }
+    
+    /// Append this group's values for one site
+    fn extract(&self, site: &FeatureSite, features: &mut Vec<f32>) -> Result<()>;
+}
+
+/// Feature group built into the scorer
+#[derive(Debug)]
+struct BuiltinFeature {
+    name: &'static str,
+    columns: fn(&FeatureManifest) -> Vec<String>,
+    needs_reference: bool,
+    extract: fn(&FeatureSite, &mut Vec<f32>) -> Result<()>,
+}
+
+impl FeatureExtractor for BuiltinFeature {
+    fn name(&self) -> &'static str {
+        self.name
+    }
     
-    // Get graph context at this position
-    let node_degree = graph.degree_at(chrom, pos as u64).unwrap_or(0) as f32;
-    features.push(node_degree);
+    fn columns(&self, manifest: &FeatureManifest) -> Vec<String> {
+        (self.columns)(manifest)
+    }
+    
+    fn needs_reference(&self) -> bool {
+        self.needs_reference
+    }
     
-    // Add extended features if requested
+    fn extract(&self, site: &FeatureSite, features: &mut Vec<f32>) -> Result<()> {
+        (self.extract)(site, features)
+    }
+}
+
+fn column_names(names: &[&str]) -> Vec<String> {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+/// Feature groups known to `--features`
+static FEATURE_EXTRACTORS: &[BuiltinFeature] = &[
+    BuiltinFeature {
+        name: "ref_len",
+        columns: |_| column_names(&["ref_len"]),
+        needs_reference: false,
+        extract: |site, features| {
+            features.push(site.ref_allele.len() as f32);
+            Ok(())
+        },
+    },
+    BuiltinFeature {
+        name: "alt_len",
+        columns: |_| column_names(&["alt_len"]),
+        needs_reference: false,
+        extract: |site, features| {
+            features.push(site.alt_allele.len() as f32);
+            Ok(())
+        },
+    },
+    BuiltinFeature {
+        name: "degree",
+        columns: |_| column_names(&["node_degree"]),
+        needs_reference: false,
+        extract: |site, features| {
+            features.push(site.graph.degree_at(site.chrom, site.pos as u64).unwrap_or(0) as f32);
+            Ok(())
+        },
+    },
+    // Node centrality (proxy for importance in graph)
+    BuiltinFeature {
+        name: "centrality",
+        columns: |_| column_names(&["centrality"]),
+        needs_reference: false,
+        extract: |site, features| {
+            features.push(site.graph.centrality_at(site.chrom, site.pos as u64).unwrap_or(0.0) as f32);
+            Ok(())
+        },
+    },
+    // Sequence complexity metrics selected by the manifest
+    BuiltinFeature {
+        name: "complexity",
+        columns: |manifest| manifest.complexity.iter().map(ComplexityMetric::column_name).collect(),
+        needs_reference: false,
+        extract: |site, features| {
+            let context = complexity_context(
+                site.reference,
+                site.chrom,
+                site.pos,
+                site.ref_allele,
+                site.alt_allele,
+                site.manifest.flank,
+            )?;
+            for metric in &site.manifest.complexity {
+                features.push(compute_sequence_complexity(&context, metric));
+            }
+            Ok(())
+        },
+    },
+    // Size and density of the surrounding subgraph
+    BuiltinFeature {
+        name: "neighborhood",
+        columns: |_| {
+            column_names(&[
+                "neighborhood_nodes",
+                "neighborhood_edges",
+                "neighborhood_mean_degree",
+                "neighborhood_sequence_length",
+            ])
+        },
+        needs_reference: false,
+        extract: |site, features| {
+            let neighborhood = site
+                .graph
+                .neighborhood_at(site.chrom, site.pos as u64, site.manifest.neighborhood_radius)
+                .unwrap_or_default();
+            features.push(neighborhood.node_count as f32);
+            features.push(neighborhood.edge_count as f32);
+            features.push(neighborhood.mean_degree as f32);
+            features.push(neighborhood.sequence_length as f32);
+            Ok(())
+        },
+    },
+    // Repeat context around the site; strong predictor of indel artifacts
+    BuiltinFeature {
+        name: "repeat_context",
+        columns: |_| column_names(&["homopolymer_len", "str_unit_len", "str_copies"]),
+        needs_reference: true,
+        extract: |site, features| {
+            let context = site.repeat_context()?;
+            features.push(context.homopolymer_len as f32);
+            features.push(context.str_unit.len() as f32);
+            features.push(context.str_copies as f32);
+            Ok(())
+        },
+    },
+    // Structural complexity: variants inside (nested) bubbles sit in
+    // regions where haplotypes diverge
+    BuiltinFeature {
+        name: "bubble",
+        columns: |_| column_names(&["in_bubble", "bubble_depth", "bubble_interior_nodes"]),
+        needs_reference: false,
+        extract: |site, features| {
+            let bubble = site.graph.bubble_at(site.chrom, site.pos as u64).unwrap_or_default();
+            features.push(site.graph.is_in_bubble(site.chrom, site.pos as u64) as u8 as f32);
+            features.push(bubble.depth as f32);
+            features.push(bubble.interior_nodes as f32);
+            Ok(())
+        },
+    },
+    // GC fraction of the alt allele and the manifest's flanking context
+    BuiltinFeature {
+        name: "gc",
+        columns: |_| column_names(&["gc_fraction"]),
+        needs_reference: false,
+        extract: |site, features| {
+            let context = complexity_context(
+                site.reference,
+                site.chrom,
+                site.pos,
+                site.ref_allele,
+                site.alt_allele,
+                site.manifest.flank,
+            )?;
+            features.push(gc_fraction(&context));
+            Ok(())
+        },
+    },
+    // Reference homopolymer run at the site, without the STR annotation
+    BuiltinFeature {
+        name: "homopolymer",
+        columns: |_| column_names(&["ref_homopolymer_len"]),
+        needs_reference: true,
+        extract: |site, features| {
+            features.push(site.repeat_context()?.homopolymer_len as f32);
+            Ok(())
+        },
+    },
+];
+
+fn feature_extractor(name: &str) -> Option<&'static dyn FeatureExtractor> {
+    FEATURE_EXTRACTORS
+        .iter()
+        .find(|extractor| extractor.name == name)
+        .map(|extractor| extractor as &dyn FeatureExtractor)
+}
+
+/// Feature groups of the default layout: the base features, the extended
+/// graph and complexity set, then the manifest's optional context
+fn default_features(extended_features: bool, manifest: &FeatureManifest) -> Vec<&'static dyn FeatureExtractor> {
+    let mut names = vec!["ref_len", "alt_len", "degree"];
     if extended_features {
-        // Get node centrality (proxy for importance in graph)
-        let centrality = graph.centrality_at(chrom, pos as u64).unwrap_or(0.0) as f32;
-        features.push(centrality);
-        
-        // Compute sequence complexity feature
-        // Simple implementation: ratio of unique k-mers to length
-        let seq_complexity = compute_sequence_complexity(alt_allele);
-        features.push(seq_complexity);
+        names.extend(["centrality", "complexity", "neighborhood"]);
+    }
+    if manifest.repeat_context {
+        names.push("repeat_context");
+    }
+    if manifest.bubble_context {
+        names.push("bubble");
+    }
+    names.into_iter().filter_map(feature_extractor).collect()
+}
+
+/// Resolve `--features` into extractors, falling back to the default layout
+/// when none are named
+fn resolve_features(
+    requested: &[String],
+    extended_features: bool,
+    manifest: &FeatureManifest,
+    reference: Option<&str>,
+) -> Result<Vec<&'static dyn FeatureExtractor>> {
+    if requested.is_empty() {
+        return Ok(default_features(extended_features, manifest));
     }
     
+    let mut features: Vec<&'static dyn FeatureExtractor> = Vec::with_capacity(requested.len());
+    for name in requested {
+        let extractor = feature_extractor(name).ok_or_else(|| {
+            let available = FEATURE_EXTRACTORS.iter().map(|extractor| extractor.name).collect::<Vec<_>>();
+            anyhow!(ScoringError::InvalidInputError(format!(
+                "Unknown feature set '{}' (available: {})",
+                name,
+                available.join(", ")
+            )))
+        })?;
+        if features.iter().any(|selected| selected.name() == extractor.name()) {
+            return Err(anyhow!(ScoringError::InvalidInputError(format!(
+                "Feature set '{}' is listed more than once",
+                name
+            ))));
+        }
+        if extractor.needs_reference() && reference.is_none() {
+            return Err(anyhow!(ScoringError::InvalidInputError(format!(
+                "Feature set '{}' needs --reference",
+                name
+            ))));
+        }
+        features.push(extractor);
+    }
     Ok(features)
 }
 
-/// Compute sequence complexity (simple k-mer based approach)
-fn compute_sequence_complexity(sequence: &str) -> f32 {
-    if sequence.len() <= 3 {
-        return 1.0;
+/// Extract the configured feature groups for a variant and its graph context
+fn extract_features(extractors: &[&dyn FeatureExtractor], site: &FeatureSite) -> Result<Vec<f32>> {
+    let mut features = Vec::new();
+    for extractor in extractors {
+        extractor
+            .extract(site, &mut features)
+            .with_context(|| format!("Failed to extract {} features", extractor.name()))?;
+    }
+    Ok(features)
+}
+
+/// Bases fetched on each side of a variant for repeat annotation
+const REPEAT_WINDOW: usize = 50;
+
+/// Longest tandem repeat unit considered as an STR
+const MAX_STR_UNIT: usize = 6;
+
+/// Homopolymer and short tandem repeat context at a variant position
+#[derive(Debug, Clone, Default, PartialEq)]
+struct RepeatContext {
+    homopolymer_len: u32,
+    str_unit: String,
+    str_copies: u32,
+}
+
+/// Repeat context of `seq` around offset `at`. Repeats starting right after
+/// `at` count too, so a VCF-anchored indel sees the repeat it expands.
+fn repeat_context(seq: &[u8], at: usize) -> RepeatContext {
+    let mut context = RepeatContext::default();
+    if at >= seq.len() {
+        return context;
+    }
+
+    // Homopolymer run containing the position or starting just after it
+    let run_len = |i: usize| {
+        let base = seq[i].to_ascii_uppercase();
+        let left = seq[..i].iter().rev().take_while(|b| b.to_ascii_uppercase() == base).count();
+        let right = seq[i..].iter().take_while(|b| b.to_ascii_uppercase() == base).count();
+        left + right
+    };
+    let mut homopolymer = run_len(at);
+    if at + 1 < seq.len() {
+        homopolymer = homopolymer.max(run_len(at + 1));
+    }
+    context.homopolymer_len = homopolymer as u32;
+
+    // Longest tandem repeat (unit 2..=6 bases, at least two copies)
+    // overlapping the position or the base after it
+    let upper: Vec<u8> = seq.iter().map(|b| b.to_ascii_uppercase()).collect();
+    let mut best_span = 0;
+    for unit_len in 2..=MAX_STR_UNIT {
+        for start in (at + 1).saturating_sub(unit_len - 1)..=(at + 1) {
+            if start + unit_len > upper.len() {
+                break;
+            }
+            let unit = &upper[start..start + unit_len];
+            // Units that are themselves repeats (e.g. ATAT) belong to a shorter period
+            if (1..unit_len).any(|p| unit_len % p == 0 && unit.iter().zip(&unit[p..]).all(|(a, b)| a == b)) {
+                continue;
+            }
+            let mut left = start;
+            while left > 0 && upper[left - 1] == upper[left - 1 + unit_len] {
+                left -= 1;
+            }
+            let mut right = start + unit_len;
+            while right < upper.len() && upper[right] == upper[right - unit_len] {
+                right += 1;
+            }
+            let span = right - left;
+            let copies = span / unit_len;
+            if copies >= 2 && left <= at + 1 && right > at && span > best_span {
+                best_span = span;
+                context.str_unit = String::from_utf8_lossy(&upper[left..left + unit_len]).into_owned();
+                context.str_copies = copies as u32;
+            }
+        }
+    }
+
+    context
+}
+
+/// Sequence scored by the complexity metrics: the alt allele, wrapped in
+/// `flank` reference bases on each side when a FASTA is available
+fn complexity_context(
+    reference: Option<&ReferenceFasta>,
+    chrom: &str,
+    pos: i64,
+    ref_allele: &str,
+    alt_allele: &str,
+    flank: usize,
+) -> Result<Vec<u8>> {
+    let mut context = Vec::with_capacity(alt_allele.len() + 2 * flank);
+    let start = pos as usize;
+    let end = start + ref_allele.len();
+    match reference {
+        Some(reference) if flank > 0 => {
+            context.extend(reference.fetch(chrom, start.saturating_sub(flank), start)?);
+            context.extend(alt_allele.bytes());
+            context.extend(reference.fetch(chrom, end, end + flank)?);
+        }
+        _ => context.extend(alt_allele.bytes()),
     }
     
-    let k = 3; // k-mer size
-    let mut kmers = std::collections::HashSet::new();
-    
-    for i in 0..=(sequence.len() - k) {
-        kmers.insert(&sequence[i..(i + k)]);
+    context.make_ascii_uppercase();
+    Ok(context)
+}
+
+/// Compute one sequence-complexity metric
+fn compute_sequence_complexity(sequence: &[u8], metric: &ComplexityMetric) -> f32 {
+    match metric {
+        ComplexityMetric::KmerUniqueness { k } => kmer_uniqueness(sequence, *k),
+        ComplexityMetric::ShannonEntropy { k } => kmer_entropy(sequence, *k),
+        ComplexityMetric::Dust => dust_score(sequence),
+        ComplexityMetric::HomopolymerMax => homopolymer_max(sequence) as f32,
+    }
+}
+
+/// Ratio of unique k-mers to possible k-mers
+fn kmer_uniqueness(sequence: &[u8], k: usize) -> f32 {
+    if sequence.len() <= k {
+        return 1.0;
     }
     
-    // Ratio of unique k-mers to possible k-mers
+    let kmers: std::collections::HashSet<&[u8]> = sequence.windows(k).collect();
     let max_kmers = sequence.len() - k + 1;
     kmers.len() as f32 / max_kmers as f32
 }
 
+/// Shannon entropy of the k-mer frequencies, divided by the maximum
+/// attainable for this many k-mers so short and long contexts compare
+fn kmer_entropy(sequence: &[u8], k: usize) -> f32 {
+    if sequence.len() < k {
+        return 0.0;
+    }
+    
+    let mut counts: std::collections::HashMap<&[u8], u32> = std::collections::HashMap::new();
+    for kmer in sequence.windows(k) {
+        *counts.entry(kmer).or_insert(0) += 1;
+    }
+    
+    let total = (sequence.len() - k + 1) as f64;
+    let max_entropy = total.min(4f64.powi(k as i32)).log2();
+    if max_entropy <= 0.0 {
+        return 0.0;
+    }
+    let entropy: f64 = counts
+        .values()
+        .map(|&c| {
+            let p = c as f64 / total;
+            p * (1.0 / p).log2()
+        })
+        .sum();
+    (entropy / max_entropy) as f32
+}
+
+/// SDUST score: sum over triplets of c * (c - 1) / 2, divided by the
+/// number of triplets minus one
+fn dust_score(sequence: &[u8]) -> f32 {
+    if sequence.len() < 4 {
+        return 0.0;
+    }
+    
+    let mut counts: std::collections::HashMap<&[u8], u32> = std::collections::HashMap::new();
+    for triplet in sequence.windows(3) {
+        *counts.entry(triplet).or_insert(0) += 1;
+    }
+    
+    let score: u32 = counts.values().map(|&c| c * (c - 1) / 2).sum();
+    score as f32 / (sequence.len() - 3) as f32
+}
+
+/// Length of the longest run of a single base
+fn homopolymer_max(sequence: &[u8]) -> usize {
+    sequence
+        .chunk_by(|a, b| a == b)
+        .map(|run| run.len())
+        .max()
+        .unwrap_or(0)
+}
+
+/// Fraction of G/C among the called (non-N) bases
+fn gc_fraction(sequence: &[u8]) -> f32 {
+    let called = sequence.iter().filter(|&&b| b != b'N').count();
+    if called == 0 {
+        return 0.0;
+    }
+    let gc = sequence.iter().filter(|&&b| b == b'G' || b == b'C').count();
+    gc as f32 / called as f32
+}
+
+/// Graph and model sessions a scoring run reads from; `batch-score` loads
+/// them once and shares them between the VCFs it scores in parallel
+struct ScoringResources {
+    graph: Graph,
+    /// One session per inference worker
+    sessions: Vec<(Environment, Session)>,
+}
+
+impl ScoringResources {
+    fn load(graph_path: &str, model_path: &str, config: &ScoringConfig, profile: &mut RunProfile) -> Result<Self> {
+        if config.inference_workers == 0 || config.max_inflight_batches == 0 {
+            return Err(anyhow!(ScoringError::InvalidInputError(
+                "--inference-workers and --max-inflight-batches must be at least 1".to_string()
+            )));
+        }
+        if config.per_sample && matches!(config.output_format, OutputFormat::Vcf) {
+            return Err(anyhow!(ScoringError::InvalidInputError(
+                "--per-sample writes one row per sample and needs a table --format, not vcf".to_string()
+            )));
+        }
+        
+        // Load graph
+        let stage_start = Instant::now();
+        let graph = load_graph(graph_path)?;
+        profile.record("load_graph", graph_path, stage_start, graph.node_count() as u64, file_len(graph_path), 0);
+        
+        // Load model, one session per inference worker
+        let stage_start = Instant::now();
+        let sessions = (0..config.inference_workers)
+            .map(|_| load_model(model_path, &feature_columns(config), &config.gpu))
+            .collect::<Result<Vec<_>>>()?;
+        profile.record("load_model", model_path, stage_start, 0, file_len(model_path), 0);
+        
+        Ok(Self { graph, sessions })
+    }
+}
+
 /// Score variants in a VCF file
 fn run_score(
     graph_path: &str,
@@ -458,14 +2039,39 @@ fn run_score(
     model_path: &str,
     out_path: &str,
     config: &ScoringConfig,
+    profile: &mut RunProfile,
+) -> Result<()> {
+    let resources = ScoringResources::load(graph_path, model_path, config, profile)?;
+    score_vcf(&resources, vcf_path, out_path, config, profile)
+}
+
+/// Score one VCF with already loaded resources
+fn score_vcf(
+    resources: &ScoringResources,
+    vcf_path: &str,
+    out_path: &str,
+    config: &ScoringConfig,
+    profile: &mut RunProfile,
 ) -> Result<()> {
     let start_time = Instant::now();
+    let ScoringResources { graph, sessions } = resources;
     
-    // Load graph
-    let graph = load_graph(graph_path)?;
+    // Each extraction thread opens its own alignment and reference readers;
+    // open them once here so a bad path fails before any work starts
+    ExtractReaders::open(config)?;
     
-    // Load model
-    let (_environment, session) = load_model(model_path)?;
+    // Read-backed phasing needs the whole VCF up front, since phase blocks
+    // span scoring batches
+    let phasing = if config.skip_phasing {
+        None
+    } else {
+        let stage_start = Instant::now();
+        let phasing = run_phasing(vcf_path, config)?;
+        if let Some(phasing) = &phasing {
+            profile.record("phase", vcf_path, stage_start, phasing.len() as u64, file_len(vcf_path), 0);
+        }
+        phasing
+    };
     
     // Setup progress tracking
     let multi_progress = MultiProgress::new();
@@ -494,97 +2100,181 @@ fn run_score(
     }
     
     // Open VCF reader
-    let mut reader = bcf::Reader::from_path(vcf_path)
-        .with_context(|| format!("Failed to open VCF file: {}", vcf_path))?;
-    
-    // Count total variants for progress tracking
-    let total_variants = count_variants(vcf_path)?;
-    let batch_progress = multi_progress.add(
-        ProgressBar::new(total_variants as u64)
-            .with_style(
-                ProgressStyle::default_bar()
-                    .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} variants ({eta})")
-                    .unwrap()
-                    .progress_chars("=>-"),
-            ),
-    );
+    let mut records = VcfRecords::open(vcf_path, &config.regions)?;
+    
+    // The VCF is read once; the progress bar takes its length from the
+    // index statistics when there are any and counts up otherwise
+    let expected_records = if config.regions.is_empty() { indexed_record_count(vcf_path) } else { None };
+    let batch_progress = match expected_records {
+        Some(expected) => multi_progress.add(
+            ProgressBar::new(expected)
+                .with_style(
+                    ProgressStyle::default_bar()
+                        .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} variants ({eta})")
+                        .unwrap()
+                        .progress_chars("=>-"),
+                ),
+        ),
+        None => multi_progress.add(
+            ProgressBar::new_spinner()
+                .with_style(
+                    ProgressStyle::default_spinner()
+                        .template("{spinner:.green} [{elapsed_precise}] {pos} variants ({per_sec})")
+                        .unwrap(),
+                ),
+        ),
+    };
     
     // Setup statistics tracking
     let stats = Arc::new(Mutex::new(ScoringStats {
-        total_variants,
+        phased_variants: phasing.as_ref().map_or(0, Phasing::len),
+        phasing: phasing.as_ref().map(|p| phasing_stats(p, config)).transpose()?,
         ..Default::default()
     }));
     
-    // Setup shared data structure for collecting results
-    let variants_info = Arc::new(Mutex::new(Vec::with_capacity(total_variants)));
-    let counter = Arc::new(AtomicUsize::new(0));
-    
-    // Process VCF in batches
-    let batch_size = config.batch_size;
-    let mut batch = Vec::with_capacity(batch_size);
-    let mut records = reader.records();
-    
-    // Get batch of records
-    while let Some(record_result) = records.next() {
-        let record = record_result.with_context(|| "Failed to read VCF record")?;
-        batch.push(record);
-        
-        if batch.len() >= batch_size {
-            // Process batch
-            process_batch(
-                &batch,
-                &graph,
-                &session,
-                config,
-                &variants_info,
-                &stats,
-                &counter,
-                &batch_progress,
-            )?;
-            
-            // Clear batch
-            batch.clear();
-        }
-    }
+    // Results are written batch by batch as the pipeline runs
+    let results = Mutex::new(OrderedResults::new(ResultWriter::create(
+        vcf_path,
+        &config.regions,
+        out_path,
+        config.output_format,
+    )?));
+    let counter = AtomicUsize::new(0);
     
-    // Process final batch if there are remaining records
-    if !batch.is_empty() {
-        process_batch(
-            &batch,
-            &graph,
-            &session,
-            config,
-            &variants_info,
-            &stats,
-            &counter,
-            &batch_progress,
-        )?;
-    }
+    // Pipeline: this thread reads records and extracts features (BAM and
+    // FASTA readers are not shared) while the workers run inference. The
+    // bounded queue keeps extraction at most `queue_depth` batches ahead.
+    let stage_start = Instant::now();
+    let (sender, receiver) = mpsc::sync_channel::<FeatureBatch>(config.queue_depth);
+    // Workers own the receiver, so the queue closes if they all fail
+    let receiver = Arc::new(Mutex::new(receiver));
+    let total_variants = thread::scope(|scope| -> Result<usize> {
+        let workers = sessions
+            .iter()
+            .map(|(_, session)| {
+                let receiver = Arc::clone(&receiver);
+                let (results, counter) = (&results, &counter);
+                let (phasing, stats) = (phasing.as_ref(), &stats);
+                scope.spawn(move || -> Result<()> {
+                    loop {
+                        let next = receiver.lock().unwrap().recv();
+                        let Ok(batch) = next else {
+                            return Ok(());
+                        };
+                        let index = batch.index;
+                        let batch_start = Instant::now();
+                        let variants = score_batch(session, batch, graph, config, phasing, stats)?;
+                        stats.lock().unwrap().inference_seconds.push(batch_start.elapsed().as_secs_f64());
+                        counter.fetch_add(variants.len(), Ordering::SeqCst);
+                        results.lock().unwrap().push(index, variants)?;
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        drop(receiver);
+        
+        let produced = (|| -> Result<usize> {
+            let with_counts = config.cohort_af || config.max_cohort_af.is_some();
+            let mut records_read = 0;
+            let mut index = 0;
+            let mut done = false;
+            while !done {
+                // Read the next run of up to `max_inflight_batches` batches
+                let mut inflight = Vec::with_capacity(config.max_inflight_batches);
+                while !done && inflight.len() < config.max_inflight_batches {
+                    let mut batch = Vec::with_capacity(config.batch_size);
+                    while batch.len() < config.batch_size {
+                        match records.next_record()? {
+                            Some(record) => batch.push(SiteRecord::from_record(&record, with_counts, config.per_sample)?),
+                            None => {
+                                done = true;
+                                break;
+                            }
+                        }
+                    }
+                    if !batch.is_empty() {
+                        inflight.push((index, batch));
+                        index += 1;
+                    }
+                }
+                
+                // Extract them in parallel, in order, each rayon thread
+                // with its own readers
+                let extracted = inflight
+                    .par_iter()
+                    .map_init(
+                        || ExtractReaders::open(config),
+                        |readers, (index, batch)| {
+                            let readers = readers.as_mut().map_err(|e| anyhow!("{:#}", e))?;
+                            let batch_start = Instant::now();
+                            let features = extract_batch(*index, batch, graph, config, readers.bam.as_mut(), readers.reference.as_ref(), &stats);
+                            stats.lock().unwrap().extract_seconds.push(batch_start.elapsed().as_secs_f64());
+                            features
+                        },
+                    )
+                    .collect::<Result<Vec<_>>>()?;
+                let read = inflight.iter().map(|(_, batch)| batch.len()).sum::<usize>();
+                records_read += read;
+                batch_progress.inc(read as u64);
+                
+                for ((index, _), features) in inflight.iter().zip(extracted) {
+                    let Some(features) = features else {
+                        // Nothing scoreable; the batch still takes its place in the output order
+                        results.lock().unwrap().push(*index, Vec::new())?;
+                        continue;
+                    };
+                    // A closed queue means a worker failed; its error is reported below
+                    if sender.send(features).is_err() {
+                        return Ok(records_read);
+                    }
+                }
+            }
+            Ok(records_read)
+        })();
+        drop(sender);
+        
+        for worker in workers {
+            worker.join().map_err(|_| anyhow!(ScoringError::InferenceError("inference worker panicked".to_string())))??;
+        }
+        produced
+    })?;
     
     // Get final count
     let processed_count = counter.load(Ordering::SeqCst);
     batch_progress.finish_with_message(format!("Processed {} variants", processed_count));
+    profile.record("score", vcf_path, stage_start, total_variants as u64, file_len(vcf_path), 0);
     
     // Update elapsed time in stats
     {
         let mut stats_guard = stats.lock().unwrap();
+        stats_guard.total_variants = total_variants;
         stats_guard.elapsed_seconds = start_time.elapsed().as_secs_f64();
         stats_guard.processed_variants = processed_count;
+        stats_guard.variants_per_second = total_variants as f64 / stats_guard.elapsed_seconds.max(f64::EPSILON);
+        stats_guard.extract_latency = LatencySummary::new(&stats_guard.extract_seconds);
+        stats_guard.inference_latency = LatencySummary::new(&stats_guard.inference_seconds);
     }
     
-    // Get all variant information
-    let all_variants = {
-        let guard = variants_info.lock().unwrap();
-        guard.clone()
-    };
-    
-    // Save results
+    // Complete the output; everything but the end was written while scoring
     main_progress.set_message(format!("Writing results to {}", out_path));
-    save_results(&all_variants, out_path, config.output_format)?;
+    let stage_start = Instant::now();
+    let written = results.into_inner().unwrap().finish()?;
+    profile.record("save", out_path, stage_start, written as u64, 0, file_len(out_path));
     
     // Print statistics
     let stats_guard = stats.lock().unwrap();
     print_statistics(&stats_guard);
+    if let Some(stats_path) = &config.stats_out {
+        let file = File::create(stats_path)
+            .with_context(|| format!("Failed to create stats file: {}", stats_path))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), &*stats_guard)
+            .with_context(|| format!("Failed to write stats file: {}", stats_path))?;
+        info!("Statistics written to {}", stats_path);
+    }
+    if let Some(metrics_path) = &config.metrics_textfile {
+        write_metrics_textfile(&stats_guard, vcf_path, metrics_path)?;
+        info!("Metrics written to {}", metrics_path);
+    }
     
     // Finish progress
     main_progress.finish_with_message(format!(
@@ -600,88 +2290,454 @@ fn run_score(
     Ok(())
 }
 
-/// Count variants in a VCF file
-fn count_variants(vcf_path: &str) -> Result<usize> {
-    let mut reader = bcf::Reader::from_path(vcf_path)?;
-    let count = reader.records().count();
-    Ok(count)
+/// Fields of a VCF record needed for feature extraction, detached from the
+/// reader's header so batches can move between threads
+struct SiteRecord {
+    chrom: String,
+    pos: i64,
+    alleles: Vec<String>,
+    /// GT allele counts for cohort frequencies, when those are used
+    allele_counts: Option<Vec<u32>>,
+    /// Per-sample calls with `--per-sample`
+    samples: Option<Vec<SampleCall>>,
 }
 
-/// Process a batch of variants
-fn process_batch(
-    batch: &[bcf::Record],
-    graph: &Graph,
-    session: &Session,
-    config: &ScoringConfig,
-    variants_info: &Arc<Mutex<Vec<VariantInfo>>>,
-    stats: &Arc<Mutex<ScoringStats>>,
-    counter: &Arc<AtomicUsize>,
-    progress: &ProgressBar,
-) -> Result<()> {
-    // Create batch feature matrix
-    let mut feature_vectors = Vec::with_capacity(batch.len());
-    let mut variant_meta = Vec::with_capacity(batch.len());
-    
-    // Process each variant in the batch
-    for record in batch {
-        // Get chromosome and position
+impl SiteRecord {
+    fn from_record(record: &bcf::Record, with_counts: bool, with_samples: bool) -> Result<Self> {
         // Use rid to get chromosome name since chrom() method doesn't exist
         let rid = record.rid().ok_or_else(|| anyhow!("Record has no RID"))?;
-        let header = record.header();
-        let chrom = std::str::from_utf8(header.rid2name(rid)?)
+        let chrom = std::str::from_utf8(record.header().rid2name(rid)?)
             .context("Failed to decode chromosome name")?
             .to_owned();
-        let pos = record.pos();
-        
-        // Get alleles - accessing directly without error matching since it returns Vec<&[u8]>
-        let alleles = record.alleles();
-        
-        // Skip if not biallelic
-        if alleles.len() != 2 {
-            // Update multi-allelic counter
-            {
-                let mut stats_guard = stats.lock().unwrap();
-                stats_guard.multi_allelic_variants += 1;
+        let alleles = record
+            .alleles()
+            .iter()
+            .map(|a| std::str::from_utf8(a).map(str::to_owned))
+            .collect::<Result<Vec<_>, _>>()
+            .context("Failed to decode alleles")?;
+        Ok(Self {
+            chrom,
+            pos: record.pos(),
+            alleles,
+            allele_counts: if with_counts { allele_counts(record) } else { None },
+            samples: if with_samples { Some(sample_calls(record)?) } else { None },
+        })
+    }
+}
+
+/// Genotype and FORMAT depth/quality of one sample at a record
+#[derive(Debug, Clone)]
+struct SampleCall {
+    name: String,
+    /// GT as written in the VCF, e.g. `0|1`
+    genotype: String,
+    /// Called allele indices; missing calls are left out
+    alleles: Vec<u32>,
+    /// FORMAT/AD, one depth per allele (0 where missing)
+    allele_depths: Vec<u32>,
+    /// FORMAT/GQ
+    genotype_quality: Option<u32>,
+}
+
+impl SampleCall {
+    fn carries(&self, alt_index: usize) -> bool {
+        self.alleles.contains(&(alt_index as u32))
+    }
+    
+    fn depth(&self, allele: usize) -> u32 {
+        self.allele_depths.get(allele).copied().unwrap_or(0)
+    }
+    
+    /// Feature channel appended per row with `--per-sample`: ref and alt
+    /// allele depth, genotype quality, heterozygous, homozygous alt
+    fn features(&self, alt_index: usize) -> [f32; 5] {
+        let alt = alt_index as u32;
+        let hom_alt = !self.alleles.is_empty() && self.alleles.iter().all(|&a| a == alt);
+        let het = self.carries(alt_index) && !hom_alt;
+        [
+            self.depth(0) as f32,
+            self.depth(alt_index) as f32,
+            self.genotype_quality.unwrap_or(0) as f32,
+            het as u8 as f32,
+            hom_alt as u8 as f32,
+        ]
+    }
+}
+
+/// Calls of every sample in a record; AD and GQ are optional FORMAT fields
+fn sample_calls(record: &bcf::Record) -> Result<Vec<SampleCall>> {
+    let header = record.header();
+    let genotypes = record.genotypes().context("Failed to read GT")?;
+    let allele_depths = record.format(b"AD").integer().ok();
+    let qualities = record.format(b"GQ").integer().ok();
+    // Missing values and vector-end padding are negative sentinels
+    let value = |v: i32| u32::try_from(v).ok();
+    
+    Ok(header
+        .samples()
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let genotype = genotypes.get(i);
+            SampleCall {
+                name: String::from_utf8_lossy(name).into_owned(),
+                genotype: genotype.to_string(),
+                alleles: genotype.iter().filter_map(|allele| allele.index()).collect(),
+                allele_depths: allele_depths
+                    .as_ref()
+                    .map(|ad| ad[i].iter().map(|&d| value(d).unwrap_or(0)).collect())
+                    .unwrap_or_default(),
+                genotype_quality: qualities.as_ref().and_then(|gq| gq[i].first().and_then(|&q| value(q))),
             }
-            continue;
+        })
+        .collect())
+}
+
+/// Per-thread readers for feature extraction
+struct ExtractReaders {
+    bam: Option<bam::IndexedReader>,
+    reference: Option<ReferenceFasta>,
+}
+
+impl ExtractReaders {
+    fn open(config: &ScoringConfig) -> Result<Self> {
+        Ok(Self {
+            bam: config.bam.as_deref().map(open_bam).transpose()?,
+            reference: config.reference.as_deref().map(ReferenceFasta::open).transpose()?,
+        })
+    }
+}
+
+/// Feature matrix of one VCF batch with the variants behind its rows,
+/// queued from extraction to the inference workers
+struct FeatureBatch {
+    index: usize,
+    features: Array2<f32>,
+    variants: Vec<VariantMeta>,
+}
+
+/// One scored allele: a biallelic record, or one ALT of a split
+/// multi-allelic record, in one sample with `--per-sample`
+#[derive(Clone)]
+struct VariantMeta {
+    /// 0-based position of the source record in the input VCF
+    record_index: usize,
+    /// ALT number within the source record (1 for biallelic records)
+    alt_index: usize,
+    chrom: String,
+    pos: i64,
+    ref_allele: String,
+    alt_allele: String,
+    cohort_af: Option<f32>,
+    evidence: Option<ReadEvidence>,
+    sample: Option<SampleCall>,
+}
+
+/// Minimal representation of one ALT of a multi-allelic record: the
+/// shared suffix is trimmed, then the shared prefix, keeping one base
+fn trim_alleles(pos: i64, ref_allele: &str, alt_allele: &str) -> (i64, String, String) {
+    let (mut ref_bytes, mut alt_bytes) = (ref_allele.as_bytes(), alt_allele.as_bytes());
+    while ref_bytes.len() > 1 && alt_bytes.len() > 1 && ref_bytes.last() == alt_bytes.last() {
+        ref_bytes = &ref_bytes[..ref_bytes.len() - 1];
+        alt_bytes = &alt_bytes[..alt_bytes.len() - 1];
+    }
+    let mut pos = pos;
+    while ref_bytes.len() > 1 && alt_bytes.len() > 1 && ref_bytes[0] == alt_bytes[0] {
+        ref_bytes = &ref_bytes[1..];
+        alt_bytes = &alt_bytes[1..];
+        pos += 1;
+    }
+    (
+        pos,
+        String::from_utf8_lossy(ref_bytes).into_owned(),
+        String::from_utf8_lossy(alt_bytes).into_owned(),
+    )
+}
+
+/// Number of records in a bgzipped VCF/BCF according to its CSI or tabix
+/// index, without reading the records; `None` without an index or stats
+fn indexed_record_count(vcf_path: &str) -> Option<u64> {
+    use rust_htslib::htslib;
+    
+    let path = std::ffi::CString::new(vcf_path).ok()?;
+    // SAFETY: the index pointer is checked for null and destroyed once
+    unsafe {
+        // Looks for <path>.csi first, then <path>.tbi
+        let index = htslib::hts_idx_load(path.as_ptr(), htslib::HTS_FMT_TBI as i32);
+        if index.is_null() {
+            return None;
+        }
+        // Contigs without records have no stats; an index without any
+        // (old tabix files) gives no count
+        let mut total = None;
+        for tid in 0..htslib::hts_idx_nseq(index) {
+            let (mut mapped, mut unmapped) = (0u64, 0u64);
+            if htslib::hts_idx_get_stat(index, tid, &mut mapped, &mut unmapped) == 0 {
+                total = Some(total.unwrap_or(0) + mapped);
+            }
+        }
+        htslib::hts_idx_destroy(index);
+        total
+    }
+}
+
+/// Genomic region from `--regions`: 1-based inclusive bounds, open-ended
+/// when omitted
+#[derive(Debug, Clone)]
+struct Region {
+    chrom: String,
+    start: u64,
+    end: Option<u64>,
+}
+
+impl FromStr for Region {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Positions like 5M or 250k
+        let position = |value: &str| -> Result<u64, String> {
+            let (digits, scale) = match value.char_indices().last() {
+                Some((i, 'k' | 'K')) => (&value[..i], 1_000),
+                Some((i, 'm' | 'M')) => (&value[..i], 1_000_000),
+                Some((i, 'g' | 'G')) => (&value[..i], 1_000_000_000),
+                _ => (value, 1),
+            };
+            match digits.parse::<u64>() {
+                Ok(n) if n > 0 => Ok(n * scale),
+                _ => Err(format!("invalid position '{}' in region '{}'", value, s)),
+            }
+        };
+        let (chrom, range) = match s.rsplit_once(':') {
+            Some((chrom, range)) => (chrom, Some(range)),
+            None => (s, None),
+        };
+        if chrom.is_empty() {
+            return Err(format!("region '{}' has no chromosome", s));
+        }
+        let (start, end) = match range.map(|r| r.split_once('-').unwrap_or((r, ""))) {
+            None => (1, None),
+            Some((start, "")) => (position(start)?, None),
+            Some((start, end)) => (position(start)?, Some(position(end)?)),
+        };
+        if end.is_some_and(|end| end < start) {
+            return Err(format!("region '{}' ends before it starts", s));
+        }
+        Ok(Region { chrom: chrom.to_string(), start, end })
+    }
+}
+
+/// VCF records in file order: all of them, or those whose POS falls in
+/// `--regions`, fetched through the index. Regions are sorted and merged
+/// first, so each record is returned once.
+enum VcfRecords {
+    All(bcf::Reader),
+    Regions {
+        reader: bcf::IndexedReader,
+        /// (rid, 0-based start, 0-based inclusive end) still to fetch
+        pending: VecDeque<(u32, u64, Option<u64>)>,
+        current: Option<(u64, Option<u64>)>,
+    },
+}
+
+impl VcfRecords {
+    fn open(vcf_path: &str, regions: &[Region]) -> Result<Self> {
+        if regions.is_empty() {
+            let reader = bcf::Reader::from_path(vcf_path)
+                .with_context(|| format!("Failed to open VCF file: {}", vcf_path))?;
+            return Ok(VcfRecords::All(reader));
+        }
+        let reader = bcf::IndexedReader::from_path(vcf_path)
+            .with_context(|| format!("Failed to open indexed VCF (is there a .csi or .tbi?): {}", vcf_path))?;
+        let mut windows = Vec::with_capacity(regions.len());
+        for region in regions {
+            match reader.header().name2rid(region.chrom.as_bytes()) {
+                Ok(rid) => windows.push((rid, region.start - 1, region.end.map(|end| end - 1))),
+                Err(_) => warn!("Region {} is not in the VCF header; skipped", region.chrom),
+            }
+        }
+        windows.sort_by_key(|&(rid, start, _)| (rid, start));
+        let mut pending: VecDeque<(u32, u64, Option<u64>)> = VecDeque::new();
+        for (rid, start, end) in windows {
+            match pending.back_mut() {
+                Some((last_rid, _, last_end)) if *last_rid == rid && last_end.is_none_or(|e| start <= e + 1) => {
+                    *last_end = last_end.zip(end).map(|(a, b)| a.max(b));
+                }
+                _ => pending.push_back((rid, start, end)),
+            }
+        }
+        Ok(VcfRecords::Regions { reader, pending, current: None })
+    }
+    
+    fn header(&self) -> &bcf::header::HeaderView {
+        match self {
+            VcfRecords::All(reader) => reader.header(),
+            VcfRecords::Regions { reader, .. } => reader.header(),
+        }
+    }
+    
+    fn next_record(&mut self) -> Result<Option<bcf::Record>> {
+        match self {
+            VcfRecords::All(reader) => {
+                let mut record = reader.empty_record();
+                match reader.read(&mut record) {
+                    Some(result) => result.map(|_| Some(record)).context("Failed to read VCF record"),
+                    None => Ok(None),
+                }
+            }
+            VcfRecords::Regions { reader, pending, current } => loop {
+                let Some((start, end)) = *current else {
+                    let Some((rid, start, end)) = pending.pop_front() else {
+                        return Ok(None);
+                    };
+                    reader.fetch(rid, start, end).context("Failed to fetch VCF region")?;
+                    *current = Some((start, end));
+                    continue;
+                };
+                let mut record = reader.empty_record();
+                match reader.read(&mut record) {
+                    Some(result) => {
+                        result.context("Failed to read VCF record")?;
+                        // Fetch also returns records that start earlier and overlap
+                        let pos = record.pos() as u64;
+                        if pos >= start && end.is_none_or(|end| pos <= end) {
+                            return Ok(Some(record));
+                        }
+                    }
+                    None => *current = None,
+                }
+            },
+        }
+    }
+}
+
+/// Read a batch of records into a feature matrix; `None` when no variant
+/// in it is scoreable
+fn extract_batch(
+    index: usize,
+    batch: &[SiteRecord],
+    graph: &Graph,
+    config: &ScoringConfig,
+    mut bam_reader: Option<&mut bam::IndexedReader>,
+    reference: Option<&ReferenceFasta>,
+    stats: &Arc<Mutex<ScoringStats>>,
+) -> Result<Option<FeatureBatch>> {
+    // Create batch feature matrix
+    let mut feature_vectors = Vec::with_capacity(batch.len());
+    let mut variant_meta = Vec::with_capacity(batch.len());
+    
+    // Process each variant in the batch
+    for (offset, record) in batch.iter().enumerate() {
+        // Every batch but the last is full, so this is the record's position in the VCF
+        let record_index = index * config.batch_size + offset;
+        let (chrom, alleles) = (&record.chrom, &record.alleles);
+        if alleles.len() > 2 {
+            let mut stats_guard = stats.lock().unwrap();
+            stats_guard.multi_allelic_variants += 1;
         }
         
-        // Convert alleles to strings
-        let ref_allele = std::str::from_utf8(alleles[0])
-            .context("Failed to decode reference allele")?
-            .to_owned();
-        let alt_allele = std::str::from_utf8(alleles[1])
-            .context("Failed to decode alternate allele")?
-            .to_owned();
-        
-        // Extract features
-        match extract_features(
-            graph,
-            &chrom,
-            pos as i64,
-            &ref_allele,
-            &alt_allele,
-            config.extended_features,
-        ) {
-            Ok(features) => {
-                // Store features and metadata
-                feature_vectors.push(features);
-                variant_meta.push((chrom, pos as i64, ref_allele, alt_allele));
-            }
-            Err(err) => {
-                warn!("Failed to extract features for variant at {}:{}: {}", chrom, pos, err);
+        // Multi-allelic records are split into one pseudo-record per ALT
+        for alt_index in 1..alleles.len() {
+            let alt = &alleles[alt_index];
+            // Spanning deletions and symbolic alleles carry no sequence to score
+            if alt == "*" || alt.starts_with('<') {
+                let mut stats_guard = stats.lock().unwrap();
+                stats_guard.symbolic_alleles_skipped += 1;
                 continue;
             }
+            let (pos, ref_allele, alt_allele) = if alleles.len() > 2 {
+                stats.lock().unwrap().split_alleles += 1;
+                trim_alleles(record.pos, &alleles[0], alt)
+            } else {
+                (record.pos, alleles[0].clone(), alt.clone())
+            };
+            
+            // Cohort allele frequency: recurrent calls across the cohort are a
+            // common signature of technical artifacts
+            let cohort_af = record
+                .allele_counts
+                .as_deref()
+                .and_then(|counts| cohort_allele_frequency(counts, alt_index));
+            if let (Some(max_af), Some(af)) = (config.max_cohort_af, cohort_af) {
+                if af > max_af {
+                    let mut stats_guard = stats.lock().unwrap();
+                    stats_guard.cohort_af_filtered += 1;
+                    continue;
+                }
+            }
+            
+            // Read-level evidence from the sample's alignments
+            let evidence = match bam_reader.as_deref_mut() {
+                Some(reader) => {
+                    let evidence = read_evidence(reader, chrom, pos, &ref_allele, &alt_allele)?;
+                    if evidence.is_none() {
+                        debug!("Contig {} not present in BAM; read evidence left empty", chrom);
+                        let mut stats_guard = stats.lock().unwrap();
+                        stats_guard.no_read_evidence += 1;
+                    }
+                    Some(evidence.unwrap_or_default())
+                }
+                None => None,
+            };
+            
+            // Extract features
+            let site = FeatureSite {
+                graph,
+                chrom,
+                pos,
+                ref_allele: &ref_allele,
+                alt_allele: &alt_allele,
+                manifest: &config.manifest,
+                reference,
+            };
+            match extract_features(&config.features, &site) {
+                Ok(mut features) => {
+                    if config.cohort_af {
+                        features.push(cohort_af.unwrap_or(0.0));
+                    }
+                    if let Some(evidence) = &evidence {
+                        features.extend_from_slice(&evidence.features());
+                    }
+                    let meta = VariantMeta {
+                        record_index,
+                        alt_index,
+                        chrom: chrom.clone(),
+                        pos,
+                        ref_allele,
+                        alt_allele,
+                        cohort_af,
+                        evidence,
+                        sample: None,
+                    };
+                    
+                    // Store features and metadata: one row for the allele,
+                    // or one per sample carrying it with --per-sample
+                    if config.per_sample {
+                        for sample in record.samples.iter().flatten().filter(|s| s.carries(alt_index)) {
+                            let mut row = features.clone();
+                            row.extend_from_slice(&sample.features(alt_index));
+                            feature_vectors.push(row);
+                            variant_meta.push(VariantMeta { sample: Some(sample.clone()), ..meta.clone() });
+                        }
+                    } else {
+                        feature_vectors.push(features);
+                        variant_meta.push(meta);
+                    }
+                }
+                Err(err) => {
+                    warn!("Failed to extract features for variant at {}:{}: {}", chrom, pos, err);
+                    continue;
+                }
+            }
         }
     }
     
     // Skip if no valid variants
     if feature_vectors.is_empty() {
-        return Ok(());
+        return Ok(None);
     }
     
     // Create feature array
-    let feature_dim = if config.extended_features { 5 } else { 3 };
+    let feature_dim = feature_count(config);
     let mut feature_array = Array2::zeros((feature_vectors.len(), feature_dim));
     
     for (i, features) in feature_vectors.iter().enumerate() {
@@ -690,41 +2746,31 @@ fn process_batch(
         }
     }
     
+    Ok(Some(FeatureBatch {
+        index,
+        features: feature_array,
+        variants: variant_meta,
+    }))
+}
+
+/// Score one feature batch and build its output records
+fn score_batch(
+    session: &Session,
+    batch: FeatureBatch,
+    graph: &Graph,
+    config: &ScoringConfig,
+    phasing: Option<&Phasing>,
+    stats: &Arc<Mutex<ScoringStats>>,
+) -> Result<Vec<VariantInfo>> {
     // Run inference
-    let scores = run_inference(session, feature_array, config.extended_features)?;
-    
-    // Phase variants if requested
-    let phase_results = if !config.skip_phasing {
-        // In our simplified implementation, we'll just phase each variant directly
-        batch
-            .par_iter()
-            .map(|record| {
-                let mut record_copy = record.clone();
-                match phase_block(&mut record_copy, config.phase_window) {
-                    Ok(phase_tag) => {
-                        // Increment phased counter if tag is not empty
-                        if !phase_tag.is_empty() && phase_tag != "." {
-                            let mut stats_guard = stats.lock().unwrap();
-                            stats_guard.phased_variants += 1;
-                        }
-                        Ok(phase_tag)
-                    }
-                    Err(err) => Err(anyhow!(ScoringError::PhasingError(format!(
-                        "Failed to phase variant: {}",
-                        err
-                    )))),
-                }
-            })
-            .collect::<Result<Vec<_>>>()?
-    } else {
-        // If phasing is skipped, just use empty tags
-        vec![".".to_string(); batch.len()]
-    };
+    let scores = run_inference(session, batch.features, feature_count(config))?;
     
     // Create variant info records
-    let mut new_variants = Vec::with_capacity(feature_vectors.len());
+    let mut new_variants = Vec::with_capacity(batch.variants.len());
     
-    for (i, (chrom, pos, ref_allele, alt_allele)) in variant_meta.into_iter().enumerate() {
+    for (i, VariantMeta { record_index, alt_index, chrom, pos, ref_allele, alt_allele, cohort_af, evidence, sample }) in
+        batch.variants.into_iter().enumerate()
+    {
         let score = scores[i];
         
         // Skip if below threshold
@@ -750,34 +2796,130 @@ fn process_batch(
             stats_guard.high_scoring_variants += 1;
         }
         
+        // Phase set (PS) of the variant, "." when unphased
+        let phase_block = phasing
+            .and_then(|p| p.get(&chrom, pos))
+            .map_or_else(|| ".".to_string(), |site| site.phase_set.to_string());
+        
         // Create variant info
         let variant_info = VariantInfo {
+            record_index,
+            alt_index,
             chrom,
             pos,
             ref_allele,
             alt_allele,
             score: score as f64,
-            phase_block: phase_results[i].clone(),
+            calibrated_score: config.calibration.as_ref().map(|c| c.apply(score as f64)),
+            phase_block,
             node_id,
             node_degree,
             centrality,
+            cohort_af: cohort_af.map(|af| af as f64),
+            read_depth: evidence.map(|e| e.depth),
+            allele_balance: evidence.map(|e| e.allele_balance() as f64),
+            alt_mapq: evidence.map(|e| e.mean_alt_mapq() as f64),
+            softclip_fraction: evidence.map(|e| e.softclip_fraction() as f64),
+            sample_ref_depth: sample.as_ref().map(|s| s.depth(0)),
+            sample_alt_depth: sample.as_ref().map(|s| s.depth(alt_index)),
+            sample_gq: sample.as_ref().and_then(|s| s.genotype_quality),
+            genotype: sample.as_ref().map(|s| s.genotype.clone()),
+            sample: sample.map(|s| s.name),
         };
         
         new_variants.push(variant_info);
     }
     
-    // Update progress
-    let new_count = new_variants.len();
-    counter.fetch_add(new_count, Ordering::SeqCst);
-    progress.inc(new_count as u64);
+    Ok(new_variants)
+}
+
+/// How `batch-score` schedules and restarts its files
+#[derive(Debug, Clone, Copy)]
+struct BatchOptions {
+    resume: bool,
+    parallel_files: usize,
+}
+
+/// Checkpoint file `batch-score` keeps in its output directory
+const CHECKPOINT_FILE: &str = "batch_checkpoint.json";
+
+/// Progress of one `batch-score` input
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum FileState {
+    Running,
+    Done,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct FileStatus {
+    state: FileState,
+    output: String,
+    elapsed_seconds: Option<f64>,
+    error: Option<String>,
+}
+
+/// Per-VCF status of a `batch-score` run, rewritten after every change so
+/// an interrupted run can be resumed. The model and feature columns are
+/// kept to refuse resuming with outputs from a different model.
+#[derive(Debug, Serialize, Deserialize)]
+struct BatchCheckpoint {
+    model: String,
+    feature_columns: Vec<String>,
+    files: BTreeMap<String, FileStatus>,
+}
+
+impl BatchCheckpoint {
+    fn load(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("Failed to open checkpoint: {}", path.display()))?;
+        serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("Invalid checkpoint: {}", path.display()))
+    }
     
-    // Add variants to the shared collection
-    {
-        let mut variants_guard = variants_info.lock().unwrap();
-        variants_guard.extend(new_variants);
+    /// Write through a temporary file, so a crash never leaves a torn checkpoint
+    fn save(&self, path: &Path) -> Result<()> {
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        let temp_file = NamedTempFile::new_in(dir)?;
+        let mut writer = BufWriter::new(temp_file.as_file());
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.flush()?;
+        drop(writer);
+        temp_file
+            .persist(path)
+            .with_context(|| format!("Failed to write checkpoint: {}", path.display()))?;
+        Ok(())
     }
     
-    Ok(())
+    fn is_done(&self, vcf_path: &str) -> bool {
+        self.files
+            .get(vcf_path)
+            .is_some_and(|status| status.state == FileState::Done && Path::new(&status.output).exists())
+    }
+}
+
+/// Output path of one `batch-score` input
+fn batch_output_path(out_dir: &str, vcf_path: &str, format: OutputFormat) -> String {
+    let file_name = Path::new(vcf_path)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("unknown")
+        .replace(".vcf", "")
+        .replace(".gz", "");
+    
+    format!(
+        "{}/{}.scored.{}",
+        out_dir,
+        file_name,
+        match format {
+            OutputFormat::Parquet => "parquet",
+            OutputFormat::Ipc => "arrow",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Json => "json",
+            OutputFormat::Tsv => "tsv",
+            OutputFormat::Vcf => "vcf.gz",
+        }
+    )
 }
 
 /// Run batch scoring on multiple VCF files
@@ -787,14 +2929,18 @@ fn run_batch_score(
     model_path: &str,
     out_dir: &str,
     config: &ScoringConfig,
+    batch: &BatchOptions,
+    profile: &mut RunProfile,
 ) -> Result<()> {
     let start_time = Instant::now();
+    if batch.parallel_files == 0 {
+        return Err(anyhow!(ScoringError::InvalidInputError(
+            "--parallel-files must be at least 1".to_string()
+        )));
+    }
     
-    // Load graph
-    let _graph = load_graph(graph_path)?;
-    
-    // Load model
-    let (_environment, _session) = load_model(model_path)?;
+    // Graph and sessions are loaded once and shared by every file
+    let resources = Arc::new(ScoringResources::load(graph_path, model_path, config, profile)?);
     
     // Read VCF list
     let vcf_files = read_file_list(vcf_list_path)
@@ -804,45 +2950,115 @@ fn run_batch_score(
     std::fs::create_dir_all(out_dir)
         .with_context(|| format!("Failed to create output directory: {}", out_dir))?;
     
-    // Process each VCF file
-    for (idx, vcf_path) in vcf_files.iter().enumerate() {
-        info!("Processing file {}/{}: {}", idx + 1, vcf_files.len(), vcf_path);
-        
-        // Create output path
-        let file_name = Path::new(vcf_path)
-            .file_name()
-            .and_then(|n| n.to_str())
-            .unwrap_or("unknown")
-            .replace(".vcf", "")
-            .replace(".gz", "");
-        
-        let out_path = format!(
-            "{}/{}.scored.{}",
-            out_dir,
-            file_name,
-            match config.output_format {
-                OutputFormat::Parquet => "parquet",
-                OutputFormat::Ipc => "arrow",
-                OutputFormat::Csv => "csv",
-                OutputFormat::Json => "json",
-                OutputFormat::Tsv => "tsv",
-            }
-        );
-        
-        // Process this VCF
-        match run_score(graph_path, vcf_path, model_path, &out_path, config) {
-            Ok(_) => info!("Successfully processed {}", vcf_path),
-            Err(e) => {
-                error!("Failed to process {}: {}", vcf_path, e);
-                // Continue with next file
-            }
+    // Start a new checkpoint, or pick up the previous run's
+    let checkpoint_path = Path::new(out_dir).join(CHECKPOINT_FILE);
+    let mut checkpoint = BatchCheckpoint {
+        model: model_path.to_string(),
+        feature_columns: feature_columns(config),
+        files: BTreeMap::new(),
+    };
+    if batch.resume && checkpoint_path.exists() {
+        let previous = BatchCheckpoint::load(&checkpoint_path)?;
+        if previous.model != checkpoint.model || previous.feature_columns != checkpoint.feature_columns {
+            return Err(anyhow!(ScoringError::InvalidInputError(format!(
+                "{} was written for model {} with different features; rerun without --resume",
+                checkpoint_path.display(),
+                previous.model
+            ))));
         }
+        checkpoint.files = previous.files;
+    } else if batch.resume {
+        warn!("No checkpoint at {}; scoring every file", checkpoint_path.display());
     }
     
+    let pending = vcf_files
+        .iter()
+        .enumerate()
+        .filter(|(_, vcf_path)| {
+            let done = checkpoint.is_done(vcf_path);
+            if done {
+                info!("Skipping {} (done in a previous run)", vcf_path);
+            }
+            !done
+        })
+        .collect::<Vec<_>>();
+    let skipped = vcf_files.len() - pending.len();
+    checkpoint.save(&checkpoint_path)?;
+    
+    // Workers take the next pending file until none are left; the
+    // checkpoint is saved under its lock after every state change
+    let checkpoint = Mutex::new(checkpoint);
+    let update = |vcf_path: &str, status: FileStatus| -> Result<()> {
+        let mut checkpoint = checkpoint.lock().unwrap();
+        checkpoint.files.insert(vcf_path.to_string(), status);
+        checkpoint.save(&checkpoint_path)
+    };
+    let next = AtomicUsize::new(0);
+    let worker_profiles = thread::scope(|scope| {
+        let workers = (0..batch.parallel_files.min(pending.len()))
+            .map(|_| {
+                let resources = Arc::clone(&resources);
+                let (vcf_files, pending, next, update) = (&vcf_files, &pending, &next, &update);
+                scope.spawn(move || -> Result<RunProfile> {
+                    let mut worker_profile = RunProfile::default();
+                    while let Some(&(idx, vcf_path)) = pending.get(next.fetch_add(1, Ordering::SeqCst)) {
+                        info!("Processing file {}/{}: {}", idx + 1, vcf_files.len(), vcf_path);
+                        let out_path = batch_output_path(out_dir, vcf_path, config.output_format);
+                        update(vcf_path, FileStatus {
+                            state: FileState::Running,
+                            output: out_path.clone(),
+                            elapsed_seconds: None,
+                            error: None,
+                        })?;
+                        
+                        // Process this VCF
+                        let file_start = Instant::now();
+                        let result = score_vcf(&resources, vcf_path, &out_path, config, &mut worker_profile);
+                        let mut status = FileStatus {
+                            state: FileState::Done,
+                            output: out_path,
+                            elapsed_seconds: Some(file_start.elapsed().as_secs_f64()),
+                            error: None,
+                        };
+                        match result {
+                            Ok(_) => info!("Successfully processed {}", vcf_path),
+                            Err(e) => {
+                                error!("Failed to process {}: {}", vcf_path, e);
+                                // Continue with next file
+                                status.state = FileState::Failed;
+                                status.error = Some(format!("{:#}", e));
+                            }
+                        }
+                        update(vcf_path, status)?;
+                    }
+                    Ok(worker_profile)
+                })
+            })
+            .collect::<Vec<_>>();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().map_err(|_| anyhow!("batch-score worker panicked"))?)
+            .collect::<Result<Vec<_>>>()
+    })?;
+    for worker_profile in worker_profiles {
+        profile.merge(worker_profile);
+    }
+    
+    let checkpoint = checkpoint.into_inner().unwrap();
+    let failed = vcf_files
+        .iter()
+        .filter(|vcf_path| checkpoint.files.get(*vcf_path).is_some_and(|s| s.state == FileState::Failed))
+        .count();
     info!(
-        "Batch processing completed in {:.2?}",
-        start_time.elapsed()
+        "Batch processing completed in {:.2?}: {} scored, {} skipped, {} failed",
+        start_time.elapsed(),
+        pending.len() - failed,
+        skipped,
+        failed
     );
+    if failed > 0 {
+        warn!("Rerun with --resume to retry only the failed files (see {})", checkpoint_path.display());
+    }
     
     Ok(())
 }
@@ -866,90 +3082,366 @@ fn read_file_list(path: &str) -> Result<Vec<String>> {
     Ok(file_list)
 }
 
-/// Save results to a file in the specified format
-fn save_results(
-    variants: &[VariantInfo],
-    out_path: &str,
+/// Output file written one scored batch at a time, so whole-genome runs
+/// do not hold every result in memory. Writes go to a temporary file that
+/// replaces `out_path` on `finish`.
+struct ResultWriter {
+    temp_file: NamedTempFile,
+    out_path: String,
     format: OutputFormat,
-) -> Result<()> {
-    // Create a temporary file for writing
-    let dir = Path::new(out_path).parent().unwrap_or_else(|| Path::new("."));
-    let temp_file = NamedTempFile::new_in(dir)?;
-    
-    // Get a mutable reference to the DataFrame before writing
-    let mut df = create_dataframe(variants)?;
+    sink: ResultSink,
+    written: usize,
+}
+
+enum ResultSink {
+    /// polars batched writer (IPC record batches, Parquet row groups or
+    /// CSV chunks): `Some` writes a frame, `None` finishes the file
+    Frames(Box<dyn FnMut(Option<&DataFrame>) -> PolarsResult<()> + Send>),
+    /// Elements of one JSON array
+    Json { writer: BufWriter<File>, first: bool },
+    Vcf(AnnotatedVcfWriter),
+}
+
+impl ResultWriter {
+    fn create(vcf_path: &str, regions: &[Region], out_path: &str, format: OutputFormat) -> Result<Self> {
+        let dir = Path::new(out_path).parent().unwrap_or_else(|| Path::new("."));
+        let temp_file = NamedTempFile::new_in(dir)?;
+        let file = || File::create(temp_file.path());
+        let schema = create_dataframe(&[])?.schema();
+        
+        let sink = match format {
+            OutputFormat::Parquet => {
+                let mut writer = ParquetWriter::new(file()?)
+                    .with_compression(ParquetCompression::Snappy)
+                    .batched(&schema)?;
+                ResultSink::Frames(Box::new(move |df: Option<&DataFrame>| match df {
+                    Some(df) => writer.write_batch(df),
+                    None => writer.finish().map(|_| ()),
+                }))
+            }
+            OutputFormat::Ipc => {
+                let mut writer = IpcWriter::new(file()?).batched(&schema)?;
+                ResultSink::Frames(Box::new(move |df: Option<&DataFrame>| match df {
+                    Some(df) => writer.write_batch(df),
+                    None => writer.finish(),
+                }))
+            }
+            OutputFormat::Csv | OutputFormat::Tsv => {
+                let delimiter = if let OutputFormat::Tsv = format { b'\t' } else { b',' };
+                let mut writer = CsvWriter::new(file()?)
+                    .has_header(true)
+                    .with_delimiter(delimiter)
+                    .batched(&schema)?;
+                ResultSink::Frames(Box::new(move |df: Option<&DataFrame>| match df {
+                    Some(df) => writer.write_batch(df),
+                    None => Ok(()),
+                }))
+            }
+            OutputFormat::Json => {
+                let mut writer = BufWriter::new(file()?);
+                writer.write_all(b"[")?;
+                ResultSink::Json { writer, first: true }
+            }
+            OutputFormat::Vcf => ResultSink::Vcf(AnnotatedVcfWriter::new(vcf_path, regions, temp_file.path())?),
+        };
+        
+        Ok(Self {
+            temp_file,
+            out_path: out_path.to_string(),
+            format,
+            sink,
+            written: 0,
+        })
+    }
     
-    match format {
-        OutputFormat::Parquet => {
-            ParquetWriter::new(File::create(temp_file.path())?)
-                .with_compression(ParquetCompression::Snappy)
-                .finish(&mut df)?;
+    /// Write the next batch; batches must arrive in input order
+    fn write_batch(&mut self, variants: &[VariantInfo]) -> Result<()> {
+        if variants.is_empty() {
+            return Ok(());
         }
-        OutputFormat::Ipc => {
-            IpcWriter::new(File::create(temp_file.path())?)
-                .finish(&mut df)?;
+        match &mut self.sink {
+            ResultSink::Frames(write) => write(Some(&create_dataframe(variants)?))?,
+            ResultSink::Json { writer, first } => {
+                for variant in variants {
+                    writer.write_all(if *first { b"\n" } else { b",\n" })?;
+                    serde_json::to_writer_pretty(&mut *writer, variant)?;
+                    *first = false;
+                }
+            }
+            ResultSink::Vcf(writer) => writer.write_batch(variants)?,
         }
-        OutputFormat::Csv => {
-            CsvWriter::new(File::create(temp_file.path())?)
-                .has_header(true)
-                .with_delimiter(b',')
-                .finish(&mut df)?;
+        self.written += variants.len();
+        Ok(())
+    }
+    
+    /// Complete the file and move it to the output path; returns the
+    /// number of variants written
+    fn finish(self) -> Result<usize> {
+        match self.sink {
+            ResultSink::Frames(mut write) => write(None)?,
+            ResultSink::Json { mut writer, .. } => {
+                writer.write_all(b"\n]\n")?;
+                writer.flush()?;
+            }
+            ResultSink::Vcf(writer) => writer.finish()?,
         }
-        OutputFormat::Json => {
-            // Serialize to JSON using serde
-            let json = serde_json::to_string_pretty(variants)?;
-            let mut file = BufWriter::new(File::create(temp_file.path())?);
-            file.write_all(json.as_bytes())?;
-            file.flush()?;
+        
+        // Rename temporary file to the target path (atomic operation)
+        let out_path = self.out_path;
+        self.temp_file.persist(&out_path)
+            .with_context(|| format!("Failed to write output file: {}", out_path))?;
+        
+        // The index is named after the final path, so build it after the rename
+        if let OutputFormat::Vcf = self.format {
+            bcf::index::build(&out_path, None::<&str>, 1, bcf::index::Type::Tbx).map_err(|e| {
+                anyhow!(ScoringError::OutputError(format!("Failed to tabix-index {}: {:?}", out_path, e)))
+            })?;
         }
-        OutputFormat::Tsv => {
-            CsvWriter::new(File::create(temp_file.path())?)
-                .has_header(true)
-                .with_delimiter(b'\t')
-                .finish(&mut df)?;
+        
+        info!("Results saved to {}", out_path);
+        Ok(self.written)
+    }
+}
+
+/// Batches of one scoring run, written in input order as soon as every
+/// earlier batch is in; workers finish out of order
+struct OrderedResults {
+    next: usize,
+    pending: BTreeMap<usize, Vec<VariantInfo>>,
+    writer: ResultWriter,
+}
+
+impl OrderedResults {
+    fn new(writer: ResultWriter) -> Self {
+        Self { next: 0, pending: BTreeMap::new(), writer }
+    }
+    
+    /// Add batch `index` (empty when nothing in it was scored)
+    fn push(&mut self, index: usize, variants: Vec<VariantInfo>) -> Result<()> {
+        self.pending.insert(index, variants);
+        while let Some(variants) = self.pending.remove(&self.next) {
+            self.writer.write_batch(&variants)?;
+            self.next += 1;
         }
+        Ok(())
     }
     
-    // Rename temporary file to the target path (atomic operation)
-    temp_file.persist(out_path)
-        .with_context(|| format!("Failed to write output file: {}", out_path))?;
+    fn finish(self) -> Result<usize> {
+        if !self.pending.is_empty() {
+            return Err(anyhow!(ScoringError::OutputError(format!(
+                "Batch {} was never scored; results are incomplete",
+                self.next
+            ))));
+        }
+        self.writer.finish()
+    }
+}
+
+/// Copy of the input VCF (only `regions`, when given) as bgzipped VCF, adding SCORE, PHASE_BLOCK,
+/// NODE_DEGREE and CENTRALITY INFO fields to every scored record, plus
+/// CALIBRATED_SCORE with `--calibration`. Records that were not scored
+/// (filtered or skipped) are copied unchanged; ALTs of a split record that
+/// were not scored get a missing SCORE. Scored batches come in input
+/// order, so the input is read alongside them.
+struct AnnotatedVcfWriter {
+    records: VcfRecords,
+    writer: bcf::Writer,
+    /// Index of the next input record to copy
+    record_index: usize,
+}
+
+impl AnnotatedVcfWriter {
+    fn new(vcf_path: &str, regions: &[Region], out_path: &Path) -> Result<Self> {
+        let records = VcfRecords::open(vcf_path, regions)?;
+        let mut header = bcf::Header::from_template(records.header());
+        header.push_record(br#"##INFO=<ID=SCORE,Number=A,Type=Float,Description="Variant score from the ONNX model">"#);
+        header.push_record(br#"##INFO=<ID=CALIBRATED_SCORE,Number=A,Type=Float,Description="Calibrated probability that the allele is real">"#);
+        header.push_record(br#"##INFO=<ID=PHASE_BLOCK,Number=1,Type=Integer,Description="Phase set (PS) of the variant">"#);
+        header.push_record(br#"##INFO=<ID=NODE_DEGREE,Number=1,Type=Integer,Description="Degree of the pangenome graph node at the variant">"#);
+        header.push_record(br#"##INFO=<ID=CENTRALITY,Number=1,Type=Float,Description="Betweenness centrality of the graph node at the variant">"#);
+        let writer = bcf::Writer::from_path(out_path, &header, false, bcf::Format::Vcf)
+            .with_context(|| format!("Failed to create VCF: {}", out_path.display()))?;
+        Ok(Self { records, writer, record_index: 0 })
+    }
     
-    info!("Results saved to {}", out_path);
+    /// Copy input records up to the last one scored in this batch,
+    /// annotating the scored ones
+    fn write_batch(&mut self, variants: &[VariantInfo]) -> Result<()> {
+        for alts in variants.chunk_by(|a, b| a.record_index == b.record_index) {
+            let target = alts[0].record_index;
+            while self.record_index <= target {
+                let mut record = self.records.next_record()?.ok_or_else(|| {
+                    anyhow!(ScoringError::OutputError(format!("Scored record {} is not in the input VCF", target)))
+                })?;
+                self.writer.translate(&mut record);
+                if self.record_index == target {
+                    annotate_record(&mut record, alts)?;
+                }
+                self.writer.write(&record)?;
+                self.record_index += 1;
+            }
+        }
+        Ok(())
+    }
+    
+    /// Copy the records after the last scored one
+    fn finish(mut self) -> Result<()> {
+        while let Some(mut record) = self.records.next_record()? {
+            self.writer.translate(&mut record);
+            self.writer.write(&record)?;
+        }
+        Ok(())
+    }
+}
+
+/// Add the score INFO fields of one record's scored ALTs
+fn annotate_record(record: &mut bcf::Record, alts: &[VariantInfo]) -> Result<()> {
+    let mut scores = vec![f32::missing(); record.allele_count().saturating_sub(1) as usize];
+    let mut calibrated = scores.clone();
+    for alt in alts {
+        scores[alt.alt_index - 1] = alt.score as f32;
+        if let Some(probability) = alt.calibrated_score {
+            calibrated[alt.alt_index - 1] = probability as f32;
+        }
+    }
+    record.push_info_float(b"SCORE", &scores)?;
+    if alts.iter().any(|alt| alt.calibrated_score.is_some()) {
+        record.push_info_float(b"CALIBRATED_SCORE", &calibrated)?;
+    }
+    // Site-level fields are shared by every ALT of the record
+    let variant = &alts[0];
+    if let Ok(phase_set) = variant.phase_block.parse::<i32>() {
+        record.push_info_integer(b"PHASE_BLOCK", &[phase_set])?;
+    }
+    if let Some(degree) = variant.node_degree {
+        record.push_info_integer(b"NODE_DEGREE", &[degree as i32])?;
+    }
+    if let Some(centrality) = variant.centrality {
+        record.push_info_float(b"CENTRALITY", &[centrality as f32])?;
+    }
     Ok(())
 }
 
 /// Create a DataFrame from variant information
 fn create_dataframe(variants: &[VariantInfo]) -> Result<DataFrame> {
     // Create vectors for each column
+    let record_indices = variants.iter().map(|v| v.record_index as u64).collect::<Vec<_>>();
+    let alt_indices = variants.iter().map(|v| v.alt_index as u32).collect::<Vec<_>>();
     let chroms = variants.iter().map(|v| v.chrom.clone()).collect::<Vec<_>>();
     let positions = variants.iter().map(|v| v.pos).collect::<Vec<_>>();
     let ref_alleles = variants.iter().map(|v| v.ref_allele.clone()).collect::<Vec<_>>();
     let alt_alleles = variants.iter().map(|v| v.alt_allele.clone()).collect::<Vec<_>>();
     let scores = variants.iter().map(|v| v.score).collect::<Vec<_>>();
+    let calibrated_scores = variants.iter().map(|v| v.calibrated_score).collect::<Vec<_>>();
     let phase_blocks = variants.iter().map(|v| v.phase_block.clone()).collect::<Vec<_>>();
     
     // Optional columns
     let node_ids = variants.iter().map(|v| v.node_id.unwrap_or(0)).collect::<Vec<_>>();
     let node_degrees = variants.iter().map(|v| v.node_degree.unwrap_or(0)).collect::<Vec<_>>();
     let centralities = variants.iter().map(|v| v.centrality.unwrap_or(0.0)).collect::<Vec<_>>();
+    let cohort_afs = variants.iter().map(|v| v.cohort_af).collect::<Vec<_>>();
+    let read_depths = variants.iter().map(|v| v.read_depth).collect::<Vec<_>>();
+    let allele_balances = variants.iter().map(|v| v.allele_balance).collect::<Vec<_>>();
+    let alt_mapqs = variants.iter().map(|v| v.alt_mapq).collect::<Vec<_>>();
+    let softclip_fractions = variants.iter().map(|v| v.softclip_fraction).collect::<Vec<_>>();
+    let samples = variants.iter().map(|v| v.sample.clone()).collect::<Vec<_>>();
+    let genotypes = variants.iter().map(|v| v.genotype.clone()).collect::<Vec<_>>();
+    let sample_ref_depths = variants.iter().map(|v| v.sample_ref_depth).collect::<Vec<_>>();
+    let sample_alt_depths = variants.iter().map(|v| v.sample_alt_depth).collect::<Vec<_>>();
+    let sample_gqs = variants.iter().map(|v| v.sample_gq).collect::<Vec<_>>();
     
     // Create DataFrame
     let df_columns = vec![
+        Series::new("record_index", record_indices),
+        Series::new("alt_index", alt_indices),
         Series::new("chrom", chroms),
         Series::new("pos", positions),
         Series::new("ref", ref_alleles),
         Series::new("alt", alt_alleles),
         Series::new("score", scores),
+        Series::new("calibrated_score", calibrated_scores),
         Series::new("phase", phase_blocks),
         Series::new("node_id", node_ids),
         Series::new("node_degree", node_degrees),
         Series::new("centrality", centralities),
+        Series::new("cohort_af", cohort_afs),
+        Series::new("read_depth", read_depths),
+        Series::new("allele_balance", allele_balances),
+        Series::new("alt_mapq", alt_mapqs),
+        Series::new("softclip_fraction", softclip_fractions),
+        Series::new("sample", samples),
+        Series::new("genotype", genotypes),
+        Series::new("sample_ref_depth", sample_ref_depths),
+        Series::new("sample_alt_depth", sample_alt_depths),
+        Series::new("sample_gq", sample_gqs),
     ];
     
     DataFrame::new(df_columns)
         .with_context(|| "Failed to create DataFrame from variant data")
 }
 
+/// Upper bounds of the per-batch latency histogram buckets, in seconds
+const LATENCY_BUCKETS: [f64; 10] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 10.0];
+
+/// Write the statistics of one run as Prometheus metrics labeled with the
+/// VCF. The file is replaced in one rename, as the node_exporter textfile
+/// collector may read it at any time.
+fn write_metrics_textfile(stats: &ScoringStats, vcf_path: &str, out_path: &str) -> Result<()> {
+    use std::fmt::Write as _;
+    
+    let vcf = vcf_path.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+    let mut text = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, samples: &[(&str, f64)]| {
+        let _ = writeln!(text, "# HELP variant_scorer_{} {}", name, help);
+        let _ = writeln!(text, "# TYPE variant_scorer_{} {}", name, kind);
+        for (labels, value) in samples {
+            let _ = writeln!(text, "variant_scorer_{}{{vcf=\"{}\"{}}} {}", name, vcf, labels, value);
+        }
+    };
+    metric("variants_total", "counter", "VCF records read", &[("", stats.total_variants as f64)]);
+    metric("processed_variants_total", "counter", "Variant alleles scored", &[("", stats.processed_variants as f64)]);
+    metric(
+        "high_scoring_variants_total",
+        "counter",
+        "Scored alleles with score >= 0.7",
+        &[("", stats.high_scoring_variants as f64)],
+    );
+    metric(
+        "skipped_variants_total",
+        "counter",
+        "Variant alleles not scored or not written, by reason",
+        &[
+            (",reason=\"min_score\"", stats.filtered_variants as f64),
+            (",reason=\"cohort_af\"", stats.cohort_af_filtered as f64),
+            (",reason=\"symbolic\"", stats.symbolic_alleles_skipped as f64),
+        ],
+    );
+    metric("phased_variants_total", "counter", "Variants with a phase set", &[("", stats.phased_variants as f64)]);
+    metric("elapsed_seconds", "gauge", "Wall time of the scoring run", &[("", stats.elapsed_seconds)]);
+    metric("variants_per_second", "gauge", "VCF records read per second", &[("", stats.variants_per_second)]);
+    
+    let _ = writeln!(text, "# HELP variant_scorer_batch_seconds Wall time per batch, by pipeline stage");
+    let _ = writeln!(text, "# TYPE variant_scorer_batch_seconds histogram");
+    for (stage, samples) in [("extract", &stats.extract_seconds), ("inference", &stats.inference_seconds)] {
+        let labels = format!("vcf=\"{}\",stage=\"{}\"", vcf, stage);
+        for bound in LATENCY_BUCKETS {
+            let count = samples.iter().filter(|&&s| s <= bound).count();
+            let _ = writeln!(text, "variant_scorer_batch_seconds_bucket{{{},le=\"{}\"}} {}", labels, bound, count);
+        }
+        let _ = writeln!(text, "variant_scorer_batch_seconds_bucket{{{},le=\"+Inf\"}} {}", labels, samples.len());
+        let _ = writeln!(text, "variant_scorer_batch_seconds_sum{{{}}} {}", labels, samples.iter().sum::<f64>());
+        let _ = writeln!(text, "variant_scorer_batch_seconds_count{{{}}} {}", labels, samples.len());
+    }
+    
+    let dir = Path::new(out_path).parent().unwrap_or_else(|| Path::new("."));
+    let mut temp_file = NamedTempFile::new_in(dir)?;
+    temp_file.write_all(text.as_bytes())?;
+    temp_file
+        .persist(out_path)
+        .with_context(|| format!("Failed to write metrics file: {}", out_path))?;
+    Ok(())
+}
+
 /// Print statistics about the scoring process
 fn print_statistics(stats: &ScoringStats) {
     println!("\n===== Variant Scoring Statistics =====");
@@ -957,8 +3449,795 @@ fn print_statistics(stats: &ScoringStats) {
     println!("Processed variants: {}", stats.processed_variants);
     println!("High scoring variants (≥0.7): {}", stats.high_scoring_variants);
     println!("Filtered variants: {}", stats.filtered_variants);
-    println!("Multi-allelic variants: {}", stats.multi_allelic_variants);
+    println!(
+        "Multi-allelic variants: {} (split into {} alleles)",
+        stats.multi_allelic_variants, stats.split_alleles
+    );
+    if stats.symbolic_alleles_skipped > 0 {
+        println!("Symbolic/spanning-deletion alleles skipped: {}", stats.symbolic_alleles_skipped);
+    }
+    println!("Cohort AF filtered variants: {}", stats.cohort_af_filtered);
+    if stats.no_read_evidence > 0 {
+        println!("Variants on contigs missing from BAM: {}", stats.no_read_evidence);
+    }
     println!("Phased variants: {}", stats.phased_variants);
+    if let Some(phasing) = &stats.phasing {
+        println!("Unphased heterozygous variants: {}", phasing.unphased_sites);
+        println!(
+            "Phase blocks: {} (mean {:.1} variants, N50 {} bp, largest {} bp)",
+            phasing.blocks, phasing.mean_block_sites, phasing.block_n50_bp, phasing.largest_block_bp
+        );
+        if phasing.ambiguous_sites > 0 || phasing.mendelian_errors > 0 {
+            println!("Ambiguous trio sites: {}", phasing.ambiguous_sites);
+            println!("Mendelian errors: {}", phasing.mendelian_errors);
+        }
+        if let Some(truth) = &phasing.truth {
+            println!(
+                "Switch error rate: {:.4} ({}/{} adjacent pairs)",
+                truth.switch_error_rate, truth.switch_errors, truth.assessed_pairs
+            );
+            println!(
+                "Hamming error rate: {:.4} ({}/{} variants)",
+                truth.hamming_error_rate, truth.hamming_errors, truth.compared_sites
+            );
+        }
+    }
     println!("Processing time: {:.2} seconds", stats.elapsed_seconds);
+    println!(
+        "Throughput: {:.0} variants/s (batch p95: extract {:.3}s, inference {:.3}s)",
+        stats.variants_per_second, stats.extract_latency.p95_seconds, stats.inference_latency.p95_seconds
+    );
     println!("=====================================\n");
-}
\ No newline at end of file
+}
+
+/// Calibration fitted by `calibrate`
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum CalibrationMethod {
+    /// Logistic fit of the label on the raw score
+    Platt,
+    /// Non-decreasing step function (pool adjacent violators)
+    Isotonic,
+}
+
+/// Mapping from raw model scores to probabilities that a variant is real
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+enum Calibration {
+    /// p = 1 / (1 + exp(a * score + b))
+    Platt { a: f64, b: f64 },
+    /// Probabilities at increasing score thresholds, interpolated linearly
+    /// between them and held constant outside
+    Isotonic { thresholds: Vec<f64>, values: Vec<f64> },
+}
+
+impl Calibration {
+    /// Calibration section of a `calibrate` report
+    fn load(path: &str) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("Failed to open calibration: {}", path))?;
+        let report: CalibrationReport = serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("Invalid calibration file: {}", path))?;
+        Ok(report.calibration)
+    }
+    
+    fn apply(&self, score: f64) -> f64 {
+        match self {
+            Calibration::Platt { a, b } => sigmoid(-(a * score + b)),
+            Calibration::Isotonic { thresholds, values } => {
+                let i = thresholds.partition_point(|&t| t <= score);
+                if i == 0 {
+                    values[0]
+                } else if i == thresholds.len() {
+                    values[i - 1]
+                } else {
+                    let fraction = (score - thresholds[i - 1]) / (thresholds[i] - thresholds[i - 1]);
+                    values[i - 1] + fraction * (values[i] - values[i - 1])
+                }
+            }
+        }
+    }
+}
+
+fn sigmoid(x: f64) -> f64 {
+    if x >= 0.0 {
+        1.0 / (1.0 + (-x).exp())
+    } else {
+        let e = x.exp();
+        e / (1.0 + e)
+    }
+}
+
+/// Platt scaling with Platt's smoothed targets, fitted by Newton's method
+/// with backtracking (Lin, Lin & Weng 2007)
+fn fit_platt(scores: &[f64], labels: &[bool]) -> Calibration {
+    let positives = labels.iter().filter(|&&label| label).count() as f64;
+    let negatives = labels.len() as f64 - positives;
+    let (hi, lo) = ((positives + 1.0) / (positives + 2.0), 1.0 / (negatives + 2.0));
+    let targets = labels.iter().map(|&label| if label { hi } else { lo }).collect::<Vec<_>>();
+    
+    // Negative log-likelihood, written to avoid overflow in exp
+    let objective = |a: f64, b: f64| -> f64 {
+        scores
+            .iter()
+            .zip(&targets)
+            .map(|(&s, &t)| {
+                let z = a * s + b;
+                if z >= 0.0 {
+                    t * z + (-z).exp().ln_1p()
+                } else {
+                    (t - 1.0) * z + z.exp().ln_1p()
+                }
+            })
+            .sum()
+    };
+    
+    let (mut a, mut b) = (0.0, ((negatives + 1.0) / (positives + 1.0)).ln());
+    let mut value = objective(a, b);
+    for _ in 0..100 {
+        // Gradient and Hessian; the ridge keeps the Hessian invertible
+        let (mut h11, mut h22, mut h21, mut g1, mut g2) = (1e-12, 1e-12, 0.0, 0.0, 0.0);
+        for (&s, &t) in scores.iter().zip(&targets) {
+            let p = sigmoid(-(a * s + b));
+            let d2 = p * (1.0 - p);
+            h11 += s * s * d2;
+            h22 += d2;
+            h21 += s * d2;
+            g1 += s * (t - p);
+            g2 += t - p;
+        }
+        if g1.abs() < 1e-5 && g2.abs() < 1e-5 {
+            break;
+        }
+        let det = h11 * h22 - h21 * h21;
+        let da = -(h22 * g1 - h21 * g2) / det;
+        let db = -(h11 * g2 - h21 * g1) / det;
+        let slope = g1 * da + g2 * db;
+        
+        let mut step = 1.0;
+        while step >= 1e-10 {
+            let candidate = objective(a + step * da, b + step * db);
+            if candidate < value + 1e-4 * step * slope {
+                a += step * da;
+                b += step * db;
+                value = candidate;
+                break;
+            }
+            step /= 2.0;
+        }
+        if step < 1e-10 {
+            warn!("Platt scaling line search did not converge");
+            break;
+        }
+    }
+    Calibration::Platt { a, b }
+}
+
+/// Isotonic regression of the labels on the scores by pool adjacent
+/// violators; tied scores always share a block
+fn fit_isotonic(scores: &[f64], labels: &[bool]) -> Calibration {
+    let mut points = scores.iter().copied().zip(labels.iter().copied()).collect::<Vec<_>>();
+    points.sort_by(|x, y| x.0.total_cmp(&y.0));
+    
+    // (lowest score, highest score, positives, count)
+    let mut blocks: Vec<(f64, f64, f64, f64)> = Vec::new();
+    for (score, label) in points {
+        blocks.push((score, score, label as u8 as f64, 1.0));
+        while let [.., previous, last] = blocks[..] {
+            if previous.1 < last.0 && previous.2 / previous.3 <= last.2 / last.3 {
+                break;
+            }
+            blocks.pop();
+            *blocks.last_mut().unwrap() = (previous.0, last.1, previous.2 + last.2, previous.3 + last.3);
+        }
+    }
+    
+    let mut thresholds = Vec::with_capacity(2 * blocks.len());
+    let mut values = Vec::with_capacity(2 * blocks.len());
+    for (low, high, positives, count) in blocks {
+        thresholds.push(low);
+        values.push(positives / count);
+        if high > low {
+            thresholds.push(high);
+            values.push(positives / count);
+        }
+    }
+    Calibration::Isotonic { thresholds, values }
+}
+
+/// One equal-width probability bin of a reliability curve
+#[derive(Debug, Serialize, Deserialize)]
+struct ReliabilityBin {
+    lower: f64,
+    upper: f64,
+    count: usize,
+    mean_predicted: f64,
+    observed_rate: f64,
+}
+
+/// How well a set of probabilities matches the truth labels
+#[derive(Debug, Serialize, Deserialize)]
+struct CalibrationMetrics {
+    brier_score: f64,
+    /// Count-weighted mean gap between predicted and observed rate per bin
+    expected_calibration_error: f64,
+    reliability: Vec<ReliabilityBin>,
+}
+
+impl CalibrationMetrics {
+    fn new(probabilities: &[f64], labels: &[bool], bins: usize) -> Self {
+        let total = probabilities.len() as f64;
+        let brier_score = probabilities
+            .iter()
+            .zip(labels)
+            .map(|(&p, &label)| (p - label as u8 as f64).powi(2))
+            .sum::<f64>()
+            / total;
+        
+        // (count, sum of predictions, positives) per bin
+        let mut sums = vec![(0usize, 0.0, 0usize); bins];
+        for (&p, &label) in probabilities.iter().zip(labels) {
+            let bin = ((p.clamp(0.0, 1.0) * bins as f64) as usize).min(bins - 1);
+            sums[bin].0 += 1;
+            sums[bin].1 += p;
+            sums[bin].2 += label as usize;
+        }
+        let reliability = sums
+            .into_iter()
+            .enumerate()
+            .map(|(i, (count, predicted, positives))| ReliabilityBin {
+                lower: i as f64 / bins as f64,
+                upper: (i + 1) as f64 / bins as f64,
+                count,
+                mean_predicted: if count == 0 { 0.0 } else { predicted / count as f64 },
+                observed_rate: if count == 0 { 0.0 } else { positives as f64 / count as f64 },
+            })
+            .collect::<Vec<_>>();
+        let expected_calibration_error = reliability
+            .iter()
+            .map(|bin| bin.count as f64 / total * (bin.mean_predicted - bin.observed_rate).abs())
+            .sum();
+        
+        Self { brier_score, expected_calibration_error, reliability }
+    }
+}
+
+/// Output of `calibrate`: the fitted mapping, read back by `--calibration`,
+/// and its fit on the labeled variants
+#[derive(Debug, Serialize, Deserialize)]
+struct CalibrationReport {
+    calibration: Calibration,
+    labeled_variants: usize,
+    true_variants: usize,
+    raw: CalibrationMetrics,
+    calibrated: CalibrationMetrics,
+}
+
+/// Variant allele identity for truth matching: chromosome plus the
+/// trimmed (0-based) position and alleles
+type AlleleKey = (String, i64, String, String);
+
+fn allele_key(chrom: &str, pos: i64, ref_allele: &str, alt_allele: &str) -> AlleleKey {
+    let (pos, ref_allele, alt_allele) = trim_alleles(pos, ref_allele, alt_allele);
+    (chrom.to_string(), pos, ref_allele, alt_allele)
+}
+
+/// Whether an allele lies in `regions`; everything does when there are none
+fn in_regions(regions: &[Region], (chrom, pos, _, _): &AlleleKey) -> bool {
+    // Region bounds are 1-based, allele positions 0-based
+    let pos = (*pos + 1) as u64;
+    regions.is_empty()
+        || regions.iter().any(|r| &r.chrom == chrom && r.start <= pos && r.end.is_none_or(|end| pos <= end))
+}
+
+/// Every sequence-resolved ALT allele in the truth VCF
+fn read_truth_alleles(truth_path: &str) -> Result<std::collections::HashSet<AlleleKey>> {
+    let mut reader = bcf::Reader::from_path(truth_path)
+        .with_context(|| format!("Failed to open truth VCF: {}", truth_path))?;
+    let mut alleles = std::collections::HashSet::new();
+    let mut record = reader.empty_record();
+    while let Some(result) = reader.read(&mut record) {
+        result.with_context(|| format!("Failed to read truth VCF: {}", truth_path))?;
+        let site = SiteRecord::from_record(&record, false, false)?;
+        for alt in &site.alleles[1..] {
+            if alt != "*" && !alt.starts_with('<') {
+                alleles.insert(allele_key(&site.chrom, site.pos, &site.alleles[0], alt));
+            }
+        }
+    }
+    Ok(alleles)
+}
+
+/// (allele, raw score) pairs from a `score` output file, by extension
+fn read_scored_alleles(scores_path: &str) -> Result<Vec<(AlleleKey, f64)>> {
+    let lower = scores_path.to_ascii_lowercase();
+    
+    if lower.ends_with(".json") {
+        let file = File::open(scores_path).with_context(|| format!("Failed to open scores: {}", scores_path))?;
+        let variants: Vec<VariantInfo> = serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("Invalid scores JSON: {}", scores_path))?;
+        return Ok(variants
+            .iter()
+            .map(|v| (allele_key(&v.chrom, v.pos, &v.ref_allele, &v.alt_allele), v.score))
+            .collect());
+    }
+    
+    if lower.ends_with(".vcf") || lower.ends_with(".vcf.gz") || lower.ends_with(".bcf") {
+        let mut reader = bcf::Reader::from_path(scores_path)
+            .with_context(|| format!("Failed to open scored VCF: {}", scores_path))?;
+        let mut scored = Vec::new();
+        let mut record = reader.empty_record();
+        while let Some(result) = reader.read(&mut record) {
+            result.with_context(|| format!("Failed to read scored VCF: {}", scores_path))?;
+            let Some(scores) = record.info(b"SCORE").float()? else {
+                continue;
+            };
+            let scores = scores.to_vec();
+            let site = SiteRecord::from_record(&record, false, false)?;
+            for (alt, score) in site.alleles[1..].iter().zip(scores) {
+                if !score.is_missing() {
+                    scored.push((allele_key(&site.chrom, site.pos, &site.alleles[0], alt), score as f64));
+                }
+            }
+        }
+        return Ok(scored);
+    }
+    
+    let file = File::open(scores_path).with_context(|| format!("Failed to open scores: {}", scores_path))?;
+    let df = if lower.ends_with(".parquet") {
+        ParquetReader::new(file).finish()?
+    } else if lower.ends_with(".arrow") || lower.ends_with(".ipc") {
+        IpcReader::new(file).finish()?
+    } else if lower.ends_with(".csv") || lower.ends_with(".tsv") {
+        CsvReader::new(file)
+            .has_header(true)
+            .with_delimiter(if lower.ends_with(".tsv") { b'\t' } else { b',' })
+            .finish()?
+    } else {
+        return Err(anyhow!(ScoringError::InvalidInputError(format!(
+            "Cannot tell the format of {} from its extension",
+            scores_path
+        ))));
+    };
+    
+    let chroms = df.column("chrom")?.cast(&DataType::Utf8)?;
+    let positions = df.column("pos")?.cast(&DataType::Int64)?;
+    let ref_alleles = df.column("ref")?.cast(&DataType::Utf8)?;
+    let alt_alleles = df.column("alt")?.cast(&DataType::Utf8)?;
+    let scores = df.column("score")?.cast(&DataType::Float64)?;
+    let mut scored = Vec::with_capacity(df.height());
+    for ((((chrom, pos), ref_allele), alt_allele), score) in chroms
+        .utf8()?
+        .into_iter()
+        .zip(positions.i64()?)
+        .zip(ref_alleles.utf8()?)
+        .zip(alt_alleles.utf8()?)
+        .zip(scores.f64()?)
+    {
+        if let (Some(chrom), Some(pos), Some(ref_allele), Some(alt_allele), Some(score)) =
+            (chrom, pos, ref_allele, alt_allele, score)
+        {
+            scored.push((allele_key(chrom, pos, ref_allele, alt_allele), score));
+        }
+    }
+    Ok(scored)
+}
+
+/// Fit a calibration of raw scores against a truth set and write it, with
+/// reliability statistics before and after, as JSON
+fn run_calibrate(
+    scores_path: &str,
+    truth_path: &str,
+    method: CalibrationMethod,
+    regions: &[Region],
+    bins: usize,
+    out_path: &str,
+    profile: &mut RunProfile,
+) -> Result<()> {
+    if bins == 0 {
+        return Err(anyhow!(ScoringError::InvalidInputError("--bins must be at least 1".to_string())));
+    }
+    
+    let stage_start = Instant::now();
+    let truth = read_truth_alleles(truth_path)?;
+    let mut scored = read_scored_alleles(scores_path)?;
+    scored.retain(|(key, _)| in_regions(regions, key));
+    let scores = scored.iter().map(|(_, score)| *score).collect::<Vec<_>>();
+    let labels = scored.iter().map(|(key, _)| truth.contains(key)).collect::<Vec<_>>();
+    let true_variants = labels.iter().filter(|&&label| label).count();
+    info!(
+        "Labeled {} scored variants against {} truth alleles: {} true, {} false",
+        labels.len(),
+        truth.len(),
+        true_variants,
+        labels.len() - true_variants
+    );
+    profile.record("label", scores_path, stage_start, labels.len() as u64, file_len(scores_path) + file_len(truth_path), 0);
+    
+    if true_variants == 0 || true_variants == labels.len() {
+        return Err(anyhow!(ScoringError::InvalidInputError(
+            "Calibration needs both true and false variants among the scored ones".to_string()
+        )));
+    }
+    
+    let stage_start = Instant::now();
+    let calibration = match method {
+        CalibrationMethod::Platt => fit_platt(&scores, &labels),
+        CalibrationMethod::Isotonic => fit_isotonic(&scores, &labels),
+    };
+    let probabilities = scores.iter().map(|&score| calibration.apply(score)).collect::<Vec<_>>();
+    let report = CalibrationReport {
+        calibration,
+        labeled_variants: labels.len(),
+        true_variants,
+        raw: CalibrationMetrics::new(&scores, &labels, bins),
+        calibrated: CalibrationMetrics::new(&probabilities, &labels, bins),
+    };
+    profile.record("calibrate", scores_path, stage_start, labels.len() as u64, 0, 0);
+    
+    let file = File::create(out_path).with_context(|| format!("Failed to create calibration file: {}", out_path))?;
+    serde_json::to_writer_pretty(BufWriter::new(file), &report)
+        .with_context(|| format!("Failed to write calibration file: {}", out_path))?;
+    info!("Calibration written to {}", out_path);
+    
+    print_calibration(&report);
+    Ok(())
+}
+
+/// Print the reliability curve of a calibration
+fn print_calibration(report: &CalibrationReport) {
+    println!("\n===== Score Calibration =====");
+    println!("Labeled variants: {} ({} true)", report.labeled_variants, report.true_variants);
+    println!(
+        "Brier score: {:.4} raw, {:.4} calibrated",
+        report.raw.brier_score, report.calibrated.brier_score
+    );
+    println!(
+        "Expected calibration error: {:.4} raw, {:.4} calibrated",
+        report.raw.expected_calibration_error, report.calibrated.expected_calibration_error
+    );
+    println!("Bin          Count  Predicted  Observed");
+    for bin in &report.calibrated.reliability {
+        println!(
+            "[{:.2}, {:.2})  {:>6}  {:>9.3}  {:>8.3}",
+            bin.lower, bin.upper, bin.count, bin.mean_predicted, bin.observed_rate
+        );
+    }
+    println!("=============================\n");
+}
+
+/// Variant class benchmarked separately, from the trimmed alleles
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum VariantClass {
+    Snv,
+    Indel,
+    /// MNVs and complex substitutions
+    Complex,
+}
+
+impl VariantClass {
+    fn of((_, _, ref_allele, alt_allele): &AlleleKey) -> Self {
+        match (ref_allele.len(), alt_allele.len()) {
+            (1, 1) => VariantClass::Snv,
+            (1, _) | (_, 1) => VariantClass::Indel,
+            _ => VariantClass::Complex,
+        }
+    }
+}
+
+/// Operating point at one score threshold: alleles scoring at least the
+/// threshold are called
+#[derive(Debug, Serialize, Deserialize)]
+struct CurvePoint {
+    threshold: f64,
+    true_positives: usize,
+    false_positives: usize,
+    /// Of the truth alleles, including those the scorer never saw
+    recall: f64,
+    precision: f64,
+    /// Of the scored true and false alleles, for the ROC curve
+    true_positive_rate: f64,
+    false_positive_rate: f64,
+}
+
+/// Benchmark of one variant class (or all, with no class)
+#[derive(Debug, Serialize, Deserialize)]
+struct ClassBenchmark {
+    class: Option<VariantClass>,
+    truth_alleles: usize,
+    scored_alleles: usize,
+    scored_true: usize,
+    /// Area under the ROC curve; undefined without both true and false alleles
+    auroc: Option<f64>,
+    /// Average precision over the recall steps
+    auprc: Option<f64>,
+    best_f1: Option<f64>,
+    best_f1_threshold: Option<f64>,
+    curve: Vec<CurvePoint>,
+}
+
+impl ClassBenchmark {
+    fn new(class: Option<VariantClass>, scored: &[(f64, bool)], truth_alleles: usize, curve_points: usize) -> Self {
+        let mut sorted = scored.to_vec();
+        sorted.sort_by(|x, y| y.0.total_cmp(&x.0));
+        let positives = sorted.iter().filter(|(_, label)| *label).count();
+        let negatives = sorted.len() - positives;
+        
+        // One point per distinct score, from the highest threshold down
+        let mut points = Vec::new();
+        let (mut tp, mut fp) = (0, 0);
+        for (i, &(score, label)) in sorted.iter().enumerate() {
+            if label {
+                tp += 1;
+            } else {
+                fp += 1;
+            }
+            if sorted.get(i + 1).is_none_or(|next| next.0 != score) {
+                let ratio = |n: usize, d: usize| if d == 0 { 0.0 } else { n as f64 / d as f64 };
+                points.push(CurvePoint {
+                    threshold: score,
+                    true_positives: tp,
+                    false_positives: fp,
+                    recall: ratio(tp, truth_alleles),
+                    precision: ratio(tp, tp + fp),
+                    true_positive_rate: ratio(tp, positives),
+                    false_positive_rate: ratio(fp, negatives),
+                });
+            }
+        }
+        
+        let auroc = (positives > 0 && negatives > 0).then(|| {
+            let mut previous = (0.0, 0.0);
+            points.iter().fold(0.0, |area, point| {
+                let (fpr, tpr) = (point.false_positive_rate, point.true_positive_rate);
+                let area = area + (fpr - previous.0) * (tpr + previous.1) / 2.0;
+                previous = (fpr, tpr);
+                area
+            })
+        });
+        let auprc = (truth_alleles > 0 && !points.is_empty()).then(|| {
+            let mut previous_recall = 0.0;
+            points.iter().fold(0.0, |area, point| {
+                let area = area + (point.recall - previous_recall) * point.precision;
+                previous_recall = point.recall;
+                area
+            })
+        });
+        let best = points
+            .iter()
+            .filter(|point| point.precision + point.recall > 0.0)
+            .map(|point| (2.0 * point.precision * point.recall / (point.precision + point.recall), point.threshold))
+            .max_by(|x, y| x.0.total_cmp(&y.0));
+        
+        // Evenly spaced points, always keeping the lowest threshold
+        let curve = if points.len() > curve_points && curve_points > 1 {
+            let step = (points.len() - 1) as f64 / (curve_points - 1) as f64;
+            let keep = (0..curve_points).map(|i| (i as f64 * step).round() as usize).collect::<std::collections::BTreeSet<_>>();
+            points.into_iter().enumerate().filter(|(i, _)| keep.contains(i)).map(|(_, point)| point).collect()
+        } else {
+            points
+        };
+        
+        Self {
+            class,
+            truth_alleles,
+            scored_alleles: sorted.len(),
+            scored_true: positives,
+            auroc,
+            auprc,
+            best_f1: best.map(|(f1, _)| f1),
+            best_f1_threshold: best.map(|(_, threshold)| threshold),
+            curve,
+        }
+    }
+}
+
+/// Benchmark of one scored file
+#[derive(Debug, Serialize, Deserialize)]
+struct BenchmarkRun {
+    scores: String,
+    classes: Vec<ClassBenchmark>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BenchmarkReport {
+    truth: String,
+    regions: Vec<String>,
+    runs: Vec<BenchmarkRun>,
+}
+
+/// Benchmark scored outputs against a truth VCF and write a JSON report
+fn run_benchmark(
+    scores_paths: &[String],
+    truth_path: &str,
+    regions: &[Region],
+    curve_points: usize,
+    out_path: &str,
+    profile: &mut RunProfile,
+) -> Result<()> {
+    let mut truth = read_truth_alleles(truth_path)?;
+    truth.retain(|key| in_regions(regions, key));
+    let classes = [None, Some(VariantClass::Snv), Some(VariantClass::Indel), Some(VariantClass::Complex)];
+    let truth_counts = classes.map(|class| truth.iter().filter(|key| class.is_none_or(|c| VariantClass::of(key) == c)).count());
+    info!("Benchmarking against {} truth alleles from {}", truth.len(), truth_path);
+    
+    let mut runs = Vec::with_capacity(scores_paths.len());
+    for scores_path in scores_paths {
+        let stage_start = Instant::now();
+        let mut scored = read_scored_alleles(scores_path)?;
+        scored.retain(|(key, _)| in_regions(regions, key));
+        let benchmarks = classes
+            .iter()
+            .zip(truth_counts)
+            .map(|(&class, truth_alleles)| {
+                let labeled = scored
+                    .iter()
+                    .filter(|(key, _)| class.is_none_or(|c| VariantClass::of(key) == c))
+                    .map(|(key, score)| (*score, truth.contains(key)))
+                    .collect::<Vec<_>>();
+                ClassBenchmark::new(class, &labeled, truth_alleles, curve_points)
+            })
+            .collect();
+        profile.record("benchmark", scores_path, stage_start, scored.len() as u64, file_len(scores_path), 0);
+        runs.push(BenchmarkRun { scores: scores_path.clone(), classes: benchmarks });
+    }
+    
+    let report = BenchmarkReport {
+        truth: truth_path.to_string(),
+        regions: regions
+            .iter()
+            .map(|r| match r.end {
+                Some(end) => format!("{}:{}-{}", r.chrom, r.start, end),
+                None => format!("{}:{}", r.chrom, r.start),
+            })
+            .collect(),
+        runs,
+    };
+    let file = File::create(out_path).with_context(|| format!("Failed to create benchmark report: {}", out_path))?;
+    serde_json::to_writer_pretty(BufWriter::new(file), &report)
+        .with_context(|| format!("Failed to write benchmark report: {}", out_path))?;
+    info!("Benchmark report written to {}", out_path);
+    
+    print_benchmark(&report);
+    Ok(())
+}
+
+/// Print one summary line per scored file and variant class
+fn print_benchmark(report: &BenchmarkReport) {
+    let metric = |value: Option<f64>| value.map_or_else(|| "-".to_string(), |v| format!("{:.4}", v));
+    println!("\n===== Truth-Set Benchmark =====");
+    println!("{:<30} {:<8} {:>8} {:>8} {:>8} {:>8} {:>8}", "Scores", "Class", "Truth", "Scored", "AUROC", "AUPRC", "Best F1");
+    for run in &report.runs {
+        for class in &run.classes {
+            let name = match class.class {
+                None => "all",
+                Some(VariantClass::Snv) => "snv",
+                Some(VariantClass::Indel) => "indel",
+                Some(VariantClass::Complex) => "complex",
+            };
+            println!(
+                "{:<30} {:<8} {:>8} {:>8} {:>8} {:>8} {:>8}",
+                run.scores,
+                name,
+                class.truth_alleles,
+                class.scored_alleles,
+                metric(class.auroc),
+                metric(class.auprc),
+                metric(class.best_f1)
+            );
+        }
+    }
+    println!("===============================\n");
+}
+
+/// Columns the scorer and the variant annotator are joined on
+const JOIN_KEYS: [&str; 4] = ["chrom", "pos", "ref", "alt"];
+
+/// Lazily scan a Parquet, Arrow IPC or CSV/TSV table, by extension
+fn scan_table(path: &str) -> Result<LazyFrame> {
+    let lower = path.to_ascii_lowercase();
+    let frame = if lower.ends_with(".parquet") {
+        LazyFrame::scan_parquet(path, ScanArgsParquet::default())?
+    } else if lower.ends_with(".arrow") || lower.ends_with(".ipc") {
+        LazyFrame::scan_ipc(path, ScanArgsIpc::default())?
+    } else if lower.ends_with(".csv") || lower.ends_with(".tsv") {
+        LazyCsvReader::new(path)
+            .has_header(true)
+            .with_delimiter(if lower.ends_with(".tsv") { b'\t' } else { b',' })
+            .finish()?
+    } else {
+        return Err(anyhow!(ScoringError::InvalidInputError(format!(
+            "Cannot tell the format of {} from its extension",
+            path
+        ))));
+    };
+    Ok(frame)
+}
+
+/// Join a scorer output with variant-annotator output on chromosome,
+/// position and alleles and write the combined table, highest score first
+fn run_annotate_scores(
+    scores_path: &str,
+    annotations_path: &str,
+    keep_unannotated: bool,
+    out_path: &str,
+    profile: &mut RunProfile,
+) -> Result<()> {
+    let stage_start = Instant::now();
+    // CSV inference reads numeric chromosome names as integers
+    let scores = scan_table(scores_path)?.with_columns([
+        col("chrom").cast(DataType::Utf8),
+        col("pos").cast(DataType::Int64),
+    ]);
+    // The annotator writes 1-based positions and names its allele columns
+    // ref_allele/alt_allele
+    let annotations = scan_table(annotations_path)?
+        .rename(["ref_allele", "alt_allele"], ["ref", "alt"])
+        .with_columns([
+            col("chrom").cast(DataType::Utf8),
+            (col("pos").cast(DataType::Int64) - lit(1i64)).alias("pos"),
+            lit(true).alias("annotated"),
+        ]);
+    
+    let keys = JOIN_KEYS.map(col);
+    let mut combined = scores
+        .join_builder()
+        .with(annotations)
+        .left_on(keys.clone())
+        .right_on(keys)
+        .how(if keep_unannotated { JoinType::Left } else { JoinType::Inner })
+        .finish()
+        .with_column(col("annotated").fill_null(lit(false)))
+        .sort(
+            "score",
+            SortOptions {
+                descending: true,
+                nulls_last: true,
+                ..Default::default()
+            },
+        )
+        .collect()
+        .with_context(|| format!("Failed to join {} with {}", scores_path, annotations_path))?;
+    
+    let annotated = combined
+        .column("annotated")?
+        .bool()?
+        .into_iter()
+        .filter(|annotated| *annotated == Some(true))
+        .count();
+    info!("{} scored variants, {} with an annotation", combined.height(), annotated);
+    
+    let lower = out_path.to_ascii_lowercase();
+    let dir = Path::new(out_path).parent().unwrap_or_else(|| Path::new("."));
+    let temp_file = NamedTempFile::new_in(dir)?;
+    let file = File::create(temp_file.path())?;
+    if lower.ends_with(".parquet") {
+        ParquetWriter::new(file)
+            .with_compression(ParquetCompression::Snappy)
+            .finish(&mut combined)?;
+    } else if lower.ends_with(".arrow") || lower.ends_with(".ipc") {
+        IpcWriter::new(file).finish(&mut combined)?;
+    } else if lower.ends_with(".csv") || lower.ends_with(".tsv") {
+        CsvWriter::new(file)
+            .has_header(true)
+            .with_delimiter(if lower.ends_with(".tsv") { b'\t' } else { b',' })
+            .finish(&mut combined)?;
+    } else {
+        return Err(anyhow!(ScoringError::InvalidInputError(format!(
+            "Cannot tell the format of {} from its extension",
+            out_path
+        ))));
+    }
+    temp_file
+        .persist(out_path)
+        .with_context(|| format!("Failed to write combined table: {}", out_path))?;
+    profile.record(
+        "annotate_scores",
+        scores_path,
+        stage_start,
+        combined.height() as u64,
+        file_len(scores_path) + file_len(annotations_path),
+        file_len(out_path),
+    );
+    info!("Combined table written to {}", out_path);
+    Ok(())
+}
@@ -28,6 +28,8 @@ pub enum PseudoAlignError {
     EmptyIndex,
     #[error("No reads found in input file")]
     NoReads,
+    #[error("Invalid sample sheet line {line}: {reason}")]
+    InvalidSampleSheet { line: usize, reason: String },
 }
 
 #[derive(Parser, Debug)]
@@ -41,10 +43,14 @@ struct Args {
     #[arg(short, long, default_value = "data/reads.fastq")]
     reads: String,
 
-    /// Output quantification file
+    /// Output quantification file (with --samples, the prefix for the merged matrices)
     #[arg(short, long, default_value = "results/quantification.tsv")]
     output: String,
 
+    /// Sample sheet (sample_id<TAB>reads_path) for quantifying many FASTQ files against one index
+    #[arg(long, conflicts_with = "reads")]
+    samples: Option<String>,
+
     /// K-mer length
     #[arg(short, long, default_value_t = 31)]
     kmer_length: usize,
@@ -60,6 +66,178 @@ struct Args {
     /// Verbose logging
     #[arg(short, long)]
     verbose: bool,
+
+    /// Write a JSON runtime breakdown (per-stage wall time, bytes, records/sec) on exit
+    #[arg(long)]
+    profile_json: Option<String>,
+}
+
+/// Wall time and I/O volume for one pipeline stage
+#[derive(Serialize, Debug)]
+pub struct StageProfile {
+    pub stage: String,
+    pub wall_seconds: f64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub records: u64,
+    pub records_per_sec: f64,
+}
+
+/// Runtime breakdown emitted by `--profile-json`
+#[derive(Serialize, Debug, Default)]
+pub struct RunProfile {
+    pub tool: String,
+    pub threads: usize,
+    pub total_wall_seconds: f64,
+    pub stages: Vec<StageProfile>,
+}
+
+impl RunProfile {
+    pub fn new(threads: usize) -> Self {
+        Self {
+            tool: env!("CARGO_PKG_NAME").to_string(),
+            threads,
+            ..Default::default()
+        }
+    }
+
+    pub fn record(&mut self, stage: &str, started: Instant, records: u64, bytes_read: u64, bytes_written: u64) {
+        let wall_seconds = started.elapsed().as_secs_f64();
+        self.stages.push(StageProfile {
+            stage: stage.to_string(),
+            wall_seconds,
+            bytes_read,
+            bytes_written,
+            records,
+            records_per_sec: if wall_seconds > 0.0 { records as f64 / wall_seconds } else { 0.0 },
+        });
+    }
+
+    pub fn write(mut self, path: &str, run_start: Instant) -> Result<()> {
+        self.total_wall_seconds = run_start.elapsed().as_secs_f64();
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create profile file: {}", path))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), &self)
+            .with_context(|| format!("Failed to write profile JSON: {}", path))?;
+        info!("Runtime profile written to: {}", path);
+        Ok(())
+    }
+}
+
+fn file_len(path: &str) -> u64 {
+    std::fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
+
+/// One row of the `--samples` sheet
+#[derive(Debug, Clone)]
+pub struct SampleEntry {
+    pub sample_id: String,
+    pub reads_path: String,
+}
+
+/// Read a tab-separated sample sheet of `sample_id` and reads path.
+/// Blank lines, `#` comments and a leading `sample...` header are skipped.
+pub fn read_sample_sheet(path: &str) -> Result<Vec<SampleEntry>> {
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open sample sheet: {}", path))?;
+    let mut samples: Vec<SampleEntry> = Vec::new();
+
+    for (line_num, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = trimmed.split('\t').map(str::trim).collect();
+        if samples.is_empty() && matches!(fields[0].to_ascii_lowercase().as_str(), "sample" | "sample_id") {
+            continue;
+        }
+        if fields.len() < 2 || fields[1].is_empty() {
+            return Err(PseudoAlignError::InvalidSampleSheet {
+                line: line_num + 1,
+                reason: "expected sample_id and reads path separated by a tab".to_string(),
+            }
+            .into());
+        }
+        if samples.iter().any(|s| s.sample_id == fields[0]) {
+            return Err(PseudoAlignError::InvalidSampleSheet {
+                line: line_num + 1,
+                reason: format!("duplicate sample id '{}'", fields[0]),
+            }
+            .into());
+        }
+
+        samples.push(SampleEntry {
+            sample_id: fields[0].to_string(),
+            reads_path: fields[1].to_string(),
+        });
+    }
+
+    if samples.is_empty() {
+        anyhow::bail!("Sample sheet {} lists no samples", path);
+    }
+    Ok(samples)
+}
+
+/// Write transcript x sample count and TPM matrices in the wide
+/// `gene_id<TAB>sample...` layout used by experiment_9_1.
+pub fn write_merged_matrices(
+    samples: &[(String, QuantificationResults)],
+    output_prefix: &str,
+) -> Result<(String, String)> {
+    if let Some(parent) = Path::new(output_prefix).parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let mut transcript_ids: Vec<&str> = samples
+        .iter()
+        .flat_map(|(_, results)| results.transcripts.iter().map(|t| t.transcript_id.as_str()))
+        .collect();
+    transcript_ids.sort_unstable();
+    transcript_ids.dedup();
+
+    let lookups: Vec<HashMap<&str, &TranscriptQuantification>> = samples
+        .iter()
+        .map(|(_, results)| {
+            results
+                .transcripts
+                .iter()
+                .map(|t| (t.transcript_id.as_str(), t))
+                .collect()
+        })
+        .collect();
+
+    let counts_path = format!("{}_counts.tsv", output_prefix);
+    let tpm_path = format!("{}_tpm.tsv", output_prefix);
+
+    for (path, is_tpm) in [(&counts_path, false), (&tpm_path, true)] {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create matrix file: {}", path))?;
+        let mut writer = BufWriter::new(file);
+
+        write!(writer, "gene_id")?;
+        for (sample_id, _) in samples {
+            write!(writer, "\t{}", sample_id)?;
+        }
+        writeln!(writer)?;
+
+        for transcript_id in &transcript_ids {
+            write!(writer, "{}", transcript_id)?;
+            for lookup in &lookups {
+                match lookup.get(transcript_id) {
+                    Some(quant) if is_tpm => write!(writer, "\t{:.6}", quant.tpm)?,
+                    Some(quant) => write!(writer, "\t{:.2}", quant.count)?,
+                    None => write!(writer, "\t0")?,
+                }
+            }
+            writeln!(writer)?;
+        }
+        writer.flush()?;
+    }
+
+    info!("Merged matrices written to: {} and {}", counts_path, tpm_path);
+    Ok((counts_path, tpm_path))
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -143,13 +321,8 @@ impl PseudoAligner {
         })
     }
 
-    pub fn quantify_reads(&self, reads_path: &str, num_threads: usize) -> Result<QuantificationResults> {
+    pub fn quantify_reads(&self, reads_path: &str) -> Result<QuantificationResults> {
         let start = Instant::now();
-        rayon::ThreadPoolBuilder::new()
-            .num_threads(num_threads)
-            .build_global()
-            .context("Failed to initialize thread pool")?;
-
         let transcript_counts: Arc<DashMap<String, AtomicUsize>> = Arc::new(DashMap::new());
         let total_reads = Arc::new(AtomicUsize::new(0));
 
@@ -320,14 +493,19 @@ impl PseudoAligner {
 }
 
 fn main() -> Result<()> {
+    let run_start = Instant::now();
     let args = Args::parse();
+    let mut profile = RunProfile::new(args.threads);
 
     let log_level = if args.verbose { "debug" } else { "info" };
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(log_level)).init();
 
     info!("Starting pseudo-alignment with parameters:");
     info!("  Index file: {}", args.index);
-    info!("  Reads file: {}", args.reads);
+    match &args.samples {
+        Some(samples) => info!("  Sample sheet: {}", samples),
+        None => info!("  Reads file: {}", args.reads),
+    }
     info!("  Output file: {}", args.output);
     info!("  K-mer length: {}", args.kmer_length);
     info!("  Threads: {}", args.threads);
@@ -337,21 +515,82 @@ fn main() -> Result<()> {
         error!("Index file does not exist: {}", args.index);
         std::process::exit(1);
     }
-    if !Path::new(&args.reads).exists() {
-        error!("Reads file does not exist: {}", args.reads);
-        std::process::exit(1);
+    let samples = match &args.samples {
+        Some(sheet) => Some(read_sample_sheet(sheet)?),
+        None => None,
+    };
+    let reads_files: Vec<&str> = match &samples {
+        Some(samples) => samples.iter().map(|s| s.reads_path.as_str()).collect(),
+        None => vec![args.reads.as_str()],
+    };
+    for reads in &reads_files {
+        if !Path::new(reads).exists() {
+            error!("Reads file does not exist: {}", reads);
+            std::process::exit(1);
+        }
     }
 
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(args.threads)
+        .build_global()
+        .context("Failed to initialize thread pool")?;
+
+    let stage_start = Instant::now();
     let aligner = PseudoAligner::new(&args.index, args.kmer_length, args.min_read_length)
         .context("Failed to initialize pseudo-aligner")?;
+    profile.record("load_index", stage_start, aligner.kmer_index.len() as u64, file_len(&args.index), 0);
+
+    if let Some(samples) = &samples {
+        // The index is loaded once and reused for every sample in the sheet
+        let mut quantified = Vec::with_capacity(samples.len());
+        for (idx, sample) in samples.iter().enumerate() {
+            info!("Quantifying sample {}/{}: {}", idx + 1, samples.len(), sample.sample_id);
+            let stage_start = Instant::now();
+            let results = aligner
+                .quantify_reads(&sample.reads_path)
+                .with_context(|| format!("Failed to quantify sample {}", sample.sample_id))?;
+            profile.record(
+                &format!("quantify:{}", sample.sample_id),
+                stage_start,
+                results.total_reads as u64,
+                file_len(&sample.reads_path),
+                0,
+            );
+            println!(
+                "{}: {} reads, {} aligned",
+                sample.sample_id, results.total_reads, results.aligned_reads
+            );
+            quantified.push((sample.sample_id.clone(), results));
+        }
 
-    let results = aligner
-        .quantify_reads(&args.reads, args.threads)
-        .context("Failed to quantify reads")?;
+        let stage_start = Instant::now();
+        let output_prefix = args.output.strip_suffix(".tsv").unwrap_or(&args.output);
+        let (counts_path, tpm_path) = write_merged_matrices(&quantified, output_prefix)
+            .context("Failed to write merged matrices")?;
+        profile.record(
+            "write",
+            stage_start,
+            quantified.len() as u64,
+            0,
+            file_len(&counts_path) + file_len(&tpm_path),
+        );
+    } else {
+        let stage_start = Instant::now();
+        let results = aligner
+            .quantify_reads(&args.reads)
+            .context("Failed to quantify reads")?;
+        profile.record("quantify", stage_start, results.total_reads as u64, file_len(&args.reads), 0);
+
+        let stage_start = Instant::now();
+        aligner
+            .write_results(&results, &args.output)
+            .context("Failed to write results")?;
+        profile.record("write", stage_start, results.transcripts.len() as u64, 0, file_len(&args.output));
+    }
 
-    aligner
-        .write_results(&results, &args.output)
-        .context("Failed to write results")?;
+    if let Some(path) = &args.profile_json {
+        profile.write(path, run_start)?;
+    }
 
     info!("Pseudo-alignment completed successfully!");
     Ok(())
@@ -0,0 +1,45 @@
+#!/bin/bash
+# Thread-scaling benchmark for the normalizer on a 100k-gene matrix.
+# Usage: ./benchmark.sh [genes] [samples] [thread counts...]
+
+set -euo pipefail
+
+GENES=${1:-100000}
+SAMPLES=${2:-24}
+shift $(( $# < 2 ? $# : 2 ))
+THREADS=${*:-"1 2 4 8"}
+
+BIN=./target/release/rnaseq-normalizer
+OUT=bench_results
+INPUT=$OUT/raw_counts_${GENES}.tsv
+
+mkdir -p "$OUT"
+
+if [ ! -x "$BIN" ]; then
+    echo "🔨 Building release binary..."
+    cargo build --release
+fi
+
+if [ ! -f "$INPUT" ]; then
+    echo "📊 Generating ${GENES} genes x ${SAMPLES} samples..."
+    (cd "$OUT" && python3 ../generate_sample_data.py --genes "$GENES" --samples "$SAMPLES" \
+        --output "raw_counts_${GENES}.tsv" > /dev/null)
+fi
+
+printf "%-8s %-8s %14s %14s %14s\n" "mode" "threads" "size_factors_s" "normalize_s" "total_s"
+for mode in dense sparse; do
+    flag=""
+    [ "$mode" = "sparse" ] && flag="--sparse"
+    for t in $THREADS; do
+        profile=$OUT/profile_${mode}_t${t}.json
+        $BIN -i "$INPUT" -o "$OUT/normalized_${mode}.tsv" -s "$OUT/stats_${mode}.txt" \
+            --threads "$t" --profile-json "$profile" $flag
+        python3 - "$profile" "$mode" <<'PYEOF'
+import json, sys
+profile = json.load(open(sys.argv[1]))
+stages = {s["stage"]: s["wall_seconds"] for s in profile["stages"]}
+print(f"{sys.argv[2]:<8} {profile['threads']:<8} {stages.get('size_factors', 0):14.3f} "
+      f"{stages.get('normalize', 0):14.3f} {profile['total_wall_seconds']:14.3f}")
+PYEOF
+    done
+done
@@ -1,13 +1,193 @@
+use std::alloc::{GlobalAlloc, Layout, System};
 use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::fs::File;
 use std::io::{BufRead, BufReader, Write};
 use std::path::Path;
-use clap::{Arg, Command};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+use clap::{Arg, ArgAction, Command};
 use serde::{Serialize, Deserialize};
-use ndarray::{Array2, s};
+use ndarray::{Array2, ArrayView2, ArrayViewMut2, Zip, s};
+use arrow::array::{Array, ArrayRef, Float64Array, StringArray};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use std::sync::Arc;
+use memmap2::MmapMut;
 use log::{info, warn, error};
-use env_logger;
+use rayon::prelude::*;
+use statrs::distribution::{ContinuousCDF, Normal};
+use statrs::function::gamma::ln_gamma;
+
+/// Allocator wrapper that tracks live heap bytes so matrix allocation can
+/// decide between RAM and a memory-mapped scratch file under `--max-memory`.
+struct TrackingAllocator;
+
+static HEAP_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for TrackingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            HEAP_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        HEAP_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            HEAP_BYTES.fetch_add(new_size, Ordering::Relaxed);
+            HEAP_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+        }
+        new_ptr
+    }
+}
+
+#[global_allocator]
+static GLOBAL: TrackingAllocator = TrackingAllocator;
+
+fn heap_bytes() -> usize {
+    HEAP_BYTES.load(Ordering::Relaxed)
+}
+
+/// Parses sizes like "512M", "2G" or plain byte counts.
+fn parse_memory_size(s: &str) -> Result<usize, Box<dyn Error>> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let value: f64 = number.parse()?;
+    let multiplier: f64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1.0,
+        "K" | "KB" => 1024.0,
+        "M" | "MB" => 1024.0 * 1024.0,
+        "G" | "GB" => 1024.0 * 1024.0 * 1024.0,
+        other => return Err(format!("Unknown memory unit '{}'", other).into()),
+    };
+    Ok((value * multiplier) as usize)
+}
+
+/// Gene x sample matrix that lives either on the heap or in a memory-mapped
+/// temporary file when the heap budget would otherwise be exceeded.
+enum CountMatrix {
+    InMemory(Array2<f64>),
+    Mapped {
+        mmap: MmapMut,
+        _file: File,
+        shape: (usize, usize),
+    },
+}
+
+impl CountMatrix {
+    /// Allocates a zeroed matrix, falling back to a disk-backed mapping if the
+    /// allocation would push heap usage past `max_memory`.
+    fn zeros(shape: (usize, usize), max_memory: Option<usize>) -> Result<Self, Box<dyn Error>> {
+        let bytes = shape.0 * shape.1 * std::mem::size_of::<f64>();
+        match max_memory {
+            Some(limit) if bytes > 0 && heap_bytes() + bytes > limit => {
+                warn!(
+                    "Matrix of {} bytes would exceed --max-memory ({} bytes in use); using a memory-mapped scratch file",
+                    bytes,
+                    heap_bytes()
+                );
+                let file = tempfile::tempfile()?;
+                file.set_len(bytes as u64)?;
+                // SAFETY: the file is private to this process and sized to hold the matrix.
+                let mmap = unsafe { MmapMut::map_mut(&file)? };
+                Ok(CountMatrix::Mapped { mmap, _file: file, shape })
+            }
+            _ => Ok(CountMatrix::InMemory(Array2::zeros(shape))),
+        }
+    }
+
+    fn view(&self) -> ArrayView2<'_, f64> {
+        match self {
+            CountMatrix::InMemory(array) => array.view(),
+            CountMatrix::Mapped { mmap, shape, .. } => {
+                // SAFETY: mappings are page aligned and exactly shape.0 * shape.1 f64 values long.
+                let values = unsafe {
+                    std::slice::from_raw_parts(mmap.as_ptr() as *const f64, shape.0 * shape.1)
+                };
+                ArrayView2::from_shape(*shape, values).expect("mapped matrix shape mismatch")
+            }
+        }
+    }
+
+    fn view_mut(&mut self) -> ArrayViewMut2<'_, f64> {
+        match self {
+            CountMatrix::InMemory(array) => array.view_mut(),
+            CountMatrix::Mapped { mmap, shape, .. } => {
+                // SAFETY: as in `view`, and the mutable borrow of `self` guarantees exclusivity.
+                let values = unsafe {
+                    std::slice::from_raw_parts_mut(mmap.as_mut_ptr() as *mut f64, shape.0 * shape.1)
+                };
+                ArrayViewMut2::from_shape(*shape, values).expect("mapped matrix shape mismatch")
+            }
+        }
+    }
+
+    fn is_mapped(&self) -> bool {
+        matches!(self, CountMatrix::Mapped { .. })
+    }
+}
+
+/// Count matrix together with its gene (row) and sample (column) labels.
+type LabeledMatrix = (CountMatrix, Vec<String>, Vec<String>);
+
+/// Compressed sparse row gene x sample matrix holding only non-zero counts,
+/// for inputs (e.g. single-cell) where a dense matrix would be mostly zeros.
+struct CsrMatrix {
+    shape: (usize, usize),
+    /// Row `g` occupies `col_idx[row_ptr[g]..row_ptr[g + 1]]`
+    row_ptr: Vec<usize>,
+    col_idx: Vec<usize>,
+    values: Vec<f64>,
+}
+
+impl CsrMatrix {
+    /// Column indices and values of the non-zero entries in one row
+    fn row(&self, g_idx: usize) -> (&[usize], &[f64]) {
+        let range = self.row_ptr[g_idx]..self.row_ptr[g_idx + 1];
+        (&self.col_idx[range.clone()], &self.values[range])
+    }
+
+    fn nnz(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Dense copy for the stages (dispersions, transforms) that need one
+    fn to_dense(&self, max_memory: Option<usize>) -> Result<CountMatrix, Box<dyn Error>> {
+        let mut dense = CountMatrix::zeros(self.shape, max_memory)?;
+        {
+            let mut values = dense.view_mut();
+            for g_idx in 0..self.shape.0 {
+                let (cols, vals) = self.row(g_idx);
+                for (&s_idx, &value) in cols.iter().zip(vals) {
+                    values[[g_idx, s_idx]] = value;
+                }
+            }
+        }
+        Ok(dense)
+    }
+}
+
+/// Sparse counterpart of `LabeledMatrix`.
+type SparseLabeledMatrix = (CsrMatrix, Vec<String>, Vec<String>);
+
+/// Count storage selected by `--sparse`
+enum Counts {
+    Dense(CountMatrix),
+    Sparse(CsrMatrix),
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct GeneCount {
@@ -16,16 +196,189 @@ struct GeneCount {
     count: f64,
 }
 
+/// Wall time and I/O volume for one normalization stage
+#[derive(Serialize, Debug)]
+struct StageProfile {
+    stage: String,
+    wall_seconds: f64,
+    bytes_read: u64,
+    bytes_written: u64,
+    records: u64,
+    records_per_sec: f64,
+}
+
+/// Runtime breakdown written by `--profile-json`
+#[derive(Serialize, Debug, Default)]
+struct RunProfile {
+    tool: String,
+    threads: usize,
+    total_wall_seconds: f64,
+    peak_heap_bytes: usize,
+    stages: Vec<StageProfile>,
+}
+
+impl RunProfile {
+    fn new(threads: usize) -> Self {
+        RunProfile {
+            tool: env!("CARGO_PKG_NAME").to_string(),
+            threads,
+            ..Default::default()
+        }
+    }
+
+    fn record(&mut self, stage: &str, started: Instant, records: u64, bytes_read: u64, bytes_written: u64) {
+        let wall_seconds = started.elapsed().as_secs_f64();
+        self.peak_heap_bytes = self.peak_heap_bytes.max(heap_bytes());
+        self.stages.push(StageProfile {
+            stage: stage.to_string(),
+            wall_seconds,
+            bytes_read,
+            bytes_written,
+            records,
+            records_per_sec: if wall_seconds > 0.0 { records as f64 / wall_seconds } else { 0.0 },
+        });
+    }
+
+    fn write(mut self, filename: &str, run_start: Instant) -> Result<(), Box<dyn Error>> {
+        self.total_wall_seconds = run_start.elapsed().as_secs_f64();
+        let file = File::create(filename)?;
+        serde_json::to_writer_pretty(file, &self)?;
+        Ok(())
+    }
+}
+
+fn file_len(filename: &str) -> u64 {
+    std::fs::metadata(filename).map(|m| m.len()).unwrap_or(0)
+}
+
+/// Two-group design: condition label per sample plus the reference level
+struct Design {
+    reference: String,
+    treatment: String,
+    /// `true` for samples in the treatment group, in matrix column order
+    is_treatment: Vec<bool>,
+}
+
+impl Design {
+    /// Intercept-only design (`~ 1`) used for blind dispersion estimation
+    fn intercept_only(n_samples: usize) -> Self {
+        Design {
+            reference: "all".to_string(),
+            treatment: String::new(),
+            is_treatment: vec![false; n_samples],
+        }
+    }
+
+    fn group_count(&self) -> usize {
+        let treated = self.is_treatment.iter().filter(|&&t| t).count();
+        if treated == 0 || treated == self.is_treatment.len() { 1 } else { 2 }
+    }
+}
+
+/// On-disk layout of a count table
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MatrixLayout {
+    /// One `gene_id<TAB>sample_id<TAB>count` triple per line
+    Long,
+    /// `gene_id` column followed by one column per sample
+    Wide,
+}
+
+impl std::str::FromStr for MatrixLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "long" => Ok(MatrixLayout::Long),
+            "wide" => Ok(MatrixLayout::Wide),
+            other => Err(format!("Unknown matrix layout '{}' (expected long or wide)", other)),
+        }
+    }
+}
+
+fn is_parquet(filename: &str) -> bool {
+    filename.to_ascii_lowercase().ends_with(".parquet")
+}
+
+/// Size-factor estimators available through `--size-factor-method`
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SizeFactorMethod {
+    /// Median ratio to the pseudocounted geometric mean of non-zero counts
+    Ratio,
+    /// DESeq2 `type = "poscounts"`: zeros enter the geometric mean as ones
+    Poscounts,
+    /// DESeq2 `type = "iterate"`: maximum likelihood under an intercept-only NB fit
+    Iterate,
+}
+
+impl std::str::FromStr for SizeFactorMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "ratio" => Ok(SizeFactorMethod::Ratio),
+            "poscounts" => Ok(SizeFactorMethod::Poscounts),
+            "iterate" => Ok(SizeFactorMethod::Iterate),
+            other => Err(format!("Unknown size factor method '{}' (expected ratio, poscounts or iterate)", other)),
+        }
+    }
+}
+
+/// Variance-stabilizing transformations available through `--transform`
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Transform {
+    Vst,
+    Rlog,
+}
+
+impl std::str::FromStr for Transform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "vst" => Ok(Transform::Vst),
+            "rlog" => Ok(Transform::Rlog),
+            other => Err(format!("Unknown transform '{}' (expected vst or rlog)", other)),
+        }
+    }
+}
+
+/// Per-gene dispersion estimates at each stage of the DESeq2-style fit
+struct DispersionFit {
+    gene_wise: Vec<f64>,
+    trend: Vec<f64>,
+    map: Vec<f64>,
+    /// Dispersion used for testing (MAP, or gene-wise for dispersion outliers)
+    final_disp: Vec<f64>,
+    trend_coefs: (f64, f64),
+    prior_var: f64,
+    outliers: usize,
+}
+
+/// Wald test result for one gene; `None` fields are written as NA
+struct DeResult {
+    base_mean: f64,
+    log2_fold_change: Option<f64>,
+    lfc_se: Option<f64>,
+    stat: Option<f64>,
+    pvalue: Option<f64>,
+    padj: Option<f64>,
+    dispersion: Option<f64>,
+}
+
 #[derive(Debug)]
 struct NormalizationStats {
     total_genes: usize,
     total_samples: usize,
     zero_counts: usize,
+    size_factor_method: SizeFactorMethod,
+    control_genes: Option<usize>,
     size_factors: Vec<f64>,
     geometric_means_computed: usize,
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
+    let run_start = Instant::now();
     env_logger::init();
     
     let matches = Command::new("RNA-seq Normalizer")
@@ -50,6 +403,18 @@ fn main() -> Result<(), Box<dyn Error>> {
             .value_name("FILE")
             .help("Output file for normalization statistics")
             .default_value("normalization_stats.txt"))
+        .arg(Arg::new("input_layout")
+            .long("input-layout")
+            .value_name("LAYOUT")
+            .value_parser(["auto", "long", "wide"])
+            .help("Input table layout; auto detects wide TSVs from a numeric second column (.parquet is always wide)")
+            .default_value("auto"))
+        .arg(Arg::new("output_layout")
+            .long("output-layout")
+            .value_name("LAYOUT")
+            .value_parser(["long", "wide"])
+            .help("Layout of the normalized counts (.parquet outputs are always wide)")
+            .default_value("long"))
         .arg(Arg::new("min_count")
             .long("min-count")
             .value_name("NUMBER")
@@ -60,6 +425,62 @@ fn main() -> Result<(), Box<dyn Error>> {
             .value_name("NUMBER")
             .help("Pseudocount to add for geometric mean calculation")
             .default_value("1.0"))
+        .arg(Arg::new("size_factor_method")
+            .long("size-factor-method")
+            .value_name("METHOD")
+            .value_parser(["ratio", "poscounts", "iterate"])
+            .help("Size factor estimator; poscounts and iterate suit data where most genes contain zeros")
+            .default_value("ratio"))
+        .arg(Arg::new("control_genes")
+            .long("control-genes")
+            .value_name("FILE")
+            .help("Gene IDs (one per line) to estimate size factors from, e.g. housekeeping genes or spike-ins"))
+        .arg(Arg::new("threads")
+            .short('t')
+            .long("threads")
+            .value_name("NUMBER")
+            .help("Worker threads for the per-gene and per-sample stages (0 = all cores)")
+            .default_value("0"))
+        .arg(Arg::new("max_memory")
+            .long("max-memory")
+            .value_name("SIZE")
+            .help("Heap budget (e.g. 4G); larger matrices are backed by a memory-mapped scratch file"))
+        .arg(Arg::new("sparse")
+            .long("sparse")
+            .action(ArgAction::SetTrue)
+            .help("Store counts as a CSR sparse matrix (for mostly-zero, single-cell sized inputs); only non-zero normalized counts are written"))
+        .arg(Arg::new("design")
+            .long("design")
+            .value_name("FILE")
+            .help("Sample sheet (sample_id plus a condition column); enables dispersion estimation and the Wald test"))
+        .arg(Arg::new("condition_column")
+            .long("condition-column")
+            .value_name("NAME")
+            .help("Design column holding the two-level condition")
+            .default_value("condition"))
+        .arg(Arg::new("reference_level")
+            .long("reference-level")
+            .value_name("LEVEL")
+            .help("Condition level used as the denominator of fold changes (default: first level alphabetically)"))
+        .arg(Arg::new("results")
+            .long("results")
+            .value_name("FILE")
+            .help("Output file for differential expression results")
+            .default_value("de_results.tsv"))
+        .arg(Arg::new("transform")
+            .long("transform")
+            .value_name("METHOD")
+            .value_parser(["vst", "rlog"])
+            .help("Write a variance-stabilized matrix (vst or rlog) for clustering and PCA"))
+        .arg(Arg::new("transform_output")
+            .long("transform-output")
+            .value_name("FILE")
+            .help("Output file for the transformed gene x sample matrix")
+            .default_value("transformed_counts.tsv"))
+        .arg(Arg::new("profile_json")
+            .long("profile-json")
+            .value_name("FILE")
+            .help("Write a JSON runtime breakdown (per-stage wall time, bytes, records/sec) on exit"))
         .get_matches();
 
     let input_file = matches.get_one::<String>("input").unwrap();
@@ -67,16 +488,49 @@ fn main() -> Result<(), Box<dyn Error>> {
     let stats_file = matches.get_one::<String>("stats").unwrap();
     let min_count: f64 = matches.get_one::<String>("min_count").unwrap().parse()?;
     let pseudocount: f64 = matches.get_one::<String>("pseudocount").unwrap().parse()?;
+    let max_memory = matches
+        .get_one::<String>("max_memory")
+        .map(|s| parse_memory_size(s))
+        .transpose()?;
+    let sparse = matches.get_flag("sparse");
+    let input_layout = match matches.get_one::<String>("input_layout").unwrap().as_str() {
+        _ if is_parquet(input_file) => MatrixLayout::Wide,
+        "auto" => detect_layout(input_file)?,
+        layout => layout.parse()?,
+    };
+    let output_layout: MatrixLayout = if is_parquet(output_file) {
+        MatrixLayout::Wide
+    } else {
+        matches.get_one::<String>("output_layout").unwrap().parse()?
+    };
+    let size_factor_method: SizeFactorMethod = matches.get_one::<String>("size_factor_method").unwrap().parse()?;
+    if sparse && size_factor_method == SizeFactorMethod::Iterate {
+        return Err("--size-factor-method iterate needs the dense matrix; drop --sparse".into());
+    }
+    let threads: usize = matches.get_one::<String>("threads").unwrap().parse()?;
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build_global()
+        .map_err(|e| format!("Failed to initialize thread pool: {}", e))?;
+    let mut profile = RunProfile::new(rayon::current_num_threads());
 
     info!("Starting RNA-seq normalization pipeline");
-    info!("Input file: {}", input_file);
-    info!("Output file: {}", output_file);
+    info!("Input file: {} ({:?} layout)", input_file, input_layout);
+    info!("Output file: {} ({:?} layout)", output_file, output_layout);
     info!("Min count threshold: {}", min_count);
     info!("Pseudocount: {}", pseudocount);
+    info!("Size factor method: {:?}", size_factor_method);
+    info!("Threads: {}", rayon::current_num_threads());
 
     // Read and validate input data
-    let data = read_count_data(input_file)?;
+    let stage_start = Instant::now();
+    let data = match input_layout {
+        MatrixLayout::Long => read_count_data(input_file)?,
+        MatrixLayout::Wide if is_parquet(input_file) => read_wide_parquet(input_file)?,
+        MatrixLayout::Wide => read_wide_tsv(input_file)?,
+    };
     info!("Read {} count entries from input file", data.len());
+    profile.record("read", stage_start, data.len() as u64, file_len(input_file), 0);
 
     if data.is_empty() {
         error!("No valid data found in input file");
@@ -84,35 +538,177 @@ fn main() -> Result<(), Box<dyn Error>> {
     }
 
     // Create count matrix
-    let (matrix, genes, samples) = create_count_matrix(&data)?;
-    info!("Created count matrix: {} genes x {} samples", genes.len(), samples.len());
+    let stage_start = Instant::now();
+    let (counts, genes, samples) = if sparse {
+        let (csr, genes, samples) = create_sparse_count_matrix(&data)?;
+        info!(
+            "Created sparse count matrix: {} genes x {} samples, {} non-zero ({:.2}% dense)",
+            genes.len(),
+            samples.len(),
+            csr.nnz(),
+            100.0 * csr.nnz() as f64 / (genes.len() * samples.len()).max(1) as f64
+        );
+        (Counts::Sparse(csr), genes, samples)
+    } else {
+        let (matrix, genes, samples) = create_count_matrix(&data, max_memory)?;
+        info!(
+            "Created count matrix: {} genes x {} samples{}",
+            genes.len(),
+            samples.len(),
+            if matrix.is_mapped() { " (memory-mapped)" } else { "" }
+        );
+        (Counts::Dense(matrix), genes, samples)
+    };
+    profile.record("build_matrix", stage_start, data.len() as u64, 0, 0);
+    drop(data);
 
     // Quality control checks
-    perform_quality_checks(&matrix, &genes, &samples, min_count)?;
+    match &counts {
+        Counts::Dense(matrix) => perform_quality_checks(matrix.view(), &genes, &samples, min_count)?,
+        Counts::Sparse(csr) => perform_sparse_quality_checks(csr, &samples, min_count)?,
+    }
+
+    // Restrict size factor estimation to control genes if given
+    let control = matches
+        .get_one::<String>("control_genes")
+        .map(|file| read_control_genes(file, &genes))
+        .transpose()?;
 
     // Calculate normalization factors
-    let size_factors = calculate_size_factors(&matrix, &genes, &samples, pseudocount)?;
+    let stage_start = Instant::now();
+    let size_factors = match &counts {
+        Counts::Dense(matrix) if size_factor_method == SizeFactorMethod::Iterate => {
+            iterate_size_factors(matrix.view(), &samples, control.as_deref())?
+        }
+        Counts::Dense(matrix) => calculate_size_factors(
+            matrix.view(),
+            &genes,
+            &samples,
+            pseudocount,
+            size_factor_method,
+            control.as_deref(),
+        )?,
+        Counts::Sparse(csr) => {
+            calculate_sparse_size_factors(csr, &samples, pseudocount, size_factor_method, control.as_deref())?
+        }
+    };
     info!("Calculated size factors for {} samples", size_factors.len());
+    profile.record("size_factors", stage_start, genes.len() as u64, 0, 0);
 
-    // Normalize the matrix
-    let normalized_matrix = normalize_counts(&matrix, &size_factors)?;
-    info!("Normalization completed");
+    // Normalize and write results; the sparse path scales entries as it writes
+    match &counts {
+        Counts::Dense(matrix) => {
+            let stage_start = Instant::now();
+            let normalized_matrix = normalize_counts(matrix.view(), &size_factors, max_memory)?;
+            info!("Normalization completed");
+            profile.record("normalize", stage_start, genes.len() as u64, 0, 0);
 
-    // Write results
-    write_normalized_counts(&normalized_matrix, &genes, &samples, output_file)?;
+            let stage_start = Instant::now();
+            match output_layout {
+                MatrixLayout::Long => write_normalized_counts(normalized_matrix.view(), &genes, &samples, output_file)?,
+                MatrixLayout::Wide => write_matrix(normalized_matrix.view(), &genes, &samples, output_file)?,
+            }
+            profile.record("write", stage_start, (genes.len() * samples.len()) as u64, 0, file_len(output_file));
+        }
+        Counts::Sparse(csr) if output_layout == MatrixLayout::Wide => {
+            // A wide table stores every zero anyway, so go through a dense copy
+            let stage_start = Instant::now();
+            let normalized_matrix = normalize_counts(csr.to_dense(max_memory)?.view(), &size_factors, max_memory)?;
+            write_matrix(normalized_matrix.view(), &genes, &samples, output_file)?;
+            profile.record("write", stage_start, (genes.len() * samples.len()) as u64, 0, file_len(output_file));
+        }
+        Counts::Sparse(csr) => {
+            let stage_start = Instant::now();
+            write_sparse_normalized_counts(csr, &size_factors, &genes, &samples, output_file)?;
+            profile.record("write", stage_start, csr.nnz() as u64, 0, file_len(output_file));
+        }
+    }
     info!("Normalized counts written to {}", output_file);
 
     // Write statistics
     let stats = NormalizationStats {
         total_genes: genes.len(),
         total_samples: samples.len(),
-        zero_counts: count_zeros(&matrix),
+        zero_counts: match &counts {
+            Counts::Dense(matrix) => count_zeros(matrix.view()),
+            Counts::Sparse(csr) => genes.len() * samples.len() - csr.nnz(),
+        },
+        size_factor_method,
+        control_genes: control.as_ref().map(|c| c.iter().filter(|&&g| g).count()),
         size_factors: size_factors.clone(),
         geometric_means_computed: genes.len(),
     };
     write_statistics(&stats, &genes, &samples, stats_file)?;
     info!("Statistics written to {}", stats_file);
 
+    // Dispersion-based stages work on a dense matrix
+    let needs_dense = matches.contains_id("transform") || matches.contains_id("design");
+    let matrix = match counts {
+        Counts::Dense(matrix) => matrix,
+        Counts::Sparse(csr) if needs_dense => {
+            info!("Densifying sparse matrix for dispersion estimation");
+            csr.to_dense(max_memory)?
+        }
+        // No later stage reads the matrix
+        Counts::Sparse(_) => CountMatrix::zeros((0, 0), None)?,
+    };
+
+    // Variance-stabilized output, using blind (intercept-only) dispersions
+    if let Some(method) = matches.get_one::<String>("transform") {
+        let method: Transform = method.parse()?;
+        let transform_file = matches.get_one::<String>("transform_output").unwrap();
+
+        let stage_start = Instant::now();
+        let blind = Design::intercept_only(samples.len());
+        let dispersions = estimate_dispersions(matrix.view(), &size_factors, &blind)?;
+        let transformed = match method {
+            Transform::Vst => vst_transform(matrix.view(), &size_factors, &dispersions, max_memory)?,
+            Transform::Rlog => rlog_transform(matrix.view(), &size_factors, &dispersions, max_memory)?,
+        };
+        write_matrix(transformed.view(), &genes, &samples, transform_file)?;
+        info!("{:?}-transformed matrix written to {}", method, transform_file);
+        profile.record("transform", stage_start, genes.len() as u64, 0, file_len(transform_file));
+    }
+
+    // Differential expression when a design is supplied
+    if let Some(design_file) = matches.get_one::<String>("design") {
+        let condition_column = matches.get_one::<String>("condition_column").unwrap();
+        let reference_level = matches.get_one::<String>("reference_level").map(|s| s.as_str());
+        let results_file = matches.get_one::<String>("results").unwrap();
+
+        let design = read_design(design_file, condition_column, reference_level, &samples)?;
+        info!(
+            "Testing {} vs {} ({} vs {} samples)",
+            design.treatment,
+            design.reference,
+            design.is_treatment.iter().filter(|&&t| t).count(),
+            design.is_treatment.iter().filter(|&&t| !t).count()
+        );
+
+        let stage_start = Instant::now();
+        let dispersions = estimate_dispersions(matrix.view(), &size_factors, &design)?;
+        info!(
+            "Dispersion trend: {:.4} + {:.4}/mean, prior variance {:.4}, {} outliers",
+            dispersions.trend_coefs.0,
+            dispersions.trend_coefs.1,
+            dispersions.prior_var,
+            dispersions.outliers
+        );
+        profile.record("dispersion", stage_start, genes.len() as u64, 0, 0);
+
+        let stage_start = Instant::now();
+        let results = wald_test(matrix.view(), &size_factors, &design, &dispersions);
+        write_de_results(&results, &dispersions, &genes, results_file)?;
+        let significant = results.iter().filter(|r| r.padj.is_some_and(|p| p < 0.1)).count();
+        info!("{} genes with padj < 0.1; results written to {}", significant, results_file);
+        profile.record("wald_test", stage_start, genes.len() as u64, 0, file_len(results_file));
+    }
+
+    if let Some(profile_file) = matches.get_one::<String>("profile_json") {
+        profile.write(profile_file, run_start)?;
+        info!("Runtime profile written to {}", profile_file);
+    }
+
     info!("Pipeline completed successfully");
     Ok(())
 }
@@ -174,7 +770,106 @@ fn read_count_data(filename: &str) -> Result<Vec<GeneCount>, Box<dyn Error>> {
     Ok(data)
 }
 
-fn create_count_matrix(data: &[GeneCount]) -> Result<(Array2<f64>, Vec<String>, Vec<String>), Box<dyn Error>> {
+/// Wide tables carry a count in their second column where long tables
+/// carry a sample ID; look at the first data line to tell them apart
+fn detect_layout(filename: &str) -> Result<MatrixLayout, Box<dyn Error>> {
+    let file = File::open(filename).map_err(|e| format!("Input file '{}': {}", filename, e))?;
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        let fields: Vec<&str> = line.split('\t').collect();
+        if line.trim().is_empty() || fields.len() < 2 {
+            continue;
+        }
+        // Skip the header; both layouts have a non-numeric one
+        if fields[1..].iter().all(|f| f.trim().parse::<f64>().is_err()) {
+            continue;
+        }
+        return Ok(if fields[1].trim().parse::<f64>().is_ok() { MatrixLayout::Wide } else { MatrixLayout::Long });
+    }
+    Ok(MatrixLayout::Long)
+}
+
+/// Read a wide `gene_id<TAB>sample...` TSV, the layout written by
+/// experiment_9_1 and the `--samples` mode of experiment_9_2
+fn read_wide_tsv(filename: &str) -> Result<Vec<GeneCount>, Box<dyn Error>> {
+    let reader = BufReader::new(File::open(filename)?);
+    let mut lines = reader.lines();
+    let header = lines.next().ok_or("Wide input has no header line")??;
+    let samples: Vec<String> = header.split('\t').skip(1).map(|s| s.trim().to_string()).collect();
+    if samples.is_empty() {
+        return Err("Wide input header has no sample columns".into());
+    }
+
+    let mut data = Vec::new();
+    for (line_idx, line) in lines.enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let line_number = line_idx + 2;
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != samples.len() + 1 {
+            warn!("Expected {} columns at line {}, found {}", samples.len() + 1, line_number, fields.len());
+            continue;
+        }
+        let gene_id = fields[0].trim();
+        for (sample, field) in samples.iter().zip(&fields[1..]) {
+            match field.trim().parse::<f64>() {
+                Ok(count) if count >= 0.0 && count.is_finite() => data.push(GeneCount {
+                    gene_id: gene_id.to_string(),
+                    sample_id: sample.clone(),
+                    count,
+                }),
+                _ => warn!("Invalid count at line {} for sample {}: '{}'", line_number, sample, field),
+            }
+        }
+    }
+
+    Ok(data)
+}
+
+/// Read a wide Parquet matrix: a `gene_id` string column (or the first
+/// column) plus one numeric column per sample
+fn read_wide_parquet(filename: &str) -> Result<Vec<GeneCount>, Box<dyn Error>> {
+    let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(filename)?)?.build()?;
+    let mut data = Vec::new();
+
+    for batch in reader {
+        let batch = batch?;
+        let schema = batch.schema();
+        let gene_col = schema.index_of("gene_id").unwrap_or(0);
+        let gene_ids = arrow::compute::cast(batch.column(gene_col), &DataType::Utf8)?;
+        let gene_ids = gene_ids.as_any().downcast_ref::<StringArray>().ok_or("gene_id column is not text")?;
+
+        for (col_idx, field) in schema.fields().iter().enumerate() {
+            if col_idx == gene_col {
+                continue;
+            }
+            let counts = arrow::compute::cast(batch.column(col_idx), &DataType::Float64)
+                .map_err(|e| format!("Sample column '{}' is not numeric: {}", field.name(), e))?;
+            let counts = counts.as_any().downcast_ref::<Float64Array>().unwrap();
+            for row in 0..batch.num_rows() {
+                if gene_ids.is_null(row) || counts.is_null(row) {
+                    continue;
+                }
+                let count = counts.value(row);
+                if count >= 0.0 && count.is_finite() {
+                    data.push(GeneCount {
+                        gene_id: gene_ids.value(row).to_string(),
+                        sample_id: field.name().clone(),
+                        count,
+                    });
+                } else {
+                    warn!("Invalid count for gene {} in sample {}: {}", gene_ids.value(row), field.name(), count);
+                }
+            }
+        }
+    }
+
+    Ok(data)
+}
+
+fn create_count_matrix(data: &[GeneCount], max_memory: Option<usize>) -> Result<LabeledMatrix, Box<dyn Error>> {
     // Collect unique genes and samples using HashSet for efficiency
     let mut gene_set = HashSet::new();
     let mut sample_set = HashSet::new();
@@ -197,23 +892,67 @@ fn create_count_matrix(data: &[GeneCount]) -> Result<(Array2<f64>, Vec<String>,
         .map(|(i, s)| (s.clone(), i)).collect();
 
     // Initialize matrix
-    let mut matrix = Array2::<f64>::zeros((genes.len(), samples.len()));
+    let mut matrix = CountMatrix::zeros((genes.len(), samples.len()), max_memory)?;
     
     // Fill matrix
-    for entry in data {
-        if let (Some(&g_idx), Some(&s_idx)) = (
-            gene_to_idx.get(&entry.gene_id),
-            sample_to_idx.get(&entry.sample_id)
-        ) {
-            matrix[[g_idx, s_idx]] = entry.count;
+    {
+        let mut values = matrix.view_mut();
+        for entry in data {
+            if let (Some(&g_idx), Some(&s_idx)) = (
+                gene_to_idx.get(&entry.gene_id),
+                sample_to_idx.get(&entry.sample_id)
+            ) {
+                values[[g_idx, s_idx]] = entry.count;
+            }
         }
     }
 
     Ok((matrix, genes, samples))
 }
 
+/// Build the CSR matrix directly from the long-format entries; zero counts
+/// are not stored and a repeated gene/sample pair keeps its last value, as in
+/// the dense builder.
+fn create_sparse_count_matrix(data: &[GeneCount]) -> Result<SparseLabeledMatrix, Box<dyn Error>> {
+    let mut genes: Vec<String> = data.iter().map(|e| e.gene_id.clone()).collect::<HashSet<_>>().into_iter().collect();
+    let mut samples: Vec<String> = data.iter().map(|e| e.sample_id.clone()).collect::<HashSet<_>>().into_iter().collect();
+    genes.sort();
+    samples.sort();
+
+    let gene_to_idx: HashMap<&str, usize> = genes.iter().enumerate().map(|(i, g)| (g.as_str(), i)).collect();
+    let sample_to_idx: HashMap<&str, usize> = samples.iter().enumerate().map(|(i, s)| (s.as_str(), i)).collect();
+
+    // (gene, sample, input order) sorts duplicates so the last one wins
+    let mut triplets: Vec<(usize, usize, usize)> = data
+        .iter()
+        .enumerate()
+        .map(|(order, e)| (gene_to_idx[e.gene_id.as_str()], sample_to_idx[e.sample_id.as_str()], order))
+        .collect();
+    triplets.sort_unstable();
+
+    let mut row_ptr = vec![0; genes.len() + 1];
+    let mut col_idx = Vec::new();
+    let mut values = Vec::new();
+    for (i, &(g_idx, s_idx, order)) in triplets.iter().enumerate() {
+        let superseded = triplets.get(i + 1).is_some_and(|next| next.0 == g_idx && next.1 == s_idx);
+        let count = data[order].count;
+        if superseded || count == 0.0 {
+            continue;
+        }
+        col_idx.push(s_idx);
+        values.push(count);
+        row_ptr[g_idx + 1] += 1;
+    }
+    for g_idx in 0..genes.len() {
+        row_ptr[g_idx + 1] += row_ptr[g_idx];
+    }
+
+    let csr = CsrMatrix { shape: (genes.len(), samples.len()), row_ptr, col_idx, values };
+    Ok((csr, genes, samples))
+}
+
 fn perform_quality_checks(
-    matrix: &Array2<f64>,
+    matrix: ArrayView2<f64>,
     genes: &[String],
     samples: &[String],
     min_count: f64,
@@ -245,94 +984,958 @@ fn perform_quality_checks(
     Ok(())
 }
 
+fn perform_sparse_quality_checks(
+    csr: &CsrMatrix,
+    samples: &[String],
+    min_count: f64,
+) -> Result<(), Box<dyn Error>> {
+    info!("Performing quality control checks");
+
+    let mut col_sums = vec![0.0; samples.len()];
+    for (&s_idx, &value) in csr.col_idx.iter().zip(&csr.values) {
+        col_sums[s_idx] += value;
+    }
+    for (sample, col_sum) in samples.iter().zip(&col_sums) {
+        if *col_sum < min_count {
+            warn!("Sample '{}' has very low total counts: {}", sample, col_sum);
+        }
+    }
+
+    let zero_genes = (0..csr.shape.0).filter(|&g_idx| csr.row(g_idx).0.is_empty()).count();
+    if zero_genes > 0 {
+        warn!("{} genes have zero counts across all samples", zero_genes);
+    }
+
+    info!("Quality control checks completed");
+    Ok(())
+}
+
+/// Median of the per-gene count / geometric-mean ratios of one sample
+fn median_ratio(mut ratios: Vec<f64>, sample: &str) -> f64 {
+    if ratios.is_empty() {
+        warn!("No valid ratios for sample '{}', using size factor of 1.0", sample);
+        return 1.0;
+    }
+    ratios.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let median = if ratios.len().is_multiple_of(2) {
+        (ratios[ratios.len() / 2 - 1] + ratios[ratios.len() / 2]) / 2.0
+    } else {
+        ratios[ratios.len() / 2]
+    };
+    median.max(0.001) // Avoid division by very small numbers
+}
+
+/// Per-gene reference for the median ratio, from the gene's non-zero
+/// counts out of `n_samples`
+fn gene_geometric_mean(nonzero: &[f64], n_samples: usize, method: SizeFactorMethod, pseudocount: f64) -> f64 {
+    if nonzero.is_empty() {
+        return 0.0;
+    }
+    match method {
+        SizeFactorMethod::Poscounts => {
+            (nonzero.iter().map(|c| c.ln()).sum::<f64>() / n_samples as f64).exp()
+        }
+        _ => {
+            let log_sum: f64 = nonzero.iter().map(|&count| (count + pseudocount).ln()).sum();
+            ((log_sum / nonzero.len() as f64).exp() - pseudocount).max(0.0)
+        }
+    }
+}
+
+/// Median ratios per sample, rescaled to a geometric mean of one for
+/// poscounts as DESeq2 does
+fn finish_size_factors(ratios: Vec<Vec<f64>>, samples: &[String], method: SizeFactorMethod) -> Vec<f64> {
+    let mut size_factors: Vec<f64> = ratios
+        .into_par_iter()
+        .zip(samples)
+        .map(|(sample_ratios, sample)| median_ratio(sample_ratios, sample))
+        .collect();
+    if method == SizeFactorMethod::Poscounts {
+        let log_mean = size_factors.iter().map(|sf| sf.ln()).sum::<f64>() / size_factors.len() as f64;
+        size_factors.iter_mut().for_each(|sf| *sf /= log_mean.exp());
+    }
+    size_factors
+}
+
+/// Read control gene IDs and map them to a row mask over `genes`
+fn read_control_genes(filename: &str, genes: &[String]) -> Result<Vec<bool>, Box<dyn Error>> {
+    let gene_to_idx: HashMap<&str, usize> = genes.iter().enumerate().map(|(i, g)| (g.as_str(), i)).collect();
+    let mut control = vec![false; genes.len()];
+    let mut listed = 0;
+    let mut missing = 0;
+
+    for line in BufReader::new(File::open(filename)?).lines() {
+        let line = line?;
+        let gene_id = line.split('\t').next().unwrap_or("").trim();
+        if gene_id.is_empty() || gene_id.starts_with('#') {
+            continue;
+        }
+        listed += 1;
+        match gene_to_idx.get(gene_id) {
+            Some(&g_idx) => control[g_idx] = true,
+            None => missing += 1,
+        }
+    }
+
+    let matched = control.iter().filter(|&&c| c).count();
+    if missing > 0 {
+        warn!("{} of {} control genes are not in the count matrix", missing, listed);
+    }
+    if matched == 0 {
+        return Err(format!("None of the control genes in '{}' are in the count matrix", filename).into());
+    }
+    info!("Estimating size factors from {} control genes", matched);
+    Ok(control)
+}
+
+/// Median-ratio size factors over the stored entries only. Zero counts are
+/// already excluded from the ratios and enter the geometric means only
+/// through the sample count, so this matches the dense computation without
+/// touching the implicit zeros.
+fn calculate_sparse_size_factors(
+    csr: &CsrMatrix,
+    samples: &[String],
+    pseudocount: f64,
+    method: SizeFactorMethod,
+    control: Option<&[bool]>,
+) -> Result<Vec<f64>, Box<dyn Error>> {
+    info!("Calculating geometric means");
+    let geom_means: Vec<f64> = (0..csr.shape.0)
+        .into_par_iter()
+        .map(|g_idx| {
+            if control.is_some_and(|c| !c[g_idx]) {
+                return 0.0;
+            }
+            gene_geometric_mean(csr.row(g_idx).1, samples.len(), method, pseudocount)
+        })
+        .collect();
+    let valid_genes = geom_means.iter().filter(|&&gm| gm > 0.0).count();
+    info!("Calculated geometric means for {}/{} genes", valid_genes, csr.shape.0);
+
+    // Each worker scatters the ratios of its genes into per-sample buckets;
+    // the buckets are concatenated afterwards (order is irrelevant to the
+    // median)
+    info!("Calculating size factors");
+    let empty = || vec![Vec::new(); samples.len()];
+    let ratios: Vec<Vec<f64>> = (0..csr.shape.0)
+        .into_par_iter()
+        .filter(|&g_idx| geom_means[g_idx] > 0.0)
+        .fold(empty, |mut ratios, g_idx| {
+            let (cols, vals) = csr.row(g_idx);
+            for (&s_idx, &count) in cols.iter().zip(vals) {
+                ratios[s_idx].push(count / geom_means[g_idx]);
+            }
+            ratios
+        })
+        .reduce(empty, |mut ratios, other| {
+            for (bucket, more) in ratios.iter_mut().zip(other) {
+                bucket.extend(more);
+            }
+            ratios
+        });
+    Ok(finish_size_factors(ratios, samples, method))
+}
+
 fn calculate_size_factors(
-    matrix: &Array2<f64>,
+    matrix: ArrayView2<f64>,
     genes: &[String],
     samples: &[String],
     pseudocount: f64,
+    method: SizeFactorMethod,
+    control: Option<&[bool]>,
 ) -> Result<Vec<f64>, Box<dyn Error>> {
     info!("Calculating geometric means");
-    
-    // Calculate geometric means for each gene
-    let mut geom_means = Vec::with_capacity(genes.len());
-    let mut valid_genes = 0;
-    
-    for g_idx in 0..genes.len() {
-        let row = matrix.slice(s![g_idx, ..]);
-        let mut log_sum = 0.0;
-        let mut valid_samples = 0;
-        
-        for &count in row.iter() {
-            if count > 0.0 {
-                log_sum += (count + pseudocount).ln();
-                valid_samples += 1;
+
+    // Geometric means for each gene; non-control genes get 0 and so never
+    // contribute a ratio
+    let geom_means: Vec<f64> = (0..genes.len())
+        .into_par_iter()
+        .map(|g_idx| {
+            if control.is_some_and(|c| !c[g_idx]) {
+                return 0.0;
             }
-        }
-        
-        let gm = if valid_samples > 0 {
-            (log_sum / valid_samples as f64).exp() - pseudocount
-        } else {
-            0.0
-        };
-        
-        geom_means.push(gm.max(0.0));
-        if gm > 0.0 {
-            valid_genes += 1;
-        }
-    }
-    
+            let nonzero: Vec<f64> = matrix.row(g_idx).iter().copied().filter(|&count| count > 0.0).collect();
+            gene_geometric_mean(&nonzero, samples.len(), method, pseudocount)
+        })
+        .collect();
+    let valid_genes = geom_means.iter().filter(|&&gm| gm > 0.0).count();
+
     info!("Calculated geometric means for {}/{} genes", valid_genes, genes.len());
-    
+
     // Calculate size factors
     info!("Calculating size factors");
-    let mut size_factors = Vec::with_capacity(samples.len());
-    
-    for s_idx in 0..samples.len() {
-        let mut ratios = Vec::new();
-        
-        for g_idx in 0..genes.len() {
-            let count_val = matrix[[g_idx, s_idx]];
-            if geom_means[g_idx] > 0.0 && count_val > 0.0 {
-                ratios.push(count_val / geom_means[g_idx]);
+    let ratios: Vec<Vec<f64>> = (0..samples.len())
+        .into_par_iter()
+        .map(|s_idx| {
+            matrix
+                .column(s_idx)
+                .iter()
+                .zip(&geom_means)
+                .filter(|&(&count, &gm)| gm > 0.0 && count > 0.0)
+                .map(|(&count, &gm)| count / gm)
+                .collect()
+        })
+        .collect();
+
+    Ok(finish_size_factors(ratios, samples, method))
+}
+
+/// Iterative maximum-likelihood size factors (DESeq2 `type = "iterate"`).
+/// Alternates an intercept-only NB fit (trend dispersions, per-gene means)
+/// with a per-sample likelihood maximisation of the size factor, dropping
+/// the worst-fitting 5% of genes from the objective. Size factors are kept
+/// at a geometric mean of one.
+fn iterate_size_factors(
+    matrix: ArrayView2<f64>,
+    samples: &[String],
+    control: Option<&[bool]>,
+) -> Result<Vec<f64>, Box<dyn Error>> {
+    const MAX_ITERATIONS: usize = 10;
+    const TRIM_QUANTILE: f64 = 0.05;
+
+    let n_samples = samples.len();
+    let rows: Vec<usize> = (0..matrix.nrows())
+        .filter(|&g_idx| control.is_none_or(|c| c[g_idx]))
+        .filter(|&g_idx| matrix.row(g_idx).iter().any(|&count| count > 0.0))
+        .collect();
+    if rows.is_empty() {
+        return Err("No expressed genes available for iterative size factors".into());
+    }
+    let subset = matrix.select(ndarray::Axis(0), &rows);
+    let blind = Design::intercept_only(n_samples);
+
+    let mut size_factors = vec![1.0; n_samples];
+    let mut last_deviance = f64::INFINITY;
+    let mut converged = false;
+
+    for iteration in 0..MAX_ITERATIONS {
+        let dispersions = estimate_dispersions(subset.view(), &size_factors, &blind)?;
+        let genes: Vec<(Vec<f64>, f64, f64)> = (0..subset.nrows())
+            .filter(|&g_idx| dispersions.trend[g_idx].is_finite())
+            .map(|g_idx| {
+                let counts = subset.row(g_idx).to_vec();
+                let alpha = dispersions.trend[g_idx];
+                let mean = fit_group_means(&counts, &size_factors, &blind.is_treatment, alpha)[0];
+                (counts, mean, alpha)
+            })
+            .collect();
+
+        // Trim the worst-fitting genes before updating the size factors
+        let gene_ll: Vec<f64> = genes
+            .iter()
+            .map(|(counts, mean, alpha)| {
+                let mu: Vec<f64> = size_factors.iter().map(|sf| sf * mean).collect();
+                nb_log_likelihood(counts, &mu, *alpha)
+            })
+            .collect();
+        let mut sorted_ll = gene_ll.clone();
+        sorted_ll.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let cutoff = sorted_ll[((sorted_ll.len() as f64 * TRIM_QUANTILE) as usize).min(sorted_ll.len() - 1)];
+        let kept: Vec<&(Vec<f64>, f64, f64)> = genes
+            .iter()
+            .zip(&gene_ll)
+            .filter(|(_, &ll)| ll >= cutoff)
+            .map(|(gene, _)| gene)
+            .collect();
+
+        for (s_idx, size_factor) in size_factors.iter_mut().enumerate() {
+            let log_sf = golden_section_max(
+                |log_sf| {
+                    kept.iter()
+                        .map(|(counts, mean, alpha)| {
+                            nb_log_likelihood(&counts[s_idx..=s_idx], &[log_sf.exp() * mean], *alpha)
+                        })
+                        .sum()
+                },
+                (1e-3f64).ln(),
+                (1e3f64).ln(),
+            );
+            *size_factor = log_sf.exp();
+        }
+        let log_mean = size_factors.iter().map(|sf| sf.ln()).sum::<f64>() / n_samples as f64;
+        size_factors.iter_mut().for_each(|sf| *sf /= log_mean.exp());
+
+        let deviance: f64 = -2.0 * kept
+            .iter()
+            .map(|(counts, mean, alpha)| {
+                let mu: Vec<f64> = size_factors.iter().map(|sf| sf * mean).collect();
+                nb_log_likelihood(counts, &mu, *alpha)
+            })
+            .sum::<f64>();
+        info!("Size factor iteration {}: deviance {:.4}", iteration + 1, deviance);
+        if (deviance - last_deviance).abs() / (deviance.abs() + 0.1) < 1e-6 {
+            converged = true;
+            break;
+        }
+        last_deviance = deviance;
+    }
+
+    if !converged {
+        warn!("Iterative size factors did not converge in {} iterations", MAX_ITERATIONS);
+    }
+    Ok(size_factors)
+}
+
+fn normalize_counts(
+    matrix: ArrayView2<f64>,
+    size_factors: &[f64],
+    max_memory: Option<usize>,
+) -> Result<CountMatrix, Box<dyn Error>> {
+    let mut normalized = CountMatrix::zeros(matrix.dim(), max_memory)?;
+    Zip::from(normalized.view_mut().rows_mut())
+        .and(matrix.rows())
+        .par_for_each(|mut values, counts| {
+            for ((value, &count), &size_factor) in values.iter_mut().zip(counts).zip(size_factors) {
+                *value = if size_factor > 0.0 { count / size_factor } else { count };
             }
+        });
+
+    Ok(normalized)
+}
+
+fn read_design(
+    filename: &str,
+    condition_column: &str,
+    reference_level: Option<&str>,
+    samples: &[String],
+) -> Result<Design, Box<dyn Error>> {
+    let file = File::open(filename)?;
+    let mut lines = BufReader::new(file).lines();
+    let header = lines.next().ok_or("Empty design file")??;
+    let columns: Vec<&str> = header.split('\t').map(|c| c.trim()).collect();
+    let condition_idx = columns
+        .iter()
+        .position(|&c| c == condition_column)
+        .ok_or_else(|| format!("Design file has no '{}' column", condition_column))?;
+
+    let mut conditions: HashMap<String, String> = HashMap::new();
+    for line in lines {
+        let l = line?;
+        if l.trim().is_empty() {
+            continue;
         }
-        
-        if ratios.is_empty() {
-            warn!("No valid ratios for sample '{}', using size factor of 1.0", samples[s_idx]);
-            size_factors.push(1.0);
+        let parts: Vec<&str> = l.split('\t').map(|p| p.trim()).collect();
+        if parts.len() > condition_idx {
+            conditions.insert(parts[0].to_string(), parts[condition_idx].to_string());
+        }
+    }
+
+    let mut levels: Vec<&String> = conditions.values().collect();
+    levels.sort();
+    levels.dedup();
+    if levels.len() != 2 {
+        return Err(format!(
+            "Wald test needs exactly two '{}' levels, found {}",
+            condition_column,
+            levels.len()
+        )
+        .into());
+    }
+
+    let reference = match reference_level {
+        Some(level) if levels.iter().any(|l| l.as_str() == level) => level.to_string(),
+        Some(level) => return Err(format!("Reference level '{}' not found in design", level).into()),
+        None => levels[0].clone(),
+    };
+    let treatment = levels.iter().find(|l| **l != &reference).unwrap().to_string();
+
+    let mut is_treatment = Vec::with_capacity(samples.len());
+    for sample in samples {
+        let condition = conditions
+            .get(sample)
+            .ok_or_else(|| format!("Sample '{}' is missing from the design file", sample))?;
+        is_treatment.push(*condition == treatment);
+    }
+    for (label, in_group) in [(&reference, false), (&treatment, true)] {
+        if is_treatment.iter().filter(|&&t| t == in_group).count() < 2 {
+            warn!("Condition '{}' has fewer than two samples; dispersion estimates will be unreliable", label);
+        }
+    }
+
+    Ok(Design { reference, treatment, is_treatment })
+}
+
+/// Smallest dispersion considered; DESeq2 uses the same floor
+const MIN_DISPERSION: f64 = 1e-8;
+
+/// Negative binomial log-likelihood of one gene's counts
+fn nb_log_likelihood(counts: &[f64], mu: &[f64], alpha: f64) -> f64 {
+    let size = 1.0 / alpha;
+    counts
+        .iter()
+        .zip(mu)
+        .map(|(&y, &m)| {
+            ln_gamma(y + size) - ln_gamma(size) - ln_gamma(y + 1.0)
+                - size * (1.0 + alpha * m).ln()
+                + y * (alpha * m / (1.0 + alpha * m)).ln()
+        })
+        .sum()
+}
+
+/// Cox-Reid adjusted profile log-likelihood for the two-group design.
+/// With group indicator columns X'WX is diagonal, so its log-determinant is
+/// the sum of the per-group log weight totals (empty groups contribute nothing).
+fn cox_reid_log_likelihood(counts: &[f64], mu: &[f64], is_treatment: &[bool], alpha: f64) -> f64 {
+    let mut weights = [0.0; 2];
+    for (&m, &t) in mu.iter().zip(is_treatment) {
+        weights[t as usize] += m / (1.0 + alpha * m);
+    }
+    let log_det: f64 = weights.iter().filter(|&&w| w > 0.0).map(|w| w.ln()).sum();
+    nb_log_likelihood(counts, mu, alpha) - 0.5 * log_det
+}
+
+/// Maximize `f` over `[lo, hi]` with golden-section search
+fn golden_section_max<F: Fn(f64) -> f64>(f: F, mut lo: f64, mut hi: f64) -> f64 {
+    let ratio = (5.0_f64.sqrt() - 1.0) / 2.0;
+    let mut x1 = hi - ratio * (hi - lo);
+    let mut x2 = lo + ratio * (hi - lo);
+    let (mut f1, mut f2) = (f(x1), f(x2));
+    for _ in 0..100 {
+        if (hi - lo).abs() < 1e-6 {
+            break;
+        }
+        if f1 < f2 {
+            lo = x1;
+            x1 = x2;
+            f1 = f2;
+            x2 = lo + ratio * (hi - lo);
+            f2 = f(x2);
         } else {
-            ratios.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
-            let median = if ratios.len() % 2 == 0 {
-                (ratios[ratios.len() / 2 - 1] + ratios[ratios.len() / 2]) / 2.0
+            hi = x2;
+            x2 = x1;
+            f2 = f1;
+            x1 = hi - ratio * (hi - lo);
+            f1 = f(x1);
+        }
+    }
+    (lo + hi) / 2.0
+}
+
+/// Trigamma function via recurrence and asymptotic expansion
+fn trigamma(mut x: f64) -> f64 {
+    let mut result = 0.0;
+    while x < 6.0 {
+        result += 1.0 / (x * x);
+        x += 1.0;
+    }
+    let x2 = 1.0 / (x * x);
+    result + 1.0 / x + x2 / 2.0
+        + x2 / x * (1.0 / 6.0 - x2 * (1.0 / 30.0 - x2 * (1.0 / 42.0 - x2 / 30.0)))
+}
+
+/// Per-group negative binomial means `q` such that mu_j = s_j * q_group,
+/// solving the score equation for the given dispersion by bisection.
+fn fit_group_means(counts: &[f64], size_factors: &[f64], is_treatment: &[bool], alpha: f64) -> [f64; 2] {
+    let mut means = [0.0; 2];
+    for (group, mean) in means.iter_mut().enumerate() {
+        let members: Vec<(f64, f64)> = counts
+            .iter()
+            .zip(size_factors)
+            .zip(is_treatment)
+            .filter(|(_, &t)| t as usize == group)
+            .map(|((&y, &sf), _)| (y, sf))
+            .collect();
+        let max_norm = members.iter().map(|(y, sf)| y / sf).fold(0.0, f64::max);
+        if max_norm <= 0.0 {
+            continue;
+        }
+        let score = |q: f64| -> f64 {
+            members.iter().map(|&(y, sf)| (y - sf * q) / (1.0 + alpha * sf * q)).sum()
+        };
+        let (mut lo, mut hi) = (0.0, max_norm);
+        for _ in 0..100 {
+            let mid = (lo + hi) / 2.0;
+            if score(mid) > 0.0 {
+                lo = mid;
             } else {
-                ratios[ratios.len() / 2]
-            };
-            size_factors.push(median.max(0.001)); // Avoid division by very small numbers
+                hi = mid;
+            }
+            if hi - lo < 1e-10 * max_norm {
+                break;
+            }
         }
+        *mean = (lo + hi) / 2.0;
     }
-    
-    Ok(size_factors)
+    means
 }
 
-fn normalize_counts(
-    matrix: &Array2<f64>,
+fn fitted_mu(means: &[f64; 2], size_factors: &[f64], is_treatment: &[bool]) -> Vec<f64> {
+    size_factors
+        .iter()
+        .zip(is_treatment)
+        .map(|(&sf, &t)| (sf * means[t as usize]).max(1e-10))
+        .collect()
+}
+
+/// DESeq2-style dispersion estimation: gene-wise Cox-Reid estimates, a
+/// parametric trend `a0 + a1/mean`, then empirical Bayes shrinkage towards
+/// the trend with a log-normal prior.
+fn estimate_dispersions(
+    matrix: ArrayView2<f64>,
     size_factors: &[f64],
-) -> Result<Array2<f64>, Box<dyn Error>> {
-    let mut normalized = matrix.clone();
-    
-    for s_idx in 0..size_factors.len() {
-        if size_factors[s_idx] > 0.0 {
-            for g_idx in 0..matrix.nrows() {
-                normalized[[g_idx, s_idx]] /= size_factors[s_idx];
+    design: &Design,
+) -> Result<DispersionFit, Box<dyn Error>> {
+    let n_samples = size_factors.len();
+    let n_genes = matrix.nrows();
+    let residual_df = (n_samples - design.group_count().min(n_samples)) as f64;
+    if residual_df < 1.0 {
+        return Err("Dispersion estimation needs more samples than design groups".into());
+    }
+    let max_disp = (n_samples as f64).max(10.0);
+    let mean_inv_sf = size_factors.iter().map(|sf| 1.0 / sf).sum::<f64>() / n_samples as f64;
+
+    // Gene-wise estimates
+    info!("Estimating gene-wise dispersions");
+    let mut gene_wise = vec![f64::NAN; n_genes];
+    let mut base_means = vec![0.0; n_genes];
+    for g_idx in 0..n_genes {
+        let counts: Vec<f64> = matrix.row(g_idx).to_vec();
+        let normalized: Vec<f64> = counts.iter().zip(size_factors).map(|(y, sf)| y / sf).collect();
+        let mean = normalized.iter().sum::<f64>() / n_samples as f64;
+        base_means[g_idx] = mean;
+        if mean == 0.0 {
+            continue;
+        }
+
+        // Moments estimate from within-group variance gives the starting fit
+        let group_means = fit_group_means(&counts, size_factors, &design.is_treatment, 0.0);
+        let within_var = normalized
+            .iter()
+            .zip(&design.is_treatment)
+            .map(|(x, &t)| (x - group_means[t as usize]).powi(2))
+            .sum::<f64>()
+            / residual_df;
+        let moments = ((within_var - mean * mean_inv_sf) / (mean * mean)).clamp(MIN_DISPERSION, max_disp);
+
+        let mu = fitted_mu(
+            &fit_group_means(&counts, size_factors, &design.is_treatment, moments),
+            size_factors,
+            &design.is_treatment,
+        );
+        let log_alpha = golden_section_max(
+            |la| cox_reid_log_likelihood(&counts, &mu, &design.is_treatment, la.exp()),
+            MIN_DISPERSION.ln(),
+            max_disp.ln(),
+        );
+        gene_wise[g_idx] = log_alpha.exp();
+    }
+
+    // Parametric trend fitted as a gamma GLM with identity link on 1/mean,
+    // dropping genes whose estimate is far from the current fit
+    info!("Fitting dispersion trend");
+    let usable: Vec<usize> = (0..n_genes)
+        .filter(|&g| gene_wise[g].is_finite() && gene_wise[g] > 100.0 * MIN_DISPERSION)
+        .collect();
+    if usable.len() < 3 {
+        return Err("Too few genes with usable dispersion estimates to fit a trend".into());
+    }
+    let mut coefs = (0.1, 1.0);
+    let mut converged = false;
+    for _ in 0..20 {
+        let fit: Vec<(f64, f64, f64)> = usable
+            .iter()
+            .map(|&g| (1.0 / base_means[g], gene_wise[g], coefs.0 + coefs.1 / base_means[g]))
+            .filter(|&(_, d, fitted)| {
+                let ratio = d / fitted;
+                fitted > 0.0 && ratio > 1e-4 && ratio < 15.0
+            })
+            .collect();
+        if fit.len() < 3 {
+            break;
+        }
+        // Weighted least squares with gamma variance weights 1/fitted^2
+        let (mut sw, mut sx, mut sy, mut sxx, mut sxy) = (0.0, 0.0, 0.0, 0.0, 0.0);
+        for &(x, d, fitted) in &fit {
+            let w = 1.0 / (fitted * fitted);
+            sw += w;
+            sx += w * x;
+            sy += w * d;
+            sxx += w * x * x;
+            sxy += w * x * d;
+        }
+        let denom = sw * sxx - sx * sx;
+        if denom.abs() < 1e-300 {
+            break;
+        }
+        let a1 = (sw * sxy - sx * sy) / denom;
+        let a0 = (sy - a1 * sx) / sw;
+        if a0 <= 0.0 || a1 < 0.0 {
+            break;
+        }
+        let change = (a0 / coefs.0).ln().powi(2)
+            + if a1 > 0.0 && coefs.1 > 0.0 { (a1 / coefs.1).ln().powi(2) } else { 0.0 };
+        coefs = (a0, a1);
+        if change < 1e-6 {
+            converged = true;
+            break;
+        }
+    }
+    if !converged {
+        let mean_disp = usable.iter().map(|&g| gene_wise[g]).sum::<f64>() / usable.len() as f64;
+        warn!("Parametric dispersion trend did not converge; using the mean dispersion {:.4}", mean_disp);
+        coefs = (mean_disp, 0.0);
+    }
+    let trend: Vec<f64> = base_means
+        .iter()
+        .map(|&m| if m > 0.0 { coefs.0 + coefs.1 / m } else { f64::NAN })
+        .collect();
+
+    // Prior width from the spread of log residuals, less the expected
+    // sampling variance of log dispersion estimates
+    let mut log_resid: Vec<f64> = usable.iter().map(|&g| (gene_wise[g] / trend[g]).ln()).collect();
+    log_resid.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let median = log_resid[log_resid.len() / 2];
+    let mut abs_dev: Vec<f64> = log_resid.iter().map(|r| (r - median).abs()).collect();
+    abs_dev.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let mad = 1.4826 * abs_dev[abs_dev.len() / 2];
+    let prior_var = (mad * mad - trigamma(residual_df / 2.0)).max(0.25);
+
+    // Maximum a posteriori estimates
+    info!("Shrinking dispersions towards the trend");
+    let mut map = vec![f64::NAN; n_genes];
+    let mut final_disp = vec![f64::NAN; n_genes];
+    let mut outliers = 0;
+    for g_idx in 0..n_genes {
+        if !gene_wise[g_idx].is_finite() {
+            continue;
+        }
+        let counts: Vec<f64> = matrix.row(g_idx).to_vec();
+        let mu = fitted_mu(
+            &fit_group_means(&counts, size_factors, &design.is_treatment, gene_wise[g_idx]),
+            size_factors,
+            &design.is_treatment,
+        );
+        let log_trend = trend[g_idx].ln();
+        let log_alpha = golden_section_max(
+            |la| {
+                cox_reid_log_likelihood(&counts, &mu, &design.is_treatment, la.exp())
+                    - (la - log_trend).powi(2) / (2.0 * prior_var)
+            },
+            MIN_DISPERSION.ln(),
+            max_disp.ln(),
+        );
+        map[g_idx] = log_alpha.exp();
+
+        // Genes far above the trend keep their gene-wise estimate
+        if (gene_wise[g_idx] / trend[g_idx]).ln() > 2.0 * mad {
+            final_disp[g_idx] = gene_wise[g_idx];
+            outliers += 1;
+        } else {
+            final_disp[g_idx] = map[g_idx];
+        }
+    }
+
+    Ok(DispersionFit { gene_wise, trend, map, final_disp, trend_coefs: coefs, prior_var, outliers })
+}
+
+/// Wald test of the treatment vs reference log2 fold change with
+/// Benjamini-Hochberg adjusted p-values.
+fn wald_test(
+    matrix: ArrayView2<f64>,
+    size_factors: &[f64],
+    design: &Design,
+    dispersions: &DispersionFit,
+) -> Vec<DeResult> {
+    let standard_normal = Normal::new(0.0, 1.0).unwrap();
+    let n_samples = size_factors.len() as f64;
+    let mut results = Vec::with_capacity(matrix.nrows());
+
+    for g_idx in 0..matrix.nrows() {
+        let counts: Vec<f64> = matrix.row(g_idx).to_vec();
+        let base_mean = counts.iter().zip(size_factors).map(|(y, sf)| y / sf).sum::<f64>() / n_samples;
+        let alpha = dispersions.final_disp[g_idx];
+        if base_mean == 0.0 || !alpha.is_finite() {
+            results.push(DeResult {
+                base_mean,
+                log2_fold_change: None,
+                lfc_se: None,
+                stat: None,
+                pvalue: None,
+                padj: None,
+                dispersion: None,
+            });
+            continue;
+        }
+
+        let means = fit_group_means(&counts, size_factors, &design.is_treatment, alpha);
+        let mu = fitted_mu(&means, size_factors, &design.is_treatment);
+        let mut weights = [0.0; 2];
+        for (&m, &t) in mu.iter().zip(&design.is_treatment) {
+            weights[t as usize] += m / (1.0 + alpha * m);
+        }
+
+        // Coefficients on the natural log scale; floor means so an all-zero
+        // group gives a large but finite fold change
+        let log_means = [means[0].max(1e-8).ln(), means[1].max(1e-8).ln()];
+        let lfc = (log_means[1] - log_means[0]) / std::f64::consts::LN_2;
+        let se = (1.0 / weights[0] + 1.0 / weights[1]).sqrt() / std::f64::consts::LN_2;
+        let stat = lfc / se;
+        let pvalue = 2.0 * standard_normal.cdf(-stat.abs());
+
+        results.push(DeResult {
+            base_mean,
+            log2_fold_change: Some(lfc),
+            lfc_se: Some(se),
+            stat: Some(stat),
+            pvalue: Some(pvalue),
+            padj: None,
+            dispersion: Some(alpha),
+        });
+    }
+
+    // Benjamini-Hochberg over the tested genes
+    let mut tested: Vec<(usize, f64)> = results
+        .iter()
+        .enumerate()
+        .filter_map(|(i, r)| r.pvalue.map(|p| (i, p)))
+        .collect();
+    tested.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+    let m = tested.len() as f64;
+    let mut running_min: f64 = 1.0;
+    for (rank_from_top, &(idx, p)) in tested.iter().enumerate() {
+        let rank = m - rank_from_top as f64;
+        running_min = running_min.min(p * m / rank);
+        results[idx].padj = Some(running_min);
+    }
+
+    results
+}
+
+/// Closed-form VST for the parametric dispersion trend a0 + a1/mean
+/// (Anders & Huber 2010), on the log2 scale. A mean-only trend (a1 = 0)
+/// reduces to the asinh transformation; large counts approach log2(q).
+fn vst_transform(
+    matrix: ArrayView2<f64>,
+    size_factors: &[f64],
+    dispersions: &DispersionFit,
+    max_memory: Option<usize>,
+) -> Result<CountMatrix, Box<dyn Error>> {
+    let (a0, a1) = dispersions.trend_coefs;
+    let mut transformed = CountMatrix::zeros(matrix.dim(), max_memory)?;
+    let mut values = transformed.view_mut();
+
+    for ((g_idx, s_idx), value) in values.indexed_iter_mut() {
+        let q = matrix[[g_idx, s_idx]] / size_factors[s_idx];
+        *value = ((1.0 + a1 + 2.0 * a0 * q + 2.0 * (a0 * q * (1.0 + a1 + a0 * q)).sqrt()) / (4.0 * a0)).log2();
+    }
+
+    Ok(transformed)
+}
+
+/// Weighted quantile of `values` (sorted internally)
+fn weighted_quantile(values: &[f64], weights: &[f64], quantile: f64) -> f64 {
+    let mut pairs: Vec<(f64, f64)> = values.iter().copied().zip(weights.iter().copied()).collect();
+    pairs.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+    let total: f64 = pairs.iter().map(|p| p.1).sum();
+    let mut cumulative = 0.0;
+    for (value, weight) in &pairs {
+        cumulative += weight;
+        if cumulative >= quantile * total {
+            return *value;
+        }
+    }
+    pairs.last().map_or(0.0, |p| p.0)
+}
+
+/// Regularized log transformation: per gene, fit log2(mu_ij / s_j) =
+/// beta0 + beta_j under a zero-centred normal prior on the sample effects,
+/// using trend dispersions. The prior variance matches the weighted upper
+/// 5% quantile of the observed per-sample log fold changes, as in DESeq2.
+fn rlog_transform(
+    matrix: ArrayView2<f64>,
+    size_factors: &[f64],
+    dispersions: &DispersionFit,
+    max_memory: Option<usize>,
+) -> Result<CountMatrix, Box<dyn Error>> {
+    let n_samples = size_factors.len();
+    let (n_genes, _) = matrix.dim();
+
+    // Prior variance from observed log2 fold changes against the gene mean
+    let mut lfcs = Vec::new();
+    let mut lfc_weights = Vec::new();
+    for g_idx in 0..n_genes {
+        let trend = dispersions.trend[g_idx];
+        if !trend.is_finite() {
+            continue;
+        }
+        let normalized: Vec<f64> = (0..n_samples).map(|j| matrix[[g_idx, j]] / size_factors[j]).collect();
+        let base_mean = normalized.iter().sum::<f64>() / n_samples as f64;
+        let weight = 1.0 / (1.0 / base_mean + trend);
+        for x in normalized {
+            lfcs.push(((x + 0.5) / (base_mean + 0.5)).log2().abs());
+            lfc_weights.push(weight);
+        }
+    }
+    if lfcs.is_empty() {
+        return Err("No expressed genes to estimate the rlog prior".into());
+    }
+    let standard_normal = Normal::new(0.0, 1.0).unwrap();
+    let prior_sd = weighted_quantile(&lfcs, &lfc_weights, 0.95) / standard_normal.inverse_cdf(0.975);
+    let prior_var = (prior_sd * prior_sd).max(1e-6);
+    info!("rlog prior variance: {:.4}", prior_var);
+
+    // Ridge penalties on the natural-log scale; the intercept is effectively unpenalized
+    let lambda = 1.0 / (prior_var * std::f64::consts::LN_2 * std::f64::consts::LN_2);
+    let lambda0 = 1e-6;
+
+    let mut transformed = CountMatrix::zeros(matrix.dim(), max_memory)?;
+    let mut values = transformed.view_mut();
+    let mut beta = vec![0.0; n_samples];
+    let mut z = vec![0.0; n_samples];
+    let mut w = vec![0.0; n_samples];
+
+    for g_idx in 0..n_genes {
+        let alpha = dispersions.trend[g_idx];
+        if !alpha.is_finite() {
+            // All-zero genes carry no information; report them as 0
+            continue;
+        }
+        let counts: Vec<f64> = (0..n_samples).map(|j| matrix[[g_idx, j]]).collect();
+        let base_mean = counts.iter().zip(size_factors).map(|(y, sf)| y / sf).sum::<f64>() / n_samples as f64;
+        let mut beta0 = base_mean.ln();
+        beta.iter_mut().for_each(|b| *b = 0.0);
+
+        // IRLS with X = [1 | I]: the block structure of X'WX + Lambda lets
+        // the normal equations be solved in O(samples) per iteration
+        let mut last_deviance = f64::INFINITY;
+        for _ in 0..100 {
+            for j in 0..n_samples {
+                let eta = beta0 + beta[j] + size_factors[j].ln();
+                let mu = eta.exp().max(1e-10);
+                w[j] = mu / (1.0 + alpha * mu);
+                z[j] = beta0 + beta[j] + (counts[j] - mu) / mu;
+            }
+            let mut schur = w.iter().sum::<f64>() + lambda0;
+            let mut rhs0: f64 = w.iter().zip(&z).map(|(wj, zj)| wj * zj).sum();
+            for j in 0..n_samples {
+                let d = w[j] + lambda;
+                schur -= w[j] * w[j] / d;
+                rhs0 -= w[j] * (w[j] * z[j]) / d;
             }
+            beta0 = rhs0 / schur;
+            for j in 0..n_samples {
+                beta[j] = (w[j] * z[j] - w[j] * beta0) / (w[j] + lambda);
+            }
+
+            let deviance: f64 = (0..n_samples)
+                .map(|j| {
+                    let mu = (beta0 + beta[j] + size_factors[j].ln()).exp();
+                    -2.0 * nb_log_likelihood(&counts[j..=j], &[mu], alpha)
+                })
+                .sum();
+            if (deviance - last_deviance).abs() / (deviance.abs() + 0.1) < 1e-8 {
+                break;
+            }
+            last_deviance = deviance;
+        }
+
+        for j in 0..n_samples {
+            values[[g_idx, j]] = (beta0 + beta[j]) / std::f64::consts::LN_2;
         }
     }
-    
-    Ok(normalized)
+
+    Ok(transformed)
+}
+
+/// Write a gene x sample matrix in wide layout, as Parquet when the file
+/// name ends in `.parquet` and as TSV otherwise
+fn write_matrix(
+    matrix: ArrayView2<f64>,
+    genes: &[String],
+    samples: &[String],
+    filename: &str,
+) -> Result<(), Box<dyn Error>> {
+    if is_parquet(filename) {
+        return write_matrix_parquet(matrix, genes, samples, filename);
+    }
+
+    let mut file = std::io::BufWriter::new(File::create(filename)?);
+
+    write!(file, "gene_id")?;
+    for sample in samples {
+        write!(file, "\t{}", sample)?;
+    }
+    writeln!(file)?;
+
+    for (g_idx, gene) in genes.iter().enumerate() {
+        write!(file, "{}", gene)?;
+        for value in matrix.row(g_idx) {
+            write!(file, "\t{:.6}", value)?;
+        }
+        writeln!(file)?;
+    }
+    file.flush()?;
+
+    Ok(())
+}
+
+/// Wide Parquet output: `gene_id` plus one Float64 column per sample,
+/// Snappy-compressed and written in row groups of `ROWS_PER_BATCH` genes
+fn write_matrix_parquet(
+    matrix: ArrayView2<f64>,
+    genes: &[String],
+    samples: &[String],
+    filename: &str,
+) -> Result<(), Box<dyn Error>> {
+    const ROWS_PER_BATCH: usize = 65_536;
+
+    let mut fields = vec![Field::new("gene_id", DataType::Utf8, false)];
+    fields.extend(samples.iter().map(|sample| Field::new(sample.as_str(), DataType::Float64, false)));
+    let schema = Arc::new(Schema::new(fields));
+    let props = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+    let mut writer = ArrowWriter::try_new(File::create(filename)?, schema.clone(), Some(props))?;
+
+    for start in (0..genes.len()).step_by(ROWS_PER_BATCH) {
+        let end = (start + ROWS_PER_BATCH).min(genes.len());
+        let mut columns: Vec<ArrayRef> = vec![Arc::new(StringArray::from_iter_values(&genes[start..end]))];
+        for s_idx in 0..samples.len() {
+            let values = matrix.slice(s![start..end, s_idx]);
+            columns.push(Arc::new(Float64Array::from_iter_values(values.iter().copied())));
+        }
+        writer.write(&RecordBatch::try_new(schema.clone(), columns)?)?;
+    }
+    writer.close()?;
+
+    Ok(())
+}
+
+fn write_de_results(
+    results: &[DeResult],
+    dispersions: &DispersionFit,
+    genes: &[String],
+    filename: &str,
+) -> Result<(), Box<dyn Error>> {
+    let mut file = File::create(filename)?;
+    let fmt = |v: Option<f64>| v.map_or_else(|| "NA".to_string(), |x| format!("{:.6e}", x));
+    let fmt_f = |v: f64| if v.is_finite() { format!("{:.6e}", v) } else { "NA".to_string() };
+
+    writeln!(
+        file,
+        "gene_id\tbaseMean\tlog2FoldChange\tlfcSE\tstat\tpvalue\tpadj\tdispGeneEst\tdispFit\tdispMAP\tdispersion"
+    )?;
+    for (g_idx, r) in results.iter().enumerate() {
+        writeln!(
+            file,
+            "{}\t{:.6}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            genes[g_idx],
+            r.base_mean,
+            fmt(r.log2_fold_change),
+            fmt(r.lfc_se),
+            fmt(r.stat),
+            fmt(r.pvalue),
+            fmt(r.padj),
+            fmt_f(dispersions.gene_wise[g_idx]),
+            fmt_f(dispersions.trend[g_idx]),
+            fmt_f(dispersions.map[g_idx]),
+            fmt(r.dispersion),
+        )?;
+    }
+
+    Ok(())
 }
 
 fn write_normalized_counts(
-    matrix: &Array2<f64>,
+    matrix: ArrayView2<f64>,
     genes: &[String],
     samples: &[String],
     filename: &str,
@@ -358,6 +1961,29 @@ fn write_normalized_counts(
     Ok(())
 }
 
+/// Long-format output of the non-zero normalized counts; absent gene/sample
+/// pairs are zero
+fn write_sparse_normalized_counts(
+    csr: &CsrMatrix,
+    size_factors: &[f64],
+    genes: &[String],
+    samples: &[String],
+    filename: &str,
+) -> Result<(), Box<dyn Error>> {
+    let mut file = std::io::BufWriter::new(File::create(filename)?);
+
+    writeln!(file, "gene_id\tsample_id\tnormalized_count")?;
+    for (g_idx, gene) in genes.iter().enumerate() {
+        let (cols, vals) = csr.row(g_idx);
+        for (&s_idx, &count) in cols.iter().zip(vals) {
+            writeln!(file, "{}\t{}\t{:.6}", gene, samples[s_idx], count / size_factors[s_idx])?;
+        }
+    }
+    file.flush()?;
+
+    Ok(())
+}
+
 fn write_statistics(
     stats: &NormalizationStats,
     _genes: &[String],
@@ -372,6 +1998,10 @@ fn write_statistics(
     writeln!(file, "Total samples: {}", stats.total_samples)?;
     writeln!(file, "Zero counts: {}", stats.zero_counts)?;
     writeln!(file, "Geometric means computed: {}", stats.geometric_means_computed)?;
+    writeln!(file, "Size factor method: {:?}", stats.size_factor_method)?;
+    if let Some(control_genes) = stats.control_genes {
+        writeln!(file, "Control genes: {}", control_genes)?;
+    }
     writeln!(file)?;
     
     writeln!(file, "Size Factors:")?;
@@ -384,6 +2014,6 @@ fn write_statistics(
     Ok(())
 }
 
-fn count_zeros(matrix: &Array2<f64>) -> usize {
+fn count_zeros(matrix: ArrayView2<f64>) -> usize {
     matrix.iter().filter(|&&x| x == 0.0).count()
 }
\ No newline at end of file